@@ -10,10 +10,10 @@ pub struct IoSubmission {
     pub opcode: u32,
     pub flags: u32,
     pub fd: i32,
-    pub _pad: i32,
-    pub buf_addr: u64,
     pub buf_len: u32,
-    pub offset: u32,
+    pub buf_addr: u64,
+    /// File offset for OP_READ / OP_WRITE with `IOSUB_OFFSET`.
+    pub offset: u64,
     pub timeout_ns: u64,
 }
 
@@ -24,9 +24,8 @@ impl Default for IoSubmission {
             opcode: 0,
             flags: 0,
             fd: -1,
-            _pad: 0,
-            buf_addr: 0,
             buf_len: 0,
+            buf_addr: 0,
             offset: 0,
             timeout_ns: 0,
         }
@@ -1,4 +1,8 @@
 #![no_std]
+#![cfg_attr(test, no_main)]
+#![feature(custom_test_frameworks)]
+#![test_runner(libkernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
 extern crate alloc;
 #[macro_use] extern crate log;
 
@@ -6,7 +10,35 @@ pub use devices_macros::{actor, on_info, on_message, on_start, on_tick, on_strea
 #[macro_use] pub mod macros;
 pub mod driver;
 pub mod dummy;
+pub mod net;
 pub mod pci;
 pub mod task_driver;
 pub mod virtio;
 pub mod vfs;
+
+#[cfg(test)]
+bootloader::entry_point!(test_kernel_main);
+
+#[cfg(test)]
+fn test_kernel_main(boot_info: &'static bootloader::BootInfo) -> ! {
+    use libkernel::{allocator, memory, stack_arena};
+    use x86_64::VirtAddr;
+    libkernel::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe {
+        memory::BootInfoFrameAllocator::init(&boot_info.memory_map)
+    };
+    allocator::init_heap(&mut mapper, &mut frame_allocator)
+        .expect("heap initialization failed");
+    memory::init_services(mapper, frame_allocator, phys_mem_offset, &boot_info.memory_map);
+    stack_arena::init();
+    test_main();
+    libkernel::hlt_loop();
+}
+
+#[cfg(test)]
+#[panic_handler]
+fn panic(info: &core::panic::PanicInfo) -> ! {
+    libkernel::test_panic_handler(info)
+}
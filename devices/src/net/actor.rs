@@ -0,0 +1,96 @@
+//! The `net` actor: drives smoltcp.
+//!
+//! It polls the stack whenever the NIC interrupts, and on a short tick for
+//! TCP timers and for interfaces without an interrupt.  If DHCP has not
+//! configured `eth0` after a few seconds it falls back to the static QEMU
+//! user networking address.
+
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use smoltcp::wire::IpCidr;
+
+use libkernel::task::timer::TICKS_PER_SECOND;
+
+use crate::actor;
+use crate::virtio::net::{self as virtio_net, IrqStream};
+
+/// Time between polls without an interrupt.
+const POLL_INTERVAL_MS: u64 = 10;
+/// How long DHCP gets before the fallback address is used.
+const DHCP_TIMEOUT_NS: u64 = 3_000_000_000;
+
+// ---------------------------------------------------------------------------
+// Messages (none — this actor is driven by the interrupt and its tick)
+
+pub enum NetMsg {}
+
+// ---------------------------------------------------------------------------
+// Info
+
+#[derive(Debug)]
+#[allow(dead_code)]
+pub struct NetInfo {
+    pub interfaces: Vec<(&'static str, Vec<IpCidr>)>,
+    pub interrupts: u64,
+    pub polls: u64,
+}
+
+// ---------------------------------------------------------------------------
+// Actor
+
+pub struct NetActor {
+    started_ns: u64,
+    dhcp_settled: AtomicBool,
+    interrupts: AtomicU64,
+    polls: AtomicU64,
+}
+
+impl NetActor {
+    pub fn new() -> Self {
+        NetActor {
+            started_ns: libkernel::time::monotonic_ns(),
+            dhcp_settled: AtomicBool::new(false),
+            interrupts: AtomicU64::new(0),
+            polls: AtomicU64::new(0),
+        }
+    }
+}
+
+#[actor("net", NetMsg)]
+impl NetActor {
+    fn tick_interval_ticks(&self) -> u64 {
+        (POLL_INTERVAL_MS * TICKS_PER_SECOND / 1000).max(1)
+    }
+
+    fn irq_stream(&self) -> IrqStream { IrqStream::new() }
+
+    #[on_stream(irq_stream)]
+    async fn on_irq(&self, _irq: ()) {
+        super::ack_interrupts();
+        super::poll();
+        virtio_net::unmask_irq();
+        self.interrupts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[on_tick]
+    async fn on_tick(&self) {
+        if !self.dhcp_settled.load(Ordering::Relaxed)
+            && libkernel::time::monotonic_ns() - self.started_ns >= DHCP_TIMEOUT_NS
+        {
+            super::dhcp_fallback();
+            self.dhcp_settled.store(true, Ordering::Relaxed);
+        }
+        super::poll();
+        self.polls.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[on_info]
+    async fn on_info(&self) -> NetInfo {
+        NetInfo {
+            interfaces: super::interfaces(),
+            interrupts: self.interrupts.load(Ordering::Relaxed),
+            polls: self.polls.load(Ordering::Relaxed),
+        }
+    }
+}
@@ -0,0 +1,416 @@
+//! TCP/IP networking on smoltcp.
+//!
+//! Each interface — the loopback `lo`, and the virtio-net NIC as `eth0` —
+//! has its own smoltcp `Interface` and `SocketSet`.  They live together in
+//! one stack behind an `IrqMutex`, since the `net` actor ([`actor`]) polls
+//! it from a task with interrupts enabled while syscalls reach it with them
+//! disabled.
+//!
+//! A socket ([`InetSocket`]) keeps handles into the sets of the interfaces
+//! it uses: a connection lives on the interface its peer is routed
+//! through, while a listener or datagram socket bound to the unspecified
+//! address has a smoltcp socket on every interface.
+//!
+//! smoltcp wakes one waker per socket and direction; instead, every poll
+//! that may have changed a socket's state wakes all the threads waiting on
+//! the stack, and each checks its own socket again.  Sockets are few, and
+//! this keeps `poll` / `epoll` and a blocked reader of the same socket from
+//! stealing each other's wakeups.
+
+use alloc::collections::BTreeMap;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use smoltcp::iface::{Config, Interface, PollResult, SocketHandle, SocketSet};
+use smoltcp::phy::{Loopback, Medium};
+use smoltcp::socket::{dhcpv4, tcp, udp};
+use smoltcp::time::Instant;
+use smoltcp::wire::{
+    EthernetAddress, HardwareAddress, IpAddress, IpCidr, Ipv4Address, Ipv4Cidr, Ipv6Address,
+};
+
+use libkernel::irq_mutex::IrqMutex;
+use libkernel::poll::PollWaiters;
+use libkernel::socket::SocketError;
+
+use crate::virtio::net::VirtioNet;
+
+pub mod actor;
+pub mod socket;
+
+pub use socket::{Family, InetSocket, Received};
+
+/// Receive and send buffer of each TCP socket, in bytes.  The kernel heap
+/// is small, so these stay well below the Linux defaults.
+pub const TCP_BUFFER: usize = 8 * 1024;
+/// Payload buffer of each UDP socket in each direction, which is also the
+/// largest datagram.
+pub const UDP_BUFFER: usize = 16 * 1024;
+/// Datagrams each UDP socket queues in each direction.
+const UDP_PACKETS: usize = 16;
+/// Most connections a listener queues per interface.
+pub const MAX_BACKLOG: usize = 4;
+
+/// First and last port handed out to sockets that did not bind one
+/// (the IANA dynamic range).
+const EPHEMERAL_FIRST: u16 = 49152;
+const EPHEMERAL_LAST: u16 = 65535;
+
+/// Address used on `eth0` when DHCP does not answer: QEMU user
+/// networking's guest address and gateway.
+const FALLBACK_ADDR: Ipv4Cidr = Ipv4Cidr::new(Ipv4Address::new(10, 0, 2, 15), 24);
+const FALLBACK_GATEWAY: Ipv4Address = Ipv4Address::new(10, 0, 2, 2);
+
+/// Most times one poll runs the loopback interface.  Each round delivers
+/// what the last one sent, so this bounds a ping-pong that never settles.
+const LOOPBACK_ROUNDS: usize = 64;
+
+/// Transport protocols, for the port registry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Proto {
+    Tcp,
+    Udp,
+}
+
+/// What an interface sends and receives frames through.
+pub enum Port {
+    Loopback(Loopback),
+    Virtio(VirtioNet),
+}
+
+/// One interface: its smoltcp state and the sockets bound to it.
+pub struct NetIface {
+    name: &'static str,
+    iface: Interface,
+    port: Port,
+    pub(crate) sockets: SocketSet<'static>,
+    dhcp: Option<SocketHandle>,
+    /// Has a default IPv4 route, so takes traffic for other networks.
+    default_v4: bool,
+}
+
+impl NetIface {
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub(crate) fn is_loopback(&self) -> bool {
+        matches!(self.port, Port::Loopback(_))
+    }
+
+    /// Add a TCP socket to the interface.  Over loopback an ACK costs
+    /// nothing, so it is not delayed.
+    pub(crate) fn add_tcp(&mut self, mut sock: tcp::Socket<'static>) -> SocketHandle {
+        if self.is_loopback() {
+            sock.set_ack_delay(None);
+        }
+        self.sockets.add(sock)
+    }
+
+    /// Run smoltcp over the interface.  Returns true if a socket's state
+    /// may have changed.
+    fn poll(&mut self, now: Instant) -> bool {
+        let mut changed = false;
+        match &mut self.port {
+            Port::Virtio(dev) => {
+                let result = self.iface.poll(now, dev, &mut self.sockets);
+                changed = result == PollResult::SocketStateChanged;
+            }
+            // What the interface sends is only received on its next poll,
+            // so keep going until the exchange has settled.
+            Port::Loopback(dev) => {
+                for _ in 0..LOOPBACK_ROUNDS {
+                    if self.iface.poll(now, dev, &mut self.sockets) == PollResult::None {
+                        break;
+                    }
+                    changed = true;
+                }
+            }
+        }
+        if let Some(handle) = self.dhcp {
+            changed |= self.poll_dhcp(handle);
+        }
+        changed
+    }
+
+    /// Apply what the DHCP client has learned.
+    fn poll_dhcp(&mut self, handle: SocketHandle) -> bool {
+        let lease = match self.sockets.get_mut::<dhcpv4::Socket>(handle).poll() {
+            Some(dhcpv4::Event::Configured(config)) => Some((config.address, config.router)),
+            Some(dhcpv4::Event::Deconfigured) => None,
+            None => return false,
+        };
+        match lease {
+            Some((addr, router)) => {
+                info!("[net] {}: DHCP address {}", self.name, addr);
+                self.set_ipv4(addr, router);
+            }
+            None => {
+                info!("[net] {}: DHCP lease lost", self.name);
+                self.iface.update_ip_addrs(|addrs| addrs.retain(|a| !matches!(a, IpCidr::Ipv4(_))));
+                self.iface.routes_mut().remove_default_ipv4_route();
+                self.default_v4 = false;
+            }
+        }
+        true
+    }
+
+    /// Replace the interface's IPv4 address and default route.
+    fn set_ipv4(&mut self, addr: Ipv4Cidr, router: Option<Ipv4Address>) {
+        self.iface.update_ip_addrs(|addrs| {
+            addrs.retain(|a| !matches!(a, IpCidr::Ipv4(_)));
+            addrs.push(IpCidr::Ipv4(addr)).ok();
+        });
+        match router {
+            Some(router) => {
+                self.iface.routes_mut().add_default_ipv4_route(router).ok();
+                self.default_v4 = true;
+            }
+            None => {
+                self.iface.routes_mut().remove_default_ipv4_route();
+                self.default_v4 = false;
+            }
+        }
+    }
+
+    fn has_addr(&self, addr: &IpAddress) -> bool {
+        self.iface.has_ip_addr(*addr)
+    }
+
+    /// On the same network as `addr`.
+    fn is_on_link(&self, addr: &IpAddress) -> bool {
+        self.iface.ip_addrs().iter().any(|cidr| cidr.contains_addr(addr))
+    }
+}
+
+pub(crate) struct Stack {
+    pub(crate) ifaces: Vec<NetIface>,
+    /// TCP sockets whose owner has closed them, kept until the connection
+    /// has finished shutting down.
+    closing: Vec<(usize, SocketHandle)>,
+    /// Local addresses in use per protocol and port; `None` is the
+    /// unspecified address.
+    ports: BTreeMap<(Proto, u16), Vec<Option<IpAddress>>>,
+    next_ephemeral: u16,
+    pub(crate) waiters: PollWaiters,
+}
+
+pub(crate) static STACK: IrqMutex<Stack> = IrqMutex::new(Stack {
+    ifaces: Vec::new(),
+    closing: Vec::new(),
+    ports: BTreeMap::new(),
+    next_ephemeral: EPHEMERAL_FIRST,
+    waiters: PollWaiters::new(),
+});
+
+/// The stack's clock.
+pub(crate) fn now() -> Instant {
+    Instant::from_micros((libkernel::time::monotonic_ns() / 1000) as i64)
+}
+
+fn random_seed() -> u64 {
+    let mut seed = [0u8; 8];
+    libkernel::random::fill_bytes(&mut seed);
+    u64::from_le_bytes(seed)
+}
+
+/// Add the loopback interface `lo`, with 127.0.0.1/8 and ::1.
+pub fn add_loopback() {
+    let mut dev = Loopback::new(Medium::Ip);
+    let mut config = Config::new(HardwareAddress::Ip);
+    config.random_seed = random_seed();
+    let mut iface = Interface::new(config, &mut dev, now());
+    iface.update_ip_addrs(|addrs| {
+        addrs.push(IpCidr::new(IpAddress::v4(127, 0, 0, 1), 8)).ok();
+        addrs.push(IpCidr::new(IpAddress::Ipv6(Ipv6Address::LOCALHOST), 128)).ok();
+    });
+
+    let mut stack = STACK.lock();
+    stack.ifaces.push(NetIface {
+        name: "lo",
+        iface,
+        port: Port::Loopback(dev),
+        sockets: SocketSet::new(Vec::new()),
+        dhcp: None,
+        default_v4: false,
+    });
+}
+
+/// Add the virtio-net NIC as interface `name`, configured by DHCP.
+pub fn add_virtio(name: &'static str, mut dev: VirtioNet) {
+    let mac = EthernetAddress(dev.mac_address());
+    let mut config = Config::new(HardwareAddress::Ethernet(mac));
+    config.random_seed = random_seed();
+    let iface = Interface::new(config, &mut dev, now());
+
+    let mut sockets = SocketSet::new(Vec::new());
+    let dhcp = sockets.add(dhcpv4::Socket::new());
+    info!("[net] {}: MAC {}", name, mac);
+
+    let mut stack = STACK.lock();
+    stack.ifaces.push(NetIface {
+        name,
+        iface,
+        port: Port::Virtio(dev),
+        sockets,
+        dhcp: Some(dhcp),
+        default_v4: false,
+    });
+}
+
+/// Give up on DHCP for any interface that has no IPv4 address yet and use
+/// the QEMU user networking defaults instead.
+pub fn dhcp_fallback() {
+    let mut stack = STACK.lock();
+    for iface in stack.ifaces.iter_mut() {
+        if let Some(handle) = iface.dhcp {
+            if iface.iface.ipv4_addr().is_none() {
+                warn!("[net] {}: no DHCP answer, using {}", iface.name, FALLBACK_ADDR);
+                iface.sockets.remove(handle);
+                iface.dhcp = None;
+                iface.set_ipv4(FALLBACK_ADDR, Some(FALLBACK_GATEWAY));
+            }
+        }
+    }
+}
+
+/// Poll every interface, waking the threads waiting on a socket if any
+/// socket may have changed.
+pub fn poll() {
+    STACK.lock().poll();
+}
+
+/// Acknowledge the NIC's interrupt.
+pub fn ack_interrupts() {
+    let mut stack = STACK.lock();
+    for iface in stack.ifaces.iter_mut() {
+        match &mut iface.port {
+            Port::Loopback(_) => {}
+            Port::Virtio(dev) => dev.ack_interrupt(),
+        }
+    }
+}
+
+/// Names and addresses of the interfaces, for diagnostics.
+pub fn interfaces() -> Vec<(&'static str, Vec<IpCidr>)> {
+    let stack = STACK.lock();
+    stack.ifaces.iter().map(|i| (i.name, i.iface.ip_addrs().to_vec())).collect()
+}
+
+impl Stack {
+    pub(crate) fn poll(&mut self) {
+        let now = now();
+        let mut changed = false;
+        for iface in self.ifaces.iter_mut() {
+            changed |= iface.poll(now);
+        }
+        let ifaces = &mut self.ifaces;
+        self.closing.retain(|&(i, handle)| {
+            let sockets = &mut ifaces[i].sockets;
+            match sockets.get::<tcp::Socket>(handle).state() {
+                tcp::State::Closed | tcp::State::TimeWait => {
+                    sockets.remove(handle);
+                    false
+                }
+                _ => true,
+            }
+        });
+        if changed {
+            self.waiters.wake_all();
+        }
+    }
+
+    /// The interface traffic to `addr` goes out of: the one that has it,
+    /// then one on its network, then one with a default route.
+    pub(crate) fn route(&self, addr: &IpAddress) -> Result<usize, SocketError> {
+        let find = |f: &dyn Fn(&NetIface) -> bool| self.ifaces.iter().position(f);
+        find(&|i| i.has_addr(addr))
+            .or_else(|| find(&|i| i.is_on_link(addr)))
+            .or_else(|| match addr {
+                IpAddress::Ipv4(_) => find(&|i| i.default_v4),
+                IpAddress::Ipv6(_) => None,
+            })
+            .ok_or(SocketError::NetworkUnreachable)
+    }
+
+    /// The interfaces a socket bound to `addr` uses: the one that has the
+    /// address, or all of them for the unspecified address.
+    pub(crate) fn ifaces_for(&self, addr: Option<IpAddress>) -> Result<Vec<usize>, SocketError> {
+        match addr {
+            None => Ok((0..self.ifaces.len()).collect()),
+            Some(addr) => match self.ifaces.iter().position(|i| i.has_addr(&addr)) {
+                Some(i) => Ok(vec![i]),
+                None => Err(SocketError::AddressNotAvailable),
+            },
+        }
+    }
+
+    /// Take `port` on `addr`, or a free ephemeral port if `port` is 0.
+    /// A port is taken if the same protocol already uses it on the same
+    /// address, or either address is the unspecified one.
+    pub(crate) fn claim_port(
+        &mut self,
+        proto: Proto,
+        addr: Option<IpAddress>,
+        port: u16,
+    ) -> Result<u16, SocketError> {
+        let clashes = |users: &Vec<Option<IpAddress>>| {
+            users.iter().any(|a| a.is_none() || addr.is_none() || *a == addr)
+        };
+        let port = if port != 0 {
+            if self.ports.get(&(proto, port)).map_or(false, clashes) {
+                return Err(SocketError::AddressInUse);
+            }
+            port
+        } else {
+            let range = (EPHEMERAL_LAST - EPHEMERAL_FIRST) as u32 + 1;
+            let mut found = None;
+            for _ in 0..range {
+                let candidate = self.next_ephemeral;
+                self.next_ephemeral = if candidate == EPHEMERAL_LAST {
+                    EPHEMERAL_FIRST
+                } else {
+                    candidate + 1
+                };
+                if !self.ports.get(&(proto, candidate)).map_or(false, clashes) {
+                    found = Some(candidate);
+                    break;
+                }
+            }
+            found.ok_or(SocketError::AddressInUse)?
+        };
+        self.ports.entry((proto, port)).or_default().push(addr);
+        Ok(port)
+    }
+
+    /// Give back a port taken with [`claim_port`](Self::claim_port).
+    pub(crate) fn release_port(&mut self, proto: Proto, addr: Option<IpAddress>, port: u16) {
+        if let Some(users) = self.ports.get_mut(&(proto, port)) {
+            if let Some(i) = users.iter().position(|a| *a == addr) {
+                users.remove(i);
+            }
+            if users.is_empty() {
+                self.ports.remove(&(proto, port));
+            }
+        }
+    }
+
+    /// Hand a closed TCP socket over to be removed once it has finished.
+    pub(crate) fn retire_tcp(&mut self, iface: usize, handle: SocketHandle) {
+        self.closing.push((iface, handle));
+    }
+}
+
+pub(crate) fn new_tcp_socket() -> tcp::Socket<'static> {
+    tcp::Socket::new(
+        tcp::SocketBuffer::new(vec![0; TCP_BUFFER]),
+        tcp::SocketBuffer::new(vec![0; TCP_BUFFER]),
+    )
+}
+
+pub(crate) fn new_udp_socket() -> udp::Socket<'static> {
+    let buffer = || {
+        udp::PacketBuffer::new(vec![udp::PacketMetadata::EMPTY; UDP_PACKETS], vec![0; UDP_BUFFER])
+    };
+    udp::Socket::new(buffer(), buffer())
+}
@@ -0,0 +1,904 @@
+//! Internet sockets (`AF_INET`, `AF_INET6`): TCP and UDP over smoltcp.
+//!
+//! A socket is a `FileHandle` of kind `"inet_socket"`.  It holds handles
+//! to smoltcp sockets in the interfaces' socket sets (see the module
+//! above); its own state — what it is bound to, shutdown flags, options —
+//! is under its lock, which is always taken before the stack's.
+//!
+//! Addresses cross this API as `core::net::SocketAddr`.  An `AF_INET6`
+//! socket is dual-stack: IPv4 peers appear as v4-mapped addresses, and a
+//! v4-mapped address names an IPv4 peer.  An `AF_INET` socket bound to the
+//! unspecified address never sees IPv6 traffic.
+//!
+//! A TCP listener keeps up to its backlog of smoltcp sockets listening on
+//! each interface; `accept` takes one that has connected and puts a fresh
+//! one in its place.  A closed connection stays in its set until TCP has
+//! finished with it.
+//!
+//! The syscalls are in osl's `socket` module.
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use core::task::Waker;
+
+use smoltcp::iface::SocketHandle;
+use smoltcp::socket::{tcp, udp};
+use smoltcp::time::Duration;
+use smoltcp::wire::{IpAddress, IpEndpoint, IpListenEndpoint};
+
+use libkernel::file::{FileError, FileHandle, O_NONBLOCK, O_RDWR};
+use libkernel::poll::{POLLERR, POLLHUP, POLLRDHUP, POLL_READABLE, POLL_WRITABLE};
+use libkernel::socket::{
+    gather, scatter, wait, SocketError, SocketType, MSG_DONTWAIT, MSG_PEEK, MSG_WAITALL,
+};
+use libkernel::spin_mutex::SpinMutex;
+use libkernel::time::NSEC_PER_SEC;
+
+use super::{new_tcp_socket, new_udp_socket, Proto, Stack, MAX_BACKLOG, STACK, TCP_BUFFER, UDP_BUFFER};
+
+/// How long a connection attempt may go unanswered before `ETIMEDOUT`.
+const CONNECT_TIMEOUT_SECS: u64 = 75;
+/// Keep-alive probe interval under `SO_KEEPALIVE`.
+const KEEPALIVE_SECS: u64 = 75;
+
+/// The address family a socket was created with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Family {
+    Inet,
+    Inet6,
+}
+
+/// What [`InetSocket::recv`] read.
+pub struct Received {
+    /// Bytes copied to the caller's buffers.
+    pub len: usize,
+    /// Length of the datagram read; more than `len` if it was truncated.
+    pub msg_len: usize,
+    /// The sender of a datagram.
+    pub from: Option<SocketAddr>,
+}
+
+enum Conn {
+    /// A stream socket not yet listening or connected, or a datagram
+    /// socket not yet bound.
+    Unconnected,
+    Listening(Vec<(usize, SocketHandle)>),
+    /// A stream socket's connection, from the first SYN on.
+    Stream(usize, SocketHandle),
+    /// A datagram socket's smoltcp sockets, one per interface it is
+    /// bound to.
+    Datagram(Vec<(usize, SocketHandle)>),
+}
+
+struct State {
+    /// What the socket is bound to, explicitly or on first use.
+    local: Option<IpListenEndpoint>,
+    /// `local`'s port is claimed by this socket; an accepted connection
+    /// shares its listener's.
+    claimed: bool,
+    conn: Conn,
+    /// A datagram socket's default destination.
+    peer: Option<IpEndpoint>,
+    /// A connection attempt is in progress, and when it started.
+    connecting: bool,
+    connect_start_ns: u64,
+    /// Why the last non-blocking connect failed, for `SO_ERROR`.
+    error: Option<SocketError>,
+    rd_shut: bool,
+    wr_shut: bool,
+    reuseaddr: bool,
+    keepalive: bool,
+    nodelay: bool,
+    rcvtimeo: Option<u64>,
+    sndtimeo: Option<u64>,
+}
+
+pub struct InetSocket {
+    family: Family,
+    ty: SocketType,
+    state: SpinMutex<State>,
+    /// Open fds referring to the socket.
+    refs: AtomicUsize,
+    nonblock: AtomicBool,
+}
+
+impl State {
+    fn new() -> Self {
+        State {
+            local: None,
+            claimed: false,
+            conn: Conn::Unconnected,
+            peer: None,
+            connecting: false,
+            connect_start_ns: 0,
+            error: None,
+            rd_shut: false,
+            wr_shut: false,
+            reuseaddr: false,
+            keepalive: false,
+            nodelay: false,
+            rcvtimeo: None,
+            sndtimeo: None,
+        }
+    }
+}
+
+/// The endpoint `addr` names; a v4-mapped IPv6 address is the IPv4 one.
+fn endpoint(addr: SocketAddr) -> IpEndpoint {
+    match addr {
+        SocketAddr::V6(v6) => match v6.ip().to_ipv4_mapped() {
+            Some(v4) => IpEndpoint::new(IpAddress::Ipv4(v4), v6.port()),
+            None => IpEndpoint::new(IpAddress::Ipv6(*v6.ip()), v6.port()),
+        },
+        SocketAddr::V4(v4) => IpEndpoint::new(IpAddress::Ipv4(*v4.ip()), v4.port()),
+    }
+}
+
+/// What binding to `addr` listens on: the unspecified address is any.
+fn listen_endpoint(addr: SocketAddr) -> IpListenEndpoint {
+    let ep = endpoint(addr);
+    let addr = if addr.ip().is_unspecified() { None } else { Some(ep.addr) };
+    IpListenEndpoint { addr, port: ep.port }
+}
+
+impl InetSocket {
+    pub fn new(family: Family, ty: SocketType) -> Arc<Self> {
+        Arc::new(InetSocket {
+            family,
+            ty,
+            state: SpinMutex::new(State::new()),
+            refs: AtomicUsize::new(1),
+            nonblock: AtomicBool::new(false),
+        })
+    }
+
+    pub fn family(&self) -> Family {
+        self.family
+    }
+
+    pub fn socket_type(&self) -> SocketType {
+        self.ty
+    }
+
+    pub fn set_nonblocking(&self, nonblock: bool) {
+        self.nonblock.store(nonblock, Ordering::Release);
+    }
+
+    fn nonblocking(&self, flags: u32) -> bool {
+        flags & MSG_DONTWAIT != 0 || self.nonblock.load(Ordering::Acquire)
+    }
+
+    fn proto(&self) -> Proto {
+        match self.ty {
+            SocketType::Datagram => Proto::Udp,
+            _ => Proto::Tcp,
+        }
+    }
+
+    /// `ep` as this socket reports it.
+    fn socket_addr(&self, ep: IpEndpoint) -> SocketAddr {
+        match (self.family, ep.addr) {
+            (Family::Inet6, IpAddress::Ipv4(v4)) => {
+                SocketAddr::V6(SocketAddrV6::new(v4.to_ipv6_mapped(), ep.port, 0, 0))
+            }
+            (_, addr) => SocketAddr::new(IpAddr::from(addr), ep.port),
+        }
+    }
+
+    fn unspecified(&self, port: u16) -> SocketAddr {
+        match self.family {
+            Family::Inet => SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), port),
+            Family::Inet6 => SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), port),
+        }
+    }
+
+    /// Traffic with `addr` belongs to this socket's family.
+    fn admits(&self, addr: &IpAddress) -> bool {
+        self.family == Family::Inet6 || matches!(addr, IpAddress::Ipv4(_))
+    }
+
+    // -----------------------------------------------------------------------
+    // bind / listen / accept / connect
+
+    /// Bind to `addr`; port 0 picks a free one.
+    pub fn bind(&self, addr: SocketAddr) -> Result<(), SocketError> {
+        let mut st = self.state.lock();
+        if st.local.is_some() {
+            return Err(SocketError::InvalidArgument);
+        }
+        let mut stack = STACK.lock();
+        self.bind_locked(&mut st, &mut stack, listen_endpoint(addr))
+    }
+
+    fn bind_locked(
+        &self,
+        st: &mut State,
+        stack: &mut Stack,
+        local: IpListenEndpoint,
+    ) -> Result<(), SocketError> {
+        let ifaces = stack.ifaces_for(local.addr)?;
+        let port = stack.claim_port(self.proto(), local.addr, local.port)?;
+        let local = IpListenEndpoint { addr: local.addr, port };
+        st.local = Some(local);
+        st.claimed = true;
+        if self.ty == SocketType::Datagram {
+            let mut socks = Vec::with_capacity(ifaces.len());
+            for i in ifaces {
+                let mut sock = new_udp_socket();
+                sock.bind(local).map_err(|_| SocketError::InvalidArgument)?;
+                socks.push((i, stack.ifaces[i].sockets.add(sock)));
+            }
+            st.conn = Conn::Datagram(socks);
+        }
+        Ok(())
+    }
+
+    /// Bind to a free port on the unspecified address if not yet bound.
+    fn autobind(&self, st: &mut State, stack: &mut Stack) -> Result<(), SocketError> {
+        if st.local.is_some() {
+            return Ok(());
+        }
+        self.bind_locked(st, stack, IpListenEndpoint { addr: None, port: 0 })
+    }
+
+    /// A fresh smoltcp socket listening on `local` on interface `i`.
+    fn add_listener(
+        &self,
+        st: &State,
+        stack: &mut Stack,
+        i: usize,
+        local: IpListenEndpoint,
+    ) -> Result<SocketHandle, SocketError> {
+        let mut sock = new_tcp_socket();
+        apply_options(st, &mut sock);
+        sock.listen(local).map_err(|_| SocketError::InvalidArgument)?;
+        Ok(stack.ifaces[i].add_tcp(sock))
+    }
+
+    /// Listen for connections, queueing up to `backlog` (at most
+    /// [`MAX_BACKLOG`]) per interface.  An unbound socket gets a free port.
+    pub fn listen(&self, backlog: usize) -> Result<(), SocketError> {
+        if self.ty != SocketType::Stream {
+            return Err(SocketError::NotSupported);
+        }
+        let mut st = self.state.lock();
+        match st.conn {
+            Conn::Unconnected => {}
+            Conn::Listening(_) => return Ok(()),
+            _ => return Err(SocketError::InvalidArgument),
+        }
+        let mut stack = STACK.lock();
+        self.autobind(&mut st, &mut stack)?;
+        let local = st.local.unwrap();
+        let mut listeners = Vec::new();
+        for i in stack.ifaces_for(local.addr)? {
+            for _ in 0..backlog.clamp(1, MAX_BACKLOG) {
+                match self.add_listener(&st, &mut stack, i, local) {
+                    Ok(handle) => listeners.push((i, handle)),
+                    Err(e) => {
+                        for (i, handle) in listeners {
+                            stack.ifaces[i].sockets.remove(handle);
+                        }
+                        return Err(e);
+                    }
+                }
+            }
+        }
+        st.conn = Conn::Listening(listeners);
+        Ok(())
+    }
+
+    /// Take a connection from a listener, with its peer's address.
+    pub fn accept(&self) -> Result<(Arc<InetSocket>, SocketAddr), SocketError> {
+        let timeout = self.state.lock().rcvtimeo;
+        wait(self.nonblocking(0), timeout, |waker| self.try_accept(waker).transpose())
+    }
+
+    fn try_accept(
+        &self,
+        waker: Option<&Waker>,
+    ) -> Result<Option<(Arc<InetSocket>, SocketAddr)>, SocketError> {
+        let mut st = self.state.lock();
+        let mut stack = STACK.lock();
+        let listeners = match &st.conn {
+            Conn::Listening(listeners) => listeners.clone(),
+            _ => return Err(SocketError::InvalidArgument),
+        };
+        let local = st.local.unwrap();
+        for (slot, &(i, handle)) in listeners.iter().enumerate() {
+            let sock = stack.ifaces[i].sockets.get_mut::<tcp::Socket>(handle);
+            if matches!(sock.state(), tcp::State::Listen | tcp::State::SynReceived) {
+                continue;
+            }
+            let ends = sock.local_endpoint().zip(sock.remote_endpoint());
+            let fresh = self.add_listener(&st, &mut stack, i, local)?;
+            if let Conn::Listening(listeners) = &mut st.conn {
+                listeners[slot].1 = fresh;
+            }
+            let (local_ep, remote) = match ends {
+                Some((l, r)) if self.admits(&r.addr) => (l, r),
+                // Reset before it was accepted, or the wrong family.
+                _ => {
+                    stack.ifaces[i].sockets.get_mut::<tcp::Socket>(handle).abort();
+                    stack.retire_tcp(i, handle);
+                    continue;
+                }
+            };
+            let conn = InetSocket::new(self.family, SocketType::Stream);
+            {
+                let mut cs = conn.state.lock();
+                cs.local = Some(local_ep.into());
+                cs.conn = Conn::Stream(i, handle);
+                cs.keepalive = st.keepalive;
+                cs.nodelay = st.nodelay;
+            }
+            return Ok(Some((conn, self.socket_addr(remote))));
+        }
+        stack.waiters.register(waker);
+        Ok(None)
+    }
+
+    /// Connect to `addr`.  A datagram socket only records it as the
+    /// default destination.  A stream connect that cannot finish at once
+    /// on a non-blocking socket fails with `InProgress` and goes on; its
+    /// outcome is reported by `poll` and [`take_error`](Self::take_error).
+    pub fn connect(&self, addr: SocketAddr) -> Result<(), SocketError> {
+        let remote = endpoint(addr);
+        if !self.admits(&remote.addr) {
+            return Err(SocketError::InvalidArgument);
+        }
+        {
+            let mut st = self.state.lock();
+            let mut stack = STACK.lock();
+            if self.ty == SocketType::Datagram {
+                stack.route(&remote.addr)?;
+                self.autobind(&mut st, &mut stack)?;
+                st.peer = Some(remote);
+                return Ok(());
+            }
+            match st.conn {
+                Conn::Unconnected => {}
+                Conn::Listening(_) => return Err(SocketError::InvalidArgument),
+                Conn::Stream(..) if st.connecting => return Err(SocketError::AlreadyInProgress),
+                Conn::Stream(..) | Conn::Datagram(_) => return Err(SocketError::AlreadyConnected),
+            }
+            let i = stack.route(&remote.addr)?;
+            self.autobind(&mut st, &mut stack)?;
+            let mut sock = new_tcp_socket();
+            apply_options(&st, &mut sock);
+            sock.set_timeout(Some(Duration::from_secs(CONNECT_TIMEOUT_SECS)));
+            let iface = &mut stack.ifaces[i];
+            sock.connect(iface.iface.context(), remote, st.local.unwrap())
+                .map_err(|_| SocketError::AddressNotAvailable)?;
+            st.conn = Conn::Stream(i, iface.add_tcp(sock));
+            st.connecting = true;
+            st.connect_start_ns = libkernel::time::monotonic_ns();
+            st.error = None;
+            stack.poll();
+        }
+        let timeout = self.state.lock().sndtimeo;
+        let result = wait(self.nonblocking(0), timeout, |waker| {
+            let mut st = self.state.lock();
+            let mut stack = STACK.lock();
+            settle(&mut st, &mut stack);
+            if st.connecting {
+                stack.waiters.register(waker);
+                return None;
+            }
+            Some(match st.error.take() {
+                Some(e) => Err(e),
+                None => Ok(()),
+            })
+        });
+        match result {
+            Err(SocketError::WouldBlock) => Err(SocketError::InProgress),
+            result => result,
+        }
+    }
+
+    /// Forget a datagram socket's default destination (`AF_UNSPEC`).
+    pub fn disconnect(&self) -> Result<(), SocketError> {
+        if self.ty != SocketType::Datagram {
+            return Err(SocketError::NotSupported);
+        }
+        self.state.lock().peer = None;
+        Ok(())
+    }
+
+    // -----------------------------------------------------------------------
+    // send / recv
+
+    /// Send `bufs` as one datagram, to `to` or the default destination, or
+    /// on the connection, ignoring `to`.
+    pub fn send(&self, bufs: &[&[u8]], to: Option<SocketAddr>, flags: u32) -> Result<usize, SocketError> {
+        let len: usize = bufs.iter().map(|b| b.len()).sum();
+        let data = gather(bufs, 0, len);
+        let nonblock = self.nonblocking(flags);
+        let timeout = self.state.lock().sndtimeo;
+        if self.ty == SocketType::Datagram {
+            if len > UDP_BUFFER {
+                return Err(SocketError::MessageTooLong);
+            }
+            let to = to.map(endpoint);
+            return wait(nonblock, timeout, |waker| self.try_send_to(&data, to, waker).transpose());
+        }
+        let mut sent = 0;
+        let result = wait(nonblock, timeout, |waker| {
+            self.try_send_stream(&data, &mut sent, waker).transpose()
+        });
+        match result {
+            Err(SocketError::WouldBlock) | Err(SocketError::Interrupted) if sent > 0 => Ok(sent),
+            result => result,
+        }
+    }
+
+    fn try_send_stream(
+        &self,
+        data: &[u8],
+        sent: &mut usize,
+        waker: Option<&Waker>,
+    ) -> Result<Option<usize>, SocketError> {
+        let mut st = self.state.lock();
+        let mut stack = STACK.lock();
+        settle(&mut st, &mut stack);
+        if st.wr_shut {
+            return Err(SocketError::BrokenPipe);
+        }
+        let (i, handle) = match st.conn {
+            Conn::Stream(i, handle) if !st.connecting => (i, handle),
+            Conn::Stream(..) => {
+                stack.waiters.register(waker);
+                return Ok(None);
+            }
+            _ => return Err(SocketError::NotConnected),
+        };
+        let sock = stack.ifaces[i].sockets.get_mut::<tcp::Socket>(handle);
+        if !sock.may_send() {
+            return Err(SocketError::BrokenPipe);
+        }
+        *sent += sock.send_slice(&data[*sent..]).unwrap_or(0);
+        stack.poll();
+        if *sent == data.len() {
+            return Ok(Some(*sent));
+        }
+        stack.waiters.register(waker);
+        Ok(None)
+    }
+
+    fn try_send_to(
+        &self,
+        data: &[u8],
+        to: Option<IpEndpoint>,
+        waker: Option<&Waker>,
+    ) -> Result<Option<usize>, SocketError> {
+        let mut st = self.state.lock();
+        let mut stack = STACK.lock();
+        if st.wr_shut {
+            return Err(SocketError::BrokenPipe);
+        }
+        let to = to.or(st.peer).ok_or(SocketError::DestinationRequired)?;
+        if !self.admits(&to.addr) {
+            return Err(SocketError::InvalidArgument);
+        }
+        let i = stack.route(&to.addr)?;
+        self.autobind(&mut st, &mut stack)?;
+        let handle = match &st.conn {
+            Conn::Datagram(socks) => socks.iter().find(|s| s.0 == i).map(|s| s.1),
+            _ => None,
+        }
+        .ok_or(SocketError::NetworkUnreachable)?;
+        let sock = stack.ifaces[i].sockets.get_mut::<udp::Socket>(handle);
+        match sock.send_slice(data, to) {
+            Ok(()) => {
+                stack.poll();
+                Ok(Some(data.len()))
+            }
+            Err(udp::SendError::BufferFull) => {
+                stack.waiters.register(waker);
+                Ok(None)
+            }
+            Err(udp::SendError::Unaddressable) => Err(SocketError::NetworkUnreachable),
+        }
+    }
+
+    /// Receive into `bufs`: one datagram, or what the connection has.
+    /// Reads return 0 at end of stream and after `shutdown` for reading.
+    pub fn recv(&self, bufs: &mut [&mut [u8]], flags: u32) -> Result<Received, SocketError> {
+        let nonblock = self.nonblocking(flags);
+        let timeout = self.state.lock().rcvtimeo;
+        if self.ty == SocketType::Datagram {
+            return wait(nonblock, timeout, |waker| {
+                self.try_recv_from(bufs, flags & MSG_PEEK != 0, waker).transpose()
+            });
+        }
+        let mut got = 0;
+        let result = wait(nonblock, timeout, |waker| {
+            self.try_recv_stream(bufs, flags, &mut got, waker).transpose()
+        });
+        let len = match result {
+            Err(SocketError::WouldBlock) | Err(SocketError::Interrupted) if got > 0 => Ok(got),
+            result => result,
+        }?;
+        Ok(Received { len, msg_len: len, from: None })
+    }
+
+    fn try_recv_stream(
+        &self,
+        bufs: &mut [&mut [u8]],
+        flags: u32,
+        got: &mut usize,
+        waker: Option<&Waker>,
+    ) -> Result<Option<usize>, SocketError> {
+        let mut st = self.state.lock();
+        let mut stack = STACK.lock();
+        settle(&mut st, &mut stack);
+        let (i, handle) = match st.conn {
+            Conn::Stream(i, handle) if !st.connecting => (i, handle),
+            Conn::Stream(..) => {
+                stack.waiters.register(waker);
+                return Ok(None);
+            }
+            _ => return Err(SocketError::NotConnected),
+        };
+        if st.rd_shut {
+            return Ok(Some(*got));
+        }
+        let cap: usize = bufs.iter().map(|b| b.len()).sum();
+        let sock = stack.ifaces[i].sockets.get_mut::<tcp::Socket>(handle);
+        if flags & MSG_PEEK != 0 {
+            if let Ok(data) = sock.peek(cap) {
+                scatter(bufs, 0, data);
+                *got = data.len();
+            }
+        } else {
+            while *got < cap && sock.can_recv() {
+                let start = *got;
+                let n = sock
+                    .recv(|data| {
+                        let n = data.len().min(cap - start);
+                        scatter(bufs, start, &data[..n]);
+                        (n, n)
+                    })
+                    .unwrap_or(0);
+                if n == 0 {
+                    break;
+                }
+                *got += n;
+            }
+        }
+        let open = sock.may_recv();
+        if *got > 0 {
+            // Reading opened the window; tell the peer.
+            stack.poll();
+        }
+        let want_all = flags & MSG_WAITALL != 0 && flags & MSG_PEEK == 0;
+        if *got == cap || (*got > 0 && !want_all) || !open {
+            return Ok(Some(*got));
+        }
+        stack.waiters.register(waker);
+        Ok(None)
+    }
+
+    fn try_recv_from(
+        &self,
+        bufs: &mut [&mut [u8]],
+        peek: bool,
+        waker: Option<&Waker>,
+    ) -> Result<Option<Received>, SocketError> {
+        let st = self.state.lock();
+        let mut stack = STACK.lock();
+        let socks = match &st.conn {
+            Conn::Datagram(socks) => socks.clone(),
+            _ => Vec::new(),
+        };
+        let cap: usize = bufs.iter().map(|b| b.len()).sum();
+        for (i, handle) in socks {
+            let sock = stack.ifaces[i].sockets.get_mut::<udp::Socket>(handle);
+            // Drop what a connected socket, or the family, does not accept.
+            while let Ok((_, meta)) = sock.peek() {
+                let from = meta.endpoint;
+                if self.admits(&from.addr) && st.peer.map_or(true, |p| p == from) {
+                    break;
+                }
+                sock.recv().ok();
+            }
+            let received = if peek { sock.peek().map(|(d, m)| (d, m.endpoint)) } else {
+                sock.recv().map(|(d, m)| (d, m.endpoint))
+            };
+            if let Ok((data, from)) = received {
+                let len = data.len().min(cap);
+                scatter(bufs, 0, &data[..len]);
+                let msg_len = data.len();
+                return Ok(Some(Received { len, msg_len, from: Some(self.socket_addr(from)) }));
+            }
+        }
+        if st.rd_shut {
+            return Ok(Some(Received { len: 0, msg_len: 0, from: None }));
+        }
+        stack.waiters.register(waker);
+        Ok(None)
+    }
+
+    /// Shut the connection down for reading, writing or both.  Shutting
+    /// down for writing sends a FIN.
+    pub fn shutdown(&self, read: bool, write: bool) -> Result<(), SocketError> {
+        let mut st = self.state.lock();
+        let mut stack = STACK.lock();
+        match st.conn {
+            Conn::Stream(i, handle) if !st.connecting => {
+                if write {
+                    stack.ifaces[i].sockets.get_mut::<tcp::Socket>(handle).close();
+                    stack.poll();
+                }
+            }
+            Conn::Datagram(_) if st.peer.is_some() => {}
+            _ => return Err(SocketError::NotConnected),
+        }
+        st.rd_shut |= read;
+        st.wr_shut |= write;
+        stack.waiters.wake_all();
+        Ok(())
+    }
+
+    // -----------------------------------------------------------------------
+    // Names and options
+
+    /// The address the socket is bound to, or the unspecified address.
+    pub fn local_addr(&self) -> SocketAddr {
+        let st = self.state.lock();
+        let stack = STACK.lock();
+        if let Conn::Stream(i, handle) = st.conn {
+            if let Some(ep) = stack.ifaces[i].sockets.get::<tcp::Socket>(handle).local_endpoint() {
+                return self.socket_addr(ep);
+            }
+        }
+        match st.local {
+            Some(IpListenEndpoint { addr: Some(addr), port }) => {
+                self.socket_addr(IpEndpoint::new(addr, port))
+            }
+            Some(IpListenEndpoint { addr: None, port }) => self.unspecified(port),
+            None => self.unspecified(0),
+        }
+    }
+
+    /// The address of the peer of a connection, or a datagram socket's
+    /// default destination.
+    pub fn peer_addr(&self) -> Result<SocketAddr, SocketError> {
+        let st = self.state.lock();
+        let stack = STACK.lock();
+        let peer = match st.conn {
+            Conn::Stream(i, handle) if !st.connecting => {
+                stack.ifaces[i].sockets.get::<tcp::Socket>(handle).remote_endpoint()
+            }
+            _ => st.peer,
+        };
+        peer.map(|ep| self.socket_addr(ep)).ok_or(SocketError::NotConnected)
+    }
+
+    pub fn is_listening(&self) -> bool {
+        matches!(self.state.lock().conn, Conn::Listening(_))
+    }
+
+    /// `(SO_SNDBUF, SO_RCVBUF)`, which are fixed.
+    pub fn buffer_sizes(&self) -> (usize, usize) {
+        match self.ty {
+            SocketType::Datagram => (UDP_BUFFER, UDP_BUFFER),
+            _ => (TCP_BUFFER, TCP_BUFFER),
+        }
+    }
+
+    /// Take the error a non-blocking connect ended with (`SO_ERROR`).
+    pub fn take_error(&self) -> Option<SocketError> {
+        let mut st = self.state.lock();
+        let mut stack = STACK.lock();
+        settle(&mut st, &mut stack);
+        st.error.take()
+    }
+
+    /// `SO_REUSEADDR`.  A port is free again as soon as the socket holding
+    /// it closes, so this is only recorded.
+    pub fn reuseaddr(&self) -> bool {
+        self.state.lock().reuseaddr
+    }
+
+    pub fn set_reuseaddr(&self, on: bool) {
+        self.state.lock().reuseaddr = on;
+    }
+
+    /// `SO_KEEPALIVE`.
+    pub fn keepalive(&self) -> bool {
+        self.state.lock().keepalive
+    }
+
+    pub fn set_keepalive(&self, on: bool) {
+        self.set_tcp_option(|st| st.keepalive = on);
+    }
+
+    /// `TCP_NODELAY`: turn Nagle's algorithm off.
+    pub fn nodelay(&self) -> bool {
+        self.state.lock().nodelay
+    }
+
+    pub fn set_nodelay(&self, on: bool) {
+        self.set_tcp_option(|st| st.nodelay = on);
+    }
+
+    fn set_tcp_option(&self, f: impl FnOnce(&mut State)) {
+        let mut st = self.state.lock();
+        f(&mut st);
+        if let Conn::Stream(i, handle) = st.conn {
+            let mut stack = STACK.lock();
+            apply_options(&st, stack.ifaces[i].sockets.get_mut::<tcp::Socket>(handle));
+        }
+    }
+
+    /// `(SO_RCVTIMEO, SO_SNDTIMEO)` in nanoseconds; `None` waits forever.
+    pub fn timeouts(&self) -> (Option<u64>, Option<u64>) {
+        let st = self.state.lock();
+        (st.rcvtimeo, st.sndtimeo)
+    }
+
+    pub fn set_timeouts(&self, rcvtimeo: Option<u64>, sndtimeo: Option<u64>) {
+        let mut st = self.state.lock();
+        st.rcvtimeo = rcvtimeo;
+        st.sndtimeo = sndtimeo;
+    }
+
+    /// The last reference has gone: close the connection gracefully, drop
+    /// the connections a listener has not accepted, and free the port.
+    fn release(&self) {
+        let mut st = self.state.lock();
+        let mut stack = STACK.lock();
+        match core::mem::replace(&mut st.conn, Conn::Unconnected) {
+            Conn::Unconnected => {}
+            Conn::Stream(i, handle) => {
+                stack.ifaces[i].sockets.get_mut::<tcp::Socket>(handle).close();
+                stack.retire_tcp(i, handle);
+            }
+            Conn::Listening(listeners) => {
+                for (i, handle) in listeners {
+                    stack.ifaces[i].sockets.get_mut::<tcp::Socket>(handle).abort();
+                    stack.retire_tcp(i, handle);
+                }
+            }
+            Conn::Datagram(socks) => {
+                for (i, handle) in socks {
+                    stack.ifaces[i].sockets.remove(handle);
+                }
+            }
+        }
+        if st.claimed {
+            if let Some(local) = st.local {
+                stack.release_port(self.proto(), local.addr, local.port);
+            }
+        }
+        stack.poll();
+    }
+}
+
+/// Carry the socket's TCP options over to its smoltcp socket.
+fn apply_options(st: &State, sock: &mut tcp::Socket<'static>) {
+    sock.set_nagle_enabled(!st.nodelay);
+    sock.set_keep_alive(if st.keepalive { Some(Duration::from_secs(KEEPALIVE_SECS)) } else { None });
+}
+
+/// Note the outcome of a connection attempt once there is one: on
+/// success the attempt's timeout no longer applies, and on failure the
+/// socket can connect again.
+fn settle(st: &mut State, stack: &mut Stack) {
+    let (i, handle) = match st.conn {
+        Conn::Stream(i, handle) if st.connecting => (i, handle),
+        _ => return,
+    };
+    let sock = stack.ifaces[i].sockets.get_mut::<tcp::Socket>(handle);
+    match sock.state() {
+        tcp::State::SynSent | tcp::State::SynReceived => {}
+        tcp::State::Closed => {
+            // smoltcp closes the socket on a reset, or when its timeout
+            // runs out with the SYN unanswered.
+            let elapsed = libkernel::time::monotonic_ns() - st.connect_start_ns;
+            let timed_out = elapsed >= CONNECT_TIMEOUT_SECS * NSEC_PER_SEC;
+            st.error = Some(if timed_out { SocketError::TimedOut } else { SocketError::ConnectionRefused });
+            stack.ifaces[i].sockets.remove(handle);
+            st.conn = Conn::Unconnected;
+            st.connecting = false;
+        }
+        _ => {
+            sock.set_timeout(None);
+            st.connecting = false;
+        }
+    }
+}
+
+impl FileHandle for InetSocket {
+    fn read(&self, buf: &mut [u8]) -> Result<usize, FileError> {
+        Ok(self.recv(&mut [buf], 0)?.len)
+    }
+
+    fn write(&self, buf: &[u8]) -> Result<usize, FileError> {
+        Ok(self.send(&[buf], None, 0)?)
+    }
+
+    fn close(&self) -> Option<usize> {
+        if self.refs.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.release();
+        }
+        None
+    }
+
+    fn on_dup(&self) {
+        self.refs.fetch_add(1, Ordering::AcqRel);
+    }
+
+    fn status_flags(&self) -> u32 {
+        if self.nonblock.load(Ordering::Acquire) { O_RDWR | O_NONBLOCK } else { O_RDWR }
+    }
+
+    fn set_status_flags(&self, flags: u32) {
+        self.set_nonblocking(flags & O_NONBLOCK != 0);
+    }
+
+    /// A listener is readable with a connection to accept.  A connection
+    /// is readable with data queued or once the peer has finished, and
+    /// writable with room in its send buffer; it reports `POLLHUP` once it
+    /// has closed.  A datagram socket is readable with a datagram queued.
+    /// A failed connect reports `POLLERR`.
+    fn poll_events(&self, waker: Option<&Waker>) -> u32 {
+        let mut st = self.state.lock();
+        let mut stack = STACK.lock();
+        settle(&mut st, &mut stack);
+        stack.waiters.register(waker);
+        let mut mask = 0;
+        if st.error.is_some() {
+            mask |= POLLERR;
+        }
+        if st.rd_shut {
+            mask |= POLL_READABLE | POLLRDHUP;
+        }
+        match &st.conn {
+            Conn::Unconnected if self.ty == SocketType::Stream => mask |= POLL_WRITABLE | POLLHUP,
+            Conn::Unconnected => mask |= POLL_WRITABLE,
+            Conn::Listening(listeners) => {
+                let ready = listeners.iter().any(|&(i, handle)| {
+                    let state = stack.ifaces[i].sockets.get::<tcp::Socket>(handle).state();
+                    !matches!(state, tcp::State::Listen | tcp::State::SynReceived)
+                });
+                if ready {
+                    mask |= POLL_READABLE;
+                }
+            }
+            Conn::Stream(..) if st.connecting => {}
+            &Conn::Stream(i, handle) => {
+                let sock = stack.ifaces[i].sockets.get::<tcp::Socket>(handle);
+                if sock.can_recv() {
+                    mask |= POLL_READABLE;
+                }
+                if !sock.may_recv() {
+                    mask |= POLL_READABLE | POLLRDHUP;
+                }
+                if sock.can_send() || st.wr_shut || !sock.may_send() {
+                    mask |= POLL_WRITABLE;
+                }
+                if sock.state() == tcp::State::Closed || (st.rd_shut && st.wr_shut) {
+                    mask |= POLLHUP;
+                }
+            }
+            Conn::Datagram(socks) => {
+                for &(i, handle) in socks {
+                    let sock = stack.ifaces[i].sockets.get::<udp::Socket>(handle);
+                    if sock.can_recv() {
+                        mask |= POLL_READABLE;
+                    }
+                    if sock.can_send() {
+                        mask |= POLL_WRITABLE;
+                    }
+                }
+            }
+        }
+        mask
+    }
+
+    fn kind(&self) -> &'static str { "inet_socket" }
+}
@@ -0,0 +1,37 @@
+//! `/dev/fb0` — the BGA linear framebuffer.
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use libkernel::consts::PAGE_SIZE;
+use libkernel::file::FdObject;
+use libkernel::shmem::SharedMemInner;
+use x86_64::PhysAddr;
+
+use super::VfsError;
+
+/// A shared-memory object wrapping the linear framebuffer's physical
+/// frames, for `mmap(MAP_SHARED)`.  The frames are non-owning (MMIO frames
+/// are never freed).  Fails with `NoDevice` if there is no framebuffer.
+pub fn open_framebuffer() -> Result<FdObject, VfsError> {
+    let (lfb_phys, lfb_size) = libkernel::framebuffer::get_lfb_phys()
+        .ok_or(VfsError::NoDevice)?;
+
+    let page_size = PAGE_SIZE as u64;
+    let num_pages = (lfb_size + page_size - 1) / page_size;
+
+    let mut frames = Vec::with_capacity(num_pages as usize);
+    for i in 0..num_pages {
+        frames.push(PhysAddr::new(lfb_phys + i * page_size));
+    }
+
+    let inner = SharedMemInner::from_existing(frames, lfb_size as usize);
+    Ok(FdObject::SharedMem(Arc::new(inner)))
+}
+
+/// Like `framebuffer_open`, opening the node hands the display to the
+/// caller: kernel output stops drawing over it.
+pub(super) fn open(_flags: u32) -> Result<FdObject, VfsError> {
+    let obj = open_framebuffer()?;
+    libkernel::vga_buffer::suppress_display(libkernel::process::current_pid());
+    Ok(obj)
+}
@@ -0,0 +1,105 @@
+//! `/dev/null`, `/dev/zero` and `/dev/full`.
+//!
+//! None of them has a position: reads and writes ignore the offset of
+//! `pread` / `pwrite`, and `lseek` always returns 0, as on Linux.
+
+use alloc::sync::Arc;
+use libkernel::file::{FdObject, FileError, FileHandle};
+
+use super::VfsError;
+
+/// Reads return end of file; writes are discarded.
+pub struct NullHandle;
+
+impl FileHandle for NullHandle {
+    fn read(&self, _buf: &mut [u8]) -> Result<usize, FileError> {
+        Ok(0)
+    }
+
+    fn write(&self, buf: &[u8]) -> Result<usize, FileError> {
+        Ok(buf.len())
+    }
+
+    fn read_at(&self, _offset: u64, buf: &mut [u8]) -> Result<usize, FileError> {
+        self.read(buf)
+    }
+
+    fn write_at(&self, _offset: u64, buf: &[u8]) -> Result<usize, FileError> {
+        self.write(buf)
+    }
+
+    fn seek(&self, _offset: i64, _whence: u32) -> Result<u64, FileError> {
+        Ok(0)
+    }
+
+    fn kind(&self) -> &'static str { "null" }
+}
+
+/// Reads return zeros; writes are discarded.  A private mapping reads as
+/// zeros through [`read_at`](FileHandle::read_at), as anonymous memory.
+pub struct ZeroHandle;
+
+impl FileHandle for ZeroHandle {
+    fn read(&self, buf: &mut [u8]) -> Result<usize, FileError> {
+        buf.fill(0);
+        Ok(buf.len())
+    }
+
+    fn write(&self, buf: &[u8]) -> Result<usize, FileError> {
+        Ok(buf.len())
+    }
+
+    fn read_at(&self, _offset: u64, buf: &mut [u8]) -> Result<usize, FileError> {
+        self.read(buf)
+    }
+
+    fn write_at(&self, _offset: u64, buf: &[u8]) -> Result<usize, FileError> {
+        self.write(buf)
+    }
+
+    fn seek(&self, _offset: i64, _whence: u32) -> Result<u64, FileError> {
+        Ok(0)
+    }
+
+    fn kind(&self) -> &'static str { "zero" }
+}
+
+/// Reads return zeros; writes fail with `ENOSPC`.
+pub struct FullHandle;
+
+impl FileHandle for FullHandle {
+    fn read(&self, buf: &mut [u8]) -> Result<usize, FileError> {
+        buf.fill(0);
+        Ok(buf.len())
+    }
+
+    fn write(&self, _buf: &[u8]) -> Result<usize, FileError> {
+        Err(FileError::NoSpace)
+    }
+
+    fn read_at(&self, _offset: u64, buf: &mut [u8]) -> Result<usize, FileError> {
+        self.read(buf)
+    }
+
+    fn write_at(&self, _offset: u64, buf: &[u8]) -> Result<usize, FileError> {
+        self.write(buf)
+    }
+
+    fn seek(&self, _offset: i64, _whence: u32) -> Result<u64, FileError> {
+        Ok(0)
+    }
+
+    fn kind(&self) -> &'static str { "full" }
+}
+
+pub(super) fn open_null(_flags: u32) -> Result<FdObject, VfsError> {
+    Ok(FdObject::File(Arc::new(NullHandle)))
+}
+
+pub(super) fn open_zero(_flags: u32) -> Result<FdObject, VfsError> {
+    Ok(FdObject::File(Arc::new(ZeroHandle)))
+}
+
+pub(super) fn open_full(_flags: u32) -> Result<FdObject, VfsError> {
+    Ok(FdObject::File(Arc::new(FullHandle)))
+}
@@ -0,0 +1,186 @@
+//! devfs — device nodes under `/dev`.
+//!
+//! The directory is flat and its entries come from a global registry, so a
+//! driver publishes a node with [`register`] whether or not `/dev` is
+//! mounted yet.  Character nodes are never read through the VFS: opening
+//! one calls its [`OpenFn`], which returns the kernel object the file
+//! descriptor refers to (see [`super::open_device`]).  Block nodes are
+//! read and written by byte offset like regular files.
+
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+use core::future::Future;
+use core::pin::Pin;
+use lazy_static::lazy_static;
+use libkernel::file::FdObject;
+use libkernel::spin_mutex::SpinMutex as Mutex;
+
+use super::{makedev, FileType, VfsDirEntry, VfsError, VfsStat, S_IFBLK, S_IFCHR, S_IFDIR};
+
+mod fb;
+mod mem;
+mod random;
+mod tty;
+
+pub use fb::open_framebuffer;
+pub use mem::{FullHandle, NullHandle, ZeroHandle};
+pub use random::RandomHandle;
+pub use tty::TtyHandle;
+
+/// Opens a character node.  `flags` are the `open` flags.  Runs in the
+/// opening process's syscall context and must not block.
+pub type OpenFn = fn(flags: u32) -> Result<FdObject, VfsError>;
+
+/// Future returned by [`BlockDevice`] methods.
+pub type DevFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T, VfsError>> + Send + 'a>>;
+
+/// A disk published as a block node.  devfs keeps requests inside
+/// [`size`](BlockDevice::size), so implementations need not check bounds.
+pub trait BlockDevice: Send + Sync {
+    /// Size of the device in bytes.
+    fn size(&self) -> u64;
+    /// Read `len` bytes at byte `offset`.
+    fn read_at(&self, offset: u64, len: usize) -> DevFuture<'_, Vec<u8>>;
+    /// Write `data` at byte `offset`, returning the number of bytes written.
+    fn write_at<'a>(&'a self, offset: u64, data: &'a [u8]) -> DevFuture<'a, usize>;
+}
+
+#[derive(Clone)]
+pub enum DevNode {
+    /// A character device: each open creates a handle of its own.
+    Char(OpenFn),
+    /// A block device, opened as a regular file of fixed size.
+    Block(Arc<dyn BlockDevice>),
+}
+
+lazy_static! {
+    /// Each node with its device number.
+    static ref NODES: Mutex<BTreeMap<String, (DevNode, u64)>> = Mutex::new(BTreeMap::new());
+}
+
+/// Publish `node` as `/dev/<name>` with device number `rdev` (see
+/// [`makedev`]), replacing any node of that name.
+pub fn register(name: &str, rdev: u64, node: DevNode) {
+    debug_assert!(!name.is_empty() && !name.contains('/'));
+    NODES.lock().insert(name.to_string(), (node, rdev));
+}
+
+/// Remove `/dev/<name>`.  Descriptors already open keep working.
+pub fn unregister(name: &str) {
+    NODES.lock().remove(name);
+}
+
+/// Register the nodes the kernel itself provides: `null`, `zero`, `full`,
+/// `random`, `urandom`, `console`, `tty`, and `fb0` if there is a
+/// framebuffer.  They have Linux's device numbers.
+pub fn register_builtin() {
+    register("null",    makedev(1, 3), DevNode::Char(mem::open_null));
+    register("zero",    makedev(1, 5), DevNode::Char(mem::open_zero));
+    register("full",    makedev(1, 7), DevNode::Char(mem::open_full));
+    register("random",  makedev(1, 8), DevNode::Char(random::open));
+    register("urandom", makedev(1, 9), DevNode::Char(random::open));
+    register("tty",     makedev(5, 0), DevNode::Char(tty::open_tty));
+    register("console", makedev(5, 1), DevNode::Char(tty::open_console));
+    if libkernel::framebuffer::get_lfb_phys().is_some() {
+        register("fb0", makedev(29, 0), DevNode::Char(fb::open));
+    }
+}
+
+/// Look up the node at `path` (relative to the mount) and its device
+/// number.  The registry lock is released before returning.
+fn entry(path: &str) -> Result<(DevNode, u64), VfsError> {
+    let name = path.strip_prefix('/').unwrap_or(path);
+    NODES.lock().get(name).cloned().ok_or(VfsError::NotFound)
+}
+
+fn node(path: &str) -> Result<DevNode, VfsError> {
+    entry(path).map(|(node, _)| node)
+}
+
+fn block(path: &str) -> Result<Arc<dyn BlockDevice>, VfsError> {
+    match node(path)? {
+        DevNode::Block(dev) => Ok(dev),
+        DevNode::Char(_) => Err(VfsError::InvalidArgument),
+    }
+}
+
+pub struct DevVfs;
+
+impl DevVfs {
+    pub async fn list_dir(&self, path: &str) -> Result<Vec<VfsDirEntry>, VfsError> {
+        if path != "/" {
+            return Err(match node(path) {
+                Ok(_) => VfsError::NotADirectory,
+                Err(e) => e,
+            });
+        }
+        let nodes = NODES.lock();
+        Ok(nodes.iter().map(|(name, (node, _))| match node {
+            DevNode::Block(dev) => VfsDirEntry { name: name.clone(), kind: FileType::BlockDevice, size: dev.size() },
+            DevNode::Char(_) => VfsDirEntry { name: name.clone(), kind: FileType::CharDevice, size: 0 },
+        }).collect())
+    }
+
+    pub async fn read_file(&self, path: &str) -> Result<Vec<u8>, VfsError> {
+        let dev = block(path)?;
+        let len = usize::try_from(dev.size()).map_err(|_| VfsError::FileTooLarge)?;
+        dev.read_at(0, len).await
+    }
+
+    /// Reads stop at the end of the device.
+    pub async fn read_at(&self, path: &str, offset: u64, len: usize) -> Result<Vec<u8>, VfsError> {
+        let dev = block(path)?;
+        let size = dev.size();
+        if offset >= size || len == 0 {
+            return Ok(Vec::new());
+        }
+        let len = len.min((size - offset).min(usize::MAX as u64) as usize);
+        dev.read_at(offset, len).await
+    }
+
+    /// Nodes are owned by root with mode `0666`; character nodes report
+    /// size 0.
+    pub async fn stat(&self, path: &str) -> Result<VfsStat, VfsError> {
+        if path == "/" {
+            return Ok(VfsStat { mode: S_IFDIR | 0o755, nlink: 2, ino: 1, ..VfsStat::default() });
+        }
+        let (node, rdev) = entry(path)?;
+        let (kind, size) = match node {
+            DevNode::Block(dev) => (S_IFBLK, dev.size()),
+            DevNode::Char(_) => (S_IFCHR, 0),
+        };
+        Ok(VfsStat { mode: kind | 0o666, nlink: 1, size, rdev, ..VfsStat::default() })
+    }
+
+    /// Writes stop at the end of the device; none fit past it.
+    pub async fn write_at(&self, path: &str, offset: u64, data: &[u8]) -> Result<usize, VfsError> {
+        let dev = block(path)?;
+        let size = dev.size();
+        if data.is_empty() {
+            return Ok(0);
+        }
+        if offset >= size {
+            return Err(VfsError::NoSpace);
+        }
+        let len = data.len().min((size - offset).min(usize::MAX as u64) as usize);
+        dev.write_at(offset, &data[..len]).await
+    }
+
+    /// A block device has a fixed size, so truncation (`O_TRUNC`) is a
+    /// no-op, as on Linux.
+    pub async fn truncate(&self, path: &str, _size: u64) -> Result<(), VfsError> {
+        block(path).map(|_| ())
+    }
+
+    /// The character node at `path`, opened; `None` if `path` is not one.
+    pub fn open_node(&self, path: &str, flags: u32) -> Option<Result<FdObject, VfsError>> {
+        match node(path) {
+            Ok(DevNode::Char(open)) => Some(open(flags)),
+            _ => None,
+        }
+    }
+}
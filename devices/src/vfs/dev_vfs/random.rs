@@ -0,0 +1,42 @@
+//! `/dev/random` and `/dev/urandom`.
+
+use alloc::sync::Arc;
+use libkernel::file::{FdObject, FileError, FileHandle};
+
+use super::VfsError;
+
+/// Reads return output from the kernel generator; writes are mixed into the
+/// entropy pool without being credited.
+pub struct RandomHandle;
+
+impl FileHandle for RandomHandle {
+    fn read(&self, buf: &mut [u8]) -> Result<usize, FileError> {
+        libkernel::random::fill_bytes(buf);
+        Ok(buf.len())
+    }
+
+    fn write(&self, buf: &[u8]) -> Result<usize, FileError> {
+        libkernel::random::add_device_randomness(buf);
+        Ok(buf.len())
+    }
+
+    // No position: the offset is ignored and `lseek` returns 0.
+
+    fn read_at(&self, _offset: u64, buf: &mut [u8]) -> Result<usize, FileError> {
+        self.read(buf)
+    }
+
+    fn write_at(&self, _offset: u64, buf: &[u8]) -> Result<usize, FileError> {
+        self.write(buf)
+    }
+
+    fn seek(&self, _offset: i64, _whence: u32) -> Result<u64, FileError> {
+        Ok(0)
+    }
+
+    fn kind(&self) -> &'static str { "random" }
+}
+
+pub(super) fn open(_flags: u32) -> Result<FdObject, VfsError> {
+    Ok(FdObject::File(Arc::new(RandomHandle)))
+}
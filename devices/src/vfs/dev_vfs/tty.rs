@@ -0,0 +1,100 @@
+//! `/dev/console` and `/dev/tty`.
+
+use alloc::sync::Arc;
+use core::task::{Context, Poll, Waker};
+use libkernel::file::{ConsoleHandle, FdObject, FileError, FileHandle};
+use libkernel::poll::{POLLERR, POLLHUP, POLL_READABLE, POLL_WRITABLE};
+use libkernel::process;
+
+use super::VfsError;
+
+pub(super) fn open_console(_flags: u32) -> Result<FdObject, VfsError> {
+    Ok(FdObject::File(Arc::new(ConsoleHandle { readable: true })))
+}
+
+/// The opening process's terminal: the console or pipe its standard
+/// descriptors lead to when `/dev/tty` is opened.  Reads go to the handle
+/// behind fd 0, writes to the one behind fd 2 (or fd 1), so output still
+/// reaches the terminal when stdout is redirected to a file.
+pub struct TtyHandle {
+    input:  Option<Arc<dyn FileHandle>>,
+    output: Option<Arc<dyn FileHandle>>,
+}
+
+/// True if `h` can stand for a terminal: the console, a pipe to a
+/// terminal emulator, or another `/dev/tty` descriptor.
+fn is_terminal(h: &Arc<dyn FileHandle>, reading: bool) -> bool {
+    match h.kind() {
+        "console" | "tty" => true,
+        "pipe_r" => reading,
+        "pipe_w" => !reading,
+        _ => false,
+    }
+}
+
+pub(super) fn open_tty(_flags: u32) -> Result<FdObject, VfsError> {
+    let pid = process::current_pid();
+    let std_fd = |fd: usize, reading: bool| {
+        process::with_process_ref(pid, |p| p.get_fd(fd).ok())
+            .flatten()
+            .and_then(|obj| obj.as_file().cloned())
+            .filter(|h| is_terminal(h, reading))
+    };
+    let input = std_fd(0, true);
+    let output = std_fd(2, false).or_else(|| std_fd(1, false));
+    if input.is_none() && output.is_none() {
+        return Err(VfsError::NoDevice);
+    }
+    // This handle holds a reference of its own to each end; a pipe writer
+    // counts it so the reader does not see end of file while it is open.
+    for h in input.iter().chain(output.iter()) {
+        h.on_dup();
+    }
+    Ok(FdObject::File(Arc::new(TtyHandle { input, output })))
+}
+
+impl FileHandle for TtyHandle {
+    fn read(&self, buf: &mut [u8]) -> Result<usize, FileError> {
+        self.input.as_ref().ok_or(FileError::BadFd)?.read(buf)
+    }
+
+    fn write(&self, buf: &[u8]) -> Result<usize, FileError> {
+        self.output.as_ref().ok_or(FileError::BadFd)?.write(buf)
+    }
+
+    fn poll_read(&self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<Result<usize, FileError>> {
+        match self.input {
+            Some(ref h) => h.poll_read(cx, buf),
+            None => Poll::Ready(Err(FileError::BadFd)),
+        }
+    }
+
+    fn poll_write(&self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize, FileError>> {
+        match self.output {
+            Some(ref h) => h.poll_write(cx, buf),
+            None => Poll::Ready(Err(FileError::BadFd)),
+        }
+    }
+
+    /// Read readiness from the input end, write readiness from the output
+    /// end.
+    fn poll_events(&self, waker: Option<&Waker>) -> u32 {
+        let input = self.input.as_ref().map_or(0, |h| h.poll_events(waker));
+        let output = self.output.as_ref().map_or(0, |h| h.poll_events(waker));
+        (input & (POLL_READABLE | POLLHUP)) | (output & (POLL_WRITABLE | POLLERR))
+    }
+
+    fn close(&self) -> Option<usize> {
+        let woken = self.input.as_ref().and_then(|h| h.close());
+        let woken_out = self.output.as_ref().and_then(|h| h.close());
+        woken.or(woken_out)
+    }
+
+    fn on_dup(&self) {
+        for h in self.input.iter().chain(self.output.iter()) {
+            h.on_dup();
+        }
+    }
+
+    fn kind(&self) -> &'static str { "tty" }
+}
@@ -1,34 +1,135 @@
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
 use alloc::vec::Vec;
 
-use super::{VfsDirEntry, VfsError};
-use crate::virtio::exfat::{self, ExfatError};
+use libkernel::task::async_mutex::{AsyncMutex, AsyncMutexGuard};
+
+use super::{FileType, VfsDirEntry, VfsError, VfsStat};
+use crate::virtio::exfat::{self, ChainPos, ExfatError};
 pub use crate::virtio::exfat::BlkInbox;
 
 // ---------------------------------------------------------------------------
 
+/// Chain positions remembered for this many recently read files.
+const MAX_CURSORS: usize = 8;
+
 pub struct ExfatVfs {
     inbox: BlkInbox,
+    /// Serialises operations: an update reads and writes many sectors, and
+    /// a concurrent reader or writer must not see it half done.
+    state: AsyncMutex<ExfatState>,
+}
+
+struct ExfatState {
+    /// Where recent `read_at` calls left off in each file's cluster chain,
+    /// most recent first.  Cleared by every update, which may move clusters.
+    cursors: Vec<(String, ChainPos)>,
 }
 
 impl ExfatVfs {
     pub fn new(inbox: BlkInbox) -> Self {
-        Self { inbox }
+        Self { inbox, state: AsyncMutex::new(ExfatState { cursors: Vec::new() }) }
+    }
+
+    /// Identifies this volume to the page cache.
+    pub fn cache_id(&self) -> usize {
+        Arc::as_ptr(&self.inbox) as usize
+    }
+
+    /// Lock the volume for an update.
+    async fn lock_for_update(&self) -> AsyncMutexGuard<'_, ExfatState> {
+        let mut state = self.state.lock().await;
+        state.cursors.clear();
+        state
     }
 
     pub async fn list_dir(&self, path: &str) -> Result<Vec<VfsDirEntry>, VfsError> {
+        let _state = self.state.lock().await;
         let vol = exfat::open_exfat(&self.inbox).await.map_err(map_err)?;
         let entries = exfat::list_dir(&vol, &self.inbox, path).await.map_err(map_err)?;
         Ok(entries.into_iter().map(|e| VfsDirEntry {
-            name:   e.name,
-            is_dir: e.is_dir,
-            size:   e.size,
+            name: e.name,
+            kind: if e.is_dir { FileType::Directory } else { FileType::Regular },
+            size: e.size,
         }).collect())
     }
 
     pub async fn read_file(&self, path: &str) -> Result<Vec<u8>, VfsError> {
+        let _state = self.state.lock().await;
         let vol = exfat::open_exfat(&self.inbox).await.map_err(map_err)?;
         exfat::read_file(&vol, &self.inbox, path).await.map_err(map_err)
     }
+
+    pub async fn read_at(&self, path: &str, offset: u64, len: usize) -> Result<Vec<u8>, VfsError> {
+        let mut state = self.state.lock().await;
+        let vol = exfat::open_exfat(&self.inbox).await.map_err(map_err)?;
+
+        let mut pos = match state.cursors.iter().position(|(p, _)| p == path) {
+            Some(i) => Some(state.cursors.remove(i).1),
+            None    => None,
+        };
+        let result = exfat::read_at(&vol, &self.inbox, path, offset, len, &mut pos).await;
+        if let Some(pos) = pos {
+            state.cursors.truncate(MAX_CURSORS - 1);
+            state.cursors.insert(0, (path.to_string(), pos));
+        }
+        result.map_err(map_err)
+    }
+
+    pub async fn stat(&self, path: &str) -> Result<VfsStat, VfsError> {
+        let _state = self.state.lock().await;
+        let vol = exfat::open_exfat(&self.inbox).await.map_err(map_err)?;
+        let e = exfat::stat(&vol, &self.inbox, path).await.map_err(map_err)?;
+        Ok(VfsStat { mtime: e.mtime, ..VfsStat::basic(e.is_dir, e.size, e.ino()) })
+    }
+
+    pub async fn create(&self, path: &str) -> Result<(), VfsError> {
+        let _state = self.lock_for_update().await;
+        let vol = exfat::open_exfat(&self.inbox).await.map_err(map_err)?;
+        exfat::create(&vol, &self.inbox, path).await.map_err(map_err)
+    }
+
+    pub async fn write_at(&self, path: &str, offset: u64, data: &[u8]) -> Result<usize, VfsError> {
+        let _state = self.lock_for_update().await;
+        let vol = exfat::open_exfat(&self.inbox).await.map_err(map_err)?;
+        exfat::write_at(&vol, &self.inbox, path, offset, data).await.map_err(map_err)
+    }
+
+    pub async fn truncate(&self, path: &str, size: u64) -> Result<(), VfsError> {
+        let _state = self.lock_for_update().await;
+        let vol = exfat::open_exfat(&self.inbox).await.map_err(map_err)?;
+        exfat::truncate(&vol, &self.inbox, path, size).await.map_err(map_err)
+    }
+
+    pub async fn unlink(&self, path: &str) -> Result<(), VfsError> {
+        let _state = self.lock_for_update().await;
+        let vol = exfat::open_exfat(&self.inbox).await.map_err(map_err)?;
+        exfat::unlink(&vol, &self.inbox, path).await.map_err(map_err)
+    }
+
+    pub async fn mkdir(&self, path: &str) -> Result<(), VfsError> {
+        let _state = self.lock_for_update().await;
+        let vol = exfat::open_exfat(&self.inbox).await.map_err(map_err)?;
+        exfat::mkdir(&vol, &self.inbox, path).await.map_err(map_err)
+    }
+
+    pub async fn rmdir(&self, path: &str) -> Result<(), VfsError> {
+        let _state = self.lock_for_update().await;
+        let vol = exfat::open_exfat(&self.inbox).await.map_err(map_err)?;
+        exfat::rmdir(&vol, &self.inbox, path).await.map_err(map_err)
+    }
+
+    pub async fn rename(&self, from: &str, to: &str) -> Result<(), VfsError> {
+        let _state = self.lock_for_update().await;
+        let vol = exfat::open_exfat(&self.inbox).await.map_err(map_err)?;
+        exfat::rename(&vol, &self.inbox, from, to).await.map_err(map_err)
+    }
+
+    /// Every write goes straight to the device (the page cache above has
+    /// already written back), so there is nothing to flush.
+    pub async fn fsync(&self, _path: &str) -> Result<(), VfsError> {
+        Ok(())
+    }
 }
 
 fn map_err(e: ExfatError) -> VfsError {
@@ -41,5 +142,10 @@ fn map_err(e: ExfatError) -> VfsError {
         ExfatError::NotAFile      => VfsError::NotAFile,
         ExfatError::NotADirectory => VfsError::NotADirectory,
         ExfatError::FileTooLarge  => VfsError::FileTooLarge,
+        ExfatError::AlreadyExists => VfsError::AlreadyExists,
+        ExfatError::NotEmpty      => VfsError::NotEmpty,
+        ExfatError::NoSpace       => VfsError::NoSpace,
+        ExfatError::InvalidName   => VfsError::InvalidArgument,
+        ExfatError::Unsupported   => VfsError::ReadOnly,
     }
 }
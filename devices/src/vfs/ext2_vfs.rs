@@ -0,0 +1,153 @@
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use libkernel::task::async_mutex::AsyncMutex;
+use libkernel::time::NSEC_PER_SEC;
+
+use super::{FileType, VfsDirEntry, VfsError, VfsStat};
+use crate::virtio::exfat::BlkInbox;
+use crate::virtio::ext2::{self, Ext2Error, Ext2Vol};
+
+// ---------------------------------------------------------------------------
+
+pub struct Ext2Vfs {
+    inbox: BlkInbox,
+    /// The superblock as read at mount.  Only its geometry and feature
+    /// flags are used, and neither changes while mounted; the free counts
+    /// are read from disk by each allocation.
+    vol: Ext2Vol,
+    /// Held for every operation.  An update keeps its bitmap and free-count
+    /// changes in memory until it finishes, so two at once could hand out
+    /// the same block or inode.
+    state: AsyncMutex<()>,
+}
+
+impl Ext2Vfs {
+    /// Wrap the volume `vol`, opened with [`ext2::open_ext2`] on `inbox`.
+    pub fn new(inbox: BlkInbox, vol: Ext2Vol) -> Self {
+        Self { inbox, vol, state: AsyncMutex::new(()) }
+    }
+
+    /// Identifies this volume to the page cache.
+    pub fn cache_id(&self) -> usize {
+        Arc::as_ptr(&self.inbox) as usize
+    }
+
+    pub async fn list_dir(&self, path: &str) -> Result<Vec<VfsDirEntry>, VfsError> {
+        let _state = self.state.lock().await;
+        let entries = ext2::list_dir(&self.vol, &self.inbox, path).await.map_err(map_err)?;
+        Ok(entries.into_iter().map(|e| VfsDirEntry {
+            name: e.name,
+            kind: FileType::from_mode(e.mode as u32),
+            size: e.size,
+        }).collect())
+    }
+
+    pub async fn read_file(&self, path: &str) -> Result<Vec<u8>, VfsError> {
+        let _state = self.state.lock().await;
+        ext2::read_file(&self.vol, &self.inbox, path).await.map_err(map_err)
+    }
+
+    pub async fn read_at(&self, path: &str, offset: u64, len: usize) -> Result<Vec<u8>, VfsError> {
+        let _state = self.state.lock().await;
+        ext2::read_at(&self.vol, &self.inbox, path, offset, len).await.map_err(map_err)
+    }
+
+    pub async fn stat(&self, path: &str) -> Result<VfsStat, VfsError> {
+        let _state = self.state.lock().await;
+        let info = ext2::stat(&self.vol, &self.inbox, path).await.map_err(map_err)?;
+        Ok(VfsStat {
+            mode:    info.mode as u32,
+            nlink:   info.nlink as u64,
+            uid:     info.uid,
+            gid:     info.gid,
+            size:    info.size,
+            blocks:  info.blocks,
+            atime:   info.atime as u64 * NSEC_PER_SEC,
+            mtime:   info.mtime as u64 * NSEC_PER_SEC,
+            ctime:   info.ctime as u64 * NSEC_PER_SEC,
+            ino:     info.ino as u64,
+            dev:     0,
+            rdev:    0,
+            version: 0,
+        })
+    }
+
+    pub async fn readlink(&self, path: &str) -> Result<String, VfsError> {
+        let _state = self.state.lock().await;
+        ext2::readlink(&self.vol, &self.inbox, path).await.map_err(map_err)
+    }
+
+    pub async fn symlink(&self, path: &str, target: &str) -> Result<(), VfsError> {
+        let _state = self.state.lock().await;
+        ext2::symlink(&self.vol, &self.inbox, path, target).await.map_err(map_err)
+    }
+
+    pub async fn link(&self, path: &str, target: &str) -> Result<(), VfsError> {
+        let _state = self.state.lock().await;
+        ext2::link(&self.vol, &self.inbox, path, target).await.map_err(map_err)
+    }
+
+    pub async fn create(&self, path: &str) -> Result<(), VfsError> {
+        let _state = self.state.lock().await;
+        ext2::create(&self.vol, &self.inbox, path).await.map_err(map_err)
+    }
+
+    pub async fn write_at(&self, path: &str, offset: u64, data: &[u8]) -> Result<usize, VfsError> {
+        let _state = self.state.lock().await;
+        ext2::write_at(&self.vol, &self.inbox, path, offset, data).await.map_err(map_err)
+    }
+
+    pub async fn truncate(&self, path: &str, size: u64) -> Result<(), VfsError> {
+        let _state = self.state.lock().await;
+        ext2::truncate(&self.vol, &self.inbox, path, size).await.map_err(map_err)
+    }
+
+    pub async fn unlink(&self, path: &str) -> Result<(), VfsError> {
+        let _state = self.state.lock().await;
+        ext2::unlink(&self.vol, &self.inbox, path).await.map_err(map_err)
+    }
+
+    pub async fn mkdir(&self, path: &str) -> Result<(), VfsError> {
+        let _state = self.state.lock().await;
+        ext2::mkdir(&self.vol, &self.inbox, path).await.map_err(map_err)
+    }
+
+    pub async fn rmdir(&self, path: &str) -> Result<(), VfsError> {
+        let _state = self.state.lock().await;
+        ext2::rmdir(&self.vol, &self.inbox, path).await.map_err(map_err)
+    }
+
+    pub async fn rename(&self, from: &str, to: &str) -> Result<(), VfsError> {
+        let _state = self.state.lock().await;
+        ext2::rename(&self.vol, &self.inbox, from, to).await.map_err(map_err)
+    }
+
+    /// ext2 has no journal, and the driver writes each block it changes
+    /// before the operation returns: once the page cache has written the
+    /// file back, it is on the device.
+    pub async fn fsync(&self, _path: &str) -> Result<(), VfsError> {
+        Ok(())
+    }
+}
+
+fn map_err(e: Ext2Error) -> VfsError {
+    match e {
+        Ext2Error::NoDevice
+        | Ext2Error::IoError
+        | Ext2Error::NotExt2
+        | Ext2Error::UnknownPartitionLayout
+        | Ext2Error::Unsupported
+        | Ext2Error::Corrupt     => VfsError::IoError,
+        Ext2Error::ReadOnly      => VfsError::ReadOnly,
+        Ext2Error::PathNotFound  => VfsError::NotFound,
+        Ext2Error::NotAFile      => VfsError::NotAFile,
+        Ext2Error::NotADirectory => VfsError::NotADirectory,
+        Ext2Error::FileTooLarge  => VfsError::FileTooLarge,
+        Ext2Error::AlreadyExists => VfsError::AlreadyExists,
+        Ext2Error::NotEmpty      => VfsError::NotEmpty,
+        Ext2Error::NoSpace       => VfsError::NoSpace,
+        Ext2Error::InvalidName   => VfsError::InvalidArgument,
+    }
+}
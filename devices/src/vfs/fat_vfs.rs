@@ -0,0 +1,123 @@
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use libkernel::task::async_mutex::AsyncMutex;
+
+use super::{FileType, VfsDirEntry, VfsError, VfsStat};
+use crate::virtio::exfat::BlkInbox;
+use crate::virtio::fat::{self, FatError, FatVol};
+
+// ---------------------------------------------------------------------------
+
+pub struct FatVfs {
+    inbox: BlkInbox,
+    /// Layout from the boot sector, read at mount; it does not change
+    /// while mounted.
+    vol: FatVol,
+    /// Held for every operation.  An update clears the clean-shutdown bit
+    /// in FAT entry 1 and sets it again when it ends, and adds its change
+    /// in free clusters to the FSInfo count then: overlapping updates
+    /// would mark the volume clean early or lose part of the count.  A
+    /// file's cluster chain and its directory entry are also written
+    /// separately, so a reader could see one without the other.
+    state: AsyncMutex<()>,
+}
+
+impl FatVfs {
+    /// Wrap the volume `vol`, opened with [`fat::open_fat`] on `inbox`.
+    pub fn new(inbox: BlkInbox, vol: FatVol) -> Self {
+        Self { inbox, vol, state: AsyncMutex::new(()) }
+    }
+
+    /// Identifies this volume to the page cache.
+    pub fn cache_id(&self) -> usize {
+        Arc::as_ptr(&self.inbox) as usize
+    }
+
+    pub async fn list_dir(&self, path: &str) -> Result<Vec<VfsDirEntry>, VfsError> {
+        let _state = self.state.lock().await;
+        let entries = fat::list_dir(&self.vol, &self.inbox, path).await.map_err(map_err)?;
+        Ok(entries.into_iter().map(|e| VfsDirEntry {
+            name: e.name,
+            kind: if e.is_dir { FileType::Directory } else { FileType::Regular },
+            size: e.size,
+        }).collect())
+    }
+
+    pub async fn read_file(&self, path: &str) -> Result<Vec<u8>, VfsError> {
+        let _state = self.state.lock().await;
+        fat::read_file(&self.vol, &self.inbox, path).await.map_err(map_err)
+    }
+
+    pub async fn read_at(&self, path: &str, offset: u64, len: usize) -> Result<Vec<u8>, VfsError> {
+        let _state = self.state.lock().await;
+        fat::read_at(&self.vol, &self.inbox, path, offset, len).await.map_err(map_err)
+    }
+
+    pub async fn stat(&self, path: &str) -> Result<VfsStat, VfsError> {
+        let _state = self.state.lock().await;
+        let entry = fat::stat(&self.vol, &self.inbox, path).await.map_err(map_err)?;
+        Ok(VfsStat { mtime: entry.mtime, ..VfsStat::basic(entry.is_dir, entry.size, entry.ino) })
+    }
+
+    pub async fn create(&self, path: &str) -> Result<(), VfsError> {
+        let _state = self.state.lock().await;
+        fat::create(&self.vol, &self.inbox, path).await.map_err(map_err)
+    }
+
+    pub async fn write_at(&self, path: &str, offset: u64, data: &[u8]) -> Result<usize, VfsError> {
+        let _state = self.state.lock().await;
+        fat::write_at(&self.vol, &self.inbox, path, offset, data).await.map_err(map_err)
+    }
+
+    pub async fn truncate(&self, path: &str, size: u64) -> Result<(), VfsError> {
+        let _state = self.state.lock().await;
+        fat::truncate(&self.vol, &self.inbox, path, size).await.map_err(map_err)
+    }
+
+    pub async fn unlink(&self, path: &str) -> Result<(), VfsError> {
+        let _state = self.state.lock().await;
+        fat::unlink(&self.vol, &self.inbox, path).await.map_err(map_err)
+    }
+
+    pub async fn mkdir(&self, path: &str) -> Result<(), VfsError> {
+        let _state = self.state.lock().await;
+        fat::mkdir(&self.vol, &self.inbox, path).await.map_err(map_err)
+    }
+
+    pub async fn rmdir(&self, path: &str) -> Result<(), VfsError> {
+        let _state = self.state.lock().await;
+        fat::rmdir(&self.vol, &self.inbox, path).await.map_err(map_err)
+    }
+
+    pub async fn rename(&self, from: &str, to: &str) -> Result<(), VfsError> {
+        let _state = self.state.lock().await;
+        fat::rename(&self.vol, &self.inbox, from, to).await.map_err(map_err)
+    }
+
+    /// The driver writes each FAT entry, to every copy of the FAT it keeps
+    /// in step, and each directory entry as it changes them, and FSInfo
+    /// before an update returns.  Nothing is held back to flush.
+    pub async fn fsync(&self, _path: &str) -> Result<(), VfsError> {
+        Ok(())
+    }
+}
+
+fn map_err(e: FatError) -> VfsError {
+    match e {
+        FatError::NoDevice
+        | FatError::IoError
+        | FatError::NotFat
+        | FatError::UnknownPartitionLayout
+        | FatError::Unsupported
+        | FatError::Corrupt       => VfsError::IoError,
+        FatError::PathNotFound    => VfsError::NotFound,
+        FatError::NotAFile        => VfsError::NotAFile,
+        FatError::NotADirectory   => VfsError::NotADirectory,
+        FatError::FileTooLarge    => VfsError::FileTooLarge,
+        FatError::AlreadyExists   => VfsError::AlreadyExists,
+        FatError::NotEmpty        => VfsError::NotEmpty,
+        FatError::NoSpace         => VfsError::NoSpace,
+        FatError::InvalidName     => VfsError::InvalidArgument,
+    }
+}
@@ -0,0 +1,355 @@
+//! initramfs: a newc-format cpio archive unpacked into a [`TmpVfs`].
+//!
+//! The archive is linked into the kernel image (see `kernel/build.rs`) and
+//! unpacked before any device is probed, so `/` can hold a complete
+//! userland without a disk or host share.  Directories, regular files and
+//! symbolic links are created with the archive's mode and owner, and hard
+//! links become tmpfs hard links; device nodes and FIFOs are skipped, since
+//! `/dev` comes from devfs.  Several archives may be concatenated, as with
+//! Linux.
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use futures_util::FutureExt;
+use log::warn;
+
+use super::tmp_vfs::TmpVfs;
+use super::{VfsError, S_IFDIR, S_IFLNK, S_IFMT, S_IFREG};
+
+const HEADER_LEN: usize = 110;
+const MAGIC_NEWC: &[u8] = b"070701";
+/// newc with a checksum of the data; the checksum is not verified.
+const MAGIC_CRC: &[u8] = b"070702";
+const TRAILER: &str = "TRAILER!!!";
+
+#[derive(Debug)]
+pub enum InitramfsError {
+    /// A header, name or file body runs past the end of the archive.
+    Truncated,
+    /// A header does not start with a newc magic number.
+    BadMagic,
+    /// A header field is not hexadecimal, or a name is not UTF-8.
+    BadHeader,
+    /// A name has a `..` component.
+    BadName,
+    /// Creating an entry in the filesystem failed.
+    Vfs(VfsError),
+}
+
+impl From<VfsError> for InitramfsError {
+    fn from(e: VfsError) -> Self {
+        InitramfsError::Vfs(e)
+    }
+}
+
+/// One archive member.
+struct Member<'a> {
+    ino:   u32,
+    mode:  u32,
+    uid:   u32,
+    gid:   u32,
+    nlink: u32,
+    name:  &'a str,
+    data:  &'a [u8],
+}
+
+fn align4(n: usize) -> usize {
+    (n + 3) & !3
+}
+
+fn hex_field(header: &[u8], index: usize) -> Result<u32, InitramfsError> {
+    let start = MAGIC_NEWC.len() + index * 8;
+    let text = core::str::from_utf8(&header[start..start + 8]).map_err(|_| InitramfsError::BadHeader)?;
+    u32::from_str_radix(text, 16).map_err(|_| InitramfsError::BadHeader)
+}
+
+/// Parse the member at `pos`; returns it and the offset of the next one.
+fn parse_member(archive: &[u8], pos: usize) -> Result<(Member<'_>, usize), InitramfsError> {
+    let header = archive.get(pos..pos + HEADER_LEN).ok_or(InitramfsError::Truncated)?;
+    let magic = &header[..MAGIC_NEWC.len()];
+    if magic != MAGIC_NEWC && magic != MAGIC_CRC {
+        return Err(InitramfsError::BadMagic);
+    }
+    // Fields: ino, mode, uid, gid, nlink, mtime, filesize, devmajor,
+    // devminor, rdevmajor, rdevminor, namesize, check.
+    let namesize = hex_field(header, 11)? as usize;
+    let filesize = hex_field(header, 6)? as usize;
+
+    let name_start = pos + HEADER_LEN;
+    let name = archive.get(name_start..name_start + namesize).ok_or(InitramfsError::Truncated)?;
+    // The name size counts the terminating NUL.
+    let name = name.split(|&b| b == 0).next().unwrap_or(&[]);
+    let name = core::str::from_utf8(name).map_err(|_| InitramfsError::BadHeader)?;
+
+    let data_start = align4(name_start + namesize);
+    let data = archive.get(data_start..data_start + filesize).ok_or(InitramfsError::Truncated)?;
+
+    let member = Member {
+        ino:   hex_field(header, 0)?,
+        mode:  hex_field(header, 1)?,
+        uid:   hex_field(header, 2)?,
+        gid:   hex_field(header, 3)?,
+        nlink: hex_field(header, 4)?,
+        name,
+        data,
+    };
+    Ok((member, align4(data_start + filesize)))
+}
+
+/// `./bin/sh`, `bin/sh` and `/bin/sh` all name `/bin/sh`; `.` is the root.
+/// A `..` component is refused rather than handed on to tmpfs.
+fn normalize(name: &str) -> Result<String, InitramfsError> {
+    let mut path = String::new();
+    for component in name.split('/').filter(|c| !c.is_empty() && *c != ".") {
+        if component == ".." {
+            return Err(InitramfsError::BadName);
+        }
+        path.push('/');
+        path.push_str(component);
+    }
+    if path.is_empty() {
+        path.push('/');
+    }
+    Ok(path)
+}
+
+/// Create every missing directory above `path`.
+fn make_parents(fs: &TmpVfs, path: &str) -> Result<(), InitramfsError> {
+    let mut end = 0;
+    while let Some(i) = path[end + 1..].find('/') {
+        end += 1 + i;
+        match run(fs.mkdir(&path[..end])) {
+            Ok(()) | Err(VfsError::AlreadyExists) => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(())
+}
+
+/// Run a tmpfs operation to completion.  tmpfs keeps everything in memory
+/// behind a spin lock, so its futures never suspend.
+fn run<T>(fut: impl core::future::Future<Output = Result<T, VfsError>>) -> Result<T, VfsError> {
+    fut.now_or_never().unwrap_or(Err(VfsError::Busy))
+}
+
+/// Replace whatever is at `path` with a regular file holding `data`.
+fn write_file(fs: &TmpVfs, path: &str, data: &[u8]) -> Result<(), InitramfsError> {
+    match run(fs.create(path)) {
+        Ok(()) => {}
+        Err(VfsError::AlreadyExists) => run(fs.truncate(path, 0))?,
+        Err(e) => return Err(e.into()),
+    }
+    run(fs.write_at(path, 0, data))?;
+    Ok(())
+}
+
+/// Remove the file or symlink at `path`, if there is one.
+fn remove(fs: &TmpVfs, path: &str) -> Result<(), InitramfsError> {
+    match run(fs.unlink(path)) {
+        Ok(()) | Err(VfsError::NotFound) => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Unpack `archive` into a new tmpfs with at most `max_bytes` of file data.
+pub fn unpack(archive: &[u8], max_bytes: u64) -> Result<TmpVfs, InitramfsError> {
+    let fs = TmpVfs::new(max_bytes, 0o755);
+    // The first path of each hard-linked file, by inode: newc stores the
+    // data with the last link only, so it is written through that path
+    // when it arrives.
+    let mut links: BTreeMap<u32, String> = BTreeMap::new();
+
+    let mut pos = 0;
+    while pos < archive.len() {
+        // Concatenated archives may be separated by zero padding.
+        if archive[pos] == 0 {
+            pos += 1;
+            continue;
+        }
+        let (member, next) = parse_member(archive, pos)?;
+        pos = next;
+        if member.name == TRAILER {
+            links.clear();
+            continue;
+        }
+
+        let path = normalize(member.name)?;
+        make_parents(&fs, &path)?;
+        let perm = member.mode & 0o7777;
+        match member.mode & S_IFMT {
+            S_IFDIR => {
+                if path != "/" {
+                    match run(fs.mkdir(&path)) {
+                        Ok(()) | Err(VfsError::AlreadyExists) => {}
+                        Err(e) => return Err(e.into()),
+                    }
+                }
+            }
+            S_IFREG => match links.get(&member.ino) {
+                Some(first) if member.nlink > 1 && *first != path => {
+                    remove(&fs, &path)?;
+                    fs.link(&path, first)?;
+                    if !member.data.is_empty() {
+                        write_file(&fs, &path, member.data)?;
+                    }
+                }
+                _ => {
+                    write_file(&fs, &path, member.data)?;
+                    if member.nlink > 1 {
+                        links.insert(member.ino, path.clone());
+                    }
+                }
+            },
+            S_IFLNK => {
+                let target = core::str::from_utf8(member.data).map_err(|_| InitramfsError::BadHeader)?;
+                remove(&fs, &path)?;
+                fs.symlink(&path, target)?;
+            }
+            _ => {
+                warn!("[initramfs] skipping special file {}", path);
+                continue;
+            }
+        }
+        fs.set_attr(&path, perm, member.uid, member.gid)?;
+    }
+    Ok(fs)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use alloc::format;
+    use alloc::vec::Vec;
+    use libkernel::{serial_print, serial_println};
+
+    const LIMIT: u64 = 1 << 20;
+
+    /// Append a newc member to `out`.
+    fn member(out: &mut Vec<u8>, ino: u32, mode: u32, nlink: u32, name: &str, data: &[u8]) {
+        let fields = [ino, mode, 0, 0, nlink, 0, data.len() as u32, 0, 0, 0, 0, name.len() as u32 + 1, 0];
+        out.extend_from_slice(MAGIC_NEWC);
+        for f in &fields {
+            out.extend_from_slice(format!("{:08X}", f).as_bytes());
+        }
+        out.extend_from_slice(name.as_bytes());
+        out.push(0);
+        out.resize(align4(out.len()), 0);
+        out.extend_from_slice(data);
+        out.resize(align4(out.len()), 0);
+    }
+
+    fn trailer(out: &mut Vec<u8>) {
+        member(out, 0, 0, 1, TRAILER, &[]);
+    }
+
+    fn archive(name: &str, data: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        member(&mut out, 1, S_IFREG | 0o644, 1, name, data);
+        trailer(&mut out);
+        out
+    }
+
+    fn read(fs: &TmpVfs, path: &str) -> Vec<u8> {
+        run(fs.read_file(path)).unwrap()
+    }
+
+    #[test_case]
+    fn test_initramfs_unpack() {
+        serial_print!("test_initramfs_unpack... ");
+        let mut out = Vec::new();
+        member(&mut out, 1, S_IFDIR | 0o755, 2, ".", &[]);
+        member(&mut out, 2, S_IFDIR | 0o700, 2, "bin", &[]);
+        member(&mut out, 3, S_IFREG | 0o4755, 1, "./bin/sh", b"#!sh");
+        member(&mut out, 4, S_IFLNK | 0o777, 1, "bin/ash", b"sh");
+        // Device nodes come from devfs.
+        member(&mut out, 5, 0o020666, 1, "dev/null", &[]);
+        trailer(&mut out);
+        // A second archive after padding; its parent directory is implied
+        // and its file replaces one from the first.
+        out.extend_from_slice(&[0; 12]);
+        member(&mut out, 1, S_IFREG | 0o600, 1, "/etc/motd", b"hello");
+        member(&mut out, 2, S_IFREG | 0o644, 1, "bin/sh", b"new");
+        trailer(&mut out);
+
+        let fs = unpack(&out, LIMIT).unwrap();
+        assert_eq!(read(&fs, "/bin/sh"), b"new");
+        assert_eq!(read(&fs, "/etc/motd"), b"hello");
+        assert_eq!(fs.readlink("/bin/ash").unwrap(), "sh");
+        assert_eq!(run(fs.stat("/bin")).unwrap().mode, S_IFDIR | 0o700);
+        assert_eq!(run(fs.stat("/etc/motd")).unwrap().mode, S_IFREG | 0o600);
+        assert!(matches!(run(fs.stat("/dev/null")), Err(VfsError::NotFound)));
+        serial_println!("[ok]");
+    }
+
+    #[test_case]
+    fn test_initramfs_hard_links() {
+        serial_print!("test_initramfs_hard_links... ");
+        // newc stores the data with the last link only.
+        let mut out = Vec::new();
+        member(&mut out, 7, S_IFREG | 0o755, 3, "bin/a", &[]);
+        member(&mut out, 7, S_IFREG | 0o755, 3, "bin/b", &[]);
+        member(&mut out, 7, S_IFREG | 0o755, 3, "bin/c", b"shared");
+        trailer(&mut out);
+
+        let fs = unpack(&out, LIMIT).unwrap();
+        let a = run(fs.stat("/bin/a")).unwrap();
+        for path in &["/bin/a", "/bin/b", "/bin/c"] {
+            assert_eq!(read(&fs, path), b"shared");
+            let st = run(fs.stat(path)).unwrap();
+            assert_eq!((st.ino, st.nlink), (a.ino, 3));
+        }
+        serial_println!("[ok]");
+    }
+
+    #[test_case]
+    fn test_initramfs_truncated() {
+        serial_print!("test_initramfs_truncated... ");
+        let out = archive("file", b"0123456789");
+        // In the header, in the name, and in the data.
+        for &len in &[HEADER_LEN - 1, HEADER_LEN + 2, HEADER_LEN + 8 + 5] {
+            assert!(matches!(unpack(&out[..len], LIMIT), Err(InitramfsError::Truncated)));
+        }
+        // A file size past the end of the archive.
+        let mut out = archive("file", b"0123456789");
+        out[MAGIC_NEWC.len() + 6 * 8..][..8].copy_from_slice(b"0000FFFF");
+        assert!(matches!(unpack(&out, LIMIT), Err(InitramfsError::Truncated)));
+        serial_println!("[ok]");
+    }
+
+    #[test_case]
+    fn test_initramfs_bad_header() {
+        serial_print!("test_initramfs_bad_header... ");
+        // The old portable ("odc") format.
+        let mut out = archive("file", b"data");
+        out[..6].copy_from_slice(b"070707");
+        assert!(matches!(unpack(&out, LIMIT), Err(InitramfsError::BadMagic)));
+        // Junk after a trailer is another member with a bad magic.
+        let mut out = archive("file", b"data");
+        out.extend_from_slice(&[b'x'; HEADER_LEN]);
+        assert!(matches!(unpack(&out, LIMIT), Err(InitramfsError::BadMagic)));
+
+        let mut out = archive("file", b"data");
+        out[MAGIC_NEWC.len() + 1] = b'x';
+        assert!(matches!(unpack(&out, LIMIT), Err(InitramfsError::BadHeader)));
+        let mut out = archive("file", b"data");
+        out[HEADER_LEN] = 0xFF;
+        assert!(matches!(unpack(&out, LIMIT), Err(InitramfsError::BadHeader)));
+        // The checksum format is accepted, unchecked.
+        let mut out = archive("file", b"data");
+        out[..6].copy_from_slice(MAGIC_CRC);
+        assert_eq!(read(&unpack(&out, LIMIT).unwrap(), "/file"), b"data");
+        serial_println!("[ok]");
+    }
+
+    #[test_case]
+    fn test_initramfs_rejects_dotdot() {
+        serial_print!("test_initramfs_rejects_dotdot... ");
+        for name in &["../x", "a/../../x", "a/.."] {
+            assert!(matches!(unpack(&archive(name, b"x"), LIMIT), Err(InitramfsError::BadName)));
+        }
+        assert_eq!(normalize("./a/./b/").unwrap(), "/a/b");
+        assert_eq!(normalize(".").unwrap(), "/");
+        assert_eq!(normalize("a..b").unwrap(), "/a..b");
+        serial_println!("[ok]");
+    }
+}
@@ -4,23 +4,165 @@ use alloc::sync::Arc;
 use lazy_static::lazy_static;
 use libkernel::process::ProcessId;
 use libkernel::spin_mutex::SpinMutex as Mutex;
+use x86_64::PhysAddr;
 
+pub mod dev_vfs;
 pub mod exfat_vfs;
+pub mod ext2_vfs;
+pub mod fat_vfs;
+pub mod initramfs;
+pub mod page_cache;
 pub mod plan9_vfs;
 pub mod proc_vfs;
+pub mod tmp_vfs;
 
+pub use dev_vfs::DevVfs;
 pub use exfat_vfs::ExfatVfs;
+pub use ext2_vfs::Ext2Vfs;
+pub use fat_vfs::FatVfs;
 pub use plan9_vfs::Plan9Vfs;
 pub use proc_vfs::ProcVfs;
+pub use tmp_vfs::TmpVfs;
 
 // ---------------------------------------------------------------------------
 // Public types
 
+/// File type bits of [`VfsStat::mode`], as in Linux's `st_mode`.
+pub const S_IFMT:   u32 = 0o170000;
+pub const S_IFSOCK: u32 = 0o140000;
+pub const S_IFLNK:  u32 = 0o120000;
+pub const S_IFREG:  u32 = 0o100000;
+pub const S_IFBLK:  u32 = 0o060000;
+pub const S_IFDIR:  u32 = 0o040000;
+pub const S_IFCHR:  u32 = 0o020000;
+pub const S_IFIFO:  u32 = 0o010000;
+
+/// Most symbolic links followed while resolving one path, as on Linux.
+pub const MAX_SYMLINKS: usize = 40;
+
+/// The type of a directory entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileType {
+    Regular,
+    Directory,
+    Symlink,
+    CharDevice,
+    BlockDevice,
+    Fifo,
+    Socket,
+}
+
+impl FileType {
+    /// The type named by the `S_IFMT` bits of `mode`.
+    pub fn from_mode(mode: u32) -> Self {
+        match mode & S_IFMT {
+            S_IFDIR  => FileType::Directory,
+            S_IFLNK  => FileType::Symlink,
+            S_IFCHR  => FileType::CharDevice,
+            S_IFBLK  => FileType::BlockDevice,
+            S_IFIFO  => FileType::Fifo,
+            S_IFSOCK => FileType::Socket,
+            _        => FileType::Regular,
+        }
+    }
+
+    /// The `d_type` getdents64 reports.
+    pub fn dirent_type(self) -> u8 {
+        match self {
+            FileType::Fifo        => 1,
+            FileType::CharDevice  => 2,
+            FileType::Directory   => 4,
+            FileType::BlockDevice => 6,
+            FileType::Regular     => 8,
+            FileType::Symlink     => 10,
+            FileType::Socket      => 12,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct VfsDirEntry {
-    pub name:   String,
-    pub is_dir: bool,
-    pub size:   u64,
+    pub name: String,
+    pub kind: FileType,
+    pub size: u64,
+}
+
+impl VfsDirEntry {
+    pub fn is_dir(&self) -> bool {
+        self.kind == FileType::Directory
+    }
+}
+
+/// Metadata returned by [`stat`].  Times are nanoseconds since the Unix
+/// epoch; a filesystem that does not record one reports 0.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct VfsStat {
+    /// File type and permission bits.
+    pub mode:    u32,
+    pub nlink:   u64,
+    pub uid:     u32,
+    pub gid:     u32,
+    pub size:    u64,
+    /// Space allocated, in 512-byte units.
+    pub blocks:  u64,
+    pub atime:   u64,
+    pub mtime:   u64,
+    pub ctime:   u64,
+    /// Identifies the file within its filesystem; 0 if it has no stable
+    /// identity (synthetic files).
+    pub ino:     u64,
+    /// The mount the file is on.  Filled in by [`stat`]; filesystems
+    /// leave it 0.
+    pub dev:     u64,
+    /// The device a device node stands for ([`makedev`]); 0 otherwise.
+    pub rdev:    u64,
+    /// Changes when the file is modified behind the kernel's back (9P's
+    /// qid version); always 0 on filesystems only this kernel writes.
+    pub version: u64,
+}
+
+impl VfsStat {
+    /// Metadata for a filesystem that records no owner, permissions or
+    /// links: owned by root, mode `0755` for directories and `0644` for
+    /// files, one link.
+    pub fn basic(is_dir: bool, size: u64, ino: u64) -> Self {
+        VfsStat {
+            mode: if is_dir { S_IFDIR | 0o755 } else { S_IFREG | 0o644 },
+            nlink: 1,
+            size,
+            blocks: (size + 511) / 512,
+            ino,
+            ..VfsStat::default()
+        }
+    }
+
+    pub fn kind(&self) -> FileType {
+        FileType::from_mode(self.mode)
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.mode & S_IFMT == S_IFDIR
+    }
+
+    pub fn is_symlink(&self) -> bool {
+        self.mode & S_IFMT == S_IFLNK
+    }
+}
+
+/// A device number in the 64-bit encoding glibc and musl use for `dev_t`.
+pub const fn makedev(major: u32, minor: u32) -> u64 {
+    let (major, minor) = (major as u64, minor as u64);
+    (major & 0xfffff000) << 32 | (major & 0xfff) << 8 | (minor & 0xffffff00) << 12 | (minor & 0xff)
+}
+
+/// The major number of a [`makedev`] device number.
+pub const fn major(dev: u64) -> u32 {
+    ((dev >> 32 & 0xfffff000) | (dev >> 8 & 0xfff)) as u32
+}
+
+/// The minor number of a [`makedev`] device number.
+pub const fn minor(dev: u64) -> u32 {
+    ((dev >> 12 & 0xffffff00) | (dev & 0xff)) as u32
 }
 
 #[derive(Debug)]
@@ -31,6 +173,28 @@ pub enum VfsError {
     NotADirectory,
     FileTooLarge,
     NoFilesystem,
+    /// The target of `create`, `mkdir` or `rename` already exists.
+    AlreadyExists,
+    /// `rmdir` or `rename` onto a directory that still has entries.
+    NotEmpty,
+    /// The filesystem does not support modification.
+    ReadOnly,
+    NoSpace,
+    /// `rename` across two mounts.
+    CrossDevice,
+    /// The path is a mountpoint.
+    Busy,
+    InvalidArgument,
+    /// The server refused access to the file.
+    PermissionDenied,
+    /// A device node has no device behind it.
+    NoDevice,
+    /// The filesystem cannot create this kind of file or link, or the
+    /// target of `link` is a directory.
+    NotPermitted,
+    /// Resolving a path met more than [`MAX_SYMLINKS`] symbolic links, or a
+    /// symbolic link where none may be followed.
+    TooManyLinks,
 }
 
 // ---------------------------------------------------------------------------
@@ -38,32 +202,259 @@ pub enum VfsError {
 
 pub enum AnyVfs {
     Exfat(ExfatVfs),
+    Ext2(Ext2Vfs),
+    Fat(FatVfs),
     Plan9(Plan9Vfs),
     Proc(ProcVfs),
+    Tmp(TmpVfs),
+    Dev(DevVfs),
 }
 
 impl AnyVfs {
     pub async fn list_dir(&self, path: &str) -> Result<Vec<VfsDirEntry>, VfsError> {
         match self {
             AnyVfs::Exfat(fs) => fs.list_dir(path).await,
+            AnyVfs::Ext2(fs)  => fs.list_dir(path).await,
+            AnyVfs::Fat(fs)   => fs.list_dir(path).await,
             AnyVfs::Plan9(fs) => fs.list_dir(path).await,
             AnyVfs::Proc(fs)  => fs.list_dir(path).await,
+            AnyVfs::Tmp(fs)   => fs.list_dir(path).await,
+            AnyVfs::Dev(fs)   => fs.list_dir(path).await,
         }
     }
 
     pub async fn read_file(&self, path: &str, caller_pid: ProcessId) -> Result<Vec<u8>, VfsError> {
         match self {
             AnyVfs::Exfat(fs) => fs.read_file(path).await,
+            AnyVfs::Ext2(fs)  => fs.read_file(path).await,
+            AnyVfs::Fat(fs)   => fs.read_file(path).await,
             AnyVfs::Plan9(fs) => fs.read_file(path).await,
             AnyVfs::Proc(fs)  => fs.read_file(path, caller_pid).await,
+            AnyVfs::Tmp(fs)   => fs.read_file(path).await,
+            AnyVfs::Dev(fs)   => fs.read_file(path).await,
+        }
+    }
+
+    pub async fn read_at(&self, path: &str, offset: u64, len: usize, caller_pid: ProcessId) -> Result<Vec<u8>, VfsError> {
+        match self {
+            AnyVfs::Exfat(fs) => fs.read_at(path, offset, len).await,
+            AnyVfs::Ext2(fs)  => fs.read_at(path, offset, len).await,
+            AnyVfs::Fat(fs)   => fs.read_at(path, offset, len).await,
+            AnyVfs::Plan9(fs) => fs.read_at(path, offset, len).await,
+            AnyVfs::Proc(fs)  => fs.read_at(path, offset, len, caller_pid).await,
+            AnyVfs::Tmp(fs)   => fs.read_at(path, offset, len).await,
+            AnyVfs::Dev(fs)   => fs.read_at(path, offset, len).await,
+        }
+    }
+
+    pub async fn open(&self, path: &str, writable: bool) -> Result<(), VfsError> {
+        match self {
+            AnyVfs::Plan9(fs) => fs.open(path, writable).await,
+            AnyVfs::Exfat(_) | AnyVfs::Ext2(_) | AnyVfs::Fat(_) | AnyVfs::Proc(_) | AnyVfs::Tmp(_) | AnyVfs::Dev(_) => Ok(()),
+        }
+    }
+
+    pub fn release(&self, path: &str, writable: bool) {
+        if let AnyVfs::Plan9(fs) = self {
+            fs.release(path, writable);
+        }
+    }
+
+    /// Identifies the filesystem instance to the page cache.  The 9P mounts
+    /// share one client, and so share cached pages.
+    pub fn cache_id(&self) -> usize {
+        match self {
+            AnyVfs::Exfat(fs) => fs.cache_id(),
+            AnyVfs::Ext2(fs)  => fs.cache_id(),
+            AnyVfs::Fat(fs)   => fs.cache_id(),
+            AnyVfs::Plan9(fs) => fs.cache_id(),
+            AnyVfs::Proc(_) | AnyVfs::Tmp(_) | AnyVfs::Dev(_) => 0,
+        }
+    }
+
+    /// True if file content is generated when read, as in `/proc`.
+    pub fn is_synthetic(&self) -> bool {
+        matches!(self, AnyVfs::Proc(_))
+    }
+
+    /// True if file data goes through the page cache.  Synthetic files
+    /// have nothing to cache, tmpfs data lives in memory already, and
+    /// device nodes are read from the device each time.
+    pub fn uses_page_cache(&self) -> bool {
+        matches!(self, AnyVfs::Exfat(_) | AnyVfs::Ext2(_) | AnyVfs::Fat(_) | AnyVfs::Plan9(_))
+    }
+
+    /// Frame of page `index` of a file that does not use the page cache,
+    /// with a reference taken for a mapping.  Only tmpfs supports it.
+    pub async fn map_page(&self, path: &str, index: u64) -> Result<PhysAddr, VfsError> {
+        match self {
+            AnyVfs::Tmp(fs) => fs.map_page(path, index).await,
+            _ => Err(VfsError::InvalidArgument),
+        }
+    }
+
+    pub async fn stat(&self, path: &str) -> Result<VfsStat, VfsError> {
+        match self {
+            AnyVfs::Exfat(fs) => fs.stat(path).await,
+            AnyVfs::Ext2(fs)  => fs.stat(path).await,
+            AnyVfs::Fat(fs)   => fs.stat(path).await,
+            AnyVfs::Plan9(fs) => fs.stat(path).await,
+            AnyVfs::Proc(fs)  => fs.stat(path).await,
+            AnyVfs::Tmp(fs)   => fs.stat(path).await,
+            AnyVfs::Dev(fs)   => fs.stat(path).await,
+        }
+    }
+
+    pub async fn create(&self, path: &str) -> Result<(), VfsError> {
+        match self {
+            AnyVfs::Exfat(fs) => fs.create(path).await,
+            AnyVfs::Ext2(fs)  => fs.create(path).await,
+            AnyVfs::Fat(fs)   => fs.create(path).await,
+            AnyVfs::Plan9(fs) => fs.create(path).await,
+            AnyVfs::Proc(_)   => Err(VfsError::ReadOnly),
+            AnyVfs::Tmp(fs)   => fs.create(path).await,
+            AnyVfs::Dev(_)    => Err(VfsError::ReadOnly),
+        }
+    }
+
+    pub async fn write_at(&self, path: &str, offset: u64, data: &[u8]) -> Result<usize, VfsError> {
+        match self {
+            AnyVfs::Exfat(fs) => fs.write_at(path, offset, data).await,
+            AnyVfs::Ext2(fs)  => fs.write_at(path, offset, data).await,
+            AnyVfs::Fat(fs)   => fs.write_at(path, offset, data).await,
+            AnyVfs::Plan9(fs) => fs.write_at(path, offset, data).await,
+            AnyVfs::Proc(_)   => Err(VfsError::ReadOnly),
+            AnyVfs::Tmp(fs)   => fs.write_at(path, offset, data).await,
+            AnyVfs::Dev(fs)   => fs.write_at(path, offset, data).await,
+        }
+    }
+
+    pub async fn truncate(&self, path: &str, size: u64) -> Result<(), VfsError> {
+        match self {
+            AnyVfs::Exfat(fs) => fs.truncate(path, size).await,
+            AnyVfs::Ext2(fs)  => fs.truncate(path, size).await,
+            AnyVfs::Fat(fs)   => fs.truncate(path, size).await,
+            AnyVfs::Plan9(fs) => fs.truncate(path, size).await,
+            AnyVfs::Proc(_)   => Err(VfsError::ReadOnly),
+            AnyVfs::Tmp(fs)   => fs.truncate(path, size).await,
+            AnyVfs::Dev(fs)   => fs.truncate(path, size).await,
+        }
+    }
+
+    pub async fn unlink(&self, path: &str) -> Result<(), VfsError> {
+        match self {
+            AnyVfs::Exfat(fs) => fs.unlink(path).await,
+            AnyVfs::Ext2(fs)  => fs.unlink(path).await,
+            AnyVfs::Fat(fs)   => fs.unlink(path).await,
+            AnyVfs::Plan9(fs) => fs.unlink(path).await,
+            AnyVfs::Proc(_)   => Err(VfsError::ReadOnly),
+            AnyVfs::Tmp(fs)   => fs.unlink(path).await,
+            AnyVfs::Dev(_)    => Err(VfsError::ReadOnly),
+        }
+    }
+
+    pub async fn mkdir(&self, path: &str) -> Result<(), VfsError> {
+        match self {
+            AnyVfs::Exfat(fs) => fs.mkdir(path).await,
+            AnyVfs::Ext2(fs)  => fs.mkdir(path).await,
+            AnyVfs::Fat(fs)   => fs.mkdir(path).await,
+            AnyVfs::Plan9(fs) => fs.mkdir(path).await,
+            AnyVfs::Proc(_)   => Err(VfsError::ReadOnly),
+            AnyVfs::Tmp(fs)   => fs.mkdir(path).await,
+            AnyVfs::Dev(_)    => Err(VfsError::ReadOnly),
+        }
+    }
+
+    pub async fn rmdir(&self, path: &str) -> Result<(), VfsError> {
+        match self {
+            AnyVfs::Exfat(fs) => fs.rmdir(path).await,
+            AnyVfs::Ext2(fs)  => fs.rmdir(path).await,
+            AnyVfs::Fat(fs)   => fs.rmdir(path).await,
+            AnyVfs::Plan9(fs) => fs.rmdir(path).await,
+            AnyVfs::Proc(_)   => Err(VfsError::ReadOnly),
+            AnyVfs::Tmp(fs)   => fs.rmdir(path).await,
+            AnyVfs::Dev(_)    => Err(VfsError::ReadOnly),
+        }
+    }
+
+    pub async fn rename(&self, from: &str, to: &str) -> Result<(), VfsError> {
+        match self {
+            AnyVfs::Exfat(fs) => fs.rename(from, to).await,
+            AnyVfs::Ext2(fs)  => fs.rename(from, to).await,
+            AnyVfs::Fat(fs)   => fs.rename(from, to).await,
+            AnyVfs::Plan9(fs) => fs.rename(from, to).await,
+            AnyVfs::Proc(_)   => Err(VfsError::ReadOnly),
+            AnyVfs::Tmp(fs)   => fs.rename(from, to).await,
+            AnyVfs::Dev(_)    => Err(VfsError::ReadOnly),
+        }
+    }
+
+    pub async fn fsync(&self, path: &str) -> Result<(), VfsError> {
+        match self {
+            AnyVfs::Exfat(fs) => fs.fsync(path).await,
+            AnyVfs::Ext2(fs)  => fs.fsync(path).await,
+            AnyVfs::Fat(fs)   => fs.fsync(path).await,
+            AnyVfs::Plan9(fs) => fs.fsync(path).await,
+            AnyVfs::Proc(_) | AnyVfs::Dev(_) => Ok(()),
+            AnyVfs::Tmp(fs)   => fs.fsync(path).await,
+        }
+    }
+
+    /// True if the filesystem can hold symbolic links, so path resolution
+    /// has to look at each component.
+    pub fn has_symlinks(&self) -> bool {
+        matches!(self, AnyVfs::Ext2(_) | AnyVfs::Plan9(_) | AnyVfs::Tmp(_))
+    }
+
+    pub async fn readlink(&self, path: &str) -> Result<String, VfsError> {
+        match self {
+            AnyVfs::Ext2(fs)  => fs.readlink(path).await,
+            AnyVfs::Plan9(fs) => fs.readlink(path).await,
+            AnyVfs::Tmp(fs)   => fs.readlink(path),
+            AnyVfs::Exfat(_) | AnyVfs::Fat(_) | AnyVfs::Proc(_) | AnyVfs::Dev(_) => {
+                self.stat(path).await?;
+                Err(VfsError::InvalidArgument)
+            }
+        }
+    }
+
+    pub async fn symlink(&self, path: &str, target: &str) -> Result<(), VfsError> {
+        match self {
+            AnyVfs::Ext2(fs)  => fs.symlink(path, target).await,
+            AnyVfs::Plan9(fs) => fs.symlink(path, target).await,
+            AnyVfs::Tmp(fs)   => fs.symlink(path, target),
+            AnyVfs::Exfat(_) | AnyVfs::Fat(_) => Err(VfsError::NotPermitted),
+            AnyVfs::Proc(_) | AnyVfs::Dev(_) => Err(VfsError::ReadOnly),
+        }
+    }
+
+    pub async fn mksock(&self, path: &str) -> Result<(), VfsError> {
+        match self {
+            AnyVfs::Tmp(fs)   => fs.mksock(path),
+            AnyVfs::Exfat(_) | AnyVfs::Ext2(_) | AnyVfs::Fat(_) | AnyVfs::Plan9(_) => Err(VfsError::NotPermitted),
+            AnyVfs::Proc(_) | AnyVfs::Dev(_) => Err(VfsError::ReadOnly),
+        }
+    }
+
+    pub async fn link(&self, path: &str, target: &str) -> Result<(), VfsError> {
+        match self {
+            AnyVfs::Ext2(fs)  => fs.link(path, target).await,
+            AnyVfs::Plan9(fs) => fs.link(path, target).await,
+            AnyVfs::Tmp(fs)   => fs.link(path, target),
+            AnyVfs::Exfat(_) | AnyVfs::Fat(_) => Err(VfsError::NotPermitted),
+            AnyVfs::Proc(_) | AnyVfs::Dev(_) => Err(VfsError::ReadOnly),
         }
     }
 
     pub fn fs_type(&self) -> &'static str {
         match self {
             AnyVfs::Exfat(_) => "exfat",
+            AnyVfs::Ext2(_)  => "ext2",
+            AnyVfs::Fat(_)   => "vfat",
             AnyVfs::Plan9(_) => "9p",
             AnyVfs::Proc(_)  => "proc",
+            AnyVfs::Tmp(_)   => "tmpfs",
+            AnyVfs::Dev(_)   => "devfs",
         }
     }
 }
@@ -71,17 +462,46 @@ impl AnyVfs {
 // ---------------------------------------------------------------------------
 // Mount table — entries sorted longest-mountpoint-first
 
+/// A mounted filesystem.
+pub struct Mount {
+    pub path: String,
+    pub fs:   Arc<AnyVfs>,
+    /// Device number reported as `st_dev` for its files: major 0 and a
+    /// minor unique to this mount, as Linux gives filesystems without a
+    /// block device.
+    pub dev:  u64,
+}
+
 lazy_static! {
-    static ref MOUNTS: Mutex<Vec<(String, Arc<AnyVfs>)>> = Mutex::new(Vec::new());
+    static ref MOUNTS: Mutex<Vec<Mount>> = Mutex::new(Vec::new());
 }
 
+static NEXT_MINOR: core::sync::atomic::AtomicU32 = core::sync::atomic::AtomicU32::new(1);
+
 /// Register (or replace) a filesystem at `mountpoint`.
 pub fn mount(mountpoint: &str, fs: AnyVfs) {
+    let dev = makedev(0, NEXT_MINOR.fetch_add(1, core::sync::atomic::Ordering::Relaxed));
     let mut mounts = MOUNTS.lock();
-    mounts.retain(|(mp, _)| mp != mountpoint);
-    mounts.push((mountpoint.to_string(), Arc::new(fs)));
+    mounts.retain(|m| m.path != mountpoint);
+    mounts.push(Mount { path: mountpoint.to_string(), fs: Arc::new(fs), dev });
     // Longest mountpoint first so the linear scan finds the best match.
-    mounts.sort_by(|(a, _), (b, _)| b.len().cmp(&a.len()));
+    mounts.sort_by(|a, b| b.path.len().cmp(&a.path.len()));
+}
+
+/// Detect the filesystem on a block device: exFAT, then ext2, then
+/// FAT12/16/32, each bare or in an MBR/GPT partition.  `None` if none is
+/// found.
+pub async fn open_disk(inbox: crate::virtio::exfat::BlkInbox) -> Option<AnyVfs> {
+    if crate::virtio::exfat::open_exfat(&inbox).await.is_ok() {
+        return Some(AnyVfs::Exfat(ExfatVfs::new(inbox)));
+    }
+    if let Ok(vol) = crate::virtio::ext2::open_ext2(&inbox).await {
+        return Some(AnyVfs::Ext2(Ext2Vfs::new(inbox, vol)));
+    }
+    if let Ok(vol) = crate::virtio::fat::open_fat(&inbox).await {
+        return Some(AnyVfs::Fat(FatVfs::new(inbox, vol)));
+    }
+    None
 }
 
 /// Resolve `path` to the filesystem that owns it, returning the filesystem
@@ -90,7 +510,7 @@ pub fn mount(mountpoint: &str, fs: AnyVfs) {
 /// Lock is released before returning — it is never held across an await point.
 fn resolve(path: &str) -> Option<(Arc<AnyVfs>, String)> {
     let mounts = MOUNTS.lock();
-    for (mp, fs) in mounts.iter() {
+    for Mount { path: mp, fs, .. } in mounts.iter() {
         if mp == "/" {
             // Root mount: pass the full path through unchanged.
             return Some((Arc::clone(fs), path.to_string()));
@@ -120,7 +540,7 @@ pub async fn list_dir(path: &str) -> Result<Vec<VfsDirEntry>, VfsError> {
     let child_mounts = child_mount_names(path);
     for name in child_mounts {
         if !entries.iter().any(|e| e.name == name) {
-            entries.push(VfsDirEntry { name, is_dir: true, size: 0 });
+            entries.push(VfsDirEntry { name, kind: FileType::Directory, size: 0 });
         }
     }
 
@@ -133,7 +553,7 @@ fn child_mount_names(dir: &str) -> Vec<String> {
     let mounts = MOUNTS.lock();
     let mut names = Vec::new();
     let prefix = if dir == "/" { "/" } else { dir };
-    for (mp, _) in mounts.iter() {
+    for Mount { path: mp, .. } in mounts.iter() {
         // Skip the mount at dir itself.
         if mp == dir { continue; }
         // Check if mp is a direct child: starts with prefix and has no
@@ -160,11 +580,302 @@ fn child_mount_names(dir: &str) -> Vec<String> {
 /// proc-fs to generate per-process content like `/proc/maps`.
 pub async fn read_file(path: &str, caller_pid: ProcessId) -> Result<Vec<u8>, VfsError> {
     let (fs, rel) = resolve(path).ok_or(VfsError::NoFilesystem)?;
-    fs.read_file(&rel, caller_pid).await
+    if !fs.uses_page_cache() {
+        return fs.read_file(&rel, caller_pid).await;
+    }
+    page_cache::read(&fs, &rel, 0, usize::MAX).await
+}
+
+/// Read up to `len` bytes at byte `offset` of a file.  Returns fewer bytes
+/// at end of file, and none past it.
+pub async fn read_at(path: &str, offset: u64, len: usize, caller_pid: ProcessId) -> Result<Vec<u8>, VfsError> {
+    let (fs, rel) = resolve(path).ok_or(VfsError::NoFilesystem)?;
+    if !fs.uses_page_cache() {
+        return fs.read_at(&rel, offset, len, caller_pid).await;
+    }
+    page_cache::read(&fs, &rel, offset, len).await
+}
+
+/// The frame holding page `index` of a file, with a frame reference taken
+/// for a user mapping: the page-cache frame (see [`page_cache::map_page`]),
+/// or tmpfs's own.  Synthetic files cannot be mapped this way.
+pub async fn map_page(path: &str, index: u64) -> Result<PhysAddr, VfsError> {
+    let (fs, rel) = resolve(path).ok_or(VfsError::NoFilesystem)?;
+    if !fs.uses_page_cache() {
+        return fs.map_page(&rel, index).await;
+    }
+    page_cache::map_page(&fs, &rel, index).await
+}
+
+/// Tell the filesystem that a file descriptor for `path` was opened.  A
+/// filesystem may keep per-file state until the matching [`release`];
+/// 9P keeps the open fid, so the descriptor keeps referring to the file it
+/// opened.
+pub async fn open(path: &str, writable: bool) -> Result<(), VfsError> {
+    let (fs, rel) = resolve(path).ok_or(VfsError::NoFilesystem)?;
+    fs.open(&rel, writable).await
+}
+
+/// Undo an [`open`].  Never blocks, so it can run from `Drop`.
+pub fn release(path: &str, writable: bool) {
+    if let Some((fs, rel)) = resolve(path) {
+        fs.release(&rel, writable);
+    }
+}
+
+/// Open `path` if it names a character device node, returning the object
+/// its file descriptor should refer to.  `None` if it does not; the caller
+/// then opens it as a file or directory.  Never blocks.
+pub fn open_device(path: &str, flags: u32) -> Option<Result<libkernel::file::FdObject, VfsError>> {
+    let (fs, rel) = resolve(path)?;
+    match &*fs {
+        AnyVfs::Dev(dev) => dev.open_node(&rel, flags),
+        _ => None,
+    }
+}
+
+/// True if `path` is on a filesystem whose file content is generated when
+/// read.  Such files report size 0.
+pub fn is_synthetic(path: &str) -> bool {
+    resolve(path).map_or(false, |(fs, _)| fs.is_synthetic())
+}
+
+/// Resolve `path` for an operation that removes or replaces it; a
+/// mountpoint cannot be.
+fn resolve_entry(path: &str) -> Result<(Arc<AnyVfs>, String), VfsError> {
+    let (fs, rel) = resolve(path).ok_or(VfsError::NoFilesystem)?;
+    if rel == "/" {
+        return Err(VfsError::Busy);
+    }
+    Ok((fs, rel))
+}
+
+/// The device number of the mount holding `fs`.
+fn mount_dev(fs: &Arc<AnyVfs>) -> u64 {
+    MOUNTS.lock().iter().find(|m| Arc::ptr_eq(&m.fs, fs)).map_or(0, |m| m.dev)
+}
+
+/// Stat a file or directory; a symbolic link reports itself.  A mountpoint
+/// reports the root of the mounted filesystem.  The size includes data
+/// still in the page cache.
+pub async fn stat(path: &str) -> Result<VfsStat, VfsError> {
+    let (fs, rel) = resolve(path).ok_or(VfsError::NoFilesystem)?;
+    let mut st = if fs.uses_page_cache() {
+        page_cache::stat(&fs, &rel).await?
+    } else {
+        fs.stat(&rel).await?
+    };
+    st.dev = mount_dev(&fs);
+    Ok(st)
+}
+
+/// Resolve `path` to a path without symbolic links, `.` or `..`
+/// components, on which the other functions here operate.  `path` must
+/// be absolute.
+///
+/// Every component but the last must name a directory, or a symbolic link
+/// leading to one.  The last is followed if `follow` is set or `path` ends
+/// in `/`; it need not exist, so the result can name a file to create.
+/// `..` in a link's target applies to the directory the link leads to, not
+/// the one holding it.  Fails with `TooManyLinks` after [`MAX_SYMLINKS`]
+/// links.
+///
+/// Only filesystems that can hold symbolic links are asked about each
+/// component.
+pub async fn canonicalize(path: &str, follow: bool) -> Result<String, VfsError> {
+    let follow = follow || (path.len() > 1 && path.ends_with('/'));
+    // Components still to resolve, the next one last.
+    let mut todo: Vec<String> = path.split('/').rev()
+        .filter(|c| !c.is_empty())
+        .map(|c| c.to_string())
+        .collect();
+    // Resolved so far; empty for the root.
+    let mut out = String::new();
+    let mut links = 0;
+
+    while let Some(name) = todo.pop() {
+        match name.as_str() {
+            "." => continue,
+            ".." => {
+                let end = out.rfind('/').unwrap_or(0);
+                out.truncate(end);
+                continue;
+            }
+            _ => {}
+        }
+        let last = todo.is_empty();
+        let candidate = alloc::format!("{}/{}", out, name);
+        if last && !follow {
+            out = candidate;
+            break;
+        }
+        let (fs, rel) = resolve(&candidate).ok_or(VfsError::NoFilesystem)?;
+        if !fs.has_symlinks() {
+            out = candidate;
+            continue;
+        }
+        match fs.stat(&rel).await {
+            Ok(st) if st.is_symlink() => {
+                links += 1;
+                if links > MAX_SYMLINKS {
+                    return Err(VfsError::TooManyLinks);
+                }
+                let target = fs.readlink(&rel).await?;
+                if target.is_empty() {
+                    return Err(VfsError::NotFound);
+                }
+                if target.starts_with('/') {
+                    out.clear();
+                }
+                todo.extend(target.split('/').rev().filter(|c| !c.is_empty()).map(|c| c.to_string()));
+            }
+            Ok(st) if !last && !st.is_dir() => return Err(VfsError::NotADirectory),
+            Ok(_) => out = candidate,
+            Err(VfsError::NotFound) if last => out = candidate,
+            Err(e) => return Err(e),
+        }
+    }
+
+    if out.is_empty() {
+        out.push('/');
+    }
+    Ok(out)
+}
+
+/// The target of the symbolic link at `path`.  Fails with
+/// `InvalidArgument` if `path` is not a symbolic link.
+pub async fn readlink(path: &str) -> Result<String, VfsError> {
+    let (fs, rel) = resolve(path).ok_or(VfsError::NoFilesystem)?;
+    fs.readlink(&rel).await
+}
+
+/// Create a symbolic link at `path` holding `target`, which is not
+/// checked.
+pub async fn symlink(path: &str, target: &str) -> Result<(), VfsError> {
+    let (fs, rel) = resolve_entry(path).map_err(|e| match e {
+        VfsError::Busy => VfsError::AlreadyExists,
+        e => e,
+    })?;
+    fs.symlink(&rel, target).await
+}
+
+/// Create a socket node at `path`, the name of a unix domain socket.
+/// Only tmpfs can hold one.
+pub async fn mksock(path: &str) -> Result<(), VfsError> {
+    let (fs, rel) = resolve_entry(path).map_err(|e| match e {
+        VfsError::Busy => VfsError::AlreadyExists,
+        e => e,
+    })?;
+    fs.mksock(&rel).await
+}
+
+/// Add the name `path` for the existing file `target`.  Both must be on
+/// the same mount, and `target` must not be a directory.
+pub async fn link(path: &str, target: &str) -> Result<(), VfsError> {
+    let (fs, rel_target) = resolve(target).ok_or(VfsError::NoFilesystem)?;
+    let (to_fs, rel) = resolve_entry(path).map_err(|e| match e {
+        VfsError::Busy => VfsError::AlreadyExists,
+        e => e,
+    })?;
+    if fs.stat(&rel_target).await?.is_dir() {
+        return Err(VfsError::NotPermitted);
+    }
+    if !Arc::ptr_eq(&fs, &to_fs) {
+        return Err(VfsError::CrossDevice);
+    }
+    fs.link(&rel, &rel_target).await
+}
+
+/// Create an empty regular file.  Fails with `AlreadyExists` if `path`
+/// exists.
+pub async fn create(path: &str) -> Result<(), VfsError> {
+    let (fs, rel) = resolve_entry(path).map_err(|e| match e {
+        VfsError::Busy => VfsError::AlreadyExists,
+        e => e,
+    })?;
+    fs.create(&rel).await?;
+    if fs.uses_page_cache() {
+        page_cache::forget(&fs, &rel).await;
+    }
+    Ok(())
+}
+
+/// Write `data` at byte `offset`, extending the file (with zeros across
+/// any gap) if needed.  Returns the number of bytes written.  The data goes
+/// to the page cache and reaches the filesystem on write-back.
+pub async fn write_at(path: &str, offset: u64, data: &[u8]) -> Result<usize, VfsError> {
+    let (fs, rel) = resolve(path).ok_or(VfsError::NoFilesystem)?;
+    if !fs.uses_page_cache() {
+        return fs.write_at(&rel, offset, data).await;
+    }
+    page_cache::write(&fs, &rel, offset, data).await
+}
+
+/// Set the size of a file, discarding data or appending zeros.
+pub async fn truncate(path: &str, size: u64) -> Result<(), VfsError> {
+    let (fs, rel) = resolve(path).ok_or(VfsError::NoFilesystem)?;
+    if !fs.uses_page_cache() {
+        return fs.truncate(&rel, size).await;
+    }
+    page_cache::truncate(&fs, &rel, size).await
+}
+
+/// Remove a file.
+pub async fn unlink(path: &str) -> Result<(), VfsError> {
+    let (fs, rel) = resolve_entry(path)?;
+    if !fs.uses_page_cache() {
+        return fs.unlink(&rel).await;
+    }
+    page_cache::unlink(&fs, &rel).await
+}
+
+/// Create a directory.
+pub async fn mkdir(path: &str) -> Result<(), VfsError> {
+    let (fs, rel) = resolve_entry(path).map_err(|e| match e {
+        VfsError::Busy => VfsError::AlreadyExists,
+        e => e,
+    })?;
+    fs.mkdir(&rel).await
+}
+
+/// Remove an empty directory.
+pub async fn rmdir(path: &str) -> Result<(), VfsError> {
+    let (fs, rel) = resolve_entry(path)?;
+    fs.rmdir(&rel).await
+}
+
+/// Rename `from` to `to`, replacing `to` if it is a file or an empty
+/// directory.  Both paths must be on the same mount.
+pub async fn rename(from: &str, to: &str) -> Result<(), VfsError> {
+    let (fs, rel_from) = resolve_entry(from)?;
+    let (to_fs, rel_to) = resolve_entry(to)?;
+    if !Arc::ptr_eq(&fs, &to_fs) {
+        return Err(VfsError::CrossDevice);
+    }
+    // A directory cannot move inside itself.
+    if rel_to.starts_with(rel_from.as_str())
+        && rel_to.as_bytes().get(rel_from.len()) == Some(&b'/')
+    {
+        return Err(VfsError::InvalidArgument);
+    }
+    if !fs.uses_page_cache() {
+        return fs.rename(&rel_from, &rel_to).await;
+    }
+    page_cache::rename(&fs, &rel_from, &rel_to).await
+}
+
+/// Flush a file's data and metadata to stable storage, writing back its
+/// dirty pages first.
+pub async fn fsync(path: &str) -> Result<(), VfsError> {
+    let (fs, rel) = resolve(path).ok_or(VfsError::NoFilesystem)?;
+    if !fs.uses_page_cache() {
+        return fs.fsync(&rel).await;
+    }
+    page_cache::fsync(&fs, &rel).await
 }
 
 /// Invoke `f` with a snapshot of the current mount table (for listing).
-pub fn with_mounts<F: FnOnce(&[(String, Arc<AnyVfs>)])>(f: F) {
+pub fn with_mounts<F: FnOnce(&[Mount])>(f: F) {
     let mounts = MOUNTS.lock();
     f(&mounts);
 }
@@ -0,0 +1,947 @@
+//! Page cache: file data kept in 4 KiB frames below the VFS.
+//!
+//! Pages are keyed by (filesystem, inode, page index), so every open of a
+//! file shares them, and so do the two mounts of the 9P share.  Reads fill
+//! missing pages in runs of up to [`FILL_PAGES`]; writes land in the cache
+//! and reach the filesystem later, from the [`flusher`] task, `fsync`, or
+//! when the cache needs room.  Clean pages are evicted least recently used
+//! first once the cache is full or the frame allocator runs dry, whether the
+//! cache itself needs the frame or a user page fault does ([`init`]
+//! registers the reclaim hook the fault path calls).
+//!
+//! Every access stats the file first.  A file with no dirty pages whose
+//! size or 9P qid version changed since it was cached (the host modified
+//! it) loses its pages.
+//!
+//! `mmap` maps the cached frames themselves ([`map_page`]).  A mapped
+//! frame carries an extra reference and is never evicted; writes through
+//! shared mappings come back as [`frame_written`].
+
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use lazy_static::lazy_static;
+use libkernel::memory::{phys_mem_offset, with_memory};
+use libkernel::process::ProcessId;
+use libkernel::spin_mutex::SpinMutex as Mutex;
+use libkernel::task::async_mutex::AsyncMutex;
+use libkernel::task::timer::Delay;
+use x86_64::PhysAddr;
+
+use super::{AnyVfs, VfsError, VfsStat};
+
+const PAGE_SIZE: usize = libkernel::consts::PAGE_SIZE as usize;
+
+/// Most pages cached (4 MiB), further capped at an eighth of usable memory.
+const MAX_PAGES: usize = 1024;
+
+/// Pages read from the filesystem by one fill.
+const FILL_PAGES: u64 = 16;
+
+/// Seconds between write-back passes of the flusher.
+const FLUSH_INTERVAL_SECS: u64 = 5;
+
+/// (filesystem cache id, inode number)
+type InodeKey = (usize, u64);
+
+struct Page {
+    phys: PhysAddr,
+    dirty: bool,
+    last_used: u64,
+}
+
+struct Inode {
+    /// Filesystem and path last used to reach the file; write-back goes
+    /// through them.
+    fs: Arc<AnyVfs>,
+    path: String,
+    /// Size including data still in dirty pages.
+    size: u64,
+    version: u64,
+    pages: usize,
+    dirty: usize,
+    /// A write-back is in progress, so the filesystem's size may lag.
+    flushing: bool,
+}
+
+struct PageCache {
+    pages: BTreeMap<(InodeKey, u64), Page>,
+    inodes: BTreeMap<InodeKey, Inode>,
+    /// Page owning each cached frame, by physical address.
+    owners: BTreeMap<u64, (InodeKey, u64)>,
+    /// Bumped whenever cached pages are dropped or data bypasses the cache.
+    /// A fill that started before a bump does not insert its pages.
+    epoch: u64,
+    clock: u64,
+    /// Page limit; 0 until first computed.
+    limit: usize,
+    hits: u64,
+    misses: u64,
+}
+
+lazy_static! {
+    static ref CACHE: Mutex<PageCache> = Mutex::new(PageCache::new());
+}
+
+/// Serialises write-back, and keeps it out of truncates and renames.
+static FLUSH: AsyncMutex<()> = AsyncMutex::new(());
+
+/// True if `path` is `dir` or lies below it.
+fn is_under(path: &str, dir: &str) -> bool {
+    path == dir
+        || (path.starts_with(dir) && path.as_bytes().get(dir.len()) == Some(&b'/'))
+}
+
+fn page_ptr(phys: PhysAddr) -> *mut u8 {
+    (phys_mem_offset() + phys.as_u64()) as *mut u8
+}
+
+fn free_frames(frames: Vec<PhysAddr>) {
+    if !frames.is_empty() {
+        with_memory(|m| {
+            for phys in frames {
+                m.release_shared_frame(phys);
+            }
+        });
+    }
+}
+
+impl PageCache {
+    fn new() -> Self {
+        PageCache {
+            pages: BTreeMap::new(),
+            inodes: BTreeMap::new(),
+            owners: BTreeMap::new(),
+            epoch: 0,
+            clock: 0,
+            limit: 0,
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    fn limit(&mut self) -> usize {
+        if self.limit == 0 {
+            let (_, usable, _) = with_memory(|m| m.frame_stats());
+            self.limit = MAX_PAGES.min(usable / 8).max(1);
+        }
+        self.limit
+    }
+
+    fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+
+    /// Check a cached inode against a fresh stat, dropping its pages if the
+    /// file changed behind the cache.  Returns the size to use.
+    fn validate(&mut self, key: InodeKey, fs: &Arc<AnyVfs>, path: &str, st: &VfsStat) -> (u64, Vec<PhysAddr>) {
+        let stale = match self.inodes.get_mut(&key) {
+            None => return (st.size, Vec::new()),
+            Some(inode) => {
+                if inode.path != path {
+                    inode.fs = Arc::clone(fs);
+                    inode.path = path.to_string();
+                }
+                if inode.dirty > 0 || inode.flushing {
+                    return (inode.size, Vec::new());
+                }
+                inode.version != st.version || inode.size != st.size
+            }
+        };
+        let freed = if stale { self.drop_inode(key) } else { Vec::new() };
+        (st.size, freed)
+    }
+
+    /// Size of a file with data not yet written back, if any.
+    fn pending_size(&self, key: InodeKey) -> Option<u64> {
+        self.inodes.get(&key)
+            .filter(|inode| inode.dirty > 0 || inode.flushing)
+            .map(|inode| inode.size)
+    }
+
+    fn copy_out(&mut self, key: InodeKey, index: u64, off: usize, n: usize, out: &mut Vec<u8>) -> bool {
+        let now = self.tick();
+        match self.pages.get_mut(&(key, index)) {
+            Some(page) => {
+                page.last_used = now;
+                let src = unsafe { core::slice::from_raw_parts(page_ptr(page.phys), PAGE_SIZE) };
+                out.extend_from_slice(&src[off..off + n]);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn copy_in(&mut self, key: InodeKey, index: u64, off: usize, data: &[u8]) -> bool {
+        let now = self.tick();
+        let page = match self.pages.get_mut(&(key, index)) {
+            Some(page) => page,
+            None => return false,
+        };
+        let dst = unsafe { core::slice::from_raw_parts_mut(page_ptr(page.phys), PAGE_SIZE) };
+        dst[off..off + data.len()].copy_from_slice(data);
+        page.last_used = now;
+        let newly_dirty = !page.dirty;
+        page.dirty = true;
+        if let Some(inode) = self.inodes.get_mut(&key) {
+            if newly_dirty {
+                inode.dirty += 1;
+            }
+            inode.size = inode.size.max(index * PAGE_SIZE as u64 + (off + data.len()) as u64);
+        }
+        true
+    }
+
+    /// Add a clean page unless one is already cached or `epoch` is out of
+    /// date.  Returns false if the caller keeps the frame.
+    fn insert(&mut self, key: InodeKey, index: u64, phys: PhysAddr, epoch: u64,
+              fs: &Arc<AnyVfs>, path: &str, st: &VfsStat) -> bool {
+        if self.epoch != epoch || self.pages.contains_key(&(key, index)) {
+            return false;
+        }
+        let now = self.tick();
+        let inode = self.inodes.entry(key).or_insert_with(|| Inode {
+            fs: Arc::clone(fs),
+            path: path.to_string(),
+            size: st.size,
+            version: st.version,
+            pages: 0,
+            dirty: 0,
+            flushing: false,
+        });
+        inode.pages += 1;
+        self.pages.insert((key, index), Page { phys, dirty: false, last_used: now });
+        self.owners.insert(phys.as_u64(), (key, index));
+        true
+    }
+
+    /// Take a reference on a cached page's frame for a mapping.
+    fn share(&mut self, key: InodeKey, index: u64) -> Option<PhysAddr> {
+        let now = self.tick();
+        let page = self.pages.get_mut(&(key, index))?;
+        page.last_used = now;
+        let phys = page.phys;
+        with_memory(|m| m.ref_share(phys));
+        Some(phys)
+    }
+
+    /// Mark the page cached in frame `phys` dirty, if it is still cached.
+    fn mark_dirty(&mut self, phys: PhysAddr) {
+        let (key, index) = match self.owners.get(&phys.as_u64()) {
+            Some(&owner) => owner,
+            None => return,
+        };
+        if let Some(page) = self.pages.get_mut(&(key, index)) {
+            if !page.dirty {
+                page.dirty = true;
+                if let Some(inode) = self.inodes.get_mut(&key) {
+                    inode.dirty += 1;
+                }
+            }
+        }
+    }
+
+    fn remove_page(&mut self, key: InodeKey, index: u64) -> Option<PhysAddr> {
+        let page = self.pages.remove(&(key, index))?;
+        self.owners.remove(&page.phys.as_u64());
+        let gone = match self.inodes.get_mut(&key) {
+            Some(inode) => {
+                inode.pages -= 1;
+                if page.dirty {
+                    inode.dirty -= 1;
+                }
+                inode.pages == 0 && !inode.flushing
+            }
+            None => false,
+        };
+        if gone {
+            self.inodes.remove(&key);
+        }
+        Some(page.phys)
+    }
+
+    /// Drop every page of an inode, dirty or not.
+    fn drop_inode(&mut self, key: InodeKey) -> Vec<PhysAddr> {
+        self.epoch += 1;
+        let indices: Vec<u64> = self.pages.range((key, 0)..=(key, u64::MAX))
+            .map(|(&(_, index), _)| index)
+            .collect();
+        let freed = indices.into_iter().filter_map(|i| self.remove_page(key, i)).collect();
+        self.inodes.remove(&key);
+        freed
+    }
+
+    /// Drop the pages past `size` and zero the tail of the last one.
+    fn truncate(&mut self, key: InodeKey, size: u64) -> Vec<PhysAddr> {
+        self.epoch += 1;
+        if !self.inodes.contains_key(&key) {
+            return Vec::new();
+        }
+        let first_gone = (size + PAGE_SIZE as u64 - 1) / PAGE_SIZE as u64;
+        let indices: Vec<u64> = self.pages.range((key, first_gone)..=(key, u64::MAX))
+            .map(|(&(_, index), _)| index)
+            .collect();
+        let freed = indices.into_iter().filter_map(|i| self.remove_page(key, i)).collect();
+        let tail = (size % PAGE_SIZE as u64) as usize;
+        if tail != 0 {
+            if let Some(page) = self.pages.get(&(key, size / PAGE_SIZE as u64)) {
+                unsafe { core::ptr::write_bytes(page_ptr(page.phys).add(tail), 0, PAGE_SIZE - tail); }
+            }
+        }
+        if let Some(inode) = self.inodes.get_mut(&key) {
+            inode.size = size;
+        }
+        freed
+    }
+
+    /// Take the least recently used clean page's frame.  Pages mapped
+    /// into a process (their frame is shared) stay.
+    fn evict(&mut self) -> Option<PhysAddr> {
+        let pages = &self.pages;
+        let (&(key, index), _) = with_memory(|m| {
+            pages.iter()
+                .filter(|(_, page)| !page.dirty && !m.is_shared(page.phys))
+                .min_by_key(|(_, page)| page.last_used)
+        })?;
+        self.remove_page(key, index)
+    }
+
+    /// The inode owning the least recently used dirty page.
+    fn oldest_dirty(&self) -> Option<InodeKey> {
+        self.pages.iter()
+            .filter(|(_, page)| page.dirty)
+            .min_by_key(|(_, page)| page.last_used)
+            .map(|(&(key, _), _)| key)
+    }
+
+    /// Mark the first dirty page of an inode clean and return what to
+    /// write for it: the page clipped to the file size.
+    fn take_dirty(&mut self, key: InodeKey) -> Option<(Arc<AnyVfs>, String, u64, Vec<u8>)> {
+        let inode = self.inodes.get_mut(&key)?;
+        for (&(_, index), page) in self.pages.range_mut((key, 0)..=(key, u64::MAX)) {
+            if !page.dirty {
+                continue;
+            }
+            page.dirty = false;
+            inode.dirty -= 1;
+            let start = index * PAGE_SIZE as u64;
+            if start >= inode.size {
+                continue;
+            }
+            let len = (inode.size - start).min(PAGE_SIZE as u64) as usize;
+            let src = unsafe { core::slice::from_raw_parts(page_ptr(page.phys), len) };
+            inode.flushing = true;
+            return Some((Arc::clone(&inode.fs), inode.path.clone(), index, src.to_vec()));
+        }
+        None
+    }
+
+    /// Record the outcome of writing back one page.  A failed page is
+    /// dirtied again; a file that no longer exists loses its pages.
+    fn finish_write_back(&mut self, key: InodeKey, index: u64, result: &Result<(), VfsError>) -> Vec<PhysAddr> {
+        let gone = match self.inodes.get_mut(&key) {
+            Some(inode) => {
+                inode.flushing = false;
+                inode.pages == 0
+            }
+            None => return Vec::new(),
+        };
+        match result {
+            Ok(()) if gone => {
+                self.inodes.remove(&key);
+                Vec::new()
+            }
+            Ok(()) => Vec::new(),
+            Err(VfsError::NotFound) => self.drop_inode(key),
+            Err(_) => {
+                if let Some(page) = self.pages.get_mut(&(key, index)) {
+                    if !page.dirty {
+                        page.dirty = true;
+                        if let Some(inode) = self.inodes.get_mut(&key) {
+                            inode.dirty += 1;
+                        }
+                    }
+                }
+                Vec::new()
+            }
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Internal operations
+
+/// A file as one cache operation sees it.
+struct Handle<'a> {
+    fs: &'a Arc<AnyVfs>,
+    path: &'a str,
+    key: InodeKey,
+    stat: VfsStat,
+    /// Cache epoch when the file was looked up.
+    epoch: u64,
+}
+
+/// Stat a regular file and validate its cached pages.
+async fn lookup<'a>(fs: &'a Arc<AnyVfs>, path: &'a str) -> Result<Handle<'a>, VfsError> {
+    let mut stat = fs.stat(path).await?;
+    if stat.is_dir() {
+        return Err(VfsError::NotAFile);
+    }
+    let key = (fs.cache_id(), stat.ino);
+    let (size, freed, epoch) = {
+        let mut cache = CACHE.lock();
+        let (size, freed) = cache.validate(key, fs, path, &stat);
+        (size, freed, cache.epoch)
+    };
+    free_frames(freed);
+    stat.size = size;
+    Ok(Handle { fs, path, key, stat, epoch })
+}
+
+/// A frame for a new page, evicting to make room.  `None` if memory is
+/// exhausted and nothing can be evicted.
+async fn alloc_frame() -> Option<PhysAddr> {
+    let full = {
+        let mut cache = CACHE.lock();
+        cache.pages.len() >= cache.limit()
+    };
+    if !full {
+        if let Some(phys) = with_memory(|m| m.alloc_dma_pages(1)) {
+            return Some(phys);
+        }
+    }
+    let victim = CACHE.lock().evict();
+    if victim.is_some() {
+        return victim;
+    }
+    // Every page is dirty or mapped: write the oldest dirty one back, then
+    // take one of its pages.
+    let key = CACHE.lock().oldest_dirty();
+    if let Some(key) = key {
+        if flush_inode(key).await.is_ok() {
+            let victim = CACHE.lock().evict();
+            if victim.is_some() {
+                return victim;
+            }
+        }
+    }
+    // The rest are mapped into processes: go past the limit while memory
+    // lasts, so large mappings keep working.
+    if full { with_memory(|m| m.alloc_dma_pages(1)) } else { None }
+}
+
+/// Read pages `first..=last` of a file into the cache, stopping at the
+/// first page already cached.  Pages are inserted only if nothing was
+/// dropped since `epoch`; stops quietly if no frame can be had.
+async fn fill(h: &Handle<'_>, first: u64, last: u64, epoch: u64) -> Result<(), VfsError> {
+    let count = {
+        let cache = CACHE.lock();
+        (first..=last).take_while(|&i| !cache.pages.contains_key(&(h.key, i))).count()
+    };
+    if count == 0 {
+        return Ok(());
+    }
+    let data = h.fs.read_at(h.path, first * PAGE_SIZE as u64, count * PAGE_SIZE, ProcessId::KERNEL).await?;
+    CACHE.lock().misses += count as u64;
+
+    for i in 0..count {
+        let phys = match alloc_frame().await {
+            Some(phys) => phys,
+            None => break,
+        };
+        let start = (i * PAGE_SIZE).min(data.len());
+        let chunk = &data[start..(start + PAGE_SIZE).min(data.len())];
+        unsafe {
+            let dst = page_ptr(phys);
+            core::ptr::copy_nonoverlapping(chunk.as_ptr(), dst, chunk.len());
+            core::ptr::write_bytes(dst.add(chunk.len()), 0, PAGE_SIZE - chunk.len());
+        }
+        let inserted = CACHE.lock().insert(h.key, first + i as u64, phys, epoch, h.fs, h.path, &h.stat);
+        if !inserted {
+            free_frames(vec![phys]);
+        }
+    }
+    Ok(())
+}
+
+/// Cache a zeroed page for a write that covers it whole or starts past
+/// the end of the file.
+async fn insert_zeroed(h: &Handle<'_>, index: u64) {
+    if let Some(phys) = alloc_frame().await {
+        unsafe { libkernel::consts::clear_page(page_ptr(phys)); }
+        let inserted = CACHE.lock().insert(h.key, index, phys, h.epoch, h.fs, h.path, &h.stat);
+        if !inserted {
+            free_frames(vec![phys]);
+        }
+    }
+}
+
+/// Write straight to the filesystem when the page cannot be cached.
+async fn write_through(h: &Handle<'_>, pos: u64, data: &[u8]) -> Result<usize, VfsError> {
+    let n = h.fs.write_at(h.path, pos, data).await?;
+    let freed = {
+        let mut cache = CACHE.lock();
+        cache.epoch += 1;
+        if let Some(inode) = cache.inodes.get_mut(&h.key) {
+            inode.size = inode.size.max(pos + n as u64);
+        }
+        // A fill may have cached the old content meanwhile.
+        let index = pos / PAGE_SIZE as u64;
+        let clean = cache.pages.get(&(h.key, index)).map_or(false, |p| !p.dirty);
+        if clean { cache.remove_page(h.key, index) } else { None }
+    };
+    free_frames(freed.into_iter().collect());
+    Ok(n)
+}
+
+/// Write an inode's dirty pages back.  The caller holds [`FLUSH`].
+async fn write_back(key: InodeKey) -> Result<(), VfsError> {
+    let mut last = None;
+    loop {
+        let job = CACHE.lock().take_dirty(key);
+        let (fs, path, index, data) = match job {
+            Some(job) => job,
+            None => break,
+        };
+        let result = match fs.write_at(&path, index * PAGE_SIZE as u64, &data).await {
+            Ok(n) if n == data.len() => Ok(()),
+            Ok(_) => Err(VfsError::IoError),
+            Err(e) => Err(e),
+        };
+        let freed = CACHE.lock().finish_write_back(key, index, &result);
+        free_frames(freed);
+        result?;
+        last = Some((fs, path));
+    }
+
+    // Our own writes change the 9P qid version; record the new one so the
+    // next lookup does not take them for a change on the host.
+    if let Some((fs, path)) = last {
+        if let Ok(st) = fs.stat(&path).await {
+            if st.ino == key.1 {
+                if let Some(inode) = CACHE.lock().inodes.get_mut(&key) {
+                    inode.version = st.version;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn flush_inode(key: InodeKey) -> Result<(), VfsError> {
+    let _flush = FLUSH.lock().await;
+    write_back(key).await
+}
+
+// ---------------------------------------------------------------------------
+// Public API — called by the VFS for filesystems that are not synthetic
+
+/// Read up to `len` bytes at `offset` of a regular file.
+pub async fn read(fs: &Arc<AnyVfs>, path: &str, offset: u64, len: usize) -> Result<Vec<u8>, VfsError> {
+    let h = lookup(fs, path).await?;
+    let end = h.stat.size.min(offset.saturating_add(len as u64));
+    let mut out = Vec::with_capacity(end.saturating_sub(offset) as usize);
+    let mut pos = offset;
+
+    while pos < end {
+        let index = pos / PAGE_SIZE as u64;
+        let off = (pos % PAGE_SIZE as u64) as usize;
+        let n = (PAGE_SIZE - off).min((end - pos) as usize);
+
+        let hit = CACHE.lock().copy_out(h.key, index, off, n, &mut out);
+        if hit {
+            CACHE.lock().hits += 1;
+        } else {
+            let last = ((end - 1) / PAGE_SIZE as u64).min(index + FILL_PAGES - 1);
+            let epoch = CACHE.lock().epoch;
+            fill(&h, index, last, epoch).await?;
+            let filled = CACHE.lock().copy_out(h.key, index, off, n, &mut out);
+            if !filled {
+                // No frame, or the file changed meanwhile: read around
+                // the cache.
+                let data = fs.read_at(path, pos, n, ProcessId::KERNEL).await?;
+                out.extend_from_slice(&data);
+                out.resize(out.len() + (n - data.len()), 0);
+            }
+        }
+        pos += n as u64;
+    }
+    Ok(out)
+}
+
+/// Write `data` at `offset` of a regular file.  The data reaches the
+/// filesystem on write-back.
+pub async fn write(fs: &Arc<AnyVfs>, path: &str, offset: u64, data: &[u8]) -> Result<usize, VfsError> {
+    let h = lookup(fs, path).await?;
+    let end = offset.checked_add(data.len() as u64).ok_or(VfsError::FileTooLarge)?;
+    let mut pos = offset;
+
+    while pos < end {
+        let index = pos / PAGE_SIZE as u64;
+        let off = (pos % PAGE_SIZE as u64) as usize;
+        let n = (PAGE_SIZE - off).min((end - pos) as usize);
+        let chunk = &data[(pos - offset) as usize..][..n];
+
+        let written = CACHE.lock().copy_in(h.key, index, off, chunk);
+        if !written {
+            // A partial page inside the file needs its old content first.
+            let size = CACHE.lock().inodes.get(&h.key).map_or(h.stat.size, |i| i.size);
+            if n < PAGE_SIZE && index * (PAGE_SIZE as u64) < size {
+                fill(&h, index, index, h.epoch).await?;
+            } else {
+                insert_zeroed(&h, index).await;
+            }
+            let written = CACHE.lock().copy_in(h.key, index, off, chunk);
+            if !written {
+                let done = write_through(&h, pos, chunk).await?;
+                if done < n {
+                    return Ok((pos - offset) as usize + done);
+                }
+            }
+        }
+        pos += n as u64;
+    }
+    Ok(data.len())
+}
+
+/// The frame holding page `index` of a regular file, with a reference
+/// taken for a mapping; the caller releases it with `release_shared_frame`.
+/// A missing page is read in along with up to [`FILL_PAGES`] - 1 following
+/// pages of the file.  Pages past the end of file are zeroed.
+pub async fn map_page(fs: &Arc<AnyVfs>, path: &str, index: u64) -> Result<PhysAddr, VfsError> {
+    let h = lookup(fs, path).await?;
+    if let Some(phys) = CACHE.lock().share(h.key, index) {
+        CACHE.lock().hits += 1;
+        return Ok(phys);
+    }
+    let eof_page = h.stat.size.saturating_sub(1) / PAGE_SIZE as u64;
+    let last = (index + FILL_PAGES - 1).min(eof_page.max(index));
+    // A fill inserts nothing if pages were dropped while it read; retry a
+    // few times before giving up.
+    for _ in 0..3 {
+        let epoch = CACHE.lock().epoch;
+        fill(&h, index, last, epoch).await?;
+        if let Some(phys) = CACHE.lock().share(h.key, index) {
+            return Ok(phys);
+        }
+    }
+    Err(VfsError::IoError)
+}
+
+/// A shared mapping wrote to `phys`: mark its page dirty so write-back
+/// picks it up.  Does nothing if the page is no longer cached.
+pub fn frame_written(phys: PhysAddr) {
+    CACHE.lock().mark_dirty(phys);
+}
+
+/// Stat through the cache: a file with data not yet written back reports
+/// the size it will have.
+pub async fn stat(fs: &Arc<AnyVfs>, path: &str) -> Result<VfsStat, VfsError> {
+    let mut st = fs.stat(path).await?;
+    if !st.is_dir() {
+        if let Some(size) = CACHE.lock().pending_size((fs.cache_id(), st.ino)) {
+            st.size = size;
+        }
+    }
+    Ok(st)
+}
+
+/// Set the size of a file in the cache and on the filesystem.
+pub async fn truncate(fs: &Arc<AnyVfs>, path: &str, size: u64) -> Result<(), VfsError> {
+    let _flush = FLUSH.lock().await;
+    let st = fs.stat(path).await?;
+    if !st.is_dir() {
+        let freed = CACHE.lock().truncate((fs.cache_id(), st.ino), size);
+        free_frames(freed);
+    }
+    fs.truncate(path, size).await
+}
+
+/// Write a file's dirty pages back, then sync the filesystem.
+pub async fn fsync(fs: &Arc<AnyVfs>, path: &str) -> Result<(), VfsError> {
+    if let Ok(st) = fs.stat(path).await {
+        let _flush = FLUSH.lock().await;
+        write_back((fs.cache_id(), st.ino)).await?;
+    }
+    fs.fsync(path).await
+}
+
+/// Remove a file, discarding its cached pages.
+pub async fn unlink(fs: &Arc<AnyVfs>, path: &str) -> Result<(), VfsError> {
+    let _flush = FLUSH.lock().await;
+    let st = fs.stat(path).await.ok();
+    fs.unlink(path).await?;
+    if let Some(st) = st {
+        let freed = CACHE.lock().drop_inode((fs.cache_id(), st.ino));
+        free_frames(freed);
+    }
+    Ok(())
+}
+
+/// Rename `from` to `to`.  Dirty pages below `from` are written back
+/// first; afterwards nothing cached under either name is kept, since the
+/// filesystem may give the moved file a new inode number.
+pub async fn rename(fs: &Arc<AnyVfs>, from: &str, to: &str) -> Result<(), VfsError> {
+    let _flush = FLUSH.lock().await;
+    let id = fs.cache_id();
+    let moving: Vec<InodeKey> = CACHE.lock().inodes.iter()
+        .filter(|(key, inode)| key.0 == id && inode.dirty > 0 && is_under(&inode.path, from))
+        .map(|(&key, _)| key)
+        .collect();
+    for key in moving {
+        write_back(key).await?;
+    }
+
+    let replaced = fs.stat(to).await.ok();
+    fs.rename(from, to).await?;
+    let moved = fs.stat(to).await.ok();
+
+    let freed = {
+        let mut cache = CACHE.lock();
+        let mut keys: Vec<InodeKey> = cache.inodes.iter()
+            .filter(|(key, inode)| key.0 == id && (is_under(&inode.path, from) || is_under(&inode.path, to)))
+            .map(|(&key, _)| key)
+            .collect();
+        keys.extend(replaced.iter().chain(moved.iter()).map(|st| (id, st.ino)));
+        keys.into_iter().flat_map(|key| cache.drop_inode(key)).collect()
+    };
+    free_frames(freed);
+    Ok(())
+}
+
+/// Drop anything cached for the inode now at `path`.  Called after the
+/// file is created, since exFAT reuses the inode numbers of deleted files.
+pub async fn forget(fs: &Arc<AnyVfs>, path: &str) {
+    if let Ok(st) = fs.stat(path).await {
+        let freed = CACHE.lock().drop_inode((fs.cache_id(), st.ino));
+        free_frames(freed);
+    }
+}
+
+/// Write every dirty page back.
+pub async fn flush_all() -> Result<(), VfsError> {
+    let _flush = FLUSH.lock().await;
+    let dirty: Vec<InodeKey> = CACHE.lock().inodes.iter()
+        .filter(|(_, inode)| inode.dirty > 0)
+        .map(|(&key, _)| key)
+        .collect();
+    let mut result = Ok(());
+    for key in dirty {
+        if let Err(e) = write_back(key).await {
+            result = Err(e);
+        }
+    }
+    result
+}
+
+/// Register [`reclaim_frame`] with the memory services, so a user page
+/// fault that finds no free frame takes one from the cache.
+pub fn init() {
+    libkernel::memory::set_reclaim_hook(reclaim_frame);
+}
+
+/// Free the frame of the least recently used clean, unmapped page.  Dirty
+/// pages would need a write-back, which a page fault cannot wait for.
+///
+/// The fault path runs with interrupts off, so a task preempted while
+/// holding the cache would never release it: give up instead of spinning
+/// when the cache is busy, and let the fault fail.
+fn reclaim_frame() -> bool {
+    let victim = match CACHE.try_lock() {
+        Some(mut cache) => cache.evict(),
+        None => return false,
+    };
+    match victim {
+        Some(phys) => {
+            free_frames(vec![phys]);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Background task writing dirty pages back every few seconds.
+pub async fn flusher() {
+    loop {
+        Delay::from_secs(FLUSH_INTERVAL_SECS).await;
+        if let Err(e) = flush_all().await {
+            log::warn!("[page_cache] write-back failed: {:?}", e);
+        }
+    }
+}
+
+/// `(cached pages, dirty pages, page limit, hits, misses)`.
+pub fn stats() -> (usize, usize, usize, u64, u64) {
+    let mut cache = CACHE.lock();
+    let dirty = cache.inodes.values().map(|inode| inode.dirty).sum();
+    (cache.pages.len(), dirty, cache.limit(), cache.hits, cache.misses)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::super::TmpVfs;
+    use libkernel::memory::fault::resolve_user_fault;
+    use libkernel::process::{self, Process, Vma, MAP_ANONYMOUS, MAP_PRIVATE, PROT_READ, PROT_WRITE};
+    use libkernel::{serial_print, serial_println};
+    use x86_64::structures::idt::PageFaultErrorCode;
+    use x86_64::VirtAddr;
+
+    const PAGE: u64 = PAGE_SIZE as u64;
+    const BASE: u64 = 0x1000_0000;
+
+    /// A cache id no mounted filesystem has.
+    const TEST_ID: usize = usize::MAX;
+
+    fn tmp_fs() -> Arc<AnyVfs> {
+        Arc::new(AnyVfs::Tmp(TmpVfs::new(1 << 20, 0o777)))
+    }
+
+    /// A private cache holding pages `0..count` of one file of `size`
+    /// bytes, each page filled with its index.  Returns the frames too.
+    fn cache_with(count: u64, size: u64) -> (PageCache, Vec<PhysAddr>) {
+        let mut cache = PageCache::new();
+        let fs = tmp_fs();
+        let st = VfsStat { size, ..VfsStat::default() };
+        let frames: Vec<PhysAddr> = (0..count).map(|i| {
+            let phys = with_memory(|m| m.alloc_dma_pages(1)).unwrap();
+            unsafe { core::ptr::write_bytes(page_ptr(phys), i as u8, PAGE_SIZE); }
+            assert!(cache.insert(KEY, i, phys, 0, &fs, "/f", &st));
+            phys
+        }).collect();
+        (cache, frames)
+    }
+
+    const KEY: InodeKey = (TEST_ID, 2);
+
+    #[test_case]
+    fn test_evict_least_recently_used() {
+        serial_print!("test_evict_least_recently_used... ");
+        let (mut cache, frames) = cache_with(3, 3 * PAGE);
+        let mut out = Vec::new();
+        assert!(cache.copy_out(KEY, 0, 0, 1, &mut out));
+        assert!(cache.copy_in(KEY, 1, 0, b"x"));
+        // Page 2 is oldest now, then page 0; page 1 is dirty.
+        assert_eq!(cache.evict(), Some(frames[2]));
+        assert_eq!(cache.evict(), Some(frames[0]));
+        assert_eq!(cache.evict(), None);
+        assert_eq!(cache.inodes[&KEY].pages, 1);
+        free_frames(vec![frames[0], frames[2]]);
+        free_frames(cache.drop_inode(KEY));
+        assert!(cache.inodes.is_empty() && cache.owners.is_empty());
+        serial_println!("[ok]");
+    }
+
+    #[test_case]
+    fn test_evict_skips_shared_frames() {
+        serial_print!("test_evict_skips_shared_frames... ");
+        let (mut cache, frames) = cache_with(2, 2 * PAGE);
+        // Page 0 is the least recently used, but mapped into a process.
+        with_memory(|m| m.ref_share(frames[0]));
+        assert_eq!(cache.evict(), Some(frames[1]));
+        assert_eq!(cache.evict(), None);
+        // The mapping goes away: the page can be evicted again.
+        with_memory(|m| m.release_shared_frame(frames[0]));
+        assert_eq!(cache.evict(), Some(frames[0]));
+        free_frames(frames);
+        serial_println!("[ok]");
+    }
+
+    #[test_case]
+    fn test_take_dirty_clips_to_file_size() {
+        serial_print!("test_take_dirty_clips_to_file_size... ");
+        let (mut cache, frames) = cache_with(3, PAGE + 100);
+        assert!(cache.copy_in(KEY, 1, 0, b"abcd"));
+        // A shared mapping wrote past the end of the file.
+        cache.mark_dirty(frames[2]);
+        assert_eq!(cache.inodes[&KEY].dirty, 2);
+        assert_eq!(cache.pending_size(KEY), Some(PAGE + 100));
+
+        let (_, path, index, data) = cache.take_dirty(KEY).unwrap();
+        assert_eq!((path.as_str(), index, data.len()), ("/f", 1, 100));
+        assert_eq!(&data[..4], b"abcd");
+        assert!(data[4..].iter().all(|&b| b == 1));
+        assert!(cache.inodes[&KEY].flushing);
+        // Page 2 lies past the end: it is cleaned without being written.
+        assert!(cache.take_dirty(KEY).is_none());
+        assert_eq!(cache.inodes[&KEY].dirty, 0);
+
+        // A failed write dirties the page again.
+        assert!(cache.finish_write_back(KEY, 1, &Err(VfsError::IoError)).is_empty());
+        assert!(cache.pages[&(KEY, 1)].dirty);
+        assert_eq!(cache.inodes[&KEY].dirty, 1);
+        assert!(!cache.inodes[&KEY].flushing);
+        // A file that is gone loses its pages.
+        let freed = cache.finish_write_back(KEY, 1, &Err(VfsError::NotFound));
+        assert_eq!(freed.len(), 3);
+        assert!(cache.pages.is_empty() && cache.inodes.is_empty());
+        free_frames(frames);
+        serial_println!("[ok]");
+    }
+
+    #[test_case]
+    fn test_truncate_zeroes_tail() {
+        serial_print!("test_truncate_zeroes_tail... ");
+        let (mut cache, frames) = cache_with(3, 3 * PAGE);
+        let epoch = cache.epoch;
+        assert_eq!(cache.truncate(KEY, PAGE + 10), vec![frames[2]]);
+        assert!(cache.epoch > epoch);
+        assert_eq!(cache.inodes[&KEY].size, PAGE + 10);
+        let mut out = Vec::new();
+        assert!(cache.copy_out(KEY, 1, 0, PAGE_SIZE, &mut out));
+        assert!(out[..10].iter().all(|&b| b == 1));
+        assert!(out[10..].iter().all(|&b| b == 0));
+        free_frames(frames);
+        serial_println!("[ok]");
+    }
+
+    #[test_case]
+    fn test_fault_reclaims_clean_page() {
+        serial_print!("test_fault_reclaims_clean_page... ");
+        let pml4 = with_memory(|m| m.create_user_page_table());
+        let mut proc = Process::new(pml4, 0, 0, 0);
+        proc.vma_map.insert(BASE, Vma {
+            start: BASE,
+            len: 2 * PAGE,
+            prot: PROT_READ | PROT_WRITE,
+            flags: MAP_PRIVATE | MAP_ANONYMOUS,
+            file: None,
+            offset: 0,
+        });
+        let pid = process::insert(proc);
+        let write = PageFaultErrorCode::CAUSED_BY_WRITE | PageFaultErrorCode::USER_MODE;
+        // The first fault builds the page tables the second one shares.
+        assert!(resolve_user_fault(pid, BASE, write));
+
+        let key = (TEST_ID, 1);
+        let phys = with_memory(|m| m.alloc_dma_pages(1)).unwrap();
+        {
+            let mut cache = CACHE.lock();
+            let epoch = cache.epoch;
+            assert!(cache.insert(key, 0, phys, epoch, &tmp_fs(), "/f", &VfsStat::default()));
+        }
+        let mut taken = Vec::new();
+        while let Some(frame) = with_memory(|m| m.alloc_dma_pages(1)) {
+            taken.push(frame);
+        }
+        init();
+
+        // A busy cache fails the fault rather than spinning.
+        {
+            let _busy = CACHE.lock();
+            assert!(!resolve_user_fault(pid, BASE + PAGE, write));
+        }
+        assert!(resolve_user_fault(pid, BASE + PAGE, write));
+        assert!(!CACHE.lock().pages.contains_key(&(key, 0)));
+        let (mapped, _) = with_memory(|m| m.user_page_entry(pml4, VirtAddr::new(BASE + PAGE))).unwrap();
+        assert_eq!(mapped, phys);
+
+        free_frames(taken);
+        with_memory(|m| m.cleanup_user_address_space(pml4, false));
+        process::reap(pid);
+        serial_println!("[ok]");
+    }
+}
@@ -1,9 +1,10 @@
 //! VFS adapter for 9P2000.L filesystems (host directory sharing via virtio-9p).
 
+use alloc::string::String;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 
-use super::{VfsDirEntry, VfsError};
+use super::{FileType, VfsDirEntry, VfsError, VfsStat};
 use crate::virtio::p9::P9Client;
 use crate::virtio::p9_proto::P9Error;
 
@@ -16,14 +17,30 @@ impl Plan9Vfs {
         Self { client }
     }
 
+    /// Identifies the 9P share to the page cache; every mount of the same
+    /// client shares it.
+    pub fn cache_id(&self) -> usize {
+        Arc::as_ptr(&self.client) as usize
+    }
+
     pub async fn list_dir(&self, path: &str) -> Result<Vec<VfsDirEntry>, VfsError> {
         let entries = self.client.list_dir(path).map_err(map_err)?;
         Ok(entries.into_iter().map(|e| {
-            // DT_DIR = 4
-            let is_dir = e.dtype == 4 || (e.qid.qid_type & 0x80) != 0;
+            let kind = match e.dtype {
+                1  => FileType::Fifo,
+                2  => FileType::CharDevice,
+                4  => FileType::Directory,
+                6  => FileType::BlockDevice,
+                10 => FileType::Symlink,
+                12 => FileType::Socket,
+                // DT_UNKNOWN: fall back to the qid type bits.
+                _ if e.qid.qid_type & 0x80 != 0 => FileType::Directory,
+                _ if e.qid.qid_type & 0x02 != 0 => FileType::Symlink,
+                _ => FileType::Regular,
+            };
             VfsDirEntry {
                 name: e.name,
-                is_dir,
+                kind,
                 size: 0, // readdir doesn't give us size
             }
         }).collect())
@@ -32,13 +49,98 @@ impl Plan9Vfs {
     pub async fn read_file(&self, path: &str) -> Result<Vec<u8>, VfsError> {
         self.client.read_file(path).map_err(map_err)
     }
+
+    pub async fn read_at(&self, path: &str, offset: u64, len: usize) -> Result<Vec<u8>, VfsError> {
+        self.client.read_at(path, offset, len).map_err(map_err)
+    }
+
+    pub async fn stat(&self, path: &str) -> Result<VfsStat, VfsError> {
+        let st = self.client.stat(path).map_err(map_err)?;
+        Ok(VfsStat {
+            mode:    st.mode,
+            nlink:   st.nlink,
+            uid:     st.uid,
+            gid:     st.gid,
+            size:    st.size,
+            blocks:  st.blocks,
+            atime:   st.atime,
+            mtime:   st.mtime,
+            ctime:   st.ctime,
+            ino:     st.qid.path,
+            dev:     0,
+            rdev:    st.rdev,
+            version: st.qid.version as u64,
+        })
+    }
+
+    pub async fn readlink(&self, path: &str) -> Result<String, VfsError> {
+        self.client.readlink(path).map_err(map_err)
+    }
+
+    pub async fn symlink(&self, path: &str, target: &str) -> Result<(), VfsError> {
+        self.client.symlink(path, target).map_err(map_err)
+    }
+
+    pub async fn link(&self, path: &str, target: &str) -> Result<(), VfsError> {
+        self.client.link(path, target).map_err(map_err)
+    }
+
+    pub async fn open(&self, path: &str, writable: bool) -> Result<(), VfsError> {
+        self.client.open(path, writable).map_err(map_err)
+    }
+
+    pub fn release(&self, path: &str, writable: bool) {
+        self.client.release(path, writable);
+    }
+
+    pub async fn create(&self, path: &str) -> Result<(), VfsError> {
+        self.client.create(path).map_err(map_err)
+    }
+
+    pub async fn write_at(&self, path: &str, offset: u64, data: &[u8]) -> Result<usize, VfsError> {
+        self.client.write_at(path, offset, data).map_err(map_err)
+    }
+
+    pub async fn truncate(&self, path: &str, size: u64) -> Result<(), VfsError> {
+        self.client.truncate(path, size).map_err(map_err)
+    }
+
+    pub async fn unlink(&self, path: &str) -> Result<(), VfsError> {
+        self.client.unlink(path, false).map_err(map_err)
+    }
+
+    pub async fn mkdir(&self, path: &str) -> Result<(), VfsError> {
+        self.client.mkdir(path).map_err(map_err)
+    }
+
+    pub async fn rmdir(&self, path: &str) -> Result<(), VfsError> {
+        self.client.unlink(path, true).map_err(map_err)
+    }
+
+    pub async fn rename(&self, from: &str, to: &str) -> Result<(), VfsError> {
+        self.client.rename(from, to).map_err(map_err)
+    }
+
+    pub async fn fsync(&self, path: &str) -> Result<(), VfsError> {
+        self.client.fsync(path).map_err(map_err)
+    }
 }
 
 fn map_err(e: P9Error) -> VfsError {
     match e {
         P9Error::ServerError(2) => VfsError::NotFound,      // ENOENT
+        P9Error::ServerError(1) | P9Error::ServerError(13)
+            => VfsError::PermissionDenied,                  // EPERM, EACCES
         P9Error::ServerError(20) => VfsError::NotADirectory, // ENOTDIR
         P9Error::ServerError(21) => VfsError::NotAFile,      // EISDIR
+        P9Error::ServerError(17) => VfsError::AlreadyExists, // EEXIST
+        P9Error::ServerError(18) => VfsError::CrossDevice,   // EXDEV
+        P9Error::ServerError(22) => VfsError::InvalidArgument, // EINVAL
+        P9Error::ServerError(27) => VfsError::FileTooLarge,  // EFBIG
+        P9Error::ServerError(28) => VfsError::NoSpace,       // ENOSPC
+        P9Error::ServerError(30) => VfsError::ReadOnly,      // EROFS
+        P9Error::ServerError(39) => VfsError::NotEmpty,      // ENOTEMPTY
+        P9Error::ServerError(40) => VfsError::TooManyLinks,  // ELOOP
         P9Error::ServerError(_) | P9Error::DeviceError => VfsError::IoError,
         P9Error::BufferTooSmall | P9Error::InvalidResponse | P9Error::Utf8Error => VfsError::IoError,
     }
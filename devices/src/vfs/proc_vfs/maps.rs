@@ -36,8 +36,8 @@ pub(super) fn generate(pid: libkernel::process::ProcessId) -> String {
         let w = if vma.prot & process::PROT_WRITE != 0 { 'w' } else { '-' };
         let x = if vma.prot & process::PROT_EXEC  != 0 { 'x' } else { '-' };
         let p = if vma.flags & process::MAP_PRIVATE != 0 { 'p' } else { 's' };
-        let _ = writeln!(s, "{:012x}-{:012x} {}{}{}{} 00000000 00:00 0",
-            vma.start, vma.start + vma.len, r, w, x, p);
+        let _ = writeln!(s, "{:012x}-{:012x} {}{}{}{} {:08x} 00:00 0",
+            vma.start, vma.start + vma.len, r, w, x, p, vma.offset);
     }
 
     // User stack — grows down, so the mapped region ends at user_stack_top.
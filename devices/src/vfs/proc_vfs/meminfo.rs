@@ -18,6 +18,14 @@ pub(super) fn generate() -> String {
     let _ = writeln!(s, "Frames: {} allocated / {} usable ({} MiB usable), {} on free list",
         frames_alloc, frames_total, frames_total as u64 * 4 / 1024, free_list);
 
+    // Page cache and block buffer cache
+    let (pages, dirty, limit, hits, misses) = crate::vfs::page_cache::stats();
+    let _ = writeln!(s, "PageCache: {} KiB in {} pages ({} dirty, limit {}), {} hits, {} misses",
+        pages * 4, pages, dirty, limit, hits, misses);
+    let (buffers, buf_hits, buf_misses) = crate::virtio::buffer_cache::stats();
+    let _ = writeln!(s, "Buffers: {} KiB in {} sectors, {} hits, {} misses",
+        buffers / 2, buffers, buf_hits, buf_misses);
+
     // Known virtual regions
     let _ = writeln!(s, "Known virtual regions:");
     let _ = writeln!(s, "  {:#018x}  Heap ({} KiB)",
@@ -1,7 +1,7 @@
 use alloc::string::ToString;
 use alloc::vec::Vec;
 
-use super::{VfsDirEntry, VfsError};
+use super::{FileType, VfsDirEntry, VfsError, VfsStat, S_IFDIR, S_IFREG};
 
 mod cpuinfo;
 mod drivers;
@@ -24,20 +24,20 @@ impl ProcVfs {
     pub async fn list_dir(&self, path: &str) -> Result<Vec<VfsDirEntry>, VfsError> {
         match path {
             "/" => Ok(alloc::vec![
-                VfsDirEntry { name: "cpuinfo".to_string(),  is_dir: false, size: 0 },
-                VfsDirEntry { name: "drivers".to_string(),  is_dir: false, size: 0 },
-                VfsDirEntry { name: "idt".to_string(),      is_dir: false, size: 0 },
-                VfsDirEntry { name: "ioapic".to_string(),   is_dir: false, size: 0 },
-                VfsDirEntry { name: "irq_stats".to_string(), is_dir: false, size: 0 },
-                VfsDirEntry { name: "lapic".to_string(),    is_dir: false, size: 0 },
-                VfsDirEntry { name: "maps".to_string(),     is_dir: false, size: 0 },
-                VfsDirEntry { name: "meminfo".to_string(),  is_dir: false, size: 0 },
-                VfsDirEntry { name: "memmap".to_string(),   is_dir: false, size: 0 },
-                VfsDirEntry { name: "pci".to_string(),      is_dir: false, size: 0 },
-                VfsDirEntry { name: "pmap".to_string(),     is_dir: false, size: 0 },
-                VfsDirEntry { name: "tasks".to_string(),    is_dir: false, size: 0 },
-                VfsDirEntry { name: "threads".to_string(),  is_dir: false, size: 0 },
-                VfsDirEntry { name: "uptime".to_string(),   is_dir: false, size: 0 },
+                VfsDirEntry { name: "cpuinfo".to_string(),  kind: FileType::Regular, size: 0 },
+                VfsDirEntry { name: "drivers".to_string(),  kind: FileType::Regular, size: 0 },
+                VfsDirEntry { name: "idt".to_string(),      kind: FileType::Regular, size: 0 },
+                VfsDirEntry { name: "ioapic".to_string(),   kind: FileType::Regular, size: 0 },
+                VfsDirEntry { name: "irq_stats".to_string(), kind: FileType::Regular, size: 0 },
+                VfsDirEntry { name: "lapic".to_string(),    kind: FileType::Regular, size: 0 },
+                VfsDirEntry { name: "maps".to_string(),     kind: FileType::Regular, size: 0 },
+                VfsDirEntry { name: "meminfo".to_string(),  kind: FileType::Regular, size: 0 },
+                VfsDirEntry { name: "memmap".to_string(),   kind: FileType::Regular, size: 0 },
+                VfsDirEntry { name: "pci".to_string(),      kind: FileType::Regular, size: 0 },
+                VfsDirEntry { name: "pmap".to_string(),     kind: FileType::Regular, size: 0 },
+                VfsDirEntry { name: "tasks".to_string(),    kind: FileType::Regular, size: 0 },
+                VfsDirEntry { name: "threads".to_string(),  kind: FileType::Regular, size: 0 },
+                VfsDirEntry { name: "uptime".to_string(),   kind: FileType::Regular, size: 0 },
             ]),
             _ => Err(VfsError::NotFound),
         }
@@ -62,4 +62,35 @@ impl ProcVfs {
             _ => Err(VfsError::NotFound),
         }
     }
+
+    /// Generates the whole file and returns the requested part of it.
+    pub async fn read_at(
+        &self,
+        path: &str,
+        offset: u64,
+        len: usize,
+        caller_pid: libkernel::process::ProcessId,
+    ) -> Result<Vec<u8>, VfsError> {
+        let mut data = self.read_file(path, caller_pid).await?;
+        let start = (offset as usize).min(data.len());
+        let end = start + len.min(data.len() - start);
+        data.truncate(end);
+        data.drain(..start);
+        Ok(data)
+    }
+
+    /// Files report size 0: their content is generated on each read.  All
+    /// are read-only and owned by root.
+    pub async fn stat(&self, path: &str) -> Result<VfsStat, VfsError> {
+        if path == "/" {
+            return Ok(VfsStat { mode: S_IFDIR | 0o555, nlink: 2, ..VfsStat::default() });
+        }
+        let name = path.trim_start_matches('/');
+        let root = self.list_dir("/").await?;
+        if root.iter().any(|e| e.name == name) {
+            Ok(VfsStat { mode: S_IFREG | 0o444, nlink: 1, ..VfsStat::default() })
+        } else {
+            Err(VfsError::NotFound)
+        }
+    }
 }
@@ -0,0 +1,775 @@
+//! In-memory filesystem (tmpfs), mounted at `/tmp` and `/run`.
+//!
+//! A tree of inodes kept in kernel memory: regular files, directories and
+//! symbolic links, and socket nodes, each with a mode, owner and timestamps.  File data lives
+//! in whole frames, allocated as pages are first written and charged
+//! against the mount's size limit; unwritten ranges are holes that read as
+//! zeros.
+//!
+//! tmpfs bypasses the page cache, since its frames already are the file
+//! data: `read_at` and `write_at` copy to and from them, and `mmap` maps
+//! them directly ([`TmpVfs::map_page`]).  A frame is released by reference
+//! count, so one that is still mapped outlives a truncate or unlink.
+//!
+//! Paths are walked without following symbolic links: the VFS resolves
+//! them first ([`super::canonicalize`]).  Files may have several hard
+//! links; an inode is freed with its last one.
+
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use libkernel::memory::{phys_mem_offset, with_memory};
+use libkernel::spin_mutex::SpinMutex as Mutex;
+use x86_64::PhysAddr;
+
+use super::{FileType, VfsDirEntry, VfsError, VfsStat, S_IFDIR, S_IFLNK, S_IFREG, S_IFSOCK};
+
+const PAGE_SIZE: usize = libkernel::consts::PAGE_SIZE as usize;
+
+const ROOT_INO: u64 = 1;
+
+/// Permission bits of new files, directories, symlinks and sockets.
+const FILE_MODE: u32 = 0o644;
+const DIR_MODE: u32 = 0o755;
+const SYMLINK_MODE: u32 = 0o777;
+const SOCKET_MODE: u32 = 0o755;
+
+pub struct TmpVfs {
+    state: Mutex<TmpState>,
+}
+
+struct TmpState {
+    inodes: BTreeMap<u64, Inode>,
+    next_ino: u64,
+    /// Frames holding file data.
+    pages: usize,
+    max_pages: usize,
+}
+
+struct Inode {
+    kind: Kind,
+    /// Permission bits (`0o7777`); the file type follows from `kind`.
+    mode: u32,
+    uid: u32,
+    gid: u32,
+    nlink: u32,
+    /// Nanoseconds since the Unix epoch.
+    atime: u64,
+    mtime: u64,
+    ctime: u64,
+}
+
+enum Kind {
+    File { size: u64, pages: BTreeMap<u64, PhysAddr> },
+    Dir { entries: BTreeMap<String, u64>, parent: u64 },
+    Symlink { target: String },
+    /// A name for a bound unix domain socket; the socket itself is not
+    /// kept here.
+    Socket,
+}
+
+fn page_ptr(phys: PhysAddr) -> *mut u8 {
+    (phys_mem_offset() + phys.as_u64()) as *mut u8
+}
+
+fn now() -> u64 {
+    libkernel::time::realtime_ns()
+}
+
+/// Path components, skipping empty ones.
+fn components(path: &str) -> impl Iterator<Item = &str> {
+    path.split('/').filter(|c| !c.is_empty())
+}
+
+/// Zero the bytes of `[start, end)` that lie in allocated pages.
+fn zero_range(pages: &BTreeMap<u64, PhysAddr>, start: u64, end: u64) {
+    if start >= end {
+        return;
+    }
+    let ps = PAGE_SIZE as u64;
+    for (&index, &phys) in pages.range(start / ps..=(end - 1) / ps) {
+        let from = start.max(index * ps) - index * ps;
+        let to = end.min((index + 1) * ps) - index * ps;
+        unsafe {
+            core::ptr::write_bytes(page_ptr(phys).add(from as usize), 0, (to - from) as usize);
+        }
+    }
+}
+
+impl Inode {
+    fn new(kind: Kind, mode: u32) -> Self {
+        let t = now();
+        let nlink = if matches!(kind, Kind::Dir { .. }) { 2 } else { 1 };
+        Inode { kind, mode, uid: 0, gid: 0, nlink, atime: t, mtime: t, ctime: t }
+    }
+
+    fn is_dir(&self) -> bool {
+        matches!(self.kind, Kind::Dir { .. })
+    }
+
+    fn file_type(&self) -> u32 {
+        match self.kind {
+            Kind::File { .. } => S_IFREG,
+            Kind::Dir { .. } => S_IFDIR,
+            Kind::Symlink { .. } => S_IFLNK,
+            Kind::Socket => S_IFSOCK,
+        }
+    }
+
+    fn size(&self) -> u64 {
+        match &self.kind {
+            Kind::File { size, .. } => *size,
+            Kind::Dir { .. } | Kind::Socket => 0,
+            Kind::Symlink { target } => target.len() as u64,
+        }
+    }
+}
+
+impl TmpState {
+    fn inode(&self, ino: u64) -> &Inode {
+        self.inodes.get(&ino).expect("tmpfs: dangling inode")
+    }
+
+    fn inode_mut(&mut self, ino: u64) -> &mut Inode {
+        self.inodes.get_mut(&ino).expect("tmpfs: dangling inode")
+    }
+
+    fn entries(&self, ino: u64) -> Result<&BTreeMap<String, u64>, VfsError> {
+        match &self.inode(ino).kind {
+            Kind::Dir { entries, .. } => Ok(entries),
+            _ => Err(VfsError::NotADirectory),
+        }
+    }
+
+    fn entries_mut(&mut self, ino: u64) -> &mut BTreeMap<String, u64> {
+        match &mut self.inode_mut(ino).kind {
+            Kind::Dir { entries, .. } => entries,
+            _ => panic!("tmpfs: not a directory"),
+        }
+    }
+
+    fn lookup(&self, path: &str) -> Result<u64, VfsError> {
+        let mut ino = ROOT_INO;
+        for name in components(path) {
+            ino = match (&self.inode(ino).kind, name) {
+                (Kind::Dir { .. }, ".") => ino,
+                (Kind::Dir { parent, .. }, "..") => *parent,
+                (Kind::Dir { entries, .. }, _) => *entries.get(name).ok_or(VfsError::NotFound)?,
+                _ => return Err(VfsError::NotADirectory),
+            };
+        }
+        Ok(ino)
+    }
+
+    /// The directory holding the last component of `path`, and that
+    /// component.
+    fn lookup_parent<'a>(&self, path: &'a str) -> Result<(u64, &'a str), VfsError> {
+        let trimmed = path.trim_end_matches('/');
+        let (dir, name) = match trimmed.rfind('/') {
+            Some(i) => (&trimmed[..i], &trimmed[i + 1..]),
+            None => ("", trimmed),
+        };
+        if name.is_empty() || name == "." || name == ".." {
+            return Err(VfsError::InvalidArgument);
+        }
+        let parent = self.lookup(dir)?;
+        self.entries(parent)?;
+        Ok((parent, name))
+    }
+
+    fn file(&self, ino: u64) -> Result<(u64, &BTreeMap<u64, PhysAddr>), VfsError> {
+        match &self.inode(ino).kind {
+            Kind::File { size, pages } => Ok((*size, pages)),
+            _ => Err(VfsError::NotAFile),
+        }
+    }
+
+    fn file_mut(&mut self, ino: u64) -> Result<(&mut u64, &mut BTreeMap<u64, PhysAddr>), VfsError> {
+        match &mut self.inode_mut(ino).kind {
+            Kind::File { size, pages } => Ok((size, pages)),
+            _ => Err(VfsError::NotAFile),
+        }
+    }
+
+    /// Add a new inode named `name` in directory `parent`.
+    fn link_new(&mut self, parent: u64, name: &str, inode: Inode) -> Result<u64, VfsError> {
+        if self.entries(parent)?.contains_key(name) {
+            return Err(VfsError::AlreadyExists);
+        }
+        let ino = self.next_ino;
+        self.next_ino += 1;
+        let is_dir = inode.is_dir();
+        self.inodes.insert(ino, inode);
+        self.entries_mut(parent).insert(name.to_string(), ino);
+        let t = now();
+        let dir = self.inode_mut(parent);
+        if is_dir {
+            dir.nlink += 1;
+        }
+        dir.mtime = t;
+        dir.ctime = t;
+        Ok(ino)
+    }
+
+    /// Remove the entry `name` of `parent`, freeing its inode once nothing
+    /// links to it.
+    fn unlink_entry(&mut self, parent: u64, name: &str) {
+        let ino = match self.entries_mut(parent).remove(name) {
+            Some(ino) => ino,
+            None => return,
+        };
+        let t = now();
+        let is_dir = self.inode(ino).is_dir();
+        let dir = self.inode_mut(parent);
+        if is_dir {
+            dir.nlink -= 1;
+        }
+        dir.mtime = t;
+        dir.ctime = t;
+
+        let inode = self.inode_mut(ino);
+        inode.nlink = if is_dir { 0 } else { inode.nlink - 1 };
+        inode.ctime = t;
+        if inode.nlink == 0 {
+            if let Some(Inode { kind: Kind::File { pages, .. }, .. }) = self.inodes.remove(&ino) {
+                self.release_pages(pages.into_values());
+            }
+        }
+    }
+
+    /// Allocate zeroed frames for the missing pages `first..=last` of a
+    /// file, all or none.
+    fn fill_pages(&mut self, ino: u64, first: u64, last: u64) -> Result<(), VfsError> {
+        let missing: Vec<u64> = {
+            let (_, pages) = self.file(ino)?;
+            (first..=last).filter(|i| !pages.contains_key(i)).collect()
+        };
+        if missing.is_empty() {
+            return Ok(());
+        }
+        if self.pages + missing.len() > self.max_pages {
+            return Err(VfsError::NoSpace);
+        }
+
+        let frames = with_memory(|m| {
+            let mut frames = Vec::with_capacity(missing.len());
+            for _ in &missing {
+                match m.alloc_dma_pages(1) {
+                    Some(phys) => frames.push(phys),
+                    None => {
+                        for phys in frames {
+                            m.release_shared_frame(phys);
+                        }
+                        return None;
+                    }
+                }
+            }
+            Some(frames)
+        }).ok_or(VfsError::NoSpace)?;
+
+        self.pages += frames.len();
+        let (_, pages) = self.file_mut(ino)?;
+        for (index, phys) in missing.into_iter().zip(frames) {
+            unsafe { libkernel::consts::clear_page(page_ptr(phys)); }
+            pages.insert(index, phys);
+        }
+        Ok(())
+    }
+
+    fn release_pages(&mut self, frames: impl Iterator<Item = PhysAddr>) {
+        with_memory(|m| {
+            for phys in frames {
+                m.release_shared_frame(phys);
+                self.pages -= 1;
+            }
+        });
+    }
+
+    /// Set a file's size, dropping pages past it or zeroing the range it
+    /// grows over.
+    fn resize(&mut self, ino: u64, new_size: u64) -> Result<(), VfsError> {
+        let ps = PAGE_SIZE as u64;
+        let (size, pages) = self.file_mut(ino)?;
+        let old_size = *size;
+        *size = new_size;
+        if new_size >= old_size {
+            // Pages past the end of file may exist (mapped there); they
+            // must read as zeros once the file grows over them.
+            zero_range(pages, old_size, new_size);
+            return Ok(());
+        }
+        zero_range(pages, new_size, new_size.div_ceil(ps) * ps);
+        let dropped = pages.split_off(&new_size.div_ceil(ps));
+        self.release_pages(dropped.into_values());
+        Ok(())
+    }
+}
+
+impl TmpVfs {
+    /// An empty filesystem holding at most `max_bytes` of file data, whose
+    /// root directory has permission bits `root_mode`.
+    pub fn new(max_bytes: u64, root_mode: u32) -> Self {
+        let mut inodes = BTreeMap::new();
+        inodes.insert(ROOT_INO, Inode::new(
+            Kind::Dir { entries: BTreeMap::new(), parent: ROOT_INO },
+            root_mode,
+        ));
+        Self {
+            state: Mutex::new(TmpState {
+                inodes,
+                next_ino: ROOT_INO + 1,
+                pages: 0,
+                max_pages: (max_bytes / PAGE_SIZE as u64) as usize,
+            }),
+        }
+    }
+
+    /// Default size limit: half of usable memory, as on Linux.
+    pub fn default_size() -> u64 {
+        let (_, usable, _) = with_memory(|m| m.frame_stats());
+        (usable / 2 * PAGE_SIZE) as u64
+    }
+
+    pub async fn list_dir(&self, path: &str) -> Result<Vec<VfsDirEntry>, VfsError> {
+        let state = self.state.lock();
+        let dir = state.lookup(path)?;
+        Ok(state.entries(dir)?.iter().map(|(name, &ino)| {
+            let inode = state.inode(ino);
+            VfsDirEntry { name: name.clone(), kind: FileType::from_mode(inode.file_type()), size: inode.size() }
+        }).collect())
+    }
+
+    pub async fn read_file(&self, path: &str) -> Result<Vec<u8>, VfsError> {
+        self.read_at(path, 0, usize::MAX).await
+    }
+
+    pub async fn read_at(&self, path: &str, offset: u64, len: usize) -> Result<Vec<u8>, VfsError> {
+        let mut state = self.state.lock();
+        let ino = state.lookup(path)?;
+        let (size, pages) = state.file(ino)?;
+
+        let start = offset.min(size);
+        let end = start + (len as u64).min(size - start);
+        let mut data = alloc::vec![0u8; (end - start) as usize];
+        let ps = PAGE_SIZE as u64;
+        if end > start {
+            for (&index, &phys) in pages.range(start / ps..=(end - 1) / ps) {
+                let from = start.max(index * ps);
+                let to = end.min((index + 1) * ps);
+                let src = unsafe {
+                    core::slice::from_raw_parts(page_ptr(phys).add((from - index * ps) as usize), (to - from) as usize)
+                };
+                data[(from - start) as usize..(to - start) as usize].copy_from_slice(src);
+            }
+        }
+        state.inode_mut(ino).atime = now();
+        Ok(data)
+    }
+
+    pub async fn stat(&self, path: &str) -> Result<VfsStat, VfsError> {
+        let state = self.state.lock();
+        let ino = state.lookup(path)?;
+        let inode = state.inode(ino);
+        let pages = match &inode.kind {
+            Kind::File { pages, .. } => pages.len() as u64,
+            _ => 0,
+        };
+        Ok(VfsStat {
+            mode: inode.file_type() | inode.mode,
+            nlink: inode.nlink as u64,
+            uid: inode.uid,
+            gid: inode.gid,
+            size: inode.size(),
+            blocks: pages * (PAGE_SIZE as u64 / 512),
+            atime: inode.atime,
+            mtime: inode.mtime,
+            ctime: inode.ctime,
+            ino,
+            ..VfsStat::default()
+        })
+    }
+
+    pub async fn create(&self, path: &str) -> Result<(), VfsError> {
+        let mut state = self.state.lock();
+        let (parent, name) = state.lookup_parent(path)?;
+        state.link_new(parent, name, Inode::new(
+            Kind::File { size: 0, pages: BTreeMap::new() },
+            FILE_MODE,
+        ))?;
+        Ok(())
+    }
+
+    pub async fn write_at(&self, path: &str, offset: u64, data: &[u8]) -> Result<usize, VfsError> {
+        let mut state = self.state.lock();
+        let ino = state.lookup(path)?;
+        state.file(ino)?;
+        if data.is_empty() {
+            return Ok(0);
+        }
+        let end = offset.checked_add(data.len() as u64).ok_or(VfsError::FileTooLarge)?;
+
+        let ps = PAGE_SIZE as u64;
+        state.fill_pages(ino, offset / ps, (end - 1) / ps)?;
+        let (size, pages) = state.file_mut(ino)?;
+        if offset > *size {
+            zero_range(pages, *size, offset);
+        }
+        for (&index, &phys) in pages.range(offset / ps..=(end - 1) / ps) {
+            let from = offset.max(index * ps);
+            let to = end.min((index + 1) * ps);
+            let dst = unsafe {
+                core::slice::from_raw_parts_mut(page_ptr(phys).add((from - index * ps) as usize), (to - from) as usize)
+            };
+            dst.copy_from_slice(&data[(from - offset) as usize..(to - offset) as usize]);
+        }
+        *size = (*size).max(end);
+
+        let t = now();
+        let inode = state.inode_mut(ino);
+        inode.mtime = t;
+        inode.ctime = t;
+        Ok(data.len())
+    }
+
+    pub async fn truncate(&self, path: &str, size: u64) -> Result<(), VfsError> {
+        let mut state = self.state.lock();
+        let ino = state.lookup(path)?;
+        state.resize(ino, size)?;
+        let t = now();
+        let inode = state.inode_mut(ino);
+        inode.mtime = t;
+        inode.ctime = t;
+        Ok(())
+    }
+
+    pub async fn unlink(&self, path: &str) -> Result<(), VfsError> {
+        let mut state = self.state.lock();
+        let (parent, name) = state.lookup_parent(path)?;
+        let ino = *state.entries(parent)?.get(name).ok_or(VfsError::NotFound)?;
+        if state.inode(ino).is_dir() {
+            return Err(VfsError::NotAFile);
+        }
+        state.unlink_entry(parent, name);
+        Ok(())
+    }
+
+    pub async fn mkdir(&self, path: &str) -> Result<(), VfsError> {
+        let mut state = self.state.lock();
+        let (parent, name) = state.lookup_parent(path)?;
+        state.link_new(parent, name, Inode::new(
+            Kind::Dir { entries: BTreeMap::new(), parent },
+            DIR_MODE,
+        ))?;
+        Ok(())
+    }
+
+    pub async fn rmdir(&self, path: &str) -> Result<(), VfsError> {
+        let mut state = self.state.lock();
+        let (parent, name) = state.lookup_parent(path)?;
+        let ino = *state.entries(parent)?.get(name).ok_or(VfsError::NotFound)?;
+        if !state.entries(ino)?.is_empty() {
+            return Err(VfsError::NotEmpty);
+        }
+        state.unlink_entry(parent, name);
+        Ok(())
+    }
+
+    pub async fn rename(&self, from: &str, to: &str) -> Result<(), VfsError> {
+        let mut state = self.state.lock();
+        let (from_dir, from_name) = state.lookup_parent(from)?;
+        let (to_dir, to_name) = state.lookup_parent(to)?;
+        let ino = *state.entries(from_dir)?.get(from_name).ok_or(VfsError::NotFound)?;
+        let is_dir = state.inode(ino).is_dir();
+
+        if let Some(&target) = state.entries(to_dir)?.get(to_name) {
+            if target == ino {
+                return Ok(());
+            }
+            match (is_dir, state.inode(target).is_dir()) {
+                (true, true) => {
+                    if !state.entries(target)?.is_empty() {
+                        return Err(VfsError::NotEmpty);
+                    }
+                }
+                (true, false) => return Err(VfsError::NotADirectory),
+                (false, true) => return Err(VfsError::NotAFile),
+                (false, false) => {}
+            }
+            state.unlink_entry(to_dir, to_name);
+        }
+
+        let t = now();
+        state.entries_mut(from_dir).remove(from_name);
+        state.entries_mut(to_dir).insert(to_name.to_string(), ino);
+        for dir in [from_dir, to_dir] {
+            let inode = state.inode_mut(dir);
+            inode.mtime = t;
+            inode.ctime = t;
+        }
+        let inode = state.inode_mut(ino);
+        inode.ctime = t;
+        if let Kind::Dir { parent, .. } = &mut inode.kind {
+            *parent = to_dir;
+        }
+        if is_dir {
+            state.inode_mut(from_dir).nlink -= 1;
+            state.inode_mut(to_dir).nlink += 1;
+        }
+        Ok(())
+    }
+
+    /// Nothing to flush: the data only ever lives in memory.
+    pub async fn fsync(&self, _path: &str) -> Result<(), VfsError> {
+        Ok(())
+    }
+
+    /// The frame holding page `index` of a file, with a reference taken for
+    /// a mapping; the caller releases it with `release_shared_frame`.  A
+    /// hole is filled with a zeroed frame first, which counts against the
+    /// size limit.
+    pub async fn map_page(&self, path: &str, index: u64) -> Result<PhysAddr, VfsError> {
+        let mut state = self.state.lock();
+        let ino = state.lookup(path)?;
+        state.fill_pages(ino, index, index)?;
+        let (_, pages) = state.file(ino)?;
+        let phys = pages[&index];
+        with_memory(|m| m.ref_share(phys));
+        Ok(phys)
+    }
+
+    /// Create a symbolic link at `path` pointing to `target`.
+    pub fn symlink(&self, path: &str, target: &str) -> Result<(), VfsError> {
+        let mut state = self.state.lock();
+        let (parent, name) = state.lookup_parent(path)?;
+        state.link_new(parent, name, Inode::new(
+            Kind::Symlink { target: target.to_string() },
+            SYMLINK_MODE,
+        ))?;
+        Ok(())
+    }
+
+    /// Create a socket node at `path`, for a unix domain socket to bind.
+    pub fn mksock(&self, path: &str) -> Result<(), VfsError> {
+        let mut state = self.state.lock();
+        let (parent, name) = state.lookup_parent(path)?;
+        state.link_new(parent, name, Inode::new(Kind::Socket, SOCKET_MODE))?;
+        Ok(())
+    }
+
+    /// The target of the symbolic link at `path`.
+    pub fn readlink(&self, path: &str) -> Result<String, VfsError> {
+        let state = self.state.lock();
+        let ino = state.lookup(path)?;
+        match &state.inode(ino).kind {
+            Kind::Symlink { target } => Ok(target.clone()),
+            _ => Err(VfsError::InvalidArgument),
+        }
+    }
+
+    /// Add the name `path` for the existing file or symlink `target`.
+    pub fn link(&self, path: &str, target: &str) -> Result<(), VfsError> {
+        let mut state = self.state.lock();
+        let ino = state.lookup(target)?;
+        if state.inode(ino).is_dir() {
+            return Err(VfsError::NotPermitted);
+        }
+        let (parent, name) = state.lookup_parent(path)?;
+        if state.entries(parent)?.contains_key(name) {
+            return Err(VfsError::AlreadyExists);
+        }
+        state.entries_mut(parent).insert(name.to_string(), ino);
+        let t = now();
+        let dir = state.inode_mut(parent);
+        dir.mtime = t;
+        dir.ctime = t;
+        let inode = state.inode_mut(ino);
+        inode.nlink += 1;
+        inode.ctime = t;
+        Ok(())
+    }
+
+    /// Set the permission bits and owner of `path`.
+    pub fn set_attr(&self, path: &str, mode: u32, uid: u32, gid: u32) -> Result<(), VfsError> {
+        let mut state = self.state.lock();
+        let ino = state.lookup(path)?;
+        let inode = state.inode_mut(ino);
+        inode.mode = mode & 0o7777;
+        inode.uid = uid;
+        inode.gid = gid;
+        inode.ctime = now();
+        Ok(())
+    }
+}
+
+impl Drop for TmpVfs {
+    fn drop(&mut self) {
+        let mut state = self.state.lock();
+        let frames: Vec<PhysAddr> = state.inodes.values()
+            .flat_map(|inode| match &inode.kind {
+                Kind::File { pages, .. } => pages.values().copied().collect(),
+                _ => Vec::new(),
+            })
+            .collect();
+        state.release_pages(frames.into_iter());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures_util::FutureExt;
+    use libkernel::{serial_print, serial_println};
+
+    /// Run a tmpfs operation; they never suspend.
+    fn run<T>(fut: impl core::future::Future<Output = T>) -> T {
+        fut.now_or_never().unwrap()
+    }
+
+    fn used_pages(fs: &TmpVfs) -> usize {
+        fs.state.lock().pages
+    }
+
+    fn mode(fs: &TmpVfs, path: &str) -> u32 {
+        run(fs.stat(path)).unwrap().mode
+    }
+
+    fn nlink(fs: &TmpVfs, path: &str) -> u64 {
+        run(fs.stat(path)).unwrap().nlink
+    }
+
+    #[test_case]
+    fn test_tmpfs_size_limit() {
+        serial_print!("test_tmpfs_size_limit... ");
+        let fs = TmpVfs::new(2 * PAGE_SIZE as u64, 0o755);
+        let page = [7u8; PAGE_SIZE];
+        run(fs.create("/a")).unwrap();
+        run(fs.create("/b")).unwrap();
+
+        // A write needing more pages than are left allocates none.
+        let three = [1u8; 3 * PAGE_SIZE];
+        assert!(matches!(run(fs.write_at("/a", 0, &three)), Err(VfsError::NoSpace)));
+        assert_eq!((used_pages(&fs), run(fs.stat("/a")).unwrap().size), (0, 0));
+
+        // Holes cost nothing: the second page of /a is the first charged.
+        run(fs.write_at("/a", PAGE_SIZE as u64, &page)).unwrap();
+        assert_eq!(run(fs.stat("/a")).unwrap().blocks, PAGE_SIZE as u64 / 512);
+        assert_eq!(run(fs.read_at("/a", 0, 4)).unwrap(), [0; 4]);
+        run(fs.write_at("/b", 0, &page)).unwrap();
+        assert_eq!(used_pages(&fs), 2);
+        assert!(matches!(run(fs.write_at("/b", PAGE_SIZE as u64, b"x")), Err(VfsError::NoSpace)));
+        assert!(matches!(run(fs.map_page("/a", 0)), Err(VfsError::NoSpace)));
+        // Rewriting an allocated page needs no space.
+        run(fs.write_at("/b", 10, b"xyz")).unwrap();
+
+        // Truncating and unlinking give the space back.
+        run(fs.truncate("/a", 0)).unwrap();
+        run(fs.write_at("/b", PAGE_SIZE as u64, b"x")).unwrap();
+        run(fs.unlink("/b")).unwrap();
+        assert_eq!(used_pages(&fs), 0);
+        run(fs.write_at("/a", 0, &[2u8; 2 * PAGE_SIZE])).unwrap();
+        serial_println!("[ok]");
+    }
+
+    #[test_case]
+    fn test_tmpfs_sticky_root_mode() {
+        serial_print!("test_tmpfs_sticky_root_mode... ");
+        // /tmp is mounted world-writable with the sticky bit.  Every process
+        // runs as root, so the bit is recorded and reported, never checked.
+        let fs = TmpVfs::new(PAGE_SIZE as u64, 0o1777);
+        assert_eq!(mode(&fs, "/"), S_IFDIR | 0o1777);
+
+        // New entries do not inherit it.
+        run(fs.mkdir("/d")).unwrap();
+        run(fs.create("/d/f")).unwrap();
+        assert_eq!(mode(&fs, "/d"), S_IFDIR | DIR_MODE);
+        assert_eq!(mode(&fs, "/d/f"), S_IFREG | FILE_MODE);
+
+        // set_attr keeps the special bits and drops anything above them.
+        fs.set_attr("/d", 0o1733, 1000, 1000).unwrap();
+        fs.set_attr("/d/f", S_IFDIR | 0o4755, 1000, 100).unwrap();
+        assert_eq!(mode(&fs, "/d"), S_IFDIR | 0o1733);
+        let st = run(fs.stat("/d/f")).unwrap();
+        assert_eq!((st.mode, st.uid, st.gid), (S_IFREG | 0o4755, 1000, 100));
+
+        // Root may still remove another user's file from a sticky directory.
+        run(fs.unlink("/d/f")).unwrap();
+        run(fs.rmdir("/d")).unwrap();
+        serial_println!("[ok]");
+    }
+
+    #[test_case]
+    fn test_tmpfs_unlink() {
+        serial_print!("test_tmpfs_unlink... ");
+        let fs = TmpVfs::new(4 * PAGE_SIZE as u64, 0o755);
+        run(fs.mkdir("/d")).unwrap();
+        run(fs.create("/d/f")).unwrap();
+        run(fs.write_at("/d/f", 0, b"data")).unwrap();
+        fs.link("/g", "/d/f").unwrap();
+        assert_eq!(nlink(&fs, "/g"), 2);
+        assert!(matches!(fs.link("/h", "/d"), Err(VfsError::NotPermitted)));
+
+        assert!(matches!(run(fs.unlink("/d")), Err(VfsError::NotAFile)));
+        assert!(matches!(run(fs.rmdir("/d")), Err(VfsError::NotEmpty)));
+        assert!(matches!(run(fs.unlink("/d/missing")), Err(VfsError::NotFound)));
+        assert!(matches!(run(fs.unlink("/d/f/x")), Err(VfsError::NotADirectory)));
+
+        // The data lives on under the other name.
+        run(fs.unlink("/d/f")).unwrap();
+        assert_eq!((nlink(&fs, "/g"), used_pages(&fs)), (1, 1));
+        assert_eq!(run(fs.read_file("/g")).unwrap(), b"data");
+
+        // A mapped frame outlives the last name; the file's charge does not.
+        let phys = run(fs.map_page("/g", 0)).unwrap();
+        run(fs.unlink("/g")).unwrap();
+        assert_eq!(used_pages(&fs), 0);
+        assert_eq!(unsafe { *page_ptr(phys) }, b'd');
+        with_memory(|m| m.release_shared_frame(phys));
+
+        assert_eq!(nlink(&fs, "/"), 3);
+        run(fs.rmdir("/d")).unwrap();
+        assert_eq!(nlink(&fs, "/"), 2);
+        assert!(run(fs.list_dir("/")).unwrap().is_empty());
+        serial_println!("[ok]");
+    }
+
+    #[test_case]
+    fn test_tmpfs_rename() {
+        serial_print!("test_tmpfs_rename... ");
+        let fs = TmpVfs::new(4 * PAGE_SIZE as u64, 0o755);
+        run(fs.mkdir("/a")).unwrap();
+        run(fs.mkdir("/b")).unwrap();
+        run(fs.mkdir("/a/sub")).unwrap();
+        run(fs.create("/a/f")).unwrap();
+        run(fs.write_at("/a/f", 0, b"one")).unwrap();
+        run(fs.create("/b/g")).unwrap();
+        run(fs.write_at("/b/g", 0, b"two")).unwrap();
+
+        // Replacing a file frees the one replaced.
+        run(fs.rename("/a/f", "/b/g")).unwrap();
+        assert_eq!(run(fs.read_file("/b/g")).unwrap(), b"one");
+        assert!(matches!(run(fs.stat("/a/f")), Err(VfsError::NotFound)));
+        assert_eq!(used_pages(&fs), 1);
+
+        assert!(matches!(run(fs.rename("/a/sub", "/b/g")), Err(VfsError::NotADirectory)));
+        assert!(matches!(run(fs.rename("/b/g", "/a/sub")), Err(VfsError::NotAFile)));
+        assert!(matches!(run(fs.rename("/a", "/b")), Err(VfsError::NotEmpty)));
+        assert!(matches!(run(fs.rename("/a/none", "/b/x")), Err(VfsError::NotFound)));
+        // Renaming onto a hard link of the same file changes nothing.
+        fs.link("/b/h", "/b/g").unwrap();
+        run(fs.rename("/b/g", "/b/h")).unwrap();
+        assert_eq!(nlink(&fs, "/b/g"), 2);
+
+        // A moved directory's `..` and both parents' link counts follow.
+        run(fs.rename("/a/sub", "/b/sub")).unwrap();
+        assert_eq!((nlink(&fs, "/a"), nlink(&fs, "/b")), (2, 3));
+        run(fs.create("/b/sub/x")).unwrap();
+        assert!(run(fs.stat("/b/sub/../g")).is_ok());
+        assert!(matches!(run(fs.stat("/a/sub")), Err(VfsError::NotFound)));
+
+        // An empty directory may be replaced.
+        run(fs.rename("/b/sub", "/a")).unwrap();
+        assert_eq!(nlink(&fs, "/b"), 2);
+        assert!(run(fs.stat("/a/x")).is_ok());
+        serial_println!("[ok]");
+    }
+}
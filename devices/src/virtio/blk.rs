@@ -1,3 +1,4 @@
+use alloc::boxed::Box;
 use alloc::vec::Vec;
 use core::future::Future;
 use core::pin::Pin;
@@ -8,10 +9,12 @@ use futures_util::task::AtomicWaker;
 use virtio_drivers::device::blk::{BlkReq, BlkResp, VirtIOBlk};
 use virtio_drivers::transport::pci::PciTransport;
 
-use libkernel::task::mailbox::Reply;
+use libkernel::task::mailbox::{ActorMsg, Reply};
 
 use crate::actor;
-use super::KernelHal;
+use crate::vfs::VfsError;
+use crate::vfs::dev_vfs::{BlockDevice, DevFuture};
+use super::{buffer_cache, BlkInbox, KernelHal};
 
 // ---------------------------------------------------------------------------
 // IRQ state (one static for the single virtio-blk device)
@@ -129,6 +132,10 @@ impl VirtioBlkActor {
             writes: AtomicU64::new(0),
         }
     }
+
+    pub fn capacity_sectors(&self) -> u64 {
+        self.device.lock().capacity()
+    }
 }
 
 #[actor("virtio-blk", VirtioBlkMsg)]
@@ -213,3 +220,94 @@ impl VirtioBlkActor {
         }
     }
 }
+
+// ---------------------------------------------------------------------------
+// BlkDevice — the disk as /dev/vda
+
+const SECTOR_SIZE: u64 = 512;
+
+/// The whole disk, published as a devfs block node.  Partial sectors are
+/// read, modified and written back.  Writes keep the buffer cache in step,
+/// but not the page cache of a filesystem mounted from the same disk.
+pub struct BlkDevice {
+    inbox:   BlkInbox,
+    sectors: u64,
+}
+
+impl BlkDevice {
+    pub fn new(inbox: BlkInbox, sectors: u64) -> Self {
+        BlkDevice { inbox, sectors }
+    }
+
+    async fn read_sector(&self, lba: u64) -> Result<Vec<u8>, VfsError> {
+        let result = self.inbox.ask(|reply| {
+            ActorMsg::Inner(VirtioBlkMsg::Read(lba, reply))
+        }).await;
+        match result {
+            Some(Ok(buf)) => Ok(buf),
+            Some(Err(())) | None => Err(VfsError::IoError),
+        }
+    }
+
+    async fn write_sector(&self, lba: u64, data: Vec<u8>) -> Result<(), VfsError> {
+        buffer_cache::update(lba, &data);
+        let result = self.inbox.ask(|reply| {
+            ActorMsg::Inner(VirtioBlkMsg::Write(lba, data, reply))
+        }).await;
+        match result {
+            Some(Ok(())) => Ok(()),
+            Some(Err(())) | None => {
+                buffer_cache::invalidate(lba);
+                Err(VfsError::IoError)
+            }
+        }
+    }
+
+    async fn read(&self, offset: u64, len: usize) -> Result<Vec<u8>, VfsError> {
+        let mut out = Vec::with_capacity(len);
+        let end = offset + len as u64;
+        let mut pos = offset;
+        while pos < end {
+            let skip = (pos % SECTOR_SIZE) as usize;
+            let n = (SECTOR_SIZE as usize - skip).min((end - pos) as usize);
+            let sector = self.read_sector(pos / SECTOR_SIZE).await?;
+            out.extend_from_slice(&sector[skip..skip + n]);
+            pos += n as u64;
+        }
+        Ok(out)
+    }
+
+    async fn write(&self, offset: u64, data: &[u8]) -> Result<usize, VfsError> {
+        let mut done = 0;
+        while done < data.len() {
+            let pos = offset + done as u64;
+            let lba = pos / SECTOR_SIZE;
+            let skip = (pos % SECTOR_SIZE) as usize;
+            let n = (SECTOR_SIZE as usize - skip).min(data.len() - done);
+            let sector = if n == SECTOR_SIZE as usize {
+                data[done..done + n].to_vec()
+            } else {
+                let mut sector = self.read_sector(lba).await?;
+                sector[skip..skip + n].copy_from_slice(&data[done..done + n]);
+                sector
+            };
+            self.write_sector(lba, sector).await?;
+            done += n;
+        }
+        Ok(done)
+    }
+}
+
+impl BlockDevice for BlkDevice {
+    fn size(&self) -> u64 {
+        self.sectors * SECTOR_SIZE
+    }
+
+    fn read_at(&self, offset: u64, len: usize) -> DevFuture<'_, Vec<u8>> {
+        Box::pin(self.read(offset, len))
+    }
+
+    fn write_at<'a>(&'a self, offset: u64, data: &'a [u8]) -> DevFuture<'a, usize> {
+        Box::pin(self.write(offset, data))
+    }
+}
@@ -0,0 +1,141 @@
+//! Block buffer cache: recently used sectors of filesystem metadata.
+//!
+//! Everything that reads the virtio-blk disk a sector at a time goes through
+//! [`sector`](super::sector): the partition scanner reads the MBR and GPT
+//! through here, and the exFAT, FAT and ext2 drivers read their boot sector
+//! or superblock, allocation tables and bitmaps, inode tables and
+//! directories.  File data bypasses it, since the VFS page cache holds that.
+//! Every sector a driver writes updates the cached copy, as do raw writes to
+//! `/dev/vda`, so the cache never serves stale data.  One cache serves the
+//! single virtio-blk device.
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use lazy_static::lazy_static;
+use libkernel::spin_mutex::SpinMutex as Mutex;
+
+/// Sectors kept: 64 KiB of heap.
+const CAPACITY: usize = 128;
+
+struct Buffer {
+    data: Vec<u8>,
+    last_used: u64,
+}
+
+struct BufferCache {
+    buffers: BTreeMap<u64, Buffer>,
+    clock: u64,
+    hits: u64,
+    misses: u64,
+}
+
+lazy_static! {
+    static ref CACHE: Mutex<BufferCache> = Mutex::new(BufferCache {
+        buffers: BTreeMap::new(),
+        clock: 0,
+        hits: 0,
+        misses: 0,
+    });
+}
+
+/// The cached copy of sector `lba`, if any.
+pub fn get(lba: u64) -> Option<Vec<u8>> {
+    let mut cache = CACHE.lock();
+    cache.clock += 1;
+    let now = cache.clock;
+    let data = cache.buffers.get_mut(&lba).map(|b| {
+        b.last_used = now;
+        b.data.clone()
+    });
+    if data.is_some() { cache.hits += 1 } else { cache.misses += 1 }
+    data
+}
+
+/// Cache sector `lba` after reading it, evicting the least recently used
+/// sector if the cache is full.
+pub fn insert(lba: u64, data: &[u8]) {
+    let mut cache = CACHE.lock();
+    cache.clock += 1;
+    let now = cache.clock;
+    if !cache.buffers.contains_key(&lba) && cache.buffers.len() >= CAPACITY {
+        let oldest = cache.buffers.iter()
+            .min_by_key(|(_, b)| b.last_used)
+            .map(|(&l, _)| l);
+        if let Some(oldest) = oldest {
+            cache.buffers.remove(&oldest);
+        }
+    }
+    cache.buffers.insert(lba, Buffer { data: data.to_vec(), last_used: now });
+}
+
+/// Record a write of sector `lba`: refresh the cached copy if there is one.
+pub fn update(lba: u64, data: &[u8]) {
+    if let Some(b) = CACHE.lock().buffers.get_mut(&lba) {
+        b.data.copy_from_slice(data);
+    }
+}
+
+/// Drop the cached copy of sector `lba`, after a write that may have
+/// failed part-way.
+pub fn invalidate(lba: u64) {
+    CACHE.lock().buffers.remove(&lba);
+}
+
+/// `(cached sectors, hits, misses)`.
+pub fn stats() -> (usize, u64, u64) {
+    let cache = CACHE.lock();
+    (cache.buffers.len(), cache.hits, cache.misses)
+}
+
+/// Forget every cached sector, so a test can put another disk image at
+/// the same sector numbers.
+#[cfg(test)]
+pub(crate) fn clear() {
+    CACHE.lock().buffers.clear();
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use libkernel::{serial_print, serial_println};
+
+    fn sector(fill: u8) -> Vec<u8> {
+        alloc::vec![fill; 512]
+    }
+
+    #[test_case]
+    fn test_buffer_cache_evicts_least_recently_used() {
+        serial_print!("test_buffer_cache_evicts_least_recently_used... ");
+        clear();
+        for lba in 0..CAPACITY as u64 {
+            insert(lba, &sector(lba as u8));
+        }
+        assert!(get(0).is_some());
+        insert(CAPACITY as u64, &sector(0xff));
+        // Sector 1 was the least recently used; sector 0 was just read.
+        assert!(get(1).is_none());
+        assert_eq!(get(0).unwrap(), sector(0));
+        assert_eq!(stats().0, CAPACITY);
+        // Re-inserting a cached sector evicts nothing.
+        insert(0, &sector(7));
+        assert_eq!(stats().0, CAPACITY);
+        assert_eq!(get(0).unwrap(), sector(7));
+        clear();
+        serial_println!("[ok]");
+    }
+
+    #[test_case]
+    fn test_buffer_cache_update_and_invalidate() {
+        serial_print!("test_buffer_cache_update_and_invalidate... ");
+        clear();
+        insert(5, &sector(1));
+        update(5, &sector(2));
+        assert_eq!(get(5).unwrap(), sector(2));
+        // A write of an uncached sector does not cache it.
+        update(6, &sector(3));
+        assert!(get(6).is_none());
+        invalidate(5);
+        assert!(get(5).is_none());
+        serial_println!("[ok]");
+    }
+}
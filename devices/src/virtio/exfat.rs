@@ -6,6 +6,8 @@ use core::convert::TryInto;
 use libkernel::task::mailbox::{ActorMsg, Mailbox};
 
 use super::blk::{VirtioBlkMsg, VirtioBlkInfo};
+use super::partition::{self, PartitionError, PartitionType};
+use super::sector::{read_data_sector, read_sector, write_sector, BlkIoError};
 
 // ---------------------------------------------------------------------------
 // Type alias for the block device mailbox
@@ -25,22 +27,67 @@ pub enum ExfatError {
     NotAFile,
     NotADirectory,
     FileTooLarge,
+    /// The name is already taken in the directory.
+    AlreadyExists,
+    /// The directory still has entries.
+    NotEmpty,
+    /// No free clusters.
+    NoSpace,
+    /// Empty, too long, non-ASCII, or containing a character exFAT forbids.
+    InvalidName,
+    /// The volume uses a feature the write path does not handle (a second
+    /// FAT, as on TexFAT volumes).
+    Unsupported,
+}
+
+impl From<BlkIoError> for ExfatError {
+    fn from(e: BlkIoError) -> Self {
+        match e {
+            BlkIoError::NoDevice => ExfatError::NoDevice,
+            BlkIoError::IoError  => ExfatError::IoError,
+        }
+    }
 }
 
 // ---------------------------------------------------------------------------
 // Public types
 
 /// A directory entry returned by `list_dir`.
+#[derive(Clone)]
 pub struct DirEntry {
     pub name:   String,
     pub is_dir: bool,
     pub size:   u64,
+    /// Last modified time in nanoseconds since the Unix epoch; 0 for the
+    /// root directory, which records none.
+    pub mtime:  u64,
     /// First data cluster — used internally for traversal; not part of the
     /// stable public API surface.
     pub(crate) first_cluster: u32,
     /// If true, clusters are contiguous from `first_cluster`; do not follow
     /// the FAT chain.
     pub(crate) no_fat_chain: bool,
+    /// ValidDataLength: bytes past this offset read as zeros.
+    pub(crate) valid_size: u64,
+    /// On-disk byte address (LBA × 512 + offset) of each 32-byte entry in
+    /// the entry set.  Empty for the root directory, which has none.
+    pub(crate) set_addrs: Vec<u64>,
+}
+
+/// Inode number reported for the root directory.
+const ROOT_INO: u64 = 1;
+
+impl DirEntry {
+    fn is_root(&self) -> bool {
+        self.set_addrs.is_empty()
+    }
+
+    /// A stable inode number: the byte address of the File entry, which does
+    /// not move while the file exists (rename rewrites the entry set
+    /// elsewhere, which counts as a new inode).
+    pub fn ino(&self) -> u64 {
+        self.set_addrs.first().copied().unwrap_or(ROOT_INO)
+    }
 }
 
 /// Parsed exFAT volume state.
@@ -55,129 +102,102 @@ pub struct ExfatVol {
     pub cluster_heap_lba:    u64,
     /// First cluster of the root directory.
     pub root_cluster:        u32,
+    /// Number of clusters in the cluster heap.
+    pub cluster_count:       u32,
+    /// Number of FATs; 2 only on TexFAT volumes.
+    pub number_of_fats:      u8,
 }
 
-// ---------------------------------------------------------------------------
-// GPT "Microsoft Basic Data" partition type GUID (mixed-endian on disk)
-// EBD0A0A2-B9E5-4433-87C0-68B6B72699C7
-
-const GPT_BASIC_DATA_GUID: [u8; 16] = [
-    0xA2, 0xA0, 0xD0, 0xEB,
-    0xE5, 0xB9,
-    0x33, 0x44,
-    0x87, 0xC0, 0x68, 0xB6, 0xB7, 0x26, 0x99, 0xC7,
-];
-
-// ---------------------------------------------------------------------------
-// Internal: read a single 512-byte sector via the virtio-blk actor.
-
-async fn read_sector(inbox: &BlkInbox, lba: u64) -> Result<Vec<u8>, ExfatError> {
-    let result = inbox.ask(|reply| {
-        ActorMsg::Inner(VirtioBlkMsg::Read(lba, reply))
-    }).await;
-    match result {
-        Some(Ok(buf)) => Ok(buf),
-        Some(Err(())) => Err(ExfatError::IoError),
-        None          => Err(ExfatError::NoDevice),
+impl ExfatVol {
+    fn cluster_bytes(&self) -> u64 {
+        self.sectors_per_cluster * SECTOR_SIZE as u64
     }
-}
-
-// ---------------------------------------------------------------------------
-// Partition auto-detection
 
-/// Open the exFAT volume on the given block device.
-///
-/// Detects bare exFAT, MBR-partitioned exFAT, and GPT-partitioned exFAT
-/// automatically.
-pub async fn open_exfat(inbox: &BlkInbox) -> Result<ExfatVol, ExfatError> {
-    let sector0 = read_sector(inbox, 0).await?;
-
-    // Bare exFAT — volume starts at LBA 0.
-    if &sector0[3..11] == b"EXFAT   " {
-        return parse_exfat_boot(&sector0, 0);
+    fn cluster_lba(&self, cluster: u32) -> u64 {
+        self.cluster_heap_lba + (cluster as u64 - 2) * self.sectors_per_cluster
     }
 
-    // Need a valid MBR/protective-MBR signature to proceed.
-    if sector0[510] != 0x55 || sector0[511] != 0xAA {
-        return Err(ExfatError::UnknownPartitionLayout);
+    fn is_valid_cluster(&self, cluster: u32) -> bool {
+        cluster >= 2 && cluster - 2 < self.cluster_count
     }
 
-    // Read LBA 1 to distinguish GPT from MBR.
-    let sector1 = read_sector(inbox, 1).await?;
+    /// Clusters needed to hold `bytes`.
+    fn clusters_for(&self, bytes: u64) -> usize {
+        ((bytes + self.cluster_bytes() - 1) / self.cluster_bytes()) as usize
+    }
 
-    if &sector1[0..8] == b"EFI PART" {
-        find_exfat_gpt(inbox, &sector1).await
-    } else {
-        find_exfat_mbr(inbox, &sector0).await
+    /// LBA and byte offset of the FAT entry for `cluster`.
+    fn fat_entry_pos(&self, cluster: u32) -> (u64, usize) {
+        let byte_off = cluster as u64 * 4;
+        (self.fat_lba + byte_off / SECTOR_SIZE as u64, (byte_off % SECTOR_SIZE as u64) as usize)
     }
 }
 
 // ---------------------------------------------------------------------------
-// GPT layout
+// On-disk constants
 
-async fn find_exfat_gpt(inbox: &BlkInbox, gpt_header: &[u8]) -> Result<ExfatVol, ExfatError> {
-    // GPT header field offsets (UEFI spec 2.x):
-    //   72..80  PartitionEntryLBA       (u64 LE)
-    //   80..84  NumberOfPartitionEntries (u32 LE)
-    //   84..88  SizeOfPartitionEntry    (u32 LE)
-    let entry_lba  = u64::from_le_bytes(gpt_header[72..80].try_into().unwrap());
-    let num_parts  = u32::from_le_bytes(gpt_header[80..84].try_into().unwrap()) as usize;
-    let entry_size = u32::from_le_bytes(gpt_header[84..88].try_into().unwrap()) as usize;
+const SECTOR_SIZE: usize = 512;
+const ENTRY_SIZE:  usize = 32;
 
-    if entry_size == 0 || entry_size > 512 {
-        return Err(ExfatError::UnknownPartitionLayout);
-    }
+/// FAT entry marking the end of a cluster chain.
+const FAT_END_OF_CHAIN: u32 = 0xFFFF_FFFF;
 
-    let entries_per_sector = 512 / entry_size;
-    let num_sectors = (num_parts + entries_per_sector - 1) / entries_per_sector;
+/// Boot sector: VolumeFlags (u16 LE).  Excluded from the boot checksum.
+const VOLUME_FLAGS_OFFSET: usize = 106;
+const VOLUME_DIRTY: u16 = 1 << 1;
 
-    for sector_idx in 0..num_sectors {
-        let lba = entry_lba + sector_idx as u64;
-        let sector = read_sector(inbox, lba).await?;
+// Directory entry types.  Bit 7 (InUse) is cleared to delete an entry.
+const ENTRY_IN_USE:            u8 = 0x80;
+const ENTRY_END_OF_DIRECTORY:  u8 = 0x00;
+const ENTRY_ALLOCATION_BITMAP: u8 = 0x81;
+const ENTRY_FILE:              u8 = 0x85;
+const ENTRY_STREAM_EXTENSION:  u8 = 0xC0;
+const ENTRY_FILE_NAME:         u8 = 0xC1;
 
-        for entry_idx in 0..entries_per_sector {
-            let off = entry_idx * entry_size;
-            if off + entry_size > 512 { break; }
+const ATTR_DIRECTORY: u16 = 1 << 4;
+const ATTR_ARCHIVE:   u16 = 1 << 5;
 
-            let entry = &sector[off..off + entry_size];
+// Stream Extension GeneralSecondaryFlags.
+const FLAG_ALLOCATION_POSSIBLE: u8 = 1 << 0;
+const FLAG_NO_FAT_CHAIN:        u8 = 1 << 1;
 
-            // Skip empty entries (type GUID all-zero).
-            if entry[0..16].iter().all(|&b| b == 0) { continue; }
+/// UtcOffset field: bit 7 marks the offset (here always 0) as valid.
+const UTC_OFFSET_VALID: u8 = 0x80;
 
-            // Match "Microsoft Basic Data" type GUID.
-            if entry[0..16] == GPT_BASIC_DATA_GUID {
-                // GPT partition entry layout:
-                //   32..40  StartingLBA (u64 LE)
-                let start_lba = u64::from_le_bytes(entry[32..40].try_into().unwrap());
-                let boot = read_sector(inbox, start_lba).await?;
-                if &boot[3..11] == b"EXFAT   " {
-                    return parse_exfat_boot(&boot, start_lba);
-                }
-            }
-        }
-    }
-
-    Err(ExfatError::NotExfat)
-}
+const NAME_CHARS_PER_ENTRY: usize = 15;
+const MAX_NAME_LEN:         usize = 255;
 
 // ---------------------------------------------------------------------------
-// MBR layout
-
-async fn find_exfat_mbr(inbox: &BlkInbox, mbr: &[u8]) -> Result<ExfatVol, ExfatError> {
-    // MBR partition table: bytes 446..510, four 16-byte entries.
-    for i in 0..4usize {
-        let off   = 446 + i * 16;
-        let ptype = mbr[off + 4];
-
-        // exFAT and NTFS both use type 0x07 — verify by reading the volume.
-        if ptype == 0x07 {
-            let lba_start = u32::from_le_bytes(mbr[off + 8..off + 12].try_into().unwrap()) as u64;
-            if lba_start == 0 { continue; }
-
-            let boot = read_sector(inbox, lba_start).await?;
-            if &boot[3..11] == b"EXFAT   " {
-                return parse_exfat_boot(&boot, lba_start);
-            }
+// Partition auto-detection
+
+/// Open the exFAT volume on the given block device.
+///
+/// Detects bare exFAT, MBR-partitioned exFAT, and GPT-partitioned exFAT
+/// automatically.
+pub async fn open_exfat(inbox: &BlkInbox) -> Result<ExfatVol, ExfatError> {
+    let sector0 = read_sector(inbox, 0).await?;
+
+    // Bare exFAT — volume starts at LBA 0.
+    if &sector0[3..11] == b"EXFAT   " {
+        return parse_exfat_boot(&sector0, 0);
+    }
+
+    let parts = partition::partitions(inbox).await.map_err(|e| match e {
+        PartitionError::NoDevice      => ExfatError::NoDevice,
+        PartitionError::IoError       => ExfatError::IoError,
+        PartitionError::UnknownLayout => ExfatError::UnknownPartitionLayout,
+    })?;
+
+    // exFAT and NTFS both use MBR type 0x07 — verify by reading the volume.
+    for part in parts {
+        let candidate = match part.ptype {
+            PartitionType::Mbr(t) => t == partition::MBR_EXFAT_NTFS,
+            PartitionType::Gpt(guid) => guid == partition::GPT_BASIC_DATA,
+        };
+        if !candidate { continue; }
+        let boot = read_sector(inbox, part.start_lba).await?;
+        if &boot[3..11] == b"EXFAT   " {
+            return parse_exfat_boot(&boot, part.start_lba);
         }
     }
 
@@ -199,9 +219,12 @@ fn parse_exfat_boot(boot: &[u8], lba_base: u64) -> Result<ExfatVol, ExfatError>
     //   80..84   FatOffset              (u32 LE) sectors from volume start
     //   88..92   ClusterHeapOffset      (u32 LE) sectors from volume start
     //   96..100  FirstClusterOfRootDir  (u32 LE)
+    //   92..96   ClusterCount           (u32 LE)
     //   109      SectorsPerClusterShift (u8)
+    //   110      NumberOfFats           (u8)
     let fat_offset          = u32::from_le_bytes(boot[80..84].try_into().unwrap()) as u64;
     let cluster_heap_offset = u32::from_le_bytes(boot[88..92].try_into().unwrap()) as u64;
+    let cluster_count       = u32::from_le_bytes(boot[92..96].try_into().unwrap());
     let root_cluster        = u32::from_le_bytes(boot[96..100].try_into().unwrap());
     let spc_shift           = boot[109] as u64;
     let sectors_per_cluster = 1u64 << spc_shift;
@@ -212,131 +235,234 @@ fn parse_exfat_boot(boot: &[u8], lba_base: u64) -> Result<ExfatVol, ExfatError>
         fat_lba:          lba_base + fat_offset,
         cluster_heap_lba: lba_base + cluster_heap_offset,
         root_cluster,
+        cluster_count,
+        number_of_fats:   boot[110],
     })
 }
 
 // ---------------------------------------------------------------------------
-// FAT entry
+// Cluster chains
+
+/// List the clusters of a chain.  `size` bounds the walk to the clusters
+/// needed for that many bytes; `None` follows the FAT to end-of-chain (the
+/// root directory, whose length is not recorded anywhere else).
+async fn chain_clusters(
+    vol:          &ExfatVol,
+    inbox:        &BlkInbox,
+    first:        u32,
+    no_fat_chain: bool,
+    size:         Option<u64>,
+) -> Result<Vec<u32>, ExfatError> {
+    let mut clusters = Vec::new();
+    if !vol.is_valid_cluster(first) {
+        return Ok(clusters);
+    }
+    let limit = match size {
+        Some(bytes) => vol.clusters_for(bytes),
+        None        => vol.cluster_count as usize,
+    };
+
+    if no_fat_chain {
+        // Contiguous allocation: the FAT entries are not maintained.
+        for i in 0..limit as u32 {
+            let cluster = first + i;
+            if !vol.is_valid_cluster(cluster) {
+                return Err(ExfatError::IoError);
+            }
+            clusters.push(cluster);
+        }
+        return Ok(clusters);
+    }
 
-async fn read_fat_entry(vol: &ExfatVol, inbox: &BlkInbox, cluster: u32) -> Result<u32, ExfatError> {
-    let byte_off    = cluster as u64 * 4;
-    let sector_lba  = vol.fat_lba + byte_off / 512;
-    let sector_off  = (byte_off % 512) as usize;
+    // Consecutive FAT entries usually share a sector; keep the last one.
+    let mut cached: Option<(u64, Vec<u8>)> = None;
+    let mut current = first;
+    while clusters.len() < limit && vol.is_valid_cluster(current) {
+        clusters.push(current);
+        let (lba, off) = vol.fat_entry_pos(current);
+        if cached.as_ref().map(|(l, _)| *l) != Some(lba) {
+            cached = Some((lba, read_sector(inbox, lba).await?));
+        }
+        let sector = &cached.as_ref().unwrap().1;
+        current = u32::from_le_bytes(sector[off..off + 4].try_into().unwrap());
+    }
+    Ok(clusters)
+}
 
-    let sector = read_sector(inbox, sector_lba).await?;
-    Ok(u32::from_le_bytes(sector[sector_off..sector_off + 4].try_into().unwrap()))
+/// The content of a cluster chain together with the LBA of each sector, so
+/// that modified bytes can be written back in place.
+struct ChainData {
+    clusters: Vec<u32>,
+    bytes:    Vec<u8>,
+    lbas:     Vec<u64>,
+}
+
+impl ChainData {
+    /// On-disk byte address of `bytes[offset]`.
+    fn addr_of(&self, offset: usize) -> u64 {
+        self.lbas[offset / SECTOR_SIZE] * SECTOR_SIZE as u64 + (offset % SECTOR_SIZE) as u64
+    }
+
+    /// Append a zeroed cluster (already zeroed on disk).
+    fn push_zero_cluster(&mut self, vol: &ExfatVol, cluster: u32) {
+        let lba = vol.cluster_lba(cluster);
+        for s in 0..vol.sectors_per_cluster {
+            self.lbas.push(lba + s);
+        }
+        self.bytes.resize(self.bytes.len() + vol.cluster_bytes() as usize, 0);
+        self.clusters.push(cluster);
+    }
+
+    /// Write back the sectors covering `bytes[start..end]`.
+    async fn write_back(&self, inbox: &BlkInbox, start: usize, end: usize) -> Result<(), ExfatError> {
+        if start >= end {
+            return Ok(());
+        }
+        for i in start / SECTOR_SIZE..=(end - 1) / SECTOR_SIZE {
+            let sector = self.bytes[i * SECTOR_SIZE..(i + 1) * SECTOR_SIZE].to_vec();
+            write_sector(inbox, self.lbas[i], sector).await?;
+        }
+        Ok(())
+    }
+}
+
+async fn read_chain(vol: &ExfatVol, inbox: &BlkInbox, clusters: Vec<u32>) -> Result<ChainData, ExfatError> {
+    let mut bytes = Vec::with_capacity(clusters.len() * vol.cluster_bytes() as usize);
+    let mut lbas = Vec::with_capacity(clusters.len() * vol.sectors_per_cluster as usize);
+    for &cluster in &clusters {
+        let lba = vol.cluster_lba(cluster);
+        for s in 0..vol.sectors_per_cluster {
+            bytes.extend_from_slice(&read_sector(inbox, lba + s).await?);
+            lbas.push(lba + s);
+        }
+    }
+    Ok(ChainData { clusters, bytes, lbas })
 }
 
 // ---------------------------------------------------------------------------
 // Directory scanning
 
-/// Read all `DirEntry` values from the directory rooted at `cluster`.
-///
-/// Follows the FAT chain across multiple clusters if the directory spans more
-/// than one.  Entry sets that span a cluster boundary are silently dropped
-/// (very rare on well-formed images).
-async fn scan_dir_cluster(
-    vol: &ExfatVol,
-    inbox: &BlkInbox,
-    cluster: u32,
-) -> Result<Vec<DirEntry>, ExfatError> {
+fn root_entry(vol: &ExfatVol) -> DirEntry {
+    DirEntry {
+        name:          String::new(),
+        is_dir:        true,
+        size:          0,
+        mtime:         0,
+        first_cluster: vol.root_cluster,
+        no_fat_chain:  false,
+        valid_size:    0,
+        set_addrs:     Vec::new(),
+    }
+}
+
+/// Read the whole content of directory `dir`.
+async fn read_dir(vol: &ExfatVol, inbox: &BlkInbox, dir: &DirEntry) -> Result<ChainData, ExfatError> {
+    let size = if dir.is_root() { None } else { Some(dir.size) };
+    let clusters = chain_clusters(vol, inbox, dir.first_cluster, dir.no_fat_chain, size).await?;
+    read_chain(vol, inbox, clusters).await
+}
+
+/// Parse the File entry sets of a directory.  Reading the whole directory
+/// first means entry sets that cross a sector or cluster boundary parse
+/// like any other.
+fn parse_dir(data: &ChainData) -> Vec<DirEntry> {
+    let bytes = &data.bytes;
     let mut entries = Vec::new();
-    let mut current = cluster;
-    const MAX_CLUSTERS: usize = 1_000;
-
-    'chain: for _ in 0..MAX_CLUSTERS {
-        if current < 2 || current >= 0xFFFF_FFF8 { break; }
-        // Collect all sectors of this cluster into a flat buffer so that
-        // entry sets crossing sector boundaries are handled naturally.
-        let cluster_lba = vol.cluster_heap_lba + (current as u64 - 2) * vol.sectors_per_cluster;
-        let mut cluster_data: Vec<u8> = Vec::new();
-        for s in 0..vol.sectors_per_cluster {
-            let sector = read_sector(inbox, cluster_lba + s).await?;
-            cluster_data.extend_from_slice(&sector);
-        }
+    let mut i = 0usize;
 
-        let mut i = 0usize;
-        while i + 32 <= cluster_data.len() {
-            let etype = cluster_data[i];
+    while i + ENTRY_SIZE <= bytes.len() {
+        let etype = bytes[i];
 
-            if etype == 0x00 {
-                // End-of-directory marker — stop completely.
-                break 'chain;
-            }
+        if etype == ENTRY_END_OF_DIRECTORY {
+            // End-of-directory marker — stop completely.
+            break;
+        }
+        if etype != ENTRY_FILE {
+            // Unused / deleted entry, or one we don't need — skip.
+            i += ENTRY_SIZE;
+            continue;
+        }
 
-            if etype == 0x85 {
-                // Primary "File" entry — begins an entry set.
-                let secondary_count = cluster_data[i + 1] as usize;
+        // Primary "File" entry — begins an entry set.
+        let secondary_count = bytes[i + 1] as usize;
+        let set_len = (1 + secondary_count) * ENTRY_SIZE;
+        if secondary_count < 2 || i + set_len > bytes.len() {
+            i += ENTRY_SIZE;
+            continue;
+        }
 
-                // Bounds-check: the whole set must fit in this cluster.
-                if i + (1 + secondary_count) * 32 > cluster_data.len() {
-                    // Entry set crosses a cluster boundary — skip gracefully.
-                    i += 32;
-                    continue;
+        let file_attrs = u16::from_le_bytes([bytes[i + 4], bytes[i + 5]]);
+        let is_dir = file_attrs & ATTR_DIRECTORY != 0;
+        // File: +12 LastModifiedTimestamp, +21 its 10ms increment, +23 its
+        // UTC offset.
+        let mtime = decode_timestamp(
+            u32::from_le_bytes(bytes[i + 12..i + 16].try_into().unwrap()),
+            bytes[i + 21],
+            bytes[i + 23],
+        );
+
+        let mut name_length   = 0usize;
+        let mut valid_size    = 0u64;
+        let mut data_length   = 0u64;
+        let mut first_cluster = 0u32;
+        let mut no_fat_chain  = false;
+        let mut name_chars: Vec<u16> = Vec::new();
+
+        for j in 1..=secondary_count {
+            let e = &bytes[i + j * ENTRY_SIZE..i + (j + 1) * ENTRY_SIZE];
+            match e[0] {
+                ENTRY_STREAM_EXTENSION => {
+                    // Stream Extension:
+                    //   +1       GeneralSecondaryFlags (bit 1 = NoFatChain)
+                    //   +3       NameLength
+                    //   +8..+16  ValidDataLength (u64 LE)
+                    //   +20..+24 FirstCluster    (u32 LE)
+                    //   +24..+32 DataLength      (u64 LE)
+                    no_fat_chain  = e[1] & FLAG_NO_FAT_CHAIN != 0;
+                    name_length   = e[3] as usize;
+                    valid_size    = u64::from_le_bytes(e[8..16].try_into().unwrap());
+                    first_cluster = u32::from_le_bytes(e[20..24].try_into().unwrap());
+                    data_length   = u64::from_le_bytes(e[24..32].try_into().unwrap());
                 }
-
-                let file_attrs = u16::from_le_bytes(
-                    [cluster_data[i + 4], cluster_data[i + 5]]
-                );
-                let is_dir = file_attrs & (1 << 4) != 0;
-
-                let mut data_length    = 0u64;
-                let mut first_cluster  = 0u32;
-                let mut no_fat_chain   = false;
-                let mut name_chars: Vec<u16> = Vec::new();
-
-                for j in 1..=secondary_count {
-                    let off = i + j * 32;
-                    let stype = cluster_data[off];
-
-                    match stype {
-                        0xC0 => {
-                            // Stream Extension:
-                            //   +1       GeneralSecondaryFlags (bit 1 = NoFatChain)
-                            //   +8..+16  DataLength   (u64 LE)
-                            //   +20..+24 FirstCluster (u32 LE)
-                            let s = &cluster_data[off..off + 32];
-                            no_fat_chain  = s[1] & 0x02 != 0;
-                            data_length   = u64::from_le_bytes(s[8..16].try_into().unwrap());
-                            first_cluster = u32::from_le_bytes(s[20..24].try_into().unwrap());
-                        }
-                        0xC1 => {
-                            // File Name: 15 UTF-16LE code units at +2..+32.
-                            let s = &cluster_data[off..off + 32];
-                            for k in 0..15usize {
-                                let w = u16::from_le_bytes([s[2 + k * 2], s[2 + k * 2 + 1]]);
-                                if w == 0 { break; }
-                                name_chars.push(w);
-                            }
-                        }
-                        _ => {}
+                ENTRY_FILE_NAME => {
+                    // File Name: 15 UTF-16LE code units at +2..+32.
+                    for k in 0..NAME_CHARS_PER_ENTRY {
+                        name_chars.push(u16::from_le_bytes([e[2 + k * 2], e[3 + k * 2]]));
                     }
                 }
-
-                let name = utf16_to_string(&name_chars);
-                if !name.is_empty() {
-                    entries.push(DirEntry { name, is_dir, size: data_length, first_cluster, no_fat_chain });
-                }
-
-                i += (1 + secondary_count) * 32;
-            } else if etype < 0x80 {
-                // Unused / deleted entry — skip.
-                i += 32;
-            } else {
-                // Other secondary entry we don't need — skip.
-                i += 32;
+                _ => {}
             }
         }
-
-        // Follow the FAT chain.
-        let next = read_fat_entry(vol, inbox, current).await?;
-        if next >= 0xFFFF_FFF8 {
-            break;
+        name_chars.truncate(name_length);
+
+        let name = utf16_to_string(&name_chars);
+        if !name.is_empty() {
+            let set_addrs = (0..=secondary_count).map(|j| data.addr_of(i + j * ENTRY_SIZE)).collect();
+            entries.push(DirEntry {
+                name,
+                is_dir,
+                size: data_length,
+                mtime,
+                first_cluster,
+                no_fat_chain,
+                valid_size: valid_size.min(data_length),
+                set_addrs,
+            });
         }
-        current = next;
+
+        i += set_len;
     }
 
-    Ok(entries)
+    entries
+}
+
+/// True if the directory has no entries in use (other than the
+/// end-of-directory marker and deleted entries).
+fn dir_is_empty(data: &ChainData) -> bool {
+    data.bytes
+        .chunks(ENTRY_SIZE)
+        .take_while(|e| e[0] != ENTRY_END_OF_DIRECTORY)
+        .all(|e| e[0] & ENTRY_IN_USE == 0)
 }
 
 // ---------------------------------------------------------------------------
@@ -361,28 +487,14 @@ fn utf16_to_string(chars: &[u16]) -> String {
 /// Walk a path (e.g. `"/"`, `"/docs"`, `"/docs/readme.txt"`) and return the
 /// matching `DirEntry`, or an error.
 async fn walk_path(vol: &ExfatVol, inbox: &BlkInbox, path: &str) -> Result<DirEntry, ExfatError> {
-    let components: Vec<&str> = path
-        .trim_matches('/')
-        .split('/')
-        .filter(|s| !s.is_empty())
-        .collect();
-
-    // Virtual root entry.
-    let mut current = DirEntry {
-        name:          String::new(),
-        is_dir:        true,
-        size:          0,
-        first_cluster: vol.root_cluster,
-        no_fat_chain:  false,
-    };
+    let mut current = root_entry(vol);
 
-    for component in &components {
+    for component in path.split('/').filter(|s| !s.is_empty()) {
         if !current.is_dir {
             return Err(ExfatError::NotADirectory);
         }
-        let listing = scan_dir_cluster(vol, inbox, current.first_cluster).await?;
-        let found = listing.into_iter().find(|e| e.name.eq_ignore_ascii_case(component));
-        match found {
+        let listing = parse_dir(&read_dir(vol, inbox, &current).await?);
+        match listing.into_iter().find(|e| e.name.eq_ignore_ascii_case(component)) {
             Some(e) => current = e,
             None    => return Err(ExfatError::PathNotFound),
         }
@@ -391,6 +503,43 @@ async fn walk_path(vol: &ExfatVol, inbox: &BlkInbox, path: &str) -> Result<DirEn
     Ok(current)
 }
 
+/// A directory loaded for modification.
+struct ParentDir {
+    dir:     DirEntry,
+    data:    ChainData,
+    entries: Vec<DirEntry>,
+}
+
+impl ParentDir {
+    fn find(&self, name: &str) -> Option<&DirEntry> {
+        self.entries.iter().find(|e| e.name.eq_ignore_ascii_case(name))
+    }
+}
+
+/// Load the parent directory of `path` and return it with the last path
+/// component.
+async fn open_parent<'p>(
+    vol:   &ExfatVol,
+    inbox: &BlkInbox,
+    path:  &'p str,
+) -> Result<(ParentDir, &'p str), ExfatError> {
+    let trimmed = path.trim_end_matches('/');
+    let (parent_path, name) = match trimmed.rfind('/') {
+        Some(i) => (&trimmed[..i], &trimmed[i + 1..]),
+        None    => ("", trimmed),
+    };
+    if name.is_empty() {
+        return Err(ExfatError::InvalidName);
+    }
+    let dir = walk_path(vol, inbox, parent_path).await?;
+    if !dir.is_dir {
+        return Err(ExfatError::NotADirectory);
+    }
+    let data = read_dir(vol, inbox, &dir).await?;
+    let entries = parse_dir(&data);
+    Ok((ParentDir { dir, data, entries }, name))
+}
+
 // ---------------------------------------------------------------------------
 // Public API
 
@@ -404,52 +553,1137 @@ pub async fn list_dir(
     if !dir.is_dir {
         return Err(ExfatError::NotADirectory);
     }
-    scan_dir_cluster(vol, inbox, dir.first_cluster).await
+    Ok(parse_dir(&read_dir(vol, inbox, &dir).await?))
 }
 
-/// Read a file into memory.  Capped at 256 KiB to protect the heap.
+/// Look up the file or directory at `path`.
+pub async fn stat(
+    vol:   &ExfatVol,
+    inbox: &BlkInbox,
+    path:  &str,
+) -> Result<DirEntry, ExfatError> {
+    walk_path(vol, inbox, path).await
+}
+
+/// Read a whole file into memory.
 pub async fn read_file(
     vol:   &ExfatVol,
     inbox: &BlkInbox,
     path:  &str,
 ) -> Result<Vec<u8>, ExfatError> {
-    const MAX_FILE_SIZE: u64 = 256 * 1024;
+    let file = walk_path(vol, inbox, path).await?;
+    if file.is_dir {
+        return Err(ExfatError::NotAFile);
+    }
+    read_entry_at(vol, inbox, &file, 0, file.size as usize, &mut None).await
+}
 
+/// Read up to `len` bytes at byte `offset` of the file at `path`.  Returns
+/// fewer bytes at end of file, and none past it.
+///
+/// `pos` carries the chain position from one call to the next: a read at
+/// or after the previous one continues from there instead of following the
+/// FAT from the first cluster.
+pub async fn read_at(
+    vol:    &ExfatVol,
+    inbox:  &BlkInbox,
+    path:   &str,
+    offset: u64,
+    len:    usize,
+    pos:    &mut Option<ChainPos>,
+) -> Result<Vec<u8>, ExfatError> {
     let file = walk_path(vol, inbox, path).await?;
     if file.is_dir {
         return Err(ExfatError::NotAFile);
     }
-    if file.size > MAX_FILE_SIZE {
-        return Err(ExfatError::FileTooLarge);
+    read_entry_at(vol, inbox, &file, offset, len, pos).await
+}
+
+/// A cluster reached by an earlier read: the `index`-th cluster of the
+/// chain starting at `first_cluster`.
+#[derive(Clone, Copy)]
+pub struct ChainPos {
+    first_cluster: u32,
+    index:         usize,
+    cluster:       u32,
+}
+
+/// The cluster after `cluster` in a chain.
+async fn next_cluster(
+    vol:          &ExfatVol,
+    inbox:        &BlkInbox,
+    cluster:      u32,
+    no_fat_chain: bool,
+) -> Result<u32, ExfatError> {
+    let next = if no_fat_chain {
+        cluster + 1
+    } else {
+        let (lba, off) = vol.fat_entry_pos(cluster);
+        let sector = read_sector(inbox, lba).await?;
+        u32::from_le_bytes(sector[off..off + 4].try_into().unwrap())
+    };
+    if !vol.is_valid_cluster(next) {
+        return Err(ExfatError::IoError);
+    }
+    Ok(next)
+}
+
+/// The `index`-th cluster of `file`, starting from `pos` when it lies on
+/// the way.
+async fn seek_cluster(
+    vol:   &ExfatVol,
+    inbox: &BlkInbox,
+    file:  &DirEntry,
+    index: usize,
+    pos:   &Option<ChainPos>,
+) -> Result<u32, ExfatError> {
+    if file.no_fat_chain {
+        let cluster = file.first_cluster + index as u32;
+        return if vol.is_valid_cluster(cluster) { Ok(cluster) } else { Err(ExfatError::IoError) };
+    }
+    let (mut i, mut cluster) = match *pos {
+        Some(p) if p.first_cluster == file.first_cluster && p.index <= index => (p.index, p.cluster),
+        _ => (0, file.first_cluster),
+    };
+    if !vol.is_valid_cluster(cluster) {
+        return Err(ExfatError::IoError);
+    }
+    while i < index {
+        cluster = next_cluster(vol, inbox, cluster, false).await?;
+        i += 1;
+    }
+    Ok(cluster)
+}
+
+async fn read_entry_at(
+    vol:    &ExfatVol,
+    inbox:  &BlkInbox,
+    file:   &DirEntry,
+    offset: u64,
+    len:    usize,
+    pos:    &mut Option<ChainPos>,
+) -> Result<Vec<u8>, ExfatError> {
+    if offset >= file.size {
+        return Ok(Vec::new());
+    }
+    let end = file.size.min(offset.saturating_add(len as u64));
+    let mut data = Vec::with_capacity((end - offset) as usize);
+
+    // Past ValidDataLength the content is undefined on disk and reads as
+    // zeros.
+    let valid_end = end.min(file.valid_size);
+    if offset < valid_end {
+        let cluster_bytes = vol.cluster_bytes();
+        let mut index = (offset / cluster_bytes) as usize;
+        let mut cluster = seek_cluster(vol, inbox, file, index, pos).await?;
+        let mut at = offset;
+
+        while at < valid_end {
+            let lba = vol.cluster_lba(cluster) + (at % cluster_bytes) / SECTOR_SIZE as u64;
+            let sector = read_data_sector(inbox, lba).await?;
+            let sector_off = (at % SECTOR_SIZE as u64) as usize;
+            let n = (SECTOR_SIZE - sector_off).min((valid_end - at) as usize);
+            data.extend_from_slice(&sector[sector_off..sector_off + n]);
+            at += n as u64;
+
+            if at < valid_end && at % cluster_bytes == 0 {
+                cluster = next_cluster(vol, inbox, cluster, file.no_fat_chain).await?;
+                index += 1;
+            }
+        }
+        *pos = Some(ChainPos { first_cluster: file.first_cluster, index, cluster });
+    }
+
+    data.resize((end - offset) as usize, 0);
+    Ok(data)
+}
+
+// ---------------------------------------------------------------------------
+// Write path
+//
+// Every mutation runs between `begin_update` and `finish_update`, which set
+// and clear VolumeDirty in the boot sector.  A crash in between leaves the
+// flag set, so fsck.exfat checks the volume on the next mount.  Only the
+// first FAT and allocation bitmap are maintained; TexFAT volumes (two FATs)
+// are refused.
+
+/// Set or clear VolumeDirty.  Returns whether it was set before.
+async fn set_volume_dirty(vol: &ExfatVol, inbox: &BlkInbox, dirty: bool) -> Result<bool, ExfatError> {
+    let mut boot = read_sector(inbox, vol.lba_base).await?;
+    let off = VOLUME_FLAGS_OFFSET;
+    let flags = u16::from_le_bytes([boot[off], boot[off + 1]]);
+    let new_flags = if dirty { flags | VOLUME_DIRTY } else { flags & !VOLUME_DIRTY };
+    if new_flags != flags {
+        boot[off..off + 2].copy_from_slice(&new_flags.to_le_bytes());
+        write_sector(inbox, vol.lba_base, boot).await?;
     }
+    Ok(flags & VOLUME_DIRTY != 0)
+}
 
-    let mut data: Vec<u8> = Vec::new();
-    let mut cluster        = file.first_cluster;
-    let mut bytes_left     = file.size;
+async fn begin_update(vol: &ExfatVol, inbox: &BlkInbox) -> Result<bool, ExfatError> {
+    if vol.number_of_fats != 1 {
+        return Err(ExfatError::Unsupported);
+    }
+    set_volume_dirty(vol, inbox, true).await
+}
 
-    const MAX_CLUSTERS: usize = 1_000;
+/// Clear VolumeDirty again unless it was already set before the update,
+/// or the update failed part-way through on a device error.
+async fn finish_update<T>(
+    vol:       &ExfatVol,
+    inbox:     &BlkInbox,
+    was_dirty: bool,
+    result:    Result<T, ExfatError>,
+) -> Result<T, ExfatError> {
+    let io_failed = matches!(result, Err(ExfatError::IoError) | Err(ExfatError::NoDevice));
+    if !was_dirty && !io_failed {
+        set_volume_dirty(vol, inbox, false).await?;
+    }
+    result
+}
 
-    for _ in 0..MAX_CLUSTERS {
-        if bytes_left == 0 || cluster < 2 || cluster >= 0xFFFF_FFF8 { break; }
+// ---------------------------------------------------------------------------
+// Allocation bitmap
 
-        let cluster_lba = vol.cluster_heap_lba + (cluster as u64 - 2) * vol.sectors_per_cluster;
+/// The allocation bitmap: bit N-2 is set when cluster N is in use.
+struct Bitmap {
+    data:  ChainData,
+    /// Byte range modified since the last flush.
+    dirty: Option<(usize, usize)>,
+}
 
-        for s in 0..vol.sectors_per_cluster {
-            if bytes_left == 0 { break; }
-            let sector     = read_sector(inbox, cluster_lba + s).await?;
-            let to_copy    = (bytes_left.min(512)) as usize;
-            data.extend_from_slice(&sector[..to_copy]);
-            bytes_left     = bytes_left.saturating_sub(to_copy as u64);
+impl Bitmap {
+    fn is_free(&self, cluster: u32) -> bool {
+        let bit = (cluster - 2) as usize;
+        self.data.bytes[bit / 8] & (1 << (bit % 8)) == 0
+    }
+
+    fn set(&mut self, cluster: u32, in_use: bool) {
+        let bit = (cluster - 2) as usize;
+        let byte = bit / 8;
+        if in_use {
+            self.data.bytes[byte] |= 1 << (bit % 8);
+        } else {
+            self.data.bytes[byte] &= !(1 << (bit % 8));
+        }
+        self.dirty = Some(match self.dirty {
+            Some((start, end)) => (start.min(byte), end.max(byte + 1)),
+            None               => (byte, byte + 1),
+        });
+    }
+
+    async fn flush(&mut self, inbox: &BlkInbox) -> Result<(), ExfatError> {
+        if let Some((start, end)) = self.dirty.take() {
+            self.data.write_back(inbox, start, end).await?;
+        }
+        Ok(())
+    }
+
+    /// Find `count` free clusters.  Prefers a contiguous run starting at
+    /// `hint`, then the first contiguous run anywhere, then any free
+    /// clusters.
+    fn find_free(&self, vol: &ExfatVol, hint: Option<u32>, count: usize) -> Option<Vec<u32>> {
+        let end = 2 + vol.cluster_count;
+        let run = |start: u32| (start..start + count as u32).collect::<Vec<u32>>();
+
+        if let Some(h) = hint {
+            if vol.is_valid_cluster(h)
+                && (h as u64 + count as u64) <= end as u64
+                && (h..h + count as u32).all(|c| self.is_free(c))
+            {
+                return Some(run(h));
+            }
         }
 
-        if file.no_fat_chain {
-            // Contiguous allocation: clusters are sequential.
-            cluster += 1;
+        let mut run_start = 2;
+        let mut run_len = 0;
+        for c in 2..end {
+            if !self.is_free(c) {
+                run_len = 0;
+                continue;
+            }
+            if run_len == 0 {
+                run_start = c;
+            }
+            run_len += 1;
+            if run_len == count {
+                return Some(run(run_start));
+            }
+        }
+
+        let free: Vec<u32> = (2..end).filter(|&c| self.is_free(c)).take(count).collect();
+        if free.len() == count { Some(free) } else { None }
+    }
+}
+
+/// Load the allocation bitmap named by the root directory's Allocation
+/// Bitmap entry.
+async fn load_bitmap(vol: &ExfatVol, inbox: &BlkInbox) -> Result<Bitmap, ExfatError> {
+    let root = read_dir(vol, inbox, &root_entry(vol)).await?;
+    // Allocation Bitmap:
+    //   +20..+24 FirstCluster (u32 LE)
+    //   +24..+32 DataLength   (u64 LE)
+    let (first, len) = root.bytes
+        .chunks(ENTRY_SIZE)
+        .take_while(|e| e[0] != ENTRY_END_OF_DIRECTORY)
+        .find(|e| e[0] == ENTRY_ALLOCATION_BITMAP)
+        .map(|e| (
+            u32::from_le_bytes(e[20..24].try_into().unwrap()),
+            u64::from_le_bytes(e[24..32].try_into().unwrap()),
+        ))
+        .ok_or(ExfatError::IoError)?;
+    if len < (vol.cluster_count as u64 + 7) / 8 {
+        return Err(ExfatError::IoError);
+    }
+    let clusters = chain_clusters(vol, inbox, first, false, Some(len)).await?;
+    Ok(Bitmap { data: read_chain(vol, inbox, clusters).await?, dirty: None })
+}
+
+// ---------------------------------------------------------------------------
+// FAT and cluster chains
+
+/// Set FAT entries, given as `(cluster, next)` pairs.  Consecutive pairs in
+/// the same FAT sector are written together.
+async fn write_fat(vol: &ExfatVol, inbox: &BlkInbox, links: &[(u32, u32)]) -> Result<(), ExfatError> {
+    let mut i = 0;
+    while i < links.len() {
+        let (lba, _) = vol.fat_entry_pos(links[i].0);
+        let mut sector = read_sector(inbox, lba).await?;
+        while i < links.len() && vol.fat_entry_pos(links[i].0).0 == lba {
+            let (_, off) = vol.fat_entry_pos(links[i].0);
+            sector[off..off + 4].copy_from_slice(&links[i].1.to_le_bytes());
+            i += 1;
+        }
+        write_sector(inbox, lba, sector).await?;
+    }
+    Ok(())
+}
+
+/// Extend a chain to `want` clusters.  New clusters continue the last one
+/// when the bitmap allows, so a file stays contiguous — and `NoFatChain` —
+/// for as long as possible.  When a `NoFatChain` chain has to fragment, its
+/// existing clusters are first recorded in the FAT.
+async fn grow_chain(
+    vol:          &ExfatVol,
+    inbox:        &BlkInbox,
+    bitmap:       &mut Bitmap,
+    clusters:     &mut Vec<u32>,
+    no_fat_chain: &mut bool,
+    want:         usize,
+) -> Result<(), ExfatError> {
+    let count = want.saturating_sub(clusters.len());
+    if count == 0 {
+        return Ok(());
+    }
+    let hint = clusters.last().map(|&c| c + 1);
+    let new = bitmap.find_free(vol, hint, count).ok_or(ExfatError::NoSpace)?;
+    let contiguous = new.windows(2).all(|w| w[1] == w[0] + 1)
+        && hint.map_or(true, |h| new[0] == h);
+
+    if clusters.is_empty() {
+        *no_fat_chain = contiguous;
+    } else if *no_fat_chain && !contiguous {
+        let links: Vec<(u32, u32)> = clusters.windows(2).map(|w| (w[0], w[1])).collect();
+        write_fat(vol, inbox, &links).await?;
+        *no_fat_chain = false;
+    }
+
+    if !*no_fat_chain {
+        let mut links = Vec::with_capacity(count + 1);
+        let mut prev = clusters.last().copied();
+        for &c in &new {
+            if let Some(p) = prev {
+                links.push((p, c));
+            }
+            prev = Some(c);
+        }
+        links.push((new[count - 1], FAT_END_OF_CHAIN));
+        write_fat(vol, inbox, &links).await?;
+    }
+
+    for &c in &new {
+        bitmap.set(c, true);
+    }
+    bitmap.flush(inbox).await?;
+    clusters.extend_from_slice(&new);
+    Ok(())
+}
+
+/// Free every cluster of a chain past the first `keep`.
+async fn shrink_chain(
+    vol:          &ExfatVol,
+    inbox:        &BlkInbox,
+    bitmap:       &mut Bitmap,
+    clusters:     &mut Vec<u32>,
+    no_fat_chain: bool,
+    keep:         usize,
+) -> Result<(), ExfatError> {
+    if keep >= clusters.len() {
+        return Ok(());
+    }
+    if !no_fat_chain && keep > 0 {
+        write_fat(vol, inbox, &[(clusters[keep - 1], FAT_END_OF_CHAIN)]).await?;
+    }
+    for &c in &clusters[keep..] {
+        bitmap.set(c, false);
+    }
+    bitmap.flush(inbox).await?;
+    clusters.truncate(keep);
+    Ok(())
+}
+
+/// Write `len` bytes at byte `offset` of the data in `clusters`: `data`, or
+/// zeros if `None`.  Partially covered sectors are read first.
+async fn write_range(
+    vol:      &ExfatVol,
+    inbox:    &BlkInbox,
+    clusters: &[u32],
+    offset:   u64,
+    len:      u64,
+    data:     Option<&[u8]>,
+) -> Result<(), ExfatError> {
+    let cluster_bytes = vol.cluster_bytes();
+    let end = offset + len;
+    let mut pos = offset;
+
+    while pos < end {
+        let cluster = clusters[(pos / cluster_bytes) as usize];
+        let lba = vol.cluster_lba(cluster) + (pos % cluster_bytes) / SECTOR_SIZE as u64;
+        let sector_off = (pos % SECTOR_SIZE as u64) as usize;
+        let n = (SECTOR_SIZE - sector_off).min((end - pos) as usize);
+
+        let mut sector = if n == SECTOR_SIZE {
+            alloc::vec![0u8; SECTOR_SIZE]
         } else {
-            let next = read_fat_entry(vol, inbox, cluster).await?;
-            cluster = next;
+            read_data_sector(inbox, lba).await?
+        };
+        match data {
+            Some(d) => {
+                let src = (pos - offset) as usize;
+                sector[sector_off..sector_off + n].copy_from_slice(&d[src..src + n]);
+            }
+            None => sector[sector_off..sector_off + n].fill(0),
         }
+        write_sector(inbox, lba, sector).await?;
+        pos += n as u64;
     }
+    Ok(())
+}
 
-    Ok(data)
+// ---------------------------------------------------------------------------
+// Entry sets
+
+async fn read_set(inbox: &BlkInbox, addrs: &[u64]) -> Result<Vec<u8>, ExfatError> {
+    let mut set = Vec::with_capacity(addrs.len() * ENTRY_SIZE);
+    let mut cached: Option<(u64, Vec<u8>)> = None;
+    for &addr in addrs {
+        let lba = addr / SECTOR_SIZE as u64;
+        let off = (addr % SECTOR_SIZE as u64) as usize;
+        if cached.as_ref().map(|(l, _)| *l) != Some(lba) {
+            cached = Some((lba, read_sector(inbox, lba).await?));
+        }
+        set.extend_from_slice(&cached.as_ref().unwrap().1[off..off + ENTRY_SIZE]);
+    }
+    Ok(set)
+}
+
+async fn write_set(inbox: &BlkInbox, addrs: &[u64], set: &[u8]) -> Result<(), ExfatError> {
+    let mut i = 0;
+    while i < addrs.len() {
+        let lba = addrs[i] / SECTOR_SIZE as u64;
+        let mut sector = read_sector(inbox, lba).await?;
+        while i < addrs.len() && addrs[i] / SECTOR_SIZE as u64 == lba {
+            let off = (addrs[i] % SECTOR_SIZE as u64) as usize;
+            sector[off..off + ENTRY_SIZE].copy_from_slice(&set[i * ENTRY_SIZE..(i + 1) * ENTRY_SIZE]);
+            i += 1;
+        }
+        write_sector(inbox, lba, sector).await?;
+    }
+    Ok(())
+}
+
+/// SetChecksum: a rotate-and-add over every byte of the set except the
+/// checksum field itself (bytes 2 and 3 of the File entry).
+fn set_checksum(set: &[u8]) -> u16 {
+    let mut sum: u16 = 0;
+    for (i, &b) in set.iter().enumerate() {
+        if i == 2 || i == 3 {
+            continue;
+        }
+        sum = sum.rotate_right(1).wrapping_add(b as u16);
+    }
+    sum
+}
+
+fn seal_set(set: &mut [u8]) {
+    let sum = set_checksum(set);
+    set[2..4].copy_from_slice(&sum.to_le_bytes());
+}
+
+/// Read-modify-write the entry set at `addrs`, recomputing SetChecksum.
+async fn update_set<F: FnOnce(&mut [u8])>(inbox: &BlkInbox, addrs: &[u64], f: F) -> Result<(), ExfatError> {
+    let mut set = read_set(inbox, addrs).await?;
+    f(&mut set);
+    seal_set(&mut set);
+    write_set(inbox, addrs, &set).await
+}
+
+/// Delete an entry set by clearing InUse in each entry's type.
+async fn mark_deleted(inbox: &BlkInbox, addrs: &[u64]) -> Result<(), ExfatError> {
+    let mut set = read_set(inbox, addrs).await?;
+    for entry in set.chunks_mut(ENTRY_SIZE) {
+        entry[0] &= !ENTRY_IN_USE;
+    }
+    write_set(inbox, addrs, &set).await
+}
+
+/// Record an entry's allocation and size, and update its modified time.
+/// ValidDataLength is set to the full size: callers zero any gap first.
+async fn store_alloc(
+    inbox:        &BlkInbox,
+    addrs:        &[u64],
+    first:        u32,
+    no_fat_chain: bool,
+    size:         u64,
+) -> Result<(), ExfatError> {
+    let (stamp, increment) = timestamp_now();
+    update_set(inbox, addrs, |set| {
+        // File: +12 LastModifiedTimestamp, +21 LastModified10msIncrement,
+        //       +23 LastModifiedUtcOffset
+        set[12..16].copy_from_slice(&stamp.to_le_bytes());
+        set[21] = increment;
+        set[23] = UTC_OFFSET_VALID;
+
+        let stream = &mut set[ENTRY_SIZE..2 * ENTRY_SIZE];
+        stream[1] &= !FLAG_NO_FAT_CHAIN;
+        stream[1] |= FLAG_ALLOCATION_POSSIBLE;
+        if no_fat_chain {
+            stream[1] |= FLAG_NO_FAT_CHAIN;
+        }
+        stream[8..16].copy_from_slice(&size.to_le_bytes());
+        stream[20..24].copy_from_slice(&first.to_le_bytes());
+        stream[24..32].copy_from_slice(&size.to_le_bytes());
+    }).await
+}
+
+/// An exFAT timestamp, 10 ms increment and UTC offset as nanoseconds
+/// since the Unix epoch; 0 for an invalid date.  A timestamp without a
+/// valid offset is taken to be UTC.
+fn decode_timestamp(stamp: u32, increment: u8, utc_offset: u8) -> u64 {
+    use libkernel::time::{rtc::RtcTime, NSEC_PER_SEC};
+
+    let (month, day) = (stamp >> 21 & 0xf, stamp >> 16 & 0x1f);
+    if !(1..=12).contains(&month) || day == 0 {
+        return 0;
+    }
+    let t = RtcTime {
+        year:   (stamp >> 25) + 1980,
+        month,
+        day,
+        hour:   stamp >> 11 & 0x1f,
+        minute: stamp >> 5 & 0x3f,
+        second: (stamp & 0x1f) * 2,
+    };
+    let mut secs = t.unix_seconds() as i64 + increment.min(199) as i64 / 100;
+    if utc_offset & UTC_OFFSET_VALID != 0 {
+        // A signed 7-bit count of 15-minute intervals east of UTC.
+        let quarters = ((utc_offset << 1) as i8 >> 1) as i64;
+        secs -= quarters * 15 * 60;
+    }
+    secs.max(0) as u64 * NSEC_PER_SEC + (increment.min(199) as u64 % 100) * 10_000_000
+}
+
+/// The current time as an exFAT timestamp and 10 ms increment, in UTC.
+fn timestamp_now() -> (u32, u8) {
+    use libkernel::time::{self, rtc::RtcTime, NSEC_PER_SEC};
+
+    let ns = time::realtime_ns();
+    let t = RtcTime::from_unix_seconds(ns / NSEC_PER_SEC);
+    // Timestamp: bits 25..32 year-1980, 21..25 month, 16..21 day,
+    //            11..16 hour, 5..11 minute, 0..5 second/2
+    let year = t.year.max(1980).min(2107) - 1980;
+    let stamp = year << 25 | t.month << 21 | t.day << 16
+        | t.hour << 11 | t.minute << 5 | t.second / 2;
+    let increment = (t.second % 2) * 100 + ((ns % NSEC_PER_SEC) / 10_000_000) as u32;
+    (stamp, increment as u8)
+}
+
+/// Encode a name for a File Name entry.  Only ASCII is accepted, matching
+/// what `utf16_to_string` reads back.
+fn encode_name(name: &str) -> Result<Vec<u16>, ExfatError> {
+    if name.is_empty() || name == "." || name == ".." || name.len() > MAX_NAME_LEN {
+        return Err(ExfatError::InvalidName);
+    }
+    if name.bytes().any(|b| !b.is_ascii() || b < 0x20 || b"\"*/:<>?\\|".contains(&b)) {
+        return Err(ExfatError::InvalidName);
+    }
+    Ok(name.bytes().map(|b| b as u16).collect())
+}
+
+/// NameHash: the SetChecksum rotate-and-add over the up-cased name's
+/// UTF-16LE bytes.
+fn name_hash(units: &[u16]) -> u16 {
+    let mut hash: u16 = 0;
+    for &u in units {
+        let up = if u < 0x80 { (u as u8).to_ascii_uppercase() as u16 } else { u };
+        for &b in up.to_le_bytes().iter() {
+            hash = hash.rotate_right(1).wrapping_add(b as u16);
+        }
+    }
+    hash
+}
+
+/// Build an unsealed entry set — File, Stream Extension with no
+/// allocation, and File Name entries — stamped with the current time.
+fn new_set(units: &[u16], attrs: u16) -> Vec<u8> {
+    let name_entries = (units.len() + NAME_CHARS_PER_ENTRY - 1) / NAME_CHARS_PER_ENTRY;
+    let mut set = alloc::vec![0u8; (2 + name_entries) * ENTRY_SIZE];
+    let (stamp, increment) = timestamp_now();
+
+    // File: +1 SecondaryCount, +4 FileAttributes, +8/+12/+16 create,
+    //       modified and accessed timestamps, +20/+21 10ms increments,
+    //       +22..+25 UTC offsets
+    set[0] = ENTRY_FILE;
+    set[1] = (1 + name_entries) as u8;
+    set[4..6].copy_from_slice(&attrs.to_le_bytes());
+    for &off in [8usize, 12, 16].iter() {
+        set[off..off + 4].copy_from_slice(&stamp.to_le_bytes());
+    }
+    set[20] = increment;
+    set[21] = increment;
+    set[22..25].copy_from_slice(&[UTC_OFFSET_VALID; 3]);
+
+    // Stream Extension: +3 NameLength, +4 NameHash
+    let stream = &mut set[ENTRY_SIZE..2 * ENTRY_SIZE];
+    stream[0] = ENTRY_STREAM_EXTENSION;
+    stream[1] = FLAG_ALLOCATION_POSSIBLE;
+    stream[3] = units.len() as u8;
+    stream[4..6].copy_from_slice(&name_hash(units).to_le_bytes());
+
+    for (i, chunk) in units.chunks(NAME_CHARS_PER_ENTRY).enumerate() {
+        let entry = &mut set[(2 + i) * ENTRY_SIZE..(3 + i) * ENTRY_SIZE];
+        entry[0] = ENTRY_FILE_NAME;
+        for (k, &u) in chunk.iter().enumerate() {
+            entry[2 + k * 2..4 + k * 2].copy_from_slice(&u.to_le_bytes());
+        }
+    }
+    set
+}
+
+/// Byte offset of the first run of `count` unused entries, if any.
+fn find_free_slot(data: &ChainData, count: usize) -> Option<usize> {
+    let mut run = 0;
+    for (i, entry) in data.bytes.chunks(ENTRY_SIZE).enumerate() {
+        if entry[0] & ENTRY_IN_USE != 0 {
+            run = 0;
+            continue;
+        }
+        run += 1;
+        if run == count {
+            return Some((i + 1 - count) * ENTRY_SIZE);
+        }
+    }
+    None
+}
+
+/// Add enough zeroed clusters to a directory for `count` more entries.
+async fn grow_dir(
+    vol:    &ExfatVol,
+    inbox:  &BlkInbox,
+    bitmap: &mut Bitmap,
+    parent: &mut ParentDir,
+    count:  usize,
+) -> Result<(), ExfatError> {
+    let old_len = parent.data.clusters.len();
+    let mut clusters = parent.data.clusters.clone();
+    let mut no_fat_chain = parent.dir.no_fat_chain;
+    let want = old_len + vol.clusters_for((count * ENTRY_SIZE) as u64);
+    grow_chain(vol, inbox, bitmap, &mut clusters, &mut no_fat_chain, want).await?;
+
+    let new = &clusters[old_len..];
+    write_range(vol, inbox, new, 0, new.len() as u64 * vol.cluster_bytes(), None).await?;
+    for &c in new {
+        parent.data.push_zero_cluster(vol, c);
+    }
+
+    // The root directory has no entry set; its length is the FAT chain.
+    if !parent.dir.is_root() {
+        let size = clusters.len() as u64 * vol.cluster_bytes();
+        store_alloc(inbox, &parent.dir.set_addrs, clusters[0], no_fat_chain, size).await?;
+        parent.dir.size = size;
+        parent.dir.no_fat_chain = no_fat_chain;
+    }
+    Ok(())
+}
+
+/// Write a sealed entry set into the first free slot of `parent`, growing
+/// the directory if it is full.
+async fn insert_set(
+    vol:    &ExfatVol,
+    inbox:  &BlkInbox,
+    bitmap: &mut Bitmap,
+    parent: &mut ParentDir,
+    set:    &[u8],
+) -> Result<(), ExfatError> {
+    let count = set.len() / ENTRY_SIZE;
+    let off = match find_free_slot(&parent.data, count) {
+        Some(off) => off,
+        None => {
+            grow_dir(vol, inbox, bitmap, parent, count).await?;
+            find_free_slot(&parent.data, count).ok_or(ExfatError::NoSpace)?
+        }
+    };
+    parent.data.bytes[off..off + set.len()].copy_from_slice(set);
+    parent.data.write_back(inbox, off, off + set.len()).await
+}
+
+/// Delete an entry set and free its clusters.
+async fn remove_entry(vol: &ExfatVol, inbox: &BlkInbox, entry: &DirEntry) -> Result<(), ExfatError> {
+    mark_deleted(inbox, &entry.set_addrs).await?;
+    let mut clusters = chain_clusters(vol, inbox, entry.first_cluster, entry.no_fat_chain, Some(entry.size)).await?;
+    if !clusters.is_empty() {
+        let mut bitmap = load_bitmap(vol, inbox).await?;
+        shrink_chain(vol, inbox, &mut bitmap, &mut clusters, entry.no_fat_chain, 0).await?;
+    }
+    Ok(())
+}
+
+async fn create_entry(vol: &ExfatVol, inbox: &BlkInbox, path: &str, is_dir: bool) -> Result<(), ExfatError> {
+    let (mut parent, name) = open_parent(vol, inbox, path).await?;
+    let units = encode_name(name)?;
+    if parent.find(name).is_some() {
+        return Err(ExfatError::AlreadyExists);
+    }
+    let mut bitmap = load_bitmap(vol, inbox).await?;
+
+    let mut set = new_set(&units, if is_dir { ATTR_DIRECTORY } else { ATTR_ARCHIVE });
+    let mut clusters = Vec::new();
+    if is_dir {
+        // A directory always owns at least one cluster; zeroed, it begins
+        // with an end-of-directory marker.
+        let mut no_fat_chain = false;
+        grow_chain(vol, inbox, &mut bitmap, &mut clusters, &mut no_fat_chain, 1).await?;
+        write_range(vol, inbox, &clusters, 0, vol.cluster_bytes(), None).await?;
+
+        let size = vol.cluster_bytes();
+        let stream = &mut set[ENTRY_SIZE..2 * ENTRY_SIZE];
+        if no_fat_chain {
+            stream[1] |= FLAG_NO_FAT_CHAIN;
+        }
+        stream[8..16].copy_from_slice(&size.to_le_bytes());
+        stream[20..24].copy_from_slice(&clusters[0].to_le_bytes());
+        stream[24..32].copy_from_slice(&size.to_le_bytes());
+    }
+    seal_set(&mut set);
+
+    let result = insert_set(vol, inbox, &mut bitmap, &mut parent, &set).await;
+    if result.is_err() && !clusters.is_empty() {
+        for &c in &clusters {
+            bitmap.set(c, false);
+        }
+        bitmap.flush(inbox).await?;
+    }
+    result
+}
+
+async fn write_entry(vol: &ExfatVol, inbox: &BlkInbox, path: &str, offset: u64, data: &[u8]) -> Result<usize, ExfatError> {
+    let file = walk_path(vol, inbox, path).await?;
+    if file.is_dir {
+        return Err(ExfatError::NotAFile);
+    }
+    if data.is_empty() {
+        return Ok(0);
+    }
+    let end = offset.checked_add(data.len() as u64).ok_or(ExfatError::FileTooLarge)?;
+    let new_size = file.size.max(end);
+
+    let mut clusters = chain_clusters(vol, inbox, file.first_cluster, file.no_fat_chain, Some(file.size)).await?;
+    let mut no_fat_chain = file.no_fat_chain && !clusters.is_empty();
+    let want = vol.clusters_for(new_size);
+    if want > clusters.len() {
+        let mut bitmap = load_bitmap(vol, inbox).await?;
+        grow_chain(vol, inbox, &mut bitmap, &mut clusters, &mut no_fat_chain, want).await?;
+    }
+
+    // Everything past ValidDataLength reads as zeros.  Make that true on
+    // disk before ValidDataLength moves to the end of the file.
+    if offset > file.valid_size {
+        write_range(vol, inbox, &clusters, file.valid_size, offset - file.valid_size, None).await?;
+    }
+    write_range(vol, inbox, &clusters, offset, data.len() as u64, Some(data)).await?;
+    let tail = end.max(file.valid_size);
+    if new_size > tail {
+        write_range(vol, inbox, &clusters, tail, new_size - tail, None).await?;
+    }
+
+    store_alloc(inbox, &file.set_addrs, clusters[0], no_fat_chain, new_size).await?;
+    Ok(data.len())
+}
+
+async fn truncate_entry(vol: &ExfatVol, inbox: &BlkInbox, path: &str, size: u64) -> Result<(), ExfatError> {
+    let file = walk_path(vol, inbox, path).await?;
+    if file.is_dir {
+        return Err(ExfatError::NotAFile);
+    }
+
+    let mut clusters = chain_clusters(vol, inbox, file.first_cluster, file.no_fat_chain, Some(file.size)).await?;
+    let mut no_fat_chain = file.no_fat_chain && !clusters.is_empty();
+    let want = vol.clusters_for(size);
+    let mut bitmap = load_bitmap(vol, inbox).await?;
+
+    if want > clusters.len() {
+        grow_chain(vol, inbox, &mut bitmap, &mut clusters, &mut no_fat_chain, want).await?;
+    }
+    if size > file.valid_size {
+        write_range(vol, inbox, &clusters, file.valid_size, size - file.valid_size, None).await?;
+    }
+
+    // Point the entry at the shorter chain before freeing the clusters
+    // past it.
+    let first = if want == 0 { 0 } else { clusters[0] };
+    store_alloc(inbox, &file.set_addrs, first, no_fat_chain && want > 0, size).await?;
+    shrink_chain(vol, inbox, &mut bitmap, &mut clusters, no_fat_chain, want).await
+}
+
+async fn unlink_entry(vol: &ExfatVol, inbox: &BlkInbox, path: &str) -> Result<(), ExfatError> {
+    let (parent, name) = open_parent(vol, inbox, path).await?;
+    let file = parent.find(name).ok_or(ExfatError::PathNotFound)?;
+    if file.is_dir {
+        return Err(ExfatError::NotAFile);
+    }
+    remove_entry(vol, inbox, file).await
+}
+
+async fn rmdir_entry(vol: &ExfatVol, inbox: &BlkInbox, path: &str) -> Result<(), ExfatError> {
+    let (parent, name) = open_parent(vol, inbox, path).await?;
+    let dir = parent.find(name).ok_or(ExfatError::PathNotFound)?;
+    if !dir.is_dir {
+        return Err(ExfatError::NotADirectory);
+    }
+    if !dir_is_empty(&read_dir(vol, inbox, dir).await?) {
+        return Err(ExfatError::NotEmpty);
+    }
+    remove_entry(vol, inbox, dir).await
+}
+
+async fn rename_entry(vol: &ExfatVol, inbox: &BlkInbox, from: &str, to: &str) -> Result<(), ExfatError> {
+    let (src_parent, src_name) = open_parent(vol, inbox, from).await?;
+    let src = src_parent.find(src_name).cloned().ok_or(ExfatError::PathNotFound)?;
+    let (mut dst_parent, dst_name) = open_parent(vol, inbox, to).await?;
+    let units = encode_name(dst_name)?;
+
+    if let Some(dst) = dst_parent.find(dst_name).cloned() {
+        if dst.set_addrs == src.set_addrs {
+            // Same entry; only a change of case needs a new entry set.
+            if dst.name == dst_name {
+                return Ok(());
+            }
+        } else {
+            if dst.is_dir && !src.is_dir {
+                return Err(ExfatError::NotAFile);
+            }
+            if !dst.is_dir && src.is_dir {
+                return Err(ExfatError::NotADirectory);
+            }
+            if dst.is_dir && !dir_is_empty(&read_dir(vol, inbox, &dst).await?) {
+                return Err(ExfatError::NotEmpty);
+            }
+            remove_entry(vol, inbox, &dst).await?;
+            dst_parent = open_parent(vol, inbox, to).await?.0;
+        }
+    }
+
+    // Keep attributes, timestamps and allocation; only the name changes.
+    let old = read_set(inbox, &src.set_addrs).await?;
+    let mut set = new_set(&units, 0);
+    set[4..ENTRY_SIZE].copy_from_slice(&old[4..ENTRY_SIZE]);
+    set[ENTRY_SIZE + 1] = old[ENTRY_SIZE + 1];
+    set[ENTRY_SIZE + 8..2 * ENTRY_SIZE].copy_from_slice(&old[ENTRY_SIZE + 8..2 * ENTRY_SIZE]);
+    seal_set(&mut set);
+
+    let mut bitmap = load_bitmap(vol, inbox).await?;
+    insert_set(vol, inbox, &mut bitmap, &mut dst_parent, &set).await?;
+    mark_deleted(inbox, &src.set_addrs).await
+}
+
+/// Create an empty file at `path`.
+pub async fn create(vol: &ExfatVol, inbox: &BlkInbox, path: &str) -> Result<(), ExfatError> {
+    let was_dirty = begin_update(vol, inbox).await?;
+    let result = create_entry(vol, inbox, path, false).await;
+    finish_update(vol, inbox, was_dirty, result).await
+}
+
+/// Create an empty directory at `path`.
+pub async fn mkdir(vol: &ExfatVol, inbox: &BlkInbox, path: &str) -> Result<(), ExfatError> {
+    let was_dirty = begin_update(vol, inbox).await?;
+    let result = create_entry(vol, inbox, path, true).await;
+    finish_update(vol, inbox, was_dirty, result).await
+}
+
+/// Write `data` at byte `offset` of the file at `path`, extending it (with
+/// zeros for any gap) if the write ends past its size.
+pub async fn write_at(
+    vol:    &ExfatVol,
+    inbox:  &BlkInbox,
+    path:   &str,
+    offset: u64,
+    data:   &[u8],
+) -> Result<usize, ExfatError> {
+    let was_dirty = begin_update(vol, inbox).await?;
+    let result = write_entry(vol, inbox, path, offset, data).await;
+    finish_update(vol, inbox, was_dirty, result).await
+}
+
+/// Set the size of the file at `path`, zero-filling when it grows.
+pub async fn truncate(vol: &ExfatVol, inbox: &BlkInbox, path: &str, size: u64) -> Result<(), ExfatError> {
+    let was_dirty = begin_update(vol, inbox).await?;
+    let result = truncate_entry(vol, inbox, path, size).await;
+    finish_update(vol, inbox, was_dirty, result).await
+}
+
+/// Delete the file at `path`.
+pub async fn unlink(vol: &ExfatVol, inbox: &BlkInbox, path: &str) -> Result<(), ExfatError> {
+    let was_dirty = begin_update(vol, inbox).await?;
+    let result = unlink_entry(vol, inbox, path).await;
+    finish_update(vol, inbox, was_dirty, result).await
+}
+
+/// Delete the empty directory at `path`.
+pub async fn rmdir(vol: &ExfatVol, inbox: &BlkInbox, path: &str) -> Result<(), ExfatError> {
+    let was_dirty = begin_update(vol, inbox).await?;
+    let result = rmdir_entry(vol, inbox, path).await;
+    finish_update(vol, inbox, was_dirty, result).await
+}
+
+/// Move `from` to `to`, replacing `to` if it exists and is compatible.
+pub async fn rename(vol: &ExfatVol, inbox: &BlkInbox, from: &str, to: &str) -> Result<(), ExfatError> {
+    let was_dirty = begin_update(vol, inbox).await?;
+    let result = rename_entry(vol, inbox, from, to).await;
+    finish_update(vol, inbox, was_dirty, result).await
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::super::ram_disk::RamDisk;
+    use alloc::vec;
+    use libkernel::{serial_print, serial_println};
+
+    // A bare volume of one-sector clusters: the boot sector, a one-sector
+    // FAT, then 64 clusters holding the allocation bitmap (cluster 2) and
+    // the root directory (cluster 3).
+    const FAT_LBA: u64 = 1;
+    const HEAP_LBA: u64 = 2;
+    const CLUSTERS: u32 = 64;
+    const BITMAP_CLUSTER: u32 = 2;
+    const ROOT_CLUSTER: u32 = 3;
+    const BITMAP_BYTES: u64 = (CLUSTERS as u64 + 7) / 8;
+
+    fn put32(disk: &RamDisk, pos: u64, v: u32) {
+        disk.put(pos, &v.to_le_bytes());
+    }
+
+    fn get32(disk: &RamDisk, pos: u64) -> u32 {
+        u32::from_le_bytes(disk.get(pos, 4)[..].try_into().unwrap())
+    }
+
+    fn fat_entry(disk: &RamDisk, cluster: u32) -> u32 {
+        get32(disk, FAT_LBA * SECTOR_SIZE as u64 + cluster as u64 * 4)
+    }
+
+    fn cluster_pos(cluster: u32) -> u64 {
+        (HEAP_LBA + cluster as u64 - 2) * SECTOR_SIZE as u64
+    }
+
+    /// The test volume, formatted, with an empty root directory.
+    fn image() -> (RamDisk, ExfatVol) {
+        let disk = RamDisk::new(HEAP_LBA + CLUSTERS as u64);
+        let mut boot = vec![0u8; SECTOR_SIZE];
+        boot[3..11].copy_from_slice(b"EXFAT   ");
+        boot[80..84].copy_from_slice(&(FAT_LBA as u32).to_le_bytes());
+        boot[88..92].copy_from_slice(&(HEAP_LBA as u32).to_le_bytes());
+        boot[92..96].copy_from_slice(&CLUSTERS.to_le_bytes());
+        boot[96..100].copy_from_slice(&ROOT_CLUSTER.to_le_bytes());
+        boot[110] = 1;
+        boot[510] = 0x55;
+        boot[511] = 0xAA;
+        disk.put(0, &boot);
+
+        put32(&disk, FAT_LBA * SECTOR_SIZE as u64, 0xFFFF_FFF8);
+        for cluster in 1..=ROOT_CLUSTER {
+            put32(&disk, FAT_LBA * SECTOR_SIZE as u64 + cluster as u64 * 4, FAT_END_OF_CHAIN);
+        }
+        disk.put(cluster_pos(BITMAP_CLUSTER), &[0b11]);
+
+        let mut entry = [0u8; ENTRY_SIZE];
+        entry[0] = ENTRY_ALLOCATION_BITMAP;
+        entry[20..24].copy_from_slice(&BITMAP_CLUSTER.to_le_bytes());
+        entry[24..32].copy_from_slice(&BITMAP_BYTES.to_le_bytes());
+        disk.put(cluster_pos(ROOT_CLUSTER), &entry);
+
+        let vol = disk.run(open_exfat(&disk.inbox)).unwrap();
+        (disk, vol)
+    }
+
+    fn bitmap_bytes(disk: &RamDisk) -> Vec<u8> {
+        disk.get(cluster_pos(BITMAP_CLUSTER), BITMAP_BYTES as usize)
+    }
+
+    /// The stored SetChecksum of `entry`'s set matches its content.
+    fn assert_sealed(disk: &RamDisk, entry: &DirEntry) {
+        let set = disk.run(read_set(&disk.inbox, &entry.set_addrs)).unwrap();
+        assert_eq!(u16::from_le_bytes([set[2], set[3]]), set_checksum(&set));
+    }
+
+    #[test_case]
+    fn test_exfat_set_checksum() {
+        serial_print!("test_exfat_set_checksum... ");
+        // Bytes 2 and 3 hold the checksum and are skipped.
+        assert_eq!(set_checksum(&[0x85, 2, 0xFF, 0xFF, 0x20]), 0x4042);
+        assert_eq!(set_checksum(&[0x85, 2, 0, 0, 0x20]), 0x4042);
+        // NameHash ignores ASCII case.
+        assert_eq!(name_hash(&[b'a' as u16, b'b' as u16, b'c' as u16]), 0xC82B);
+        assert_eq!(name_hash(&[b'A' as u16, b'B' as u16, b'C' as u16]), 0xC82B);
+
+        // Sixteen characters take two File Name entries.
+        let units = encode_name("sixteen-chars.md").unwrap();
+        let mut set = new_set(&units, ATTR_ARCHIVE);
+        assert_eq!((set.len(), set[1]), (4 * ENTRY_SIZE, 3));
+        seal_set(&mut set);
+        let sum = u16::from_le_bytes([set[2], set[3]]);
+        assert_eq!(sum, set_checksum(&set));
+        set[ENTRY_SIZE + 3 * ENTRY_SIZE - 1] ^= 1;
+        assert_ne!(sum, set_checksum(&set));
+
+        assert!(matches!(encode_name("a:b"), Err(ExfatError::InvalidName)));
+        assert!(matches!(encode_name("caf\u{e9}"), Err(ExfatError::InvalidName)));
+        assert!(matches!(encode_name(".."), Err(ExfatError::InvalidName)));
+        serial_println!("[ok]");
+    }
+
+    #[test_case]
+    fn test_exfat_cluster_allocation() {
+        serial_print!("test_exfat_cluster_allocation... ");
+        let (disk, vol) = image();
+        let inbox = &disk.inbox;
+        let mut bitmap = disk.run(load_bitmap(&vol, inbox)).unwrap();
+        assert!(!bitmap.is_free(BITMAP_CLUSTER) && !bitmap.is_free(ROOT_CLUSTER));
+        bitmap.set(6, true);
+
+        // A contiguous chain records no FAT links.
+        let mut clusters = Vec::new();
+        let mut no_fat_chain = false;
+        disk.run(grow_chain(&vol, inbox, &mut bitmap, &mut clusters, &mut no_fat_chain, 2)).unwrap();
+        assert_eq!(clusters, [4, 5]);
+        assert!(no_fat_chain);
+        assert_eq!((fat_entry(&disk, 4), fat_entry(&disk, 5)), (0, 0));
+        assert_eq!(bitmap_bytes(&disk)[0], 0b0001_1111);
+
+        // Cluster 6 is taken: the chain fragments and moves to the FAT,
+        // its existing clusters included.
+        disk.run(grow_chain(&vol, inbox, &mut bitmap, &mut clusters, &mut no_fat_chain, 3)).unwrap();
+        assert_eq!(clusters, [4, 5, 7]);
+        assert!(!no_fat_chain);
+        assert_eq!((fat_entry(&disk, 4), fat_entry(&disk, 5)), (5, 7));
+        assert_eq!(fat_entry(&disk, 7), FAT_END_OF_CHAIN);
+        assert_eq!(disk.run(chain_clusters(&vol, inbox, 4, false, None)).unwrap(), clusters);
+
+        disk.run(shrink_chain(&vol, inbox, &mut bitmap, &mut clusters, false, 1)).unwrap();
+        assert_eq!(clusters, [4]);
+        assert_eq!(fat_entry(&disk, 4), FAT_END_OF_CHAIN);
+        assert!(bitmap.is_free(5) && bitmap.is_free(7));
+        assert_eq!(bitmap_bytes(&disk)[0], 0b0001_0111);
+
+        let want = CLUSTERS as usize;
+        assert!(matches!(
+            disk.run(grow_chain(&vol, inbox, &mut bitmap, &mut clusters, &mut no_fat_chain, want)),
+            Err(ExfatError::NoSpace)
+        ));
+        assert_eq!(clusters, [4]);
+
+        // Without a free run, any free clusters do.
+        for c in (8..CLUSTERS + 2).step_by(2) {
+            bitmap.set(c, true);
+        }
+        assert_eq!(bitmap.find_free(&vol, Some(9), 1).unwrap(), [9]);
+        assert_eq!(bitmap.find_free(&vol, Some(8), 1).unwrap(), [5]);
+        assert_eq!(bitmap.find_free(&vol, None, 3).unwrap(), [5, 7, 9]);
+        serial_println!("[ok]");
+    }
+
+    #[test_case]
+    fn test_exfat_create_write_truncate() {
+        serial_print!("test_exfat_create_write_truncate... ");
+        let (disk, vol) = image();
+        let inbox = &disk.inbox;
+        let bitmap = bitmap_bytes(&disk);
+        let data: Vec<u8> = (0..1200).map(|i| i as u8).collect();
+
+        disk.run(create(&vol, inbox, "/Notes.txt")).unwrap();
+        assert!(matches!(disk.run(create(&vol, inbox, "/NOTES.TXT")), Err(ExfatError::AlreadyExists)));
+        let file = disk.run(stat(&vol, inbox, "/notes.txt")).unwrap();
+        assert_eq!((file.name.as_str(), file.size, file.first_cluster), ("Notes.txt", 0, 0));
+        // After the bitmap entry.
+        assert_eq!(file.set_addrs[0], cluster_pos(ROOT_CLUSTER) + ENTRY_SIZE as u64);
+        assert_sealed(&disk, &file);
+
+        // Three contiguous clusters.
+        assert_eq!(disk.run(write_at(&vol, inbox, "/Notes.txt", 0, &data)).unwrap(), data.len());
+        let file = disk.run(stat(&vol, inbox, "/Notes.txt")).unwrap();
+        assert_eq!((file.size, file.valid_size, file.first_cluster, file.no_fat_chain), (1200, 1200, 4, true));
+        assert_sealed(&disk, &file);
+        assert_eq!(disk.run(read_file(&vol, inbox, "/Notes.txt")).unwrap(), data);
+        assert_eq!(bitmap_bytes(&disk)[0], 0b0001_1111);
+
+        disk.run(truncate(&vol, inbox, "/Notes.txt", 100)).unwrap();
+        assert_eq!(disk.run(read_file(&vol, inbox, "/Notes.txt")).unwrap(), &data[..100]);
+        assert_eq!(bitmap_bytes(&disk)[0], 0b0000_0111);
+        // Growing zero-fills.
+        disk.run(truncate(&vol, inbox, "/Notes.txt", 700)).unwrap();
+        let read = disk.run(read_file(&vol, inbox, "/Notes.txt")).unwrap();
+        assert!(read[..100] == data[..100] && read[100..].iter().all(|&b| b == 0));
+        disk.run(truncate(&vol, inbox, "/Notes.txt", 0)).unwrap();
+        let file = disk.run(stat(&vol, inbox, "/Notes.txt")).unwrap();
+        assert_eq!((file.size, file.first_cluster), (0, 0));
+        assert_sealed(&disk, &file);
+
+        disk.run(unlink(&vol, inbox, "/Notes.txt")).unwrap();
+        assert!(matches!(disk.run(stat(&vol, inbox, "/Notes.txt")), Err(ExfatError::PathNotFound)));
+        assert_eq!(bitmap_bytes(&disk), bitmap);
+        // Every update cleared VolumeDirty again.
+        assert_eq!(disk.get(VOLUME_FLAGS_OFFSET as u64, 2), [0, 0]);
+        serial_println!("[ok]");
+    }
+
+    #[test_case]
+    fn test_exfat_directory_grows() {
+        serial_print!("test_exfat_directory_grows... ");
+        let (disk, vol) = image();
+        let inbox = &disk.inbox;
+        disk.run(mkdir(&vol, inbox, "/d")).unwrap();
+        let dir = disk.run(stat(&vol, inbox, "/d")).unwrap();
+        assert!(dir.is_dir && dir.no_fat_chain);
+        assert_eq!((dir.first_cluster, dir.size), (4, SECTOR_SIZE as u64));
+
+        // A cluster holds five three-entry sets; the sixth needs another.
+        for i in 0..6 {
+            disk.run(create(&vol, inbox, &alloc::format!("/d/f{}", i))).unwrap();
+        }
+        let dir = disk.run(stat(&vol, inbox, "/d")).unwrap();
+        assert_eq!((dir.size, dir.no_fat_chain), (2 * SECTOR_SIZE as u64, true));
+        assert_sealed(&disk, &dir);
+        let names: Vec<String> = disk.run(list_dir(&vol, inbox, "/d")).unwrap().into_iter().map(|e| e.name).collect();
+        assert_eq!(names, ["f0", "f1", "f2", "f3", "f4", "f5"]);
+        // The last set starts in the first cluster and ends in the new one.
+        let last = disk.run(stat(&vol, inbox, "/d/f5")).unwrap();
+        assert_eq!(last.set_addrs[0], cluster_pos(4) + 15 * ENTRY_SIZE as u64);
+        assert_eq!(last.set_addrs[1], cluster_pos(5));
+        assert_sealed(&disk, &last);
+
+        assert!(matches!(disk.run(rmdir(&vol, inbox, "/d")), Err(ExfatError::NotEmpty)));
+        disk.run(rename(&vol, inbox, "/d/f0", "/moved")).unwrap();
+        assert!(matches!(disk.run(stat(&vol, inbox, "/d/f0")), Err(ExfatError::PathNotFound)));
+        assert_sealed(&disk, &disk.run(stat(&vol, inbox, "/moved")).unwrap());
+        serial_println!("[ok]");
+    }
 }
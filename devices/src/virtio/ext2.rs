@@ -0,0 +1,1937 @@
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+
+use futures_util::future::{BoxFuture, FutureExt};
+
+use super::exfat::BlkInbox;
+use super::partition::{self, PartitionError, PartitionType};
+use super::sector::{
+    get16, get32, put16, put32, read_data_sector, read_sector, write_sector, BlkIoError,
+};
+
+// ---------------------------------------------------------------------------
+// Public error type
+
+#[derive(Debug)]
+pub enum Ext2Error {
+    NoDevice,
+    IoError,
+    NotExt2,
+    UnknownPartitionLayout,
+    /// The volume uses an incompatible feature this driver does not
+    /// implement (extents, journal recovery, 64-bit block numbers, ...).
+    Unsupported,
+    /// The volume has read-only-compatible features this driver does not
+    /// know, so it may be read but not written.
+    ReadOnly,
+    /// An on-disk structure is inconsistent.
+    Corrupt,
+    PathNotFound,
+    NotAFile,
+    NotADirectory,
+    FileTooLarge,
+    /// The name is already taken in the directory.
+    AlreadyExists,
+    /// The directory still has entries.
+    NotEmpty,
+    /// No free blocks or inodes.
+    NoSpace,
+    /// Empty, too long, `.`/`..`, or containing `/` or NUL.
+    InvalidName,
+}
+
+impl From<BlkIoError> for Ext2Error {
+    fn from(e: BlkIoError) -> Self {
+        match e {
+            BlkIoError::NoDevice => Ext2Error::NoDevice,
+            BlkIoError::IoError  => Ext2Error::IoError,
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Public types
+
+/// A directory entry returned by `list_dir`.
+#[derive(Clone)]
+pub struct DirEntry {
+    pub name: String,
+    /// File type and permission bits (`S_IFMT | 0o7777`).
+    pub mode: u16,
+    pub size: u64,
+}
+
+/// Inode metadata returned by `stat`.
+#[derive(Clone, Copy)]
+pub struct FileInfo {
+    pub ino:   u32,
+    /// File type and permission bits (`S_IFMT | 0o7777`).
+    pub mode:  u16,
+    pub nlink: u16,
+    pub uid:   u32,
+    pub gid:   u32,
+    pub size:  u64,
+    /// Allocated space in 512-byte units, indirect blocks included.
+    pub blocks: u64,
+    pub atime: u32,
+    pub mtime: u32,
+    pub ctime: u32,
+}
+
+impl FileInfo {
+    pub fn is_dir(&self) -> bool {
+        self.mode & S_IFMT == S_IFDIR
+    }
+}
+
+/// Parsed ext2 superblock.
+pub struct Ext2Vol {
+    /// Absolute LBA where the volume starts.
+    pub lba_base:         u64,
+    /// Block size in bytes: 1024, 2048 or 4096 (up to 64 KiB is accepted).
+    pub block_size:       u64,
+    pub blocks_count:     u32,
+    pub inodes_count:     u32,
+    /// Block holding the superblock: 1 with 1 KiB blocks, else 0.
+    pub first_data_block: u32,
+    pub blocks_per_group: u32,
+    pub inodes_per_group: u32,
+    /// Bytes per on-disk inode; only the first 128 are interpreted.
+    pub inode_size:       u64,
+    /// First inode number not reserved for the filesystem.
+    pub first_ino:        u32,
+    pub group_count:      u32,
+    /// Directory entries carry a file type byte (INCOMPAT_FILETYPE).
+    filetype:             bool,
+    /// File sizes may exceed 2 GiB (RO_COMPAT_LARGE_FILE).
+    large_file:           bool,
+    /// Unknown RO_COMPAT features: reads only.
+    read_only:            bool,
+}
+
+impl Ext2Vol {
+    fn sectors_per_block(&self) -> u64 {
+        self.block_size / SECTOR_SIZE
+    }
+
+    fn block_lba(&self, block: u32) -> u64 {
+        self.lba_base + block as u64 * self.sectors_per_block()
+    }
+
+    /// Block numbers per indirect block.
+    fn ptrs_per_block(&self) -> u64 {
+        self.block_size / 4
+    }
+
+    /// Blocks a file can address through direct, indirect, double and
+    /// triple indirect pointers.
+    fn max_file_blocks(&self) -> u64 {
+        let p = self.ptrs_per_block();
+        DIRECT_BLOCKS + p + p * p + p * p * p
+    }
+
+    /// Largest file size this volume can record.
+    fn max_file_size(&self) -> u64 {
+        let addressable = self.max_file_blocks() * self.block_size;
+        if self.large_file { addressable } else { addressable.min(i32::MAX as u64) }
+    }
+
+    fn inode_group(&self, ino: u32) -> u32 {
+        (ino - 1) / self.inodes_per_group
+    }
+
+    /// Blocks in `group`; the last group may be short.
+    fn blocks_in_group(&self, group: u32) -> u32 {
+        let start = self.first_data_block + group * self.blocks_per_group;
+        self.blocks_per_group.min(self.blocks_count - start)
+    }
+
+    /// LBA and byte offset of `group`'s descriptor.  The table starts in
+    /// the block after the superblock.
+    fn group_desc_pos(&self, group: u32) -> (u64, usize) {
+        let byte = (self.first_data_block as u64 + 1) * self.block_size + group as u64 * GROUP_DESC_SIZE;
+        (self.lba_base + byte / SECTOR_SIZE, (byte % SECTOR_SIZE) as usize)
+    }
+}
+
+// ---------------------------------------------------------------------------
+// On-disk constants
+
+const SECTOR_SIZE: u64 = 512;
+
+/// The superblock is 1024 bytes into the volume.  Every field used here is
+/// in its first sector.
+const SUPERBLOCK_LBA: u64 = 2;
+const EXT2_MAGIC: u16 = 0xEF53;
+
+// Superblock field offsets.
+const SB_INODES_COUNT:      usize = 0;
+const SB_BLOCKS_COUNT:      usize = 4;
+const SB_FREE_BLOCKS:       usize = 12;
+const SB_FREE_INODES:       usize = 16;
+const SB_FIRST_DATA_BLOCK:  usize = 20;
+const SB_LOG_BLOCK_SIZE:    usize = 24;
+const SB_BLOCKS_PER_GROUP:  usize = 32;
+const SB_INODES_PER_GROUP:  usize = 40;
+const SB_WTIME:             usize = 48;
+const SB_MAGIC:             usize = 56;
+const SB_STATE:             usize = 58;
+const SB_REV_LEVEL:         usize = 76;
+const SB_FIRST_INO:         usize = 84;
+const SB_INODE_SIZE:        usize = 88;
+const SB_FEATURE_INCOMPAT:  usize = 96;
+const SB_FEATURE_RO_COMPAT: usize = 100;
+
+/// `s_state`: cleared while an update is in progress.
+const STATE_VALID: u16 = 1;
+
+const INCOMPAT_FILETYPE: u32 = 0x0002;
+/// Only moves metadata around; the group descriptors still say where.
+const INCOMPAT_FLEX_BG:  u32 = 0x0200;
+const INCOMPAT_SUPPORTED: u32 = INCOMPAT_FILETYPE | INCOMPAT_FLEX_BG;
+
+const RO_COMPAT_SPARSE_SUPER: u32 = 0x0001;
+const RO_COMPAT_LARGE_FILE:   u32 = 0x0002;
+const RO_COMPAT_SUPPORTED: u32 = RO_COMPAT_SPARSE_SUPER | RO_COMPAT_LARGE_FILE;
+
+// Revision 0 ("good old") values.
+const GOOD_OLD_FIRST_INO:  u32 = 11;
+const GOOD_OLD_INODE_SIZE: u64 = 128;
+
+// Group descriptor: 32 bytes.
+const GROUP_DESC_SIZE: u64 = 32;
+const GD_BLOCK_BITMAP: usize = 0;
+const GD_INODE_BITMAP: usize = 4;
+const GD_INODE_TABLE:  usize = 8;
+const GD_FREE_BLOCKS:  usize = 12;
+const GD_FREE_INODES:  usize = 14;
+const GD_USED_DIRS:    usize = 16;
+
+const ROOT_INO: u32 = 2;
+
+// Inode field offsets.
+const INODE_SIZE:     usize = 128;
+const I_MODE:         usize = 0;
+const I_UID:          usize = 2;
+const I_SIZE:         usize = 4;
+const I_ATIME:        usize = 8;
+const I_CTIME:        usize = 12;
+const I_MTIME:        usize = 16;
+const I_DTIME:        usize = 20;
+const I_GID:          usize = 24;
+const I_LINKS_COUNT:  usize = 26;
+const I_BLOCKS:       usize = 28;
+const I_FLAGS:        usize = 32;
+const I_BLOCK:        usize = 40;
+const I_FILE_ACL:     usize = 104;
+const I_SIZE_HIGH:    usize = 108;
+const I_UID_HIGH:     usize = 120;
+const I_GID_HIGH:     usize = 122;
+
+/// `i_flags`: the directory has an htree index.  This driver updates
+/// directories linearly, so it clears the flag on any change.
+const INDEX_FL: u32 = 0x1000;
+
+/// Pointers held in the inode itself; 12, 13 and 14 are the single,
+/// double and triple indirect blocks.
+const DIRECT_BLOCKS: u64 = 12;
+const N_BLOCKS:      usize = 15;
+
+/// Most links one inode may have (`EXT2_LINK_MAX`).
+const LINK_MAX: u16 = 32000;
+
+pub const S_IFMT:  u16 = 0o170000;
+pub const S_IFREG: u16 = 0o100000;
+pub const S_IFDIR: u16 = 0o040000;
+pub const S_IFLNK: u16 = 0o120000;
+
+// Directory entry file types.
+const FT_REG_FILE: u8 = 1;
+const FT_DIR:      u8 = 2;
+
+/// Directory entry header: inode (4), rec_len (2), name_len (1),
+/// file_type (1).
+const DIRENT_HEADER: usize = 8;
+const MAX_NAME_LEN:  usize = 255;
+
+/// Seconds since the epoch, for inode timestamps.
+fn now() -> u32 {
+    (libkernel::time::realtime_ns() / 1_000_000_000) as u32
+}
+
+// ---------------------------------------------------------------------------
+// Internal: block I/O
+
+/// Read a metadata block (directory, bitmap, indirect block).
+async fn read_block(vol: &Ext2Vol, inbox: &BlkInbox, block: u32) -> Result<Vec<u8>, Ext2Error> {
+    let lba = vol.block_lba(block);
+    let mut data = Vec::with_capacity(vol.block_size as usize);
+    for i in 0..vol.sectors_per_block() {
+        data.extend_from_slice(&read_sector(inbox, lba + i).await?);
+    }
+    Ok(data)
+}
+
+async fn write_block(vol: &Ext2Vol, inbox: &BlkInbox, block: u32, data: &[u8]) -> Result<(), Ext2Error> {
+    let lba = vol.block_lba(block);
+    for (i, sector) in data.chunks(SECTOR_SIZE as usize).enumerate() {
+        write_sector(inbox, lba + i as u64, sector.to_vec()).await?;
+    }
+    Ok(())
+}
+
+// ---------------------------------------------------------------------------
+// Volume detection
+
+fn is_ext2(sb: &[u8]) -> bool {
+    get16(sb, SB_MAGIC) == EXT2_MAGIC
+}
+
+/// Open the ext2 volume on the given block device.
+///
+/// Detects a bare volume and volumes in MBR (type 0x83) or GPT (Linux
+/// filesystem data or Basic Data) partitions.
+pub async fn open_ext2(inbox: &BlkInbox) -> Result<Ext2Vol, Ext2Error> {
+    // Bare ext2 — the superblock is 1024 bytes into the disk.
+    let sb = read_sector(inbox, SUPERBLOCK_LBA).await?;
+    if is_ext2(&sb) {
+        return parse_superblock(&sb, 0);
+    }
+
+    let parts = partition::partitions(inbox).await.map_err(|e| match e {
+        PartitionError::NoDevice      => Ext2Error::NoDevice,
+        PartitionError::IoError       => Ext2Error::IoError,
+        PartitionError::UnknownLayout => Ext2Error::UnknownPartitionLayout,
+    })?;
+
+    for part in parts {
+        let candidate = match part.ptype {
+            PartitionType::Mbr(t) => t == partition::MBR_LINUX,
+            PartitionType::Gpt(guid) => {
+                guid == partition::GPT_LINUX_DATA || guid == partition::GPT_BASIC_DATA
+            }
+        };
+        if !candidate { continue; }
+        let sb = read_sector(inbox, part.start_lba + SUPERBLOCK_LBA).await?;
+        if is_ext2(&sb) {
+            return parse_superblock(&sb, part.start_lba);
+        }
+    }
+
+    Err(Ext2Error::NotExt2)
+}
+
+fn parse_superblock(sb: &[u8], lba_base: u64) -> Result<Ext2Vol, Ext2Error> {
+    if !is_ext2(sb) {
+        return Err(Ext2Error::NotExt2);
+    }
+    let rev = get32(sb, SB_REV_LEVEL);
+    let (first_ino, inode_size, incompat, ro_compat) = if rev == 0 {
+        (GOOD_OLD_FIRST_INO, GOOD_OLD_INODE_SIZE, 0, 0)
+    } else {
+        (
+            get32(sb, SB_FIRST_INO),
+            get16(sb, SB_INODE_SIZE) as u64,
+            get32(sb, SB_FEATURE_INCOMPAT),
+            get32(sb, SB_FEATURE_RO_COMPAT),
+        )
+    };
+    if incompat & !INCOMPAT_SUPPORTED != 0 {
+        return Err(Ext2Error::Unsupported);
+    }
+
+    let log_block_size = get32(sb, SB_LOG_BLOCK_SIZE);
+    let blocks_count = get32(sb, SB_BLOCKS_COUNT);
+    let first_data_block = get32(sb, SB_FIRST_DATA_BLOCK);
+    let blocks_per_group = get32(sb, SB_BLOCKS_PER_GROUP);
+    let inodes_per_group = get32(sb, SB_INODES_PER_GROUP);
+    if log_block_size > 6
+        || blocks_per_group == 0
+        || inodes_per_group == 0
+        || first_data_block >= blocks_count
+        || inode_size < INODE_SIZE as u64
+        || !inode_size.is_power_of_two()
+    {
+        return Err(Ext2Error::Corrupt);
+    }
+    let block_size = 1024u64 << log_block_size;
+    // Each group's bitmaps are a single block.
+    let bits_per_block = block_size * 8;
+    if inode_size > block_size
+        || blocks_per_group as u64 > bits_per_block
+        || inodes_per_group as u64 > bits_per_block
+    {
+        return Err(Ext2Error::Corrupt);
+    }
+    let data_blocks = (blocks_count - first_data_block) as u64;
+    let group_count = (data_blocks + blocks_per_group as u64 - 1) / blocks_per_group as u64;
+
+    Ok(Ext2Vol {
+        lba_base,
+        block_size,
+        blocks_count,
+        inodes_count: get32(sb, SB_INODES_COUNT),
+        first_data_block,
+        blocks_per_group,
+        inodes_per_group,
+        inode_size,
+        first_ino,
+        group_count: group_count as u32,
+        filetype:    incompat & INCOMPAT_FILETYPE != 0,
+        large_file:  ro_compat & RO_COMPAT_LARGE_FILE != 0,
+        read_only:   ro_compat & !RO_COMPAT_SUPPORTED != 0,
+    })
+}
+
+// ---------------------------------------------------------------------------
+// Group descriptors
+
+struct GroupDesc {
+    block_bitmap: u32,
+    inode_bitmap: u32,
+    inode_table:  u32,
+    free_blocks:  u16,
+    free_inodes:  u16,
+}
+
+async fn read_group(vol: &Ext2Vol, inbox: &BlkInbox, group: u32) -> Result<GroupDesc, Ext2Error> {
+    let (lba, off) = vol.group_desc_pos(group);
+    let sector = read_sector(inbox, lba).await?;
+    Ok(GroupDesc {
+        block_bitmap: get32(&sector, off + GD_BLOCK_BITMAP),
+        inode_bitmap: get32(&sector, off + GD_INODE_BITMAP),
+        inode_table:  get32(&sector, off + GD_INODE_TABLE),
+        free_blocks:  get16(&sector, off + GD_FREE_BLOCKS),
+        free_inodes:  get16(&sector, off + GD_FREE_INODES),
+    })
+}
+
+// ---------------------------------------------------------------------------
+// Inodes
+
+/// The first 128 bytes of an on-disk inode, the part all revisions share.
+#[derive(Clone)]
+struct Inode {
+    ino: u32,
+    raw: [u8; INODE_SIZE],
+}
+
+impl Inode {
+    /// A fresh inode with one link (two for a directory, counting `.`).
+    fn new(ino: u32, mode: u16) -> Self {
+        let mut inode = Inode { ino, raw: [0; INODE_SIZE] };
+        put16(&mut inode.raw, I_MODE, mode);
+        inode.set_links(if mode & S_IFMT == S_IFDIR { 2 } else { 1 });
+        let t = now();
+        for off in [I_ATIME, I_CTIME, I_MTIME] {
+            put32(&mut inode.raw, off, t);
+        }
+        inode
+    }
+
+    fn mode(&self) -> u16 { get16(&self.raw, I_MODE) }
+    fn is_dir(&self) -> bool { self.mode() & S_IFMT == S_IFDIR }
+    fn is_reg(&self) -> bool { self.mode() & S_IFMT == S_IFREG }
+    fn is_symlink(&self) -> bool { self.mode() & S_IFMT == S_IFLNK }
+
+    /// The high half of the size is only recorded for regular files; in
+    /// directories the field is `i_dir_acl`.
+    fn size(&self) -> u64 {
+        let lo = get32(&self.raw, I_SIZE) as u64;
+        if self.is_reg() { lo | (get32(&self.raw, I_SIZE_HIGH) as u64) << 32 } else { lo }
+    }
+
+    fn set_size(&mut self, size: u64) {
+        put32(&mut self.raw, I_SIZE, size as u32);
+        if self.is_reg() {
+            put32(&mut self.raw, I_SIZE_HIGH, (size >> 32) as u32);
+        }
+    }
+
+    fn links(&self) -> u16 { get16(&self.raw, I_LINKS_COUNT) }
+    fn set_links(&mut self, n: u16) { put16(&mut self.raw, I_LINKS_COUNT, n) }
+
+    fn blocks(&self) -> u32 { get32(&self.raw, I_BLOCKS) }
+
+    /// Account for `n` blocks allocated (positive) or freed (negative).
+    fn add_blocks(&mut self, vol: &Ext2Vol, n: i64) {
+        let sectors = (self.blocks() as i64 + n * vol.sectors_per_block() as i64).max(0);
+        put32(&mut self.raw, I_BLOCKS, sectors as u32);
+    }
+
+    fn block(&self, i: usize) -> u32 { get32(&self.raw, I_BLOCK + i * 4) }
+    fn set_block(&mut self, i: usize, b: u32) { put32(&mut self.raw, I_BLOCK + i * 4, b) }
+
+    fn file_acl(&self) -> u32 { get32(&self.raw, I_FILE_ACL) }
+
+    /// A symlink whose target is stored in `i_block` itself: it owns no
+    /// blocks other than an extended-attribute block.
+    fn is_fast_symlink(&self, vol: &Ext2Vol) -> bool {
+        let acl = if self.file_acl() != 0 { vol.sectors_per_block() as u32 } else { 0 };
+        self.is_symlink() && self.blocks() == acl
+    }
+
+    /// Record a modification now (mtime and ctime).
+    fn touch(&mut self) {
+        let t = now();
+        put32(&mut self.raw, I_MTIME, t);
+        put32(&mut self.raw, I_CTIME, t);
+    }
+
+    /// Record a metadata change now (ctime).
+    fn touch_ctime(&mut self) {
+        put32(&mut self.raw, I_CTIME, now());
+    }
+
+    fn info(&self) -> FileInfo {
+        let uid = get16(&self.raw, I_UID) as u32 | (get16(&self.raw, I_UID_HIGH) as u32) << 16;
+        let gid = get16(&self.raw, I_GID) as u32 | (get16(&self.raw, I_GID_HIGH) as u32) << 16;
+        FileInfo {
+            ino:    self.ino,
+            mode:   self.mode(),
+            nlink:  self.links(),
+            uid,
+            gid,
+            size:   self.size(),
+            blocks: self.blocks() as u64,
+            atime:  get32(&self.raw, I_ATIME),
+            mtime:  get32(&self.raw, I_MTIME),
+            ctime:  get32(&self.raw, I_CTIME),
+        }
+    }
+}
+
+/// LBA and byte offset of inode `ino` in its group's inode table.  An
+/// inode never straddles a sector: its size is a power of two of at least
+/// 128 bytes.
+async fn inode_pos(vol: &Ext2Vol, inbox: &BlkInbox, ino: u32) -> Result<(u64, usize), Ext2Error> {
+    if ino == 0 || ino > vol.inodes_count {
+        return Err(Ext2Error::Corrupt);
+    }
+    let gd = read_group(vol, inbox, vol.inode_group(ino)).await?;
+    let index = ((ino - 1) % vol.inodes_per_group) as u64;
+    let byte = gd.inode_table as u64 * vol.block_size + index * vol.inode_size;
+    Ok((vol.lba_base + byte / SECTOR_SIZE, (byte % SECTOR_SIZE) as usize))
+}
+
+async fn read_inode(vol: &Ext2Vol, inbox: &BlkInbox, ino: u32) -> Result<Inode, Ext2Error> {
+    let (lba, off) = inode_pos(vol, inbox, ino).await?;
+    let sector = read_sector(inbox, lba).await?;
+    let mut raw = [0u8; INODE_SIZE];
+    raw.copy_from_slice(&sector[off..off + INODE_SIZE]);
+    Ok(Inode { ino, raw })
+}
+
+async fn write_inode(vol: &Ext2Vol, inbox: &BlkInbox, inode: &Inode) -> Result<(), Ext2Error> {
+    let (lba, off) = inode_pos(vol, inbox, inode.ino).await?;
+    let mut sector = read_sector(inbox, lba).await?;
+    sector[off..off + INODE_SIZE].copy_from_slice(&inode.raw);
+    Ok(write_sector(inbox, lba, sector).await?)
+}
+
+/// Write a newly allocated inode, zeroing the rest of its on-disk slot
+/// (large inodes), as Linux does.
+async fn write_new_inode(vol: &Ext2Vol, inbox: &BlkInbox, inode: &Inode) -> Result<(), Ext2Error> {
+    let (lba, off) = inode_pos(vol, inbox, inode.ino).await?;
+    let mut sector = read_sector(inbox, lba).await?;
+    let slot = (vol.inode_size as usize).min(SECTOR_SIZE as usize - off);
+    sector[off..off + slot].fill(0);
+    sector[off..off + INODE_SIZE].copy_from_slice(&inode.raw);
+    Ok(write_sector(inbox, lba, sector).await?)
+}
+
+// ---------------------------------------------------------------------------
+// Block mapping
+
+/// Where file block `n` is recorded: the `i_block` slot, then the index
+/// in each indirect block on the way down to the data block.
+fn block_path(vol: &Ext2Vol, n: u64) -> Result<Vec<usize>, Ext2Error> {
+    let p = vol.ptrs_per_block();
+    if n < DIRECT_BLOCKS {
+        return Ok(vec![n as usize]);
+    }
+    let n = n - DIRECT_BLOCKS;
+    if n < p {
+        return Ok(vec![12, n as usize]);
+    }
+    let n = n - p;
+    if n < p * p {
+        return Ok(vec![13, (n / p) as usize, (n % p) as usize]);
+    }
+    let n = n - p * p;
+    if n < p * p * p {
+        return Ok(vec![14, (n / (p * p)) as usize, (n / p % p) as usize, (n % p) as usize]);
+    }
+    Err(Ext2Error::FileTooLarge)
+}
+
+/// Entry `index` of indirect block `block`.
+async fn read_ptr(vol: &Ext2Vol, inbox: &BlkInbox, block: u32, index: usize) -> Result<u32, Ext2Error> {
+    let byte = index as u64 * 4;
+    let sector = read_sector(inbox, vol.block_lba(block) + byte / SECTOR_SIZE).await?;
+    Ok(get32(&sector, (byte % SECTOR_SIZE) as usize))
+}
+
+async fn write_ptr(vol: &Ext2Vol, inbox: &BlkInbox, block: u32, index: usize, value: u32) -> Result<(), Ext2Error> {
+    let byte = index as u64 * 4;
+    let lba = vol.block_lba(block) + byte / SECTOR_SIZE;
+    let mut sector = read_sector(inbox, lba).await?;
+    put32(&mut sector, (byte % SECTOR_SIZE) as usize, value);
+    Ok(write_sector(inbox, lba, sector).await?)
+}
+
+/// The block holding file block `n`, or 0 for a hole.
+async fn bmap(vol: &Ext2Vol, inbox: &BlkInbox, inode: &Inode, n: u64) -> Result<u32, Ext2Error> {
+    let path = block_path(vol, n)?;
+    let mut ptr = inode.block(path[0]);
+    for &index in &path[1..] {
+        if ptr == 0 {
+            return Ok(0);
+        }
+        ptr = read_ptr(vol, inbox, ptr, index).await?;
+    }
+    Ok(ptr)
+}
+
+/// The block holding file block `n`, allocating it and any indirect blocks
+/// on the way.  Returns the block and whether it was just allocated (its
+/// contents are then undefined).  The caller writes the inode.
+async fn bmap_alloc(
+    vol:   &Ext2Vol,
+    inbox: &BlkInbox,
+    alloc: &mut Alloc,
+    inode: &mut Inode,
+    n:     u64,
+) -> Result<(u32, bool), Ext2Error> {
+    let path = block_path(vol, n)?;
+    let goal = vol.inode_group(inode.ino);
+    let depth = path.len() - 1;
+
+    let mut ptr = inode.block(path[0]);
+    let mut fresh = false;
+    if ptr == 0 {
+        ptr = alloc.alloc_block(vol, inbox, goal).await?;
+        inode.add_blocks(vol, 1);
+        if depth > 0 {
+            write_block(vol, inbox, ptr, &vec![0; vol.block_size as usize]).await?;
+        }
+        inode.set_block(path[0], ptr);
+        fresh = true;
+    }
+    for (level, &index) in path[1..].iter().enumerate() {
+        let parent = ptr;
+        ptr = read_ptr(vol, inbox, parent, index).await?;
+        fresh = ptr == 0;
+        if fresh {
+            ptr = alloc.alloc_block(vol, inbox, goal).await?;
+            inode.add_blocks(vol, 1);
+            if level + 1 < depth {
+                write_block(vol, inbox, ptr, &vec![0; vol.block_size as usize]).await?;
+            }
+            write_ptr(vol, inbox, parent, index, ptr).await?;
+        }
+    }
+    Ok((ptr, fresh))
+}
+
+/// Free the blocks under indirect block `block` (`depth` levels above the
+/// data blocks, covering file blocks from `base`) that hold file block
+/// `keep` or later.  Returns true if nothing is left under it, so the
+/// caller frees `block` itself.  `freed` counts freed blocks.
+fn truncate_tree<'a>(
+    vol:   &'a Ext2Vol,
+    inbox: &'a BlkInbox,
+    alloc: &'a mut Alloc,
+    freed: &'a mut i64,
+    block: u32,
+    depth: u32,
+    base:  u64,
+    keep:  u64,
+) -> BoxFuture<'a, Result<bool, Ext2Error>> {
+    async move {
+        let span = vol.ptrs_per_block().pow(depth - 1);
+        let mut ptrs = read_block(vol, inbox, block).await?;
+        let mut changed = false;
+        let mut empty = true;
+        for i in 0..vol.ptrs_per_block() as usize {
+            let child = get32(&ptrs, i * 4);
+            if child == 0 {
+                continue;
+            }
+            let child_base = base + i as u64 * span;
+            if child_base + span <= keep {
+                empty = false;
+                continue;
+            }
+            let gone = depth == 1
+                || truncate_tree(vol, inbox, &mut *alloc, &mut *freed, child, depth - 1, child_base, keep).await?;
+            if gone {
+                alloc.free_block(vol, inbox, child).await?;
+                *freed += 1;
+                put32(&mut ptrs, i * 4, 0);
+                changed = true;
+            } else {
+                empty = false;
+            }
+        }
+        if changed && !empty {
+            write_block(vol, inbox, block, &ptrs).await?;
+        }
+        Ok(empty)
+    }.boxed()
+}
+
+/// Free every block of `inode` holding file block `keep` or later.
+async fn free_blocks_from(
+    vol:   &Ext2Vol,
+    inbox: &BlkInbox,
+    alloc: &mut Alloc,
+    inode: &mut Inode,
+    keep:  u64,
+) -> Result<(), Ext2Error> {
+    let p = vol.ptrs_per_block();
+    let mut freed = 0i64;
+    for slot in 0..N_BLOCKS {
+        let block = inode.block(slot);
+        if block == 0 {
+            continue;
+        }
+        let gone = match slot {
+            s if (s as u64) < DIRECT_BLOCKS => s as u64 >= keep,
+            12 => truncate_tree(vol, inbox, alloc, &mut freed, block, 1, DIRECT_BLOCKS, keep).await?,
+            13 => truncate_tree(vol, inbox, alloc, &mut freed, block, 2, DIRECT_BLOCKS + p, keep).await?,
+            _  => truncate_tree(vol, inbox, alloc, &mut freed, block, 3, DIRECT_BLOCKS + p + p * p, keep).await?,
+        };
+        if gone {
+            alloc.free_block(vol, inbox, block).await?;
+            freed += 1;
+            inode.set_block(slot, 0);
+        }
+    }
+    inode.add_blocks(vol, -freed);
+    Ok(())
+}
+
+// ---------------------------------------------------------------------------
+// Allocation
+
+struct Bitmap {
+    block: u32,
+    data:  Vec<u8>,
+    dirty: bool,
+}
+
+/// First clear bit in `data[..n]` at or after `start`.
+fn find_clear(data: &[u8], start: u32, n: u32) -> Option<u32> {
+    let n = n.min((data.len() * 8) as u32);
+    (start..n).find(|&i| data[(i / 8) as usize] & (1 << (i % 8)) == 0)
+}
+
+/// Bitmap and free-count changes made by one update.  Bitmaps are loaded
+/// once and written back, with the group descriptor and superblock
+/// counts, by [`Alloc::flush`].
+#[derive(Default)]
+struct Alloc {
+    /// Keyed by (inode bitmap?, group).
+    bitmaps: BTreeMap<(bool, u32), Bitmap>,
+    /// Per group: change in free blocks, free inodes, and directories.
+    deltas:  BTreeMap<u32, [i32; 3]>,
+}
+
+impl Alloc {
+    async fn load(&mut self, vol: &Ext2Vol, inbox: &BlkInbox, inodes: bool, group: u32) -> Result<(), Ext2Error> {
+        if !self.bitmaps.contains_key(&(inodes, group)) {
+            let gd = read_group(vol, inbox, group).await?;
+            let block = if inodes { gd.inode_bitmap } else { gd.block_bitmap };
+            let data = read_block(vol, inbox, block).await?;
+            self.bitmaps.insert((inodes, group), Bitmap { block, data, dirty: false });
+        }
+        Ok(())
+    }
+
+    /// Mark bit `bit` of a loaded bitmap.
+    fn set(&mut self, inodes: bool, group: u32, bit: u32, in_use: bool) {
+        let bitmap = self.bitmaps.get_mut(&(inodes, group)).unwrap();
+        let byte = &mut bitmap.data[(bit / 8) as usize];
+        if in_use {
+            *byte |= 1 << (bit % 8);
+        } else {
+            *byte &= !(1 << (bit % 8));
+        }
+        bitmap.dirty = true;
+    }
+
+    fn delta(&mut self, group: u32) -> &mut [i32; 3] {
+        self.deltas.entry(group).or_default()
+    }
+
+    /// Allocate a block, preferring group `goal`.
+    async fn alloc_block(&mut self, vol: &Ext2Vol, inbox: &BlkInbox, goal: u32) -> Result<u32, Ext2Error> {
+        for group in (goal..vol.group_count).chain(0..goal) {
+            let gd = read_group(vol, inbox, group).await?;
+            if gd.free_blocks as i32 + self.delta(group)[0] <= 0 {
+                continue;
+            }
+            self.load(vol, inbox, false, group).await?;
+            let data = &self.bitmaps[&(false, group)].data;
+            if let Some(bit) = find_clear(data, 0, vol.blocks_in_group(group)) {
+                self.set(false, group, bit, true);
+                self.delta(group)[0] -= 1;
+                return Ok(vol.first_data_block + group * vol.blocks_per_group + bit);
+            }
+        }
+        Err(Ext2Error::NoSpace)
+    }
+
+    async fn free_block(&mut self, vol: &Ext2Vol, inbox: &BlkInbox, block: u32) -> Result<(), Ext2Error> {
+        if block < vol.first_data_block || block >= vol.blocks_count {
+            return Err(Ext2Error::Corrupt);
+        }
+        let rel = block - vol.first_data_block;
+        let group = rel / vol.blocks_per_group;
+        self.load(vol, inbox, false, group).await?;
+        self.set(false, group, rel % vol.blocks_per_group, false);
+        self.delta(group)[0] += 1;
+        Ok(())
+    }
+
+    /// Allocate an inode, preferring group `goal`.
+    async fn alloc_inode(&mut self, vol: &Ext2Vol, inbox: &BlkInbox, goal: u32, is_dir: bool) -> Result<u32, Ext2Error> {
+        for group in (goal..vol.group_count).chain(0..goal) {
+            let gd = read_group(vol, inbox, group).await?;
+            if gd.free_inodes as i32 + self.delta(group)[1] <= 0 {
+                continue;
+            }
+            self.load(vol, inbox, true, group).await?;
+            // Inodes below first_ino are reserved; they are all in group 0.
+            let first = (vol.first_ino - 1).saturating_sub(group * vol.inodes_per_group);
+            let data = &self.bitmaps[&(true, group)].data;
+            if let Some(bit) = find_clear(data, first, vol.inodes_per_group) {
+                self.set(true, group, bit, true);
+                let delta = self.delta(group);
+                delta[1] -= 1;
+                if is_dir {
+                    delta[2] += 1;
+                }
+                return Ok(group * vol.inodes_per_group + bit + 1);
+            }
+        }
+        Err(Ext2Error::NoSpace)
+    }
+
+    async fn free_inode(&mut self, vol: &Ext2Vol, inbox: &BlkInbox, ino: u32, is_dir: bool) -> Result<(), Ext2Error> {
+        let group = vol.inode_group(ino);
+        self.load(vol, inbox, true, group).await?;
+        self.set(true, group, (ino - 1) % vol.inodes_per_group, false);
+        let delta = self.delta(group);
+        delta[1] += 1;
+        if is_dir {
+            delta[2] -= 1;
+        }
+        Ok(())
+    }
+
+    /// Write back changed bitmaps, group descriptor counts and superblock
+    /// counts.  `mark_valid` also sets the superblock's clean state.
+    async fn flush(self, vol: &Ext2Vol, inbox: &BlkInbox, mark_valid: bool) -> Result<(), Ext2Error> {
+        for bitmap in self.bitmaps.values().filter(|b| b.dirty) {
+            write_block(vol, inbox, bitmap.block, &bitmap.data).await?;
+        }
+
+        let (mut free_blocks, mut free_inodes) = (0i64, 0i64);
+        for (&group, delta) in self.deltas.iter().filter(|(_, d)| **d != [0; 3]) {
+            let (lba, off) = vol.group_desc_pos(group);
+            let mut sector = read_sector(inbox, lba).await?;
+            for (field, &d) in [GD_FREE_BLOCKS, GD_FREE_INODES, GD_USED_DIRS].iter().zip(delta) {
+                let v = get16(&sector, off + field) as i32 + d;
+                put16(&mut sector, off + field, v.max(0) as u16);
+            }
+            write_sector(inbox, lba, sector).await?;
+            free_blocks += delta[0] as i64;
+            free_inodes += delta[1] as i64;
+        }
+
+        let lba = vol.lba_base + SUPERBLOCK_LBA;
+        let mut sb = read_sector(inbox, lba).await?;
+        let blocks = get32(&sb, SB_FREE_BLOCKS) as i64 + free_blocks;
+        let inodes = get32(&sb, SB_FREE_INODES) as i64 + free_inodes;
+        put32(&mut sb, SB_FREE_BLOCKS, blocks.max(0) as u32);
+        put32(&mut sb, SB_FREE_INODES, inodes.max(0) as u32);
+        put32(&mut sb, SB_WTIME, now());
+        if mark_valid {
+            let state = get16(&sb, SB_STATE);
+            put16(&mut sb, SB_STATE, state | STATE_VALID);
+        }
+        Ok(write_sector(inbox, lba, sb).await?)
+    }
+}
+
+/// Clear the superblock's valid state for the duration of an update, so
+/// an interrupted one leaves the volume marked for checking.  Returns
+/// whether the volume was clean before.
+async fn begin_update(vol: &Ext2Vol, inbox: &BlkInbox) -> Result<bool, Ext2Error> {
+    if vol.read_only {
+        return Err(Ext2Error::ReadOnly);
+    }
+    let lba = vol.lba_base + SUPERBLOCK_LBA;
+    let mut sb = read_sector(inbox, lba).await?;
+    let state = get16(&sb, SB_STATE);
+    if state & STATE_VALID != 0 {
+        put16(&mut sb, SB_STATE, state & !STATE_VALID);
+        write_sector(inbox, lba, sb).await?;
+    }
+    Ok(state & STATE_VALID != 0)
+}
+
+/// Write back the allocations made, even by a failed update (blocks it
+/// linked in must stay allocated).  The volume is marked clean again
+/// unless it was not clean before, or the update failed on a device
+/// error.
+async fn finish_update<T>(
+    vol:       &Ext2Vol,
+    inbox:     &BlkInbox,
+    was_clean: bool,
+    alloc:     Alloc,
+    result:    Result<T, Ext2Error>,
+) -> Result<T, Ext2Error> {
+    let io_failed = matches!(result, Err(Ext2Error::IoError) | Err(Ext2Error::NoDevice));
+    alloc.flush(vol, inbox, was_clean && !io_failed).await?;
+    result
+}
+
+// ---------------------------------------------------------------------------
+// Directories
+
+/// One record in a directory block.
+struct RawEntry {
+    /// Offset in the block.
+    off:      usize,
+    ino:      u32,
+    rec_len:  usize,
+    name_len: usize,
+}
+
+/// Space a record with a `name_len`-byte name needs.
+fn rec_len_for(name_len: usize) -> usize {
+    (DIRENT_HEADER + name_len + 3) & !3
+}
+
+fn parse_block(block: &[u8]) -> Result<Vec<RawEntry>, Ext2Error> {
+    let mut entries = Vec::new();
+    let mut off = 0;
+    while off + DIRENT_HEADER <= block.len() {
+        let rec_len = get16(block, off + 4) as usize;
+        let name_len = block[off + 6] as usize;
+        if rec_len < DIRENT_HEADER
+            || rec_len % 4 != 0
+            || off + rec_len > block.len()
+            || DIRENT_HEADER + name_len > rec_len
+        {
+            return Err(Ext2Error::Corrupt);
+        }
+        entries.push(RawEntry { off, ino: get32(block, off), rec_len, name_len });
+        off += rec_len;
+    }
+    Ok(entries)
+}
+
+fn entry_name<'b>(block: &'b [u8], e: &RawEntry) -> &'b [u8] {
+    &block[e.off + DIRENT_HEADER..e.off + DIRENT_HEADER + e.name_len]
+}
+
+fn put_entry(vol: &Ext2Vol, block: &mut [u8], off: usize, rec_len: usize, ino: u32, name: &str, ftype: u8) {
+    put32(block, off, ino);
+    put16(block, off + 4, rec_len as u16);
+    block[off + 6] = name.len() as u8;
+    // Without INCOMPAT_FILETYPE this byte is the high byte of name_len.
+    block[off + 7] = if vol.filetype { ftype } else { 0 };
+    block[off + DIRENT_HEADER..off + DIRENT_HEADER + name.len()].copy_from_slice(name.as_bytes());
+}
+
+fn file_type(inode: &Inode) -> u8 {
+    match inode.mode() & S_IFMT {
+        S_IFREG => FT_REG_FILE,
+        S_IFDIR => FT_DIR,
+        0o020000 => 3, // character device
+        0o060000 => 4, // block device
+        0o010000 => 5, // FIFO
+        0o140000 => 6, // socket
+        S_IFLNK => 7,
+        _ => 0,
+    }
+}
+
+/// A directory loaded for lookup or modification.
+struct Dir {
+    inode:  Inode,
+    /// (block number, contents) for each block, in order.
+    blocks: Vec<(u32, Vec<u8>)>,
+}
+
+impl Dir {
+    /// The block index and record for `name`.
+    fn find(&self, name: &str) -> Result<Option<(usize, RawEntry)>, Ext2Error> {
+        for (i, (_, data)) in self.blocks.iter().enumerate() {
+            for e in parse_block(data)? {
+                if e.ino != 0 && entry_name(data, &e) == name.as_bytes() {
+                    return Ok(Some((i, e)));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Every live entry except `.` and `..`, as (name, inode).
+    fn entries(&self) -> Result<Vec<(String, u32)>, Ext2Error> {
+        let mut out = Vec::new();
+        for (_, data) in &self.blocks {
+            for e in parse_block(data)? {
+                let name = entry_name(data, &e);
+                if e.ino != 0 && name != b"." && name != b".." {
+                    out.push((String::from_utf8_lossy(name).into_owned(), e.ino));
+                }
+            }
+        }
+        Ok(out)
+    }
+}
+
+async fn load_dir(vol: &Ext2Vol, inbox: &BlkInbox, inode: Inode) -> Result<Dir, Ext2Error> {
+    if !inode.is_dir() {
+        return Err(Ext2Error::NotADirectory);
+    }
+    let count = inode.size() / vol.block_size;
+    let mut blocks = Vec::with_capacity(count as usize);
+    for n in 0..count {
+        let block = bmap(vol, inbox, &inode, n).await?;
+        if block == 0 {
+            return Err(Ext2Error::Corrupt);
+        }
+        blocks.push((block, read_block(vol, inbox, block).await?));
+    }
+    Ok(Dir { inode, blocks })
+}
+
+/// Record a change to a directory's entries: update its times, drop any
+/// htree index (now stale), and write its inode.
+async fn dir_changed(vol: &Ext2Vol, inbox: &BlkInbox, dir: &mut Dir) -> Result<(), Ext2Error> {
+    dir.inode.touch();
+    let flags = get32(&dir.inode.raw, I_FLAGS);
+    put32(&mut dir.inode.raw, I_FLAGS, flags & !INDEX_FL);
+    write_inode(vol, inbox, &dir.inode).await
+}
+
+/// Add an entry for `ino` to `dir`, in the first record with room to
+/// split, or in a new block at the end.
+async fn add_entry(
+    vol:   &Ext2Vol,
+    inbox: &BlkInbox,
+    alloc: &mut Alloc,
+    dir:   &mut Dir,
+    name:  &str,
+    ino:   u32,
+    ftype: u8,
+) -> Result<(), Ext2Error> {
+    let need = rec_len_for(name.len());
+    let mut slot = None;
+    'search: for (i, (_, data)) in dir.blocks.iter().enumerate() {
+        for e in parse_block(data)? {
+            let used = if e.ino == 0 { 0 } else { rec_len_for(e.name_len) };
+            if e.rec_len - used >= need {
+                slot = Some((i, e, used));
+                break 'search;
+            }
+        }
+    }
+    if let Some((i, e, used)) = slot {
+        let (block, data) = &mut dir.blocks[i];
+        if used == 0 {
+            put_entry(vol, data, e.off, e.rec_len, ino, name, ftype);
+        } else {
+            put16(data, e.off + 4, used as u16);
+            put_entry(vol, data, e.off + used, e.rec_len - used, ino, name, ftype);
+        }
+        write_block(vol, inbox, *block, data).await?;
+        return dir_changed(vol, inbox, dir).await;
+    }
+
+    let n = dir.blocks.len() as u64;
+    let (block, _) = bmap_alloc(vol, inbox, alloc, &mut dir.inode, n).await?;
+    let mut data = vec![0u8; vol.block_size as usize];
+    put_entry(vol, &mut data, 0, data.len(), ino, name, ftype);
+    write_block(vol, inbox, block, &data).await?;
+    dir.blocks.push((block, data));
+    dir.inode.set_size((n + 1) * vol.block_size);
+    dir_changed(vol, inbox, dir).await
+}
+
+/// Remove `name` from `dir`, merging its record into the previous one in
+/// the block (or clearing its inode if it is the first).  Returns the
+/// inode it named.
+async fn remove_entry(vol: &Ext2Vol, inbox: &BlkInbox, dir: &mut Dir, name: &str) -> Result<u32, Ext2Error> {
+    let (i, e) = dir.find(name)?.ok_or(Ext2Error::PathNotFound)?;
+    let (block, data) = &mut dir.blocks[i];
+    let prev = parse_block(data)?.into_iter().take_while(|p| p.off < e.off).last();
+    match prev {
+        Some(p) => put16(data, p.off + 4, (p.rec_len + e.rec_len) as u16),
+        None    => put32(data, e.off, 0),
+    }
+    write_block(vol, inbox, *block, data).await?;
+    dir_changed(vol, inbox, dir).await?;
+    Ok(e.ino)
+}
+
+/// Point the existing entry `name` in `dir` at `ino`.
+async fn replace_entry(
+    vol:   &Ext2Vol,
+    inbox: &BlkInbox,
+    dir:   &mut Dir,
+    name:  &str,
+    ino:   u32,
+    ftype: u8,
+) -> Result<(), Ext2Error> {
+    let (i, e) = dir.find(name)?.ok_or(Ext2Error::PathNotFound)?;
+    let (block, data) = &mut dir.blocks[i];
+    put32(data, e.off, ino);
+    if vol.filetype {
+        data[e.off + 7] = ftype;
+    }
+    write_block(vol, inbox, *block, data).await?;
+    dir_changed(vol, inbox, dir).await
+}
+
+// ---------------------------------------------------------------------------
+// Path traversal
+
+/// Walk a path (e.g. `"/"`, `"/etc"`, `"/etc/passwd"`) and return the
+/// inode it names.  Symbolic links are not followed.
+async fn walk_path(vol: &Ext2Vol, inbox: &BlkInbox, path: &str) -> Result<Inode, Ext2Error> {
+    let mut current = read_inode(vol, inbox, ROOT_INO).await?;
+    for component in path.split('/').filter(|s| !s.is_empty()) {
+        let dir = load_dir(vol, inbox, current).await?;
+        let (_, e) = dir.find(component)?.ok_or(Ext2Error::PathNotFound)?;
+        current = read_inode(vol, inbox, e.ino).await?;
+    }
+    Ok(current)
+}
+
+fn split_path(path: &str) -> Result<(&str, &str), Ext2Error> {
+    let trimmed = path.trim_end_matches('/');
+    let (parent, name) = match trimmed.rfind('/') {
+        Some(i) => (&trimmed[..i], &trimmed[i + 1..]),
+        None    => ("", trimmed),
+    };
+    if name.is_empty() || name == "." || name == ".." || name.len() > MAX_NAME_LEN || name.contains('\0') {
+        return Err(Ext2Error::InvalidName);
+    }
+    Ok((parent, name))
+}
+
+/// Load the parent directory of `path` and return it with the last path
+/// component.
+async fn open_parent<'p>(vol: &Ext2Vol, inbox: &BlkInbox, path: &'p str) -> Result<(Dir, &'p str), Ext2Error> {
+    let (parent, name) = split_path(path)?;
+    let inode = walk_path(vol, inbox, parent).await?;
+    Ok((load_dir(vol, inbox, inode).await?, name))
+}
+
+// ---------------------------------------------------------------------------
+// File data
+
+/// Read up to `len` bytes at `offset` of `inode`.  Holes read as zeros.  A
+/// symlink reads as its target.
+async fn read_inode_at(
+    vol:    &Ext2Vol,
+    inbox:  &BlkInbox,
+    inode:  &Inode,
+    offset: u64,
+    len:    usize,
+) -> Result<Vec<u8>, Ext2Error> {
+    let size = inode.size();
+    if offset >= size {
+        return Ok(Vec::new());
+    }
+    let end = size.min(offset.saturating_add(len as u64));
+
+    if inode.is_fast_symlink(vol) {
+        let target = &inode.raw[I_BLOCK..I_BLOCK + N_BLOCKS * 4];
+        let end = (end as usize).min(target.len());
+        return Ok(target[(offset as usize).min(end)..end].to_vec());
+    }
+
+    let bs = vol.block_size;
+    let mut out = Vec::with_capacity((end - offset) as usize);
+    let mut pos = offset;
+    while pos < end {
+        let in_block = pos % bs;
+        let chunk = (bs - in_block).min(end - pos);
+        let block = bmap(vol, inbox, inode, pos / bs).await?;
+        if block == 0 {
+            out.resize(out.len() + chunk as usize, 0);
+        } else {
+            let lba = vol.block_lba(block);
+            for s in in_block / SECTOR_SIZE..=(in_block + chunk - 1) / SECTOR_SIZE {
+                let sector = read_data_sector(inbox, lba + s).await?;
+                let lo = in_block.max(s * SECTOR_SIZE) - s * SECTOR_SIZE;
+                let hi = (in_block + chunk).min((s + 1) * SECTOR_SIZE) - s * SECTOR_SIZE;
+                out.extend_from_slice(&sector[lo as usize..hi as usize]);
+            }
+        }
+        pos += chunk;
+    }
+    Ok(out)
+}
+
+/// Write `data` at `offset` of `inode`, allocating blocks as needed, and
+/// write the inode.
+async fn write_inode_at(
+    vol:    &Ext2Vol,
+    inbox:  &BlkInbox,
+    alloc:  &mut Alloc,
+    inode:  &mut Inode,
+    offset: u64,
+    data:   &[u8],
+) -> Result<usize, Ext2Error> {
+    let end = offset.checked_add(data.len() as u64).ok_or(Ext2Error::FileTooLarge)?;
+    if end > vol.max_file_size() {
+        return Err(Ext2Error::FileTooLarge);
+    }
+
+    let bs = vol.block_size;
+    let mut done = 0usize;
+    let mut result = Ok(());
+    while done < data.len() {
+        let pos = offset + done as u64;
+        let in_block = pos % bs;
+        let chunk = (bs - in_block).min((data.len() - done) as u64) as usize;
+        let src = &data[done..done + chunk];
+        let (block, fresh) = match bmap_alloc(vol, inbox, alloc, inode, pos / bs).await {
+            Ok(b) => b,
+            Err(e) => { result = Err(e); break; }
+        };
+        let lba = vol.block_lba(block);
+        if fresh {
+            // Zero the parts of a new block the write does not cover.
+            let mut buf = vec![0u8; bs as usize];
+            buf[in_block as usize..in_block as usize + chunk].copy_from_slice(src);
+            result = write_block(vol, inbox, block, &buf).await;
+        } else {
+            let start = in_block as usize;
+            let sectors = start / SECTOR_SIZE as usize..=(start + chunk - 1) / SECTOR_SIZE as usize;
+            for s in sectors {
+                let s_start = s * SECTOR_SIZE as usize;
+                let lo = start.max(s_start);
+                let hi = (start + chunk).min(s_start + SECTOR_SIZE as usize);
+                let mut sector = if hi - lo == SECTOR_SIZE as usize {
+                    vec![0u8; SECTOR_SIZE as usize]
+                } else {
+                    match read_data_sector(inbox, lba + s as u64).await {
+                        Ok(b) => b,
+                        Err(e) => { result = Err(e.into()); break; }
+                    }
+                };
+                sector[lo - s_start..hi - s_start].copy_from_slice(&src[lo - start..hi - start]);
+                if let Err(e) = write_sector(inbox, lba + s as u64, sector).await {
+                    result = Err(e.into());
+                    break;
+                }
+            }
+        }
+        if result.is_err() {
+            break;
+        }
+        done += chunk;
+    }
+
+    // Record what was written even if the write stopped part-way.
+    let written_end = offset + done as u64;
+    if written_end > inode.size() {
+        inode.set_size(written_end);
+    }
+    inode.touch();
+    write_inode(vol, inbox, inode).await?;
+    result.map(|()| done)
+}
+
+/// Set the size of `inode`, freeing blocks past the new end and zeroing
+/// the tail of the last block, and write the inode.  Growing leaves a
+/// hole.
+async fn truncate_inode(
+    vol:   &Ext2Vol,
+    inbox: &BlkInbox,
+    alloc: &mut Alloc,
+    inode: &mut Inode,
+    size:  u64,
+) -> Result<(), Ext2Error> {
+    if size > vol.max_file_size() {
+        return Err(Ext2Error::FileTooLarge);
+    }
+    let bs = vol.block_size;
+    if size < inode.size() {
+        free_blocks_from(vol, inbox, alloc, inode, (size + bs - 1) / bs).await?;
+        let tail = size % bs;
+        if tail != 0 {
+            let block = bmap(vol, inbox, inode, size / bs).await?;
+            if block != 0 {
+                let lba = vol.block_lba(block);
+                for s in tail / SECTOR_SIZE..bs / SECTOR_SIZE {
+                    let mut sector = if s == tail / SECTOR_SIZE {
+                        read_data_sector(inbox, lba + s).await?
+                    } else {
+                        vec![0u8; SECTOR_SIZE as usize]
+                    };
+                    let from = tail.saturating_sub(s * SECTOR_SIZE) as usize;
+                    sector[from..].fill(0);
+                    write_sector(inbox, lba + s, sector).await?;
+                }
+            }
+        }
+    }
+    inode.set_size(size);
+    inode.touch();
+    write_inode(vol, inbox, inode).await
+}
+
+/// Free an inode whose last link is gone: its blocks, its extended
+/// attribute block (if no other inode shares it) and the inode itself.
+async fn release_inode(vol: &Ext2Vol, inbox: &BlkInbox, alloc: &mut Alloc, inode: &mut Inode) -> Result<(), Ext2Error> {
+    if !inode.is_fast_symlink(vol) {
+        free_blocks_from(vol, inbox, alloc, inode, 0).await?;
+    }
+    let acl = inode.file_acl();
+    if acl != 0 {
+        // Extended attribute block header: h_refcount at offset 4.
+        let mut block = read_block(vol, inbox, acl).await?;
+        let refs = get32(&block, 4);
+        if refs <= 1 {
+            alloc.free_block(vol, inbox, acl).await?;
+            inode.add_blocks(vol, -1);
+        } else {
+            put32(&mut block, 4, refs - 1);
+            write_block(vol, inbox, acl, &block).await?;
+        }
+        put32(&mut inode.raw, I_FILE_ACL, 0);
+    }
+    inode.set_links(0);
+    inode.set_size(0);
+    put32(&mut inode.raw, I_DTIME, now());
+    write_inode(vol, inbox, inode).await?;
+    alloc.free_inode(vol, inbox, inode.ino, inode.is_dir()).await
+}
+
+/// Remove one link to `inode`, releasing it when none are left.
+async fn drop_link(vol: &Ext2Vol, inbox: &BlkInbox, alloc: &mut Alloc, mut inode: Inode) -> Result<(), Ext2Error> {
+    let links = inode.links().saturating_sub(1);
+    if links == 0 {
+        return release_inode(vol, inbox, alloc, &mut inode).await;
+    }
+    inode.set_links(links);
+    inode.touch_ctime();
+    write_inode(vol, inbox, &inode).await
+}
+
+/// Add `delta` to the link count of directory `ino`.
+async fn adjust_links(vol: &Ext2Vol, inbox: &BlkInbox, ino: u32, delta: i32) -> Result<(), Ext2Error> {
+    let mut inode = read_inode(vol, inbox, ino).await?;
+    inode.set_links((inode.links() as i32 + delta).max(0) as u16);
+    inode.touch_ctime();
+    write_inode(vol, inbox, &inode).await
+}
+
+// ---------------------------------------------------------------------------
+// Updates
+
+async fn create_entry(
+    vol:    &Ext2Vol,
+    inbox:  &BlkInbox,
+    alloc:  &mut Alloc,
+    path:   &str,
+    is_dir: bool,
+) -> Result<(), Ext2Error> {
+    let (mut parent, name) = open_parent(vol, inbox, path).await?;
+    if parent.find(name)?.is_some() {
+        return Err(Ext2Error::AlreadyExists);
+    }
+
+    let group = vol.inode_group(parent.inode.ino);
+    let ino = alloc.alloc_inode(vol, inbox, group, is_dir).await?;
+    let mode = if is_dir { S_IFDIR | 0o755 } else { S_IFREG | 0o644 };
+    let mut inode = Inode::new(ino, mode);
+
+    if is_dir {
+        let (block, _) = bmap_alloc(vol, inbox, alloc, &mut inode, 0).await?;
+        let mut data = vec![0u8; vol.block_size as usize];
+        let dot = rec_len_for(1);
+        put_entry(vol, &mut data, 0, dot, ino, ".", FT_DIR);
+        put_entry(vol, &mut data, dot, data.len() - dot, parent.inode.ino, "..", FT_DIR);
+        write_block(vol, inbox, block, &data).await?;
+        inode.set_size(vol.block_size);
+        // The new directory's `..`.
+        let links = parent.inode.links();
+        parent.inode.set_links(links + 1);
+    }
+    write_new_inode(vol, inbox, &inode).await?;
+
+    let ftype = if is_dir { FT_DIR } else { FT_REG_FILE };
+    add_entry(vol, inbox, alloc, &mut parent, name, ino, ftype).await
+}
+
+async fn symlink_entry(
+    vol:    &Ext2Vol,
+    inbox:  &BlkInbox,
+    alloc:  &mut Alloc,
+    path:   &str,
+    target: &str,
+) -> Result<(), Ext2Error> {
+    if target.is_empty() || target.len() >= vol.block_size as usize {
+        return Err(Ext2Error::InvalidName);
+    }
+    let (mut parent, name) = open_parent(vol, inbox, path).await?;
+    if parent.find(name)?.is_some() {
+        return Err(Ext2Error::AlreadyExists);
+    }
+
+    let group = vol.inode_group(parent.inode.ino);
+    let ino = alloc.alloc_inode(vol, inbox, group, false).await?;
+    let mut inode = Inode::new(ino, S_IFLNK | 0o777);
+    if target.len() < N_BLOCKS * 4 {
+        // A fast symlink: the target lives in `i_block`.
+        inode.raw[I_BLOCK..I_BLOCK + target.len()].copy_from_slice(target.as_bytes());
+        inode.set_size(target.len() as u64);
+        write_new_inode(vol, inbox, &inode).await?;
+    } else {
+        write_new_inode(vol, inbox, &inode).await?;
+        write_inode_at(vol, inbox, alloc, &mut inode, 0, target.as_bytes()).await?;
+    }
+    add_entry(vol, inbox, alloc, &mut parent, name, ino, file_type(&inode)).await
+}
+
+async fn link_entry(vol: &Ext2Vol, inbox: &BlkInbox, alloc: &mut Alloc, path: &str, target: &str) -> Result<(), Ext2Error> {
+    let mut inode = walk_path(vol, inbox, target).await?;
+    if inode.is_dir() {
+        return Err(Ext2Error::NotAFile);
+    }
+    if inode.links() >= LINK_MAX {
+        return Err(Ext2Error::NoSpace);
+    }
+    let (mut parent, name) = open_parent(vol, inbox, path).await?;
+    if parent.find(name)?.is_some() {
+        return Err(Ext2Error::AlreadyExists);
+    }
+    add_entry(vol, inbox, alloc, &mut parent, name, inode.ino, file_type(&inode)).await?;
+    let links = inode.links();
+    inode.set_links(links + 1);
+    inode.touch_ctime();
+    write_inode(vol, inbox, &inode).await
+}
+
+async fn write_entry(
+    vol:    &Ext2Vol,
+    inbox:  &BlkInbox,
+    alloc:  &mut Alloc,
+    path:   &str,
+    offset: u64,
+    data:   &[u8],
+) -> Result<usize, Ext2Error> {
+    let mut inode = walk_path(vol, inbox, path).await?;
+    if inode.is_dir() {
+        return Err(Ext2Error::NotAFile);
+    }
+    write_inode_at(vol, inbox, alloc, &mut inode, offset, data).await
+}
+
+async fn truncate_entry(vol: &Ext2Vol, inbox: &BlkInbox, alloc: &mut Alloc, path: &str, size: u64) -> Result<(), Ext2Error> {
+    let mut inode = walk_path(vol, inbox, path).await?;
+    if !inode.is_reg() {
+        return Err(Ext2Error::NotAFile);
+    }
+    truncate_inode(vol, inbox, alloc, &mut inode, size).await
+}
+
+async fn unlink_entry(vol: &Ext2Vol, inbox: &BlkInbox, alloc: &mut Alloc, path: &str) -> Result<(), Ext2Error> {
+    let (mut parent, name) = open_parent(vol, inbox, path).await?;
+    let (_, e) = parent.find(name)?.ok_or(Ext2Error::PathNotFound)?;
+    let inode = read_inode(vol, inbox, e.ino).await?;
+    if inode.is_dir() {
+        return Err(Ext2Error::NotAFile);
+    }
+    remove_entry(vol, inbox, &mut parent, name).await?;
+    drop_link(vol, inbox, alloc, inode).await
+}
+
+async fn rmdir_entry(vol: &Ext2Vol, inbox: &BlkInbox, alloc: &mut Alloc, path: &str) -> Result<(), Ext2Error> {
+    let (mut parent, name) = open_parent(vol, inbox, path).await?;
+    let (_, e) = parent.find(name)?.ok_or(Ext2Error::PathNotFound)?;
+    let dir = load_dir(vol, inbox, read_inode(vol, inbox, e.ino).await?).await?;
+    if !dir.entries()?.is_empty() {
+        return Err(Ext2Error::NotEmpty);
+    }
+    // The removed directory's `..` no longer links to the parent.
+    let links = parent.inode.links();
+    parent.inode.set_links(links.saturating_sub(1));
+    remove_entry(vol, inbox, &mut parent, name).await?;
+    let mut inode = dir.inode;
+    release_inode(vol, inbox, alloc, &mut inode).await
+}
+
+async fn rename_entry(vol: &Ext2Vol, inbox: &BlkInbox, alloc: &mut Alloc, from: &str, to: &str) -> Result<(), Ext2Error> {
+    let (src_parent, src_name) = open_parent(vol, inbox, from).await?;
+    let (_, e) = src_parent.find(src_name)?.ok_or(Ext2Error::PathNotFound)?;
+    let src_parent_ino = src_parent.inode.ino;
+    let mut inode = read_inode(vol, inbox, e.ino).await?;
+    let is_dir = inode.is_dir();
+    let ftype = file_type(&inode);
+    drop(src_parent);
+
+    // Point the destination name at the inode, replacing any file or empty
+    // directory it named.
+    let (mut dst_parent, dst_name) = open_parent(vol, inbox, to).await?;
+    let dst_parent_ino = dst_parent.inode.ino;
+    match dst_parent.find(dst_name)? {
+        Some((_, target)) => {
+            if target.ino == inode.ino {
+                return Ok(());
+            }
+            let target = read_inode(vol, inbox, target.ino).await?;
+            match (is_dir, target.is_dir()) {
+                (true, true) => {
+                    let dir = load_dir(vol, inbox, target.clone()).await?;
+                    if !dir.entries()?.is_empty() {
+                        return Err(Ext2Error::NotEmpty);
+                    }
+                }
+                (true, false) => return Err(Ext2Error::NotADirectory),
+                (false, true) => return Err(Ext2Error::NotAFile),
+                (false, false) => {}
+            }
+            replace_entry(vol, inbox, &mut dst_parent, dst_name, inode.ino, ftype).await?;
+            if target.is_dir() {
+                // The replaced directory's `..`.
+                adjust_links(vol, inbox, dst_parent_ino, -1).await?;
+                let mut target = target;
+                release_inode(vol, inbox, alloc, &mut target).await?;
+            } else {
+                drop_link(vol, inbox, alloc, target).await?;
+            }
+        }
+        None => add_entry(vol, inbox, alloc, &mut dst_parent, dst_name, inode.ino, ftype).await?,
+    }
+    drop(dst_parent);
+
+    // Remove the old name; the source directory is reloaded, since it may
+    // be the destination directory just changed.
+    let mut src_parent = load_dir(vol, inbox, read_inode(vol, inbox, src_parent_ino).await?).await?;
+    remove_entry(vol, inbox, &mut src_parent, src_name).await?;
+
+    if is_dir && src_parent_ino != dst_parent_ino {
+        let mut dir = load_dir(vol, inbox, inode.clone()).await?;
+        replace_entry(vol, inbox, &mut dir, "..", dst_parent_ino, FT_DIR).await?;
+        adjust_links(vol, inbox, src_parent_ino, -1).await?;
+        adjust_links(vol, inbox, dst_parent_ino, 1).await?;
+    } else {
+        inode.touch_ctime();
+        write_inode(vol, inbox, &inode).await?;
+    }
+    Ok(())
+}
+
+// ---------------------------------------------------------------------------
+// Public API
+
+/// List the directory at `path`.  Use `"/"` for the root.
+pub async fn list_dir(vol: &Ext2Vol, inbox: &BlkInbox, path: &str) -> Result<Vec<DirEntry>, Ext2Error> {
+    let dir = load_dir(vol, inbox, walk_path(vol, inbox, path).await?).await?;
+    let mut entries = Vec::new();
+    for (name, ino) in dir.entries()? {
+        let inode = read_inode(vol, inbox, ino).await?;
+        entries.push(DirEntry { name, mode: inode.mode(), size: inode.size() });
+    }
+    Ok(entries)
+}
+
+/// Look up the file or directory at `path`.
+pub async fn stat(vol: &Ext2Vol, inbox: &BlkInbox, path: &str) -> Result<FileInfo, Ext2Error> {
+    Ok(walk_path(vol, inbox, path).await?.info())
+}
+
+/// Read a whole file into memory.
+pub async fn read_file(vol: &Ext2Vol, inbox: &BlkInbox, path: &str) -> Result<Vec<u8>, Ext2Error> {
+    let inode = walk_path(vol, inbox, path).await?;
+    if inode.is_dir() {
+        return Err(Ext2Error::NotAFile);
+    }
+    let size = usize::try_from(inode.size()).map_err(|_| Ext2Error::FileTooLarge)?;
+    read_inode_at(vol, inbox, &inode, 0, size).await
+}
+
+/// Read up to `len` bytes at byte `offset` of the file at `path`.  Returns
+/// fewer bytes at end of file, and none past it.
+pub async fn read_at(
+    vol:    &Ext2Vol,
+    inbox:  &BlkInbox,
+    path:   &str,
+    offset: u64,
+    len:    usize,
+) -> Result<Vec<u8>, Ext2Error> {
+    let inode = walk_path(vol, inbox, path).await?;
+    if inode.is_dir() {
+        return Err(Ext2Error::NotAFile);
+    }
+    read_inode_at(vol, inbox, &inode, offset, len).await
+}
+
+/// The target of the symbolic link at `path`.
+pub async fn readlink(vol: &Ext2Vol, inbox: &BlkInbox, path: &str) -> Result<String, Ext2Error> {
+    let inode = walk_path(vol, inbox, path).await?;
+    if !inode.is_symlink() {
+        return Err(Ext2Error::InvalidName);
+    }
+    let size = inode.size() as usize;
+    let target = read_inode_at(vol, inbox, &inode, 0, size).await?;
+    Ok(String::from_utf8_lossy(&target).into_owned())
+}
+
+/// Create an empty file at `path`.
+pub async fn create(vol: &Ext2Vol, inbox: &BlkInbox, path: &str) -> Result<(), Ext2Error> {
+    let was_clean = begin_update(vol, inbox).await?;
+    let mut alloc = Alloc::default();
+    let result = create_entry(vol, inbox, &mut alloc, path, false).await;
+    finish_update(vol, inbox, was_clean, alloc, result).await
+}
+
+/// Create an empty directory at `path`.
+pub async fn mkdir(vol: &Ext2Vol, inbox: &BlkInbox, path: &str) -> Result<(), Ext2Error> {
+    let was_clean = begin_update(vol, inbox).await?;
+    let mut alloc = Alloc::default();
+    let result = create_entry(vol, inbox, &mut alloc, path, true).await;
+    finish_update(vol, inbox, was_clean, alloc, result).await
+}
+
+/// Create a symbolic link at `path` pointing to `target`.
+pub async fn symlink(vol: &Ext2Vol, inbox: &BlkInbox, path: &str, target: &str) -> Result<(), Ext2Error> {
+    let was_clean = begin_update(vol, inbox).await?;
+    let mut alloc = Alloc::default();
+    let result = symlink_entry(vol, inbox, &mut alloc, path, target).await;
+    finish_update(vol, inbox, was_clean, alloc, result).await
+}
+
+/// Add the name `path` for the existing file `target`.
+pub async fn link(vol: &Ext2Vol, inbox: &BlkInbox, path: &str, target: &str) -> Result<(), Ext2Error> {
+    let was_clean = begin_update(vol, inbox).await?;
+    let mut alloc = Alloc::default();
+    let result = link_entry(vol, inbox, &mut alloc, path, target).await;
+    finish_update(vol, inbox, was_clean, alloc, result).await
+}
+
+/// Write `data` at byte `offset` of the file at `path`, extending it if the
+/// write ends past its size.  A gap before `offset` is left as a hole.
+pub async fn write_at(
+    vol:    &Ext2Vol,
+    inbox:  &BlkInbox,
+    path:   &str,
+    offset: u64,
+    data:   &[u8],
+) -> Result<usize, Ext2Error> {
+    let was_clean = begin_update(vol, inbox).await?;
+    let mut alloc = Alloc::default();
+    let result = write_entry(vol, inbox, &mut alloc, path, offset, data).await;
+    finish_update(vol, inbox, was_clean, alloc, result).await
+}
+
+/// Set the size of the file at `path`.
+pub async fn truncate(vol: &Ext2Vol, inbox: &BlkInbox, path: &str, size: u64) -> Result<(), Ext2Error> {
+    let was_clean = begin_update(vol, inbox).await?;
+    let mut alloc = Alloc::default();
+    let result = truncate_entry(vol, inbox, &mut alloc, path, size).await;
+    finish_update(vol, inbox, was_clean, alloc, result).await
+}
+
+/// Remove the name `path`; the file is freed with its last link.
+pub async fn unlink(vol: &Ext2Vol, inbox: &BlkInbox, path: &str) -> Result<(), Ext2Error> {
+    let was_clean = begin_update(vol, inbox).await?;
+    let mut alloc = Alloc::default();
+    let result = unlink_entry(vol, inbox, &mut alloc, path).await;
+    finish_update(vol, inbox, was_clean, alloc, result).await
+}
+
+/// Delete the empty directory at `path`.
+pub async fn rmdir(vol: &Ext2Vol, inbox: &BlkInbox, path: &str) -> Result<(), Ext2Error> {
+    let was_clean = begin_update(vol, inbox).await?;
+    let mut alloc = Alloc::default();
+    let result = rmdir_entry(vol, inbox, &mut alloc, path).await;
+    finish_update(vol, inbox, was_clean, alloc, result).await
+}
+
+/// Move `from` to `to`, replacing `to` if it exists and is compatible.
+pub async fn rename(vol: &Ext2Vol, inbox: &BlkInbox, from: &str, to: &str) -> Result<(), Ext2Error> {
+    let was_clean = begin_update(vol, inbox).await?;
+    let mut alloc = Alloc::default();
+    let result = rename_entry(vol, inbox, &mut alloc, from, to).await;
+    finish_update(vol, inbox, was_clean, alloc, result).await
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::super::ram_disk::RamDisk;
+    use libkernel::{serial_print, serial_println};
+
+    // A one-group volume of 1 KiB blocks: superblock in block 1, group
+    // descriptors in 2, bitmaps in 3 and 4, a 32-inode table in 5..=8 and
+    // the root directory in 9.
+    const BLOCKS: u32 = 1024;
+    const INODES: u32 = 32;
+    const BLOCK_BITMAP: u32 = 3;
+    const INODE_TABLE: u32 = 5;
+    const ROOT_BLOCK: u32 = 9;
+    const FREE_BLOCKS: u32 = BLOCKS - 1 - ROOT_BLOCK;
+
+    /// A superblock sector for the test volume.
+    fn superblock() -> Vec<u8> {
+        let mut sb = vec![0u8; SECTOR_SIZE as usize];
+        put32(&mut sb, SB_INODES_COUNT, INODES);
+        put32(&mut sb, SB_BLOCKS_COUNT, BLOCKS);
+        put32(&mut sb, SB_FREE_BLOCKS, FREE_BLOCKS);
+        put32(&mut sb, SB_FREE_INODES, INODES - GOOD_OLD_FIRST_INO);
+        put32(&mut sb, SB_FIRST_DATA_BLOCK, 1);
+        put32(&mut sb, SB_BLOCKS_PER_GROUP, 8192);
+        put32(&mut sb, SB_INODES_PER_GROUP, INODES);
+        put16(&mut sb, SB_MAGIC, EXT2_MAGIC);
+        put16(&mut sb, SB_STATE, STATE_VALID);
+        sb
+    }
+
+    /// Mark bits `0..n` of the bitmap in `block`.
+    fn fill_bitmap(disk: &RamDisk, block: u32, n: u32) {
+        let mut bits = vec![0u8; 1024];
+        for i in 0..n {
+            bits[(i / 8) as usize] |= 1 << (i % 8);
+        }
+        disk.put(block as u64 * 1024, &bits);
+    }
+
+    /// The test volume, formatted, with an empty root directory.
+    fn image() -> RamDisk {
+        let disk = RamDisk::new(BLOCKS as u64 * 2);
+        disk.put(SUPERBLOCK_LBA * SECTOR_SIZE, &superblock());
+
+        let mut gd = [0u8; GROUP_DESC_SIZE as usize];
+        put32(&mut gd, GD_BLOCK_BITMAP, BLOCK_BITMAP);
+        put32(&mut gd, GD_INODE_BITMAP, BLOCK_BITMAP + 1);
+        put32(&mut gd, GD_INODE_TABLE, INODE_TABLE);
+        put16(&mut gd, GD_FREE_BLOCKS, FREE_BLOCKS as u16);
+        put16(&mut gd, GD_FREE_INODES, (INODES - GOOD_OLD_FIRST_INO) as u16);
+        put16(&mut gd, GD_USED_DIRS, 1);
+        disk.put(2 * 1024, &gd);
+        fill_bitmap(&disk, BLOCK_BITMAP, ROOT_BLOCK);
+        fill_bitmap(&disk, BLOCK_BITMAP + 1, GOOD_OLD_FIRST_INO);
+
+        let mut root = [0u8; INODE_SIZE];
+        put16(&mut root, I_MODE, S_IFDIR | 0o755);
+        put16(&mut root, I_LINKS_COUNT, 2);
+        put32(&mut root, I_SIZE, 1024);
+        put32(&mut root, I_BLOCKS, 2);
+        put32(&mut root, I_BLOCK, ROOT_BLOCK);
+        disk.put(INODE_TABLE as u64 * 1024 + (ROOT_INO as u64 - 1) * 128, &root);
+
+        let mut dir = vec![0u8; 1024];
+        put32(&mut dir, 0, ROOT_INO);
+        put16(&mut dir, 4, 12);
+        dir[6] = 1;
+        dir[8] = b'.';
+        put32(&mut dir, 12, ROOT_INO);
+        put16(&mut dir, 16, 1012);
+        dir[18] = 2;
+        dir[20..22].copy_from_slice(b"..");
+        disk.put(ROOT_BLOCK as u64 * 1024, &dir);
+        disk
+    }
+
+    fn free_blocks(disk: &RamDisk, vol: &Ext2Vol) -> u16 {
+        disk.run(read_group(vol, &disk.inbox, 0)).unwrap().free_blocks
+    }
+
+    #[test_case]
+    fn test_ext2_superblock_rejections() {
+        serial_print!("test_ext2_superblock_rejections... ");
+        let vol = parse_superblock(&superblock(), 0).unwrap();
+        assert_eq!((vol.block_size, vol.group_count, vol.first_ino), (1024, 1, 11));
+        assert!(!vol.read_only);
+
+        let corrupt = |f: &dyn Fn(&mut Vec<u8>)| {
+            let mut sb = superblock();
+            f(&mut sb);
+            parse_superblock(&sb, 0)
+        };
+        assert!(matches!(corrupt(&|sb| put16(sb, SB_MAGIC, 0)), Err(Ext2Error::NotExt2)));
+        assert!(matches!(corrupt(&|sb| put32(sb, SB_LOG_BLOCK_SIZE, 7)), Err(Ext2Error::Corrupt)));
+        assert!(matches!(corrupt(&|sb| put32(sb, SB_INODES_PER_GROUP, 0)), Err(Ext2Error::Corrupt)));
+        assert!(matches!(corrupt(&|sb| put32(sb, SB_FIRST_DATA_BLOCK, BLOCKS)), Err(Ext2Error::Corrupt)));
+        // A group's bitmap is one block: 8192 bits with 1 KiB blocks.
+        assert!(matches!(corrupt(&|sb| put32(sb, SB_BLOCKS_PER_GROUP, 8193)), Err(Ext2Error::Corrupt)));
+        assert!(matches!(corrupt(&|sb| put32(sb, SB_INODES_PER_GROUP, 8193)), Err(Ext2Error::Corrupt)));
+
+        // Dynamic revision: inode size and feature flags are read.
+        let rev1 = |f: &dyn Fn(&mut Vec<u8>)| corrupt(&|sb| {
+            put32(sb, SB_REV_LEVEL, 1);
+            put32(sb, SB_FIRST_INO, 11);
+            put16(sb, SB_INODE_SIZE, 256);
+            f(sb);
+        });
+        assert_eq!(rev1(&|_| {}).unwrap().inode_size, 256);
+        assert!(matches!(rev1(&|sb| put16(sb, SB_INODE_SIZE, 100)), Err(Ext2Error::Corrupt)));
+        assert!(matches!(rev1(&|sb| put16(sb, SB_INODE_SIZE, 2048)), Err(Ext2Error::Corrupt)));
+        // Extents.
+        assert!(matches!(rev1(&|sb| put32(sb, SB_FEATURE_INCOMPAT, 0x0040)), Err(Ext2Error::Unsupported)));
+        // An unknown read-only feature still mounts, for reading.
+        assert!(rev1(&|sb| put32(sb, SB_FEATURE_RO_COMPAT, 0x0100)).unwrap().read_only);
+        serial_println!("[ok]");
+    }
+
+    #[test_case]
+    fn test_ext2_read_only_volume_refuses_updates() {
+        serial_print!("test_ext2_read_only_volume_refuses_updates... ");
+        let disk = image();
+        let mut vol = disk.run(open_ext2(&disk.inbox)).unwrap();
+        vol.read_only = true;
+        assert!(matches!(disk.run(create(&vol, &disk.inbox, "/f")), Err(Ext2Error::ReadOnly)));
+        serial_println!("[ok]");
+    }
+
+    #[test_case]
+    fn test_ext2_inode_numbers_checked() {
+        serial_print!("test_ext2_inode_numbers_checked... ");
+        let disk = image();
+        let vol = disk.run(open_ext2(&disk.inbox)).unwrap();
+        assert!(matches!(disk.run(inode_pos(&vol, &disk.inbox, 0)), Err(Ext2Error::Corrupt)));
+        assert!(matches!(disk.run(inode_pos(&vol, &disk.inbox, INODES + 1)), Err(Ext2Error::Corrupt)));
+        let (lba, off) = disk.run(inode_pos(&vol, &disk.inbox, INODES)).unwrap();
+        assert_eq!(lba * SECTOR_SIZE + off as u64, INODE_TABLE as u64 * 1024 + (INODES as u64 - 1) * 128);
+        let root = disk.run(read_inode(&vol, &disk.inbox, ROOT_INO)).unwrap();
+        assert!(root.is_dir());
+        serial_println!("[ok]");
+    }
+
+    #[test_case]
+    fn test_ext2_find_clear() {
+        serial_print!("test_ext2_find_clear... ");
+        let bits = [0xff, 0b1111_0111, 0xff];
+        assert_eq!(find_clear(&bits, 0, 24), Some(11));
+        assert_eq!(find_clear(&bits, 12, 24), None);
+        // The search stops at `n`, and at the end of the data.
+        assert_eq!(find_clear(&bits, 0, 11), None);
+        assert_eq!(find_clear(&[0x7f], 0, 100), Some(7));
+        assert_eq!(find_clear(&[0xff], 0, 100), None);
+        serial_println!("[ok]");
+    }
+
+    #[test_case]
+    fn test_ext2_block_path_boundaries() {
+        serial_print!("test_ext2_block_path_boundaries... ");
+        let vol = parse_superblock(&superblock(), 0).unwrap();
+        let p = vol.ptrs_per_block() as usize;
+        assert_eq!(block_path(&vol, 11).unwrap(), [11]);
+        assert_eq!(block_path(&vol, 12).unwrap(), [12, 0]);
+        assert_eq!(block_path(&vol, 12 + 255).unwrap(), [12, p - 1]);
+        assert_eq!(block_path(&vol, 12 + 256).unwrap(), [13, 0, 0]);
+        assert_eq!(block_path(&vol, 12 + 256 + 257).unwrap(), [13, 1, 1]);
+        assert_eq!(block_path(&vol, 12 + 256 + 65536).unwrap(), [14, 0, 0, 0]);
+        assert!(matches!(block_path(&vol, vol.max_file_blocks()), Err(Ext2Error::FileTooLarge)));
+        serial_println!("[ok]");
+    }
+
+    #[test_case]
+    fn test_ext2_bmap_alloc_and_truncate() {
+        serial_print!("test_ext2_bmap_alloc_and_truncate... ");
+        let disk = image();
+        let inbox = &disk.inbox;
+        let vol = disk.run(open_ext2(inbox)).unwrap();
+        let bitmap = disk.get(BLOCK_BITMAP as u64 * 1024, 1024);
+        let mut inode = Inode::new(12, S_IFREG | 0o644);
+        let mut alloc = Alloc::default();
+
+        // Last direct, first and last single indirect, first double
+        // indirect, and the first block of its second indirect block.
+        let targets = [11u64, 12, 13, 267, 268, 524];
+        let mut blocks = Vec::new();
+        for &n in &targets {
+            let (block, fresh) = disk.run(bmap_alloc(&vol, inbox, &mut alloc, &mut inode, n)).unwrap();
+            assert!(fresh && block > ROOT_BLOCK && block < BLOCKS);
+            assert!(!blocks.contains(&block));
+            blocks.push(block);
+        }
+        // Six data blocks, one indirect block, and a double indirect block
+        // with two indirect blocks under it.
+        assert_eq!(inode.blocks(), 10 * 2);
+        assert!(inode.block(12) != 0 && inode.block(13) != 0 && inode.block(14) == 0);
+        for (&n, &block) in targets.iter().zip(&blocks) {
+            assert_eq!(disk.run(bmap(&vol, inbox, &inode, n)).unwrap(), block);
+            let (again, fresh) = disk.run(bmap_alloc(&vol, inbox, &mut alloc, &mut inode, n)).unwrap();
+            assert!(again == block && !fresh);
+        }
+        // Holes.
+        assert_eq!(disk.run(bmap(&vol, inbox, &inode, 0)).unwrap(), 0);
+        assert_eq!(disk.run(bmap(&vol, inbox, &inode, 300)).unwrap(), 0);
+        assert_eq!(disk.run(bmap(&vol, inbox, &inode, 12 + 256 + 65536)).unwrap(), 0);
+
+        // Keep blocks 0..13: the rest of the single indirect tree goes,
+        // and the whole double indirect tree.
+        disk.run(free_blocks_from(&vol, inbox, &mut alloc, &mut inode, 13)).unwrap();
+        assert_eq!(inode.blocks(), 3 * 2);
+        assert_eq!(disk.run(bmap(&vol, inbox, &inode, 12)).unwrap(), blocks[1]);
+        assert_eq!(disk.run(bmap(&vol, inbox, &inode, 13)).unwrap(), 0);
+        assert_eq!(inode.block(13), 0);
+
+        disk.run(free_blocks_from(&vol, inbox, &mut alloc, &mut inode, 0)).unwrap();
+        assert_eq!(inode.blocks(), 0);
+        assert!((0..N_BLOCKS).all(|i| inode.block(i) == 0));
+
+        // Everything allocated was freed again.
+        disk.run(alloc.flush(&vol, inbox, true)).unwrap();
+        assert_eq!(disk.get(BLOCK_BITMAP as u64 * 1024, 1024), bitmap);
+        assert_eq!(free_blocks(&disk, &vol), FREE_BLOCKS as u16);
+        serial_println!("[ok]");
+    }
+
+    #[test_case]
+    fn test_ext2_free_block_out_of_range() {
+        serial_print!("test_ext2_free_block_out_of_range... ");
+        let disk = image();
+        let vol = disk.run(open_ext2(&disk.inbox)).unwrap();
+        let mut alloc = Alloc::default();
+        assert!(matches!(disk.run(alloc.free_block(&vol, &disk.inbox, 0)), Err(Ext2Error::Corrupt)));
+        assert!(matches!(disk.run(alloc.free_block(&vol, &disk.inbox, BLOCKS)), Err(Ext2Error::Corrupt)));
+        serial_println!("[ok]");
+    }
+
+    #[test_case]
+    fn test_ext2_write_read_unlink() {
+        serial_print!("test_ext2_write_read_unlink... ");
+        let disk = image();
+        let inbox = &disk.inbox;
+        let vol = disk.run(open_ext2(inbox)).unwrap();
+        let data: Vec<u8> = (0..13 * 1024 + 100).map(|i| i as u8).collect();
+
+        disk.run(create(&vol, inbox, "/f")).unwrap();
+        assert!(matches!(disk.run(create(&vol, inbox, "/f")), Err(Ext2Error::AlreadyExists)));
+        assert_eq!(disk.run(write_at(&vol, inbox, "/f", 0, &data)).unwrap(), data.len());
+        assert_eq!(disk.run(read_file(&vol, inbox, "/f")).unwrap(), data);
+        assert_eq!(disk.run(read_at(&vol, inbox, "/f", 12 * 1024 - 2, 4)).unwrap(), &data[12 * 1024 - 2..][..4]);
+        // 14 data blocks and an indirect block.
+        assert_eq!(free_blocks(&disk, &vol), FREE_BLOCKS as u16 - 15);
+
+        disk.run(truncate(&vol, inbox, "/f", 1000)).unwrap();
+        assert_eq!(disk.run(stat(&vol, inbox, "/f")).unwrap().size, 1000);
+        assert_eq!(free_blocks(&disk, &vol), FREE_BLOCKS as u16 - 1);
+
+        disk.run(unlink(&vol, inbox, "/f")).unwrap();
+        assert!(matches!(disk.run(stat(&vol, inbox, "/f")), Err(Ext2Error::PathNotFound)));
+        assert_eq!(free_blocks(&disk, &vol), FREE_BLOCKS as u16);
+        // Every update left the volume marked clean.
+        assert_eq!(get16(&disk.get(SUPERBLOCK_LBA * SECTOR_SIZE, 512), SB_STATE) & STATE_VALID, STATE_VALID);
+        serial_println!("[ok]");
+    }
+}
@@ -0,0 +1,1815 @@
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::convert::{TryFrom, TryInto};
+
+use super::exfat::BlkInbox;
+use super::partition::{self, PartitionError, PartitionType};
+use super::sector::{
+    get16, get32, put16, put32, read_data_sector, read_sector, write_sector, BlkIoError,
+};
+
+// ---------------------------------------------------------------------------
+// Public error type
+
+#[derive(Debug)]
+pub enum FatError {
+    NoDevice,
+    IoError,
+    NotFat,
+    UnknownPartitionLayout,
+    /// Sectors other than 512 bytes, or a FAT32 version other than 0.0.
+    Unsupported,
+    /// An on-disk structure is inconsistent.
+    Corrupt,
+    PathNotFound,
+    NotAFile,
+    NotADirectory,
+    /// Files are limited to 4 GiB − 1.
+    FileTooLarge,
+    /// The name is already taken in the directory.
+    AlreadyExists,
+    /// The directory still has entries.
+    NotEmpty,
+    /// No free clusters, or the fixed FAT12/16 root directory is full.
+    NoSpace,
+    /// Empty, too long, `.`/`..`, or containing a character FAT forbids.
+    InvalidName,
+}
+
+impl From<BlkIoError> for FatError {
+    fn from(e: BlkIoError) -> Self {
+        match e {
+            BlkIoError::NoDevice => FatError::NoDevice,
+            BlkIoError::IoError  => FatError::IoError,
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Public types
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FatType {
+    Fat12,
+    Fat16,
+    Fat32,
+}
+
+/// A directory entry returned by `list_dir` and `stat`.
+#[derive(Clone)]
+pub struct DirEntry {
+    /// The long name if the entry has one, else the 8.3 name.
+    pub name:   String,
+    pub is_dir: bool,
+    /// Always 0 for directories, which record no size.
+    pub size:   u64,
+    /// The byte address of the 8.3 entry, which does not move while the
+    /// file exists; 1 for the root directory.
+    pub ino:    u64,
+    /// Last write time in nanoseconds since the Unix epoch; 0 for the root
+    /// directory, which records none.
+    pub mtime:  u64,
+}
+
+/// Parsed FAT volume state.
+pub struct FatVol {
+    /// Absolute LBA of the boot sector.
+    pub lba_base:            u64,
+    pub fat_type:            FatType,
+    /// Sectors per cluster (always a power of two).
+    pub sectors_per_cluster: u64,
+    /// Absolute LBA of the first FAT.
+    pub fat_lba:             u64,
+    /// Sectors in each FAT.
+    pub fat_sectors:         u64,
+    pub number_of_fats:      u8,
+    /// Absolute LBA and length of the fixed root directory (FAT12/16).
+    pub root_lba:            u64,
+    pub root_sectors:        u64,
+    /// First cluster of the root directory (FAT32); 0 on FAT12/16.
+    pub root_cluster:        u32,
+    /// Absolute LBA of cluster 2.
+    pub data_lba:            u64,
+    /// Number of clusters in the data region.
+    pub cluster_count:       u32,
+    /// The only FAT kept up to date, when FAT32 mirroring is disabled.
+    active_fat:              Option<u8>,
+    /// Absolute LBA of the FAT32 FSInfo sector.
+    fs_info_lba:             Option<u64>,
+}
+
+impl FatVol {
+    fn cluster_bytes(&self) -> u64 {
+        self.sectors_per_cluster * SECTOR_SIZE
+    }
+
+    fn cluster_lba(&self, cluster: u32) -> u64 {
+        self.data_lba + (cluster - 2) as u64 * self.sectors_per_cluster
+    }
+
+    fn is_valid_cluster(&self, cluster: u32) -> bool {
+        cluster >= 2 && cluster < self.cluster_count + 2
+    }
+
+    fn clusters_for(&self, bytes: u64) -> usize {
+        ((bytes + self.cluster_bytes() - 1) / self.cluster_bytes()) as usize
+    }
+
+    /// Value written to end a chain.
+    fn eoc(&self) -> u32 {
+        match self.fat_type {
+            FatType::Fat12 => 0xFFF,
+            FatType::Fat16 => 0xFFFF,
+            FatType::Fat32 => 0x0FFF_FFFF,
+        }
+    }
+
+    /// Smallest value that ends a chain.
+    fn eoc_min(&self) -> u32 {
+        match self.fat_type {
+            FatType::Fat12 => 0xFF8,
+            FatType::Fat16 => 0xFFF8,
+            FatType::Fat32 => 0x0FFF_FFF8,
+        }
+    }
+
+    /// Byte offset of the entry for `cluster` within a FAT.
+    fn fat_offset(&self, cluster: u32) -> u64 {
+        let n = cluster as u64;
+        match self.fat_type {
+            FatType::Fat12 => n + n / 2,
+            FatType::Fat16 => n * 2,
+            FatType::Fat32 => n * 4,
+        }
+    }
+
+    /// Absolute LBA of the FAT that is read.
+    fn read_fat_lba(&self) -> u64 {
+        self.fat_lba + self.active_fat.unwrap_or(0) as u64 * self.fat_sectors
+    }
+
+    /// Absolute LBA of each FAT that is written.
+    fn write_fat_lbas(&self) -> Vec<u64> {
+        match self.active_fat {
+            Some(i) => vec![self.fat_lba + i as u64 * self.fat_sectors],
+            None => (0..self.number_of_fats as u64)
+                .map(|i| self.fat_lba + i * self.fat_sectors)
+                .collect(),
+        }
+    }
+
+    /// The clean-shutdown bit in FAT[1]; FAT12 has none.
+    fn clean_bit(&self) -> Option<u32> {
+        match self.fat_type {
+            FatType::Fat12 => None,
+            FatType::Fat16 => Some(0x8000),
+            FatType::Fat32 => Some(0x0800_0000),
+        }
+    }
+
+    /// How `..` refers to directory `cluster`: the root is cluster 0.
+    fn dotdot_ref(&self, cluster: u32) -> u32 {
+        if cluster == self.root_cluster { 0 } else { cluster }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// On-disk constants
+
+const SECTOR_SIZE: u64 = 512;
+const ENTRY_SIZE: usize = 32;
+const ENTRIES_PER_SECTOR: usize = SECTOR_SIZE as usize / ENTRY_SIZE;
+/// A directory may hold at most 65536 entries.
+const MAX_DIR_ENTRIES: usize = 65536;
+
+/// Inode number reported for the root directory.
+const ROOT_INO: u64 = 1;
+
+// BIOS Parameter Block field offsets.
+const BPB_BYTES_PER_SEC: usize = 11;
+const BPB_SEC_PER_CLUS:  usize = 13;
+const BPB_RSVD_SEC_CNT:  usize = 14;
+const BPB_NUM_FATS:      usize = 16;
+const BPB_ROOT_ENT_CNT:  usize = 17;
+const BPB_TOT_SEC16:     usize = 19;
+const BPB_MEDIA:         usize = 21;
+const BPB_FAT_SZ16:      usize = 22;
+const BPB_TOT_SEC32:     usize = 32;
+// FAT32 only.
+const BPB_FAT_SZ32:      usize = 36;
+const BPB_EXT_FLAGS:     usize = 40;
+const BPB_FS_VER:        usize = 42;
+const BPB_ROOT_CLUS:     usize = 44;
+const BPB_FS_INFO:       usize = 48;
+
+/// `BPB_ExtFlags`: only the FAT numbered in bits 0..4 is active.
+const EXT_FLAGS_NO_MIRROR: u16 = 0x0080;
+
+// FSInfo sector.
+const FSI_LEAD_SIG:  u32 = 0x4161_5252;
+const FSI_STRUC_SIG: u32 = 0x6141_7272;
+const FSI_FREE_COUNT: usize = 488;
+const FSI_NXT_FREE:   usize = 492;
+const FSI_UNKNOWN:    u32 = 0xFFFF_FFFF;
+
+// Directory entry fields.
+const DIR_NAME:          usize = 0;
+const DIR_ATTR:          usize = 11;
+const DIR_NTRES:         usize = 12;
+const DIR_CRT_TIME_TENTH: usize = 13;
+const DIR_CRT_TIME:      usize = 14;
+const DIR_CRT_DATE:      usize = 16;
+const DIR_LST_ACC_DATE:  usize = 18;
+const DIR_FST_CLUS_HI:   usize = 20;
+const DIR_WRT_TIME:      usize = 22;
+const DIR_WRT_DATE:      usize = 24;
+const DIR_FST_CLUS_LO:   usize = 26;
+const DIR_FILE_SIZE:     usize = 28;
+
+const ENTRY_END:     u8 = 0x00;
+const ENTRY_DELETED: u8 = 0xE5;
+/// A leading 0x05 stands for a name starting with the byte 0xE5.
+const ENTRY_KANJI_E5: u8 = 0x05;
+
+const ATTR_VOLUME_ID: u8 = 0x08;
+const ATTR_DIRECTORY: u8 = 0x10;
+const ATTR_ARCHIVE:   u8 = 0x20;
+/// Read-only | hidden | system | volume ID marks a long-name entry.
+const ATTR_LONG_NAME: u8 = 0x0F;
+const ATTR_LONG_NAME_MASK: u8 = 0x3F;
+
+/// `DIR_NTRes`: the base name / extension is shown in lower case.
+const NTRES_LOWER_BASE: u8 = 0x08;
+const NTRES_LOWER_EXT:  u8 = 0x10;
+
+/// Long-name entry: `LDIR_Ord` bit marking the last (first stored) entry.
+const LAST_LONG_ENTRY: u8 = 0x40;
+const LDIR_CHKSUM:     usize = 13;
+/// Offsets of the 13 UTF-16 units a long-name entry holds.
+const LDIR_CHARS: [usize; 13] = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+const CHARS_PER_LONG_ENTRY: usize = 13;
+const MAX_NAME_UNITS: usize = 255;
+
+const DOT:    [u8; 11] = *b".          ";
+const DOTDOT: [u8; 11] = *b"..         ";
+
+/// Characters allowed in an 8.3 name besides letters and digits.
+const SHORT_NAME_PUNCT: &[u8] = b"$%'-_@~`!(){}^#&";
+/// Characters never allowed in a long name.
+const LONG_NAME_FORBIDDEN: &str = "\"*/:<>?\\|";
+
+// ---------------------------------------------------------------------------
+// Volume detection
+
+/// A jump instruction, a 0x55AA signature and a plausible BPB.  exFAT and
+/// NTFS boot sectors fail the BPB checks.
+fn is_fat_boot(boot: &[u8]) -> bool {
+    let jump = (boot[0] == 0xEB && boot[2] == 0x90) || boot[0] == 0xE9;
+    let media = boot[BPB_MEDIA];
+    jump
+        && boot[510] == 0x55 && boot[511] == 0xAA
+        && matches!(get16(boot, BPB_BYTES_PER_SEC), 512 | 1024 | 2048 | 4096)
+        && boot[BPB_SEC_PER_CLUS].is_power_of_two()
+        && get16(boot, BPB_RSVD_SEC_CNT) != 0
+        && boot[BPB_NUM_FATS] != 0
+        && (media == 0xF0 || media >= 0xF8)
+}
+
+/// Open the FAT12/16/32 volume on the given block device.
+///
+/// Detects a bare volume and volumes in MBR (FAT or EFI system types) or
+/// GPT (Basic Data or EFI System) partitions.
+pub async fn open_fat(inbox: &BlkInbox) -> Result<FatVol, FatError> {
+    let sector0 = read_sector(inbox, 0).await?;
+    if is_fat_boot(&sector0) {
+        return parse_boot(&sector0, 0);
+    }
+
+    let parts = partition::partitions(inbox).await.map_err(|e| match e {
+        PartitionError::NoDevice      => FatError::NoDevice,
+        PartitionError::IoError       => FatError::IoError,
+        PartitionError::UnknownLayout => FatError::UnknownPartitionLayout,
+    })?;
+
+    for part in parts {
+        let candidate = match part.ptype {
+            PartitionType::Mbr(t) => partition::MBR_FAT.contains(&t),
+            PartitionType::Gpt(guid) => {
+                guid == partition::GPT_BASIC_DATA || guid == partition::GPT_EFI_SYSTEM
+            }
+        };
+        if !candidate { continue; }
+        let boot = read_sector(inbox, part.start_lba).await?;
+        if is_fat_boot(&boot) {
+            return parse_boot(&boot, part.start_lba);
+        }
+    }
+
+    Err(FatError::NotFat)
+}
+
+fn parse_boot(boot: &[u8], lba_base: u64) -> Result<FatVol, FatError> {
+    if get16(boot, BPB_BYTES_PER_SEC) != SECTOR_SIZE as u16 {
+        return Err(FatError::Unsupported);
+    }
+    let sectors_per_cluster = boot[BPB_SEC_PER_CLUS] as u64;
+    let reserved = get16(boot, BPB_RSVD_SEC_CNT) as u64;
+    let number_of_fats = boot[BPB_NUM_FATS];
+    let root_entries = get16(boot, BPB_ROOT_ENT_CNT) as u64;
+    let total = match get16(boot, BPB_TOT_SEC16) {
+        0 => get32(boot, BPB_TOT_SEC32) as u64,
+        n => n as u64,
+    };
+    let fat_sectors = match get16(boot, BPB_FAT_SZ16) {
+        0 => get32(boot, BPB_FAT_SZ32) as u64,
+        n => n as u64,
+    };
+    if total == 0 || fat_sectors == 0 {
+        return Err(FatError::Corrupt);
+    }
+
+    let root_sectors = (root_entries * ENTRY_SIZE as u64 + SECTOR_SIZE - 1) / SECTOR_SIZE;
+    let first_data = reserved + number_of_fats as u64 * fat_sectors + root_sectors;
+    if first_data >= total {
+        return Err(FatError::Corrupt);
+    }
+    let clusters = (total - first_data) / sectors_per_cluster;
+
+    // The type is decided by the cluster count alone (Microsoft FAT spec).
+    let fat_type = if clusters < 4085 {
+        FatType::Fat12
+    } else if clusters < 65525 {
+        FatType::Fat16
+    } else {
+        FatType::Fat32
+    };
+
+    let mut vol = FatVol {
+        lba_base,
+        fat_type,
+        sectors_per_cluster,
+        fat_lba:        lba_base + reserved,
+        fat_sectors,
+        number_of_fats,
+        root_lba:       lba_base + reserved + number_of_fats as u64 * fat_sectors,
+        root_sectors,
+        root_cluster:   0,
+        data_lba:       lba_base + first_data,
+        cluster_count:  clusters.min(u32::MAX as u64 - 2) as u32,
+        active_fat:     None,
+        fs_info_lba:    None,
+    };
+
+    if fat_type == FatType::Fat32 {
+        if get16(boot, BPB_FS_VER) != 0 {
+            return Err(FatError::Unsupported);
+        }
+        if root_entries != 0 {
+            return Err(FatError::Corrupt);
+        }
+        vol.root_cluster = get32(boot, BPB_ROOT_CLUS);
+        if !vol.is_valid_cluster(vol.root_cluster) {
+            return Err(FatError::Corrupt);
+        }
+        let ext_flags = get16(boot, BPB_EXT_FLAGS);
+        if ext_flags & EXT_FLAGS_NO_MIRROR != 0 {
+            let active = (ext_flags & 0xF) as u8;
+            if active >= number_of_fats {
+                return Err(FatError::Corrupt);
+            }
+            vol.active_fat = Some(active);
+        }
+        vol.fs_info_lba = match get16(boot, BPB_FS_INFO) {
+            0 | 0xFFFF => None,
+            n => Some(lba_base + n as u64),
+        };
+    }
+
+    // Never address entries past the end of the FAT.
+    let fat_entries = match fat_type {
+        FatType::Fat12 => fat_sectors * SECTOR_SIZE * 2 / 3,
+        FatType::Fat16 => fat_sectors * SECTOR_SIZE / 2,
+        FatType::Fat32 => fat_sectors * SECTOR_SIZE / 4,
+    };
+    vol.cluster_count = vol.cluster_count.min(fat_entries.saturating_sub(2).min(u32::MAX as u64) as u32);
+    Ok(vol)
+}
+
+// ---------------------------------------------------------------------------
+// FAT access
+
+async fn read_fat(vol: &FatVol, inbox: &BlkInbox, cluster: u32) -> Result<u32, FatError> {
+    let off = vol.fat_offset(cluster);
+    let lba = vol.read_fat_lba() + off / SECTOR_SIZE;
+    let i = (off % SECTOR_SIZE) as usize;
+    let sector = read_sector(inbox, lba).await?;
+    Ok(match vol.fat_type {
+        FatType::Fat12 => {
+            // A 12-bit entry may straddle two sectors.
+            let hi = if i == SECTOR_SIZE as usize - 1 {
+                read_sector(inbox, lba + 1).await?[0]
+            } else {
+                sector[i + 1]
+            };
+            let v = u16::from_le_bytes([sector[i], hi]);
+            (if cluster & 1 == 1 { v >> 4 } else { v & 0x0FFF }) as u32
+        }
+        FatType::Fat16 => get16(&sector, i) as u32,
+        FatType::Fat32 => get32(&sector, i) & 0x0FFF_FFFF,
+    })
+}
+
+/// Set the entry for `cluster` in every FAT that is kept up to date.  The
+/// top four bits of a FAT32 entry are reserved and preserved.
+async fn write_fat(vol: &FatVol, inbox: &BlkInbox, cluster: u32, value: u32) -> Result<(), FatError> {
+    let off = vol.fat_offset(cluster);
+    for base in vol.write_fat_lbas() {
+        let lba = base + off / SECTOR_SIZE;
+        let i = (off % SECTOR_SIZE) as usize;
+        let mut sector = read_sector(inbox, lba).await?;
+        match vol.fat_type {
+            FatType::Fat12 => {
+                let mut next = if i == SECTOR_SIZE as usize - 1 {
+                    Some(read_sector(inbox, lba + 1).await?)
+                } else {
+                    None
+                };
+                let hi = match &next { Some(s) => s[0], None => sector[i + 1] };
+                let old = u16::from_le_bytes([sector[i], hi]);
+                let v = if cluster & 1 == 1 {
+                    (old & 0x000F) | ((value as u16) << 4)
+                } else {
+                    (old & 0xF000) | (value as u16 & 0x0FFF)
+                };
+                let [lo, hi] = v.to_le_bytes();
+                sector[i] = lo;
+                match &mut next {
+                    Some(s) => s[0] = hi,
+                    None    => sector[i + 1] = hi,
+                }
+                write_sector(inbox, lba, sector).await?;
+                if let Some(s) = next {
+                    write_sector(inbox, lba + 1, s).await?;
+                }
+                continue;
+            }
+            FatType::Fat16 => put16(&mut sector, i, value as u16),
+            FatType::Fat32 => {
+                let old = get32(&sector, i);
+                put32(&mut sector, i, (old & 0xF000_0000) | (value & 0x0FFF_FFFF));
+            }
+        }
+        write_sector(inbox, lba, sector).await?;
+    }
+    Ok(())
+}
+
+/// The clusters of the chain starting at `first`; empty if `first` is 0.
+async fn chain(vol: &FatVol, inbox: &BlkInbox, first: u32) -> Result<Vec<u32>, FatError> {
+    let mut clusters = Vec::new();
+    let mut cluster = first;
+    while cluster != 0 {
+        if !vol.is_valid_cluster(cluster) || clusters.len() >= vol.cluster_count as usize {
+            return Err(FatError::Corrupt);
+        }
+        clusters.push(cluster);
+        let next = read_fat(vol, inbox, cluster).await?;
+        if next >= vol.eoc_min() {
+            break;
+        }
+        cluster = next;
+    }
+    Ok(clusters)
+}
+
+// ---------------------------------------------------------------------------
+// Allocation
+
+/// Cluster allocation state for one update: where to search next and the
+/// change in free clusters, for the FAT32 FSInfo sector.
+struct Alloc {
+    hint:      u32,
+    delta:     i64,
+    allocated: bool,
+}
+
+impl Alloc {
+    /// Start searching where the FSInfo sector says the free clusters are.
+    async fn new(vol: &FatVol, inbox: &BlkInbox) -> Result<Self, FatError> {
+        let mut hint = 2;
+        if let Some(lba) = vol.fs_info_lba {
+            let fsi = read_sector(inbox, lba).await?;
+            let next = get32(&fsi, FSI_NXT_FREE);
+            if fs_info_valid(&fsi) && vol.is_valid_cluster(next) {
+                hint = next;
+            }
+        }
+        Ok(Alloc { hint, delta: 0, allocated: false })
+    }
+
+    /// Allocate a cluster and mark it as the end of a chain.
+    async fn alloc_cluster(&mut self, vol: &FatVol, inbox: &BlkInbox) -> Result<u32, FatError> {
+        let end = vol.cluster_count + 2;
+        for cluster in (self.hint..end).chain(2..self.hint) {
+            if read_fat(vol, inbox, cluster).await? == 0 {
+                write_fat(vol, inbox, cluster, vol.eoc()).await?;
+                self.hint = cluster + 1;
+                self.delta -= 1;
+                self.allocated = true;
+                return Ok(cluster);
+            }
+        }
+        Err(FatError::NoSpace)
+    }
+
+    async fn free_clusters(&mut self, vol: &FatVol, inbox: &BlkInbox, clusters: &[u32]) -> Result<(), FatError> {
+        for &cluster in clusters {
+            write_fat(vol, inbox, cluster, 0).await?;
+            self.delta += 1;
+        }
+        Ok(())
+    }
+}
+
+fn fs_info_valid(fsi: &[u8]) -> bool {
+    get32(fsi, 0) == FSI_LEAD_SIG && get32(fsi, 484) == FSI_STRUC_SIG
+}
+
+/// Extend `clusters` to `want` clusters.  New clusters are not zeroed.
+async fn grow_chain(
+    vol:      &FatVol,
+    inbox:    &BlkInbox,
+    alloc:    &mut Alloc,
+    clusters: &mut Vec<u32>,
+    want:     usize,
+) -> Result<(), FatError> {
+    while clusters.len() < want {
+        let cluster = alloc.alloc_cluster(vol, inbox).await?;
+        if let Some(&last) = clusters.last() {
+            write_fat(vol, inbox, last, cluster).await?;
+        }
+        clusters.push(cluster);
+    }
+    Ok(())
+}
+
+/// Cut `clusters` to its first `keep` clusters, freeing the rest.
+async fn shrink_chain(
+    vol:      &FatVol,
+    inbox:    &BlkInbox,
+    alloc:    &mut Alloc,
+    clusters: &mut Vec<u32>,
+    keep:     usize,
+) -> Result<(), FatError> {
+    if keep >= clusters.len() {
+        return Ok(());
+    }
+    if keep > 0 {
+        write_fat(vol, inbox, clusters[keep - 1], vol.eoc()).await?;
+    }
+    let freed = clusters.split_off(keep);
+    alloc.free_clusters(vol, inbox, &freed).await
+}
+
+/// Clear the clean-shutdown bit for the duration of an update, so an
+/// interrupted one leaves the volume marked for checking.  Returns whether
+/// the volume was clean before (always false on FAT12, which has no bit).
+async fn begin_update(vol: &FatVol, inbox: &BlkInbox) -> Result<bool, FatError> {
+    let bit = match vol.clean_bit() {
+        Some(bit) => bit,
+        None => return Ok(false),
+    };
+    let v = read_fat(vol, inbox, 1).await?;
+    if v & bit == 0 {
+        return Ok(false);
+    }
+    write_fat(vol, inbox, 1, v & !bit).await?;
+    Ok(true)
+}
+
+/// Record the free-cluster change in the FSInfo sector, and set the clean
+/// bit again unless the volume was not clean before or the update failed
+/// on a device error.
+async fn finish_update<T>(
+    vol:       &FatVol,
+    inbox:     &BlkInbox,
+    was_clean: bool,
+    alloc:     Alloc,
+    result:    Result<T, FatError>,
+) -> Result<T, FatError> {
+    if let Some(lba) = vol.fs_info_lba {
+        if alloc.delta != 0 || alloc.allocated {
+            let mut fsi = read_sector(inbox, lba).await?;
+            if fs_info_valid(&fsi) {
+                let free = get32(&fsi, FSI_FREE_COUNT);
+                if free != FSI_UNKNOWN {
+                    let free = (free as i64 + alloc.delta).max(0).min(vol.cluster_count as i64);
+                    put32(&mut fsi, FSI_FREE_COUNT, free as u32);
+                }
+                if alloc.allocated {
+                    put32(&mut fsi, FSI_NXT_FREE, alloc.hint);
+                }
+                write_sector(inbox, lba, fsi).await?;
+            }
+        }
+    }
+
+    let io_failed = matches!(result, Err(FatError::IoError) | Err(FatError::NoDevice));
+    if let Some(bit) = vol.clean_bit() {
+        if was_clean && !io_failed {
+            let v = read_fat(vol, inbox, 1).await?;
+            write_fat(vol, inbox, 1, v | bit).await?;
+        }
+    }
+    result
+}
+
+// ---------------------------------------------------------------------------
+// File data
+
+/// Read up to `len` bytes at `offset` of a file of `size` bytes starting at
+/// cluster `first`.
+async fn read_range(
+    vol:    &FatVol,
+    inbox:  &BlkInbox,
+    first:  u32,
+    size:   u64,
+    offset: u64,
+    len:    usize,
+) -> Result<Vec<u8>, FatError> {
+    if offset >= size || len == 0 {
+        return Ok(Vec::new());
+    }
+    let end = size.min(offset.saturating_add(len as u64));
+    let cb = vol.cluster_bytes();
+
+    // Follow the chain to the cluster holding `offset`.
+    let mut cluster = first;
+    for _ in 0..offset / cb {
+        cluster = read_fat(vol, inbox, cluster).await?;
+        if !vol.is_valid_cluster(cluster) {
+            return Err(FatError::Corrupt);
+        }
+    }
+
+    let mut out = Vec::with_capacity((end - offset) as usize);
+    let mut pos = offset;
+    loop {
+        if !vol.is_valid_cluster(cluster) {
+            return Err(FatError::Corrupt);
+        }
+        let lba = vol.cluster_lba(cluster);
+        let stop = end.min((pos / cb + 1) * cb);
+        while pos < stop {
+            let sector = read_data_sector(inbox, lba + pos % cb / SECTOR_SIZE).await?;
+            let lo = (pos % SECTOR_SIZE) as usize;
+            let hi = (lo as u64 + (stop - pos)).min(SECTOR_SIZE) as usize;
+            out.extend_from_slice(&sector[lo..hi]);
+            pos += (hi - lo) as u64;
+        }
+        if pos >= end {
+            return Ok(out);
+        }
+        cluster = read_fat(vol, inbox, cluster).await?;
+    }
+}
+
+/// Write `data` at byte `offset` of the file stored in `clusters`, which
+/// must cover it.  Partly covered sectors are read, modified and written.
+async fn write_range(
+    vol:      &FatVol,
+    inbox:    &BlkInbox,
+    clusters: &[u32],
+    offset:   u64,
+    data:     &[u8],
+) -> Result<(), FatError> {
+    let cb = vol.cluster_bytes();
+    let mut done = 0usize;
+    while done < data.len() {
+        let pos = offset + done as u64;
+        let lba = vol.cluster_lba(clusters[(pos / cb) as usize]) + pos % cb / SECTOR_SIZE;
+        let in_sector = (pos % SECTOR_SIZE) as usize;
+        let n = (SECTOR_SIZE as usize - in_sector).min(data.len() - done);
+        let mut sector = if n == SECTOR_SIZE as usize {
+            vec![0u8; SECTOR_SIZE as usize]
+        } else {
+            read_data_sector(inbox, lba).await?
+        };
+        sector[in_sector..in_sector + n].copy_from_slice(&data[done..done + n]);
+        write_sector(inbox, lba, sector).await?;
+        done += n;
+    }
+    Ok(())
+}
+
+/// Zero bytes `from..to` of the file stored in `clusters`.  FAT files
+/// have no holes, so space a file grows into is zeroed explicitly.
+async fn zero_range(
+    vol:      &FatVol,
+    inbox:    &BlkInbox,
+    clusters: &[u32],
+    from:     u64,
+    to:       u64,
+) -> Result<(), FatError> {
+    const CHUNK: u64 = 4096;
+    let zeros = [0u8; CHUNK as usize];
+    let mut pos = from;
+    while pos < to {
+        let n = (to - pos).min(CHUNK);
+        write_range(vol, inbox, clusters, pos, &zeros[..n as usize]).await?;
+        pos += n;
+    }
+    Ok(())
+}
+
+/// Grow or shrink a file's chain to hold `size` bytes, zeroing what lies
+/// between the old size and the new one.  `clusters` is updated in place,
+/// also when an error stops the change part-way.
+async fn resize_chain(
+    vol:      &FatVol,
+    inbox:    &BlkInbox,
+    alloc:    &mut Alloc,
+    clusters: &mut Vec<u32>,
+    old_size: u64,
+    size:     u64,
+) -> Result<(), FatError> {
+    let cb = vol.cluster_bytes();
+    let old_alloc = clusters.len() as u64 * cb;
+    let want = vol.clusters_for(size);
+    if want < clusters.len() {
+        return shrink_chain(vol, inbox, alloc, clusters, want).await;
+    }
+    grow_chain(vol, inbox, alloc, clusters, want).await?;
+    if size > old_size {
+        zero_range(vol, inbox, clusters, old_size.min(old_alloc), size).await?;
+    }
+    Ok(())
+}
+
+// ---------------------------------------------------------------------------
+// Directories
+
+/// An entry parsed from a directory.
+struct Entry {
+    /// The long name, or the 8.3 name if there is none.
+    name:       String,
+    short:      [u8; 11],
+    attr:       u8,
+    cluster:    u32,
+    size:       u32,
+    /// Last write time, nanoseconds since the Unix epoch.
+    mtime:      u64,
+    /// First slot of the entry, its long-name entries included.
+    first_slot: usize,
+    /// Slot of the 8.3 entry.
+    slot:       usize,
+}
+
+impl Entry {
+    fn is_dir(&self) -> bool {
+        self.attr & ATTR_DIRECTORY != 0
+    }
+
+    fn is_dot(&self) -> bool {
+        self.short == DOT || self.short == DOTDOT
+    }
+}
+
+/// A directory loaded for lookup or modification.
+struct Dir {
+    /// First cluster; 0 for the fixed FAT12/16 root.
+    cluster: u32,
+    /// Absolute LBA of each sector, in order.
+    sectors: Vec<u64>,
+    data:    Vec<u8>,
+}
+
+impl Dir {
+    /// Byte address of entry slot `slot`.
+    fn slot_addr(&self, slot: usize) -> u64 {
+        self.sectors[slot / ENTRIES_PER_SECTOR] * SECTOR_SIZE + ((slot % ENTRIES_PER_SECTOR) * ENTRY_SIZE) as u64
+    }
+
+    fn slot_mut(&mut self, slot: usize) -> &mut [u8] {
+        &mut self.data[slot * ENTRY_SIZE..(slot + 1) * ENTRY_SIZE]
+    }
+
+    fn find(&self, vol: &FatVol, name: &str) -> Option<Entry> {
+        parse_dir(vol, &self.data).into_iter().find(|e| {
+            !e.is_dot()
+                && (e.name.eq_ignore_ascii_case(name) || short_display(&e.short, 0).eq_ignore_ascii_case(name))
+        })
+    }
+
+    fn is_empty(&self, vol: &FatVol) -> bool {
+        parse_dir(vol, &self.data).iter().all(Entry::is_dot)
+    }
+
+    /// Write the sectors holding slots `first..=last`.
+    async fn write_slots(&self, inbox: &BlkInbox, first: usize, last: usize) -> Result<(), FatError> {
+        for s in first / ENTRIES_PER_SECTOR..=last / ENTRIES_PER_SECTOR {
+            let bytes = self.data[s * SECTOR_SIZE as usize..(s + 1) * SECTOR_SIZE as usize].to_vec();
+            write_sector(inbox, self.sectors[s], bytes).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Load the directory starting at `cluster`; 0 is the root.
+async fn load_dir(vol: &FatVol, inbox: &BlkInbox, cluster: u32) -> Result<Dir, FatError> {
+    let cluster = if cluster == 0 { vol.root_cluster } else { cluster };
+    let sectors: Vec<u64> = if cluster == 0 {
+        (vol.root_lba..vol.root_lba + vol.root_sectors).collect()
+    } else {
+        chain(vol, inbox, cluster).await?
+            .into_iter()
+            .flat_map(|c| {
+                let lba = vol.cluster_lba(c);
+                lba..lba + vol.sectors_per_cluster
+            })
+            .collect()
+    };
+    let mut data = Vec::with_capacity(sectors.len() * SECTOR_SIZE as usize);
+    for &lba in &sectors {
+        data.extend_from_slice(&read_sector(inbox, lba).await?);
+    }
+    Ok(Dir { cluster, sectors, data })
+}
+
+/// Long-name entries collected so far for the next 8.3 entry.
+struct LongName {
+    first_slot: usize,
+    checksum:   u8,
+    /// Ordinal of the entry expected next; 0 once all have been seen.
+    next:       u8,
+    units:      Vec<u16>,
+}
+
+fn parse_dir(vol: &FatVol, data: &[u8]) -> Vec<Entry> {
+    let mut entries = Vec::new();
+    let mut long: Option<LongName> = None;
+    for (slot, e) in data.chunks(ENTRY_SIZE).enumerate() {
+        match e[DIR_NAME] {
+            ENTRY_END => break,
+            ENTRY_DELETED => { long = None; continue; }
+            _ => {}
+        }
+        let attr = e[DIR_ATTR];
+        if attr & ATTR_LONG_NAME_MASK == ATTR_LONG_NAME {
+            let ord = e[DIR_NAME];
+            if ord & LAST_LONG_ENTRY != 0 {
+                let count = ord & !LAST_LONG_ENTRY;
+                if count == 0 || count as usize * CHARS_PER_LONG_ENTRY > MAX_NAME_UNITS + CHARS_PER_LONG_ENTRY {
+                    long = None;
+                    continue;
+                }
+                long = Some(LongName {
+                    first_slot: slot,
+                    checksum:   e[LDIR_CHKSUM],
+                    next:       count,
+                    units:      vec![0xFFFF; count as usize * CHARS_PER_LONG_ENTRY],
+                });
+            }
+            let in_sequence = match &mut long {
+                Some(l) if l.next != 0 && ord & !LAST_LONG_ENTRY == l.next && e[LDIR_CHKSUM] == l.checksum => {
+                    let base = (l.next as usize - 1) * CHARS_PER_LONG_ENTRY;
+                    for (k, &off) in LDIR_CHARS.iter().enumerate() {
+                        l.units[base + k] = get16(e, off);
+                    }
+                    l.next -= 1;
+                    true
+                }
+                _ => false,
+            };
+            if !in_sequence {
+                long = None;
+            }
+            continue;
+        }
+
+        let pending = long.take();
+        if attr & ATTR_VOLUME_ID != 0 {
+            continue;
+        }
+        let short: [u8; 11] = e[DIR_NAME..DIR_NAME + 11].try_into().unwrap();
+        let (name, first_slot) = match pending {
+            Some(l) if l.next == 0 && l.checksum == short_checksum(&short) => {
+                (decode_long_name(&l.units), l.first_slot)
+            }
+            _ => (short_display(&short, e[DIR_NTRES]), slot),
+        };
+        let hi = if vol.fat_type == FatType::Fat32 { get16(e, DIR_FST_CLUS_HI) as u32 } else { 0 };
+        entries.push(Entry {
+            name,
+            short,
+            attr,
+            cluster: hi << 16 | get16(e, DIR_FST_CLUS_LO) as u32,
+            size: get32(e, DIR_FILE_SIZE),
+            mtime: decode_timestamp(get16(e, DIR_WRT_DATE), get16(e, DIR_WRT_TIME)),
+            first_slot,
+            slot,
+        });
+    }
+    entries
+}
+
+fn decode_long_name(units: &[u16]) -> String {
+    let len = units.iter().position(|&u| u == 0).unwrap_or(units.len());
+    core::char::decode_utf16(units[..len].iter().copied())
+        .map(|r| r.unwrap_or(core::char::REPLACEMENT_CHARACTER))
+        .collect()
+}
+
+/// The 8.3 name as shown: `BASE.EXT` without padding, each part lower-cased
+/// if `DIR_NTRes` says so.
+fn short_display(short: &[u8; 11], ntres: u8) -> String {
+    let part = |bytes: &[u8], lower: bool| -> String {
+        let len = bytes.iter().rposition(|&b| b != b' ').map_or(0, |i| i + 1);
+        bytes[..len].iter().map(|&b| {
+            let c = b as char;
+            if lower { c.to_ascii_lowercase() } else { c }
+        }).collect()
+    };
+    let mut base = *short;
+    if base[0] == ENTRY_KANJI_E5 {
+        base[0] = ENTRY_DELETED;
+    }
+    let mut name = part(&base[..8], ntres & NTRES_LOWER_BASE != 0);
+    let ext = part(&base[8..], ntres & NTRES_LOWER_EXT != 0);
+    if !ext.is_empty() {
+        name.push('.');
+        name.push_str(&ext);
+    }
+    name
+}
+
+/// Checksum of an 8.3 name, stored in each of its long-name entries.
+fn short_checksum(short: &[u8; 11]) -> u8 {
+    short.iter().fold(0u8, |sum, &b| sum.rotate_right(1).wrapping_add(b))
+}
+
+fn short_char_ok(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || SHORT_NAME_PUNCT.contains(&b)
+}
+
+/// The 8.3 name and `DIR_NTRes` case bits for `name`, if it can be stored
+/// without a long name: at most 8.3 characters, each part in one case.
+fn exact_short_name(name: &str) -> Option<([u8; 11], u8)> {
+    let (base, ext) = match name.rfind('.') {
+        Some(i) => (&name[..i], &name[i + 1..]),
+        None    => (name, ""),
+    };
+    if base.is_empty() || base.len() > 8 || ext.len() > 3 || name.ends_with('.') {
+        return None;
+    }
+    if !base.bytes().chain(ext.bytes()).all(short_char_ok) {
+        return None;
+    }
+    let case = |part: &str, flag: u8| -> Option<u8> {
+        let lower = part.bytes().any(|b| b.is_ascii_lowercase());
+        let upper = part.bytes().any(|b| b.is_ascii_uppercase());
+        match (lower, upper) {
+            (true, true)  => None,
+            (true, false) => Some(flag),
+            _             => Some(0),
+        }
+    };
+    let ntres = case(base, NTRES_LOWER_BASE)? | case(ext, NTRES_LOWER_EXT)?;
+    let mut short = [b' '; 11];
+    for (i, b) in base.bytes().enumerate() {
+        short[i] = b.to_ascii_uppercase();
+    }
+    for (i, b) in ext.bytes().enumerate() {
+        short[8 + i] = b.to_ascii_uppercase();
+    }
+    Some((short, ntres))
+}
+
+/// A `BASIS~N.EXT` 8.3 name for a name that needs a long name, unique in
+/// the directory.
+fn generate_short_name(vol: &FatVol, dir: &Dir, name: &str) -> Result<[u8; 11], FatError> {
+    let clean = |s: &str| -> Vec<u8> {
+        s.chars()
+            .filter(|&c| c != ' ' && c != '.')
+            .map(|c| {
+                let b = if c.is_ascii() { (c as u8).to_ascii_uppercase() } else { b'_' };
+                if short_char_ok(b) { b } else { b'_' }
+            })
+            .collect()
+    };
+    let trimmed = name.trim_start_matches('.');
+    let (base, ext) = match trimmed.rfind('.') {
+        Some(i) => (clean(&trimmed[..i]), clean(&trimmed[i + 1..])),
+        None    => (clean(trimmed), Vec::new()),
+    };
+
+    let taken: Vec<[u8; 11]> = parse_dir(vol, &dir.data).into_iter().map(|e| e.short).collect();
+    let mut short = [b' '; 11];
+    for (i, &b) in ext.iter().take(3).enumerate() {
+        short[8 + i] = b;
+    }
+    for n in 1..1_000_000u32 {
+        let tail = format!("~{}", n);
+        let keep = base.len().min(8 - tail.len());
+        short[..8].copy_from_slice(b"        ");
+        short[..keep].copy_from_slice(&base[..keep]);
+        short[keep..keep + tail.len()].copy_from_slice(tail.as_bytes());
+        if !taken.contains(&short) {
+            return Ok(short);
+        }
+    }
+    Err(FatError::NoSpace)
+}
+
+fn validate_name(name: &str) -> Result<(), FatError> {
+    if name.is_empty()
+        || name == "."
+        || name == ".."
+        || name.encode_utf16().count() > MAX_NAME_UNITS
+        || name.chars().any(|c| (c as u32) < 0x20 || LONG_NAME_FORBIDDEN.contains(c))
+    {
+        return Err(FatError::InvalidName);
+    }
+    Ok(())
+}
+
+/// The current time as a FAT date, time and 10 ms count (0..200), in UTC.
+fn timestamp_now() -> (u16, u16, u8) {
+    use libkernel::time::{self, rtc::RtcTime, NSEC_PER_SEC};
+
+    let ns = time::realtime_ns();
+    let t = RtcTime::from_unix_seconds(ns / NSEC_PER_SEC);
+    // Date: bits 9..16 year-1980, 5..9 month, 0..5 day
+    // Time: bits 11..16 hour, 5..11 minute, 0..5 second/2
+    let year = t.year.max(1980).min(2107) - 1980;
+    let date = (year << 9 | t.month << 5 | t.day) as u16;
+    let time = (t.hour << 11 | t.minute << 5 | t.second / 2) as u16;
+    let tenth = (t.second % 2) * 100 + ((ns % NSEC_PER_SEC) / 10_000_000) as u32;
+    (date, time, tenth as u8)
+}
+
+/// A FAT date and time (UTC) as nanoseconds since the Unix epoch; 0 for
+/// an unset or invalid date.
+fn decode_timestamp(date: u16, time: u16) -> u64 {
+    use libkernel::time::{rtc::RtcTime, NSEC_PER_SEC};
+
+    let (month, day) = ((date >> 5 & 0xf) as u32, (date & 0x1f) as u32);
+    if !(1..=12).contains(&month) || day == 0 {
+        return 0;
+    }
+    let t = RtcTime {
+        year:   (date >> 9) as u32 + 1980,
+        month,
+        day,
+        hour:   (time >> 11) as u32,
+        minute: (time >> 5 & 0x3f) as u32,
+        second: (time & 0x1f) as u32 * 2,
+    };
+    t.unix_seconds() * NSEC_PER_SEC
+}
+
+fn set_cluster(entry: &mut [u8], cluster: u32) {
+    put16(entry, DIR_FST_CLUS_HI, (cluster >> 16) as u16);
+    put16(entry, DIR_FST_CLUS_LO, cluster as u16);
+}
+
+/// Record a modification now: write time and date, access date.
+fn touch(entry: &mut [u8]) {
+    let (date, time, _) = timestamp_now();
+    put16(entry, DIR_WRT_TIME, time);
+    put16(entry, DIR_WRT_DATE, date);
+    put16(entry, DIR_LST_ACC_DATE, date);
+}
+
+/// A new 8.3 entry stamped with the current time.
+fn short_entry(short: &[u8; 11], ntres: u8, attr: u8, cluster: u32) -> [u8; ENTRY_SIZE] {
+    let mut e = [0u8; ENTRY_SIZE];
+    e[DIR_NAME..DIR_NAME + 11].copy_from_slice(short);
+    e[DIR_ATTR] = attr;
+    e[DIR_NTRES] = ntres;
+    let (date, time, tenth) = timestamp_now();
+    e[DIR_CRT_TIME_TENTH] = tenth;
+    put16(&mut e, DIR_CRT_TIME, time);
+    put16(&mut e, DIR_CRT_DATE, date);
+    set_cluster(&mut e, cluster);
+    touch(&mut e);
+    e
+}
+
+/// Long-name entries for `name`, last part first, as stored on disk.
+fn long_entries(name: &str, short: &[u8; 11]) -> Vec<u8> {
+    let units: Vec<u16> = name.encode_utf16().collect();
+    let count = (units.len() + CHARS_PER_LONG_ENTRY - 1) / CHARS_PER_LONG_ENTRY;
+    let checksum = short_checksum(short);
+    let mut set = Vec::with_capacity(count * ENTRY_SIZE);
+    for ord in (1..=count).rev() {
+        let mut e = [0u8; ENTRY_SIZE];
+        e[DIR_NAME] = ord as u8 | if ord == count { LAST_LONG_ENTRY } else { 0 };
+        e[DIR_ATTR] = ATTR_LONG_NAME;
+        e[LDIR_CHKSUM] = checksum;
+        for (k, &off) in LDIR_CHARS.iter().enumerate() {
+            let i = (ord - 1) * CHARS_PER_LONG_ENTRY + k;
+            // The name is NUL-terminated if it does not fill the entry,
+            // then padded with 0xFFFF.
+            let unit = match i.cmp(&units.len()) {
+                core::cmp::Ordering::Less    => units[i],
+                core::cmp::Ordering::Equal   => 0,
+                core::cmp::Ordering::Greater => 0xFFFF,
+            };
+            put16(&mut e, off, unit);
+        }
+        set.extend_from_slice(&e);
+    }
+    set
+}
+
+/// The entries naming a file `name` whose 8.3 entry is `short_entry`
+/// (name field aside): long-name entries if needed, then the 8.3 entry.
+fn name_entries(vol: &FatVol, dir: &Dir, name: &str, mut entry: [u8; ENTRY_SIZE]) -> Result<Vec<u8>, FatError> {
+    let (short, ntres, long) = match exact_short_name(name) {
+        Some((short, ntres)) => (short, ntres, false),
+        None => (generate_short_name(vol, dir, name)?, 0, true),
+    };
+    entry[DIR_NAME..DIR_NAME + 11].copy_from_slice(&short);
+    entry[DIR_NTRES] = (entry[DIR_NTRES] & !(NTRES_LOWER_BASE | NTRES_LOWER_EXT)) | ntres;
+    let mut set = if long { long_entries(name, &short) } else { Vec::new() };
+    set.extend_from_slice(&entry);
+    Ok(set)
+}
+
+/// First slot of a run of `count` free slots.  Everything after the end
+/// marker is free.
+fn find_free_run(data: &[u8], count: usize) -> Option<usize> {
+    let mut run = 0;
+    for (i, e) in data.chunks(ENTRY_SIZE).enumerate() {
+        if e[DIR_NAME] == ENTRY_END {
+            let left = data.len() / ENTRY_SIZE - i;
+            return if run + left >= count { Some(i - run) } else { None };
+        }
+        if e[DIR_NAME] == ENTRY_DELETED {
+            run += 1;
+            if run == count {
+                return Some(i + 1 - count);
+            }
+        } else {
+            run = 0;
+        }
+    }
+    None
+}
+
+/// Add a zeroed cluster to a directory.  The fixed root cannot grow.
+async fn grow_dir(vol: &FatVol, inbox: &BlkInbox, alloc: &mut Alloc, dir: &mut Dir) -> Result<(), FatError> {
+    if dir.cluster == 0 || dir.data.len() / ENTRY_SIZE >= MAX_DIR_ENTRIES {
+        return Err(FatError::NoSpace);
+    }
+    let mut clusters = chain(vol, inbox, dir.cluster).await?;
+    let want = clusters.len() + 1;
+    grow_chain(vol, inbox, alloc, &mut clusters, want).await?;
+    let lba = vol.cluster_lba(*clusters.last().unwrap());
+    for i in 0..vol.sectors_per_cluster {
+        write_sector(inbox, lba + i, vec![0u8; SECTOR_SIZE as usize]).await?;
+        dir.sectors.push(lba + i);
+    }
+    dir.data.resize(dir.data.len() + vol.cluster_bytes() as usize, 0);
+    Ok(())
+}
+
+/// Store `set` in the first free run of slots, growing the directory if
+/// needed.  Returns the slot of the 8.3 entry.
+async fn insert_entries(
+    vol:   &FatVol,
+    inbox: &BlkInbox,
+    alloc: &mut Alloc,
+    dir:   &mut Dir,
+    set:   &[u8],
+) -> Result<usize, FatError> {
+    let count = set.len() / ENTRY_SIZE;
+    let slot = loop {
+        if let Some(slot) = find_free_run(&dir.data, count) {
+            break slot;
+        }
+        grow_dir(vol, inbox, alloc, dir).await?;
+    };
+    dir.data[slot * ENTRY_SIZE..slot * ENTRY_SIZE + set.len()].copy_from_slice(set);
+    dir.write_slots(inbox, slot, slot + count - 1).await?;
+    Ok(slot + count - 1)
+}
+
+/// Mark an entry, long-name entries included, deleted.
+async fn remove_entries(inbox: &BlkInbox, dir: &mut Dir, entry: &Entry) -> Result<(), FatError> {
+    for slot in entry.first_slot..=entry.slot {
+        dir.slot_mut(slot)[DIR_NAME] = ENTRY_DELETED;
+    }
+    dir.write_slots(inbox, entry.first_slot, entry.slot).await
+}
+
+// ---------------------------------------------------------------------------
+// Path traversal
+
+/// Walk to the directory at `path` (e.g. `"/"`, `"/EFI/BOOT"`).  Names are
+/// matched case-insensitively (ASCII), against long and 8.3 names.
+async fn walk_dir(vol: &FatVol, inbox: &BlkInbox, path: &str) -> Result<Dir, FatError> {
+    let mut dir = load_dir(vol, inbox, 0).await?;
+    for component in path.split('/').filter(|s| !s.is_empty()) {
+        let e = dir.find(vol, component).ok_or(FatError::PathNotFound)?;
+        if !e.is_dir() {
+            return Err(FatError::NotADirectory);
+        }
+        dir = load_dir(vol, inbox, e.cluster).await?;
+    }
+    Ok(dir)
+}
+
+fn split_path(path: &str) -> Result<(&str, &str), FatError> {
+    let trimmed = path.trim_end_matches('/');
+    let (parent, name) = match trimmed.rfind('/') {
+        Some(i) => (&trimmed[..i], &trimmed[i + 1..]),
+        None    => ("", trimmed),
+    };
+    if name.is_empty() {
+        return Err(FatError::InvalidName);
+    }
+    Ok((parent, name))
+}
+
+/// The parent directory of `path` and the entry `path` names in it.
+async fn lookup(vol: &FatVol, inbox: &BlkInbox, path: &str) -> Result<(Dir, Entry), FatError> {
+    let (parent, name) = split_path(path)?;
+    let dir = walk_dir(vol, inbox, parent).await?;
+    let entry = dir.find(vol, name).ok_or(FatError::PathNotFound)?;
+    Ok((dir, entry))
+}
+
+fn is_root(path: &str) -> bool {
+    path.split('/').all(|s| s.is_empty())
+}
+
+// ---------------------------------------------------------------------------
+// Updates
+
+async fn create_entry(
+    vol:    &FatVol,
+    inbox:  &BlkInbox,
+    alloc:  &mut Alloc,
+    path:   &str,
+    is_dir: bool,
+) -> Result<(), FatError> {
+    let (parent, name) = split_path(path)?;
+    validate_name(name)?;
+    let mut dir = walk_dir(vol, inbox, parent).await?;
+    if dir.find(vol, name).is_some() {
+        return Err(FatError::AlreadyExists);
+    }
+
+    let mut cluster = 0;
+    if is_dir {
+        cluster = alloc.alloc_cluster(vol, inbox).await?;
+        let mut data = vec![0u8; vol.cluster_bytes() as usize];
+        data[..ENTRY_SIZE].copy_from_slice(&short_entry(&DOT, 0, ATTR_DIRECTORY, cluster));
+        data[ENTRY_SIZE..2 * ENTRY_SIZE].copy_from_slice(
+            &short_entry(&DOTDOT, 0, ATTR_DIRECTORY, vol.dotdot_ref(dir.cluster)));
+        write_range(vol, inbox, &[cluster], 0, &data).await?;
+    }
+
+    let attr = if is_dir { ATTR_DIRECTORY } else { ATTR_ARCHIVE };
+    let set = name_entries(vol, &dir, name, short_entry(&[b' '; 11], 0, attr, cluster))?;
+    insert_entries(vol, inbox, alloc, &mut dir, &set).await?;
+    Ok(())
+}
+
+/// Store a file's new first cluster and size in its 8.3 entry.
+async fn update_entry(
+    inbox:    &BlkInbox,
+    dir:      &mut Dir,
+    entry:    &Entry,
+    clusters: &[u32],
+    size:     u64,
+) -> Result<(), FatError> {
+    let e = dir.slot_mut(entry.slot);
+    set_cluster(e, clusters.first().copied().unwrap_or(0));
+    put32(e, DIR_FILE_SIZE, size as u32);
+    e[DIR_ATTR] |= ATTR_ARCHIVE;
+    touch(e);
+    dir.write_slots(inbox, entry.slot, entry.slot).await
+}
+
+async fn write_entry(
+    vol:    &FatVol,
+    inbox:  &BlkInbox,
+    alloc:  &mut Alloc,
+    path:   &str,
+    offset: u64,
+    data:   &[u8],
+) -> Result<usize, FatError> {
+    let (mut dir, entry) = lookup(vol, inbox, path).await?;
+    if entry.is_dir() {
+        return Err(FatError::NotAFile);
+    }
+    let end = offset.checked_add(data.len() as u64).ok_or(FatError::FileTooLarge)?;
+    if end > u32::MAX as u64 {
+        return Err(FatError::FileTooLarge);
+    }
+    if data.is_empty() {
+        return Ok(0);
+    }
+
+    let old_size = entry.size as u64;
+    let mut clusters = chain(vol, inbox, entry.cluster).await?;
+    let mut result = Ok(data.len());
+    if end > old_size {
+        // Zero the gap before `offset`, and the new clusters' slack.
+        let old_alloc = clusters.len() as u64 * vol.cluster_bytes();
+        if let Err(e) = resize_chain(vol, inbox, alloc, &mut clusters, old_size, old_size.max(offset)).await {
+            result = Err(e);
+        } else if let Err(e) = grow_chain(vol, inbox, alloc, &mut clusters, vol.clusters_for(end)).await {
+            result = Err(e);
+        } else {
+            let slack_from = end.max(old_alloc);
+            let slack_to = clusters.len() as u64 * vol.cluster_bytes();
+            if let Err(e) = zero_range(vol, inbox, &clusters, slack_from, slack_to).await {
+                result = Err(e);
+            }
+        }
+    }
+    if result.is_ok() {
+        if let Err(e) = write_range(vol, inbox, &clusters, offset, data).await {
+            result = Err(e);
+        }
+    }
+
+    // Record the chain even after a failure, so allocated clusters are not
+    // lost; the size only grows if the data was written.
+    let size = if result.is_ok() { old_size.max(end) } else { old_size };
+    update_entry(inbox, &mut dir, &entry, &clusters, size).await?;
+    result
+}
+
+async fn truncate_entry(vol: &FatVol, inbox: &BlkInbox, alloc: &mut Alloc, path: &str, size: u64) -> Result<(), FatError> {
+    let (mut dir, entry) = lookup(vol, inbox, path).await?;
+    if entry.is_dir() {
+        return Err(FatError::NotAFile);
+    }
+    if size > u32::MAX as u64 {
+        return Err(FatError::FileTooLarge);
+    }
+    let old_size = entry.size as u64;
+    let mut clusters = chain(vol, inbox, entry.cluster).await?;
+    let result = resize_chain(vol, inbox, alloc, &mut clusters, old_size, size).await;
+    let size = if result.is_ok() { size } else { old_size.min(clusters.len() as u64 * vol.cluster_bytes()) };
+    update_entry(inbox, &mut dir, &entry, &clusters, size).await?;
+    result
+}
+
+async fn unlink_entry(vol: &FatVol, inbox: &BlkInbox, alloc: &mut Alloc, path: &str) -> Result<(), FatError> {
+    let (mut dir, entry) = lookup(vol, inbox, path).await?;
+    if entry.is_dir() {
+        return Err(FatError::NotAFile);
+    }
+    remove_entries(inbox, &mut dir, &entry).await?;
+    let clusters = chain(vol, inbox, entry.cluster).await?;
+    alloc.free_clusters(vol, inbox, &clusters).await
+}
+
+async fn rmdir_entry(vol: &FatVol, inbox: &BlkInbox, alloc: &mut Alloc, path: &str) -> Result<(), FatError> {
+    let (mut dir, entry) = lookup(vol, inbox, path).await?;
+    if !entry.is_dir() {
+        return Err(FatError::NotADirectory);
+    }
+    if !load_dir(vol, inbox, entry.cluster).await?.is_empty(vol) {
+        return Err(FatError::NotEmpty);
+    }
+    remove_entries(inbox, &mut dir, &entry).await?;
+    let clusters = chain(vol, inbox, entry.cluster).await?;
+    alloc.free_clusters(vol, inbox, &clusters).await
+}
+
+async fn rename_entry(vol: &FatVol, inbox: &BlkInbox, alloc: &mut Alloc, from: &str, to: &str) -> Result<(), FatError> {
+    let (src_dir, entry) = lookup(vol, inbox, from).await?;
+    let src_cluster = src_dir.cluster;
+    let src_addr = src_dir.slot_addr(entry.slot);
+    let raw: [u8; ENTRY_SIZE] = src_dir.data[entry.slot * ENTRY_SIZE..(entry.slot + 1) * ENTRY_SIZE]
+        .try_into().unwrap();
+    drop(src_dir);
+
+    let (dst_parent, dst_name) = split_path(to)?;
+    validate_name(dst_name)?;
+    let mut dst_dir = walk_dir(vol, inbox, dst_parent).await?;
+    if let Some(target) = dst_dir.find(vol, dst_name) {
+        if dst_dir.slot_addr(target.slot) == src_addr {
+            // The same file.  Only a change of case needs doing.
+            if target.name == dst_name {
+                return Ok(());
+            }
+        } else {
+            match (entry.is_dir(), target.is_dir()) {
+                (true, true) => {
+                    if !load_dir(vol, inbox, target.cluster).await?.is_empty(vol) {
+                        return Err(FatError::NotEmpty);
+                    }
+                }
+                (true, false) => return Err(FatError::NotADirectory),
+                (false, true) => return Err(FatError::NotAFile),
+                (false, false) => {}
+            }
+            remove_entries(inbox, &mut dst_dir, &target).await?;
+            let clusters = chain(vol, inbox, target.cluster).await?;
+            alloc.free_clusters(vol, inbox, &clusters).await?;
+        }
+    }
+
+    let set = name_entries(vol, &dst_dir, dst_name, raw)?;
+    insert_entries(vol, inbox, alloc, &mut dst_dir, &set).await?;
+    let dst_cluster = dst_dir.cluster;
+    drop(dst_dir);
+
+    // Remove the old name; the source directory is reloaded, since it may
+    // be the destination directory just changed.  Slots in use never move.
+    let mut src_dir = load_dir(vol, inbox, src_cluster).await?;
+    remove_entries(inbox, &mut src_dir, &entry).await?;
+
+    if entry.is_dir() && src_cluster != dst_cluster {
+        let mut moved = load_dir(vol, inbox, entry.cluster).await?;
+        if let Some(dotdot) = parse_dir(vol, &moved.data).into_iter().find(|e| e.short == DOTDOT) {
+            set_cluster(moved.slot_mut(dotdot.slot), vol.dotdot_ref(dst_cluster));
+            moved.write_slots(inbox, dotdot.slot, dotdot.slot).await?;
+        }
+    }
+    Ok(())
+}
+
+// ---------------------------------------------------------------------------
+// Public API
+
+/// List the directory at `path`.  Use `"/"` for the root.
+pub async fn list_dir(vol: &FatVol, inbox: &BlkInbox, path: &str) -> Result<Vec<DirEntry>, FatError> {
+    let dir = walk_dir(vol, inbox, path).await?;
+    Ok(parse_dir(vol, &dir.data).into_iter().filter(|e| !e.is_dot()).map(|e| DirEntry {
+        is_dir: e.is_dir(),
+        size:   if e.is_dir() { 0 } else { e.size as u64 },
+        ino:    dir.slot_addr(e.slot),
+        mtime:  e.mtime,
+        name:   e.name,
+    }).collect())
+}
+
+/// Look up the file or directory at `path`.
+pub async fn stat(vol: &FatVol, inbox: &BlkInbox, path: &str) -> Result<DirEntry, FatError> {
+    if is_root(path) {
+        return Ok(DirEntry { name: String::from("/"), is_dir: true, size: 0, ino: ROOT_INO, mtime: 0 });
+    }
+    let (dir, e) = lookup(vol, inbox, path).await?;
+    Ok(DirEntry {
+        is_dir: e.is_dir(),
+        size:   if e.is_dir() { 0 } else { e.size as u64 },
+        ino:    dir.slot_addr(e.slot),
+        mtime:  e.mtime,
+        name:   e.name,
+    })
+}
+
+/// Read a whole file into memory.
+pub async fn read_file(vol: &FatVol, inbox: &BlkInbox, path: &str) -> Result<Vec<u8>, FatError> {
+    let (_, e) = lookup(vol, inbox, path).await?;
+    if e.is_dir() {
+        return Err(FatError::NotAFile);
+    }
+    let size = usize::try_from(e.size).map_err(|_| FatError::FileTooLarge)?;
+    read_range(vol, inbox, e.cluster, e.size as u64, 0, size).await
+}
+
+/// Read up to `len` bytes at byte `offset` of the file at `path`.  Returns
+/// fewer bytes at end of file, and none past it.
+pub async fn read_at(
+    vol:    &FatVol,
+    inbox:  &BlkInbox,
+    path:   &str,
+    offset: u64,
+    len:    usize,
+) -> Result<Vec<u8>, FatError> {
+    let (_, e) = lookup(vol, inbox, path).await?;
+    if e.is_dir() {
+        return Err(FatError::NotAFile);
+    }
+    read_range(vol, inbox, e.cluster, e.size as u64, offset, len).await
+}
+
+/// Create an empty file at `path`.
+pub async fn create(vol: &FatVol, inbox: &BlkInbox, path: &str) -> Result<(), FatError> {
+    let was_clean = begin_update(vol, inbox).await?;
+    let mut alloc = Alloc::new(vol, inbox).await?;
+    let result = create_entry(vol, inbox, &mut alloc, path, false).await;
+    finish_update(vol, inbox, was_clean, alloc, result).await
+}
+
+/// Create an empty directory at `path`.
+pub async fn mkdir(vol: &FatVol, inbox: &BlkInbox, path: &str) -> Result<(), FatError> {
+    let was_clean = begin_update(vol, inbox).await?;
+    let mut alloc = Alloc::new(vol, inbox).await?;
+    let result = create_entry(vol, inbox, &mut alloc, path, true).await;
+    finish_update(vol, inbox, was_clean, alloc, result).await
+}
+
+/// Write `data` at byte `offset` of the file at `path`, extending it if the
+/// write ends past its size.  A gap before `offset` is zero-filled.
+pub async fn write_at(
+    vol:    &FatVol,
+    inbox:  &BlkInbox,
+    path:   &str,
+    offset: u64,
+    data:   &[u8],
+) -> Result<usize, FatError> {
+    let was_clean = begin_update(vol, inbox).await?;
+    let mut alloc = Alloc::new(vol, inbox).await?;
+    let result = write_entry(vol, inbox, &mut alloc, path, offset, data).await;
+    finish_update(vol, inbox, was_clean, alloc, result).await
+}
+
+/// Set the size of the file at `path`; growing zero-fills.
+pub async fn truncate(vol: &FatVol, inbox: &BlkInbox, path: &str, size: u64) -> Result<(), FatError> {
+    let was_clean = begin_update(vol, inbox).await?;
+    let mut alloc = Alloc::new(vol, inbox).await?;
+    let result = truncate_entry(vol, inbox, &mut alloc, path, size).await;
+    finish_update(vol, inbox, was_clean, alloc, result).await
+}
+
+/// Delete the file at `path`.
+pub async fn unlink(vol: &FatVol, inbox: &BlkInbox, path: &str) -> Result<(), FatError> {
+    let was_clean = begin_update(vol, inbox).await?;
+    let mut alloc = Alloc::new(vol, inbox).await?;
+    let result = unlink_entry(vol, inbox, &mut alloc, path).await;
+    finish_update(vol, inbox, was_clean, alloc, result).await
+}
+
+/// Delete the empty directory at `path`.
+pub async fn rmdir(vol: &FatVol, inbox: &BlkInbox, path: &str) -> Result<(), FatError> {
+    let was_clean = begin_update(vol, inbox).await?;
+    let mut alloc = Alloc::new(vol, inbox).await?;
+    let result = rmdir_entry(vol, inbox, &mut alloc, path).await;
+    finish_update(vol, inbox, was_clean, alloc, result).await
+}
+
+/// Move `from` to `to`, replacing `to` if it exists and is compatible.
+pub async fn rename(vol: &FatVol, inbox: &BlkInbox, from: &str, to: &str) -> Result<(), FatError> {
+    let was_clean = begin_update(vol, inbox).await?;
+    let mut alloc = Alloc::new(vol, inbox).await?;
+    let result = rename_entry(vol, inbox, &mut alloc, from, to).await;
+    finish_update(vol, inbox, was_clean, alloc, result).await
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::super::ram_disk::RamDisk;
+    use libkernel::{serial_print, serial_println};
+
+    // A bare FAT12 volume of one-sector clusters: boot sector, two
+    // two-sector FATs, a one-sector root directory of 16 entries, and 600
+    // data clusters.
+    const FAT_SECTORS: u64 = 2;
+    const ROOT_LBA: u64 = 1 + 2 * FAT_SECTORS;
+    const CLUSTERS: u64 = 600;
+
+    fn boot_sector() -> Vec<u8> {
+        let mut boot = vec![0u8; SECTOR_SIZE as usize];
+        boot[..3].copy_from_slice(&[0xEB, 0x3C, 0x90]);
+        put16(&mut boot, BPB_BYTES_PER_SEC, SECTOR_SIZE as u16);
+        boot[BPB_SEC_PER_CLUS] = 1;
+        put16(&mut boot, BPB_RSVD_SEC_CNT, 1);
+        boot[BPB_NUM_FATS] = 2;
+        put16(&mut boot, BPB_ROOT_ENT_CNT, ENTRIES_PER_SECTOR as u16);
+        put16(&mut boot, BPB_TOT_SEC16, (ROOT_LBA + 1 + CLUSTERS) as u16);
+        boot[BPB_MEDIA] = 0xF8;
+        put16(&mut boot, BPB_FAT_SZ16, FAT_SECTORS as u16);
+        boot[510] = 0x55;
+        boot[511] = 0xAA;
+        boot
+    }
+
+    /// The test volume, formatted, with an empty root directory.
+    fn image() -> (RamDisk, FatVol) {
+        let disk = RamDisk::new(ROOT_LBA + 1 + CLUSTERS);
+        disk.put(0, &boot_sector());
+        for fat in 0..2 {
+            // FAT[0] holds the media byte, FAT[1] an end of chain.
+            disk.put((1 + fat * FAT_SECTORS) * SECTOR_SIZE, &[0xF8, 0xFF, 0xFF]);
+        }
+        let vol = disk.run(open_fat(&disk.inbox)).unwrap();
+        (disk, vol)
+    }
+
+    /// Both copies of the FAT, which must agree.
+    fn fat_bytes(disk: &RamDisk) -> Vec<u8> {
+        let len = (FAT_SECTORS * SECTOR_SIZE) as usize;
+        let fat = disk.get(SECTOR_SIZE, len);
+        assert_eq!(disk.get((1 + FAT_SECTORS) * SECTOR_SIZE, len), fat);
+        fat
+    }
+
+    #[test_case]
+    fn test_fat12_volume_geometry() {
+        serial_print!("test_fat12_volume_geometry... ");
+        let (_disk, vol) = image();
+        assert_eq!(vol.fat_type, FatType::Fat12);
+        assert_eq!((vol.fat_lba, vol.root_lba, vol.root_sectors), (1, ROOT_LBA, 1));
+        assert_eq!((vol.data_lba, vol.cluster_count), (ROOT_LBA + 1, CLUSTERS as u32));
+        assert_eq!(vol.cluster_lba(2), ROOT_LBA + 1);
+
+        let mut boot = boot_sector();
+        boot[BPB_MEDIA] = 0x12;
+        assert!(!is_fat_boot(&boot));
+        let mut boot = boot_sector();
+        put16(&mut boot, BPB_BYTES_PER_SEC, 4096);
+        assert!(matches!(parse_boot(&boot, 0), Err(FatError::Unsupported)));
+        let mut boot = boot_sector();
+        put16(&mut boot, BPB_TOT_SEC16, ROOT_LBA as u16);
+        assert!(matches!(parse_boot(&boot, 0), Err(FatError::Corrupt)));
+        serial_println!("[ok]");
+    }
+
+    #[test_case]
+    fn test_fat12_packed_entries() {
+        serial_print!("test_fat12_packed_entries... ");
+        let (disk, vol) = image();
+        let inbox = &disk.inbox;
+
+        // Clusters 2 and 3 share the middle byte of bytes 3..6.
+        disk.run(write_fat(&vol, inbox, 2, 0x123)).unwrap();
+        disk.run(write_fat(&vol, inbox, 3, 0xABC)).unwrap();
+        assert_eq!(fat_bytes(&disk)[..6], [0xF8, 0xFF, 0xFF, 0x23, 0xC1, 0xAB]);
+        assert_eq!(disk.run(read_fat(&vol, inbox, 2)).unwrap(), 0x123);
+        assert_eq!(disk.run(read_fat(&vol, inbox, 3)).unwrap(), 0xABC);
+        // Rewriting one keeps the other's nibble.
+        disk.run(write_fat(&vol, inbox, 2, 0xFFF)).unwrap();
+        assert_eq!(fat_bytes(&disk)[3..6], [0xFF, 0xCF, 0xAB]);
+        assert_eq!(disk.run(read_fat(&vol, inbox, 3)).unwrap(), 0xABC);
+
+        // Cluster 341's entry starts at byte 511 and ends in the FAT's
+        // second sector.
+        assert_eq!(vol.fat_offset(341), SECTOR_SIZE - 1);
+        disk.run(write_fat(&vol, inbox, 340, 0x789)).unwrap();
+        disk.run(write_fat(&vol, inbox, 341, 0x456)).unwrap();
+        assert_eq!(fat_bytes(&disk)[510..514], [0x89, 0x67, 0x45, 0x00]);
+        assert_eq!(disk.run(read_fat(&vol, inbox, 340)).unwrap(), 0x789);
+        assert_eq!(disk.run(read_fat(&vol, inbox, 341)).unwrap(), 0x456);
+        assert_eq!(disk.run(read_fat(&vol, inbox, 342)).unwrap(), 0);
+        // The reserved entries are untouched.
+        assert_eq!(disk.run(read_fat(&vol, inbox, 0)).unwrap(), 0xFF8);
+        assert_eq!(disk.run(read_fat(&vol, inbox, 1)).unwrap(), 0xFFF);
+        serial_println!("[ok]");
+    }
+
+    #[test_case]
+    fn test_fat_chain_growth() {
+        serial_print!("test_fat_chain_growth... ");
+        let (disk, vol) = image();
+        let inbox = &disk.inbox;
+        disk.run(write_fat(&vol, inbox, 3, vol.eoc())).unwrap();
+
+        // Allocation skips the cluster in use.
+        let mut alloc = disk.run(Alloc::new(&vol, inbox)).unwrap();
+        let mut clusters = Vec::new();
+        disk.run(grow_chain(&vol, inbox, &mut alloc, &mut clusters, 3)).unwrap();
+        assert_eq!(clusters, [2, 4, 5]);
+        assert_eq!(disk.run(chain(&vol, inbox, 2)).unwrap(), clusters);
+        assert_eq!(disk.run(read_fat(&vol, inbox, 5)).unwrap(), 0xFFF);
+
+        // Growing links the old last cluster to the new ones.
+        disk.run(grow_chain(&vol, inbox, &mut alloc, &mut clusters, 5)).unwrap();
+        assert_eq!(clusters, [2, 4, 5, 6, 7]);
+        assert_eq!(disk.run(read_fat(&vol, inbox, 5)).unwrap(), 6);
+        assert_eq!(disk.run(chain(&vol, inbox, 2)).unwrap(), clusters);
+        assert_eq!(alloc.delta, -5);
+
+        disk.run(shrink_chain(&vol, inbox, &mut alloc, &mut clusters, 2)).unwrap();
+        assert_eq!(clusters, [2, 4]);
+        assert_eq!(disk.run(chain(&vol, inbox, 2)).unwrap(), clusters);
+        for c in 5..8 {
+            assert_eq!(disk.run(read_fat(&vol, inbox, c)).unwrap(), 0);
+        }
+        assert_eq!(alloc.delta, -2);
+
+        // The search goes on past the last allocation, then wraps.
+        assert_eq!(disk.run(alloc.alloc_cluster(&vol, inbox)).unwrap(), 8);
+        alloc.hint = CLUSTERS as u32 + 1;
+        assert_eq!(disk.run(alloc.alloc_cluster(&vol, inbox)).unwrap(), CLUSTERS as u32 + 1);
+        assert_eq!(disk.run(alloc.alloc_cluster(&vol, inbox)).unwrap(), 5);
+
+        // A link to a reserved cluster is corruption.
+        disk.run(write_fat(&vol, inbox, 4, 1)).unwrap();
+        assert!(matches!(disk.run(chain(&vol, inbox, 2)), Err(FatError::Corrupt)));
+        assert!(disk.run(chain(&vol, inbox, 0)).unwrap().is_empty());
+        serial_println!("[ok]");
+    }
+
+    #[test_case]
+    fn test_fat_long_name_entries() {
+        serial_print!("test_fat_long_name_entries... ");
+        let (_disk, vol) = image();
+        let name = "A long file name.txt";
+        let short = *b"ALONGF~1TXT";
+        assert_eq!(short_checksum(&short), 0x02);
+
+        // Twenty UTF-16 units take two entries, the last part stored first.
+        let set = long_entries(name, &short);
+        assert_eq!(set.len(), 2 * ENTRY_SIZE);
+        let (second, first) = set.split_at(ENTRY_SIZE);
+        assert_eq!((second[DIR_NAME], first[DIR_NAME]), (LAST_LONG_ENTRY | 2, 1));
+        for e in &[first, second] {
+            assert_eq!((e[DIR_ATTR], e[LDIR_CHKSUM]), (ATTR_LONG_NAME, 0x02));
+        }
+        assert_eq!(get16(first, LDIR_CHARS[0]), 'A' as u16);
+        assert_eq!(get16(second, LDIR_CHARS[0]), 'a' as u16);
+        // The name ends in a NUL, then 0xFFFF padding.
+        assert_eq!(get16(second, LDIR_CHARS[6]), 't' as u16);
+        assert_eq!(get16(second, LDIR_CHARS[7]), 0);
+        assert!(LDIR_CHARS[8..].iter().all(|&off| get16(second, off) == 0xFFFF));
+
+        let mut entry = [0u8; ENTRY_SIZE];
+        entry[DIR_NAME..DIR_NAME + 11].copy_from_slice(&short);
+        entry[DIR_ATTR] = ATTR_ARCHIVE;
+        let mut data = set.clone();
+        data.extend_from_slice(&entry);
+        data.resize(SECTOR_SIZE as usize, 0);
+        let entries = parse_dir(&vol, &data);
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].name, name);
+        assert_eq!((entries[0].first_slot, entries[0].slot), (0, 2));
+
+        // A checksum that does not match the 8.3 name drops the long name.
+        data[2 * ENTRY_SIZE + 7] = b'2';
+        let entries = parse_dir(&vol, &data);
+        assert_eq!(entries[0].name, "ALONGF~2.TXT");
+        assert_eq!(entries[0].first_slot, 2);
+
+        // So does a missing part.
+        let mut data = set[ENTRY_SIZE..].to_vec();
+        data.extend_from_slice(&entry);
+        data.resize(SECTOR_SIZE as usize, 0);
+        assert_eq!(parse_dir(&vol, &data)[0].name, "ALONGF~1.TXT");
+        serial_println!("[ok]");
+    }
+
+    #[test_case]
+    fn test_fat_short_names() {
+        serial_print!("test_fat_short_names... ");
+        let (_disk, vol) = image();
+        assert_eq!(exact_short_name("readme.txt"), Some((*b"README  TXT", NTRES_LOWER_BASE | NTRES_LOWER_EXT)));
+        assert_eq!(exact_short_name("BOOT"), Some((*b"BOOT       ", 0)));
+        assert_eq!(exact_short_name("ReadMe.txt"), None);
+        assert_eq!(exact_short_name("toolongname.txt"), None);
+        assert_eq!(exact_short_name("a.text"), None);
+        assert_eq!(exact_short_name("a b"), None);
+
+        // A generated name is unique in the directory.
+        let mut dir = Dir { cluster: 0, sectors: Vec::new(), data: vec![0u8; SECTOR_SIZE as usize] };
+        let name = "A long file name.txt";
+        let short = generate_short_name(&vol, &dir, name).unwrap();
+        assert_eq!(&short, b"ALONGF~1TXT");
+        dir.slot_mut(0)[DIR_NAME..DIR_NAME + 11].copy_from_slice(&short);
+        assert_eq!(&generate_short_name(&vol, &dir, name).unwrap(), b"ALONGF~2TXT");
+
+        assert!(validate_name("a:b").is_err());
+        assert!(validate_name("..").is_err());
+        assert!(validate_name(&"x".repeat(MAX_NAME_UNITS + 1)).is_err());
+        assert!(validate_name("Ünïcode name").is_ok());
+        serial_println!("[ok]");
+    }
+
+    #[test_case]
+    fn test_fat_write_read_truncate_unlink() {
+        serial_print!("test_fat_write_read_truncate_unlink... ");
+        let (disk, vol) = image();
+        let inbox = &disk.inbox;
+        let path = "/A long file name.txt";
+        let data: Vec<u8> = (0..1500).map(|i| i as u8).collect();
+
+        disk.run(create(&vol, inbox, path)).unwrap();
+        assert!(matches!(disk.run(create(&vol, inbox, "/a LONG file NAME.TXT")), Err(FatError::AlreadyExists)));
+        assert_eq!(disk.run(write_at(&vol, inbox, path, 0, &data)).unwrap(), data.len());
+        let listed = disk.run(list_dir(&vol, inbox, "/")).unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!((listed[0].name.as_str(), listed[0].size), (&path[1..], 1500));
+        // The 8.3 alias names the same file.
+        assert_eq!(disk.run(read_file(&vol, inbox, "/ALONGF~1.TXT")).unwrap(), data);
+        assert_eq!(disk.run(read_at(&vol, inbox, path, 510, 4)).unwrap(), &data[510..514]);
+
+        // Three clusters, then one.
+        let first = get16(&disk.get(ROOT_LBA * SECTOR_SIZE + 2 * ENTRY_SIZE as u64, ENTRY_SIZE), DIR_FST_CLUS_LO);
+        assert_eq!(disk.run(chain(&vol, inbox, first as u32)).unwrap().len(), 3);
+        disk.run(truncate(&vol, inbox, path, 100)).unwrap();
+        assert_eq!(disk.run(stat(&vol, inbox, path)).unwrap().size, 100);
+        assert_eq!(disk.run(chain(&vol, inbox, first as u32)).unwrap().len(), 1);
+        assert_eq!(disk.run(read_file(&vol, inbox, path)).unwrap(), &data[..100]);
+
+        // Unlinking deletes the long-name entries too and frees the chain.
+        disk.run(unlink(&vol, inbox, path)).unwrap();
+        assert!(matches!(disk.run(stat(&vol, inbox, path)), Err(FatError::PathNotFound)));
+        let root = disk.get(ROOT_LBA * SECTOR_SIZE, 3 * ENTRY_SIZE);
+        assert!(root.chunks(ENTRY_SIZE).all(|e| e[DIR_NAME] == ENTRY_DELETED));
+        assert_eq!(disk.run(read_fat(&vol, inbox, first as u32)).unwrap(), 0);
+        serial_println!("[ok]");
+    }
+}
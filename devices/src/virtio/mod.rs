@@ -8,7 +8,15 @@ use virtio_drivers::transport::pci::PciTransport;
 use libkernel::memory;
 
 pub mod blk;
+pub mod buffer_cache;
 pub mod exfat;
+pub mod ext2;
+pub mod fat;
+pub mod net;
+pub mod partition;
+#[cfg(test)]
+pub(crate) mod ram_disk;
+pub mod sector;
 pub mod p9_proto;
 pub mod p9;
 pub use exfat::{BlkInbox, DirEntry, ExfatError, ExfatVol,
@@ -125,3 +133,9 @@ pub fn create_blk_transport(bus: u8, device: u8, function: u8) -> Option<PciTran
 pub fn register_blk_irq(handler: fn(usize)) -> Option<u8> {
     libkernel::interrupts::register_handler(handler)
 }
+
+/// Register a dynamic interrupt handler for the virtio-net device.
+/// Returns the assigned vector, or `None` if all dynamic slots are in use.
+pub fn register_net_irq(handler: fn(usize)) -> Option<u8> {
+    libkernel::interrupts::register_handler(handler)
+}
@@ -0,0 +1,211 @@
+//! virtio-net: the NIC as a smoltcp `phy::Device`.
+//!
+//! `VirtioNet` wraps `VirtIONet` and hands smoltcp one frame per token:
+//! a receive token returns its buffer to the device once consumed (or
+//! dropped), and a transmit token sends as soon as it is filled.  The
+//! interrupt only masks its GSI and wakes the `net` actor through
+//! [`IrqStream`]; the actor acknowledges it, polls the stack and unmasks.
+
+use core::cell::RefCell;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use core::task::{Context, Poll};
+
+use futures_util::stream::Stream;
+use futures_util::task::AtomicWaker;
+use smoltcp::phy::{self, DeviceCapabilities, Medium};
+use smoltcp::time::Instant;
+use virtio_drivers::device::net::{RxBuffer, VirtIONet};
+use virtio_drivers::transport::pci::PciTransport;
+
+use super::KernelHal;
+
+/// Descriptors per virtqueue.  Every receive descriptor owns a buffer on
+/// the kernel heap, so this is kept small.
+pub const QUEUE_SIZE: usize = 16;
+/// Size of each receive buffer: a full frame plus the virtio-net header.
+const BUF_LEN: usize = 2048;
+/// Largest Ethernet frame, without the FCS.
+pub const MTU: usize = 1514;
+
+// ---------------------------------------------------------------------------
+// IRQ state (one static for the single virtio-net device)
+
+static IRQ_PENDING: AtomicBool = AtomicBool::new(false);
+static IRQ_WAKER: AtomicWaker = AtomicWaker::new();
+static NET_GSI: AtomicU32 = AtomicU32::new(u32::MAX); // MAX = not configured
+
+/// Called from the interrupt handler registered for this device.
+pub fn virtio_net_irq_handler(_slot: usize) {
+    IRQ_PENDING.store(true, Ordering::Release);
+    // Mask the GSI until the actor has drained the queues.
+    let gsi = NET_GSI.load(Ordering::Relaxed);
+    if gsi != u32::MAX {
+        libkernel::apic::mask_gsi(gsi);
+    }
+    IRQ_WAKER.wake();
+}
+
+/// Initialise IRQ-driven receive for the virtio-net device.
+///
+/// `gsi` is the PCI interrupt line (GSI) read from config space.
+/// Registers a dynamic interrupt vector, routes the GSI to it, and unmasks.
+pub fn init_irq(gsi: u32) {
+    NET_GSI.store(gsi, Ordering::Relaxed);
+    let vector = match super::register_net_irq(virtio_net_irq_handler) {
+        Some(v) => v,
+        None => {
+            log::warn!("[virtio-net] no free vector for IRQ; falling back to polling");
+            NET_GSI.store(u32::MAX, Ordering::Relaxed);
+            return;
+        }
+    };
+    libkernel::apic::route_gsi(gsi, vector);
+    libkernel::apic::unmask_gsi(gsi);
+    log::info!("[virtio-net] IRQ: GSI {} -> vector {:#x}", gsi, vector);
+}
+
+/// Unmask the GSI once the interrupt has been handled.
+pub fn unmask_irq() {
+    let gsi = NET_GSI.load(Ordering::Relaxed);
+    if gsi != u32::MAX {
+        libkernel::apic::unmask_gsi(gsi);
+    }
+}
+
+/// Yields once per interrupt, however many arrived since the last item.
+pub struct IrqStream {
+    _private: (),
+}
+
+impl IrqStream {
+    pub fn new() -> Self {
+        IrqStream { _private: () }
+    }
+}
+
+impl Stream for IrqStream {
+    type Item = ();
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<()>> {
+        if IRQ_PENDING.swap(false, Ordering::AcqRel) {
+            return Poll::Ready(Some(()));
+        }
+        IRQ_WAKER.register(cx.waker());
+        // Re-check after registration to avoid a missed wake.
+        if IRQ_PENDING.swap(false, Ordering::AcqRel) {
+            Poll::Ready(Some(()))
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Device
+
+type Net = VirtIONet<KernelHal, PciTransport, QUEUE_SIZE>;
+
+pub struct VirtioNet {
+    inner: RefCell<Net>,
+}
+
+// VirtIONet contains raw pointers (queue DMA buffers).  The device lives
+// in the network stack, which is only reached under its lock.
+unsafe impl Send for VirtioNet {}
+
+impl VirtioNet {
+    pub fn new(transport: PciTransport) -> Result<Self, virtio_drivers::Error> {
+        let net = Net::new(transport, BUF_LEN)?;
+        Ok(VirtioNet { inner: RefCell::new(net) })
+    }
+
+    pub fn mac_address(&self) -> [u8; 6] {
+        self.inner.borrow().mac_address()
+    }
+
+    /// Acknowledge a pending interrupt at the device.
+    pub fn ack_interrupt(&mut self) {
+        self.inner.get_mut().ack_interrupt();
+    }
+}
+
+impl phy::Device for VirtioNet {
+    type RxToken<'a> = VirtioRxToken<'a>;
+    type TxToken<'a> = VirtioTxToken<'a>;
+
+    fn receive(&mut self, _timestamp: Instant) -> Option<(Self::RxToken<'_>, Self::TxToken<'_>)> {
+        let buf = {
+            let mut net = self.inner.borrow_mut();
+            if !net.can_recv() {
+                return None;
+            }
+            net.receive().ok()?
+        };
+        Some((
+            VirtioRxToken { net: &self.inner, buf: Some(buf) },
+            VirtioTxToken { net: &self.inner },
+        ))
+    }
+
+    fn transmit(&mut self, _timestamp: Instant) -> Option<Self::TxToken<'_>> {
+        if self.inner.borrow().can_send() {
+            Some(VirtioTxToken { net: &self.inner })
+        } else {
+            None
+        }
+    }
+
+    fn capabilities(&self) -> DeviceCapabilities {
+        let mut caps = DeviceCapabilities::default();
+        caps.medium = Medium::Ethernet;
+        caps.max_transmission_unit = MTU;
+        caps.max_burst_size = Some(QUEUE_SIZE);
+        caps
+    }
+}
+
+/// One received frame.  Its buffer goes back to the device's receive
+/// queue when the token is consumed or dropped.
+pub struct VirtioRxToken<'a> {
+    net: &'a RefCell<Net>,
+    buf: Option<RxBuffer>,
+}
+
+impl phy::RxToken for VirtioRxToken<'_> {
+    fn consume<R, F>(self, f: F) -> R
+    where
+        F: FnOnce(&[u8]) -> R,
+    {
+        f(self.buf.as_ref().unwrap().packet())
+    }
+}
+
+impl Drop for VirtioRxToken<'_> {
+    fn drop(&mut self) {
+        if let Some(buf) = self.buf.take() {
+            if self.net.borrow_mut().recycle_rx_buffer(buf).is_err() {
+                log::warn!("[virtio-net] failed to recycle a receive buffer");
+            }
+        }
+    }
+}
+
+pub struct VirtioTxToken<'a> {
+    net: &'a RefCell<Net>,
+}
+
+impl phy::TxToken for VirtioTxToken<'_> {
+    fn consume<R, F>(self, len: usize, f: F) -> R
+    where
+        F: FnOnce(&mut [u8]) -> R,
+    {
+        let mut net = self.net.borrow_mut();
+        let mut buf = net.new_tx_buffer(len);
+        let result = f(buf.packet_mut());
+        if net.send(buf).is_err() {
+            log::warn!("[virtio-net] transmit failed");
+        }
+        result
+    }
+}
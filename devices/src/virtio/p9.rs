@@ -1,5 +1,11 @@
 //! High-level 9P2000.L client wrapping `VirtIO9p`.
+//!
+//! Most operations walk a fresh fid from the root and clunk it when done.
+//! Files opened through [`P9Client::open`] instead keep one open fid until
+//! the last [`P9Client::release`], so reads and writes on an open file skip
+//! the walk and keep referring to the file that was opened.
 
+use alloc::string::{String, ToString};
 use alloc::vec;
 use alloc::vec::Vec;
 use libkernel::spin_mutex::SpinMutex as Mutex;
@@ -22,6 +28,36 @@ const TAG: u16 = 1;
 const S_IFDIR: u32 = 0o040000;
 const S_IFMT:  u32 = 0o170000;
 
+/// Permissions for new files and directories.
+const FILE_MODE: u32 = 0o644;
+const DIR_MODE:  u32 = 0o755;
+const GID: u32 = 0;
+
+const ENOENT: u32 = 2;
+const EINVAL: u32 = 22;
+
+/// Space left in each Tread / Twrite message for its header.
+const IO_OVERHEAD: u32 = 64;
+
+/// An fid held open for the files opened through [`P9Client::open`].
+struct OpenFid {
+    path: String,
+    fid: u32,
+    writable: bool,
+    iounit: u32,
+    refs: usize,
+    /// The path was unlinked or renamed; keep the fid for its holders but
+    /// do not hand it out for the path any more.
+    stale: bool,
+}
+
+#[derive(Default)]
+struct OpenFids {
+    fids: Vec<OpenFid>,
+    /// Released fids waiting for a Tclunk.
+    retired: Vec<u32>,
+}
+
 // ---------------------------------------------------------------------------
 // P9Client
 
@@ -29,6 +65,7 @@ pub struct P9Client {
     device: Mutex<VirtIO9p<KernelHal, PciTransport>>,
     msize:  u32,
     next_fid: Mutex<u32>,
+    open: Mutex<OpenFids>,
 }
 
 // VirtIO9p contains raw pointers (DMA buffers). Access is serialised
@@ -59,6 +96,7 @@ impl P9Client {
             device: Mutex::new(device),
             msize,
             next_fid: Mutex::new(ROOT_FID + 1),
+            open: Mutex::new(OpenFids::default()),
         })
     }
 
@@ -71,6 +109,7 @@ impl P9Client {
 
     /// Send a request and return the validated payload for `expected_type`.
     fn request(&self, req: &[u8], resp: &mut [u8], expected_type: u8) -> Result<Vec<u8>, P9Error> {
+        self.clunk_retired();
         let mut dev = self.device.lock();
         let n = dev.request(req, resp).map_err(|_| P9Error::DeviceError)?;
         let payload = check_response(resp, n, expected_type)?;
@@ -97,7 +136,7 @@ impl P9Client {
         if !names.is_empty() && qids.len() != names.len() {
             // Partial walk — path not found. Clunk the newfid.
             let _ = self.clunk(newfid);
-            return Err(P9Error::ServerError(2)); // ENOENT
+            return Err(P9Error::ServerError(ENOENT));
         }
 
         Ok(newfid)
@@ -143,6 +182,110 @@ impl P9Client {
         Ok(())
     }
 
+    /// Clunk the fids dropped by `release`, which must not block.
+    fn clunk_retired(&self) {
+        let retired = core::mem::take(&mut self.open.lock().retired);
+        for fid in retired {
+            let req = encode_tclunk(TAG, fid);
+            let mut resp = vec![0u8; self.msize as usize];
+            let mut dev = self.device.lock();
+            let _ = dev.request(&req, &mut resp);
+        }
+    }
+
+    /// Walk to `path`, run `f` on the fid and clunk it.
+    fn with_walk<T>(&self, path: &str, f: impl FnOnce(u32) -> Result<T, P9Error>) -> Result<T, P9Error> {
+        let fid = self.walk(path)?;
+        let result = f(fid);
+        let _ = self.clunk(fid);
+        result
+    }
+
+    /// Walk to the parent directory of `path` and run `f` on its fid and
+    /// the final name.
+    fn with_parent<T>(&self, path: &str, f: impl FnOnce(u32, &str) -> Result<T, P9Error>) -> Result<T, P9Error> {
+        let path = path.trim_end_matches('/');
+        let (parent, name) = match path.rfind('/') {
+            Some(i) => (&path[..i], &path[i + 1..]),
+            None    => ("", path),
+        };
+        if name.is_empty() || name == "." || name == ".." {
+            return Err(P9Error::ServerError(EINVAL));
+        }
+        self.with_walk(parent, |dfid| f(dfid, name))
+    }
+
+    /// Run `f` on an open fid for `path` and its iounit: the fid kept by
+    /// `open` if there is one, otherwise a temporary one.
+    fn with_open<T>(&self, path: &str, writable: bool, f: impl FnOnce(u32, u32) -> Result<T, P9Error>) -> Result<T, P9Error> {
+        if let Some((fid, iounit)) = self.hold(path, writable) {
+            let result = f(fid, iounit);
+            self.unref(fid);
+            return result;
+        }
+        self.with_walk(path, |fid| {
+            let (_, iounit) = self.lopen(fid, open_flags(writable))?;
+            f(fid, iounit)
+        })
+    }
+
+    /// Take a reference to the kept fid for `path`, if there is one, and
+    /// return it with its iounit.  A writable fid also serves reads.
+    fn hold(&self, path: &str, writable: bool) -> Option<(u32, u32)> {
+        let mut open = self.open.lock();
+        let held = open.fids.iter_mut()
+            .find(|o| o.path == path && !o.stale && (o.writable || !writable))
+            .map(|o| {
+                o.refs += 1;
+                (o.fid, o.iounit)
+            });
+        held
+    }
+
+    /// Drop one reference to a kept fid, retiring it at zero.
+    fn unref(&self, fid: u32) {
+        let mut open = self.open.lock();
+        if let Some(i) = open.fids.iter().position(|o| o.fid == fid) {
+            open.fids[i].refs -= 1;
+            if open.fids[i].refs == 0 {
+                open.fids.remove(i);
+                open.retired.push(fid);
+            }
+        }
+    }
+
+    /// Stop handing out kept fids for `path` and anything below it.
+    fn forget(&self, path: &str) {
+        let mut open = self.open.lock();
+        for o in open.fids.iter_mut() {
+            if o.path == path
+                || (o.path.starts_with(path) && o.path.as_bytes().get(path.len()) == Some(&b'/'))
+            {
+                o.stale = true;
+            }
+        }
+    }
+
+    fn write_chunk(&self, fid: u32, offset: u64, data: &[u8]) -> Result<u32, P9Error> {
+        let req = encode_twrite(TAG, fid, offset, data);
+        let mut resp = vec![0u8; self.msize as usize];
+        let payload = self.request(&req, &mut resp, RWRITE)?;
+        decode_rwrite(&payload)
+    }
+
+    fn setattr_fid(&self, fid: u32, attr: &SetAttr9p) -> Result<(), P9Error> {
+        let req = encode_tsetattr(TAG, fid, attr);
+        let mut resp = vec![0u8; self.msize as usize];
+        self.request(&req, &mut resp, RSETATTR)?;
+        Ok(())
+    }
+
+    /// Largest data payload for one Tread / Twrite on a fid.
+    fn io_size(&self, iounit: u32) -> u32 {
+        let max = self.msize - IO_OVERHEAD;
+        if iounit == 0 { max } else { iounit.min(max) }
+    }
+
     // -----------------------------------------------------------------------
     // Public API
 
@@ -196,12 +339,211 @@ impl P9Client {
         Ok(data)
     }
 
-    /// Get file attributes (mode, size) for the given path.
-    pub fn stat(&self, path: &str) -> Result<Stat9p, P9Error> {
+    /// Read up to `len` bytes at `offset` of the file at `path`.  Returns
+    /// fewer bytes at end of file.
+    pub fn read_at(&self, path: &str, offset: u64, len: usize) -> Result<Vec<u8>, P9Error> {
+        self.with_open(path, false, |fid, iounit| {
+            let mut data = Vec::new();
+            let chunk_size = self.io_size(iounit);
+
+            while data.len() < len {
+                let count = (len - data.len()).min(chunk_size as usize) as u32;
+                let chunk = self.read_chunk(fid, offset + data.len() as u64, count)?;
+                if chunk.is_empty() { break; }
+                data.extend_from_slice(&chunk);
+            }
+            Ok(data)
+        })
+    }
+
+    /// Write `data` at `offset` of the file at `path`.  Returns the number
+    /// of bytes written, which is short only if the server stops early.
+    pub fn write_at(&self, path: &str, offset: u64, data: &[u8]) -> Result<usize, P9Error> {
+        self.with_open(path, true, |fid, iounit| {
+            let chunk_size = self.io_size(iounit) as usize;
+            let mut done = 0;
+
+            while done < data.len() {
+                let end = (done + chunk_size).min(data.len());
+                let n = self.write_chunk(fid, offset + done as u64, &data[done..end])? as usize;
+                if n == 0 { break; }
+                done += n;
+            }
+            Ok(done)
+        })
+    }
+
+    /// Open the file at `path` and keep its fid until the matching
+    /// [`release`](Self::release).  Opens of the same path and mode share
+    /// one fid.
+    pub fn open(&self, path: &str, writable: bool) -> Result<(), P9Error> {
+        let kept = |o: &OpenFid| o.path == path && o.writable == writable && !o.stale;
+        {
+            let mut open = self.open.lock();
+            if let Some(i) = open.fids.iter().position(kept) {
+                open.fids[i].refs += 1;
+                return Ok(());
+            }
+        }
+
         let fid = self.walk(path)?;
-        let stat = self.getattr(fid)?;
-        self.clunk(fid)?;
-        Ok(stat)
+        let iounit = match self.lopen(fid, open_flags(writable)) {
+            Ok((_, iounit)) => iounit,
+            Err(e) => {
+                let _ = self.clunk(fid);
+                return Err(e);
+            }
+        };
+
+        let mut open = self.open.lock();
+        // Another caller may have opened the same path meanwhile.
+        if let Some(i) = open.fids.iter().position(kept) {
+            open.fids[i].refs += 1;
+            open.retired.push(fid);
+            return Ok(());
+        }
+        open.fids.push(OpenFid { path: path.to_string(), fid, writable, iounit, refs: 1, stale: false });
+        Ok(())
+    }
+
+    /// Drop a reference taken by [`open`](Self::open).  Does not block: the
+    /// last release queues the Tclunk for the next request.
+    pub fn release(&self, path: &str, writable: bool) {
+        let fid = {
+            let open = self.open.lock();
+            let found = open.fids.iter()
+                .find(|o| o.path == path && o.writable == writable && o.stale)
+                .or_else(|| open.fids.iter().find(|o| o.path == path && o.writable == writable));
+            match found {
+                Some(o) => o.fid,
+                None => return,
+            }
+        };
+        self.unref(fid);
+    }
+
+    /// Create an empty regular file.  Fails with EEXIST if `path` exists.
+    pub fn create(&self, path: &str) -> Result<(), P9Error> {
+        // Tlcreate turns the directory fid into an open fid for the new
+        // file; `with_parent` clunks it afterwards either way.
+        self.with_parent(path, |dfid, name| {
+            let req = encode_tlcreate(TAG, dfid, name, L_O_RDWR | L_O_CREAT | L_O_EXCL, FILE_MODE, GID);
+            let mut resp = vec![0u8; self.msize as usize];
+            let payload = self.request(&req, &mut resp, RLCREATE)?;
+            decode_rlcreate(&payload).map(|_| ())
+        })
+    }
+
+    /// Create a directory.
+    pub fn mkdir(&self, path: &str) -> Result<(), P9Error> {
+        self.with_parent(path, |dfid, name| {
+            let req = encode_tmkdir(TAG, dfid, name, DIR_MODE, GID);
+            let mut resp = vec![0u8; self.msize as usize];
+            let payload = self.request(&req, &mut resp, RMKDIR)?;
+            decode_rmkdir(&payload).map(|_| ())
+        })
+    }
+
+    /// Create a symbolic link at `path` pointing to `target`.
+    pub fn symlink(&self, path: &str, target: &str) -> Result<(), P9Error> {
+        self.with_parent(path, |dfid, name| {
+            let req = encode_tsymlink(TAG, dfid, name, target, GID);
+            let mut resp = vec![0u8; self.msize as usize];
+            let payload = self.request(&req, &mut resp, RSYMLINK)?;
+            decode_rsymlink(&payload).map(|_| ())
+        })
+    }
+
+    /// Create a hard link at `path` to the existing file `target`.
+    pub fn link(&self, path: &str, target: &str) -> Result<(), P9Error> {
+        self.with_walk(target, |fid| {
+            self.with_parent(path, |dfid, name| {
+                let req = encode_tlink(TAG, dfid, fid, name);
+                let mut resp = vec![0u8; self.msize as usize];
+                self.request(&req, &mut resp, RLINK)?;
+                Ok(())
+            })
+        })
+    }
+
+    /// Read the target of the symbolic link at `path`.
+    pub fn readlink(&self, path: &str) -> Result<String, P9Error> {
+        self.with_walk(path, |fid| {
+            let req = encode_treadlink(TAG, fid);
+            let mut resp = vec![0u8; self.msize as usize];
+            let payload = self.request(&req, &mut resp, RREADLINK)?;
+            decode_rreadlink(&payload)
+        })
+    }
+
+    /// Remove a file, or an empty directory with `dir`.
+    pub fn unlink(&self, path: &str, dir: bool) -> Result<(), P9Error> {
+        self.with_parent(path, |dfid, name| {
+            let flags = if dir { AT_REMOVEDIR } else { 0 };
+            let req = encode_tunlinkat(TAG, dfid, name, flags);
+            let mut resp = vec![0u8; self.msize as usize];
+            self.request(&req, &mut resp, RUNLINKAT)?;
+            self.forget(path);
+            Ok(())
+        })
+    }
+
+    /// Rename `from` to `to`, replacing `to` if the server allows it.
+    pub fn rename(&self, from: &str, to: &str) -> Result<(), P9Error> {
+        self.with_parent(from, |old_dfid, old_name| {
+            self.with_parent(to, |new_dfid, new_name| {
+                let req = encode_trenameat(TAG, old_dfid, old_name, new_dfid, new_name);
+                let mut resp = vec![0u8; self.msize as usize];
+                self.request(&req, &mut resp, RRENAMEAT)?;
+                self.forget(from);
+                self.forget(to);
+                Ok(())
+            })
+        })
+    }
+
+    /// Set attributes of the file at `path`.
+    pub fn setattr(&self, path: &str, attr: &SetAttr9p) -> Result<(), P9Error> {
+        self.with_walk(path, |fid| self.setattr_fid(fid, attr))
+    }
+
+    /// Set the size of the file at `path`.
+    pub fn truncate(&self, path: &str, size: u64) -> Result<(), P9Error> {
+        let attr = SetAttr9p { valid: P9_SETATTR_SIZE, size, ..SetAttr9p::default() };
+        self.setattr(path, &attr)
+    }
+
+    /// Ask the server to flush the file at `path` to stable storage.
+    pub fn fsync(&self, path: &str) -> Result<(), P9Error> {
+        self.with_open(path, false, |fid, _| {
+            let req = encode_tfsync(TAG, fid, false);
+            let mut resp = vec![0u8; self.msize as usize];
+            self.request(&req, &mut resp, RFSYNC)?;
+            Ok(())
+        })
+    }
+
+    /// Filesystem statistics for the exported tree.
+    pub fn statfs(&self) -> Result<StatFs9p, P9Error> {
+        let req = encode_tstatfs(TAG, ROOT_FID);
+        let mut resp = vec![0u8; self.msize as usize];
+        let payload = self.request(&req, &mut resp, RSTATFS)?;
+        decode_rstatfs(&payload)
+    }
+
+    /// Get the attributes of the file at `path`; a symbolic link reports
+    /// itself, since a walk does not follow links.
+    ///
+    /// An open file reports the attributes of the file that was opened,
+    /// even if the host has since replaced it, so its qid matches the data
+    /// its reads return.
+    pub fn stat(&self, path: &str) -> Result<Stat9p, P9Error> {
+        if let Some((fid, _)) = self.hold(path, false) {
+            let stat = self.getattr(fid);
+            self.unref(fid);
+            return stat;
+        }
+        self.with_walk(path, |fid| self.getattr(fid))
     }
 
     /// Returns true if the stat mode indicates a directory.
@@ -209,3 +551,7 @@ impl P9Client {
         (mode & S_IFMT) == S_IFDIR
     }
 }
+
+fn open_flags(writable: bool) -> u32 {
+    if writable { L_O_RDWR } else { L_O_RDONLY }
+}
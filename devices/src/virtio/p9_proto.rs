@@ -1,7 +1,9 @@
 //! Minimal 9P2000.L wire protocol encoding/decoding.
 //!
-//! Only the subset needed for read-only host directory sharing is implemented:
-//! version, attach, walk, lopen, read, readdir, getattr, clunk.
+//! Only the subset needed for host directory sharing is implemented:
+//! version, attach, walk, lopen, lcreate, read, write, readdir, getattr,
+//! setattr, mkdir, symlink, link, readlink, unlinkat, renameat, fsync,
+//! statfs and clunk.
 
 use alloc::string::String;
 use alloc::vec::Vec;
@@ -22,13 +24,33 @@ pub enum P9Error {
 // ---------------------------------------------------------------------------
 // 9P2000.L message type constants
 
-pub const RLERROR:  u8 = 7;
-pub const TLOPEN:   u8 = 12;
-pub const RLOPEN:   u8 = 13;
-pub const TGETATTR: u8 = 24;
-pub const RGETATTR: u8 = 25;
-pub const TREADDIR: u8 = 40;
-pub const RREADDIR: u8 = 41;
+pub const RLERROR:   u8 = 7;
+pub const TSTATFS:   u8 = 8;
+pub const RSTATFS:   u8 = 9;
+pub const TLOPEN:    u8 = 12;
+pub const RLOPEN:    u8 = 13;
+pub const TLCREATE:  u8 = 14;
+pub const RLCREATE:  u8 = 15;
+pub const TSYMLINK:  u8 = 16;
+pub const RSYMLINK:  u8 = 17;
+pub const TREADLINK: u8 = 22;
+pub const RREADLINK: u8 = 23;
+pub const TGETATTR:  u8 = 24;
+pub const RGETATTR:  u8 = 25;
+pub const TSETATTR:  u8 = 26;
+pub const RSETATTR:  u8 = 27;
+pub const TREADDIR:  u8 = 40;
+pub const RREADDIR:  u8 = 41;
+pub const TFSYNC:    u8 = 50;
+pub const RFSYNC:    u8 = 51;
+pub const TLINK:     u8 = 70;
+pub const RLINK:     u8 = 71;
+pub const TMKDIR:    u8 = 72;
+pub const RMKDIR:    u8 = 73;
+pub const TRENAMEAT: u8 = 74;
+pub const RRENAMEAT: u8 = 75;
+pub const TUNLINKAT: u8 = 76;
+pub const RUNLINKAT: u8 = 77;
 pub const TVERSION: u8 = 100;
 pub const RVERSION: u8 = 101;
 pub const TATTACH:  u8 = 104;
@@ -37,16 +59,36 @@ pub const TWALK:    u8 = 110;
 pub const RWALK:    u8 = 111;
 pub const TREAD:    u8 = 116;
 pub const RREAD:    u8 = 117;
+pub const TWRITE:   u8 = 118;
+pub const RWRITE:   u8 = 119;
 pub const TCLUNK:   u8 = 120;
 pub const RCLUNK:   u8 = 121;
 
-/// 9P2000.L open flags: read-only.
+/// 9P2000.L open flags (Linux values).
 pub const L_O_RDONLY: u32 = 0;
-
-/// getattr request mask: request mode + size.
-pub const P9_GETATTR_MODE: u64 = 0x0000_0001;
-pub const P9_GETATTR_SIZE: u64 = 0x0000_0200;
-pub const P9_GETATTR_BASIC: u64 = P9_GETATTR_MODE | P9_GETATTR_SIZE;
+pub const L_O_WRONLY: u32 = 0o1;
+pub const L_O_RDWR:   u32 = 0o2;
+pub const L_O_CREAT:  u32 = 0o100;
+pub const L_O_EXCL:   u32 = 0o200;
+pub const L_O_TRUNC:  u32 = 0o1000;
+
+/// Tunlinkat flag: remove a directory.
+pub const AT_REMOVEDIR: u32 = 0x200;
+
+/// getattr request mask: everything `stat` reports (mode, nlink, uid, gid,
+/// rdev, atime, mtime, ctime, ino, size, blocks).
+pub const P9_GETATTR_BASIC: u64 = 0x0000_07ff;
+
+/// setattr valid mask bits.
+pub const P9_SETATTR_MODE:      u32 = 0x0000_0001;
+pub const P9_SETATTR_UID:       u32 = 0x0000_0002;
+pub const P9_SETATTR_GID:       u32 = 0x0000_0004;
+pub const P9_SETATTR_SIZE:      u32 = 0x0000_0008;
+pub const P9_SETATTR_ATIME:     u32 = 0x0000_0010;
+pub const P9_SETATTR_MTIME:     u32 = 0x0000_0020;
+pub const P9_SETATTR_CTIME:     u32 = 0x0000_0040;
+pub const P9_SETATTR_ATIME_SET: u32 = 0x0000_0080;
+pub const P9_SETATTR_MTIME_SET: u32 = 0x0000_0100;
 
 // ---------------------------------------------------------------------------
 // Wire types
@@ -66,11 +108,50 @@ pub struct DirEntry9p {
     pub name:   String,
 }
 
+/// Rgetattr fields.  Times are nanoseconds since the Unix epoch.
 #[derive(Debug, Clone)]
 pub struct Stat9p {
-    pub mode: u32,
-    pub size: u64,
-    pub qid:  Qid,
+    pub mode:   u32,
+    pub uid:    u32,
+    pub gid:    u32,
+    pub nlink:  u64,
+    pub rdev:   u64,
+    pub size:   u64,
+    /// 512-byte blocks allocated.
+    pub blocks: u64,
+    pub atime:  u64,
+    pub mtime:  u64,
+    pub ctime:  u64,
+    pub qid:    Qid,
+}
+
+/// Attributes for Tsetattr.  Only the fields selected by `valid` are
+/// applied; the `*_SET` bits take the given times instead of the server's
+/// current time.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SetAttr9p {
+    pub valid:      u32,
+    pub mode:       u32,
+    pub uid:        u32,
+    pub gid:        u32,
+    pub size:       u64,
+    pub atime_sec:  u64,
+    pub atime_nsec: u64,
+    pub mtime_sec:  u64,
+    pub mtime_nsec: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct StatFs9p {
+    pub fs_type: u32,
+    pub bsize:   u32,
+    pub blocks:  u64,
+    pub bfree:   u64,
+    pub bavail:  u64,
+    pub files:   u64,
+    pub ffree:   u64,
+    pub fsid:    u64,
+    pub namelen: u32,
 }
 
 // ---------------------------------------------------------------------------
@@ -263,6 +344,46 @@ pub fn decode_rread(payload: &[u8]) -> Result<&[u8], P9Error> {
     Ok(&payload[off..off + count])
 }
 
+// ---------------------------------------------------------------------------
+// Tlcreate / Rlcreate
+
+/// Create and open `name` in directory `fid`.  On success `fid` refers to
+/// the new, open file.
+pub fn encode_tlcreate(tag: u16, fid: u32, name: &str, flags: u32, mode: u32, gid: u32) -> Vec<u8> {
+    let mut buf = begin(TLCREATE, tag);
+    put_u32(&mut buf, fid);
+    put_str(&mut buf, name);
+    put_u32(&mut buf, flags);
+    put_u32(&mut buf, mode);
+    put_u32(&mut buf, gid);
+    finish(&mut buf);
+    buf
+}
+
+pub fn decode_rlcreate(payload: &[u8]) -> Result<(Qid, u32), P9Error> {
+    decode_rlopen(payload)
+}
+
+// ---------------------------------------------------------------------------
+// Twrite / Rwrite
+
+pub fn encode_twrite(tag: u16, fid: u32, offset: u64, data: &[u8]) -> Vec<u8> {
+    let mut buf = begin(TWRITE, tag);
+    buf.reserve(16 + data.len());
+    put_u32(&mut buf, fid);
+    put_u64(&mut buf, offset);
+    put_u32(&mut buf, data.len() as u32);
+    buf.extend_from_slice(data);
+    finish(&mut buf);
+    buf
+}
+
+/// Decode Rwrite: returns the number of bytes written.
+pub fn decode_rwrite(payload: &[u8]) -> Result<u32, P9Error> {
+    let mut off = 0;
+    get_u32(payload, &mut off)
+}
+
 // ---------------------------------------------------------------------------
 // Treaddir / Rreaddir
 
@@ -303,19 +424,174 @@ pub fn encode_tgetattr(tag: u16, fid: u32, request_mask: u64) -> Vec<u8> {
     buf
 }
 
-/// Decode Rgetattr: extract mode, size, and qid from the fixed-layout response.
+/// Read a (seconds, nanoseconds) pair as nanoseconds.
+fn get_time(data: &[u8], off: &mut usize) -> Result<u64, P9Error> {
+    let sec  = get_u64(data, off)?;
+    let nsec = get_u64(data, off)?;
+    Ok(sec.saturating_mul(1_000_000_000).saturating_add(nsec))
+}
+
+/// Decode Rgetattr from the fixed-layout response.
 pub fn decode_rgetattr(payload: &[u8]) -> Result<Stat9p, P9Error> {
     let mut off = 0;
-    let _valid = get_u64(payload, &mut off)?;
-    let qid    = get_qid(payload, &mut off)?;
-    let mode   = get_u32(payload, &mut off)?;
-    let _uid   = get_u32(payload, &mut off)?;
-    let _gid   = get_u32(payload, &mut off)?;
-    let _nlink = get_u64(payload, &mut off)?;
-    let _rdev  = get_u64(payload, &mut off)?;
-    let size   = get_u64(payload, &mut off)?;
-    // Remaining fields (blksize, blocks, timestamps) are ignored.
-    Ok(Stat9p { mode, size, qid })
+    let _valid   = get_u64(payload, &mut off)?;
+    let qid      = get_qid(payload, &mut off)?;
+    let mode     = get_u32(payload, &mut off)?;
+    let uid      = get_u32(payload, &mut off)?;
+    let gid      = get_u32(payload, &mut off)?;
+    let nlink    = get_u64(payload, &mut off)?;
+    let rdev     = get_u64(payload, &mut off)?;
+    let size     = get_u64(payload, &mut off)?;
+    let _blksize = get_u64(payload, &mut off)?;
+    let blocks   = get_u64(payload, &mut off)?;
+    let atime    = get_time(payload, &mut off)?;
+    let mtime    = get_time(payload, &mut off)?;
+    let ctime    = get_time(payload, &mut off)?;
+    // btime, gen and data_version are not requested.
+    Ok(Stat9p { mode, uid, gid, nlink, rdev, size, blocks, atime, mtime, ctime, qid })
+}
+
+// ---------------------------------------------------------------------------
+// Tsetattr / Rsetattr
+
+pub fn encode_tsetattr(tag: u16, fid: u32, attr: &SetAttr9p) -> Vec<u8> {
+    let mut buf = begin(TSETATTR, tag);
+    put_u32(&mut buf, fid);
+    put_u32(&mut buf, attr.valid);
+    put_u32(&mut buf, attr.mode);
+    put_u32(&mut buf, attr.uid);
+    put_u32(&mut buf, attr.gid);
+    put_u64(&mut buf, attr.size);
+    put_u64(&mut buf, attr.atime_sec);
+    put_u64(&mut buf, attr.atime_nsec);
+    put_u64(&mut buf, attr.mtime_sec);
+    put_u64(&mut buf, attr.mtime_nsec);
+    finish(&mut buf);
+    buf
+}
+
+// ---------------------------------------------------------------------------
+// Tmkdir / Rmkdir
+
+pub fn encode_tmkdir(tag: u16, dfid: u32, name: &str, mode: u32, gid: u32) -> Vec<u8> {
+    let mut buf = begin(TMKDIR, tag);
+    put_u32(&mut buf, dfid);
+    put_str(&mut buf, name);
+    put_u32(&mut buf, mode);
+    put_u32(&mut buf, gid);
+    finish(&mut buf);
+    buf
+}
+
+pub fn decode_rmkdir(payload: &[u8]) -> Result<Qid, P9Error> {
+    let mut off = 0;
+    get_qid(payload, &mut off)
+}
+
+// ---------------------------------------------------------------------------
+// Tsymlink / Rsymlink
+
+pub fn encode_tsymlink(tag: u16, dfid: u32, name: &str, target: &str, gid: u32) -> Vec<u8> {
+    let mut buf = begin(TSYMLINK, tag);
+    put_u32(&mut buf, dfid);
+    put_str(&mut buf, name);
+    put_str(&mut buf, target);
+    put_u32(&mut buf, gid);
+    finish(&mut buf);
+    buf
+}
+
+pub fn decode_rsymlink(payload: &[u8]) -> Result<Qid, P9Error> {
+    let mut off = 0;
+    get_qid(payload, &mut off)
+}
+
+// ---------------------------------------------------------------------------
+// Tlink / Rlink
+
+pub fn encode_tlink(tag: u16, dfid: u32, fid: u32, name: &str) -> Vec<u8> {
+    let mut buf = begin(TLINK, tag);
+    put_u32(&mut buf, dfid);
+    put_u32(&mut buf, fid);
+    put_str(&mut buf, name);
+    finish(&mut buf);
+    buf
+}
+
+// ---------------------------------------------------------------------------
+// Treadlink / Rreadlink
+
+pub fn encode_treadlink(tag: u16, fid: u32) -> Vec<u8> {
+    let mut buf = begin(TREADLINK, tag);
+    put_u32(&mut buf, fid);
+    finish(&mut buf);
+    buf
+}
+
+pub fn decode_rreadlink(payload: &[u8]) -> Result<String, P9Error> {
+    let mut off = 0;
+    get_str(payload, &mut off)
+}
+
+// ---------------------------------------------------------------------------
+// Tunlinkat / Runlinkat
+
+pub fn encode_tunlinkat(tag: u16, dfid: u32, name: &str, flags: u32) -> Vec<u8> {
+    let mut buf = begin(TUNLINKAT, tag);
+    put_u32(&mut buf, dfid);
+    put_str(&mut buf, name);
+    put_u32(&mut buf, flags);
+    finish(&mut buf);
+    buf
+}
+
+// ---------------------------------------------------------------------------
+// Trenameat / Rrenameat
+
+pub fn encode_trenameat(tag: u16, old_dfid: u32, old_name: &str, new_dfid: u32, new_name: &str) -> Vec<u8> {
+    let mut buf = begin(TRENAMEAT, tag);
+    put_u32(&mut buf, old_dfid);
+    put_str(&mut buf, old_name);
+    put_u32(&mut buf, new_dfid);
+    put_str(&mut buf, new_name);
+    finish(&mut buf);
+    buf
+}
+
+// ---------------------------------------------------------------------------
+// Tfsync / Rfsync
+
+pub fn encode_tfsync(tag: u16, fid: u32, datasync: bool) -> Vec<u8> {
+    let mut buf = begin(TFSYNC, tag);
+    put_u32(&mut buf, fid);
+    put_u32(&mut buf, datasync as u32);
+    finish(&mut buf);
+    buf
+}
+
+// ---------------------------------------------------------------------------
+// Tstatfs / Rstatfs
+
+pub fn encode_tstatfs(tag: u16, fid: u32) -> Vec<u8> {
+    let mut buf = begin(TSTATFS, tag);
+    put_u32(&mut buf, fid);
+    finish(&mut buf);
+    buf
+}
+
+pub fn decode_rstatfs(payload: &[u8]) -> Result<StatFs9p, P9Error> {
+    let mut off = 0;
+    Ok(StatFs9p {
+        fs_type: get_u32(payload, &mut off)?,
+        bsize:   get_u32(payload, &mut off)?,
+        blocks:  get_u64(payload, &mut off)?,
+        bfree:   get_u64(payload, &mut off)?,
+        bavail:  get_u64(payload, &mut off)?,
+        files:   get_u64(payload, &mut off)?,
+        ffree:   get_u64(payload, &mut off)?,
+        fsid:    get_u64(payload, &mut off)?,
+        namelen: get_u32(payload, &mut off)?,
+    })
 }
 
 // ---------------------------------------------------------------------------
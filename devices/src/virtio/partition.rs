@@ -0,0 +1,155 @@
+use alloc::vec::Vec;
+use core::convert::TryInto;
+
+use super::exfat::BlkInbox;
+use super::sector::{read_sector, BlkIoError};
+
+// ---------------------------------------------------------------------------
+// Public types
+
+#[derive(Debug)]
+pub enum PartitionError {
+    NoDevice,
+    IoError,
+    /// Sector 0 has no MBR signature.
+    UnknownLayout,
+}
+
+impl From<BlkIoError> for PartitionError {
+    fn from(e: BlkIoError) -> Self {
+        match e {
+            BlkIoError::NoDevice => PartitionError::NoDevice,
+            BlkIoError::IoError  => PartitionError::IoError,
+        }
+    }
+}
+
+/// Partition type, as recorded in the partition table.
+#[derive(Clone, Copy, PartialEq)]
+pub enum PartitionType {
+    /// MBR partition type byte.
+    Mbr(u8),
+    /// GPT partition type GUID, in its on-disk (mixed-endian) byte order.
+    Gpt([u8; 16]),
+}
+
+/// A partition listed in the disk's MBR or GPT.
+#[derive(Clone, Copy)]
+pub struct Partition {
+    pub start_lba: u64,
+    pub ptype:     PartitionType,
+}
+
+// ---------------------------------------------------------------------------
+// Well-known partition types
+
+/// GPT "Microsoft Basic Data" (EBD0A0A2-B9E5-4433-87C0-68B6B72699C7).
+pub const GPT_BASIC_DATA: [u8; 16] = [
+    0xA2, 0xA0, 0xD0, 0xEB,
+    0xE5, 0xB9,
+    0x33, 0x44,
+    0x87, 0xC0, 0x68, 0xB6, 0xB7, 0x26, 0x99, 0xC7,
+];
+
+/// GPT "Linux filesystem data" (0FC63DAF-8483-4772-8E79-3D69D8477DE4).
+pub const GPT_LINUX_DATA: [u8; 16] = [
+    0xAF, 0x3D, 0xC6, 0x0F,
+    0x83, 0x84,
+    0x72, 0x47,
+    0x8E, 0x79, 0x3D, 0x69, 0xD8, 0x47, 0x7D, 0xE4,
+];
+
+/// GPT "EFI System Partition" (C12A7328-F81F-11D2-BA4B-00A0C93EC93B).
+pub const GPT_EFI_SYSTEM: [u8; 16] = [
+    0x28, 0x73, 0x2A, 0xC1,
+    0x1F, 0xF8,
+    0xD2, 0x11,
+    0xBA, 0x4B, 0x00, 0xA0, 0xC9, 0x3E, 0xC9, 0x3B,
+];
+
+/// MBR types of FAT volumes: FAT12, FAT16 (< 32 MiB), FAT16, FAT32 (CHS),
+/// FAT32 (LBA), FAT16 (LBA), and the EFI system partition.
+pub const MBR_FAT: [u8; 7] = [0x01, 0x04, 0x06, 0x0B, 0x0C, 0x0E, 0xEF];
+/// MBR type shared by exFAT and NTFS.
+pub const MBR_EXFAT_NTFS: u8 = 0x07;
+/// MBR "Linux native".
+pub const MBR_LINUX: u8 = 0x83;
+
+// ---------------------------------------------------------------------------
+// Partition tables
+
+/// List the partitions on the disk, from its GPT if it has one and its MBR
+/// otherwise.  Empty slots are skipped.  Filesystem drivers check the start
+/// of each candidate for their own signature.
+pub async fn partitions(inbox: &BlkInbox) -> Result<Vec<Partition>, PartitionError> {
+    let sector0 = read_sector(inbox, 0).await?;
+
+    // Need a valid MBR/protective-MBR signature to proceed.
+    if sector0[510] != 0x55 || sector0[511] != 0xAA {
+        return Err(PartitionError::UnknownLayout);
+    }
+
+    // Read LBA 1 to distinguish GPT from MBR.
+    let sector1 = read_sector(inbox, 1).await?;
+
+    if &sector1[0..8] == b"EFI PART" {
+        gpt_partitions(inbox, &sector1).await
+    } else {
+        Ok(mbr_partitions(&sector0))
+    }
+}
+
+async fn gpt_partitions(inbox: &BlkInbox, gpt_header: &[u8]) -> Result<Vec<Partition>, PartitionError> {
+    // GPT header field offsets (UEFI spec 2.x):
+    //   72..80  PartitionEntryLBA       (u64 LE)
+    //   80..84  NumberOfPartitionEntries (u32 LE)
+    //   84..88  SizeOfPartitionEntry    (u32 LE)
+    let entry_lba  = u64::from_le_bytes(gpt_header[72..80].try_into().unwrap());
+    let num_parts  = u32::from_le_bytes(gpt_header[80..84].try_into().unwrap()) as usize;
+    let entry_size = u32::from_le_bytes(gpt_header[84..88].try_into().unwrap()) as usize;
+
+    if entry_size == 0 || entry_size > 512 {
+        return Err(PartitionError::UnknownLayout);
+    }
+
+    let entries_per_sector = 512 / entry_size;
+    let num_sectors = (num_parts + entries_per_sector - 1) / entries_per_sector;
+
+    let mut parts = Vec::new();
+    for sector_idx in 0..num_sectors {
+        let lba = entry_lba + sector_idx as u64;
+        let sector = read_sector(inbox, lba).await?;
+
+        for entry_idx in 0..entries_per_sector {
+            let off = entry_idx * entry_size;
+            if off + entry_size > 512 { break; }
+
+            let entry = &sector[off..off + entry_size];
+
+            // Skip empty entries (type GUID all-zero).
+            if entry[0..16].iter().all(|&b| b == 0) { continue; }
+
+            // GPT partition entry layout:
+            //   0..16   PartitionTypeGUID
+            //   32..40  StartingLBA (u64 LE)
+            parts.push(Partition {
+                start_lba: u64::from_le_bytes(entry[32..40].try_into().unwrap()),
+                ptype:     PartitionType::Gpt(entry[0..16].try_into().unwrap()),
+            });
+        }
+    }
+    Ok(parts)
+}
+
+fn mbr_partitions(mbr: &[u8]) -> Vec<Partition> {
+    // MBR partition table: bytes 446..510, four 16-byte entries.
+    let mut parts = Vec::new();
+    for i in 0..4usize {
+        let off   = 446 + i * 16;
+        let ptype = mbr[off + 4];
+        let lba_start = u32::from_le_bytes(mbr[off + 8..off + 12].try_into().unwrap()) as u64;
+        if ptype == 0 || lba_start == 0 { continue; }
+        parts.push(Partition { start_lba: lba_start, ptype: PartitionType::Mbr(ptype) });
+    }
+    parts
+}
@@ -0,0 +1,98 @@
+//! An in-memory disk answering a block device mailbox, for the filesystem
+//! driver tests.
+//!
+//! Sectors are stored sparsely: one never written reads as zeros, so an
+//! image only costs heap for the sectors it uses.  [`RamDisk::run`] drives
+//! a driver future to completion on the calling thread, answering its
+//! sector requests between polls.
+
+use alloc::collections::BTreeMap;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+
+use futures_util::task::noop_waker;
+use libkernel::task::mailbox::{ActorMsg, Mailbox};
+
+use super::blk::VirtioBlkMsg;
+use super::buffer_cache;
+use super::exfat::BlkInbox;
+
+const SECTOR_SIZE: usize = 512;
+
+pub(crate) struct RamDisk {
+    pub(crate) inbox: BlkInbox,
+    sectors: RefCell<BTreeMap<u64, Vec<u8>>>,
+    /// Size in sectors; requests past it fail.
+    len: u64,
+}
+
+impl RamDisk {
+    /// A zeroed disk of `len` sectors.  Clears the buffer cache, which
+    /// knows sectors by number alone and may hold another test's disk.
+    pub(crate) fn new(len: u64) -> Self {
+        buffer_cache::clear();
+        RamDisk { inbox: Mailbox::new(4), sectors: RefCell::new(BTreeMap::new()), len }
+    }
+
+    /// Store `data` at byte offset `pos`.
+    pub(crate) fn put(&self, pos: u64, data: &[u8]) {
+        let mut sectors = self.sectors.borrow_mut();
+        for (i, &b) in data.iter().enumerate() {
+            let at = pos as usize + i;
+            let sector = sectors.entry((at / SECTOR_SIZE) as u64)
+                .or_insert_with(|| vec![0; SECTOR_SIZE]);
+            sector[at % SECTOR_SIZE] = b;
+        }
+    }
+
+    /// `len` bytes from byte offset `pos`.
+    pub(crate) fn get(&self, pos: u64, len: usize) -> Vec<u8> {
+        let sectors = self.sectors.borrow();
+        (pos as usize..pos as usize + len)
+            .map(|at| sectors.get(&((at / SECTOR_SIZE) as u64)).map_or(0, |s| s[at % SECTOR_SIZE]))
+            .collect()
+    }
+
+    /// Poll `fut` until it completes, answering the requests it sends to
+    /// [`inbox`](Self::inbox).  Panics if it waits on anything else.
+    pub(crate) fn run<F: Future>(&self, fut: F) -> F::Output {
+        let waker = noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        let mut fut = alloc::boxed::Box::pin(fut);
+        loop {
+            if let Poll::Ready(out) = Pin::as_mut(&mut fut).poll(&mut cx) {
+                return out;
+            }
+            let mut served = false;
+            while let Some(msg) = self.inbox.try_recv() {
+                self.serve(msg);
+                served = true;
+            }
+            assert!(served, "ram disk: driver waits on something other than the disk");
+        }
+    }
+
+    fn serve(&self, msg: ActorMsg<VirtioBlkMsg, super::blk::VirtioBlkInfo>) {
+        match msg {
+            ActorMsg::Inner(VirtioBlkMsg::Read(lba, reply)) => {
+                if lba >= self.len {
+                    return reply.send(Err(()));
+                }
+                reply.send(Ok(self.get(lba * SECTOR_SIZE as u64, SECTOR_SIZE)));
+            }
+            ActorMsg::Inner(VirtioBlkMsg::Write(lba, data, reply)) => {
+                if lba >= self.len || data.len() != SECTOR_SIZE {
+                    return reply.send(Err(()));
+                }
+                self.put(lba * SECTOR_SIZE as u64, &data);
+                reply.send(Ok(()));
+            }
+            // Dropping the reply answers `None`.
+            ActorMsg::Info(_) | ActorMsg::ErasedInfo(_) => {}
+        }
+    }
+}
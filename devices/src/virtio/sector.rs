@@ -0,0 +1,83 @@
+//! Sector I/O and little-endian field access shared by the partition
+//! scanner and the filesystem drivers.
+//!
+//! Metadata sectors go through the [buffer cache](super::buffer_cache);
+//! file data bypasses it with [`read_data_sector`], since the VFS page cache
+//! holds that.  Every write keeps the cached copy in step.  Errors come back
+//! as [`BlkIoError`]; each caller's error type implements `From` for it, so
+//! `?` converts them.
+
+use alloc::vec::Vec;
+use core::convert::TryInto;
+
+use libkernel::task::mailbox::ActorMsg;
+
+use super::blk::VirtioBlkMsg;
+use super::buffer_cache;
+use super::exfat::BlkInbox;
+
+/// Why a sector could not be read or written.
+#[derive(Debug)]
+pub enum BlkIoError {
+    /// The device reported an error.
+    IoError,
+    /// The block device actor is gone.
+    NoDevice,
+}
+
+// ---------------------------------------------------------------------------
+// Little-endian field access
+
+pub(crate) fn get16(b: &[u8], off: usize) -> u16 {
+    u16::from_le_bytes(b[off..off + 2].try_into().unwrap())
+}
+
+pub(crate) fn get32(b: &[u8], off: usize) -> u32 {
+    u32::from_le_bytes(b[off..off + 4].try_into().unwrap())
+}
+
+pub(crate) fn put16(b: &mut [u8], off: usize, v: u16) {
+    b[off..off + 2].copy_from_slice(&v.to_le_bytes());
+}
+
+pub(crate) fn put32(b: &mut [u8], off: usize, v: u32) {
+    b[off..off + 4].copy_from_slice(&v.to_le_bytes());
+}
+
+// ---------------------------------------------------------------------------
+// Sector I/O
+
+/// Read a 512-byte metadata sector, through the buffer cache.
+pub(crate) async fn read_sector(inbox: &BlkInbox, lba: u64) -> Result<Vec<u8>, BlkIoError> {
+    if let Some(buf) = buffer_cache::get(lba) {
+        return Ok(buf);
+    }
+    let buf = read_data_sector(inbox, lba).await?;
+    buffer_cache::insert(lba, &buf);
+    Ok(buf)
+}
+
+/// Read a 512-byte sector of file data, bypassing the buffer cache.
+pub(crate) async fn read_data_sector(inbox: &BlkInbox, lba: u64) -> Result<Vec<u8>, BlkIoError> {
+    let result = inbox.ask(|reply| {
+        ActorMsg::Inner(VirtioBlkMsg::Read(lba, reply))
+    }).await;
+    match result {
+        Some(Ok(buf)) => Ok(buf),
+        Some(Err(())) => Err(BlkIoError::IoError),
+        None          => Err(BlkIoError::NoDevice),
+    }
+}
+
+/// Write a 512-byte sector, keeping any buffer-cache copy in step.
+pub(crate) async fn write_sector(inbox: &BlkInbox, lba: u64, data: Vec<u8>) -> Result<(), BlkIoError> {
+    buffer_cache::update(lba, &data);
+    let result = inbox.ask(|reply| {
+        ActorMsg::Inner(VirtioBlkMsg::Write(lba, data, reply))
+    }).await;
+    match result {
+        Some(Ok(()))  => Ok(()),
+        Some(Err(())) => { buffer_cache::invalidate(lba); Err(BlkIoError::IoError) }
+        None          => { buffer_cache::invalidate(lba); Err(BlkIoError::NoDevice) }
+    }
+}
@@ -3,4 +3,18 @@ fn main() {
         .join("x86_64-kernel.ld");
     println!("cargo:rustc-link-arg=-T{}", script.display());
     println!("cargo:rerun-if-changed={}", script.display());
+
+    // Link the cpio archive named by OSTOO_INITRAMFS into the image; an
+    // empty archive means there is no initramfs.
+    let out = std::path::PathBuf::from(std::env::var("OUT_DIR").unwrap())
+        .join("initramfs.cpio");
+    println!("cargo:rerun-if-env-changed=OSTOO_INITRAMFS");
+    match std::env::var("OSTOO_INITRAMFS") {
+        Ok(path) if !path.is_empty() => {
+            println!("cargo:rerun-if-changed={}", path);
+            std::fs::copy(&path, &out)
+                .unwrap_or_else(|e| panic!("OSTOO_INITRAMFS={}: {}", path, e));
+        }
+        _ => std::fs::write(&out, b"").unwrap(),
+    }
 }
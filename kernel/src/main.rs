@@ -46,10 +46,16 @@ const VIRTIO_BLK_MODERN: u16 = 0x1042;
 const VIRTIO_BLK_LEGACY: u16 = 0x1001;
 const VIRTIO_9P_MODERN: u16 = 0x1049;
 const VIRTIO_9P_LEGACY: u16 = 0x1009;
+const VIRTIO_NET_MODERN: u16 = 0x1041;
+const VIRTIO_NET_LEGACY: u16 = 0x1000;
 
 const BGA_VENDOR: u16 = 0x1234;
 const BGA_DEVICE: u16 = 0x1111;
 
+/// newc cpio archive linked in by `build.rs` from `OSTOO_INITRAMFS`; empty
+/// if the variable was not set.
+static INITRAMFS: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/initramfs.cpio"));
+
 // ---------------------------------------------------------------------------
 // Panic handlers
 
@@ -107,7 +113,7 @@ pub fn libkernel_main(boot_info: &'static BootInfo) -> ! {
 // Kernel main (runs on heap stack)
 
 fn run_kernel() -> ! {
-    const BOOT_STEPS: usize = 8;
+    const BOOT_STEPS: usize = 9;
     let progress = |step, label| libkernel::vga_buffer::boot_progress(step, BOOT_STEPS, label);
 
     progress(0, "Remapping VGA...");
@@ -116,8 +122,14 @@ fn run_kernel() -> ! {
 
     progress(1, "Configuring interrupts...");
     init_apic();
+    libkernel::time::init();
+    libkernel::vdso::init();
+    libkernel::random::init();
     progress(2, "Interrupts configured");
 
+    progress(2, "Unpacking initramfs...");
+    let have_initramfs = init_initramfs();
+
     progress(2, "Scanning PCI bus...");
     init_pci();
     progress(3, "PCI bus scanned");
@@ -134,21 +146,28 @@ fn run_kernel() -> ! {
     let p9_client = init_virtio_9p();
     progress(6, "virtio-9p done");
 
-    progress(6, "Mounting filesystems...");
-    init_vfs_mounts(p9_client);
-    progress(7, "Filesystems mounted");
+    progress(6, "Starting network...");
+    init_network();
+    progress(7, "Network started");
+
+    progress(7, "Mounting filesystems...");
+    init_vfs_mounts(&p9_client);
+    progress(8, "Filesystems mounted");
 
-    progress(7, "Starting actors...");
+    progress(8, "Starting actors...");
     init_actors();
-    progress(8, "Ready");
+    progress(9, "Ready");
 
     libkernel::vga_buffer::boot_progress_done();
 
     #[cfg(test)]
     test_main();
 
+    executor::spawn(Task::new(mount_root(p9_client, have_initramfs)));
     executor::spawn(Task::new(timer_task()));
     executor::spawn(Task::new(status_task()));
+    devices::vfs::page_cache::init();
+    executor::spawn(Task::new(devices::vfs::page_cache::flusher()));
     executor::spawn(Task::new(launch_keyboard_driver()));
     executor::spawn(Task::new(launch_compositor()));
     executor::spawn(Task::new(launch_userspace_shell()));
@@ -306,8 +325,13 @@ fn init_virtio_blk() {
     }
 
     let actor = devices::virtio::blk::VirtioBlkActor::new(transport);
+    let sectors = actor.capacity_sectors();
     let (drv, inbox) = devices::virtio::blk::VirtioBlkActorDriver::new(actor);
     devices::driver::register(Box::new(drv));
+    // 254 is the major Linux usually assigns virtio-blk.
+    devices::vfs::dev_vfs::register("vda", devices::vfs::makedev(254, 0),
+        devices::vfs::dev_vfs::DevNode::Block(
+            Arc::new(devices::virtio::blk::BlkDevice::new(inbox.clone(), sectors))));
     libkernel::task::registry::register("virtio-blk", inbox);
     devices::driver::start_driver("virtio-blk").ok();
     info!("[kernel] virtio-blk registered");
@@ -339,9 +363,76 @@ fn init_virtio_9p() -> Option<Arc<devices::virtio::p9::P9Client>> {
     }
 }
 
-/// Set up VFS mount table: /host (9p), /proc, / (exfat or 9p fallback).
-fn init_vfs_mounts(p9_client: Option<Arc<devices::virtio::p9::P9Client>>) {
-    if let Some(ref client) = p9_client {
+/// Bring up the network stack: the loopback interface, the virtio-net NIC
+/// if there is one, and the `net` actor that drives them.
+fn init_network() {
+    devices::net::add_loopback();
+    init_virtio_net();
+
+    let (drv, inbox) =
+        devices::net::actor::NetActorDriver::new(devices::net::actor::NetActor::new());
+    devices::driver::register(Box::new(drv));
+    libkernel::task::registry::register("net", inbox);
+    devices::driver::start_driver("net").ok();
+}
+
+/// Probe the virtio-net NIC and add it to the network stack as `eth0`.
+fn init_virtio_net() {
+    let pci_dev = match find_virtio_device(VIRTIO_NET_MODERN, VIRTIO_NET_LEGACY) {
+        Some(d) => d,
+        None => { info!("[kernel] no virtio-net device found"); return; }
+    };
+
+    info!("[kernel] found virtio-net at {:02x}:{:02x}.{}",
+        pci_dev.bus, pci_dev.device, pci_dev.function);
+
+    let transport = match devices::virtio::create_pci_transport(
+        pci_dev.bus, pci_dev.device, pci_dev.function,
+    ) {
+        Some(t) => t,
+        None => { warn!("[kernel] virtio-net transport init failed"); return; }
+    };
+
+    let gsi = pci_dev.interrupt_line;
+    if gsi > 0 && gsi < 24 {
+        devices::virtio::net::init_irq(gsi as u32);
+    } else {
+        info!("[kernel] virtio-net: no valid IRQ line ({}), using polling", gsi);
+    }
+
+    let dev = match devices::virtio::net::VirtioNet::new(transport) {
+        Ok(dev) => dev,
+        Err(e) => { warn!("[kernel] virtio-net init failed: {:?}", e); return; }
+    };
+    devices::net::add_virtio("eth0", dev);
+    info!("[kernel] virtio-net registered as eth0");
+}
+
+/// Unpack the linked-in initramfs and mount it at `/`, before any device
+/// is probed.  Returns whether `/` was mounted.
+fn init_initramfs() -> bool {
+    if INITRAMFS.is_empty() {
+        return false;
+    }
+    let size = devices::vfs::TmpVfs::default_size();
+    match devices::vfs::initramfs::unpack(INITRAMFS, size) {
+        Ok(fs) => {
+            devices::vfs::mount("/", devices::vfs::AnyVfs::Tmp(fs));
+            info!("[kernel] initramfs ({} KiB) unpacked at /", INITRAMFS.len() / 1024);
+            true
+        }
+        Err(e) => {
+            warn!("[kernel] initramfs unpack failed: {:?}", e);
+            false
+        }
+    }
+}
+
+/// Set up VFS mount table: /host (9p), /proc, /dev, /tmp and /run (tmpfs).
+/// `/` is the initramfs if there is one, else it is mounted by
+/// [`mount_root`] once the executor runs, since probing the disk is async.
+fn init_vfs_mounts(p9_client: &Option<Arc<devices::virtio::p9::P9Client>>) {
+    if let Some(client) = p9_client {
         devices::vfs::mount("/host",
             devices::vfs::AnyVfs::Plan9(
                 devices::vfs::Plan9Vfs::new(Arc::clone(client))));
@@ -350,14 +441,41 @@ fn init_vfs_mounts(p9_client: Option<Arc<devices::virtio::p9::P9Client>>) {
 
     devices::vfs::mount("/proc", devices::vfs::AnyVfs::Proc(devices::vfs::ProcVfs));
 
-    // / — exFAT if disk present, else 9p fallback
+    // Drivers registered their own nodes (/dev/vda) as they started.
+    devices::vfs::dev_vfs::register_builtin();
+    devices::vfs::mount("/dev", devices::vfs::AnyVfs::Dev(devices::vfs::DevVfs));
+
+    // Scratch space and runtime state (sockets, pid files) stay in memory.
+    // /tmp is world-writable with the sticky bit, /run is root's.
+    let tmp_size = devices::vfs::TmpVfs::default_size();
+    devices::vfs::mount("/tmp", devices::vfs::AnyVfs::Tmp(
+        devices::vfs::TmpVfs::new(tmp_size, 0o1777)));
+    devices::vfs::mount("/run", devices::vfs::AnyVfs::Tmp(
+        devices::vfs::TmpVfs::new(tmp_size, 0o755)));
+}
+
+/// Mount `/`: the filesystem detected on the disk (exFAT, ext2 or FAT), else
+/// the 9p share as a fallback.  With an initramfs at `/`, the disk's
+/// filesystem goes to `/mnt` instead and there is no fallback.
+async fn mount_root(p9_client: Option<Arc<devices::virtio::p9::P9Client>>, have_initramfs: bool) {
+    let mountpoint = if have_initramfs { "/mnt" } else { "/" };
     if let Some(inbox) = libkernel::task::registry::get::<
         devices::virtio::blk::VirtioBlkMsg,
         devices::virtio::blk::VirtioBlkInfo,
     >("virtio-blk") {
-        devices::vfs::mount("/", devices::vfs::AnyVfs::Exfat(
-            devices::vfs::ExfatVfs::new(inbox)));
-    } else if let Some(client) = p9_client {
+        match devices::vfs::open_disk(inbox).await {
+            Some(fs) => {
+                info!("[kernel] {} filesystem mounted at {}", fs.fs_type(), mountpoint);
+                devices::vfs::mount(mountpoint, fs);
+                return;
+            }
+            None => warn!("[kernel] virtio-blk: no exFAT, ext2 or FAT filesystem found"),
+        }
+    }
+    if have_initramfs {
+        return;
+    }
+    if let Some(client) = p9_client {
         devices::vfs::mount("/",
             devices::vfs::AnyVfs::Plan9(
                 devices::vfs::Plan9Vfs::new(client)));
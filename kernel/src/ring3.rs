@@ -105,7 +105,7 @@ fn spawn_blob(code: &[u8]) -> ProcessId {
 
     let thread_idx = libkernel::task::scheduler::spawn_user_thread(pid, pml4_phys);
     libkernel::process::with_process(pid, |p| {
-        p.thread_idx = Some(thread_idx);
+        p.set_main_thread(thread_idx);
     });
 
     pid
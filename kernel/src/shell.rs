@@ -35,6 +35,13 @@ fn resolve_path(cwd: &str, path: &str) -> String {
     libkernel::path::resolve(cwd, path)
 }
 
+/// [`resolve_path`], then follow symbolic links.  A path that does not
+/// resolve is returned as is, for the command to report.
+async fn follow_path(cwd: &str, path: &str) -> String {
+    let path = resolve_path(cwd, path);
+    devices::vfs::canonicalize(&path, true).await.unwrap_or(path)
+}
+
 #[devices::actor("shell", ShellMsg)]
 impl Shell {
     // ── Startup ──────────────────────────────────────────────────────────
@@ -160,9 +167,13 @@ impl Shell {
     async fn cmd_cd(&self, path: &str) {
         let cwd    = self.cwd.lock().clone();
         let target = resolve_path(&cwd, if path.is_empty() { "/" } else { path });
+        let result = match devices::vfs::canonicalize(&target, true).await {
+            Ok(dir) => devices::vfs::list_dir(&dir).await.map(|_| dir),
+            Err(e)  => Err(e),
+        };
 
-        match devices::vfs::list_dir(&target).await {
-            Ok(_)                                         => *self.cwd.lock() = target,
+        match result {
+            Ok(dir)                                       => *self.cwd.lock() = dir,
             Err(devices::vfs::VfsError::NotFound)         => println!("cd: not found: {}", target),
             Err(devices::vfs::VfsError::NotADirectory)    => println!("cd: not a directory: {}", target),
             Err(e)                                        => println!("cd: {:?}", e),
@@ -172,7 +183,7 @@ impl Shell {
     // ── blk ls ────────────────────────────────────────────────────────────────
     async fn cmd_blk_ls(&self, path: &str) {
         let cwd  = self.cwd.lock().clone();
-        let path = resolve_path(&cwd, path);
+        let path = follow_path(&cwd, path).await;
 
         match devices::vfs::list_dir(&path).await {
             Ok(entries) => {
@@ -180,7 +191,7 @@ impl Shell {
                     println!("  (empty)");
                 } else {
                     for e in &entries {
-                        if e.is_dir {
+                        if e.is_dir() {
                             println!("  [DIR]        {}", e.name);
                         } else {
                             println!("  [FILE {:5}]  {}", e.size, e.name);
@@ -200,7 +211,7 @@ impl Shell {
         }
 
         let cwd  = self.cwd.lock().clone();
-        let path = resolve_path(&cwd, path);
+        let path = follow_path(&cwd, path).await;
 
         match devices::vfs::read_file(&path, libkernel::process::ProcessId::KERNEL).await {
             Ok(data) => {
@@ -228,8 +239,8 @@ impl Shell {
                 if mounts.is_empty() {
                     println!("  (no mounts)");
                 } else {
-                    for (mp, fs) in mounts {
-                        println!("  {}  {}", mp, fs.fs_type());
+                    for m in mounts {
+                        println!("  {}  {}", m.path, m.fs.fs_type());
                     }
                 }
             });
@@ -258,12 +269,27 @@ impl Shell {
                     Some(mb) => mb,
                     None => { println!("virtio-blk: driver not found"); return; }
                 };
-                devices::vfs::mount(mountpoint, devices::vfs::AnyVfs::Exfat(
-                    devices::vfs::ExfatVfs::new(inbox)
+                match devices::vfs::open_disk(inbox).await {
+                    Some(fs) => {
+                        let fs_type = fs.fs_type();
+                        devices::vfs::mount(mountpoint, fs);
+                        println!("mounted blk ({}) at {}", fs_type, mountpoint);
+                    }
+                    None => println!("virtio-blk: no exFAT, ext2 or FAT filesystem found"),
+                }
+            }
+            "tmpfs" => {
+                let size = devices::vfs::TmpVfs::default_size();
+                devices::vfs::mount(mountpoint, devices::vfs::AnyVfs::Tmp(
+                    devices::vfs::TmpVfs::new(size, 0o1777)
                 ));
-                println!("mounted blk at {}", mountpoint);
+                println!("mounted tmpfs at {}", mountpoint);
+            }
+            "devfs" => {
+                devices::vfs::mount(mountpoint, devices::vfs::AnyVfs::Dev(devices::vfs::DevVfs));
+                println!("mounted devfs at {}", mountpoint);
             }
-            other => println!("unknown filesystem type '{}' (use: proc | blk)", other),
+            other => println!("unknown filesystem type '{}' (use: proc | blk | tmpfs | devfs)", other),
         }
     }
 
@@ -302,7 +328,7 @@ impl Shell {
             return;
         }
         let cwd  = self.cwd.lock().clone();
-        let path = resolve_path(&cwd, path);
+        let path = follow_path(&cwd, path).await;
 
         let data = match devices::vfs::read_file(&path, libkernel::process::ProcessId::KERNEL).await {
             Ok(d) => d,
@@ -326,7 +352,7 @@ impl Shell {
             return;
         }
         let cwd  = self.cwd.lock().clone();
-        let path = resolve_path(&cwd, path);
+        let path = follow_path(&cwd, path).await;
 
         match devices::vfs::read_file(&path, libkernel::process::ProcessId::KERNEL).await {
             Ok(data) => {
@@ -408,7 +434,9 @@ fn cmd_help() {
     println!("  cd [path]         change working directory");
     println!("  mount             list mounted filesystems");
     println!("  mount proc <mp>   mount procfs at <mountpoint>");
-    println!("  mount blk <mp>    mount exFAT block device at <mountpoint>");
+    println!("  mount blk <mp>    mount the disk's filesystem at <mountpoint>");
+    println!("  mount tmpfs <mp>  mount an empty in-memory filesystem at <mountpoint>");
+    println!("  mount devfs <mp>  mount the device nodes at <mountpoint>");
     println!("  md5 <path>        print MD5 hash of a file");
     println!("  exec <path>       load and run an ELF binary from the VFS");
     println!("  test ring3        ring-3 write+exit via syscall (spawns process)");
@@ -0,0 +1,239 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(libkernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+//! TCP and UDP over the loopback interface, with no NIC attached.
+//!
+//! Nothing runs the `net` actor here, so the sockets are non-blocking and
+//! the tests poll the stack themselves until each step has happened.
+
+extern crate alloc;
+
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use bootloader::{entry_point, BootInfo};
+use core::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use core::panic::PanicInfo;
+use devices::net::{Family, InetSocket};
+use libkernel::file::FileHandle;
+use libkernel::poll::POLL_WRITABLE;
+use libkernel::socket::{SocketError, SocketType};
+use libkernel::{serial_print, serial_println};
+
+entry_point!(main);
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    libkernel::test_panic_handler(info)
+}
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use libkernel::allocator;
+    use libkernel::memory::{self, BootInfoFrameAllocator};
+    use x86_64::VirtAddr;
+
+    libkernel::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe {
+        BootInfoFrameAllocator::init(&boot_info.memory_map)
+    };
+    allocator::init_heap(&mut mapper, &mut frame_allocator)
+        .expect("heap initialization failed");
+
+    devices::net::add_loopback();
+
+    test_main();
+    loop {}
+}
+
+/// Polls of the stack a step may take before the test gives up.
+const ROUNDS: usize = 1000;
+
+fn localhost(port: u16) -> SocketAddr {
+    SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), port)
+}
+
+fn socket(family: Family, ty: SocketType) -> Arc<InetSocket> {
+    let sock = InetSocket::new(family, ty);
+    sock.set_nonblocking(true);
+    sock
+}
+
+/// Run `f` until it stops failing with `WouldBlock`, polling in between.
+fn retry<T>(mut f: impl FnMut() -> Result<T, SocketError>) -> T {
+    for _ in 0..ROUNDS {
+        match f() {
+            Err(SocketError::WouldBlock) => devices::net::poll(),
+            Err(e) => panic!("socket error: {:?}", e),
+            Ok(v) => return v,
+        }
+    }
+    panic!("no progress over loopback");
+}
+
+/// A listener on `addr` and a connection to it, from both ends.
+fn connected(
+    family: Family,
+    addr: SocketAddr,
+) -> (Arc<InetSocket>, Arc<InetSocket>, Arc<InetSocket>) {
+    let listener = socket(family, SocketType::Stream);
+    listener.bind(addr).unwrap();
+    listener.listen(1).unwrap();
+
+    let client = socket(family, SocketType::Stream);
+    match client.connect(addr) {
+        Ok(()) | Err(SocketError::InProgress) => {}
+        Err(e) => panic!("connect: {:?}", e),
+    }
+    let (server, from) = retry(|| listener.accept());
+    server.set_nonblocking(true);
+    retry(|| match client.poll_events(None) & POLL_WRITABLE {
+        0 => Err(SocketError::WouldBlock),
+        _ => Ok(()),
+    });
+    assert!(client.take_error().is_none());
+    assert_eq!(from, client.local_addr());
+    assert_eq!(client.peer_addr().unwrap(), addr);
+    (listener, client, server)
+}
+
+fn recv_all(sock: &InetSocket, len: usize) -> Vec<u8> {
+    let mut data = vec![0u8; len];
+    let mut got = 0;
+    while got < len {
+        got += retry(|| sock.recv(&mut [&mut data[got..]], 0)).len;
+    }
+    data
+}
+
+#[test_case]
+fn tcp_connect_accept() {
+    serial_print!("tcp_connect_accept... ");
+    let (listener, client, server) = connected(Family::Inet, localhost(7001));
+    assert_eq!(client.send(&[b"ping"], None, 0).unwrap(), 4);
+    assert_eq!(&recv_all(&server, 4), b"ping");
+    assert_eq!(server.send(&[b"pong"], None, 0).unwrap(), 4);
+    assert_eq!(&recv_all(&client, 4), b"pong");
+
+    // Nothing else is queued.
+    let mut buf = [0u8; 4];
+    assert!(matches!(client.recv(&mut [&mut buf[..]], 0), Err(SocketError::WouldBlock)));
+    for sock in [client, server, listener] {
+        sock.close();
+    }
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn tcp_connect_accept_ipv6() {
+    serial_print!("tcp_connect_accept_ipv6... ");
+    let addr = SocketAddr::new(IpAddr::V6(Ipv6Addr::LOCALHOST), 7002);
+    let (listener, client, server) = connected(Family::Inet6, addr);
+    assert_eq!(client.send(&[b"over ::1"], None, 0).unwrap(), 8);
+    assert_eq!(&recv_all(&server, 8), b"over ::1");
+    for sock in [client, server, listener] {
+        sock.close();
+    }
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn tcp_large_transfer() {
+    serial_print!("tcp_large_transfer... ");
+    let (listener, client, server) = connected(Family::Inet, localhost(7003));
+
+    // Eight times the socket buffers, so the window has to keep reopening.
+    let len = 8 * devices::net::TCP_BUFFER;
+    let data: Vec<u8> = (0..len).map(|i| (i % 251) as u8).collect();
+    let mut received = vec![0u8; len];
+    let (mut sent, mut got) = (0, 0);
+    for _ in 0..ROUNDS {
+        if sent < len {
+            match client.send(&[&data[sent..]], None, 0) {
+                Ok(n) => sent += n,
+                Err(SocketError::WouldBlock) => {}
+                Err(e) => panic!("send: {:?}", e),
+            }
+        }
+        match server.recv(&mut [&mut received[got..]], 0) {
+            Ok(r) => got += r.len,
+            Err(SocketError::WouldBlock) => devices::net::poll(),
+            Err(e) => panic!("recv: {:?}", e),
+        }
+        if got == len {
+            break;
+        }
+    }
+    assert_eq!(got, len);
+    assert!(received == data);
+    for sock in [client, server, listener] {
+        sock.close();
+    }
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn tcp_half_close() {
+    serial_print!("tcp_half_close... ");
+    let (listener, client, server) = connected(Family::Inet, localhost(7004));
+    client.send(&[b"request"], None, 0).unwrap();
+    client.shutdown(false, true).unwrap();
+    assert!(matches!(client.send(&[b"more"], None, 0), Err(SocketError::BrokenPipe)));
+
+    // The server reads the request, then end of stream...
+    assert_eq!(&recv_all(&server, 7), b"request");
+    let mut buf = [0u8; 16];
+    assert_eq!(retry(|| server.recv(&mut [&mut buf[..]], 0)).len, 0);
+
+    // ...and can still answer.
+    server.send(&[b"response"], None, 0).unwrap();
+    assert_eq!(&recv_all(&client, 8), b"response");
+    server.close();
+    assert_eq!(retry(|| client.recv(&mut [&mut buf[..]], 0)).len, 0);
+    client.close();
+    listener.close();
+    serial_println!("[ok]");
+}
+
+#[test_case]
+fn udp_datagram_boundaries() {
+    serial_print!("udp_datagram_boundaries... ");
+    let a = socket(Family::Inet, SocketType::Datagram);
+    let b = socket(Family::Inet, SocketType::Datagram);
+    a.bind(localhost(7005)).unwrap();
+    b.bind(localhost(7006)).unwrap();
+
+    let big = vec![0x5a; 1000];
+    a.send(&[b"one"], Some(localhost(7006)), 0).unwrap();
+    a.send(&[b"two ", b"parts"], Some(localhost(7006)), 0).unwrap();
+    a.send(&[&big], Some(localhost(7006)), 0).unwrap();
+    a.send(&[b""], Some(localhost(7006)), 0).unwrap();
+
+    // Each receive takes one whole datagram, never more.
+    let mut buf = [0u8; 64];
+    let got = retry(|| b.recv(&mut [&mut buf[..]], 0));
+    assert_eq!((&buf[..got.len], got.from), (&b"one"[..], Some(localhost(7005))));
+    let got = retry(|| b.recv(&mut [&mut buf[..]], 0));
+    assert_eq!(&buf[..got.len], b"two parts");
+
+    // A short buffer truncates; the rest of the datagram is gone.
+    let got = retry(|| b.recv(&mut [&mut buf[..]], 0));
+    assert_eq!((got.len, got.msg_len), (64, 1000));
+    assert!(buf.iter().all(|&x| x == 0x5a));
+    let got = retry(|| b.recv(&mut [&mut buf[..]], 0));
+    assert_eq!((got.len, got.msg_len), (0, 0));
+    assert!(matches!(b.recv(&mut [&mut buf[..]], 0), Err(SocketError::WouldBlock)));
+
+    // A connected socket's sends go to its peer.
+    b.connect(localhost(7005)).unwrap();
+    b.send(&[b"reply"], None, 0).unwrap();
+    let got = retry(|| a.recv(&mut [&mut buf[..]], 0));
+    assert_eq!((&buf[..got.len], got.from), (&b"reply"[..], Some(localhost(7006))));
+    a.close();
+    b.close();
+    serial_println!("[ok]");
+}
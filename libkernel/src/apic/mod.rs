@@ -202,7 +202,8 @@ const CALIBRATION_PIT_HZ:    u64 = 100;
 const LAPIC_TARGET_HZ:       u64 = 1000;
 const LAPIC_DIVIDE_BY_16:    u8  = 0x3;
 
-/// Calibrate the LAPIC timer against the PIT and start it in periodic mode at 1000 Hz.
+/// Calibrate the LAPIC timer and the TSC against the PIT and start the LAPIC
+/// timer in periodic mode at 1000 Hz.
 /// Must be called after `init()` and with interrupts enabled (PIT drives TICK_COUNT during calibration).
 pub fn calibrate_and_start_lapic_timer() {
     use crate::{interrupts::LAPIC_TIMER_VECTOR, task::timer};
@@ -210,12 +211,16 @@ pub fn calibrate_and_start_lapic_timer() {
     info!("[apic] calibrating LAPIC timer against PIT ({}ms window)...",
         CALIBRATION_PIT_TICKS * 1000 / CALIBRATION_PIT_HZ);
 
+    // Start on a PIT tick edge so the window is a whole number of ticks.
+    timer::wait_ticks(1);
+
     // Phase 1: start one-shot countdown. Release lock before entering HLT loop.
-    {
+    let tsc_start = {
         let guard = LOCAL_APIC.lock();
         let apic = guard.as_ref().expect("LOCAL_APIC not initialised");
         apic.start_oneshot_timer(0xFFFF_FFFF, LAPIC_DIVIDE_BY_16, LAPIC_TIMER_VECTOR);
-    }
+        crate::time::rdtsc()
+    };
 
     // Phase 2: busy-wait 500ms (PIT drives TICK_COUNT via timer ISR during this window).
     timer::wait_ticks(CALIBRATION_PIT_TICKS);
@@ -224,6 +229,7 @@ pub fn calibrate_and_start_lapic_timer() {
     let guard = LOCAL_APIC.lock();
     let apic = guard.as_ref().unwrap();
     let remaining = apic.read_current_count();
+    let tsc_end = crate::time::rdtsc();
     let elapsed = 0xFFFF_FFFFu64 - remaining as u64;
     apic.stop_timer();
 
@@ -235,6 +241,10 @@ pub fn calibrate_and_start_lapic_timer() {
     info!("[apic] LAPIC bus {} MHz, timer initial_count={} ({}Hz)",
         lapic_bus_freq / 1_000_000, initial_count, LAPIC_TARGET_HZ);
 
+    let tsc_hz = (tsc_end - tsc_start) * CALIBRATION_PIT_HZ / CALIBRATION_PIT_TICKS;
+    crate::time::set_tsc_frequency(tsc_hz);
+    info!("[apic] TSC {} MHz", tsc_hz / 1_000_000);
+
     apic.start_periodic_timer(initial_count as u32, LAPIC_DIVIDE_BY_16, LAPIC_TIMER_VECTOR);
 
     // Mask the PIT's IO APIC redirection entry so it no longer fires.
@@ -16,6 +16,7 @@ use alloc::sync::Arc;
 use crate::completion_port::CompletionPort;
 use crate::file::FdObject;
 use crate::irq_mutex::IrqMutex;
+use crate::poll::{PollWaiters, POLLERR, POLLHUP, POLL_READABLE, POLL_WRITABLE};
 use crate::task::scheduler;
 
 /// A fixed-size IPC message (48 bytes).
@@ -160,6 +161,9 @@ pub struct ChannelInner {
     // --- Peer lifetime ---
     send_closed: bool,
     recv_closed: bool,
+
+    /// poll / epoll waiters on either end, woken on every state change.
+    pollers: PollWaiters,
 }
 
 impl ChannelInner {
@@ -177,7 +181,38 @@ impl ChannelInner {
             pending_send_port: None,
             send_closed: false,
             recv_closed: false,
+            pollers: PollWaiters::new(),
+        }
+    }
+
+    /// Readiness of the send end: `POLLOUT` while a send would not block,
+    /// `POLLERR` once the receive end is closed.  A rendezvous channel is
+    /// writable only while a receiver is blocked in recv or armed on a port.
+    pub fn poll_send(&mut self, waker: Option<&core::task::Waker>) -> u32 {
+        self.pollers.register(waker);
+        if self.recv_closed {
+            return POLL_WRITABLE | POLLERR;
+        }
+        let writable = if self.capacity == 0 {
+            self.blocked_receiver.is_some() || self.pending_port.is_some()
+        } else {
+            self.queue.len() < self.capacity
+        };
+        if writable { POLL_WRITABLE } else { 0 }
+    }
+
+    /// Readiness of the receive end: `POLLIN` while a message is waiting,
+    /// `POLLHUP` once the send end is closed.
+    pub fn poll_recv(&mut self, waker: Option<&core::task::Waker>) -> u32 {
+        self.pollers.register(waker);
+        let mut mask = 0;
+        if !self.queue.is_empty() || self.pending_send.is_some() || self.pending_send_port.is_some() {
+            mask |= POLL_READABLE;
+        }
+        if self.send_closed {
+            mask |= POLLHUP;
         }
+        mask
     }
 
     /// Increment the send-end reference count (called on dup/fork).
@@ -194,6 +229,7 @@ impl ChannelInner {
     ///
     /// Returns the action the caller must take after releasing the lock.
     pub fn try_send(&mut self, env: EnvelopedMessage, nonblock: bool) -> SendAction {
+        self.pollers.wake_all();
         if self.recv_closed {
             return SendAction::PeerClosed(env);
         }
@@ -253,6 +289,7 @@ impl ChannelInner {
     ///
     /// Returns the action the caller must take after releasing the lock.
     pub fn try_recv(&mut self, nonblock: bool) -> RecvAction {
+        self.pollers.wake_all();
         if self.capacity == 0 {
             // Synchronous rendezvous.
             if let Some(env) = self.pending_send.take() {
@@ -321,6 +358,7 @@ impl ChannelInner {
     /// waiting), returns a Ready variant.  Otherwise stores the port + message
     /// for future delivery when a receiver drains space.
     pub fn arm_send(&mut self, info: PendingPortSend) -> ArmSendAction {
+        self.pollers.wake_all();
         if self.recv_closed {
             return ArmSendAction::PeerClosed;
         }
@@ -365,6 +403,7 @@ impl ChannelInner {
     /// it immediately.  Otherwise stores the port registration for future
     /// notification when a sender calls `try_send`.
     pub fn arm_recv(&mut self, info: PendingPortRecv) -> ArmRecvAction {
+        self.pollers.wake_all();
         if self.capacity == 0 {
             // Sync: check thread-blocked sender first, then port-based sender.
             if let Some(env) = self.pending_send.take() {
@@ -406,6 +445,7 @@ impl ChannelInner {
     /// Returns the thread index of a woken receiver (if any) so the caller
     /// can donate after releasing the lock.
     pub fn close_send(&mut self) -> CloseSendAction {
+        self.pollers.wake_all();
         self.send_count -= 1;
         if self.send_count > 0 {
             return CloseSendAction::None;
@@ -427,6 +467,7 @@ impl ChannelInner {
     /// Returns the thread index of a woken sender (if any) so the caller
     /// can donate after releasing the lock.
     pub fn close_recv(&mut self) -> CloseRecvAction {
+        self.pollers.wake_all();
         self.recv_count -= 1;
         if self.recv_count > 0 {
             return CloseRecvAction::None;
@@ -8,6 +8,7 @@ use alloc::vec::Vec;
 use x86_64::PhysAddr;
 use crate::channel::TransferFds;
 use crate::memory::PHYS_MAP_BASE;
+use crate::poll::{PollWaiters, POLL_READABLE};
 use crate::task::scheduler;
 
 // ---------------------------------------------------------------------------
@@ -30,6 +31,14 @@ pub const OP_IPC_SEND: u32 = 5;
 pub const OP_IPC_RECV: u32 = 6;
 pub const OP_RING_WAIT: u32 = 7;
 
+// ---------------------------------------------------------------------------
+// Submission flags (IoSubmission::flags)
+
+/// OP_READ / OP_WRITE transfer at `IoSubmission::offset`, like pread /
+/// pwrite, leaving the file position alone.  Without it they use and
+/// advance the file position.
+pub const IOSUB_OFFSET: u32 = 0x1;
+
 // ---------------------------------------------------------------------------
 // SchedulerWaker — bridges generic Waker trait to kernel scheduler
 
@@ -214,6 +223,8 @@ impl Drop for IoRing {
 pub struct CompletionPort {
     inner: completion_port::CompletionPort<Completion, SchedulerWaker>,
     io_ring: Option<IoRing>,
+    /// poll / epoll waiters, woken on every post.
+    pollers: PollWaiters,
 }
 
 impl CompletionPort {
@@ -221,6 +232,7 @@ impl CompletionPort {
         CompletionPort {
             inner: completion_port::CompletionPort::new(SchedulerWaker),
             io_ring: None,
+            pollers: PollWaiters::new(),
         }
     }
 
@@ -237,6 +249,7 @@ impl CompletionPort {
     /// return value.
     // [spec: completion_port/completion_port.tla Post — entire body is one atomic step under IrqMutex]
     pub fn post(&mut self, c: Completion) -> Option<usize> {
+        self.pollers.wake_all();
         // [spec: completion_port/completion_port.tla Post — p_simple branch (cq_count) vs queue branch]
         if let Some(ref ring) = self.io_ring {
             if c.read_buf.is_none() && c.transfer_fds.is_none() {
@@ -271,6 +284,17 @@ impl CompletionPort {
         self.inner.pending()
     }
 
+    /// `POLLIN` while completions are queued or waiting in the CQ ring.
+    pub fn poll_events(&mut self, waker: Option<&core::task::Waker>) -> u32 {
+        self.pollers.register(waker);
+        let ring_ready = self.io_ring.as_ref().map_or(false, |r| r.cq_available() > 0);
+        if self.pending() > 0 || ring_ready {
+            POLL_READABLE
+        } else {
+            0
+        }
+    }
+
     /// Whether this port has shared-memory rings set up.
     pub fn has_ring(&self) -> bool {
         self.io_ring.is_some()
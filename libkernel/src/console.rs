@@ -4,6 +4,7 @@ use alloc::collections::VecDeque;
 use core::sync::atomic::{AtomicU64, Ordering};
 use crate::spin_mutex::SpinMutex as Mutex;
 
+use crate::poll::PollWaiters;
 use crate::process::ProcessId;
 use crate::task::scheduler;
 use crate::wait_condition::WaitCondition;
@@ -18,6 +19,8 @@ struct ConsoleInner {
     blocked_reader_pid: Option<ProcessId>,
     /// Waker for async readers (completion port OP_READ).
     blocked_waker: Option<core::task::Waker>,
+    /// poll / epoll waiters for input.
+    pollers: PollWaiters,
 }
 
 static CONSOLE_INPUT: Mutex<ConsoleInner> = Mutex::new(ConsoleInner {
@@ -25,6 +28,7 @@ static CONSOLE_INPUT: Mutex<ConsoleInner> = Mutex::new(ConsoleInner {
     blocked_reader: None,
     blocked_reader_pid: None,
     blocked_waker: None,
+    pollers: PollWaiters::new(),
 });
 
 /// PID of the foreground process that receives keyboard input.
@@ -45,6 +49,7 @@ pub fn push_input(byte: u8) {
     if let Some(waker) = inner.blocked_waker.take() {
         waker.wake();
     }
+    inner.pollers.wake_all();
 }
 
 /// Result of a console read attempt.
@@ -60,6 +65,7 @@ pub enum ReadResult {
 /// Returns `Data(n)` on success or `Interrupted` if a signal is pending.
 pub fn read_input(buf: &mut [u8]) -> ReadResult {
     let pid = crate::process::current_pid();
+    let tid = crate::process::current_tid();
     loop {
         let mut inner = CONSOLE_INPUT.lock();
         if !inner.buf.is_empty() {
@@ -72,8 +78,7 @@ pub fn read_input(buf: &mut [u8]) -> ReadResult {
         // Before blocking, check for pending deliverable signals.
         if pid != ProcessId::KERNEL {
             let has_signal = crate::process::with_process_ref(pid, |p| {
-                let deliverable = p.signal.pending & !p.signal.blocked;
-                deliverable != 0
+                p.interrupted(tid)
             }).unwrap_or(false);
             if has_signal {
                 return ReadResult::Interrupted;
@@ -121,6 +126,14 @@ pub fn poll_read_input(cx: &mut core::task::Context<'_>, buf: &mut [u8])
     core::task::Poll::Pending
 }
 
+/// True if input is buffered.  `waker`, if given, is woken when input
+/// arrives (poll / epoll).
+pub fn poll_input(waker: Option<&core::task::Waker>) -> bool {
+    let mut inner = CONSOLE_INPUT.lock();
+    inner.pollers.register(waker);
+    !inner.buf.is_empty()
+}
+
 /// Set the foreground process.
 pub fn set_foreground(pid: ProcessId) {
     FOREGROUND_PID.store(pid.as_u64(), Ordering::Relaxed);
@@ -4,6 +4,9 @@ pub const PAGE_SIZE: u64 = 0x1000;
 pub const PAGE_MASK: u64 = 0xFFF;
 pub const KERNEL_STACK_SIZE: usize = 64 * 1024;
 
+/// First non-canonical address above the user half.
+pub const USER_SPACE_END: u64 = 0x0000_8000_0000_0000;
+
 /// Base virtual address of the kernel stack arena (right after the 1 MiB heap).
 pub const STACK_ARENA_BASE: u64 = 0xFFFF_8000_0010_0000;
 /// Number of 64 KiB stack slots in the arena.
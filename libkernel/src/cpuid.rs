@@ -49,6 +49,23 @@ pub fn vendor_into(buf: &mut [u8; 12]) -> usize {
     }
 }
 
+/// True if the TSC ticks at a constant rate in every P-, C- and T-state.
+pub fn has_invariant_tsc() -> bool {
+    CpuId::new().get_extended_function_info()
+        .map_or(false, |info| info.has_invariant_tsc())
+}
+
+/// True if the CPU implements the RDRAND instruction.
+pub fn has_rdrand() -> bool {
+    CpuId::new().get_feature_info().map_or(false, |f| f.has_rdrand())
+}
+
+/// True if the CPU implements the RDSEED instruction (CPUID leaf 7, EBX bit 18).
+pub fn has_rdseed() -> bool {
+    // raw-cpuid 7 spells the accessor `has_rdseet`.
+    CpuId::new().get_extended_feature_info().map_or(false, |f| f.has_rdseet())
+}
+
 pub fn init() {
     let features = CpuId::new().get_feature_info();
     info!("[cpuid] init {:?}", features);
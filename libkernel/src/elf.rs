@@ -96,13 +96,32 @@ pub struct ElfInfo {
 // Parser
 
 pub fn parse(data: &[u8]) -> Result<ElfInfo, ElfError> {
-    let ehdr_size = core::mem::size_of::<Elf64Ehdr>();
-    if data.len() < ehdr_size {
+    parse_headers(data, data.len() as u64)
+}
+
+fn read_ehdr(data: &[u8]) -> Result<Elf64Ehdr, ElfError> {
+    if data.len() < core::mem::size_of::<Elf64Ehdr>() {
         return Err(ElfError::TooSmall);
     }
-
     // Safety: Elf64Ehdr is repr(C) and we've checked the length.
-    let ehdr: Elf64Ehdr = unsafe { core::ptr::read_unaligned(data.as_ptr() as *const Elf64Ehdr) };
+    Ok(unsafe { core::ptr::read_unaligned(data.as_ptr() as *const Elf64Ehdr) })
+}
+
+/// Bytes from the start of the file needed by [`parse_headers`]: the ELF
+/// header and the program header table.  `data` must hold at least the
+/// 64-byte ELF header.
+pub fn headers_len(data: &[u8]) -> Result<u64, ElfError> {
+    let ehdr = read_ehdr(data)?;
+    let table = ehdr.e_phnum as u64 * ehdr.e_phentsize as u64;
+    let end = ehdr.e_phoff.checked_add(table).ok_or(ElfError::BadPhdr)?;
+    Ok(end.max(core::mem::size_of::<Elf64Ehdr>() as u64))
+}
+
+/// Parse an ELF file of `file_size` bytes given only its first bytes, which
+/// must cover [`headers_len`].  Segment contents are not read, only checked
+/// to lie within the file.
+pub fn parse_headers(data: &[u8], file_size: u64) -> Result<ElfInfo, ElfError> {
+    let ehdr = read_ehdr(data)?;
 
     if ehdr.e_ident[0..4] != ELF_MAGIC {
         return Err(ElfError::BadMagic);
@@ -142,7 +161,7 @@ pub fn parse(data: &[u8]) -> Result<ElfInfo, ElfError> {
 
         // Validate that file data is within bounds.
         let end = phdr.p_offset.checked_add(phdr.p_filesz).ok_or(ElfError::BadPhdr)?;
-        if end as usize > data.len() {
+        if end > file_size {
             return Err(ElfError::BadPhdr);
         }
 
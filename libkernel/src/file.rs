@@ -3,6 +3,7 @@
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 use snafu::Snafu;
+use x86_64::PhysAddr;
 use crate::spin_mutex::SpinMutex as Mutex;
 
 use crate::channel::{ChannelInner, CloseRecvAction, CloseSendAction, PendingPortRecv, PendingPortSend};
@@ -10,6 +11,7 @@ use crate::completion_port::CompletionPort;
 use crate::irq_handle::IrqInner;
 use crate::irq_mutex::IrqMutex;
 use crate::notify::NotifyInner;
+use crate::poll::{PollWaiters, DEFAULT_POLLMASK, POLLHUP, POLL_READABLE, POLL_WRITABLE};
 use crate::shmem::SharedMemInner;
 
 // ---------------------------------------------------------------------------
@@ -18,6 +20,24 @@ use crate::shmem::SharedMemInner;
 /// File descriptor flag: close-on-exec.
 pub const FD_CLOEXEC: u32 = 1;
 
+// ---------------------------------------------------------------------------
+// Open-file status flags (Linux values)
+
+pub const O_RDWR: u32 = 0o2;
+/// Reads and writes that would block fail with `EAGAIN` instead.
+pub const O_NONBLOCK: u32 = 0o4000;
+
+// ---------------------------------------------------------------------------
+// lseek whence values (Linux)
+
+pub const SEEK_SET: u32 = 0;
+pub const SEEK_CUR: u32 = 1;
+pub const SEEK_END: u32 = 2;
+/// Next offset at or after the given one that holds data.
+pub const SEEK_DATA: u32 = 3;
+/// Next offset at or after the given one inside a hole (or end of file).
+pub const SEEK_HOLE: u32 = 4;
+
 // ---------------------------------------------------------------------------
 // FdObject — what a file descriptor actually refers to
 
@@ -117,6 +137,39 @@ impl FdObject {
         }
     }
 
+    /// Readiness as a mask of `POLL*` bits.  If `waker` is given it is
+    /// woken the next time the readiness may have changed; see
+    /// [`crate::poll`].
+    pub fn poll(&self, waker: Option<&core::task::Waker>) -> u32 {
+        match self {
+            FdObject::File(h) => h.poll_events(waker),
+            FdObject::Port(p) => p.lock().poll_events(waker),
+            FdObject::Irq(i) => i.lock().poll_events(waker),
+            FdObject::Channel(ChannelFd::Send(inner)) => inner.lock().poll_send(waker),
+            FdObject::Channel(ChannelFd::Recv(inner)) => inner.lock().poll_recv(waker),
+            FdObject::SharedMem(_) => DEFAULT_POLLMASK,
+            FdObject::Notify(n) => n.lock().poll_events(waker),
+        }
+    }
+
+    /// True if both refer to the same kernel object (and, for channels,
+    /// the same end).
+    pub fn same_object(&self, other: &FdObject) -> bool {
+        fn same<T: ?Sized, U: ?Sized>(a: &Arc<T>, b: &Arc<U>) -> bool {
+            Arc::as_ptr(a) as *const () == Arc::as_ptr(b) as *const ()
+        }
+        match (self, other) {
+            (FdObject::File(a), FdObject::File(b)) => same(a, b),
+            (FdObject::Port(a), FdObject::Port(b)) => same(a, b),
+            (FdObject::Irq(a), FdObject::Irq(b)) => same(a, b),
+            (FdObject::Channel(ChannelFd::Send(a)), FdObject::Channel(ChannelFd::Send(b))) => same(a, b),
+            (FdObject::Channel(ChannelFd::Recv(a)), FdObject::Channel(ChannelFd::Recv(b))) => same(a, b),
+            (FdObject::SharedMem(a), FdObject::SharedMem(b)) => same(a, b),
+            (FdObject::Notify(a), FdObject::Notify(b)) => same(a, b),
+            _ => false,
+        }
+    }
+
     /// Get the inner FileHandle, if this is a File.
     pub fn as_file(&self) -> Option<&Arc<dyn FileHandle>> {
         match self {
@@ -221,6 +274,49 @@ pub enum FileError {
     TooManyOpenFiles,
     #[snafu(display("interrupted system call"))]
     Interrupted,
+    #[snafu(display("input/output error"))]
+    IoError,
+    #[snafu(display("no space left on device"))]
+    NoSpace,
+    #[snafu(display("read-only file system"))]
+    ReadOnly,
+    #[snafu(display("file too large"))]
+    FileTooLarge,
+    #[snafu(display("invalid argument"))]
+    InvalidArgument,
+    #[snafu(display("illegal seek"))]
+    NotSeekable,
+    #[snafu(display("no such device or address"))]
+    NoSuchAddress,
+    /// A socket operation failed; see [`SocketError`](crate::socket::SocketError).
+    #[snafu(display("{error}"))]
+    Socket { error: crate::socket::SocketError },
+}
+
+/// The offset `lseek` moves to from `pos` in a file of `size` bytes that
+/// has no holes, or `InvalidArgument` if it would be negative.
+/// `SEEK_DATA` / `SEEK_HOLE` past the end fail with `NoSuchAddress`.
+pub fn seek_offset(pos: u64, size: u64, offset: i64, whence: u32) -> Result<u64, FileError> {
+    let base = match whence {
+        SEEK_SET => 0,
+        SEEK_CUR => pos,
+        SEEK_END => size,
+        SEEK_DATA | SEEK_HOLE => {
+            if offset < 0 {
+                return Err(FileError::InvalidArgument);
+            }
+            if offset as u64 >= size {
+                return Err(FileError::NoSuchAddress);
+            }
+            return Ok(if whence == SEEK_DATA { offset as u64 } else { size });
+        }
+        _ => return Err(FileError::InvalidArgument),
+    };
+    let target = base as i128 + offset as i128;
+    if target < 0 || target > i64::MAX as i128 {
+        return Err(FileError::InvalidArgument);
+    }
+    Ok(target as u64)
 }
 
 // ---------------------------------------------------------------------------
@@ -244,9 +340,67 @@ pub trait FileHandle: Send + Sync {
     }
 
     /// Return the full file content as a byte slice, if available.
-    /// Used by mmap to copy file data into mapped pages.
+    /// Used by mmap to copy file data into mapped pages when the handle
+    /// does not support [`map_page`](FileHandle::map_page).
     fn content_bytes(&self) -> Option<&[u8]> { None }
 
+    /// The page-cache frame holding page `index` (file offset / 4096) of
+    /// the file, with one frame reference taken for the caller's mapping.
+    /// Pages past the end of the file read as zeros.  May block.  Only
+    /// files served by the page cache support it.
+    fn map_page(&self, _index: u64) -> Result<PhysAddr, FileError> {
+        Err(FileError::NotSeekable)
+    }
+
+    /// A `MAP_SHARED` mapping wrote to `phys`, a frame returned by
+    /// [`map_page`](FileHandle::map_page): queue the page for write-back.
+    /// Must not block.
+    fn page_written(&self, _phys: PhysAddr) {}
+
+    /// Read at `offset` without moving the file position (pread).  Only
+    /// regular files and devices with no position support it.
+    fn read_at(&self, _offset: u64, _buf: &mut [u8]) -> Result<usize, FileError> {
+        Err(FileError::NotSeekable)
+    }
+
+    /// Write at `offset` without moving the file position (pwrite).
+    fn write_at(&self, _offset: u64, _buf: &[u8]) -> Result<usize, FileError> {
+        Err(FileError::NotSeekable)
+    }
+
+    /// Move the file position (lseek) and return the new one.  `whence` is
+    /// one of the `SEEK_*` values.  Pipes and terminals cannot seek.
+    fn seek(&self, _offset: i64, _whence: u32) -> Result<u64, FileError> {
+        Err(FileError::NotSeekable)
+    }
+
+    /// Set the file size (ftruncate).  Only writable regular files support it.
+    fn truncate(&self, _len: u64) -> Result<(), FileError> {
+        Err(FileError::InvalidArgument)
+    }
+
+    /// Flush written data to stable storage (fsync).
+    fn fsync(&self) -> Result<(), FileError> { Ok(()) }
+
+    /// Open-file status flags (`O_ACCMODE`, `O_APPEND`) reported by F_GETFL.
+    fn status_flags(&self) -> u32 { 0 }
+
+    /// Change the status flags F_SETFL may change.  Only sockets act on
+    /// one, `O_NONBLOCK`; other handles ignore them.
+    fn set_status_flags(&self, _flags: u32) {}
+
+    /// The canonical VFS path the handle was opened at, for fstat.  Pipes,
+    /// consoles and devices opened directly have none.
+    fn vfs_path(&self) -> Option<&str> { None }
+
+    /// Readiness as a mask of `POLL*` bits, for poll, select and epoll.
+    /// Handles whose readiness can change register `waker`, if given, and
+    /// wake it when it may have.  The default suits handles that never
+    /// block, such as regular files: always readable and writable.
+    fn poll_events(&self, _waker: Option<&core::task::Waker>) -> u32 {
+        DEFAULT_POLLMASK
+    }
+
     /// Async-capable read. Default delegates to sync `read()`.
     /// Handles that may block (pipe, console) should override to register
     /// the waker and return `Pending` instead of blocking a thread.
@@ -262,6 +416,21 @@ pub trait FileHandle: Send + Sync {
     {
         core::task::Poll::Ready(self.write(buf))
     }
+
+    /// Async-capable `read_at`. Default delegates to sync `read_at()`, so
+    /// handles whose `read_at` blocks must override it.
+    fn poll_read_at(&self, _cx: &mut core::task::Context<'_>, offset: u64, buf: &mut [u8])
+        -> core::task::Poll<Result<usize, FileError>>
+    {
+        core::task::Poll::Ready(self.read_at(offset, buf))
+    }
+
+    /// Async-capable `write_at`. Default delegates to sync `write_at()`.
+    fn poll_write_at(&self, _cx: &mut core::task::Context<'_>, offset: u64, buf: &[u8])
+        -> core::task::Poll<Result<usize, FileError>>
+    {
+        core::task::Poll::Ready(self.write_at(offset, buf))
+    }
 }
 
 // ---------------------------------------------------------------------------
@@ -307,6 +476,14 @@ impl FileHandle for ConsoleHandle {
         Ok(buf.len())
     }
 
+    fn poll_events(&self, waker: Option<&core::task::Waker>) -> u32 {
+        if self.readable && crate::console::poll_input(waker) {
+            POLL_READABLE | POLL_WRITABLE
+        } else {
+            POLL_WRITABLE
+        }
+    }
+
     fn kind(&self) -> &'static str { "console" }
 }
 
@@ -325,6 +502,8 @@ struct PipeInner {
     reader_thread: Option<usize>,
     /// Waker for async readers (completion port OP_READ).
     reader_waker: Option<core::task::Waker>,
+    /// poll / epoll waiters for the read end.
+    pollers: PollWaiters,
 }
 
 /// Read end of a pipe.
@@ -341,6 +520,7 @@ pub fn make_pipe() -> (PipeReader, PipeWriter) {
         writer_count: 1,
         reader_thread: None,
         reader_waker: None,
+        pollers: PollWaiters::new(),
     }));
     (PipeReader(inner.clone()), PipeWriter(inner))
 }
@@ -348,15 +528,14 @@ pub fn make_pipe() -> (PipeReader, PipeWriter) {
 impl FileHandle for PipeReader {
     fn read(&self, buf: &mut [u8]) -> Result<usize, FileError> {
         let pid = crate::process::current_pid();
+        let tid = crate::process::current_tid();
         loop {
-            // Register signal_thread before acquiring the pipe lock to avoid
-            // lock ordering inversion (terminate_process: process_table → pipe).
+            // Mark the thread interruptible before acquiring the pipe lock to
+            // avoid lock ordering inversion (terminate_process: process_table → pipe).
             // Best-effort: if a signal arrives before mark_blocked, unblock()
             // is a no-op, but we'll catch it on the next signal check.
             if pid != crate::process::ProcessId::KERNEL {
-                crate::process::with_process(pid, |p| {
-                    p.signal_thread = Some(crate::task::scheduler::current_thread_idx());
-                });
+                crate::process::with_process(pid, |p| p.set_interruptible(tid, true));
             }
 
             let mut inner = self.0.lock();
@@ -367,14 +546,14 @@ impl FileHandle for PipeReader {
                 }
                 drop(inner);
                 if pid != crate::process::ProcessId::KERNEL {
-                    crate::process::with_process(pid, |p| { p.signal_thread = None; });
+                    crate::process::with_process(pid, |p| p.set_interruptible(tid, false));
                 }
                 return Ok(count);
             }
             if inner.write_closed {
                 drop(inner);
                 if pid != crate::process::ProcessId::KERNEL {
-                    crate::process::with_process(pid, |p| { p.signal_thread = None; });
+                    crate::process::with_process(pid, |p| p.set_interruptible(tid, false));
                 }
                 return Ok(0); // EOF
             }
@@ -382,11 +561,11 @@ impl FileHandle for PipeReader {
             // Check for pending signals before blocking.
             if pid != crate::process::ProcessId::KERNEL {
                 let has_signal = crate::process::with_process_ref(pid, |p| {
-                    (p.signal.pending & !p.signal.blocked) != 0
+                    p.interrupted(tid)
                 }).unwrap_or(false);
                 if has_signal {
                     drop(inner);
-                    crate::process::with_process(pid, |p| { p.signal_thread = None; });
+                    crate::process::with_process(pid, |p| p.set_interruptible(tid, false));
                     return Err(FileError::Interrupted);
                 }
             }
@@ -395,11 +574,9 @@ impl FileHandle for PipeReader {
             crate::wait_condition::WaitCondition::wait_while(Some(inner), |inner, thread_idx| {
                 inner.reader_thread = Some(thread_idx);
             });
-            // Clear signal_thread after waking.
+            // Clear the interruptible mark after waking.
             if pid != crate::process::ProcessId::KERNEL {
-                crate::process::with_process(pid, |p| {
-                    p.signal_thread = None;
-                });
+                crate::process::with_process(pid, |p| p.set_interruptible(tid, false));
             }
         }
     }
@@ -426,10 +603,26 @@ impl FileHandle for PipeReader {
         Err(FileError::BadFd)
     }
 
+    /// Readable while data is buffered; `POLLHUP` once every writer has
+    /// closed.
+    fn poll_events(&self, waker: Option<&core::task::Waker>) -> u32 {
+        let mut inner = self.0.lock();
+        inner.pollers.register(waker);
+        let mut mask = 0;
+        if !inner.buffer.is_empty() {
+            mask |= POLL_READABLE;
+        }
+        if inner.write_closed {
+            mask |= POLLHUP;
+        }
+        mask
+    }
+
     fn kind(&self) -> &'static str { "pipe_r" }
 }
 
-/// Helper: wake both the scheduler thread and the async waker on a pipe.
+/// Helper: wake the scheduler thread, the async waker and the pollers on a
+/// pipe.
 ///
 /// Returns the thread index that was unblocked (if any), so the caller can
 /// use it for scheduler donate after dropping the pipe lock.
@@ -441,6 +634,7 @@ fn pipe_wake_reader(inner: &mut PipeInner) -> Option<usize> {
     if let Some(waker) = inner.reader_waker.take() {
         waker.wake();
     }
+    inner.pollers.wake_all();
     thread_idx
 }
 
@@ -478,6 +672,11 @@ impl FileHandle for PipeWriter {
         inner.writer_count += 1;
     }
 
+    /// The buffer is unbounded, so a pipe is always writable.
+    fn poll_events(&self, _waker: Option<&core::task::Waker>) -> u32 {
+        POLL_WRITABLE
+    }
+
     fn kind(&self) -> &'static str { "pipe_w" }
 }
 
@@ -495,3 +694,59 @@ pub fn default_fd_table() -> Vec<Option<FdEntry>> {
     table.push(Some(FdEntry::new(Arc::new(ConsoleHandle { readable: false })))); // fd 2 = stderr
     table
 }
+
+// ---------------------------------------------------------------------------
+// seek_offset
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{serial_print, serial_println};
+
+    #[test_case]
+    fn test_seek_offset_set_cur_end() {
+        serial_print!("test_seek_offset_set_cur_end... ");
+        assert_eq!(seek_offset(5, 100, 10, SEEK_SET).unwrap(), 10);
+        assert_eq!(seek_offset(5, 100, -2, SEEK_CUR).unwrap(), 3);
+        assert_eq!(seek_offset(5, 100, 20, SEEK_END).unwrap(), 120);
+        assert!(matches!(seek_offset(5, 100, -6, SEEK_CUR), Err(FileError::InvalidArgument)));
+        assert!(matches!(seek_offset(0, 0, 0, 7), Err(FileError::InvalidArgument)));
+        serial_println!("[ok]");
+    }
+
+    #[test_case]
+    fn test_seek_offset_data_hole() {
+        serial_print!("test_seek_offset_data_hole... ");
+        assert_eq!(seek_offset(0, 100, 40, SEEK_DATA).unwrap(), 40);
+        assert_eq!(seek_offset(0, 100, 40, SEEK_HOLE).unwrap(), 100);
+        assert!(matches!(seek_offset(0, 100, 100, SEEK_DATA), Err(FileError::NoSuchAddress)));
+        assert!(matches!(seek_offset(0, 100, 100, SEEK_HOLE), Err(FileError::NoSuchAddress)));
+        serial_println!("[ok]");
+    }
+
+    struct Flag(core::sync::atomic::AtomicBool);
+
+    impl alloc::task::Wake for Flag {
+        fn wake(self: Arc<Self>) {
+            self.0.store(true, core::sync::atomic::Ordering::SeqCst);
+        }
+    }
+
+    #[test_case]
+    fn test_pipe_poll_events() {
+        serial_print!("test_pipe_poll_events... ");
+        let (reader, writer) = make_pipe();
+        let flag = Arc::new(Flag(core::sync::atomic::AtomicBool::new(false)));
+        let waker = core::task::Waker::from(flag.clone());
+        assert_eq!(reader.poll_events(Some(&waker)), 0);
+        assert_eq!(writer.poll_events(None), POLL_WRITABLE);
+
+        writer.write(b"x").unwrap();
+        assert!(flag.0.load(core::sync::atomic::Ordering::SeqCst));
+        assert_eq!(reader.poll_events(None), POLL_READABLE);
+
+        writer.close();
+        assert_eq!(reader.poll_events(None), POLL_READABLE | POLLHUP);
+        serial_println!("[ok]");
+    }
+}
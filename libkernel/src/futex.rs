@@ -0,0 +1,396 @@
+//! Futexes — user-space wait queues keyed by physical address.
+//!
+//! A futex is named by the physical address of its 32-bit word, so the same
+//! word mapped at different virtual addresses (a `MAP_SHARED` shmem region
+//! in two processes) is one futex.  Copy-on-write pages are broken before
+//! the key is taken: otherwise a waiter could key on a frame that its own
+//! process stops using at the next write.
+//!
+//! Waiters live in a fixed hash table of buckets under a single lock.  A
+//! waiter is removed from its bucket by whoever wakes it, so a thread that
+//! resumes still queued was woken by a signal, an exiting thread group or
+//! its timeout instead.
+
+use alloc::vec::Vec;
+use snafu::Snafu;
+use x86_64::VirtAddr;
+use x86_64::structures::idt::PageFaultErrorCode;
+use x86_64::structures::paging::PageTableFlags;
+
+use crate::consts::{PAGE_MASK, USER_SPACE_END};
+use crate::memory::{with_memory, PAGE_COW};
+use crate::process::{self, ProcessId};
+use crate::spin_mutex::SpinMutex as Mutex;
+use crate::task::{scheduler, timer};
+use crate::wait_condition::WaitCondition;
+
+/// Bitset that matches every waiter (`FUTEX_WAIT`/`FUTEX_WAKE`).
+pub const FUTEX_BITSET_MATCH_ANY: u32 = 0xFFFF_FFFF;
+
+const FUTEX_HASH_BITS: u32 = 6;
+const FUTEX_BUCKETS: usize = 1 << FUTEX_HASH_BITS;
+
+#[derive(Debug, Snafu)]
+pub enum FutexError {
+    #[snafu(display("bad futex address"))]
+    Fault,
+    #[snafu(display("futex word does not hold the expected value"))]
+    WouldBlock,
+    #[snafu(display("futex wait timed out"))]
+    TimedOut,
+    #[snafu(display("futex wait interrupted"))]
+    Interrupted,
+}
+
+struct Waiter {
+    key: u64,
+    thread_idx: usize,
+    bitset: u32,
+}
+
+struct FutexTable {
+    buckets: [Vec<Waiter>; FUTEX_BUCKETS],
+}
+
+static FUTEXES: Mutex<FutexTable> = Mutex::new(FutexTable {
+    buckets: [const { Vec::new() }; FUTEX_BUCKETS],
+});
+
+fn bucket(key: u64) -> usize {
+    // Fibonacci hashing on the word index.
+    ((key >> 2).wrapping_mul(0x9E37_79B9_7F4A_7C15) >> (64 - FUTEX_HASH_BITS)) as usize
+}
+
+impl FutexTable {
+    /// Wake up to `n` waiters on `key` whose bitset intersects `bitset`,
+    /// oldest first.  Returns the number woken.
+    fn wake(&mut self, key: u64, n: u32, bitset: u32) -> u32 {
+        let queue = &mut self.buckets[bucket(key)];
+        let mut woken = 0;
+        let mut i = 0;
+        while i < queue.len() && woken < n {
+            if queue[i].key == key && queue[i].bitset & bitset != 0 {
+                let waiter = queue.remove(i);
+                scheduler::unblock(waiter.thread_idx);
+                woken += 1;
+            } else {
+                i += 1;
+            }
+        }
+        woken
+    }
+
+    /// Move up to `n` waiters from `from` to `to`, oldest first.  Returns the
+    /// number moved.
+    fn requeue(&mut self, from: u64, to: u64, n: u32) -> u32 {
+        let src = bucket(from);
+        let dst = bucket(to);
+        let mut moved = 0;
+        let mut i = 0;
+        while i < self.buckets[src].len() && moved < n {
+            if self.buckets[src][i].key == from {
+                let mut waiter = self.buckets[src].remove(i);
+                waiter.key = to;
+                self.buckets[dst].push(waiter);
+                moved += 1;
+                // If `dst == src` the waiter now sits at the end of this
+                // queue with key `to`, so the scan skips it.
+            } else {
+                i += 1;
+            }
+        }
+        moved
+    }
+
+    /// Drop `thread_idx` from whichever queue it is on.  Returns `false` if
+    /// it was not queued, i.e. it has already been woken by `wake`.
+    fn cancel(&mut self, thread_idx: usize) -> bool {
+        for queue in self.buckets.iter_mut() {
+            if let Some(pos) = queue.iter().position(|w| w.thread_idx == thread_idx) {
+                queue.remove(pos);
+                return true;
+            }
+        }
+        false
+    }
+}
+
+/// Physical address of the user `u32` at `uaddr` in process `pid`, which
+/// must be the running process.  Demand-pages the word in and breaks
+/// copy-on-write sharing first.
+fn futex_key(pid: ProcessId, uaddr: u64) -> Result<u64, FutexError> {
+    resolve_word(pid, uaddr, false)
+}
+
+/// Physical address of the user `u32` at `uaddr`, as for [`futex_key`].
+/// With `write`, the word must also be in a writable mapping.
+fn resolve_word(pid: ProcessId, uaddr: u64, write: bool) -> Result<u64, FutexError> {
+    if uaddr == 0 || uaddr & 3 != 0 || uaddr >= USER_SPACE_END {
+        return Err(FutexError::Fault);
+    }
+    let pml4_phys = process::with_process_ref(pid, |p| p.pml4_phys)
+        .ok_or(FutexError::Fault)?;
+    let page = VirtAddr::new(uaddr & !PAGE_MASK);
+    let access = if write { PageFaultErrorCode::CAUSED_BY_WRITE } else { PageFaultErrorCode::empty() };
+
+    let mapped = with_memory(|mem| mem.user_page_entry(pml4_phys, page).is_some());
+    if !mapped && !crate::memory::fault::resolve_user_fault(pid, uaddr, access) {
+        return Err(FutexError::Fault);
+    }
+
+    with_memory(|mem| {
+        let (_, flags) = mem.user_page_entry(pml4_phys, page)?;
+        if flags.contains(PAGE_COW) && !mem.break_cow(pml4_phys, page) {
+            return None;
+        }
+        let (phys, flags) = mem.user_page_entry(pml4_phys, page)?;
+        if !flags.contains(PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE)
+            || (write && !flags.contains(PageTableFlags::WRITABLE))
+        {
+            return None;
+        }
+        Some(phys.as_u64() + (uaddr & PAGE_MASK))
+    }).ok_or(FutexError::Fault)
+}
+
+/// Store `value` in the user word at `uaddr` of process `pid`, which must
+/// be the running process: the `CLONE_*_SETTID` and `clear_child_tid`
+/// words.  The store goes through the physical map, so a bad address
+/// cannot fault in the kernel.  Returns `false`, storing nothing, if the
+/// word is not mapped writable, as Linux skips it.
+pub fn put_user_word(pid: ProcessId, uaddr: u64, value: u32) -> bool {
+    match resolve_word(pid, uaddr, true) {
+        Ok(phys) => {
+            let ptr = (crate::memory::phys_mem_offset() + phys) as *mut u32;
+            unsafe { core::ptr::write_volatile(ptr, value); }
+            true
+        }
+        Err(_) => false,
+    }
+}
+
+/// Read the futex word.  The caller has resolved its key, so it is mapped.
+fn read_word(uaddr: u64) -> u32 {
+    unsafe { core::ptr::read_volatile(uaddr as *const u32) }
+}
+
+/// `FUTEX_WAIT` / `FUTEX_WAIT_BITSET` for thread `tid` of the running
+/// process: block while the word at `uaddr` holds `expected`.
+///
+/// `deadline` is an absolute tick count.  Returns `Ok` when woken by
+/// [`wake`] (or spuriously), `WouldBlock` if the word had changed,
+/// `Interrupted` for a deliverable signal or group exit, and `TimedOut`.
+pub fn wait(
+    pid: ProcessId,
+    tid: ProcessId,
+    uaddr: u64,
+    expected: u32,
+    bitset: u32,
+    deadline: Option<u64>,
+) -> Result<(), FutexError> {
+    let key = futex_key(pid, uaddr)?;
+
+    // Syscalls run with interrupts disabled, so no other thread can store to
+    // the word between this check and the thread being queued.
+    let table = FUTEXES.lock();
+    if read_word(uaddr) != expected {
+        return Err(FutexError::WouldBlock);
+    }
+    if process::with_process_ref(pid, |p| p.interrupted(tid)).unwrap_or(true) {
+        return Err(FutexError::Interrupted);
+    }
+    if deadline.map_or(false, |d| timer::ticks() >= d) {
+        return Err(FutexError::TimedOut);
+    }
+
+    WaitCondition::wait_while(Some(table), |table, thread_idx| {
+        table.buckets[bucket(key)].push(Waiter { key, thread_idx, bitset });
+        process::with_process(pid, |p| p.set_interruptible(tid, true));
+        if let Some(d) = deadline {
+            scheduler::set_wake_deadline(d);
+        }
+    });
+
+    if deadline.is_some() {
+        scheduler::clear_wake_deadline();
+    }
+    let interrupted = process::with_process(pid, |p| {
+        p.set_interruptible(tid, false);
+        p.interrupted(tid)
+    }).unwrap_or(true);
+
+    if !FUTEXES.lock().cancel(scheduler::current_thread_idx()) {
+        return Ok(());
+    }
+    if interrupted {
+        Err(FutexError::Interrupted)
+    } else if deadline.map_or(false, |d| timer::ticks() >= d) {
+        Err(FutexError::TimedOut)
+    } else {
+        Ok(())
+    }
+}
+
+/// `FUTEX_WAKE` / `FUTEX_WAKE_BITSET`: wake up to `n` waiters on the word at
+/// `uaddr` in the running process `pid`.  Returns the number woken.
+pub fn wake(pid: ProcessId, uaddr: u64, n: u32, bitset: u32) -> Result<u32, FutexError> {
+    let key = futex_key(pid, uaddr)?;
+    Ok(FUTEXES.lock().wake(key, n, bitset))
+}
+
+/// `FUTEX_REQUEUE` / `FUTEX_CMP_REQUEUE`: wake up to `n_wake` waiters on
+/// `uaddr` and move up to `n_requeue` of the rest onto `uaddr2`.
+///
+/// With `expected`, fails with `WouldBlock` unless the word at `uaddr`
+/// still holds it.  Returns the number woken plus the number moved.
+pub fn requeue(
+    pid: ProcessId,
+    uaddr: u64,
+    n_wake: u32,
+    uaddr2: u64,
+    n_requeue: u32,
+    expected: Option<u32>,
+) -> Result<u32, FutexError> {
+    let key = futex_key(pid, uaddr)?;
+    let key2 = futex_key(pid, uaddr2)?;
+
+    let mut table = FUTEXES.lock();
+    if let Some(expected) = expected {
+        if read_word(uaddr) != expected {
+            return Err(FutexError::WouldBlock);
+        }
+    }
+    let woken = table.wake(key, n_wake, FUTEX_BITSET_MATCH_ANY);
+    let moved = if key == key2 { 0 } else { table.requeue(key, key2, n_requeue) };
+    Ok(woken + moved)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{serial_print, serial_println};
+    use crate::memory::{kernel_pml4_phys, with_memory};
+    use crate::process::{self, Process, ProcessId};
+    use x86_64::structures::paging::PageTableFlags;
+    use x86_64::{PhysAddr, VirtAddr};
+    use super::*;
+
+    /// A user page mapped into the running (kernel) address space, so the
+    /// futex code can read its words directly.
+    const PAGE: u64 = 0x6000_0000_0000;
+
+    /// Thread indices no scheduler thread has: waking them is a no-op.
+    const FAKE_THREAD: usize = 100_000;
+
+    /// A process whose address space is the running one, with `PAGE`
+    /// mapped with `flags` and its first word set to `value`.
+    fn setup(flags: PageTableFlags, value: u32) -> ProcessId {
+        let pml4 = PhysAddr::new(kernel_pml4_phys());
+        let phys = with_memory(|mem| {
+            assert!(mem.user_page_entry(pml4, VirtAddr::new(PAGE)).is_none());
+            mem.alloc_and_map_user_pages(1, PAGE, pml4, flags).unwrap();
+            mem.user_page_entry(pml4, VirtAddr::new(PAGE)).unwrap().0
+        });
+        // Through the physical map: the page may be read-only.
+        let ptr = (crate::memory::phys_mem_offset() + phys.as_u64()) as *mut u32;
+        unsafe { core::ptr::write_volatile(ptr, value); }
+        process::insert(Process::new(pml4, 0, 0, 0))
+    }
+
+    fn teardown(pid: ProcessId) {
+        let pml4 = PhysAddr::new(kernel_pml4_phys());
+        with_memory(|mem| mem.unmap_and_free_user_page(pml4, VirtAddr::new(PAGE), true));
+        process::reap(pid);
+    }
+
+    /// Queue `n` pretend waiters on the word at `uaddr`.
+    fn queue(pid: ProcessId, uaddr: u64, n: usize, bitset: u32) {
+        let key = futex_key(pid, uaddr).unwrap();
+        let mut table = FUTEXES.lock();
+        for i in 0..n {
+            table.buckets[bucket(key)].push(Waiter { key, thread_idx: FAKE_THREAD + i, bitset });
+        }
+    }
+
+    fn queued(pid: ProcessId, uaddr: u64) -> usize {
+        let key = futex_key(pid, uaddr).unwrap();
+        FUTEXES.lock().buckets[bucket(key)].iter().filter(|w| w.key == key).count()
+    }
+
+    const RW: PageTableFlags = PageTableFlags::PRESENT
+        .union(PageTableFlags::WRITABLE)
+        .union(PageTableFlags::USER_ACCESSIBLE);
+    const RO: PageTableFlags = PageTableFlags::PRESENT
+        .union(PageTableFlags::USER_ACCESSIBLE);
+
+    #[test_case]
+    fn test_futex_wait_value_mismatch() {
+        serial_print!("test_futex_wait_value_mismatch... ");
+        let pid = setup(RW, 7);
+        let r = wait(pid, pid, PAGE, 8, FUTEX_BITSET_MATCH_ANY, None);
+        assert!(matches!(r, Err(FutexError::WouldBlock)));
+        assert_eq!(queued(pid, PAGE), 0);
+        // Misaligned and kernel addresses are faults.
+        assert!(matches!(wait(pid, pid, PAGE + 2, 7, FUTEX_BITSET_MATCH_ANY, None),
+            Err(FutexError::Fault)));
+        assert!(matches!(wake(pid, 0xFFFF_8000_0000_0000, 1, FUTEX_BITSET_MATCH_ANY),
+            Err(FutexError::Fault)));
+        teardown(pid);
+        serial_println!("[ok]");
+    }
+
+    #[test_case]
+    fn test_futex_wake_counts_waiters() {
+        serial_print!("test_futex_wake_counts_waiters... ");
+        let pid = setup(RW, 0);
+        queue(pid, PAGE, 3, FUTEX_BITSET_MATCH_ANY);
+        assert_eq!(wake(pid, PAGE, 2, FUTEX_BITSET_MATCH_ANY).unwrap(), 2);
+        assert_eq!(queued(pid, PAGE), 1);
+        assert_eq!(wake(pid, PAGE, 5, FUTEX_BITSET_MATCH_ANY).unwrap(), 1);
+        assert_eq!(wake(pid, PAGE, 5, FUTEX_BITSET_MATCH_ANY).unwrap(), 0);
+
+        // A waiter is woken only by a bitset it shares.
+        queue(pid, PAGE, 1, 0b01);
+        assert_eq!(wake(pid, PAGE, 1, 0b10).unwrap(), 0);
+        assert_eq!(wake(pid, PAGE, 1, 0b11).unwrap(), 1);
+        teardown(pid);
+        serial_println!("[ok]");
+    }
+
+    #[test_case]
+    fn test_futex_cmp_requeue_moves_waiters() {
+        serial_print!("test_futex_cmp_requeue_moves_waiters... ");
+        let pid = setup(RW, 1);
+        let other = PAGE + 4;
+        queue(pid, PAGE, 4, FUTEX_BITSET_MATCH_ANY);
+
+        // The word changed: nothing happens.
+        assert!(matches!(requeue(pid, PAGE, 1, other, 8, Some(2)), Err(FutexError::WouldBlock)));
+        assert_eq!(queued(pid, PAGE), 4);
+
+        // One woken, two moved, one left behind.
+        assert_eq!(requeue(pid, PAGE, 1, other, 2, Some(1)).unwrap(), 3);
+        assert_eq!(queued(pid, PAGE), 1);
+        assert_eq!(queued(pid, other), 2);
+        assert_eq!(wake(pid, other, 8, FUTEX_BITSET_MATCH_ANY).unwrap(), 2);
+        assert_eq!(wake(pid, PAGE, 8, FUTEX_BITSET_MATCH_ANY).unwrap(), 1);
+        teardown(pid);
+        serial_println!("[ok]");
+    }
+
+    #[test_case]
+    fn test_put_user_word_needs_writable_mapping() {
+        serial_print!("test_put_user_word_needs_writable_mapping... ");
+        let pid = setup(RW, 0);
+        assert!(put_user_word(pid, PAGE + 8, 42));
+        assert_eq!(unsafe { core::ptr::read_volatile((PAGE + 8) as *const u32) }, 42);
+        // No VMA covers the next page, so it cannot be faulted in.
+        assert!(!put_user_word(pid, PAGE + 0x1000, 42));
+        teardown(pid);
+
+        let pid = setup(RO, 7);
+        assert!(!put_user_word(pid, PAGE, 42));
+        assert_eq!(unsafe { core::ptr::read_volatile(PAGE as *const u32) }, 7);
+        teardown(pid);
+        serial_println!("[ok]");
+    }
+}
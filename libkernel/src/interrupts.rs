@@ -232,11 +232,16 @@ fn configure_pit_100hz() {
 }
 
 /// Common cleanup for ring-3 process death (page fault, GPF, invalid opcode):
-/// mark zombie and wake parent's wait_thread so `wait4` returns.
+/// mark zombie and wake parent's wait_threads so `wait4` returns.
 fn kill_user_process(pid: process::ProcessId, exit_code: i32) {
     if pid == process::ProcessId::KERNEL {
         return;
     }
+    // The faulting thread dies here; take the rest of the group with it.
+    process::with_process(pid, |p| {
+        p.group_exit_code.get_or_insert(exit_code);
+    });
+    process::kill_other_threads(pid, process::current_tid());
     let parent_pid = process::with_process_ref(pid, |p| p.parent_pid);
     process::mark_zombie(pid, exit_code);
     if let Some(parent_pid) = parent_pid {
@@ -245,8 +250,9 @@ fn kill_user_process(pid: process::ProcessId, exit_code: i32) {
                 pp.signal.queue(crate::signal::SIGCHLD);
             });
         }
-        let wait_thread = process::with_process(parent_pid, |pp| pp.wait_thread.take());
-        if let Some(Some(thread_idx)) = wait_thread {
+        let waiters = process::with_process(parent_pid, |pp| core::mem::take(&mut pp.wait_threads))
+            .unwrap_or_default();
+        for thread_idx in waiters {
             task::scheduler::unblock(thread_idx);
         }
     }
@@ -450,8 +456,17 @@ extern "x86-interrupt" fn page_fault_handler(
     let cr2_raw: u64;
     unsafe { core::arch::asm!("mov {}, cr2", out(reg) cr2_raw, options(nostack, nomem)); }
 
+    let from_user = stack_frame.code_segment.rpl() == x86_64::PrivilegeLevel::Ring3;
+
+    // Demand paging: populate lazily-allocated user pages on first touch.
+    // Ring-0 faults on user addresses are included because syscall handlers
+    // access user buffers directly.
+    if crate::memory::fault::resolve_user_fault(process::current_pid(), cr2_raw, error_code) {
+        return;
+    }
+
     // Check whether the fault came from ring 3 (RPL field of the saved CS).
-    if stack_frame.code_segment.rpl() == x86_64::PrivilegeLevel::Ring3 {
+    if from_user {
         let pid = process::current_pid();
         let rip = stack_frame.instruction_pointer.as_u64();
         let rsp = stack_frame.stack_pointer.as_u64();
@@ -523,6 +538,7 @@ extern "x86-interrupt" fn keyboard_interrupt_handler(
     let mut port = Port::new(0x60);
     let scancode: u8 = unsafe { port.read() };
     crate::task::keyboard::add_scancode(scancode);
+    crate::random::add_interrupt_randomness(InterruptIndex::Keyboard.as_u8() as u64, scancode as u64);
     send_eoi(InterruptIndex::Keyboard.as_u8());
 }
 
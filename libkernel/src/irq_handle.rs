@@ -6,6 +6,7 @@ use core::sync::atomic::{AtomicU64, Ordering};
 
 use crate::completion_port::{Completion, CompletionPort, OP_IRQ_WAIT};
 use crate::irq_mutex::IrqMutex;
+use crate::poll::{PollWaiters, POLL_READABLE};
 
 // ---------------------------------------------------------------------------
 // Per-slot IRQ counters (lock-free, safe from ISR context)
@@ -151,6 +152,8 @@ pub struct IrqInner {
     mouse_event_buf: [i64; MOUSE_EVENT_BUF_SIZE],
     mouse_event_head: usize,
     mouse_event_tail: usize,
+    /// poll / epoll waiters, woken when an event is buffered.
+    pollers: PollWaiters,
 }
 
 impl IrqInner {
@@ -166,6 +169,19 @@ impl IrqInner {
             mouse_event_buf: [0; MOUSE_EVENT_BUF_SIZE],
             mouse_event_head: 0,
             mouse_event_tail: 0,
+            pollers: PollWaiters::new(),
+        }
+    }
+
+    /// `POLLIN` while scancodes or mouse events are buffered.
+    pub fn poll_events(&mut self, waker: Option<&core::task::Waker>) -> u32 {
+        self.pollers.register(waker);
+        if self.scancode_head != self.scancode_tail
+            || self.mouse_event_head != self.mouse_event_tail
+        {
+            POLL_READABLE
+        } else {
+            0
         }
     }
 
@@ -174,6 +190,7 @@ impl IrqInner {
         if next != self.scancode_head {
             self.scancode_buf[self.scancode_tail] = code;
             self.scancode_tail = next;
+            self.pollers.wake_all();
         }
     }
 
@@ -192,6 +209,7 @@ impl IrqInner {
         if next != self.mouse_event_head {
             self.mouse_event_buf[self.mouse_event_tail] = event;
             self.mouse_event_tail = next;
+            self.pollers.wake_all();
         }
     }
 
@@ -243,6 +261,7 @@ pub fn irq_fd_dispatch(slot: usize) {
     let mut inner = inner_arc.lock();
     let counters = &IRQ_COUNTERS[slot];
     counters.total.fetch_add(1, Ordering::Relaxed);
+    crate::random::add_interrupt_randomness(inner.gsi as u64, 0);
 
     if inner.gsi == 1 || inner.gsi == 12 {
         // Drain all available bytes from the i8042 output buffer in one ISR.
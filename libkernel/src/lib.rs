@@ -53,6 +53,13 @@ pub mod notify;
 pub mod service;
 pub mod ps2;
 pub mod wait_condition;
+pub mod poll;
+pub mod socket;
+pub mod unix_socket;
+pub mod futex;
+pub mod time;
+pub mod vdso;
+pub mod random;
 
 pub fn init() {
     cpuid::init();
@@ -67,12 +74,15 @@ pub fn init() {
 /// Clears CR0.EM (no x87 emulation), sets CR0.MP (monitor coprocessor),
 /// sets CR4.OSFXSR (enable FXSAVE/FXRSTOR) and CR4.OSXMMEXCPT (enable
 /// unmasked SIMD floating-point exceptions via #XM instead of #UD).
+/// Also sets CR0.WP so kernel writes to read-only user pages fault, which
+/// copy-on-write relies on.
 fn enable_sse() {
     use x86_64::registers::control::{Cr0, Cr0Flags, Cr4, Cr4Flags};
     unsafe {
         let mut cr0 = Cr0::read();
         cr0.remove(Cr0Flags::EMULATE_COPROCESSOR);   // clear EM
         cr0.insert(Cr0Flags::MONITOR_COPROCESSOR);    // set MP
+        cr0.insert(Cr0Flags::WRITE_PROTECT);          // ring-0 writes honour RO pages (COW)
         Cr0::write(cr0);
 
         let mut cr4 = Cr4::read();
@@ -116,6 +126,8 @@ pub fn test_kernel_main(boot_info: &'static BootInfo) -> ! {
     };
     allocator::init_heap(&mut mapper, &mut frame_allocator)
         .expect("heap initialization failed");
+    memory::init_services(mapper, frame_allocator, phys_mem_offset, &boot_info.memory_map);
+    stack_arena::init();
     test_main();
     hlt_loop();
 }
@@ -0,0 +1,113 @@
+//! User page-fault resolution.
+//!
+//! Three kinds of fault are resolved here, from `interrupts::page_fault_handler`:
+//!
+//! - **Demand paging.** Anonymous `mmap` regions and the `brk` heap are
+//!   populated lazily: the syscalls only record the region, and the first
+//!   touch of each page maps a zeroed frame with the region's protection.
+//! - **File mappings.** The first touch of a page of a mapped file maps
+//!   its page-cache frame (see [`mapped_file`](super::mapped_file)).
+//! - **Copy-on-write.** After `fork()` private pages are shared read-only
+//!   and tagged [`PAGE_COW`](super::PAGE_COW); a write gets a private copy.
+//!   Private file pages start out the same way.
+//!
+//! Faults taken in ring 0 on user addresses are resolved the same way,
+//! because syscall handlers dereference user pointers directly (CR0.WP is
+//! set so kernel writes to COW pages fault too).
+//!
+//! When a zeroed page or a COW copy finds the frame allocator empty, the
+//! page cache is asked to give back a clean frame
+//! ([`reclaim_frame`](super::reclaim_frame)) and the fault is retried once
+//! before it is reported.
+
+use x86_64::VirtAddr;
+use x86_64::structures::idt::PageFaultErrorCode;
+
+use super::{MemoryServices, PAGE_COW};
+use crate::consts::{PAGE_MASK, USER_SPACE_END};
+use crate::process::{self, ProcessId, PROT_EXEC, PROT_NONE, PROT_WRITE};
+
+/// Try to resolve a page fault at `addr` for process `pid`.
+///
+/// Returns `true` if a page was mapped and the faulting instruction can be
+/// restarted, `false` if the fault is a genuine access violation.
+pub fn resolve_user_fault(pid: ProcessId, addr: u64, error_code: PageFaultErrorCode) -> bool {
+    if pid == ProcessId::KERNEL || addr >= USER_SPACE_END {
+        return false;
+    }
+    let page_base = addr & !PAGE_MASK;
+
+    // A protection violation means the page is present; the only fixable
+    // case is a write to a copy-on-write page.
+    if error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION) {
+        if !error_code.contains(PageFaultErrorCode::CAUSED_BY_WRITE) {
+            return false;
+        }
+        let pml4_phys = match process::with_process_ref(pid, |p| p.pml4_phys) {
+            Some(v) => v,
+            None => return false,
+        };
+        let vaddr = VirtAddr::new(page_base);
+        return with_reclaim(|mem| {
+            let is_cow = mem.user_page_entry(pml4_phys, vaddr)
+                .map_or(false, |(_, flags)| flags.contains(PAGE_COW));
+            if !is_cow {
+                return Err(false);
+            }
+            if mem.break_cow(pml4_phys, vaddr) { Ok(()) } else { Err(true) }
+        });
+    }
+
+    let (pml4_phys, prot) = match process::with_process_ref(pid, |p| {
+        (p.pml4_phys, p.demand_prot(page_base))
+    }) {
+        Some((pml4_phys, Some(prot))) => (pml4_phys, prot),
+        Some((_, None)) => return super::mapped_file::fault_in(pid, page_base, error_code),
+        None => return false,
+    };
+
+    if !access_permitted(prot, error_code) {
+        return false;
+    }
+
+    let vaddr = VirtAddr::new(page_base);
+    let mapped = with_reclaim(|mem| {
+        // An entry that holds a frame without PRESENT is a PROT_NONE page,
+        // not a hole; leave it to the caller to report.
+        if mem.user_page_entry(pml4_phys, vaddr).is_some() {
+            return Err(false);
+        }
+        mem.alloc_and_map_user_pages(1, page_base, pml4_phys, process::prot_to_page_flags(prot))
+            .map_err(|()| true)
+    });
+    if mapped {
+        x86_64::instructions::tlb::flush(vaddr);
+    }
+    mapped
+}
+
+/// Run `f` with the memory services.  It fails with `Err(true)` if it
+/// needed a frame and none was free: then a cached frame is reclaimed and
+/// `f` runs once more.  Returns whether `f` succeeded.
+fn with_reclaim(mut f: impl FnMut(&mut MemoryServices) -> Result<(), bool>) -> bool {
+    match super::with_memory(&mut f) {
+        Ok(()) => true,
+        Err(true) => super::reclaim_frame() && super::with_memory(f).is_ok(),
+        Err(false) => false,
+    }
+}
+
+/// Check the faulting access against the region's `PROT_*` flags.
+pub(super) fn access_permitted(prot: u32, error_code: PageFaultErrorCode) -> bool {
+    if prot == PROT_NONE {
+        return false;
+    }
+    if error_code.contains(PageFaultErrorCode::CAUSED_BY_WRITE) {
+        return prot & PROT_WRITE != 0;
+    }
+    if error_code.contains(PageFaultErrorCode::INSTRUCTION_FETCH) {
+        return prot & PROT_EXEC != 0;
+    }
+    // Every present x86-64 user page is readable.
+    true
+}
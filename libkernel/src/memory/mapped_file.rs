@@ -0,0 +1,115 @@
+//! File mappings served from the page cache.
+//!
+//! `mmap` of a regular file only records the region; the first touch of
+//! each page maps the file's page-cache frame, obtained through
+//! [`FileHandle::map_page`](crate::file::FileHandle::map_page).  Every
+//! process mapping a file, and `read`/`write` on it, use the same frame.
+//!
+//! - **`MAP_PRIVATE`.** Pages are mapped read-only; in a writable region
+//!   they are also tagged [`PAGE_COW`], so the first write takes a second
+//!   fault and `break_cow` gives the process its own copy.
+//! - **`MAP_SHARED`.** Pages are mapped with the region's protection and
+//!   writes land in the cached page.  The CPU sets the page-table `DIRTY`
+//!   bit; [`sync_shared`] collects those pages and hands them to
+//!   `FileHandle::page_written`, which queues them for write-back.  It runs
+//!   on `msync`, `munmap`, and before an address space is torn down by
+//!   exit or `execve`.
+
+use alloc::vec::Vec;
+use x86_64::VirtAddr;
+use x86_64::structures::idt::PageFaultErrorCode;
+use x86_64::structures::paging::PageTableFlags;
+
+use super::PAGE_COW;
+use crate::consts::PAGE_SIZE;
+use crate::process::{self, ProcessId};
+
+/// Map the file page behind `page_base` after a not-present fault.
+///
+/// Returns `false` if the address is not in a page-cache file mapping, the
+/// access is not permitted, or the page cannot be read.  Blocks while the
+/// page is read from the filesystem.
+pub fn fault_in(pid: ProcessId, page_base: u64, error_code: PageFaultErrorCode) -> bool {
+    let (pml4_phys, (file, index, prot, shared)) = match process::with_process_ref(pid, |p| {
+        (p.pml4_phys, p.file_page(page_base))
+    }) {
+        Some((pml4_phys, Some(page))) => (pml4_phys, page),
+        _ => return false,
+    };
+
+    if !super::fault::access_permitted(prot, error_code) {
+        return false;
+    }
+
+    let phys = match file.map_page(index) {
+        Ok(phys) => phys,
+        Err(_) => return false,
+    };
+
+    // The region may have been unmapped or replaced while the page was read.
+    let still_mapped = process::with_process_ref(pid, |p| {
+        p.file_page(page_base)
+            .map_or(false, |(f, i, _, _)| alloc::sync::Arc::ptr_eq(&f, &file) && i == index)
+    }).unwrap_or(false);
+    if !still_mapped {
+        super::with_memory(|mem| mem.release_shared_frame(phys));
+        return false;
+    }
+
+    let mut flags = process::prot_to_page_flags(prot);
+    if !shared && flags.contains(PageTableFlags::WRITABLE) {
+        flags = (flags - PageTableFlags::WRITABLE) | PAGE_COW;
+    }
+
+    let vaddr = VirtAddr::new(page_base);
+    let mapped = super::with_memory(|mem| {
+        // Another thread faulted the page in first: retry against its entry.
+        if mem.user_page_entry(pml4_phys, vaddr).is_some() {
+            mem.release_shared_frame(phys);
+            return true;
+        }
+        if mem.map_user_page(pml4_phys, vaddr, phys, flags).is_ok() {
+            true
+        } else {
+            mem.release_shared_frame(phys);
+            false
+        }
+    });
+    if mapped {
+        x86_64::instructions::tlb::flush(vaddr);
+    }
+    mapped
+}
+
+/// Queue for write-back every page of `pid`'s `MAP_SHARED` file mappings
+/// in `[start, end)` written since the last call.
+///
+/// The address space must be the active one, so that clearing a page's
+/// `DIRTY` bit also drops its TLB entry and the next write sets it again.
+pub fn sync_shared(pid: ProcessId, start: u64, end: u64) {
+    let (pml4_phys, ranges) = match process::with_process_ref(pid, |p| {
+        (p.pml4_phys, p.shared_file_ranges(start, end))
+    }) {
+        Some(v) => v,
+        None => return,
+    };
+    if ranges.is_empty() {
+        return;
+    }
+
+    let mut written = Vec::new();
+    super::with_memory(|mem| {
+        for (range_start, range_end, file) in &ranges {
+            for page in (*range_start..*range_end).step_by(PAGE_SIZE as usize) {
+                if let Some(phys) = mem.take_dirty_page(pml4_phys, VirtAddr::new(page), true) {
+                    written.push((file.clone(), phys));
+                }
+            }
+        }
+    });
+
+    // Outside the memory lock: the page cache takes its own lock first.
+    for (file, phys) in written {
+        file.page_written(phys);
+    }
+}
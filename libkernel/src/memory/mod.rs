@@ -6,6 +6,7 @@ use x86_64::structures::paging::{
     OffsetPageTable,
     Page,
     PageTable,
+    PageTableEntry,
     PageTableFlags,
     PageTableIndex,
     PhysFrame,
@@ -18,7 +19,9 @@ use x86_64::structures::paging::{
 };
 use bootloader::bootinfo::{MemoryMap, MemoryRegion};
 
+pub mod fault;
 pub mod frame_allocator;
+pub mod mapped_file;
 pub mod vmem_allocator;
 
 pub use frame_allocator::BootInfoFrameAllocator;
@@ -45,6 +48,17 @@ pub const MMIO_VIRT_BASE: u64 = 0xFFFF_8002_0000_0000;
 /// Size of the MMIO virtual window (512 GiB).
 const MMIO_VIRT_SIZE: u64 = 0x0000_0080_0000_0000;
 
+/// Software-defined PTE bit (available bit 9) marking a private page that is
+/// read-only only because its frame is shared copy-on-write after `fork()`.
+pub const PAGE_COW: PageTableFlags = PageTableFlags::BIT_9;
+
+/// Flags for intermediate user page-table entries.  Permissions are enforced
+/// at the leaf only, so a leaf can later become writable (mprotect, COW
+/// break) without touching the upper levels.
+const USER_TABLE_FLAGS: PageTableFlags = PageTableFlags::PRESENT
+    .union(PageTableFlags::WRITABLE)
+    .union(PageTableFlags::USER_ACCESSIBLE);
+
 // ---------------------------------------------------------------------------
 // Global physical memory offset (set once during init, readable from anywhere)
 
@@ -362,7 +376,8 @@ impl MemoryServices {
         let page  = Page::<Size4KiB>::containing_address(virt);
         let frame = PhysFrame::<Size4KiB>::containing_address(phys);
         unsafe {
-            table.map_to(page, frame, flags, &mut self.frame_allocator)?.ignore();
+            table.map_to_with_table_flags(page, frame, flags, USER_TABLE_FLAGS, &mut self.frame_allocator)?
+                .ignore();
         }
         Ok(())
     }
@@ -370,29 +385,25 @@ impl MemoryServices {
     /// Unmap a single 4 KiB page from a page table rooted at `pml4_phys`.
     ///
     /// Returns the physical frame that was mapped, or `None` if the page was
-    /// not mapped. If `flush_tlb` is true the TLB entry is invalidated (use
-    /// when the target PML4 is the active address space).
+    /// not mapped.  Entries that hold a frame without `PRESENT` (PROT_NONE
+    /// pages) are unmapped too.  If `flush_tlb` is true the TLB entry is
+    /// invalidated (use when the target PML4 is the active address space).
     pub fn unmap_user_page(
         &mut self,
         pml4_phys: PhysAddr,
         vaddr: VirtAddr,
         flush_tlb: bool,
     ) -> Option<PhysFrame> {
-        let pml4_virt = self.phys_mem_offset + pml4_phys.as_u64();
-        let pml4: &mut PageTable = unsafe { &mut *pml4_virt.as_mut_ptr() };
-        let mut table = unsafe { OffsetPageTable::new(pml4, self.phys_mem_offset) };
-        let page = Page::<Size4KiB>::containing_address(vaddr);
-        match table.unmap(page) {
-            Ok((frame, flush_token)) => {
-                if flush_tlb {
-                    flush_token.flush();
-                } else {
-                    flush_token.ignore();
-                }
-                Some(frame)
-            }
-            Err(_) => None,
+        let entry = unsafe { &mut *self.leaf_entry(pml4_phys, vaddr)? };
+        if entry.is_unused() {
+            return None;
+        }
+        let frame = PhysFrame::containing_address(entry.addr());
+        entry.set_unused();
+        if flush_tlb {
+            x86_64::instructions::tlb::flush(vaddr);
         }
+        Some(frame)
     }
 
     /// Unmap a single user page and return its frame to the free list.
@@ -474,6 +485,216 @@ impl MemoryServices {
         }
     }
 
+    /// Look up the leaf 4 KiB entry for `vaddr` in a page table rooted at
+    /// `pml4_phys`, returning its frame address and flags.
+    ///
+    /// Unlike `translate_virt` this works on non-active page tables and also
+    /// reports entries that hold a frame without `PRESENT` (PROT_NONE pages).
+    /// Returns `None` if an intermediate table is missing or the leaf entry
+    /// is unused.
+    pub fn user_page_entry(
+        &self,
+        pml4_phys: PhysAddr,
+        vaddr: VirtAddr,
+    ) -> Option<(PhysAddr, PageTableFlags)> {
+        let entry = unsafe { &*self.leaf_entry(pml4_phys, vaddr)? };
+        if entry.is_unused() {
+            None
+        } else {
+            Some((entry.addr(), entry.flags()))
+        }
+    }
+
+    /// Walk to the level-1 entry for `vaddr` without allocating tables.
+    ///
+    /// Returns `None` if an intermediate table is missing or is a huge page.
+    /// The entry itself may be unused.
+    fn leaf_entry(&self, pml4_phys: PhysAddr, vaddr: VirtAddr) -> Option<*mut PageTableEntry> {
+        let page = Page::<Size4KiB>::containing_address(vaddr);
+        let mut table_phys = pml4_phys;
+        for index in [page.p4_index(), page.p3_index(), page.p2_index()] {
+            let table: &PageTable =
+                unsafe { &*(self.phys_mem_offset + table_phys.as_u64()).as_ptr() };
+            let entry = &table[index];
+            if !entry.flags().contains(PageTableFlags::PRESENT)
+                || entry.flags().contains(PageTableFlags::HUGE_PAGE)
+            {
+                return None;
+            }
+            table_phys = entry.addr();
+        }
+        let pt: &mut PageTable =
+            unsafe { &mut *(self.phys_mem_offset + table_phys.as_u64()).as_mut_ptr() };
+        Some(&mut pt[page.p1_index()] as *mut PageTableEntry)
+    }
+
+    // -----------------------------------------------------------------------
+    // Copy-on-write
+
+    /// Duplicate a user address space for `fork()`.
+    ///
+    /// Creates a new PML4 and maps every user page of `parent_pml4` into it,
+    /// sharing the physical frames (each gains one reference).  Private
+    /// pages that are writable, or already copy-on-write, are downgraded to
+    /// read-only + [`PAGE_COW`] in *both* tables so the first write from
+    /// either side takes a fault and gets its own copy (see [`break_cow`]).
+    /// Pages inside `shared_ranges` (`MAP_SHARED` VMAs, as `[start, end)`)
+    /// keep their flags and stay genuinely shared.
+    ///
+    /// The parent must be the active address space; its TLB is flushed.
+    /// Returns `None` if frames run out (nothing is leaked).
+    ///
+    /// [`break_cow`]: MemoryServices::break_cow
+    pub fn fork_user_address_space(
+        &mut self,
+        parent_pml4: PhysAddr,
+        shared_ranges: &[(u64, u64)],
+    ) -> Option<PhysAddr> {
+        let phys_off = self.phys_mem_offset;
+        let child_pml4 = self.create_user_page_table();
+        let pml4: &PageTable = unsafe { &*(phys_off + parent_pml4.as_u64()).as_ptr() };
+        let mut ok = true;
+
+        'walk: for p4_idx in 0..256u16 {
+            let p4e = &pml4[PageTableIndex::new(p4_idx)];
+            if p4e.is_unused() { continue; }
+            let pdpt: &PageTable = unsafe { &*(phys_off + p4e.addr().as_u64()).as_ptr() };
+
+            for p3_idx in 0..512u16 {
+                let p3e = &pdpt[PageTableIndex::new(p3_idx)];
+                if p3e.is_unused() || p3e.flags().contains(PageTableFlags::HUGE_PAGE) { continue; }
+                let pd: &PageTable = unsafe { &*(phys_off + p3e.addr().as_u64()).as_ptr() };
+
+                for p2_idx in 0..512u16 {
+                    let p2e = &pd[PageTableIndex::new(p2_idx)];
+                    if p2e.is_unused() || p2e.flags().contains(PageTableFlags::HUGE_PAGE) { continue; }
+                    let pt: &mut PageTable =
+                        unsafe { &mut *(phys_off + p2e.addr().as_u64()).as_mut_ptr() };
+
+                    for p1_idx in 0..512u16 {
+                        let p1e = &mut pt[PageTableIndex::new(p1_idx)];
+                        if p1e.is_unused() { continue; }
+
+                        let vaddr = ((p4_idx as u64) << 39) | ((p3_idx as u64) << 30)
+                            | ((p2_idx as u64) << 21) | ((p1_idx as u64) << 12);
+                        let frame = p1e.addr();
+                        let mut flags = p1e.flags();
+
+                        let shared = shared_ranges.iter()
+                            .any(|&(start, end)| vaddr >= start && vaddr < end);
+                        if !shared && flags.intersects(PageTableFlags::WRITABLE | PAGE_COW) {
+                            flags.remove(PageTableFlags::WRITABLE);
+                            flags.insert(PAGE_COW);
+                            p1e.set_flags(flags);
+                        }
+
+                        if self.map_user_page(child_pml4, VirtAddr::new(vaddr), frame, flags).is_err() {
+                            ok = false;
+                            break 'walk;
+                        }
+                        self.ref_share(frame);
+                    }
+                }
+            }
+        }
+
+        x86_64::instructions::tlb::flush_all();
+
+        if ok {
+            Some(child_pml4)
+        } else {
+            // Drops the references taken so far and frees the child tables.
+            self.cleanup_user_address_space(child_pml4, false);
+            None
+        }
+    }
+
+    /// Resolve a write fault on a copy-on-write page in the active address
+    /// space rooted at `pml4_phys`.
+    ///
+    /// If the frame is still shared, its contents are copied into a fresh
+    /// frame which replaces it in this mapping and the old frame loses one
+    /// reference.  If this was the last reference the page is simply made
+    /// writable again.  Returns `false` if `vaddr` is not a present COW page
+    /// or no frame is available.
+    pub fn break_cow(&mut self, pml4_phys: PhysAddr, vaddr: VirtAddr) -> bool {
+        let entry = match self.leaf_entry(pml4_phys, vaddr) {
+            Some(e) => unsafe { &mut *e },
+            None => return false,
+        };
+        let flags = entry.flags();
+        if !flags.contains(PageTableFlags::PRESENT | PAGE_COW) {
+            return false;
+        }
+
+        let old_phys = entry.addr();
+        let new_flags = (flags - PAGE_COW) | PageTableFlags::WRITABLE;
+
+        if self.is_shared(old_phys) {
+            let new_phys = match self.alloc_dma_pages(1) {
+                Some(f) => f,
+                None => return false,
+            };
+            unsafe {
+                core::ptr::copy_nonoverlapping(
+                    (self.phys_mem_offset + old_phys.as_u64()).as_ptr::<u8>(),
+                    (self.phys_mem_offset + new_phys.as_u64()).as_mut_ptr::<u8>(),
+                    crate::consts::PAGE_SIZE as usize,
+                );
+            }
+            entry.set_addr(new_phys, new_flags);
+            // Shared means at least one other owner remains.
+            self.ref_release(old_phys);
+        } else {
+            entry.set_flags(new_flags);
+        }
+
+        x86_64::instructions::tlb::flush(vaddr);
+        true
+    }
+
+    /// Adjust page-table `flags` for a *private* page about to be made
+    /// writable: if its frame is still shared copy-on-write, keep it
+    /// read-only and mark it [`PAGE_COW`] instead.
+    pub fn private_page_flags(
+        &self,
+        pml4_phys: PhysAddr,
+        vaddr: VirtAddr,
+        flags: PageTableFlags,
+    ) -> PageTableFlags {
+        if !flags.contains(PageTableFlags::WRITABLE) {
+            return flags;
+        }
+        match self.user_page_entry(pml4_phys, vaddr) {
+            Some((frame, _)) if self.is_shared(frame) => {
+                (flags - PageTableFlags::WRITABLE) | PAGE_COW
+            }
+            _ => flags,
+        }
+    }
+
+    /// Clear the hardware `DIRTY` bit of a user page, returning its frame
+    /// if the bit was set: the page was written since it was mapped or
+    /// since the last call.  Used to find pages written through
+    /// `MAP_SHARED` file mappings.
+    pub fn take_dirty_page(
+        &mut self,
+        pml4_phys: PhysAddr,
+        vaddr: VirtAddr,
+        flush_tlb: bool,
+    ) -> Option<PhysAddr> {
+        let entry = unsafe { &mut *self.leaf_entry(pml4_phys, vaddr)? };
+        let flags = entry.flags();
+        if entry.is_unused() || !flags.contains(PageTableFlags::DIRTY) {
+            return None;
+        }
+        entry.set_flags(flags - PageTableFlags::DIRTY);
+        if flush_tlb {
+            x86_64::instructions::tlb::flush(vaddr);
+        }
+        Some(entry.addr())
+    }
+
     /// Free all user-space pages and intermediate page table frames for a
     /// process address space.
     ///
@@ -668,6 +889,26 @@ where
     f(svc)
 }
 
+/// Frees one frame held by a cache; see [`set_reclaim_hook`].
+static RECLAIM_HOOK: Mutex<Option<fn() -> bool>> = Mutex::new(None);
+
+/// Register `hook` to free one cached frame when a user page fault finds
+/// the frame allocator empty.  The page cache registers itself here.
+///
+/// The hook runs without the memory services locked, so it may call
+/// [`with_memory`] itself.  It returns `true` if it freed a frame.
+pub fn set_reclaim_hook(hook: fn() -> bool) {
+    *RECLAIM_HOOK.lock() = Some(hook);
+}
+
+/// Ask the registered cache for a frame back.  Returns `false` if there is
+/// no hook or it had nothing to free.  Must not be called inside
+/// [`with_memory`].
+pub fn reclaim_frame() -> bool {
+    let hook = *RECLAIM_HOOK.lock();
+    hook.map_or(false, |hook| hook())
+}
+
 /// Switch the active address space by writing a new PML4 physical address to CR3.
 ///
 /// # Safety
@@ -745,3 +986,127 @@ pub fn map_page(
     let offset = addr.as_u64() - frame.start_address().as_u64();
     VirtAddr::new(page.start_address().as_u64() + offset)
 }
+
+#[cfg(test)]
+mod test {
+    use crate::{serial_print, serial_println};
+    use x86_64::structures::paging::PageTableFlags;
+    use x86_64::{PhysAddr, VirtAddr};
+    use super::{with_memory, MemoryServices, PAGE_COW};
+
+    /// User pages in a fresh page table, nothing else mapped near them.
+    const BASE: u64 = 0x1000_0000;
+    const PAGE: u64 = 4096;
+
+    const RW: PageTableFlags = PageTableFlags::PRESENT
+        .union(PageTableFlags::WRITABLE)
+        .union(PageTableFlags::USER_ACCESSIBLE);
+    const RO: PageTableFlags = PageTableFlags::PRESENT
+        .union(PageTableFlags::USER_ACCESSIBLE);
+
+    /// Frames in use: allocated since boot less those on the free list.
+    fn frames_in_use(mem: &MemoryServices) -> usize {
+        let (allocated, _, free) = mem.frame_stats();
+        allocated - free
+    }
+
+    fn entry(mem: &MemoryServices, pml4: PhysAddr, addr: u64) -> (PhysAddr, PageTableFlags) {
+        mem.user_page_entry(pml4, VirtAddr::new(addr)).expect("page not mapped")
+    }
+
+    fn byte(mem: &MemoryServices, phys: PhysAddr) -> *mut u8 {
+        (mem.phys_mem_offset + phys.as_u64()).as_mut_ptr()
+    }
+
+    #[test_case]
+    fn test_fork_marks_private_pages_cow() {
+        serial_print!("test_fork_marks_private_pages_cow... ");
+        with_memory(|mem| {
+            let before = frames_in_use(mem);
+            let parent = mem.create_user_page_table();
+            // Writable private, read-only private, writable shared.
+            mem.alloc_and_map_user_pages(1, BASE, parent, RW).unwrap();
+            mem.alloc_and_map_user_pages(1, BASE + PAGE, parent, RO).unwrap();
+            mem.alloc_and_map_user_pages(1, BASE + 2 * PAGE, parent, RW).unwrap();
+
+            let shared = [(BASE + 2 * PAGE, BASE + 3 * PAGE)];
+            let child = mem.fork_user_address_space(parent, &shared).unwrap();
+
+            for i in 0..3 {
+                let (pp, pf) = entry(mem, parent, BASE + i * PAGE);
+                let (cp, cf) = entry(mem, child, BASE + i * PAGE);
+                assert_eq!(pp, cp, "page {} not shared", i);
+                assert_eq!(pf, cf);
+                assert!(mem.is_shared(pp));
+            }
+            let (_, flags) = entry(mem, parent, BASE);
+            assert!(flags.contains(PAGE_COW) && !flags.contains(PageTableFlags::WRITABLE));
+            let (_, flags) = entry(mem, parent, BASE + PAGE);
+            assert!(!flags.contains(PAGE_COW) && !flags.contains(PageTableFlags::WRITABLE));
+            let (_, flags) = entry(mem, parent, BASE + 2 * PAGE);
+            assert!(!flags.contains(PAGE_COW) && flags.contains(PageTableFlags::WRITABLE));
+
+            // Tearing down the child drops one reference from each frame.
+            mem.cleanup_user_address_space(child, false);
+            for i in 0..3 {
+                let (phys, _) = entry(mem, parent, BASE + i * PAGE);
+                assert!(!mem.is_shared(phys));
+            }
+            mem.cleanup_user_address_space(parent, false);
+            assert_eq!(frames_in_use(mem), before);
+        });
+        serial_println!("[ok]");
+    }
+
+    #[test_case]
+    fn test_break_cow_copies_shared_frame() {
+        serial_print!("test_break_cow_copies_shared_frame... ");
+        with_memory(|mem| {
+            let parent = mem.create_user_page_table();
+            mem.alloc_and_map_user_pages(1, BASE, parent, RW).unwrap();
+            let (old, _) = entry(mem, parent, BASE);
+            unsafe { *byte(mem, old) = 0x5a; }
+            let child = mem.fork_user_address_space(parent, &[]).unwrap();
+
+            assert!(mem.break_cow(child, VirtAddr::new(BASE)));
+            let (new, flags) = entry(mem, child, BASE);
+            assert_ne!(new, old);
+            assert!(flags.contains(PageTableFlags::WRITABLE) && !flags.contains(PAGE_COW));
+            assert_eq!(unsafe { *byte(mem, new) }, 0x5a);
+            // The parent is the last owner of the old frame now.
+            assert!(!mem.is_shared(old));
+            let (phys, flags) = entry(mem, parent, BASE);
+            assert_eq!(phys, old);
+            assert!(flags.contains(PAGE_COW));
+
+            mem.cleanup_user_address_space(child, false);
+            mem.cleanup_user_address_space(parent, false);
+        });
+        serial_println!("[ok]");
+    }
+
+    #[test_case]
+    fn test_break_cow_reuses_unshared_frame() {
+        serial_print!("test_break_cow_reuses_unshared_frame... ");
+        with_memory(|mem| {
+            let before = frames_in_use(mem);
+            let pml4 = mem.create_user_page_table();
+            mem.alloc_and_map_user_pages(1, BASE, pml4, RO | PAGE_COW).unwrap();
+            let (old, _) = entry(mem, pml4, BASE);
+            let allocated = frames_in_use(mem);
+
+            // A reference count of one: the frame is made writable in place.
+            assert!(mem.break_cow(pml4, VirtAddr::new(BASE)));
+            let (phys, flags) = entry(mem, pml4, BASE);
+            assert_eq!(phys, old);
+            assert!(flags.contains(PageTableFlags::WRITABLE) && !flags.contains(PAGE_COW));
+            assert_eq!(frames_in_use(mem), allocated);
+
+            // Not a COW page any more.
+            assert!(!mem.break_cow(pml4, VirtAddr::new(BASE)));
+            mem.cleanup_user_address_space(pml4, false);
+            assert_eq!(frames_in_use(mem), before);
+        });
+        serial_println!("[ok]");
+    }
+}
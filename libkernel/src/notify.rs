@@ -10,6 +10,7 @@ use alloc::sync::Arc;
 
 use crate::completion_port::{Completion, CompletionPort, OP_RING_WAIT};
 use crate::irq_mutex::IrqMutex;
+use crate::poll::{PollWaiters, POLL_READABLE};
 
 // ---------------------------------------------------------------------------
 // NotifyInner — per-notification-fd kernel state
@@ -20,6 +21,8 @@ pub struct NotifyInner {
     /// True if notify() was called while no OP_RING_WAIT was pending.
     /// Consumed by arm_notify if set (buffered notification, coalescing).
     notified: bool,
+    /// poll / epoll waiters, woken on every signal.
+    pollers: PollWaiters,
 }
 
 impl NotifyInner {
@@ -27,8 +30,15 @@ impl NotifyInner {
         Self {
             pending: None,
             notified: false,
+            pollers: PollWaiters::new(),
         }
     }
+
+    /// `POLLIN` while a notification is buffered.
+    pub fn poll_events(&mut self, waker: Option<&core::task::Waker>) -> u32 {
+        self.pollers.register(waker);
+        if self.notified { POLL_READABLE } else { 0 }
+    }
 }
 
 // ---------------------------------------------------------------------------
@@ -71,6 +81,7 @@ pub fn arm_notify(
 /// Returns the thread index that was woken (if any), for scheduler donate.
 pub fn signal_notify(inner: &Arc<IrqMutex<NotifyInner>>) -> Option<usize> {
     let mut guard = inner.lock();
+    guard.pollers.wake_all();
 
     if let Some((port, user_data)) = guard.pending.take() {
         port.lock().post(Completion {
@@ -0,0 +1,180 @@
+//! Readiness notification for poll, select and epoll.
+//!
+//! Every kind of fd reports its readiness as a mask of `POLL*` bits
+//! ([`FdObject::poll`](crate::file::FdObject::poll)).  A caller that may
+//! block passes a `Waker`, which the object keeps in its [`PollWaiters`]
+//! and wakes the next time its readiness may have changed.  Registrations
+//! are one-shot: a woken poller polls again, which registers it again.
+//!
+//! Wakes can be spurious, so a woken poller always re-checks the mask.
+
+use alloc::sync::Arc;
+use alloc::task::Wake;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::Waker;
+
+use crate::irq_mutex::IrqMutex;
+use crate::process::{self, ProcessId};
+use crate::task::scheduler;
+use crate::time;
+use crate::wait_condition::WaitCondition;
+
+// ---------------------------------------------------------------------------
+// Event bits (Linux values, shared by poll and epoll)
+
+pub const POLLIN: u32 = 0x001;
+pub const POLLPRI: u32 = 0x002;
+pub const POLLOUT: u32 = 0x004;
+pub const POLLERR: u32 = 0x008;
+pub const POLLHUP: u32 = 0x010;
+pub const POLLNVAL: u32 = 0x020;
+pub const POLLRDNORM: u32 = 0x040;
+pub const POLLRDBAND: u32 = 0x080;
+pub const POLLWRNORM: u32 = 0x100;
+pub const POLLWRBAND: u32 = 0x200;
+pub const POLLRDHUP: u32 = 0x2000;
+
+/// Readable.
+pub const POLL_READABLE: u32 = POLLIN | POLLRDNORM;
+/// Writable.
+pub const POLL_WRITABLE: u32 = POLLOUT | POLLWRNORM;
+/// Readiness of objects that never block, such as regular files.
+pub const DEFAULT_POLLMASK: u32 = POLL_READABLE | POLL_WRITABLE;
+
+// ---------------------------------------------------------------------------
+// PollWaiters
+
+/// The wakers waiting for one object's readiness to change.  Lives under
+/// the object's own lock; the object calls [`wake_all`](Self::wake_all)
+/// whenever its readiness may have changed.
+#[derive(Default)]
+pub struct PollWaiters {
+    wakers: Vec<Waker>,
+}
+
+impl PollWaiters {
+    pub const fn new() -> Self {
+        PollWaiters { wakers: Vec::new() }
+    }
+
+    /// Register `waker`, if given, for the next wake.  A waker that is
+    /// already registered is not added twice.
+    pub fn register(&mut self, waker: Option<&Waker>) {
+        if let Some(waker) = waker {
+            if !self.wakers.iter().any(|w| w.will_wake(waker)) {
+                self.wakers.push(waker.clone());
+            }
+        }
+    }
+
+    /// Wake and forget every registered waker.  Safe from ISR context.
+    pub fn wake_all(&mut self) {
+        for waker in core::mem::take(&mut self.wakers) {
+            waker.wake();
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Blocking until ready
+
+/// Wakes one scheduler thread while it is inside [`wait_until`].
+///
+/// Each thread keeps the same waker, so registering it again with an object
+/// it is already registered with is a no-op.  Outside `wait_until` it is
+/// disarmed: a stale registration that fires later cannot wake the thread
+/// out of an unrelated block.
+struct ThreadWaker {
+    thread: usize,
+    armed: AtomicBool,
+    woken: AtomicBool,
+}
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.woken.store(true, Ordering::Release);
+        if self.armed.load(Ordering::Acquire) {
+            scheduler::unblock(self.thread);
+        }
+    }
+}
+
+/// Per-thread wakers, indexed by scheduler thread index.
+static THREAD_WAKERS: IrqMutex<Vec<Option<Arc<ThreadWaker>>>> = IrqMutex::new(Vec::new());
+
+fn thread_waker(thread: usize) -> Arc<ThreadWaker> {
+    let mut wakers = THREAD_WAKERS.lock();
+    if wakers.len() <= thread {
+        wakers.resize(thread + 1, None);
+    }
+    wakers[thread]
+        .get_or_insert_with(|| Arc::new(ThreadWaker {
+            thread,
+            armed: AtomicBool::new(false),
+            woken: AtomicBool::new(false),
+        }))
+        .clone()
+}
+
+/// Why [`wait_until`] returned without a result.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WaitError {
+    /// The deadline passed.
+    TimedOut,
+    /// A signal the thread does not block is pending, or it must exit.
+    Interrupted,
+}
+
+/// Block thread `tid` of the running process `pid` until `check` returns
+/// `Some`, the monotonic clock reaches `deadline_ns` (never, if `None`), or
+/// a signal arrives.
+///
+/// `check` registers the waker it is given with every object it polls; it
+/// runs first, and again after each wake.  With a deadline already passed
+/// it runs once, so `Some(0)` polls without blocking.
+pub fn wait_until<T>(
+    pid: ProcessId,
+    tid: ProcessId,
+    deadline_ns: Option<u64>,
+    mut check: impl FnMut(&Waker) -> Option<T>,
+) -> Result<T, WaitError> {
+    let tw = thread_waker(scheduler::current_thread_idx());
+    let waker = Waker::from(tw.clone());
+    tw.armed.store(true, Ordering::Release);
+    let result = loop {
+        tw.woken.store(false, Ordering::Release);
+        if let Some(r) = check(&waker) {
+            break Ok(r);
+        }
+        if deadline_ns.map_or(false, |d| time::monotonic_ns() >= d) {
+            break Err(WaitError::TimedOut);
+        }
+        let table = process::lock_table();
+        if table.get(&pid).map_or(true, |p| p.interrupted(tid)) {
+            break Err(WaitError::Interrupted);
+        }
+        if tw.woken.load(Ordering::Acquire) {
+            // Woken while checking: check again without blocking.
+            continue;
+        }
+        WaitCondition::wait_while(Some(table), |table, _| {
+            if let Some(p) = table.get_mut(&pid) {
+                p.set_interruptible(tid, true);
+            }
+            if let Some(d) = deadline_ns {
+                scheduler::set_wake_deadline(time::deadline_ticks(d));
+            }
+        });
+        if deadline_ns.is_some() {
+            scheduler::clear_wake_deadline();
+        }
+        process::with_process(pid, |p| p.set_interruptible(tid, false));
+    };
+    tw.armed.store(false, Ordering::Release);
+    result
+}
@@ -1,12 +1,13 @@
 use alloc::collections::BTreeMap;
 use alloc::string::String;
+use alloc::sync::Arc;
 use alloc::vec::Vec;
 use core::sync::atomic::{AtomicU64, Ordering};
 use crate::spin_mutex::SpinMutex as Mutex;
 use x86_64::PhysAddr;
 use x86_64::structures::paging::PageTableFlags;
 
-use crate::file::{CloseResult, FileError, FdEntry, FdObject, FD_CLOEXEC};
+use crate::file::{CloseResult, FileError, FileHandle, FdEntry, FdObject, FD_CLOEXEC};
 use crate::signal::SignalState;
 use crate::stack_arena::StackSlot;
 
@@ -26,14 +27,30 @@ pub const MAP_FIXED:     u32 = 0x10;
 pub const MAP_ANONYMOUS: u32 = 0x20;
 
 /// A virtual memory area tracked per-process.
-#[derive(Debug, Clone)]
+#[derive(Clone)]
 pub struct Vma {
     pub start: u64,        // page-aligned start address
     pub len: u64,          // page-aligned length
     pub prot: u32,         // PROT_READ | PROT_WRITE | PROT_EXEC
     pub flags: u32,        // MAP_PRIVATE | MAP_ANONYMOUS etc.
-    pub fd: Option<usize>, // file descriptor (Phase 5)
-    pub offset: u64,       // file offset (Phase 5)
+    /// Mapped file whose pages are faulted in from the page cache
+    /// (see `memory::mapped_file`).  `None` for anonymous and shmem
+    /// mappings, and for files mapped by copying.
+    pub file: Option<Arc<dyn FileHandle>>,
+    pub offset: u64,       // file offset of `start`
+}
+
+impl core::fmt::Debug for Vma {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Vma")
+            .field("start", &self.start)
+            .field("len", &self.len)
+            .field("prot", &self.prot)
+            .field("flags", &self.flags)
+            .field("file", &self.file.as_ref().map(|h| h.kind()))
+            .field("offset", &self.offset)
+            .finish()
+    }
 }
 
 /// Convert Linux `PROT_*` flags to x86-64 page table flags.
@@ -87,6 +104,49 @@ pub enum ProcessState {
     Zombie,
 }
 
+// ---------------------------------------------------------------------------
+// Threads
+
+/// Per-thread state of one thread of a user process.
+///
+/// A process starts with a single thread whose TID equals its PID; more are
+/// added by `clone(CLONE_THREAD)`.  Everything not in here — address space,
+/// fd table, cwd, signal dispositions and pending set — is shared.
+pub struct UserThread {
+    /// Index of this thread in the scheduler's thread vec.
+    pub thread_idx: Option<usize>,
+    /// Signal mask (blocked signals).
+    pub blocked: u64,
+    /// Mask to restore on return to user mode, set while a ppoll /
+    /// pselect6 / epoll_pwait mask is in effect.  A handler delivered on
+    /// that return runs with the temporary mask and gets this one in its
+    /// frame, so rt_sigreturn restores it.
+    pub saved_blocked: Option<u64>,
+    /// User address of a `u32` zeroed when the thread exits
+    /// (`CLONE_CHILD_CLEARTID` / `set_tid_address`); 0 if none.
+    pub clear_child_tid: u64,
+    /// True while the thread is in an interruptible block (pipe read,
+    /// waitpid).  `sys_kill` unblocks such threads so the syscall can
+    /// return EINTR.
+    pub interruptible: bool,
+    /// Set by `exit_group`/`execve` in another thread: this thread exits
+    /// instead of returning to user mode.
+    pub exiting: bool,
+}
+
+impl UserThread {
+    pub fn new(blocked: u64) -> Self {
+        UserThread {
+            thread_idx: None,
+            blocked,
+            saved_blocked: None,
+            clear_child_tid: 0,
+            interruptible: false,
+            exiting: false,
+        }
+    }
+}
+
 // ---------------------------------------------------------------------------
 // Process struct
 
@@ -97,12 +157,18 @@ pub struct Process {
     /// Kernel stack from the stack arena. Kept for Drop (returns slot to arena).
     #[allow(dead_code)]
     kernel_stack: StackSlot,
-    /// Cached top of `kernel_stack`, 16-byte aligned.
+    /// Cached top of `kernel_stack`, 16-byte aligned.  Used by the main
+    /// thread; other threads own their kernel stacks in the scheduler.
     pub kernel_stack_top: u64,
     pub entry_point: u64,
     pub user_stack_top: u64,
-    /// Index of this process's thread in the scheduler's thread vec.
+    /// Index of the main thread in the scheduler's thread vec.
     pub thread_idx: Option<usize>,
+    /// Live threads keyed by TID (the main thread's TID is `pid`).
+    pub threads: BTreeMap<ProcessId, UserThread>,
+    /// Exit code recorded by `exit_group` or a fatal signal; reported by
+    /// whichever thread exits last.
+    pub group_exit_code: Option<i32>,
     pub exit_code: Option<i32>,
     /// Page-aligned end of the highest PT_LOAD segment (initial program break).
     pub brk_base: u64,
@@ -116,19 +182,20 @@ pub struct Process {
     pub cwd: String,
     /// Parent process ID (KERNEL for top-level processes).
     pub parent_pid: ProcessId,
-    /// Scheduler thread index to wake when a child exits (for waitpid).
-    pub wait_thread: Option<usize>,
+    /// Scheduler thread indices to wake when a child exits (for waitpid):
+    /// every thread of the process blocked in `wait4`.
+    pub wait_threads: Vec<usize>,
     /// For vfork children: parent's thread index to unblock on execve/_exit.
     pub vfork_parent_thread: Option<usize>,
     /// True when this process shares its PML4 with the parent (CLONE_VM).
     /// Cleanup must not free the PML4 or its pages in this case.
     pub pml4_shared: bool,
-    /// Signal state (dispositions, pending mask, blocked mask).
+    /// Signal state (dispositions, pending mask).  Signal masks are
+    /// per-thread, in [`UserThread::blocked`].
     pub signal: SignalState,
-    /// Thread index of an interruptible blocking syscall (pipe read, waitpid).
-    /// Set before blocking, cleared after waking. `sys_kill` uses this to
-    /// unblock the thread so the syscall can return EINTR.
-    pub signal_thread: Option<usize>,
+    /// TSC cycles used by threads that have already exited; live threads'
+    /// time is kept by the scheduler.
+    pub exited_cpu_cycles: u64,
 }
 
 /// Top of the mmap search range (exclusive). Allocations are placed below this.
@@ -142,6 +209,8 @@ impl Process {
         let kernel_stack = crate::stack_arena::alloc()
             .expect("stack arena exhausted");
         let stack_top = kernel_stack.top();
+        let mut threads = BTreeMap::new();
+        threads.insert(pid, UserThread::new(0));
         Process {
             pid,
             state: ProcessState::Running,
@@ -151,6 +220,8 @@ impl Process {
             entry_point,
             user_stack_top,
             thread_idx: None,
+            threads,
+            group_exit_code: None,
             exit_code: None,
             brk_base,
             brk_current: brk_base,
@@ -158,11 +229,52 @@ impl Process {
             fd_table: crate::file::default_fd_table(),
             cwd: String::from("/"),
             parent_pid: ProcessId::KERNEL,
-            wait_thread: None,
+            wait_threads: Vec::new(),
             vfork_parent_thread: None,
             pml4_shared: false,
             signal: SignalState::new(),
-            signal_thread: None,
+            exited_cpu_cycles: 0,
+        }
+    }
+
+    /// Record the scheduler thread index of the main thread.
+    pub fn set_main_thread(&mut self, thread_idx: usize) {
+        self.thread_idx = Some(thread_idx);
+        if let Some(t) = self.threads.get_mut(&self.pid) {
+            t.thread_idx = Some(thread_idx);
+        }
+    }
+
+    /// Per-thread state for `tid`, if it is a live thread of this process.
+    pub fn thread(&self, tid: ProcessId) -> Option<&UserThread> {
+        self.threads.get(&tid)
+    }
+
+    /// Mutable per-thread state for `tid`.
+    pub fn thread_mut(&mut self, tid: ProcessId) -> Option<&mut UserThread> {
+        self.threads.get_mut(&tid)
+    }
+
+    /// Signal mask of thread `tid`.
+    pub fn blocked(&self, tid: ProcessId) -> u64 {
+        self.thread(tid).map_or(0, |t| t.blocked)
+    }
+
+    /// Pending signals that thread `tid` does not block.
+    pub fn deliverable(&self, tid: ProcessId) -> u64 {
+        self.signal.pending & !self.blocked(tid)
+    }
+
+    /// True if thread `tid` should abandon an interruptible block: a signal
+    /// it does not block is pending, or it has been told to exit.
+    pub fn interrupted(&self, tid: ProcessId) -> bool {
+        self.deliverable(tid) != 0 || self.thread(tid).map_or(true, |t| t.exiting)
+    }
+
+    /// Mark thread `tid` as entering (`true`) or leaving an interruptible block.
+    pub fn set_interruptible(&mut self, tid: ProcessId, interruptible: bool) {
+        if let Some(t) = self.thread_mut(tid) {
+            t.interruptible = interruptible;
         }
     }
 
@@ -291,6 +403,7 @@ impl Process {
                 let mut new_vma = vma.clone();
                 new_vma.start = end;
                 new_vma.len = vma.len - removed;
+                new_vma.offset += removed;
                 to_remove.push(*key);
                 to_insert.push((end, new_vma));
             } else if start > vma_start && end >= vma_end {
@@ -316,6 +429,7 @@ impl Process {
                 let mut right = vma.clone();
                 right.start = end;
                 right.len = vma_end - end;
+                right.offset += end - vma_start;
 
                 to_remove.push(*key);
                 to_insert.push((vma_start, left));
@@ -378,6 +492,7 @@ impl Process {
                 let mut tail = vma.clone();
                 tail.start = end;
                 tail.len = vma_end - end;
+                tail.offset += front_len;
 
                 to_remove.push(*key);
                 to_insert.push((vma_start, front));
@@ -394,6 +509,7 @@ impl Process {
                 let mut tail = vma.clone();
                 tail.start = start;
                 tail.len = tail_len;
+                tail.offset += start - vma_start;
                 tail.prot = new_prot;
 
                 to_remove.push(*key);
@@ -411,11 +527,13 @@ impl Process {
                 let mut mid = vma.clone();
                 mid.start = start;
                 mid.len = mid_len;
+                mid.offset += start - vma_start;
                 mid.prot = new_prot;
 
                 let mut right = vma.clone();
                 right.start = end;
                 right.len = vma_end - end;
+                right.offset += end - vma_start;
 
                 to_remove.push(*key);
                 to_insert.push((vma_start, left));
@@ -435,6 +553,55 @@ impl Process {
         pages_to_update
     }
 
+    /// Return the VMA containing `addr`, if any.
+    pub fn find_vma(&self, addr: u64) -> Option<&Vma> {
+        self.vma_map.range(..=addr)
+            .next_back()
+            .map(|(_, vma)| vma)
+            .filter(|vma| addr < vma.start + vma.len)
+    }
+
+    /// Protection flags for a demand-paged region containing `addr`.
+    ///
+    /// Anonymous private VMAs and the brk heap `[brk_base, brk_current)` are
+    /// populated lazily on first touch; returns `None` for every other
+    /// address (including shmem, and file mappings, which
+    /// [`file_page`](Self::file_page) covers).
+    pub fn demand_prot(&self, addr: u64) -> Option<u32> {
+        if addr >= self.brk_base && addr < self.brk_current {
+            return Some(PROT_READ | PROT_WRITE);
+        }
+        let vma = self.find_vma(addr)?;
+        if vma.file.is_none() && vma.flags & MAP_ANONYMOUS != 0 && vma.flags & MAP_PRIVATE != 0 {
+            Some(vma.prot)
+        } else {
+            None
+        }
+    }
+
+    /// The file page behind `addr` in a page-cache file mapping: the file,
+    /// the page index within it, the region's protection and whether it is
+    /// `MAP_SHARED`.
+    pub fn file_page(&self, addr: u64) -> Option<(Arc<dyn FileHandle>, u64, u32, bool)> {
+        let vma = self.find_vma(addr)?;
+        let file = vma.file.as_ref()?;
+        let index = (vma.offset + (addr - vma.start)) / crate::consts::PAGE_SIZE;
+        Some((Arc::clone(file), index, vma.prot, vma.flags & MAP_SHARED != 0))
+    }
+
+    /// The parts of `MAP_SHARED` file mappings inside `[start, end)`, as
+    /// `(start, end, file)`.
+    pub fn shared_file_ranges(&self, start: u64, end: u64) -> Vec<(u64, u64, Arc<dyn FileHandle>)> {
+        self.vma_map.range(..end)
+            .map(|(_, vma)| vma)
+            .filter(|vma| vma.start + vma.len > start && vma.flags & MAP_SHARED != 0)
+            .filter_map(|vma| {
+                let file = vma.file.as_ref()?;
+                Some((vma.start.max(start), (vma.start + vma.len).min(end), Arc::clone(file)))
+            })
+            .collect()
+    }
+
     /// Find the highest gap of at least `len` bytes in the mmap region.
     pub fn find_mmap_gap(&self, len: u64) -> Option<u64> {
         crate::gap::find_gap_topdown(&self.vma_map, MMAP_FLOOR, MMAP_CEILING, len)
@@ -459,12 +626,14 @@ impl Process {
 pub struct ProcessManager {
     table: Mutex<BTreeMap<ProcessId, Process>>,
     current_pid: AtomicU64,
+    current_tid: AtomicU64,
     next_pid: AtomicU64,
 }
 
 pub static PROCESSES: ProcessManager = ProcessManager {
     table: Mutex::new(BTreeMap::new()),
     current_pid: AtomicU64::new(0),
+    current_tid: AtomicU64::new(0),
     next_pid: AtomicU64::new(1),
 };
 
@@ -477,10 +646,15 @@ impl ProcessManager {
         self.table.lock()
     }
 
+    /// Allocate a PID.  TIDs come from the same number space.
     fn alloc_pid(&self) -> ProcessId {
         ProcessId(self.next_pid.fetch_add(1, Ordering::Relaxed))
     }
 
+    pub fn alloc_tid(&self) -> ProcessId {
+        self.alloc_pid()
+    }
+
     pub fn insert(&self, mut proc: Process) -> ProcessId {
         let pid = proc.pid;
         // Ensure state is Running on insert.
@@ -497,6 +671,15 @@ impl ProcessManager {
         self.current_pid.store(pid.0, Ordering::Relaxed);
     }
 
+    /// TID of the running thread (`KERNEL` for kernel threads).
+    pub fn current_tid(&self) -> ProcessId {
+        ProcessId(self.current_tid.load(Ordering::Relaxed))
+    }
+
+    pub fn set_current_tid(&self, tid: ProcessId) {
+        self.current_tid.store(tid.0, Ordering::Relaxed);
+    }
+
     /// Run `f` with a mutable reference to the process. Returns `None` if not found.
     pub fn with_process<F, R>(&self, pid: ProcessId, f: F) -> Option<R>
     where
@@ -588,6 +771,9 @@ pub fn lock_table() -> spin::MutexGuard<'static, BTreeMap<ProcessId, Process>> {
 pub fn insert(proc: Process) -> ProcessId { PROCESSES.insert(proc) }
 pub fn current_pid() -> ProcessId { PROCESSES.current_pid() }
 pub fn set_current_pid(pid: ProcessId) { PROCESSES.set_current_pid(pid) }
+pub fn current_tid() -> ProcessId { PROCESSES.current_tid() }
+pub fn set_current_tid(tid: ProcessId) { PROCESSES.set_current_tid(tid) }
+pub fn alloc_tid() -> ProcessId { PROCESSES.alloc_tid() }
 
 pub fn with_process<F, R>(pid: ProcessId, f: F) -> Option<R>
 where F: FnOnce(&mut Process) -> R {
@@ -637,11 +823,85 @@ pub fn has_children_in(
     table.values().any(|p| p.parent_pid == parent_pid)
 }
 
-/// Terminate a process: unblock vfork parent, close fds, free address space,
-/// mark zombie, wake parent's wait_thread, and kill the scheduler thread.
+// ---------------------------------------------------------------------------
+// Process and thread exit
+
+/// Terminate the whole process from its running thread.
 ///
-/// Shared by `sys_exit` and signal default-terminate. Does not return.
+/// Records `exit_code`, makes every other thread exit, then exits the
+/// calling thread; the last thread out releases the process.
+///
+/// Shared by `exit_group`, signal default-terminate and fatal exceptions.
+/// Does not return.
 pub fn terminate_process(pid: ProcessId, exit_code: i32) -> ! {
+    let tid = current_tid();
+    with_process(pid, |p| {
+        p.group_exit_code.get_or_insert(exit_code);
+    });
+    kill_other_threads(pid, tid);
+    exit_thread(pid, tid, exit_code)
+}
+
+/// Make every thread of `pid` except `keep` exit.
+///
+/// Threads preempted in ring 3 hold no kernel state and are killed on the
+/// spot.  Threads inside the kernel are flagged `exiting`: interruptible
+/// blocks are woken, and the thread exits when it next heads back to user
+/// mode (`check_pending_signals`, `clone_trampoline`).
+pub fn kill_other_threads(pid: ProcessId, keep: ProcessId) {
+    let others: Vec<(ProcessId, Option<usize>, bool)> = with_process(pid, |p| {
+        p.threads.iter_mut()
+            .filter(|(&tid, _)| tid != keep)
+            .map(|(&tid, t)| {
+                t.exiting = true;
+                (tid, t.thread_idx, core::mem::take(&mut t.interruptible))
+            })
+            .collect()
+    }).unwrap_or_default();
+
+    for (tid, thread_idx, interruptible) in others {
+        let Some(idx) = thread_idx else { continue };
+        if crate::task::scheduler::kill_user_thread(idx) {
+            with_process(pid, |p| p.threads.remove(&tid));
+        } else if interruptible {
+            crate::task::scheduler::unblock(idx);
+        }
+    }
+}
+
+/// Exit the calling thread `tid` of process `pid`.
+///
+/// Zeroes the thread's `clear_child_tid` word and wakes a futex waiter on
+/// it, so `pthread_join` sees the thread gone.  Its CPU time is folded into
+/// the process total.  If this was the last thread the process is released
+/// with the `exit_group` code, or `exit_code` if there was none.  Does not
+/// return.
+pub fn exit_thread(pid: ProcessId, tid: ProcessId, exit_code: i32) -> ! {
+    let cpu_cycles = crate::task::scheduler::current_thread_cpu_cycles();
+    let (last, clear_child_tid, code) = with_process(pid, |p| {
+        let thread = p.threads.remove(&tid);
+        p.exited_cpu_cycles += cpu_cycles;
+        (p.threads.is_empty(),
+         thread.map_or(0, |t| t.clear_child_tid),
+         p.group_exit_code.unwrap_or(exit_code))
+    }).unwrap_or((true, 0, exit_code));
+
+    if last {
+        release_process(pid, code);
+    }
+
+    if clear_child_tid != 0 && crate::futex::put_user_word(pid, clear_child_tid, 0) {
+        let _ = crate::futex::wake(pid, clear_child_tid, 1, crate::futex::FUTEX_BITSET_MATCH_ANY);
+    }
+
+    crate::task::scheduler::kill_current_thread();
+}
+
+/// Release a process whose last thread is exiting: unblock vfork parent,
+/// close fds, queue shared file pages for write-back, free address space,
+/// mark zombie, wake parent's wait_threads, and kill the scheduler thread.
+/// Does not return.
+fn release_process(pid: ProcessId, exit_code: i32) -> ! {
     // Restore kernel display output if this process owned the framebuffer.
     if crate::vga_buffer::is_display_owner(pid) {
         crate::vga_buffer::unsuppress_display();
@@ -657,6 +917,10 @@ pub fn terminate_process(pid: ProcessId, exit_code: i32) -> ! {
     // completion ports, pipes) are released while page tables are active.
     with_process(pid, |p| p.close_all_fds());
 
+    // Queue pages written through shared file mappings for write-back
+    // while the page tables still record them.
+    crate::memory::mapped_file::sync_shared(pid, 0, crate::consts::USER_SPACE_END);
+
     // Free all user-space pages and page tables.
     // Skip if the PML4 is shared with the parent (CLONE_VM/vfork child
     // that called _exit without execve).
@@ -694,11 +958,12 @@ pub fn terminate_process(pid: ProcessId, exit_code: i32) -> ! {
         if parent_pid != ProcessId::KERNEL {
             with_process(parent_pid, |pp| pp.signal.queue(crate::signal::SIGCHLD));
         }
-        let wait_thread = with_process(parent_pid, |pp| pp.wait_thread.take());
-        if let Some(Some(thread_idx)) = wait_thread {
+        let waiters = with_process(parent_pid, |pp| core::mem::take(&mut pp.wait_threads))
+            .unwrap_or_default();
+        for &thread_idx in &waiters {
             crate::task::scheduler::unblock(thread_idx);
-            donate_to = Some(thread_idx);
         }
+        donate_to = waiters.first().copied();
     }
 
     // Yield before dying — donate remaining quantum to the parent so it
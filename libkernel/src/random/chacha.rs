@@ -0,0 +1,69 @@
+//! ChaCha20 block function (RFC 8439).
+
+/// "expand 32-byte k".
+pub const CONSTANTS: [u32; 4] = [0x6170_7865, 0x3320_646e, 0x7962_2d32, 0x6b20_6574];
+
+fn quarter_round(s: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+    s[a] = s[a].wrapping_add(s[b]); s[d] ^= s[a]; s[d] = s[d].rotate_left(16);
+    s[c] = s[c].wrapping_add(s[d]); s[b] ^= s[c]; s[b] = s[b].rotate_left(12);
+    s[a] = s[a].wrapping_add(s[b]); s[d] ^= s[a]; s[d] = s[d].rotate_left(8);
+    s[c] = s[c].wrapping_add(s[d]); s[b] ^= s[c]; s[b] = s[b].rotate_left(7);
+}
+
+/// The 20-round ChaCha permutation, without the final feed-forward.
+pub fn permute(s: &mut [u32; 16]) {
+    for _ in 0..10 {
+        quarter_round(s, 0, 4, 8, 12);
+        quarter_round(s, 1, 5, 9, 13);
+        quarter_round(s, 2, 6, 10, 14);
+        quarter_round(s, 3, 7, 11, 15);
+        quarter_round(s, 0, 5, 10, 15);
+        quarter_round(s, 1, 6, 11, 12);
+        quarter_round(s, 2, 7, 8, 13);
+        quarter_round(s, 3, 4, 9, 14);
+    }
+}
+
+/// One 64-byte keystream block for `key`, block `counter` and `nonce`.
+pub fn block(key: &[u32; 8], counter: u32, nonce: &[u32; 3]) -> [u8; 64] {
+    let mut input = [0u32; 16];
+    input[..4].copy_from_slice(&CONSTANTS);
+    input[4..12].copy_from_slice(key);
+    input[12] = counter;
+    input[13..].copy_from_slice(nonce);
+
+    let mut state = input;
+    permute(&mut state);
+
+    let mut out = [0u8; 64];
+    for (i, (word, orig)) in state.iter().zip(input.iter()).enumerate() {
+        out[i * 4..i * 4 + 4].copy_from_slice(&word.wrapping_add(*orig).to_le_bytes());
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{serial_print, serial_println};
+    use super::block;
+
+    #[test_case]
+    fn test_chacha20_block() {
+        serial_print!("test_chacha20_block... ");
+        // RFC 8439 section 2.3.2.
+        let mut key = [0u32; 8];
+        for (i, word) in key.iter_mut().enumerate() {
+            let b = (i * 4) as u32;
+            *word = u32::from_le_bytes([b as u8, (b + 1) as u8, (b + 2) as u8, (b + 3) as u8]);
+        }
+        let nonce = [0x0900_0000, 0x4a00_0000, 0];
+        let expected: [u8; 64] = [
+            0x10, 0xf1, 0xe7, 0xe4, 0xd1, 0x3b, 0x59, 0x15, 0x50, 0x0f, 0xdd, 0x1f, 0xa3, 0x20, 0x71, 0xc4,
+            0xc7, 0xd1, 0xf4, 0xc7, 0x33, 0xc0, 0x68, 0x03, 0x04, 0x22, 0xaa, 0x9a, 0xc3, 0xd4, 0x6c, 0x4e,
+            0xd2, 0x82, 0x64, 0x46, 0x07, 0x9f, 0xaa, 0x09, 0x14, 0xc2, 0xd7, 0x05, 0xd9, 0x8b, 0x02, 0xa2,
+            0xb5, 0x12, 0x9c, 0xd1, 0xde, 0x16, 0x4e, 0xb9, 0xcb, 0xd0, 0x83, 0xe8, 0xa2, 0x50, 0x3c, 0x4e,
+        ];
+        assert_eq!(block(&key, 1, &nonce), expected);
+        serial_println!("[ok]");
+    }
+}
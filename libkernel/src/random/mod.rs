@@ -0,0 +1,300 @@
+//! Kernel random number generator.
+//!
+//! Entropy sources are absorbed into a sponge built on the ChaCha20
+//! permutation:
+//!
+//! - **RDSEED / RDRAND**, when CPUID reports them — at boot and every reseed;
+//! - **TSC jitter** — timing of a short busy loop, sampled at boot;
+//! - **interrupt timing** — the TSC at each device interrupt
+//!   ([`add_interrupt_randomness`]);
+//! - **device data** — mixed in without credit ([`add_device_randomness`]).
+//!
+//! The pool credits each source conservatively.  Once it holds
+//! [`SEED_BITS`] it seeds a ChaCha20 generator, which is reseeded from the
+//! pool every [`RESEED_INTERVAL_NS`].  Every request ratchets the generator
+//! key first ("fast key erasure"), so earlier output cannot be recovered
+//! from the current state.
+//!
+//! [`init`] collects enough entropy to seed the generator before the first
+//! user process starts.
+
+pub mod chacha;
+
+use crate::irq_mutex::IrqMutex;
+use crate::time::{self, NSEC_PER_SEC};
+
+/// Credited bits needed to seed the generator.
+pub const SEED_BITS: u32 = 256;
+/// Interval between reseeds of a seeded generator.
+pub const RESEED_INTERVAL_NS: u64 = 60 * NSEC_PER_SEC;
+
+/// Most credited bits the pool holds.
+const POOL_BITS: u32 = 512;
+/// Words absorbed between permutations; the other 8 are capacity.
+const POOL_RATE: usize = 8;
+/// Credit for a 64-bit RDRAND output.  RDRAND is a DRBG reseeded from the
+/// hardware source, so it is credited below RDSEED's full 64 bits.
+const RDRAND_BITS: u32 = 16;
+/// Interrupts per credited bit.
+const IRQS_PER_BIT: u32 = 64;
+/// TSC jitter samples per credited bit.
+const JITTER_SAMPLES_PER_BIT: u32 = 8;
+/// Iterations of the busy loop timed by each jitter sample.
+const JITTER_LOOP: u64 = 64;
+/// RDRAND/RDSEED attempts before giving up on one output.
+const HW_RETRIES: usize = 10;
+
+/// Sponge over the ChaCha20 permutation.
+struct Pool {
+    state: [u32; 16],
+    /// Next rate word to absorb into.
+    pos: usize,
+    /// Entropy credited since the last extraction.
+    entropy_bits: u32,
+}
+
+impl Pool {
+    const fn new() -> Self {
+        let c = chacha::CONSTANTS;
+        // The all-zero state is a fixed point of the permutation; start
+        // from the ChaCha constants in the capacity instead.
+        Pool {
+            state: [0, 0, 0, 0, 0, 0, 0, 0, c[0], c[1], c[2], c[3], 0, 0, 0, 0],
+            pos: 0,
+            entropy_bits: 0,
+        }
+    }
+
+    fn absorb(&mut self, word: u64) {
+        self.state[self.pos] ^= word as u32;
+        self.state[self.pos + 1] ^= (word >> 32) as u32;
+        self.pos += 2;
+        if self.pos == POOL_RATE {
+            chacha::permute(&mut self.state);
+            self.pos = 0;
+        }
+    }
+
+    fn credit(&mut self, bits: u32) {
+        self.entropy_bits = (self.entropy_bits + bits).min(POOL_BITS);
+    }
+
+    /// Squeeze out 256 bits and reset the credit.  The rate is cleared and
+    /// the state permuted again, so the output cannot be recomputed from
+    /// the state left behind.
+    fn extract(&mut self) -> [u32; 8] {
+        chacha::permute(&mut self.state);
+        let mut out = [0u32; 8];
+        out.copy_from_slice(&self.state[..POOL_RATE]);
+        self.state[..POOL_RATE].fill(0);
+        chacha::permute(&mut self.state);
+        self.pos = 0;
+        self.entropy_bits = 0;
+        out
+    }
+}
+
+struct Rng {
+    pool: Pool,
+    /// ChaCha20 generator key.
+    key: [u32; 8],
+    /// Requests served; used as the nonce.
+    generation: u64,
+    seeded: bool,
+    last_reseed_ns: u64,
+    irq_events: u32,
+    has_rdseed: bool,
+    has_rdrand: bool,
+}
+
+impl Rng {
+    const fn new() -> Self {
+        Rng {
+            pool: Pool::new(),
+            key: [0; 8],
+            generation: 0,
+            seeded: false,
+            last_reseed_ns: 0,
+            irq_events: 0,
+            has_rdseed: false,
+            has_rdrand: false,
+        }
+    }
+
+    /// Absorb hardware random numbers until the pool holds `want_bits`.
+    fn add_hw(&mut self, want_bits: u32) {
+        while self.pool.entropy_bits < want_bits {
+            if let Some(v) = if self.has_rdseed { rdseed() } else { None } {
+                self.pool.absorb(v);
+                self.pool.credit(64);
+            } else if let Some(v) = if self.has_rdrand { rdrand() } else { None } {
+                self.pool.absorb(v);
+                self.pool.credit(RDRAND_BITS);
+            } else {
+                break;
+            }
+        }
+    }
+
+    /// Mix the pool into the generator key.
+    fn reseed(&mut self) {
+        self.add_hw(SEED_BITS);
+        self.pool.absorb(time::rdtsc());
+        let credited = self.pool.entropy_bits;
+        let seed = self.pool.extract();
+        for (k, s) in self.key.iter_mut().zip(seed.iter()) {
+            *k ^= s;
+        }
+        if credited >= SEED_BITS {
+            self.seeded = true;
+        }
+        self.last_reseed_ns = time::monotonic_ns();
+    }
+
+    /// Ratchet the generator and return a fresh key for one request.
+    fn next_key(&mut self) -> [u32; 8] {
+        let due = if self.seeded {
+            time::monotonic_ns().saturating_sub(self.last_reseed_ns) >= RESEED_INTERVAL_NS
+        } else {
+            self.pool.entropy_bits >= SEED_BITS
+        };
+        if due {
+            self.reseed();
+        }
+
+        let nonce = [self.generation as u32, (self.generation >> 32) as u32, 0];
+        self.generation = self.generation.wrapping_add(1);
+        let block = chacha::block(&self.key, 0, &nonce);
+        let mut request = [0u32; 8];
+        for (i, (k, r)) in self.key.iter_mut().zip(request.iter_mut()).enumerate() {
+            *k = word_at(&block, i);
+            *r = word_at(&block, 8 + i);
+        }
+        request
+    }
+}
+
+static RNG: IrqMutex<Rng> = IrqMutex::new(Rng::new());
+
+fn word_at(bytes: &[u8; 64], i: usize) -> u32 {
+    u32::from_le_bytes([bytes[i * 4], bytes[i * 4 + 1], bytes[i * 4 + 2], bytes[i * 4 + 3]])
+}
+
+fn rdseed() -> Option<u64> {
+    for _ in 0..HW_RETRIES {
+        let value: u64;
+        let ok: u8;
+        // Safety: only called when CPUID reports RDSEED.
+        unsafe {
+            core::arch::asm!("rdseed {}", "setc {}", out(reg) value, out(reg_byte) ok,
+                options(nomem, nostack));
+        }
+        if ok != 0 {
+            return Some(value);
+        }
+        core::hint::spin_loop();
+    }
+    None
+}
+
+fn rdrand() -> Option<u64> {
+    for _ in 0..HW_RETRIES {
+        let value: u64;
+        let ok: u8;
+        // Safety: only called when CPUID reports RDRAND.
+        unsafe {
+            core::arch::asm!("rdrand {}", "setc {}", out(reg) value, out(reg_byte) ok,
+                options(nomem, nostack));
+        }
+        if ok != 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+/// Time a short busy loop.  The low bits vary with cache, pipeline and
+/// interrupt state.
+fn jitter_sample() -> u64 {
+    let start = time::rdtsc();
+    let mut x = start;
+    for i in 0..JITTER_LOOP {
+        x = core::hint::black_box(x.rotate_left(7) ^ i);
+    }
+    time::rdtsc().wrapping_sub(start) ^ x.rotate_left(32)
+}
+
+/// Collect entropy and seed the generator.  Call once at boot, after
+/// `time::init`.
+pub fn init() {
+    let mut rng = RNG.lock();
+    rng.has_rdseed = crate::cpuid::has_rdseed();
+    rng.has_rdrand = crate::cpuid::has_rdrand();
+
+    let now = time::realtime_ns();
+    rng.pool.absorb(now);
+    rng.add_hw(SEED_BITS);
+    let hw_bits = rng.pool.entropy_bits;
+
+    let mut samples = 0u32;
+    while rng.pool.entropy_bits < SEED_BITS {
+        let sample = jitter_sample();
+        rng.pool.absorb(sample);
+        samples += 1;
+        if samples % JITTER_SAMPLES_PER_BIT == 0 {
+            rng.pool.credit(1);
+        }
+    }
+    rng.reseed();
+    let source = if rng.has_rdseed {
+        "RDSEED"
+    } else if rng.has_rdrand {
+        "RDRAND"
+    } else {
+        "no hardware RNG"
+    };
+    drop(rng);
+
+    info!("[random] seeded: {} bits from {}, {} TSC jitter samples", hw_bits, source, samples);
+}
+
+/// True once the generator has been seeded with [`SEED_BITS`] of entropy.
+pub fn is_ready() -> bool {
+    RNG.lock().seeded
+}
+
+/// Fill `buf` with output from the generator.
+pub fn fill_bytes(buf: &mut [u8]) {
+    let key = RNG.lock().next_key();
+    let nonce = [0u32; 3];
+    for (i, chunk) in buf.chunks_mut(64).enumerate() {
+        let block = chacha::block(&key, i as u32, &nonce);
+        chunk.copy_from_slice(&block[..chunk.len()]);
+    }
+}
+
+/// Mix in the timing of an interrupt.  Called from interrupt handlers with
+/// the interrupt number and a value read from the device, if any.
+///
+/// Must not block or allocate.
+pub fn add_interrupt_randomness(irq: u64, value: u64) {
+    let tsc = time::rdtsc();
+    let mut rng = RNG.lock();
+    rng.pool.absorb(tsc ^ (irq << 56) ^ value.rotate_left(24));
+    rng.irq_events += 1;
+    if rng.irq_events == IRQS_PER_BIT {
+        rng.irq_events = 0;
+        rng.pool.credit(1);
+    }
+}
+
+/// Mix in data that may be unpredictable but is not credited as entropy,
+/// such as writes to `/dev/urandom`.
+pub fn add_device_randomness(data: &[u8]) {
+    let mut rng = RNG.lock();
+    for chunk in data.chunks(8) {
+        let mut word = [0u8; 8];
+        word[..chunk.len()].copy_from_slice(chunk);
+        rng.pool.absorb(u64::from_le_bytes(word));
+    }
+}
@@ -63,6 +63,9 @@ impl SigAction {
 }
 
 /// Per-process signal state.
+///
+/// Dispositions and the pending set are shared by all threads of a process;
+/// each thread has its own signal mask (`process::UserThread::blocked`).
 #[derive(Clone)]
 pub struct SignalState {
     /// Per-signal dispositions (indexed by signal number - 1).
@@ -71,8 +74,6 @@ pub struct SignalState {
     pub actions: alloc::boxed::Box<[SigAction; NUM_SIGNALS]>,
     /// Bitmask of pending signals (bit N = signal N+1).
     pub pending: u64,
-    /// Bitmask of blocked signals (signal mask).
-    pub blocked: u64,
 }
 
 impl SignalState {
@@ -80,7 +81,6 @@ impl SignalState {
         SignalState {
             actions: alloc::boxed::Box::new([SigAction::default(); NUM_SIGNALS]),
             pending: 0,
-            blocked: 0,
         }
     }
 
@@ -91,10 +91,11 @@ impl SignalState {
         }
     }
 
-    /// Dequeue the lowest-numbered deliverable (pending & !blocked) signal.
+    /// Dequeue the lowest-numbered deliverable (pending & !blocked) signal
+    /// for a thread whose mask is `blocked`.
     /// Returns the signal number (1-based) or None.
-    pub fn dequeue(&mut self) -> Option<u8> {
-        let deliverable = self.pending & !self.blocked;
+    pub fn dequeue(&mut self, blocked: u64) -> Option<u8> {
+        let deliverable = self.pending & !blocked;
         if deliverable == 0 {
             return None;
         }
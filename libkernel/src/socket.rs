@@ -0,0 +1,155 @@
+//! Definitions shared by every socket family.
+//!
+//! Each family lives in a module of its own ([`crate::unix_socket`], and
+//! the internet sockets in the `devices` crate's `net` module) and reports
+//! failures as a [`SocketError`], which osl maps to an errno.  Socket fds
+//! are `FileHandle`s, so `read` / `write` on them report the same error
+//! wrapped in [`FileError::Socket`].
+
+use alloc::vec::Vec;
+use core::task::Waker;
+
+use snafu::Snafu;
+
+use crate::file::FileError;
+use crate::poll::{self, WaitError};
+use crate::process;
+use crate::time;
+
+// ---------------------------------------------------------------------------
+// send / recv flags (Linux values)
+
+pub const MSG_OOB: u32 = 0x1;
+pub const MSG_PEEK: u32 = 0x2;
+pub const MSG_CTRUNC: u32 = 0x8;
+pub const MSG_TRUNC: u32 = 0x20;
+pub const MSG_DONTWAIT: u32 = 0x40;
+pub const MSG_EOR: u32 = 0x80;
+pub const MSG_WAITALL: u32 = 0x100;
+pub const MSG_NOSIGNAL: u32 = 0x4000;
+pub const MSG_CMSG_CLOEXEC: u32 = 0x4000_0000;
+
+/// How a socket carries data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SocketType {
+    /// Connected byte stream (`SOCK_STREAM`).
+    Stream,
+    /// Unconnected messages (`SOCK_DGRAM`).
+    Datagram,
+    /// Connected messages (`SOCK_SEQPACKET`).
+    SeqPacket,
+}
+
+impl SocketType {
+    /// True for the types that must be connected to carry data.
+    pub fn connection_oriented(self) -> bool {
+        self != SocketType::Datagram
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Snafu)]
+pub enum SocketError {
+    #[snafu(display("operation would block"))]
+    WouldBlock,
+    #[snafu(display("interrupted system call"))]
+    Interrupted,
+    #[snafu(display("invalid argument"))]
+    InvalidArgument,
+    #[snafu(display("operation not supported"))]
+    NotSupported,
+    #[snafu(display("operation not permitted"))]
+    NotPermitted,
+    #[snafu(display("transport endpoint is not connected"))]
+    NotConnected,
+    #[snafu(display("transport endpoint is already connected"))]
+    AlreadyConnected,
+    #[snafu(display("connection refused"))]
+    ConnectionRefused,
+    #[snafu(display("address already in use"))]
+    AddressInUse,
+    #[snafu(display("protocol wrong type for socket"))]
+    WrongType,
+    #[snafu(display("broken pipe"))]
+    BrokenPipe,
+    #[snafu(display("message too long"))]
+    MessageTooLong,
+    #[snafu(display("destination address required"))]
+    DestinationRequired,
+    #[snafu(display("cannot assign requested address"))]
+    AddressNotAvailable,
+    #[snafu(display("network is unreachable"))]
+    NetworkUnreachable,
+    #[snafu(display("connection timed out"))]
+    TimedOut,
+    #[snafu(display("operation now in progress"))]
+    InProgress,
+    #[snafu(display("operation already in progress"))]
+    AlreadyInProgress,
+}
+
+impl From<SocketError> for FileError {
+    fn from(error: SocketError) -> Self {
+        FileError::Socket { error }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Helpers for the families
+
+/// Bytes `[start, start + len)` of the concatenation of `bufs`.
+pub fn gather(bufs: &[&[u8]], start: usize, len: usize) -> Vec<u8> {
+    let mut out = Vec::with_capacity(len);
+    let mut skip = start;
+    for buf in bufs {
+        if out.len() == len {
+            break;
+        }
+        if skip >= buf.len() {
+            skip -= buf.len();
+            continue;
+        }
+        let n = (buf.len() - skip).min(len - out.len());
+        out.extend_from_slice(&buf[skip..skip + n]);
+        skip = 0;
+    }
+    out
+}
+
+/// Copy `data` into the concatenation of `bufs`, starting at byte `start`.
+pub fn scatter(bufs: &mut [&mut [u8]], start: usize, mut data: &[u8]) {
+    let mut skip = start;
+    for buf in bufs.iter_mut() {
+        if data.is_empty() {
+            break;
+        }
+        if skip >= buf.len() {
+            skip -= buf.len();
+            continue;
+        }
+        let n = (buf.len() - skip).min(data.len());
+        buf[skip..skip + n].copy_from_slice(&data[..n]);
+        data = &data[n..];
+        skip = 0;
+    }
+}
+
+/// Run `attempt` until it returns a result, blocking in between unless
+/// `nonblock`.  A timeout (`SO_RCVTIMEO` / `SO_SNDTIMEO`) ends the wait
+/// with `WouldBlock`, as on Linux.
+pub fn wait<T>(
+    nonblock: bool,
+    timeout_ns: Option<u64>,
+    mut attempt: impl FnMut(Option<&Waker>) -> Option<Result<T, SocketError>>,
+) -> Result<T, SocketError> {
+    if nonblock {
+        return attempt(None).unwrap_or(Err(SocketError::WouldBlock));
+    }
+    let deadline = timeout_ns.map(|t| time::monotonic_ns().saturating_add(t));
+    let pid = process::current_pid();
+    let tid = process::current_tid();
+    match poll::wait_until(pid, tid, deadline, |waker| attempt(Some(waker))) {
+        Ok(result) => result,
+        Err(WaitError::TimedOut) => Err(SocketError::WouldBlock),
+        Err(WaitError::Interrupted) => Err(SocketError::Interrupted),
+    }
+}
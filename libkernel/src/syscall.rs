@@ -26,19 +26,55 @@ pub struct PerCpuData {
     pub saved_frame_ptr: u64,
 }
 
-/// Layout of the 8 registers pushed on the kernel stack by the SYSCALL entry stub.
-/// Matches the push order: rcx, r11, rdi, rsi, rdx, r10, r8, r9.
-/// RSP points to the r9 slot (lowest address) after all pushes.
+/// Layout of the 14 registers pushed on the kernel stack by the SYSCALL entry stub.
+/// Matches the push order: rcx, r11, rdi, rsi, rdx, r10, r8, r9, rbx, rbp,
+/// r12, r13, r14, r15.  RSP points to the r15 slot (lowest address) after
+/// all pushes.
 #[repr(C)]
 pub struct SyscallSavedFrame {
-    pub r9: u64,      // offset 0  (top of stack after pushes)
-    pub r8: u64,      // offset 8
-    pub r10: u64,     // offset 16
+    pub r15: u64,     // offset 0  (top of stack after pushes)
+    pub r14: u64,     // offset 8
+    pub r13: u64,     // offset 16
+    pub r12: u64,     // offset 24
+    pub rbp: u64,     // offset 32
+    pub rbx: u64,     // offset 40
+    pub r9: u64,      // offset 48
+    pub r8: u64,      // offset 56
+    pub r10: u64,     // offset 64
+    pub rdx: u64,     // offset 72
+    pub rsi: u64,     // offset 80
+    pub rdi: u64,     // offset 88
+    pub r11: u64,     // offset 96  (user RFLAGS)
+    pub rcx: u64,     // offset 104 (user RIP)
+}
+
+/// Complete user-mode register state of a thread at a SYSCALL boundary.
+///
+/// Captured by [`saved_user_context`] and loaded by
+/// `scheduler::resume_userspace` so a clone/fork child "returns from
+/// syscall" with the parent's registers.  Field offsets are hard-coded in
+/// `resume_userspace` — keep in sync.
+#[derive(Debug, Clone, Copy, Default)]
+#[repr(C)]
+pub struct UserContext {
+    pub rax: u64,     // offset 0
+    pub rbx: u64,     // offset 8
+    pub rcx: u64,     // offset 16
     pub rdx: u64,     // offset 24
     pub rsi: u64,     // offset 32
     pub rdi: u64,     // offset 40
-    pub r11: u64,     // offset 48 (user RFLAGS)
-    pub rcx: u64,     // offset 56 (user RIP)
+    pub rbp: u64,     // offset 48
+    pub r8: u64,      // offset 56
+    pub r9: u64,      // offset 64
+    pub r10: u64,     // offset 72
+    pub r11: u64,     // offset 80
+    pub r12: u64,     // offset 88
+    pub r13: u64,     // offset 96
+    pub r14: u64,     // offset 104
+    pub r15: u64,     // offset 112
+    pub rip: u64,     // offset 120
+    pub rsp: u64,     // offset 128
+    pub rflags: u64,  // offset 136
 }
 
 /// Wrapper for the per-CPU data block, replacing `static mut`.
@@ -152,6 +188,16 @@ syscall_entry:
     push r8                     /* save user r8  (a5) */
     push r9                     /* save user r9  (a6) */
 
+    /* Callee-saved registers survive syscall_dispatch anyway, but keeping
+       them in the frame lets clone/fork copy the complete user context and
+       lets signal delivery record and restore them. */
+    push rbx
+    push rbp
+    push r12
+    push r13
+    push r14
+    push r15
+
     mov  gs:40, rsp              /* save frame ptr for signal delivery */
 
     /* Translate syscall ABI -> SysV64 for syscall_dispatch:
@@ -176,6 +222,12 @@ syscall_entry:
     call check_pending_signals  /* returns (possibly modified) rax */
 
     /* Restore user registers (rax has the return value from dispatch). */
+    pop  r15
+    pop  r14
+    pop  r13
+    pop  r12
+    pop  rbp
+    pop  rbx
     pop  r9
     pop  r8
     pop  r10
@@ -275,6 +327,35 @@ pub fn get_saved_frame_ptr() -> *mut SyscallSavedFrame {
     unsafe { (*PER_CPU.get()).saved_frame_ptr as *mut SyscallSavedFrame }
 }
 
+/// Snapshot the complete user register state of the current syscall.
+///
+/// Must be called before the syscall blocks: a context switch lets another
+/// thread's SYSCALL entry overwrite `saved_frame_ptr`.  `rax` is left zero —
+/// the clone child's return value.
+pub fn saved_user_context() -> UserContext {
+    let frame = unsafe { &*get_saved_frame_ptr() };
+    UserContext {
+        rax: 0,
+        rbx: frame.rbx,
+        rcx: frame.rcx,
+        rdx: frame.rdx,
+        rsi: frame.rsi,
+        rdi: frame.rdi,
+        rbp: frame.rbp,
+        r8: frame.r8,
+        r9: frame.r9,
+        r10: frame.r10,
+        r11: frame.r11,
+        r12: frame.r12,
+        r13: frame.r13,
+        r14: frame.r14,
+        r15: frame.r15,
+        rip: frame.rcx,
+        rsp: get_saved_user_rsp(),
+        rflags: frame.r11,
+    }
+}
+
 /// Read the user RSP that was saved by the SYSCALL entry stub into per-CPU.
 ///
 /// This is the user-space RSP at the point of the SYSCALL instruction,
@@ -309,18 +390,44 @@ extern "C" fn check_pending_signals(syscall_ret: i64) -> i64 {
     if pid == crate::process::ProcessId::KERNEL {
         return syscall_ret;
     }
+    let tid = crate::process::current_tid();
+    let ret = deliver_pending_signal(pid, tid, syscall_ret);
+    // A temporary mask not handed to a signal frame ends with the syscall.
+    crate::process::with_process(pid, |p| {
+        if let Some(t) = p.thread_mut(tid) {
+            if let Some(saved) = t.saved_blocked.take() {
+                t.blocked = saved;
+            }
+        }
+    });
+    ret
+}
 
-    // Peek at pending & !blocked — avoid locking if nothing to do.
-    let deliverable = match crate::process::with_process_ref(pid, |p| {
-        p.signal.pending & !p.signal.blocked
+/// Deliver the lowest pending signal `tid` does not block, if any.
+fn deliver_pending_signal(
+    pid: crate::process::ProcessId,
+    tid: crate::process::ProcessId,
+    syscall_ret: i64,
+) -> i64 {
+    // Peek at pending & !blocked — avoid locking if nothing to do.  A thread
+    // told to exit by exit_group/execve in another thread goes no further.
+    let (deliverable, exiting) = match crate::process::with_process_ref(pid, |p| {
+        (p.deliverable(tid), p.thread(tid).map_or(true, |t| t.exiting))
     }) {
-        Some(d) if d != 0 => d,
-        _ => return syscall_ret,
+        Some(v) => v,
+        None => return syscall_ret,
     };
+    if exiting {
+        crate::process::exit_thread(pid, tid, 0);
+    }
+    if deliverable == 0 {
+        return syscall_ret;
+    }
 
     // Dequeue the lowest signal and get its action.
     let (signum, action) = match crate::process::with_process(pid, |p| {
-        if let Some(sig) = p.signal.dequeue() {
+        let blocked = p.blocked(tid);
+        if let Some(sig) = p.signal.dequeue(blocked) {
             let idx = (sig - 1) as usize;
             Some((sig, p.signal.actions[idx]))
         } else {
@@ -331,8 +438,6 @@ extern "C" fn check_pending_signals(syscall_ret: i64) -> i64 {
         _ => return syscall_ret,
     };
 
-    let _ = deliverable; // used only for early-exit check above
-
     use crate::signal::*;
 
     if action.handler == SIG_IGN {
@@ -351,7 +456,7 @@ extern "C" fn check_pending_signals(syscall_ret: i64) -> i64 {
     }
 
     // Deliver signal: construct rt_sigframe on user stack, rewrite saved frame.
-    deliver_signal(pid, signum, &action, syscall_ret);
+    deliver_signal(pid, tid, signum, &action, syscall_ret);
     syscall_ret
 }
 
@@ -359,6 +464,7 @@ extern "C" fn check_pending_signals(syscall_ret: i64) -> i64 {
 /// frame so that sysretq "returns" into the signal handler.
 fn deliver_signal(
     pid: crate::process::ProcessId,
+    tid: crate::process::ProcessId,
     signum: u8,
     action: &crate::signal::SigAction,
     syscall_ret: i64,
@@ -369,14 +475,21 @@ fn deliver_signal(
     let user_rsp = get_saved_user_rsp();
     let orig_rax = syscall_ret as u64;
 
-    let old_blocked = crate::process::with_process_ref(pid, |p| p.signal.blocked)
-        .unwrap_or(0);
+    // The handler's frame gets the mask to return to: the one saved by
+    // ppoll and friends if their temporary mask is still in effect.
+    let old_blocked = crate::process::with_process(pid, |p| {
+        p.thread_mut(tid).map(|t| t.saved_blocked.take().unwrap_or(t.blocked))
+    })
+    .flatten()
+    .unwrap_or(0);
 
     // Block sa_mask + the delivered signal during handler execution.
     crate::process::with_process(pid, |p| {
-        p.signal.blocked |= action.mask | (1u64 << (signum - 1));
-        let unblockable = (1u64 << (SIGKILL - 1)) | (1u64 << (SIGSTOP - 1));
-        p.signal.blocked &= !unblockable;
+        if let Some(t) = p.thread_mut(tid) {
+            t.blocked |= action.mask | (1u64 << (signum - 1));
+            let unblockable = (1u64 << (SIGKILL - 1)) | (1u64 << (SIGSTOP - 1));
+            t.blocked &= !unblockable;
+        }
     });
 
     // rt_sigframe layout (Linux x86_64):
@@ -421,9 +534,14 @@ fn deliver_signal(
         sc.add(1).write(saved.r9);
         sc.add(2).write(saved.r10);
         sc.add(3).write(saved.r11);     // r11 (user RFLAGS from SYSCALL)
-        // r12-r15, rbp, rbx = 0 (not saved by SYSCALL stub; zeroed above)
+        sc.add(4).write(saved.r12);
+        sc.add(5).write(saved.r13);
+        sc.add(6).write(saved.r14);
+        sc.add(7).write(saved.r15);
         sc.add(8).write(saved.rdi);
         sc.add(9).write(saved.rsi);
+        sc.add(10).write(saved.rbp);
+        sc.add(11).write(saved.rbx);
         sc.add(12).write(saved.rdx);
         sc.add(13).write(orig_rax);     // rax (syscall return value)
         sc.add(14).write(saved.rcx);    // rcx (user RIP from SYSCALL)
@@ -494,6 +612,7 @@ pub fn deliver_signal_from_interrupt(
 ) -> bool {
     use crate::signal::*;
 
+    let tid = crate::process::current_tid();
     let action = match crate::process::with_process(pid, |p| {
         let idx = (signum - 1) as usize;
         let act = p.signal.actions[idx];
@@ -514,14 +633,16 @@ pub fn deliver_signal_from_interrupt(
     let user_rip = stack_frame.instruction_pointer.as_u64();
     let user_rflags = stack_frame.cpu_flags.bits();
 
-    let old_blocked = crate::process::with_process_ref(pid, |p| p.signal.blocked)
+    let old_blocked = crate::process::with_process_ref(pid, |p| p.blocked(tid))
         .unwrap_or(0);
 
     // Block sa_mask + the delivered signal during handler execution.
     crate::process::with_process(pid, |p| {
-        p.signal.blocked |= action.mask | (1u64 << (signum - 1));
-        let unblockable = (1u64 << (SIGKILL - 1)) | (1u64 << (SIGSTOP - 1));
-        p.signal.blocked &= !unblockable;
+        if let Some(t) = p.thread_mut(tid) {
+            t.blocked |= action.mask | (1u64 << (signum - 1));
+            let unblockable = (1u64 << (SIGKILL - 1)) | (1u64 << (SIGSTOP - 1));
+            t.blocked &= !unblockable;
+        }
     });
 
     // Same frame layout as deliver_signal (must match rt_sigreturn).
@@ -0,0 +1,100 @@
+//! Mutual exclusion for async tasks.
+//!
+//! Unlike [`SpinMutex`](crate::spin_mutex::SpinMutex), the guard may be held
+//! across `.await`: a task that finds the lock taken registers its waker and
+//! returns `Pending` instead of spinning.  Use it to serialise multi-step
+//! operations on shared state, such as filesystem updates that read and
+//! write several sectors.
+
+use alloc::vec::Vec;
+use core::cell::UnsafeCell;
+use core::future::Future;
+use core::ops::{Deref, DerefMut};
+use core::pin::Pin;
+use core::task::{Context, Poll, Waker};
+
+use crate::spin_mutex::SpinMutex;
+
+struct LockState {
+    locked: bool,
+    waiters: Vec<Waker>,
+}
+
+pub struct AsyncMutex<T> {
+    state: SpinMutex<LockState>,
+    value: UnsafeCell<T>,
+}
+
+// Safety: access to `value` is serialised by `state.locked`.
+unsafe impl<T: Send> Send for AsyncMutex<T> {}
+unsafe impl<T: Send> Sync for AsyncMutex<T> {}
+
+impl<T> AsyncMutex<T> {
+    pub const fn new(value: T) -> Self {
+        AsyncMutex {
+            state: SpinMutex::new(LockState { locked: false, waiters: Vec::new() }),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    /// Wait for the lock.
+    pub fn lock(&self) -> LockFuture<'_, T> {
+        LockFuture { mutex: self }
+    }
+}
+
+/// Future returned by [`AsyncMutex::lock`].
+pub struct LockFuture<'a, T> {
+    mutex: &'a AsyncMutex<T>,
+}
+
+impl<'a, T> Future for LockFuture<'a, T> {
+    type Output = AsyncMutexGuard<'a, T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.mutex.state.lock();
+        if !state.locked {
+            state.locked = true;
+            return Poll::Ready(AsyncMutexGuard { mutex: self.mutex });
+        }
+        if !state.waiters.iter().any(|w| w.will_wake(cx.waker())) {
+            state.waiters.push(cx.waker().clone());
+        }
+        Poll::Pending
+    }
+}
+
+pub struct AsyncMutexGuard<'a, T> {
+    mutex: &'a AsyncMutex<T>,
+}
+
+impl<T> Deref for AsyncMutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // Safety: the guard holds the lock.
+        unsafe { &*self.mutex.value.get() }
+    }
+}
+
+impl<T> DerefMut for AsyncMutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // Safety: the guard holds the lock.
+        unsafe { &mut *self.mutex.value.get() }
+    }
+}
+
+impl<T> Drop for AsyncMutexGuard<'_, T> {
+    fn drop(&mut self) {
+        // Wake every waiter: one of them may have been dropped, and waking
+        // only that one would strand the rest.
+        let waiters = {
+            let mut state = self.mutex.state.lock();
+            state.locked = false;
+            core::mem::take(&mut state.waiters)
+        };
+        for waker in waiters {
+            waker.wake();
+        }
+    }
+}
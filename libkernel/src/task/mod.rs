@@ -23,6 +23,7 @@ where
     stream.poll_next_unpin(cx)
 }
 pub mod simple_executor;
+pub mod async_mutex;
 pub mod keyboard;
 pub mod mailbox;
 pub mod registry;
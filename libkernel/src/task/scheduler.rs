@@ -66,7 +66,8 @@ impl ThreadState {
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum SchedulableKind {
     Kernel,
-    UserProcess(ProcessId),
+    /// Thread `tid` of user process `pid` (`tid == pid` for the main thread).
+    UserProcess { pid: ProcessId, tid: ProcessId },
 }
 
 struct Thread {
@@ -93,6 +94,8 @@ struct Thread {
     /// Saved FS_BASE (IA32_FS_BASE MSR).  musl uses FS-relative addressing
     /// for TLS (errno, etc.), so each user process needs its own FS_BASE.
     fs_base: u64,
+    /// TSC cycles spent running, not counting the current time slice.
+    cpu_cycles: u64,
 }
 
 struct Scheduler {
@@ -104,6 +107,11 @@ struct Scheduler {
     /// Index of the idle thread.  Never on the ready queue — used as fallback
     /// when the ready queue is empty.
     idle_thread_idx: usize,
+    /// Blocked threads with a wake-up deadline, as `(tick, thread index)`.
+    /// Checked on every timer tick; see [`set_wake_deadline`].
+    sleepers: Vec<(u64, usize)>,
+    /// TSC value when the running thread was switched in.
+    slice_start: u64,
 }
 
 impl Scheduler {
@@ -114,6 +122,8 @@ impl Scheduler {
             current_idx: 0,
             ready_queue: VecDeque::new(),
             idle_thread_idx: 0,
+            sleepers: Vec::new(),
+            slice_start: 0,
         }
     }
 
@@ -122,6 +132,8 @@ impl Scheduler {
         // Skip slot 0 (boot thread) — always kept.
         if let Some(idx) = self.threads.iter().position(|t| t.state == ThreadState::Dead) {
             self.threads[idx] = thread;
+            // A dead thread's timeout must not fire on the slot's new owner.
+            self.sleepers.retain(|&(_, i)| i != idx);
             idx
         } else {
             let idx = self.threads.len();
@@ -322,6 +334,7 @@ pub fn init() {
         // ISR context (where the heap allocator may already be locked).
         sched.ready_queue.reserve(16);
         sched.threads.reserve(16);
+        sched.sleepers.reserve(16);
         let id = ThreadId::new();
         sched.threads.push(Thread {
             id,
@@ -334,8 +347,10 @@ pub fn init() {
             kernel_stack_top: 0,
             user_rsp: 0,
             fs_base: 0,
+            cpu_cycles: 0,
         });
         sched.current_idx = 0;
+        sched.slice_start = crate::time::rdtsc();
         sched.initialized = true;
     });
 
@@ -385,6 +400,7 @@ pub fn spawn_thread(entry: fn() -> !) {
             kernel_stack_top: 0,
             user_rsp: 0,
             fs_base: 0,
+            cpu_cycles: 0,
         });
         sched.ready_queue.push_back(idx);
     });
@@ -433,41 +449,75 @@ pub fn spawn_user_thread(pid: ProcessId, pml4_phys: x86_64::PhysAddr) -> usize {
             pml4_phys: pml4_phys.as_u64(),
             ticks_remaining: QUANTUM_TICKS,
             _stack: None, // kernel stack owned by Process, not Thread
-            kind: SchedulableKind::UserProcess(pid),
+            kind: SchedulableKind::UserProcess { pid, tid: pid },
             kernel_stack_top: stack_top,
             user_rsp: 0,
             fs_base: 0,
+            cpu_cycles: 0,
         });
         sched.ready_queue.push_back(idx);
         idx
     })
 }
 
-/// Spawn a scheduler thread for a clone(CLONE_VM|CLONE_VFORK) child.
+/// Spawn a scheduler thread for a clone/fork child.
 ///
-/// The child "returns from syscall" at `user_rip` with RAX=0, on `child_stack`.
+/// The child "returns from syscall" with the register state in `ctx`
+/// (normally the parent's, with RAX=0 and possibly a new RSP).  It runs as
+/// the main thread of `pid`, on the process's kernel stack.
 /// Returns the thread index.
 pub fn spawn_clone_thread(
     pid: ProcessId,
     pml4_phys: x86_64::PhysAddr,
-    user_rip: u64,
-    child_stack: u64,
-    user_rflags: u64,
-    user_r9: u64,
+    ctx: crate::syscall::UserContext,
     fs_base: u64,
 ) -> usize {
     let kernel_stack_top = crate::process::with_process_ref(pid, |p| {
         p.kernel_stack_top
     }).expect("spawn_clone_thread: process not found");
 
+    spawn_resumed_thread(pid, pid, pml4_phys, ctx, fs_base, kernel_stack_top, None)
+}
+
+/// Spawn an additional thread `tid` in process `pid` (`CLONE_THREAD`).
+///
+/// Like [`spawn_clone_thread`], but the thread gets its own kernel stack,
+/// owned by the scheduler and freed when the thread slot is reused.
+/// Returns `None` if the stack arena is exhausted.
+pub fn spawn_process_thread(
+    pid: ProcessId,
+    tid: ProcessId,
+    pml4_phys: x86_64::PhysAddr,
+    ctx: crate::syscall::UserContext,
+    fs_base: u64,
+) -> Option<usize> {
+    let stack = crate::stack_arena::alloc()?;
+    let kernel_stack_top = stack.top();
+    Some(spawn_resumed_thread(pid, tid, pml4_phys, ctx, fs_base, kernel_stack_top, Some(stack)))
+}
+
+fn spawn_resumed_thread(
+    pid: ProcessId,
+    tid: ProcessId,
+    pml4_phys: x86_64::PhysAddr,
+    ctx: crate::syscall::UserContext,
+    fs_base: u64,
+    kernel_stack_top: u64,
+    stack: Option<crate::stack_arena::StackSlot>,
+) -> usize {
+    let user_rsp = ctx.rsp;
+    // The context is handed to the trampoline on the heap; it takes
+    // ownership back with Box::from_raw.
+    let ctx_ptr = alloc::boxed::Box::into_raw(alloc::boxed::Box::new(ctx));
+
     // Build a SwitchFrame that enters `clone_trampoline` in kernel mode.
-    // Pass the PID via RDI (first SysV64 argument).
+    // Pass the PID via RDI, the context pointer via RSI and the TID via RDX.
     let stack_top = kernel_stack_top;
     let frame = SwitchFrame {
         r15: 0, r14: 0, r13: 0, r12: 0,
-        r11: user_rflags, r10: 0, r9: 0, r8: 0,
-        rbp: 0, rdi: pid.as_u64(), rsi: user_rip,
-        rdx: child_stack, rcx: user_r9, rbx: 0, rax: 0,
+        r11: 0, r10: 0, r9: 0, r8: 0,
+        rbp: 0, rdi: pid.as_u64(), rsi: ctx_ptr as u64,
+        rdx: tid.as_u64(), rcx: 0, rbx: 0, rax: 0,
         rip: clone_trampoline as *const () as usize as u64,
         cs: KERNEL_CS,
         rflags: RFLAGS_IF,
@@ -490,47 +540,60 @@ pub fn spawn_clone_thread(
             saved_rsp,
             pml4_phys: pml4_phys.as_u64(),
             ticks_remaining: QUANTUM_TICKS,
-            _stack: None,
-            kind: SchedulableKind::UserProcess(pid),
+            _stack: stack,
+            kind: SchedulableKind::UserProcess { pid, tid },
             kernel_stack_top: stack_top,
-            user_rsp: child_stack,
+            user_rsp,
             fs_base,
+            cpu_cycles: 0,
         });
         sched.ready_queue.push_back(idx);
         idx
     })
 }
 
-/// Trampoline for clone() child threads.
+/// Trampoline for clone/fork child threads.
 ///
-/// Receives PID in RDI, user_rip in RSI, child_stack in RDX, user_r9 in RCX.
-/// Drops to ring 3 at user_rip with RAX=0, R9=user_r9, RSP=child_stack.
-extern "C" fn clone_trampoline(pid_raw: u64, user_rip: u64, child_stack: u64, user_r9: u64) -> ! {
+/// Receives PID in RDI, a boxed `UserContext` in RSI and the TID in RDX.
+/// Drops to ring 3 with every general-purpose register loaded from the
+/// context — unless another thread has already told this one to exit.
+extern "C" fn clone_trampoline(pid_raw: u64, ctx_ptr: u64, tid_raw: u64) -> ! {
     let pid = ProcessId::from_raw(pid_raw);
+    let tid = ProcessId::from_raw(tid_raw);
+    // Move the context onto this stack so the heap copy can be freed
+    // before we leave the kernel for good.
+    let ctx = *unsafe { alloc::boxed::Box::from_raw(ctx_ptr as *mut crate::syscall::UserContext) };
+
+    // Disable interrupts before the exit check: once we are past it nothing
+    // can flag this thread until it is in ring 3, where
+    // `process::kill_other_threads` kills it directly.
+    x86_64::instructions::interrupts::disable();
 
-    let (pml4_phys, kernel_stack_top) =
+    let (pml4_phys, exiting) =
         crate::process::with_process_ref(pid, |p| {
-            (p.pml4_phys.as_u64(), p.kernel_stack_top)
+            (p.pml4_phys.as_u64(), p.thread(tid).map_or(true, |t| t.exiting))
         }).expect("clone_trampoline: process not found");
 
-    serial_println!("[clone_trampoline] pid={} rip={:#x} stack={:#x} pml4={:#x} r9={:#x}",
-        pid.as_u64(), user_rip, child_stack, pml4_phys, user_r9);
+    crate::process::set_current_pid(pid);
+    crate::process::set_current_tid(tid);
+    if exiting {
+        crate::process::exit_thread(pid, tid, 0);
+    }
 
-    x86_64::instructions::interrupts::disable();
+    serial_println!("[clone_trampoline] pid={} tid={} rip={:#x} stack={:#x} pml4={:#x}",
+        pid.as_u64(), tid.as_u64(), ctx.rip, ctx.rsp, pml4_phys);
 
+    let kernel_stack_top = current_kernel_stack_top();
     crate::gdt::set_kernel_stack(x86_64::VirtAddr::new(kernel_stack_top));
     crate::syscall::set_kernel_rsp(kernel_stack_top);
-    crate::process::set_current_pid(pid);
     set_current_cr3(pml4_phys);
 
     let user_cs = crate::gdt::user_code_selector().0 as u64;
     let user_ss = crate::gdt::user_data_selector().0 as u64;
     let per_cpu = crate::syscall::per_cpu_addr();
 
-    // RAX=0 tells musl's __clone that this is the child.
-    // R9=user_r9 restores the child function pointer that musl stored in R9.
     unsafe {
-        jump_to_userspace(user_rip, child_stack, pml4_phys, user_cs, user_ss, per_cpu, 0, user_r9);
+        resume_userspace(&ctx, pml4_phys, user_cs, user_ss, per_cpu);
     }
 }
 
@@ -563,6 +626,7 @@ extern "C" fn process_trampoline(pid_raw: u64) -> ! {
     crate::gdt::set_kernel_stack(x86_64::VirtAddr::new(kernel_stack_top));
     crate::syscall::set_kernel_rsp(kernel_stack_top);
     crate::process::set_current_pid(pid);
+    crate::process::set_current_tid(pid);
 
     // Tell the scheduler about our CR3 for context-switch address space restore.
     // Note: with interrupts already disabled, the without_interrupts() inside
@@ -629,6 +693,65 @@ pub unsafe fn jump_to_userspace(
     );
 }
 
+/// Arithmetic and direction flags a user context may carry into ring 3
+/// (CF, PF, AF, ZF, SF, DF, OF).  IOPL, TF and friends are never restored.
+const USER_RFLAGS_MASK: u64 = 0x0CD5;
+
+/// Drop to ring 3 with the complete register state in `ctx`.
+///
+/// Like [`jump_to_userspace`] but loads every general-purpose register,
+/// so a clone/fork child resumes exactly where the parent's SYSCALL left
+/// off.  RFLAGS is sanitised to the arithmetic flags plus IF.
+///
+/// # Safety
+/// Same requirements as [`jump_to_userspace`]; `ctx.rip`/`ctx.rsp` must be
+/// valid user addresses in `pml4_phys`.
+pub unsafe fn resume_userspace(
+    ctx: &crate::syscall::UserContext,
+    pml4_phys: u64,
+    user_cs: u64, user_ss: u64, per_cpu: u64,
+) -> ! {
+    core::arch::asm!("cli", options(nostack, nomem));
+    x86_64::registers::model_specific::Msr::new(crate::msr::IA32_GS_BASE).write(0);
+    x86_64::registers::model_specific::Msr::new(crate::msr::IA32_KERNEL_GS_BASE).write(per_cpu);
+
+    let rflags = (ctx.rflags & USER_RFLAGS_MASK) | RFLAGS_IF;
+
+    // RDI holds the context pointer and is loaded last.
+    core::arch::asm!(
+        "mov cr3, {pml4}",
+        "push {ss}",
+        "push {usp}",
+        "push {rf}",
+        "push {cs}",
+        "push {ip}",
+        "mov rax, [rdi + 0]",
+        "mov rbx, [rdi + 8]",
+        "mov rcx, [rdi + 16]",
+        "mov rdx, [rdi + 24]",
+        "mov rsi, [rdi + 32]",
+        "mov rbp, [rdi + 48]",
+        "mov r8,  [rdi + 56]",
+        "mov r9,  [rdi + 64]",
+        "mov r10, [rdi + 72]",
+        "mov r11, [rdi + 80]",
+        "mov r12, [rdi + 88]",
+        "mov r13, [rdi + 96]",
+        "mov r14, [rdi + 104]",
+        "mov r15, [rdi + 112]",
+        "mov rdi, [rdi + 40]",
+        "iretq",
+        pml4 = in(reg) pml4_phys,
+        ss   = in(reg) user_ss,
+        usp  = in(reg) ctx.rsp,
+        rf   = in(reg) rflags,
+        cs   = in(reg) user_cs,
+        ip   = in(reg) ctx.rip,
+        in("rdi") ctx as *const crate::syscall::UserContext,
+        options(noreturn),
+    );
+}
+
 /// Returns `true` if the thread at `idx` is in the `Dead` state.
 pub fn is_thread_dead(idx: usize) -> bool {
     x86_64::instructions::interrupts::without_interrupts(|| {
@@ -637,6 +760,62 @@ pub fn is_thread_dead(idx: usize) -> bool {
     })
 }
 
+/// Top of the running thread's kernel stack (0 for kernel threads).
+pub fn current_kernel_stack_top() -> u64 {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let sched = SCHEDULER.lock();
+        sched.threads[sched.current_idx].kernel_stack_top
+    })
+}
+
+/// Change the TID of the running user thread (execve from a non-main
+/// thread takes over the PID).
+pub fn set_current_thread_tid(tid: ProcessId) {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut sched = SCHEDULER.lock();
+        let idx = sched.current_idx;
+        if let SchedulableKind::UserProcess { pid, .. } = sched.threads[idx].kind {
+            sched.threads[idx].kind = SchedulableKind::UserProcess { pid, tid };
+        }
+    });
+    crate::process::set_current_tid(tid);
+}
+
+/// Kill a user thread that was preempted while running in ring 3.
+///
+/// Such a thread holds no kernel locks or waiter registrations, so it can
+/// be marked `Dead` and dropped from the ready queue on the spot.  Returns
+/// `false` (and does nothing) if the thread is running, blocked, or was
+/// last switched out in kernel mode.
+pub fn kill_user_thread(idx: usize) -> bool {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut sched = SCHEDULER.lock();
+        if idx == sched.current_idx {
+            return false;
+        }
+        let in_user = match sched.threads.get(idx) {
+            Some(t) if t.state == ThreadState::Ready
+                && matches!(t.kind, SchedulableKind::UserProcess { .. }) =>
+            {
+                // Safety: a Ready thread's saved_rsp points at the FXSAVE area
+                // below the SwitchFrame pushed by the timer/yield stub.
+                let frame = unsafe {
+                    &*((t.saved_rsp + FXSAVE_SIZE as u64) as *const SwitchFrame)
+                };
+                frame.cs & 3 == 3
+            }
+            _ => false,
+        };
+        if in_user {
+            sched.threads[idx].state = ThreadState::Dead;
+            if let Some(pos) = sched.ready_queue.iter().position(|&i| i == idx) {
+                sched.ready_queue.remove(pos);
+            }
+        }
+        in_user
+    })
+}
+
 /// Mark the current thread as dead and yield.
 ///
 /// The scheduler will see `Dead` and switch to the next ready thread (or the
@@ -710,6 +889,91 @@ pub fn unblock(thread_idx: usize) {
     });
 }
 
+/// Arm a timeout for the current thread: if it is still `Blocked` when the
+/// tick count reaches `deadline`, the timer interrupt unblocks it.
+///
+/// Pair with [`clear_wake_deadline`] once the thread runs again, so a stale
+/// deadline can never wake a later, unrelated block.
+pub fn set_wake_deadline(deadline: u64) {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut sched = SCHEDULER.lock();
+        let idx = sched.current_idx;
+        sched.sleepers.push((deadline, idx));
+    });
+}
+
+/// Disarm any timeout armed by [`set_wake_deadline`] for the current thread.
+pub fn clear_wake_deadline() {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut sched = SCHEDULER.lock();
+        let idx = sched.current_idx;
+        sched.sleepers.retain(|&(_, i)| i != idx);
+    });
+}
+
+/// Unblock sleepers whose deadline has passed.  Called from the timer ISR;
+/// must not allocate.  Returns `true` if any thread was made ready.
+fn wake_expired_sleepers(sched: &mut Scheduler, now: u64) -> bool {
+    let mut woke = false;
+    let mut i = 0;
+    while i < sched.sleepers.len() {
+        let (deadline, idx) = sched.sleepers[i];
+        if deadline > now {
+            i += 1;
+            continue;
+        }
+        sched.sleepers.swap_remove(i);
+        if sched.threads[idx].state == ThreadState::Blocked {
+            sched.threads[idx].state = ThreadState::Ready;
+            sched.ready_queue.push_back(idx);
+            woke = true;
+        }
+    }
+    woke
+}
+
+/// Charge the time slice that is ending to the running thread.  Called on
+/// every context switch, before `current_idx` changes.
+fn charge_cpu_time(sched: &mut Scheduler) {
+    let now = crate::time::rdtsc();
+    let idx = sched.current_idx;
+    sched.threads[idx].cpu_cycles += now.wrapping_sub(sched.slice_start);
+    sched.slice_start = now;
+}
+
+/// TSC cycles the running thread has spent on the CPU, including the
+/// current time slice.
+pub fn current_thread_cpu_cycles() -> u64 {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let sched = SCHEDULER.lock();
+        if !sched.initialized {
+            return 0;
+        }
+        let slice = crate::time::rdtsc().wrapping_sub(sched.slice_start);
+        sched.threads[sched.current_idx].cpu_cycles + slice
+    })
+}
+
+/// TSC cycles the live threads of `pid` have spent on the CPU.  Threads
+/// that have exited are accounted in `Process::exited_cpu_cycles`.
+pub fn process_cpu_cycles(pid: ProcessId) -> u64 {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let sched = SCHEDULER.lock();
+        let now = crate::time::rdtsc();
+        sched.threads.iter().enumerate()
+            .filter(|(_, t)| t.state != ThreadState::Dead)
+            .filter(|(_, t)| matches!(t.kind, SchedulableKind::UserProcess { pid: p, .. } if p == pid))
+            .map(|(idx, t)| {
+                if idx == sched.current_idx {
+                    t.cpu_cycles + now.wrapping_sub(sched.slice_start)
+                } else {
+                    t.cpu_cycles
+                }
+            })
+            .sum()
+    })
+}
+
 // ---------------------------------------------------------------------------
 // Voluntary yield / scheduler donate
 
@@ -780,13 +1044,15 @@ fn restore_thread_state(target: &SwitchTarget) {
     unsafe { crate::msr::write_fs_base(target.next_fs_base); }
 
     match target.next_kind {
-        SchedulableKind::UserProcess(pid) => {
+        SchedulableKind::UserProcess { pid, tid } => {
             crate::gdt::set_kernel_stack(x86_64::VirtAddr::new(target.next_kstack_top));
             crate::syscall::set_kernel_rsp(target.next_kstack_top);
             crate::process::set_current_pid(pid);
+            crate::process::set_current_tid(tid);
         }
         SchedulableKind::Kernel => {
             crate::process::set_current_pid(ProcessId::KERNEL);
+            crate::process::set_current_tid(ProcessId::KERNEL);
         }
     }
 
@@ -843,9 +1109,13 @@ unsafe extern "C" fn preempt_tick(current_rsp: u64) -> u64 {
 
     let current_idx = sched.current_idx;
 
+    // Expired timeouts end the idle thread's quantum early.
+    let woke = wake_expired_sleepers(&mut sched, crate::task::timer::ticks());
+    let idle = current_idx == sched.idle_thread_idx;
+
     // Decrement the running thread's quantum; keep running if ticks remain
     // (unless the thread is Dead or Blocked, in which case we must switch away).
-    if sched.threads[current_idx].state.is_runnable() {
+    if sched.threads[current_idx].state.is_runnable() && !(woke && idle) {
         sched.threads[current_idx].ticks_remaining -= 1;
         if sched.threads[current_idx].ticks_remaining > 0 {
             return current_rsp;
@@ -874,6 +1144,7 @@ unsafe extern "C" fn preempt_tick(current_rsp: u64) -> u64 {
         return current_rsp;
     }
 
+    charge_cpu_time(&mut sched);
     sched.current_idx = next_idx;
     sched.threads[next_idx].state = ThreadState::Running;
     sched.threads[next_idx].ticks_remaining = QUANTUM_TICKS;
@@ -946,6 +1217,7 @@ unsafe extern "C" fn yield_tick(current_rsp: u64) -> u64 {
         return current_rsp;
     }
 
+    charge_cpu_time(&mut sched);
     sched.current_idx = next_idx;
     sched.threads[next_idx].state = ThreadState::Running;
     sched.threads[next_idx].ticks_remaining = QUANTUM_TICKS;
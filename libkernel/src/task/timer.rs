@@ -33,6 +33,12 @@ pub fn ticks() -> u64 {
     TICK_COUNT.load(Ordering::Acquire)
 }
 
+/// Number of ticks covering `ns` nanoseconds, rounded up so a timeout never
+/// fires early.
+pub fn ticks_from_nanos(ns: u64) -> u64 {
+    ns.div_ceil(1_000_000_000 / TICKS_PER_SECOND)
+}
+
 /// Busy-waits until `n` ticks have elapsed, using HLT between checks.
 /// Requires interrupts to be enabled. Safe to call before the executor starts.
 pub fn wait_ticks(n: u64) {
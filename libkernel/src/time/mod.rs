@@ -0,0 +1,159 @@
+//! Kernel clocks.
+//!
+//! Every clock is derived from the TSC.  `apic::calibrate_and_start_lapic_timer`
+//! measures its frequency against the PIT at boot, and [`init`] then reads
+//! the CMOS RTC once to anchor wall-clock time:
+//!
+//! - **monotonic** — nanoseconds since the TSC was reset (power-on).  Also
+//!   serves `CLOCK_BOOTTIME`, since the kernel never suspends.
+//! - **realtime** — monotonic plus the offset taken from the RTC at boot.
+//! - **CPU time** — the scheduler charges TSC cycles to each thread on
+//!   every context switch; see [`process_cpu_ns`] and [`thread_cpu_ns`].
+//!
+//! Until the TSC is calibrated (or on the legacy PIC path, where it never
+//! is) the monotonic clock falls back to counting timer ticks.
+//!
+//! The TSC parameters and realtime offset are also published to the vDSO
+//! data page ([`crate::vdso`]) so user space can read the clocks directly.
+//!
+//! Timed blocks still sleep on the scheduler's tick-based wake deadlines;
+//! [`deadline_ticks`] converts a monotonic deadline to one.
+
+pub mod rtc;
+
+use core::sync::atomic::{AtomicU64, Ordering};
+use snafu::Snafu;
+
+use crate::process::{self, ProcessId};
+use crate::task::{scheduler, timer};
+use crate::wait_condition::WaitCondition;
+
+pub const NSEC_PER_SEC: u64 = 1_000_000_000;
+
+/// Fixed-point shift for the cycles → nanoseconds multiplier.
+pub const TSC_SHIFT: u32 = 32;
+
+/// Calibrated TSC frequency in Hz; 0 until calibrated.
+static TSC_HZ: AtomicU64 = AtomicU64::new(0);
+/// `(NSEC_PER_SEC << TSC_SHIFT) / TSC_HZ`; 0 until calibrated.
+static TSC_MULT: AtomicU64 = AtomicU64::new(0);
+/// Unix time in nanoseconds at monotonic time zero.
+static REALTIME_OFFSET_NS: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, Snafu)]
+pub enum SleepError {
+    #[snafu(display("sleep interrupted"))]
+    Interrupted,
+}
+
+/// Read the time-stamp counter.
+pub fn rdtsc() -> u64 {
+    // Safety: RDTSC is unprivileged (CR4.TSD is clear) and has no side effects.
+    unsafe { core::arch::x86_64::_rdtsc() }
+}
+
+/// Record the TSC frequency measured at boot.
+pub fn set_tsc_frequency(hz: u64) {
+    assert!(hz > 0, "TSC frequency must be non-zero");
+    let mult = ((NSEC_PER_SEC as u128) << TSC_SHIFT) / hz as u128;
+    TSC_HZ.store(hz, Ordering::Relaxed);
+    TSC_MULT.store(mult as u64, Ordering::Release);
+    crate::vdso::publish_clock();
+    if !crate::cpuid::has_invariant_tsc() {
+        warn!("[time] TSC is not invariant; clocks may drift with CPU frequency");
+    }
+}
+
+/// Calibrated TSC frequency in Hz, or 0 if the TSC has not been calibrated.
+pub fn tsc_frequency() -> u64 {
+    TSC_HZ.load(Ordering::Relaxed)
+}
+
+/// Cycles → nanoseconds multiplier (shifted by [`TSC_SHIFT`]), or 0 if the
+/// TSC has not been calibrated.
+pub fn tsc_mult() -> u64 {
+    TSC_MULT.load(Ordering::Acquire)
+}
+
+/// Convert a TSC cycle count to nanoseconds.
+pub fn cycles_to_nanos(cycles: u64) -> u64 {
+    let mult = TSC_MULT.load(Ordering::Acquire);
+    ((cycles as u128 * mult as u128) >> TSC_SHIFT) as u64
+}
+
+/// Nanoseconds on the monotonic clock.
+pub fn monotonic_ns() -> u64 {
+    if TSC_MULT.load(Ordering::Acquire) == 0 {
+        return timer::ticks() * (NSEC_PER_SEC / timer::TICKS_PER_SECOND);
+    }
+    cycles_to_nanos(rdtsc())
+}
+
+/// Nanoseconds since the Unix epoch.
+pub fn realtime_ns() -> u64 {
+    monotonic_ns() + REALTIME_OFFSET_NS.load(Ordering::Relaxed)
+}
+
+/// Offset from the monotonic clock to the realtime clock, in nanoseconds.
+pub fn realtime_offset_ns() -> u64 {
+    REALTIME_OFFSET_NS.load(Ordering::Relaxed)
+}
+
+/// CPU time used by the running thread.
+pub fn thread_cpu_ns() -> u64 {
+    cycles_to_nanos(scheduler::current_thread_cpu_cycles())
+}
+
+/// CPU time used by every thread of `pid`, live and exited.
+pub fn process_cpu_ns(pid: ProcessId) -> u64 {
+    let exited = process::with_process_ref(pid, |p| p.exited_cpu_cycles).unwrap_or(0);
+    cycles_to_nanos(exited + scheduler::process_cpu_cycles(pid))
+}
+
+/// Read the RTC and anchor the realtime clock to it.  Call once at boot,
+/// after the TSC has been calibrated.
+pub fn init() {
+    let now = rtc::read();
+    let unix_ns = now.unix_seconds() * NSEC_PER_SEC;
+    REALTIME_OFFSET_NS.store(unix_ns.saturating_sub(monotonic_ns()), Ordering::Relaxed);
+    crate::vdso::publish_clock();
+    info!("[time] RTC {:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC, TSC {} kHz",
+        now.year, now.month, now.day, now.hour, now.minute, now.second,
+        tsc_frequency() / 1000);
+}
+
+/// Timer tick by which monotonic time `deadline_ns` has certainly passed,
+/// for [`scheduler::set_wake_deadline`].  One tick is added because the
+/// current tick is already partly over.
+pub fn deadline_ticks(deadline_ns: u64) -> u64 {
+    let remaining = deadline_ns.saturating_sub(monotonic_ns());
+    timer::ticks()
+        .saturating_add(timer::ticks_from_nanos(remaining))
+        .saturating_add(1)
+}
+
+/// Block thread `tid` of the running process `pid` until the monotonic
+/// clock reaches `deadline_ns`.
+///
+/// The sleep is interruptible: it ends early with `Interrupted` when a
+/// signal the thread does not block is pending, or the thread is told to
+/// exit.
+pub fn sleep_until(pid: ProcessId, tid: ProcessId, deadline_ns: u64) -> Result<(), SleepError> {
+    loop {
+        if monotonic_ns() >= deadline_ns {
+            return Ok(());
+        }
+        let table = process::lock_table();
+        if table.get(&pid).map_or(true, |p| p.interrupted(tid)) {
+            return Err(SleepError::Interrupted);
+        }
+        WaitCondition::wait_while(Some(table), |table, _| {
+            if let Some(p) = table.get_mut(&pid) {
+                p.set_interruptible(tid, true);
+            }
+            scheduler::set_wake_deadline(deadline_ticks(deadline_ns));
+        });
+        scheduler::clear_wake_deadline();
+        process::with_process(pid, |p| p.set_interruptible(tid, false));
+    }
+}
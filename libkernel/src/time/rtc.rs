@@ -0,0 +1,185 @@
+//! CMOS real-time clock.
+//!
+//! Read once at boot to set the realtime clock.  The RTC keeps UTC (QEMU's
+//! default `-rtc base=utc`); local-time RTCs are not handled.
+
+use x86_64::instructions::port::Port;
+
+const CMOS_ADDR: u16 = 0x70;
+const CMOS_DATA: u16 = 0x71;
+
+const REG_SECONDS: u8 = 0x00;
+const REG_MINUTES: u8 = 0x02;
+const REG_HOURS: u8 = 0x04;
+const REG_DAY: u8 = 0x07;
+const REG_MONTH: u8 = 0x08;
+const REG_YEAR: u8 = 0x09;
+const REG_STATUS_A: u8 = 0x0A;
+const REG_STATUS_B: u8 = 0x0B;
+/// Century register advertised by the ACPI FADT on PC-compatibles.
+const REG_CENTURY: u8 = 0x32;
+
+/// Status A: an update is in progress and the time registers are unstable.
+const STATUS_A_UIP: u8 = 0x80;
+/// Status B: hours are in 24-hour format.
+const STATUS_B_24H: u8 = 0x02;
+/// Status B: registers are binary rather than BCD.
+const STATUS_B_BINARY: u8 = 0x04;
+/// Hours register: PM flag in 12-hour mode.
+const HOURS_PM: u8 = 0x80;
+
+/// A calendar date and time read from the RTC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RtcTime {
+    pub year: u32,
+    pub month: u32,
+    pub day: u32,
+    pub hour: u32,
+    pub minute: u32,
+    pub second: u32,
+}
+
+impl RtcTime {
+    /// Seconds since the Unix epoch.
+    pub fn unix_seconds(&self) -> u64 {
+        let days = days_from_civil(self.year, self.month, self.day);
+        days * 86_400 + (self.hour * 3600 + self.minute * 60 + self.second) as u64
+    }
+
+    /// The UTC date and time `secs` seconds after the Unix epoch.
+    pub fn from_unix_seconds(secs: u64) -> RtcTime {
+        let (year, month, day) = civil_from_days(secs / 86_400);
+        let rem = (secs % 86_400) as u32;
+        RtcTime { year, month, day, hour: rem / 3600, minute: rem / 60 % 60, second: rem % 60 }
+    }
+}
+
+/// Inverse of [`days_from_civil`].
+fn civil_from_days(days: u64) -> (u32, u32, u32) {
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = (if mp < 10 { mp + 3 } else { mp - 9 }) as u32;
+    let year = (yoe + era * 400) as u32 + (month <= 2) as u32;
+    (year, month, day)
+}
+
+/// Days from 1970-01-01 to the given date in the proleptic Gregorian
+/// calendar.  Dates before the epoch clamp to it.
+fn days_from_civil(year: u32, month: u32, day: u32) -> u64 {
+    // Count years from March so the leap day falls at the end.
+    let y = (if month <= 2 { year - 1 } else { year }) as u64;
+    let era = y / 400;
+    let yoe = y - era * 400;
+    let mp = ((month + 9) % 12) as u64;
+    let doy = (153 * mp + 2) / 5 + day as u64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    (era * 146_097 + doe).saturating_sub(719_468)
+}
+
+fn read_register(reg: u8) -> u8 {
+    // Safety: ports 0x70/0x71 are the CMOS index/data pair; selecting a
+    // register and reading it has no other side effects.
+    unsafe {
+        Port::<u8>::new(CMOS_ADDR).write(reg);
+        Port::<u8>::new(CMOS_DATA).read()
+    }
+}
+
+fn update_in_progress() -> bool {
+    read_register(REG_STATUS_A) & STATUS_A_UIP != 0
+}
+
+/// Raw register values: seconds, minutes, hours, day, month, year, century.
+fn read_raw() -> [u8; 7] {
+    while update_in_progress() {
+        core::hint::spin_loop();
+    }
+    [
+        read_register(REG_SECONDS),
+        read_register(REG_MINUTES),
+        read_register(REG_HOURS),
+        read_register(REG_DAY),
+        read_register(REG_MONTH),
+        read_register(REG_YEAR),
+        read_register(REG_CENTURY),
+    ]
+}
+
+fn bcd_to_binary(v: u8) -> u8 {
+    (v & 0x0F) + (v >> 4) * 10
+}
+
+/// Read the current date and time from the RTC.
+pub fn read() -> RtcTime {
+    // An update can start between the UIP check and the reads; read until
+    // two consecutive snapshots agree.
+    let mut raw = read_raw();
+    loop {
+        let again = read_raw();
+        if again == raw {
+            break;
+        }
+        raw = again;
+    }
+
+    let status_b = read_register(REG_STATUS_B);
+    let decode = |v: u8| if status_b & STATUS_B_BINARY != 0 { v } else { bcd_to_binary(v) };
+
+    let [second, minute, hours, day, month, year, century] = raw;
+    let pm = hours & HOURS_PM != 0;
+    let mut hour = decode(hours & !HOURS_PM) as u32;
+    if status_b & STATUS_B_24H == 0 {
+        hour = hour % 12 + if pm { 12 } else { 0 };
+    }
+
+    // Not every RTC implements the century register.
+    let century = match decode(century) {
+        c @ 19..=21 => c as u32,
+        _ => 20,
+    };
+
+    RtcTime {
+        year: century * 100 + decode(year) as u32,
+        month: decode(month) as u32,
+        day: decode(day) as u32,
+        hour,
+        minute: decode(minute) as u32,
+        second: decode(second) as u32,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{serial_print, serial_println};
+    use super::RtcTime;
+
+    fn at(year: u32, month: u32, day: u32, hour: u32, minute: u32, second: u32) -> u64 {
+        RtcTime { year, month, day, hour, minute, second }.unix_seconds()
+    }
+
+    #[test_case]
+    fn test_unix_seconds() {
+        serial_print!("test_unix_seconds... ");
+        assert_eq!(at(1970, 1, 1, 0, 0, 0), 0);
+        assert_eq!(at(2000, 1, 1, 0, 0, 0), 946_684_800);
+        assert_eq!(at(2024, 2, 29, 12, 34, 56), 1_709_210_096);
+        assert_eq!(at(2038, 1, 19, 3, 14, 8), 2_147_483_648);
+        serial_println!("[ok]");
+    }
+
+    #[test_case]
+    fn test_from_unix_seconds() {
+        serial_print!("test_from_unix_seconds... ");
+        let t = RtcTime { year: 2024, month: 2, day: 29, hour: 12, minute: 34, second: 56 };
+        assert_eq!(RtcTime::from_unix_seconds(t.unix_seconds()), t);
+        let t = RtcTime { year: 1999, month: 12, day: 31, hour: 23, minute: 59, second: 59 };
+        assert_eq!(RtcTime::from_unix_seconds(t.unix_seconds()), t);
+        assert_eq!(RtcTime::from_unix_seconds(0).year, 1970);
+        serial_println!("[ok]");
+    }
+}
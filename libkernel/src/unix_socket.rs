@@ -0,0 +1,932 @@
+//! Unix domain sockets (`AF_UNIX`).
+//!
+//! A socket is a `FileHandle` of kind `"unix_socket"`, so `read`, `write`,
+//! `poll`, `dup` and `close` work on it as on a pipe.  Data is queued on
+//! the *receiving* socket as segments, each carrying the sender's
+//! credentials and any file descriptions passed with it (`SCM_RIGHTS`); a
+//! sender blocks while the receiver's queue is full.  Stream sockets read
+//! across segment boundaries, datagram and seqpacket sockets one segment
+//! (message) at a time.
+//!
+//! Named sockets are found through a registry: a filesystem name by the
+//! device and inode of its socket node, which osl creates in the VFS, and
+//! an abstract name (one starting with a NUL byte) by its bytes.
+//!
+//! Connecting to a listener creates the server end at once and queues it
+//! on the listener for `accept`, so the client can send before the server
+//! has accepted, as on Linux.
+//!
+//! A socket lives until the last fd referring to it closes.  Descriptions
+//! in flight count as references, so a socket passed over itself and then
+//! closed is never freed: there is no garbage collector for such cycles.
+//!
+//! The syscalls are in osl's `socket` module.
+
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::string::String;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+use core::task::Waker;
+
+use crate::file::{FdObject, FileError, FileHandle, O_NONBLOCK, O_RDWR};
+use crate::poll::{PollWaiters, POLLHUP, POLLRDHUP, POLL_READABLE, POLL_WRITABLE};
+use crate::process;
+use crate::socket::{
+    gather, scatter, wait, SocketError, SocketType, MSG_DONTWAIT, MSG_PEEK, MSG_WAITALL,
+};
+use crate::spin_mutex::SpinMutex;
+
+/// Default receive queue limit and largest message, in bytes.
+pub const DEFAULT_BUFFER: usize = 64 * 1024;
+/// Bounds of `SO_SNDBUF` / `SO_RCVBUF`.
+pub const MIN_BUFFER: usize = 2048;
+pub const MAX_BUFFER: usize = 1024 * 1024;
+/// Most connections a listener queues (`SOMAXCONN`).
+pub const MAX_BACKLOG: usize = 4096;
+
+/// The name of a socket.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UnixAddr {
+    Unnamed,
+    /// A filesystem name, as given to `bind`.
+    Path(String),
+    /// An abstract name: the bytes after the leading NUL.
+    Abstract(Vec<u8>),
+}
+
+/// What the registry finds a named socket by.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum NameKey {
+    /// The socket node at this device and inode.
+    Inode { dev: u64, ino: u64 },
+    Abstract(Vec<u8>),
+}
+
+/// Process credentials, as passed with `SCM_CREDENTIALS` and reported by
+/// `SO_PEERCRED`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ucred {
+    pub pid: u32,
+    pub uid: u32,
+    pub gid: u32,
+}
+
+impl Ucred {
+    /// What `SO_PEERCRED` reports for a socket with no peer.
+    pub const NONE: Ucred = Ucred { pid: 0, uid: u32::MAX, gid: u32::MAX };
+
+    /// The running process's.  Every process runs as root.
+    pub fn current() -> Self {
+        Ucred { pid: process::current_pid().as_u64() as u32, uid: 0, gid: 0 }
+    }
+}
+
+/// File descriptions passed with `SCM_RIGHTS`.  Each holds a reference
+/// taken with [`FdObject::notify_dup`]; those never installed in a
+/// receiver's fd table are closed on drop.
+#[derive(Default)]
+pub struct Rights(pub Vec<FdObject>);
+
+impl Rights {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Further references to the same objects, for `MSG_PEEK`.
+    fn dup(&self) -> Rights {
+        Rights(self.0.iter().map(|obj| {
+            obj.notify_dup();
+            obj.clone()
+        }).collect())
+    }
+}
+
+impl Drop for Rights {
+    fn drop(&mut self) {
+        for obj in self.0.drain(..) {
+            obj.close();
+        }
+    }
+}
+
+/// What [`UnixSocket::recv`] read.
+pub struct Received {
+    /// Bytes copied to the caller's buffers.
+    pub len: usize,
+    /// Length of the message read from a datagram or seqpacket socket;
+    /// more than `len` if it was truncated.
+    pub msg_len: usize,
+    pub rights: Rights,
+    /// The sender's credentials, if `SO_PASSCRED` is set.
+    pub cred: Option<Ucred>,
+    /// The sender's name.
+    pub from: UnixAddr,
+}
+
+struct Segment {
+    data: Vec<u8>,
+    /// Bytes already read (streams).
+    read: usize,
+    rights: Rights,
+    cred: Ucred,
+    from: UnixAddr,
+}
+
+impl Segment {
+    fn remaining(&self) -> usize {
+        self.data.len() - self.read
+    }
+}
+
+enum Conn {
+    /// Not connected, or a datagram socket with no default destination.
+    Unconnected,
+    Listening { backlog: usize, pending: VecDeque<Arc<UnixSocket>> },
+    /// A stream or seqpacket socket and its peer.
+    Connected(Arc<UnixSocket>),
+    /// A datagram socket's default destination.
+    Datagram(Weak<UnixSocket>),
+    /// A stream or seqpacket socket whose peer has closed.
+    Disconnected,
+}
+
+struct State {
+    name: UnixAddr,
+    key: Option<NameKey>,
+    conn: Conn,
+    queue: VecDeque<Segment>,
+    /// Unread bytes in `queue`, limited by `rcvbuf`.
+    queued: usize,
+    sndbuf: usize,
+    rcvbuf: usize,
+    /// Nothing more will arrive: shut down for reading here, or for
+    /// writing at the peer.  What is queued can still be read.
+    rd_closed: bool,
+    /// Nothing more may be sent.
+    wr_closed: bool,
+    passcred: bool,
+    /// Handed to peers: the creator's, or a listener's at `listen`.
+    cred: Ucred,
+    /// The peer's, from when the connection was made.
+    peer_cred: Ucred,
+    rcvtimeo: Option<u64>,
+    sndtimeo: Option<u64>,
+    /// The last reference has gone.
+    closed: bool,
+    pollers: PollWaiters,
+}
+
+pub struct UnixSocket {
+    ty: SocketType,
+    state: SpinMutex<State>,
+    /// Open fds referring to the socket, plus one per description in
+    /// flight and one while it waits to be accepted.
+    refs: AtomicUsize,
+    nonblock: AtomicBool,
+}
+
+/// Named sockets.
+static NAMES: SpinMutex<BTreeMap<NameKey, Weak<UnixSocket>>> = SpinMutex::new(BTreeMap::new());
+
+static NEXT_AUTOBIND: AtomicU32 = AtomicU32::new(0);
+
+/// The open socket bound to `key`.
+pub fn lookup(key: &NameKey) -> Option<Arc<UnixSocket>> {
+    NAMES.lock().get(key).and_then(Weak::upgrade)
+}
+
+impl UnixSocket {
+    pub fn new(ty: SocketType) -> Arc<Self> {
+        Arc::new(UnixSocket {
+            ty,
+            state: SpinMutex::new(State {
+                name: UnixAddr::Unnamed,
+                key: None,
+                conn: Conn::Unconnected,
+                queue: VecDeque::new(),
+                queued: 0,
+                sndbuf: DEFAULT_BUFFER,
+                rcvbuf: DEFAULT_BUFFER,
+                rd_closed: false,
+                wr_closed: false,
+                passcred: false,
+                cred: Ucred::current(),
+                peer_cred: Ucred::NONE,
+                rcvtimeo: None,
+                sndtimeo: None,
+                closed: false,
+                pollers: PollWaiters::new(),
+            }),
+            refs: AtomicUsize::new(1),
+            nonblock: AtomicBool::new(false),
+        })
+    }
+
+    /// A pair of sockets connected to each other (`socketpair`).
+    pub fn pair(ty: SocketType) -> (Arc<Self>, Arc<Self>) {
+        let a = Self::new(ty);
+        let b = Self::new(ty);
+        for (this, other) in [(&a, &b), (&b, &a)] {
+            let mut st = this.state.lock();
+            st.peer_cred = Ucred::current();
+            st.conn = match ty {
+                SocketType::Datagram => Conn::Datagram(Arc::downgrade(other)),
+                _ => Conn::Connected(other.clone()),
+            };
+        }
+        (a, b)
+    }
+
+    pub fn socket_type(&self) -> SocketType {
+        self.ty
+    }
+
+    pub fn set_nonblocking(&self, nonblock: bool) {
+        self.nonblock.store(nonblock, Ordering::Release);
+    }
+
+    pub fn local_name(&self) -> UnixAddr {
+        self.state.lock().name.clone()
+    }
+
+    /// The name of the other end of the connection, or of a datagram
+    /// socket's default destination.
+    pub fn peer_name(&self) -> Result<UnixAddr, SocketError> {
+        let peer = match &self.state.lock().conn {
+            Conn::Connected(peer) => peer.clone(),
+            Conn::Datagram(peer) => peer.upgrade().ok_or(SocketError::NotConnected)?,
+            _ => return Err(SocketError::NotConnected),
+        };
+        Ok(peer.local_name())
+    }
+
+    pub fn is_bound(&self) -> bool {
+        self.state.lock().key.is_some()
+    }
+
+    /// Name the socket `name`, registered under `key`.  Fails with
+    /// `InvalidArgument` if it is already named, or `AddressInUse` if an
+    /// open socket is registered under `key`.
+    pub fn bind(self: &Arc<Self>, name: UnixAddr, key: NameKey) -> Result<(), SocketError> {
+        let mut names = NAMES.lock();
+        let mut st = self.state.lock();
+        if st.key.is_some() {
+            return Err(SocketError::InvalidArgument);
+        }
+        if names.get(&key).and_then(Weak::upgrade).is_some() {
+            return Err(SocketError::AddressInUse);
+        }
+        names.insert(key.clone(), Arc::downgrade(self));
+        st.name = name;
+        st.key = Some(key);
+        Ok(())
+    }
+
+    /// Bind an unnamed socket to a fresh abstract name of five hex digits,
+    /// as Linux does when one listens or is bound without a name.
+    pub fn autobind(self: &Arc<Self>) -> Result<(), SocketError> {
+        while !self.is_bound() {
+            let n = NEXT_AUTOBIND.fetch_add(1, Ordering::Relaxed) & 0xf_ffff;
+            let name = alloc::format!("{:05x}", n).into_bytes();
+            match self.bind(UnixAddr::Abstract(name.clone()), NameKey::Abstract(name)) {
+                Err(SocketError::AddressInUse) => continue,
+                result => return result,
+            }
+        }
+        Ok(())
+    }
+
+    /// Accept connections, queueing up to `backlog` unaccepted ones.  A
+    /// listening socket may call it again to change the backlog.
+    pub fn listen(self: &Arc<Self>, backlog: usize) -> Result<(), SocketError> {
+        if !self.ty.connection_oriented() {
+            return Err(SocketError::NotSupported);
+        }
+        self.autobind()?;
+        let backlog = backlog.min(MAX_BACKLOG);
+        let mut st = self.state.lock();
+        match &mut st.conn {
+            Conn::Listening { backlog: b, .. } => *b = backlog,
+            conn @ Conn::Unconnected => *conn = Conn::Listening { backlog, pending: VecDeque::new() },
+            _ => return Err(SocketError::InvalidArgument),
+        }
+        st.cred = Ucred::current();
+        Ok(())
+    }
+
+    /// Connect to `target`.  A stream or seqpacket socket queues a new
+    /// connection on the listener `target`, blocking while its backlog is
+    /// full; a datagram socket makes `target` its default destination.
+    pub fn connect(self: &Arc<Self>, target: Arc<UnixSocket>) -> Result<(), SocketError> {
+        if target.ty != self.ty {
+            return Err(SocketError::WrongType);
+        }
+        if self.ty == SocketType::Datagram {
+            let peer_cred = target.state.lock().cred;
+            let mut st = self.state.lock();
+            st.conn = Conn::Datagram(Arc::downgrade(&target));
+            st.peer_cred = peer_cred;
+            return Ok(());
+        }
+        let cred = Ucred::current();
+        let timeout = self.state.lock().sndtimeo;
+        wait(self.nonblock.load(Ordering::Acquire), timeout, |waker| {
+            match self.state.lock().conn {
+                Conn::Unconnected => {}
+                Conn::Listening { .. } => return Some(Err(SocketError::InvalidArgument)),
+                _ => return Some(Err(SocketError::AlreadyConnected)),
+            }
+            let mut guard = target.state.lock();
+            let ts = &mut *guard;
+            let pending = match &mut ts.conn {
+                Conn::Listening { backlog, pending } if pending.len() <= *backlog => pending,
+                Conn::Listening { .. } => {
+                    ts.pollers.register(waker);
+                    return None;
+                }
+                _ => return Some(Err(SocketError::ConnectionRefused)),
+            };
+            let server = UnixSocket::new(self.ty);
+            {
+                let mut ss = server.state.lock();
+                ss.name = ts.name.clone();
+                ss.cred = ts.cred;
+                ss.peer_cred = cred;
+                ss.conn = Conn::Connected(self.clone());
+            }
+            pending.push_back(server.clone());
+            ts.pollers.wake_all();
+            let listener_cred = ts.cred;
+            drop(guard);
+            let mut st = self.state.lock();
+            st.conn = Conn::Connected(server);
+            st.peer_cred = listener_cred;
+            Some(Ok(()))
+        })
+    }
+
+    /// Forget a datagram socket's default destination (`connect` to
+    /// `AF_UNSPEC`).
+    pub fn disconnect(&self) -> Result<(), SocketError> {
+        if self.ty != SocketType::Datagram {
+            return Err(SocketError::InvalidArgument);
+        }
+        let mut st = self.state.lock();
+        st.conn = Conn::Unconnected;
+        st.peer_cred = Ucred::NONE;
+        Ok(())
+    }
+
+    /// Take the next connection queued on a listener, blocking until one
+    /// arrives.
+    pub fn accept(&self) -> Result<Arc<UnixSocket>, SocketError> {
+        if !self.ty.connection_oriented() {
+            return Err(SocketError::NotSupported);
+        }
+        let timeout = self.state.lock().rcvtimeo;
+        wait(self.nonblock.load(Ordering::Acquire), timeout, |waker| {
+            let mut guard = self.state.lock();
+            let st = &mut *guard;
+            let pending = match &mut st.conn {
+                Conn::Listening { pending, .. } => pending,
+                _ => return Some(Err(SocketError::InvalidArgument)),
+            };
+            match pending.pop_front() {
+                Some(server) => {
+                    // A connect may be waiting for room in the backlog.
+                    st.pollers.wake_all();
+                    Some(Ok(server))
+                }
+                None => {
+                    st.pollers.register(waker);
+                    None
+                }
+            }
+        })
+    }
+
+    /// Send the bytes of `bufs`, with `rights` and `cred` attached, to
+    /// `to` or else to the connected peer.  Returns the number of bytes
+    /// sent: all of them unless a stream send is interrupted, times out or
+    /// would block part way through.
+    pub fn send(
+        &self,
+        bufs: &[&[u8]],
+        rights: Rights,
+        cred: Ucred,
+        to: Option<Arc<UnixSocket>>,
+        flags: u32,
+    ) -> Result<usize, SocketError> {
+        let len: usize = bufs.iter().map(|b| b.len()).sum();
+        let nonblock = flags & MSG_DONTWAIT != 0 || self.nonblock.load(Ordering::Acquire);
+        let (timeout, sndbuf, from) = {
+            let st = self.state.lock();
+            (st.sndtimeo, st.sndbuf, st.name.clone())
+        };
+        if let Some(target) = &to {
+            if self.ty.connection_oriented() {
+                return Err(match self.state.lock().conn {
+                    Conn::Connected(_) => SocketError::AlreadyConnected,
+                    _ => SocketError::NotSupported,
+                });
+            }
+            if target.ty != self.ty {
+                return Err(SocketError::WrongType);
+            }
+        }
+
+        if self.ty != SocketType::Stream {
+            if len > sndbuf {
+                return Err(SocketError::MessageTooLong);
+            }
+            let mut segment = Some(Segment { data: gather(bufs, 0, len), read: 0, rights, cred, from });
+            return wait(nonblock, timeout, |waker| {
+                let target = match &to {
+                    Some(target) => target.clone(),
+                    None => match self.destination() {
+                        Ok(target) => target,
+                        Err(e) => return Some(Err(e)),
+                    },
+                };
+                let mut ts = target.state.lock();
+                if ts.closed {
+                    return Some(Err(match self.ty {
+                        SocketType::Datagram => SocketError::ConnectionRefused,
+                        _ => SocketError::BrokenPipe,
+                    }));
+                }
+                if ts.rd_closed {
+                    return Some(Err(SocketError::BrokenPipe));
+                }
+                if let Conn::Datagram(peer) = &ts.conn {
+                    if !core::ptr::eq(peer.as_ptr(), self) {
+                        return Some(Err(SocketError::NotPermitted));
+                    }
+                }
+                if ts.queued != 0 && ts.queued + len > ts.rcvbuf {
+                    ts.pollers.register(waker);
+                    return None;
+                }
+                ts.queued += len;
+                ts.queue.push_back(segment.take().unwrap());
+                ts.pollers.wake_all();
+                Some(Ok(len))
+            });
+        }
+
+        if len == 0 {
+            return self.destination().map(|_| 0);
+        }
+        let mut sent = 0;
+        let mut rights = Some(rights);
+        let result = wait(nonblock, timeout, |waker| {
+            let target = match self.destination() {
+                Ok(target) => target,
+                Err(e) => return Some(Err(e)),
+            };
+            let mut ts = target.state.lock();
+            if ts.closed || ts.rd_closed {
+                return Some(Err(SocketError::BrokenPipe));
+            }
+            let n = ts.rcvbuf.saturating_sub(ts.queued).min(len - sent);
+            if n > 0 {
+                ts.queue.push_back(Segment {
+                    data: gather(bufs, sent, n),
+                    read: 0,
+                    rights: rights.take().unwrap_or_default(),
+                    cred,
+                    from: from.clone(),
+                });
+                ts.queued += n;
+                sent += n;
+                ts.pollers.wake_all();
+            }
+            if sent == len {
+                return Some(Ok(()));
+            }
+            ts.pollers.register(waker);
+            None
+        });
+        match result {
+            Ok(()) => Ok(sent),
+            Err(_) if sent > 0 => Ok(sent),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// The socket data goes to when `send` is given no destination.
+    fn destination(&self) -> Result<Arc<UnixSocket>, SocketError> {
+        let st = self.state.lock();
+        if st.wr_closed {
+            return Err(SocketError::BrokenPipe);
+        }
+        match &st.conn {
+            Conn::Connected(peer) => Ok(peer.clone()),
+            Conn::Datagram(peer) => peer.upgrade().ok_or(SocketError::ConnectionRefused),
+            Conn::Disconnected => Err(SocketError::BrokenPipe),
+            Conn::Unconnected | Conn::Listening { .. } => Err(SocketError::NotConnected),
+        }
+    }
+
+    /// Receive into `bufs`, blocking until something arrives.
+    ///
+    /// A stream read takes what is queued, stopping before a segment with
+    /// file descriptions attached or, with `SO_PASSCRED`, from another
+    /// sender; it reads 0 bytes once the peer has closed or shut down and
+    /// the queue is empty.  A datagram or seqpacket read takes one message,
+    /// discarding what does not fit.
+    pub fn recv(&self, bufs: &mut [&mut [u8]], flags: u32) -> Result<Received, SocketError> {
+        let cap: usize = bufs.iter().map(|b| b.len()).sum();
+        let nonblock = flags & MSG_DONTWAIT != 0 || self.nonblock.load(Ordering::Acquire);
+        let peek = flags & MSG_PEEK != 0;
+        let stream = self.ty == SocketType::Stream;
+        let waitall = stream && flags & MSG_WAITALL != 0 && !peek;
+        let timeout = self.state.lock().rcvtimeo;
+        let mut out = Received {
+            len: 0,
+            msg_len: 0,
+            rights: Rights::default(),
+            cred: None,
+            from: UnixAddr::Unnamed,
+        };
+        let result = wait(nonblock, timeout, |waker| {
+            let mut guard = self.state.lock();
+            let st = &mut *guard;
+            if self.ty.connection_oriented() && out.len == 0 {
+                if let Conn::Unconnected | Conn::Listening { .. } = st.conn {
+                    return Some(Err(match self.ty {
+                        SocketType::Stream => SocketError::InvalidArgument,
+                        _ => SocketError::NotConnected,
+                    }));
+                }
+            }
+            if st.queue.is_empty() || (stream && cap == 0) {
+                if st.rd_closed || (out.len > 0 && !waitall) || (stream && cap == 0) {
+                    return Some(Ok(()));
+                }
+                st.pollers.register(waker);
+                return None;
+            }
+            let boundary = if stream {
+                read_stream(st, bufs, &mut out, peek)
+            } else {
+                read_record(st, bufs, &mut out, peek);
+                true
+            };
+            if !peek {
+                // Senders may be waiting for room.
+                st.pollers.wake_all();
+            }
+            if waitall && !boundary && out.len < cap && !st.rd_closed {
+                st.pollers.register(waker);
+                return None;
+            }
+            Some(Ok(()))
+        });
+        match result {
+            Ok(()) => Ok(out),
+            Err(_) if out.len > 0 => Ok(out),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Shut down reading, writing or both.  On a connection the peer sees
+    /// the other half: end of stream, or `EPIPE` when it writes.
+    pub fn shutdown(&self, read: bool, write: bool) {
+        let peer = {
+            let mut st = self.state.lock();
+            st.rd_closed |= read;
+            st.wr_closed |= write;
+            st.pollers.wake_all();
+            match &st.conn {
+                Conn::Connected(peer) => Some(peer.clone()),
+                _ => None,
+            }
+        };
+        if let Some(peer) = peer {
+            let mut ps = peer.state.lock();
+            ps.rd_closed |= write;
+            ps.wr_closed |= read;
+            ps.pollers.wake_all();
+        }
+    }
+
+    /// The peer's credentials, from when the connection was made.
+    pub fn peer_cred(&self) -> Ucred {
+        self.state.lock().peer_cred
+    }
+
+    pub fn passcred(&self) -> bool {
+        self.state.lock().passcred
+    }
+
+    /// Have `recv` report the sender's credentials (`SO_PASSCRED`).
+    pub fn set_passcred(&self, on: bool) {
+        self.state.lock().passcred = on;
+    }
+
+    pub fn is_listening(&self) -> bool {
+        matches!(self.state.lock().conn, Conn::Listening { .. })
+    }
+
+    /// `(SO_SNDBUF, SO_RCVBUF)`.
+    pub fn buffer_sizes(&self) -> (usize, usize) {
+        let st = self.state.lock();
+        (st.sndbuf, st.rcvbuf)
+    }
+
+    /// Set the largest message (`SO_SNDBUF`), within the bounds.
+    pub fn set_sndbuf(&self, size: usize) {
+        self.state.lock().sndbuf = size.clamp(MIN_BUFFER, MAX_BUFFER);
+    }
+
+    /// Set the receive queue limit (`SO_RCVBUF`), within the bounds.
+    pub fn set_rcvbuf(&self, size: usize) {
+        let mut st = self.state.lock();
+        st.rcvbuf = size.clamp(MIN_BUFFER, MAX_BUFFER);
+        st.pollers.wake_all();
+    }
+
+    /// `(SO_RCVTIMEO, SO_SNDTIMEO)` in nanoseconds; `None` waits forever.
+    pub fn timeouts(&self) -> (Option<u64>, Option<u64>) {
+        let st = self.state.lock();
+        (st.rcvtimeo, st.sndtimeo)
+    }
+
+    pub fn set_timeouts(&self, rcvtimeo: Option<u64>, sndtimeo: Option<u64>) {
+        let mut st = self.state.lock();
+        st.rcvtimeo = rcvtimeo;
+        st.sndtimeo = sndtimeo;
+    }
+
+    /// The last reference has gone: unregister the name, hang up on the
+    /// peer, drop the connections a listener has not accepted and close
+    /// the descriptions in flight to this socket.
+    fn release(&self) {
+        let (key, conn, queue) = {
+            let mut st = self.state.lock();
+            st.closed = true;
+            st.rd_closed = true;
+            st.wr_closed = true;
+            st.queued = 0;
+            st.pollers.wake_all();
+            (
+                st.key.take(),
+                core::mem::replace(&mut st.conn, Conn::Unconnected),
+                core::mem::take(&mut st.queue),
+            )
+        };
+        if let Some(key) = key {
+            let mut names = NAMES.lock();
+            if names.get(&key).map_or(false, |w| core::ptr::eq(w.as_ptr(), self)) {
+                names.remove(&key);
+            }
+        }
+        match conn {
+            Conn::Connected(peer) => peer.hang_up(self),
+            Conn::Listening { pending, .. } => {
+                for server in pending {
+                    server.close();
+                }
+            }
+            _ => {}
+        }
+        drop(queue);
+    }
+
+    /// The peer `gone` has closed: what is queued can still be read, then
+    /// reads see end of stream and writes fail with `EPIPE`.
+    fn hang_up(&self, gone: &UnixSocket) {
+        let old = {
+            let mut st = self.state.lock();
+            match &st.conn {
+                Conn::Connected(peer) if core::ptr::eq(Arc::as_ptr(peer), gone) => {}
+                _ => return,
+            }
+            st.rd_closed = true;
+            st.wr_closed = true;
+            st.pollers.wake_all();
+            core::mem::replace(&mut st.conn, Conn::Disconnected)
+        };
+        drop(old);
+    }
+}
+
+/// Stream read: copy from the queued segments into `bufs` after the
+/// `out.len` bytes already there.  Returns true if it stopped at a
+/// segment that must start a read of its own.
+fn read_stream(st: &mut State, bufs: &mut [&mut [u8]], out: &mut Received, peek: bool) -> bool {
+    let cap: usize = bufs.iter().map(|b| b.len()).sum();
+    let mut i = 0;
+    while out.len < cap && i < st.queue.len() {
+        let seg = &mut st.queue[i];
+        if out.len > 0 {
+            if !out.rights.is_empty()
+                || !seg.rights.is_empty()
+                || (st.passcred && out.cred != Some(seg.cred))
+            {
+                return true;
+            }
+        } else {
+            out.cred = if st.passcred { Some(seg.cred) } else { None };
+            out.rights = if peek { seg.rights.dup() } else { core::mem::take(&mut seg.rights) };
+            out.from = seg.from.clone();
+        }
+        let n = seg.remaining().min(cap - out.len);
+        scatter(bufs, out.len, &seg.data[seg.read..seg.read + n]);
+        out.len += n;
+        if peek {
+            i += 1;
+        } else {
+            seg.read += n;
+            st.queued -= n;
+            if seg.remaining() == 0 {
+                st.queue.remove(i);
+            }
+        }
+    }
+    !out.rights.is_empty()
+}
+
+/// Datagram or seqpacket read: copy the first queued message into
+/// `bufs`, truncating it.
+fn read_record(st: &mut State, bufs: &mut [&mut [u8]], out: &mut Received, peek: bool) {
+    let cap: usize = bufs.iter().map(|b| b.len()).sum();
+    let passcred = st.passcred;
+    let mut take = |seg: &mut Segment, rights: Rights| {
+        out.len = seg.data.len().min(cap);
+        out.msg_len = seg.data.len();
+        scatter(bufs, 0, &seg.data[..out.len]);
+        out.rights = rights;
+        out.cred = if passcred { Some(seg.cred) } else { None };
+        out.from = seg.from.clone();
+    };
+    if peek {
+        let seg = &mut st.queue[0];
+        let rights = seg.rights.dup();
+        take(seg, rights);
+    } else {
+        let mut seg = st.queue.pop_front().unwrap();
+        st.queued -= seg.data.len();
+        let rights = core::mem::take(&mut seg.rights);
+        take(&mut seg, rights);
+    }
+}
+
+impl FileHandle for UnixSocket {
+    fn read(&self, buf: &mut [u8]) -> Result<usize, FileError> {
+        Ok(self.recv(&mut [buf], 0)?.len)
+    }
+
+    fn write(&self, buf: &[u8]) -> Result<usize, FileError> {
+        Ok(self.send(&[buf], Rights::default(), Ucred::current(), None, 0)?)
+    }
+
+    fn close(&self) -> Option<usize> {
+        if self.refs.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.release();
+        }
+        None
+    }
+
+    fn on_dup(&self) {
+        self.refs.fetch_add(1, Ordering::AcqRel);
+    }
+
+    fn status_flags(&self) -> u32 {
+        if self.nonblock.load(Ordering::Acquire) { O_RDWR | O_NONBLOCK } else { O_RDWR }
+    }
+
+    fn set_status_flags(&self, flags: u32) {
+        self.set_nonblocking(flags & O_NONBLOCK != 0);
+    }
+
+    /// Readable while data or a connection is queued, or once nothing more
+    /// will arrive; writable while the peer's queue has room.  `POLLHUP`
+    /// once both directions are shut, and on an unconnected stream or
+    /// seqpacket socket.
+    fn poll_events(&self, waker: Option<&Waker>) -> u32 {
+        let mut mask = 0;
+        let peer = {
+            let mut st = self.state.lock();
+            st.pollers.register(waker);
+            if !st.queue.is_empty() {
+                mask |= POLL_READABLE;
+            }
+            if st.rd_closed {
+                mask |= POLL_READABLE | POLLRDHUP;
+            }
+            if st.rd_closed && st.wr_closed {
+                mask |= POLLHUP;
+            }
+            match &st.conn {
+                Conn::Listening { pending, .. } => {
+                    if !pending.is_empty() {
+                        mask |= POLL_READABLE;
+                    }
+                    return mask;
+                }
+                Conn::Unconnected if self.ty.connection_oriented() => {
+                    return mask | POLLHUP | POLL_WRITABLE;
+                }
+                Conn::Connected(peer) if !st.wr_closed => Some(peer.clone()),
+                Conn::Datagram(peer) => peer.upgrade(),
+                _ => None,
+            }
+        };
+        match peer {
+            Some(peer) => {
+                let mut ps = peer.state.lock();
+                ps.pollers.register(waker);
+                if ps.closed || ps.queued < ps.rcvbuf {
+                    mask |= POLL_WRITABLE;
+                }
+            }
+            None => mask |= POLL_WRITABLE,
+        }
+        mask
+    }
+
+    fn kind(&self) -> &'static str { "unix_socket" }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{serial_print, serial_println};
+
+    fn send(sock: &UnixSocket, data: &[u8]) -> Result<usize, SocketError> {
+        sock.send(&[data], Rights::default(), Ucred::NONE, None, MSG_DONTWAIT)
+    }
+
+    #[test_case]
+    fn test_unix_stream_pair() {
+        serial_print!("test_unix_stream_pair... ");
+        let (a, b) = UnixSocket::pair(SocketType::Stream);
+        assert_eq!(b.poll_events(None) & POLL_READABLE, 0);
+        assert_eq!(send(&a, b"hello ").unwrap(), 6);
+        assert_eq!(send(&a, b"world").unwrap(), 5);
+        assert_ne!(b.poll_events(None) & POLL_READABLE, 0);
+
+        // A stream read crosses segments and scatters over the buffers.
+        let (mut x, mut y) = ([0u8; 4], [0u8; 16]);
+        let got = b.recv(&mut [&mut x[..], &mut y[..]], MSG_DONTWAIT).unwrap();
+        assert_eq!(got.len, 11);
+        assert_eq!(&x, b"hell");
+        assert_eq!(&y[..7], b"o world");
+        assert!(matches!(b.recv(&mut [&mut y[..]], MSG_DONTWAIT), Err(SocketError::WouldBlock)));
+
+        a.close();
+        assert_ne!(b.poll_events(None) & POLLHUP, 0);
+        assert_eq!(b.recv(&mut [&mut y[..]], MSG_DONTWAIT).unwrap().len, 0);
+        assert!(matches!(send(&b, b"x"), Err(SocketError::BrokenPipe)));
+        serial_println!("[ok]");
+    }
+
+    #[test_case]
+    fn test_unix_seqpacket_boundaries() {
+        serial_print!("test_unix_seqpacket_boundaries... ");
+        let (a, b) = UnixSocket::pair(SocketType::SeqPacket);
+        send(&a, b"first message").unwrap();
+        send(&a, b"second").unwrap();
+
+        let mut buf = [0u8; 5];
+        let got = b.recv(&mut [&mut buf[..]], MSG_DONTWAIT | MSG_PEEK).unwrap();
+        assert_eq!((got.len, got.msg_len), (5, 13));
+        let got = b.recv(&mut [&mut buf[..]], MSG_DONTWAIT).unwrap();
+        assert_eq!((got.len, got.msg_len), (5, 13));
+        assert_eq!(&buf, b"first");
+
+        // The rest of a truncated message is discarded.
+        let mut buf = [0u8; 16];
+        let got = b.recv(&mut [&mut buf[..]], MSG_DONTWAIT).unwrap();
+        assert_eq!(&buf[..got.len], b"second");
+        serial_println!("[ok]");
+    }
+
+    #[test_case]
+    fn test_unix_abstract_names() {
+        serial_print!("test_unix_abstract_names... ");
+        let key = NameKey::Abstract(b"test-unix-abstract".to_vec());
+        let name = UnixAddr::Abstract(b"test-unix-abstract".to_vec());
+        let a = UnixSocket::new(SocketType::Datagram);
+        let b = UnixSocket::new(SocketType::Datagram);
+        a.bind(name.clone(), key.clone()).unwrap();
+        assert!(matches!(b.bind(name.clone(), key.clone()), Err(SocketError::AddressInUse)));
+        assert!(Arc::ptr_eq(&lookup(&key).unwrap(), &a));
+
+        b.send(&[&b"ping"[..]], Rights::default(), Ucred::NONE, Some(a.clone()), MSG_DONTWAIT).unwrap();
+        let mut buf = [0u8; 8];
+        let got = a.recv(&mut [&mut buf[..]], MSG_DONTWAIT).unwrap();
+        assert_eq!(&buf[..got.len], b"ping");
+        assert_eq!(got.from, UnixAddr::Unnamed);
+
+        // Closing frees the name.
+        a.close();
+        assert!(lookup(&key).is_none());
+        b.bind(name, key).unwrap();
+        b.close();
+        serial_println!("[ok]");
+    }
+}
@@ -0,0 +1,397 @@
+//! vDSO: a small shared object the kernel maps into every user process.
+//!
+//! It exports `__vdso_clock_gettime`, `__vdso_gettimeofday` and
+//! `__vdso_getcpu`, so the common clocks can be read without a syscall.  musl
+//! finds the image through `AT_SYSINFO_EHDR` and uses it automatically.
+//!
+//! Two global frames are mapped read-only at fixed addresses at the top of
+//! every user address space:
+//!
+//! ```text
+//! VDSO_DATA_VIRT   data page  (NX)  — VdsoData, written by the kernel
+//! VDSO_IMAGE_VIRT  ELF image  (R+X) — copied from the blob below at boot
+//! ```
+//!
+//! The code finds the data page RIP-relatively, one page below its own ELF
+//! header.  The kernel publishes the TSC conversion parameters there
+//! ([`publish_clock`]) under a sequence count: readers retry while the count is
+//! odd or has changed.  While the TSC is uncalibrated the multiplier is 0 and
+//! the functions fall back to the real syscalls.
+//!
+//! Each mapping takes a frame reference (`ref_share`), so process teardown
+//! releases it without ever freeing the frames.  Fork shares them like any
+//! other read-only page.
+
+use core::ptr::{addr_of, addr_of_mut};
+use core::sync::atomic::{compiler_fence, Ordering};
+use conquer_once::spin::OnceCell;
+use x86_64::structures::paging::PageTableFlags;
+use x86_64::{PhysAddr, VirtAddr};
+
+use crate::consts::PAGE_SIZE;
+use crate::memory::{with_memory, MemoryServices};
+
+/// User address of the vDSO data page.
+pub const VDSO_DATA_VIRT: u64 = 0x0000_7FFF_FFE0_0000;
+/// User address of the vDSO ELF image; this is the `AT_SYSINFO_EHDR` value.
+pub const VDSO_IMAGE_VIRT: u64 = VDSO_DATA_VIRT + PAGE_SIZE;
+
+/// Layout of the data page.  The offsets are hard-coded in the assembly.
+#[repr(C)]
+struct VdsoData {
+    /// Sequence count; odd while the kernel is updating the page.
+    seq: u32,
+    _pad: u32,
+    /// Cycles → nanoseconds multiplier; 0 until the TSC is calibrated.
+    tsc_mult: u64,
+    /// Cycles → nanoseconds shift.
+    tsc_shift: u64,
+    /// Realtime minus monotonic, in nanoseconds.
+    realtime_offset_ns: u64,
+}
+
+struct VdsoFrames {
+    data: PhysAddr,
+    image: PhysAddr,
+}
+
+static FRAMES: OnceCell<VdsoFrames> = OnceCell::uninit();
+
+extern "C" {
+    static vdso_image_start: u8;
+    static vdso_image_end: u8;
+}
+
+/// Allocate the data and image frames and copy the image in.  Call once at
+/// boot, before the first user process is created.
+pub fn init() {
+    let image = unsafe {
+        let start = addr_of!(vdso_image_start);
+        let len = addr_of!(vdso_image_end) as usize - start as usize;
+        core::slice::from_raw_parts(start, len)
+    };
+    assert!(image.len() <= PAGE_SIZE as usize, "vDSO image larger than a page");
+
+    let frames = with_memory(|mem| {
+        let phys_off = mem.phys_mem_offset();
+        let data = mem.alloc_dma_pages(1).expect("vdso: out of frames");
+        let code = mem.alloc_dma_pages(1).expect("vdso: out of frames");
+        unsafe {
+            crate::consts::clear_page((phys_off + data.as_u64()).as_mut_ptr::<u8>());
+            let dst = (phys_off + code.as_u64()).as_mut_ptr::<u8>();
+            crate::consts::clear_page(dst);
+            core::ptr::copy_nonoverlapping(image.as_ptr(), dst, image.len());
+        }
+        VdsoFrames { data, image: code }
+    });
+    FRAMES.try_init_once(|| frames).expect("vdso::init called twice");
+    publish_clock();
+    info!("[vdso] {} byte image mapped at {:#x}", image.len(), VDSO_IMAGE_VIRT);
+}
+
+/// Copy the current TSC parameters and realtime offset to the data page.
+/// Call after either changes; does nothing before [`init`].
+pub fn publish_clock() {
+    let frames = match FRAMES.get() {
+        Some(f) => f,
+        None => return,
+    };
+    let data = (crate::memory::phys_mem_offset() + frames.data.as_u64()) as *mut VdsoData;
+    // Safety: the data frame is owned by this module and mapped read-only
+    // into user space, so the kernel is its only writer.
+    unsafe {
+        let seq = addr_of!((*data).seq).read_volatile();
+        addr_of_mut!((*data).seq).write_volatile(seq.wrapping_add(1));
+        compiler_fence(Ordering::SeqCst);
+        addr_of_mut!((*data).tsc_mult).write_volatile(crate::time::tsc_mult());
+        addr_of_mut!((*data).tsc_shift).write_volatile(crate::time::TSC_SHIFT as u64);
+        addr_of_mut!((*data).realtime_offset_ns).write_volatile(crate::time::realtime_offset_ns());
+        compiler_fence(Ordering::SeqCst);
+        addr_of_mut!((*data).seq).write_volatile(seq.wrapping_add(2));
+    }
+}
+
+/// Map the vDSO into the user address space rooted at `pml4_phys`.
+/// Returns `false`, mapping nothing, if the vDSO is not initialised.
+pub fn map_into(mem: &mut MemoryServices, pml4_phys: PhysAddr) -> bool {
+    let frames = match FRAMES.get() {
+        Some(f) => f,
+        None => return false,
+    };
+    let user = PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+    mem.map_user_page(pml4_phys, VirtAddr::new(VDSO_DATA_VIRT), frames.data,
+        user | PageTableFlags::NO_EXECUTE)
+        .expect("vdso: failed to map data page");
+    mem.map_user_page(pml4_phys, VirtAddr::new(VDSO_IMAGE_VIRT), frames.image, user)
+        .expect("vdso: failed to map image");
+    mem.ref_share(frames.data);
+    mem.ref_share(frames.image);
+    true
+}
+
+/// `AT_SYSINFO_EHDR` value for new processes, or `None` before [`init`].
+pub fn image_base() -> Option<u64> {
+    FRAMES.get().map(|_| VDSO_IMAGE_VIRT)
+}
+
+// The vDSO image: a complete ELF64 ET_DYN object with one PT_LOAD segment
+// at vaddr 0, a PT_DYNAMIC segment, DT_HASH/DT_SYMTAB/DT_STRTAB for symbol
+// lookup, and section headers so readelf and debuggers can read it.  It has
+// no relocations and no symbol versions.
+//
+// LLVM's assembler defaults to Intel syntax (noprefix).  Numeric local
+// labels are avoided because Intel syntax reads `1b` as a binary literal.
+core::arch::global_asm!(r#"
+.pushsection .rodata.vdso, "a"
+.balign 4096
+.global vdso_image_start
+vdso_image_start:
+.Lvdso_ehdr:
+    .byte 0x7f, 0x45, 0x4c, 0x46    /* ELF magic */
+    .byte 2, 1, 1, 0                /* ELFCLASS64, little-endian, EV_CURRENT, SysV ABI */
+    .quad 0                         /* ABI version, padding */
+    .short 3                        /* e_type = ET_DYN */
+    .short 62                       /* e_machine = EM_X86_64 */
+    .long 1                         /* e_version */
+    .quad 0                         /* e_entry */
+    .quad .Lvdso_phdr - .Lvdso_ehdr /* e_phoff */
+    .quad .Lvdso_shdr - .Lvdso_ehdr /* e_shoff */
+    .long 0                         /* e_flags */
+    .short 64                       /* e_ehsize */
+    .short 56                       /* e_phentsize */
+    .short 2                        /* e_phnum */
+    .short 64                       /* e_shentsize */
+    .short 7                        /* e_shnum */
+    .short 6                        /* e_shstrndx */
+
+.Lvdso_phdr:
+    /* PT_LOAD, R+X: the whole image */
+    .long 1, 5
+    .quad 0, 0, 0
+    .quad .Lvdso_end - .Lvdso_ehdr, .Lvdso_end - .Lvdso_ehdr
+    .quad 4096
+    /* PT_DYNAMIC, R */
+    .long 2, 4
+    .quad .Lvdso_dynamic - .Lvdso_ehdr, .Lvdso_dynamic - .Lvdso_ehdr, .Lvdso_dynamic - .Lvdso_ehdr
+    .quad .Lvdso_dynamic_end - .Lvdso_dynamic, .Lvdso_dynamic_end - .Lvdso_dynamic
+    .quad 8
+
+/* SysV hash table: a single bucket chaining every symbol. */
+.balign 8
+.Lvdso_hash:
+    .long 1, 4                      /* nbucket, nchain */
+    .long 1                         /* bucket[0] */
+    .long 0, 2, 3, 0                /* chain[] */
+.Lvdso_hash_end:
+
+.balign 8
+.Lvdso_dynsym:
+    .long 0
+    .byte 0, 0
+    .short 0
+    .quad 0, 0
+    .long .Lstr_clock_gettime - .Lvdso_dynstr
+    .byte 0x12, 0                   /* STB_GLOBAL | STT_FUNC, STV_DEFAULT */
+    .short 4                        /* .text */
+    .quad .Lvdso_clock_gettime - .Lvdso_ehdr
+    .quad .Lvdso_clock_gettime_end - .Lvdso_clock_gettime
+    .long .Lstr_gettimeofday - .Lvdso_dynstr
+    .byte 0x12, 0
+    .short 4
+    .quad .Lvdso_gettimeofday - .Lvdso_ehdr
+    .quad .Lvdso_gettimeofday_end - .Lvdso_gettimeofday
+    .long .Lstr_getcpu - .Lvdso_dynstr
+    .byte 0x12, 0
+    .short 4
+    .quad .Lvdso_getcpu - .Lvdso_ehdr
+    .quad .Lvdso_getcpu_end - .Lvdso_getcpu
+.Lvdso_dynsym_end:
+
+.Lvdso_dynstr:
+    .byte 0
+.Lstr_clock_gettime:
+    .asciz "__vdso_clock_gettime"
+.Lstr_gettimeofday:
+    .asciz "__vdso_gettimeofday"
+.Lstr_getcpu:
+    .asciz "__vdso_getcpu"
+.Lvdso_dynstr_end:
+
+.balign 8
+.Lvdso_dynamic:
+    .quad 4, .Lvdso_hash - .Lvdso_ehdr                  /* DT_HASH */
+    .quad 5, .Lvdso_dynstr - .Lvdso_ehdr                /* DT_STRTAB */
+    .quad 6, .Lvdso_dynsym - .Lvdso_ehdr                /* DT_SYMTAB */
+    .quad 10, .Lvdso_dynstr_end - .Lvdso_dynstr         /* DT_STRSZ */
+    .quad 11, 24                                        /* DT_SYMENT */
+    .quad 0, 0                                          /* DT_NULL */
+.Lvdso_dynamic_end:
+
+.Lvdso_shstrtab:
+    .byte 0
+.Lsh_hash:
+    .asciz ".hash"
+.Lsh_dynsym:
+    .asciz ".dynsym"
+.Lsh_dynstr:
+    .asciz ".dynstr"
+.Lsh_text:
+    .asciz ".text"
+.Lsh_dynamic:
+    .asciz ".dynamic"
+.Lsh_shstrtab:
+    .asciz ".shstrtab"
+.Lvdso_shstrtab_end:
+
+/* Section headers: name, type, flags, addr, offset, size, link, info,
+   addralign, entsize. */
+.balign 8
+.Lvdso_shdr:
+    .zero 64
+    .long .Lsh_hash - .Lvdso_shstrtab, 5                /* SHT_HASH */
+    .quad 2, .Lvdso_hash - .Lvdso_ehdr, .Lvdso_hash - .Lvdso_ehdr
+    .quad .Lvdso_hash_end - .Lvdso_hash
+    .long 2, 0
+    .quad 8, 4
+    .long .Lsh_dynsym - .Lvdso_shstrtab, 11             /* SHT_DYNSYM */
+    .quad 2, .Lvdso_dynsym - .Lvdso_ehdr, .Lvdso_dynsym - .Lvdso_ehdr
+    .quad .Lvdso_dynsym_end - .Lvdso_dynsym
+    .long 3, 1
+    .quad 8, 24
+    .long .Lsh_dynstr - .Lvdso_shstrtab, 3              /* SHT_STRTAB */
+    .quad 2, .Lvdso_dynstr - .Lvdso_ehdr, .Lvdso_dynstr - .Lvdso_ehdr
+    .quad .Lvdso_dynstr_end - .Lvdso_dynstr
+    .long 0, 0
+    .quad 1, 0
+    .long .Lsh_text - .Lvdso_shstrtab, 1                /* SHT_PROGBITS */
+    .quad 6, .Lvdso_text - .Lvdso_ehdr, .Lvdso_text - .Lvdso_ehdr
+    .quad .Lvdso_end - .Lvdso_text
+    .long 0, 0
+    .quad 16, 0
+    .long .Lsh_dynamic - .Lvdso_shstrtab, 6             /* SHT_DYNAMIC */
+    .quad 2, .Lvdso_dynamic - .Lvdso_ehdr, .Lvdso_dynamic - .Lvdso_ehdr
+    .quad .Lvdso_dynamic_end - .Lvdso_dynamic
+    .long 3, 0
+    .quad 8, 16
+    .long .Lsh_shstrtab - .Lvdso_shstrtab, 3            /* SHT_STRTAB */
+    .quad 0, 0, .Lvdso_shstrtab - .Lvdso_ehdr
+    .quad .Lvdso_shstrtab_end - .Lvdso_shstrtab
+    .long 0, 0
+    .quad 1, 0
+
+.balign 16
+.Lvdso_text:
+
+/* Read the monotonic clock from the data page (VdsoData) one page below
+   the image.  Returns nanoseconds in rax and the realtime offset in r11, or
+   rax = -1 if the TSC is uncalibrated.  Clobbers rcx, rdx, r8-r10. */
+.Lvdso_read_clock:
+    lea r8, [rip + .Lvdso_ehdr - 4096]
+.Lread_retry:
+    mov r9d, dword ptr [r8]         /* seq */
+    test r9d, 1
+    jnz .Lread_busy
+    mov r10, qword ptr [r8 + 8]     /* tsc_mult */
+    test r10, r10
+    jz .Lread_uncalibrated
+    mov rcx, qword ptr [r8 + 16]    /* tsc_shift */
+    mov r11, qword ptr [r8 + 24]    /* realtime_offset_ns */
+    lfence
+    rdtsc
+    shl rdx, 32
+    or rax, rdx
+    mul r10
+    shrd rax, rdx, cl               /* (tsc * mult) >> shift */
+    cmp r9d, dword ptr [r8]
+    jne .Lread_retry
+    ret
+.Lread_busy:
+    pause
+    jmp .Lread_retry
+.Lread_uncalibrated:
+    mov rax, -1
+    ret
+
+/* int __vdso_clock_gettime(clockid_t clk, struct timespec *ts) */
+.balign 16
+.Lvdso_clock_gettime:
+    cmp edi, 7
+    ja .Lcgt_syscall
+    mov eax, 0xf3                   /* REALTIME, MONOTONIC, *_RAW, *_COARSE, BOOTTIME */
+    bt eax, edi
+    jnc .Lcgt_syscall
+    call .Lvdso_read_clock
+    cmp rax, -1
+    je .Lcgt_syscall
+    test edi, edi                   /* CLOCK_REALTIME */
+    je .Lcgt_realtime
+    cmp edi, 5                      /* CLOCK_REALTIME_COARSE */
+    jne .Lcgt_store
+.Lcgt_realtime:
+    add rax, r11
+.Lcgt_store:
+    xor edx, edx
+    mov ecx, 1000000000
+    div rcx
+    mov qword ptr [rsi], rax
+    mov qword ptr [rsi + 8], rdx
+    xor eax, eax
+    ret
+.Lcgt_syscall:
+    mov eax, 228                    /* SYS_clock_gettime */
+    syscall
+    ret
+.Lvdso_clock_gettime_end:
+
+/* int __vdso_gettimeofday(struct timeval *tv, struct timezone *tz) */
+.balign 16
+.Lvdso_gettimeofday:
+    call .Lvdso_read_clock
+    cmp rax, -1
+    je .Lgtod_syscall
+    test rdi, rdi
+    jz .Lgtod_tz
+    add rax, r11
+    xor edx, edx
+    mov ecx, 1000000000
+    div rcx
+    mov qword ptr [rdi], rax
+    mov rax, rdx
+    xor edx, edx
+    mov ecx, 1000
+    div rcx
+    mov qword ptr [rdi + 8], rax
+.Lgtod_tz:
+    test rsi, rsi
+    jz .Lgtod_done
+    mov qword ptr [rsi], 0          /* UTC, no DST */
+.Lgtod_done:
+    xor eax, eax
+    ret
+.Lgtod_syscall:
+    mov eax, 96                     /* SYS_gettimeofday */
+    syscall
+    ret
+.Lvdso_gettimeofday_end:
+
+/* int __vdso_getcpu(unsigned *cpu, unsigned *node, void *cache)
+   The kernel runs on a single CPU. */
+.balign 16
+.Lvdso_getcpu:
+    test rdi, rdi
+    jz .Lgetcpu_node
+    mov dword ptr [rdi], 0
+.Lgetcpu_node:
+    test rsi, rsi
+    jz .Lgetcpu_done
+    mov dword ptr [rsi], 0
+.Lgetcpu_done:
+    xor eax, eax
+    ret
+.Lvdso_getcpu_end:
+
+.Lvdso_end:
+.global vdso_image_end
+vdso_image_end:
+.popsection
+"#);
@@ -1,80 +1,147 @@
-//! clone(2) syscall implementation (CLONE_VM|CLONE_VFORK semantics).
+//! clone(2), fork(2) and vfork(2) syscall implementations.
+//!
+//! Three shapes of clone are supported:
+//!
+//! - **vfork** (`CLONE_VM | CLONE_VFORK`): the child shares the parent's
+//!   page tables and the parent blocks until the child calls execve/_exit.
+//!   This is what musl's `posix_spawn` uses.
+//! - **fork** (no `CLONE_VM`): the child gets a copy-on-write duplicate of
+//!   the parent's address space and both run concurrently.
+//! - **thread** (`CLONE_VM | CLONE_SIGHAND | CLONE_THREAD`): a new thread
+//!   in the calling process, as created by musl's `pthread_create`.
+
+use alloc::vec::Vec;
 
 use crate::errno;
-use libkernel::process::{self, Process};
+use libkernel::futex;
+use libkernel::process::{self, Process, UserThread, MAP_SHARED};
 use libkernel::task::scheduler;
 use libkernel::wait_condition::WaitCondition;
 
-/// Flags we accept from musl's posix_spawn: CLONE_VM | CLONE_VFORK | SIGCHLD.
-const CLONE_VM: u64    = 0x0000_0100;
-const CLONE_VFORK: u64 = 0x0000_4000;
-const SIGCHLD: u64     = 17;
-const SUPPORTED_FLAGS: u64 = CLONE_VM | CLONE_VFORK | SIGCHLD;
+pub const CLONE_VM: u64             = 0x0000_0100;
+pub const CLONE_FS: u64             = 0x0000_0200;
+pub const CLONE_FILES: u64          = 0x0000_0400;
+pub const CLONE_SIGHAND: u64        = 0x0000_0800;
+pub const CLONE_VFORK: u64          = 0x0000_4000;
+pub const CLONE_THREAD: u64         = 0x0001_0000;
+pub const CLONE_SYSVSEM: u64        = 0x0004_0000;
+pub const CLONE_SETTLS: u64         = 0x0008_0000;
+pub const CLONE_PARENT_SETTID: u64  = 0x0010_0000;
+pub const CLONE_CHILD_CLEARTID: u64 = 0x0020_0000;
+pub const CLONE_DETACHED: u64       = 0x0040_0000;
+pub const CLONE_CHILD_SETTID: u64   = 0x0100_0000;
+pub const SIGCHLD: u64              = 17;
+/// Low byte of the flags: signal sent to the parent when the child exits.
+const CSIGNAL: u64                  = 0x0000_00FF;
 
-pub fn sys_clone(flags: u64, child_stack: u64, _ptid: u64, _ctid: u64, _tls: u64) -> i64 {
-    // Only support the exact flag combination musl uses for posix_spawn.
-    if flags & !SUPPORTED_FLAGS != 0 || flags & CLONE_VM == 0 || flags & CLONE_VFORK == 0 {
-        libkernel::serial_println!("[clone] unsupported flags: {:#x}", flags);
-        return -errno::ENOSYS;
+/// Flags that must all be present for a thread clone.
+const THREAD_REQUIRED: u64 = CLONE_VM | CLONE_SIGHAND | CLONE_THREAD;
+/// Flags accepted alongside `THREAD_REQUIRED`.  Threads always share the
+/// filesystem context and fd table, so `CLONE_FS`/`CLONE_FILES` are implied.
+const THREAD_OPTIONAL: u64 = CLONE_FS | CLONE_FILES | CLONE_SYSVSEM | CLONE_SETTLS
+    | CLONE_PARENT_SETTID | CLONE_CHILD_CLEARTID | CLONE_CHILD_SETTID | CLONE_DETACHED;
+
+pub fn sys_clone(flags: u64, child_stack: u64, ptid: u64, ctid: u64, tls: u64) -> i64 {
+    // Capture the parent's registers before anything can block and let
+    // another thread's SYSCALL entry overwrite the saved frame pointer.
+    let mut ctx = libkernel::syscall::saved_user_context();
+    if child_stack != 0 {
+        ctx.rsp = child_stack;
     }
 
-    // CLONE_VFORK: child_stack == 0 means "share parent's stack".
-    // This is safe because the parent is blocked until the child calls execve/_exit.
-    let child_stack = if child_stack == 0 {
-        libkernel::syscall::get_user_rsp()
+    let extra = flags & !CSIGNAL;
+    if extra & CLONE_THREAD != 0 {
+        if extra & THREAD_REQUIRED != THREAD_REQUIRED {
+            return -errno::EINVAL;
+        }
+        if extra & !(THREAD_REQUIRED | THREAD_OPTIONAL) != 0 {
+            libkernel::serial_println!("[clone] unsupported thread flags: {:#x}", flags);
+            return -errno::ENOSYS;
+        }
+        clone_thread(extra, ctx, ptid, ctid, tls)
+    } else if extra == CLONE_VM | CLONE_VFORK {
+        // CLONE_VFORK: child_stack == 0 means "share parent's stack".
+        // This is safe because the parent is blocked until the child calls
+        // execve/_exit.
+        clone_vfork(ctx)
+    } else if extra == 0 {
+        clone_fork(ctx)
     } else {
-        child_stack
-    };
+        libkernel::serial_println!("[clone] unsupported flags: {:#x}", flags);
+        -errno::ENOSYS
+    }
+}
 
-    let parent_pid = process::current_pid();
-    let parent_thread_idx = scheduler::current_thread_idx();
+/// `fork()` (syscall 57) — `clone(SIGCHLD, 0)`.
+pub fn sys_fork() -> i64 {
+    sys_clone(SIGCHLD, 0, 0, 0, 0)
+}
 
-    // Read parent process info needed for the child.
-    let parent_info = match process::with_process_ref(parent_pid, |p| {
-        (p.pml4_phys, p.cwd.clone(), p.fd_table.clone(),
-         p.brk_base, p.brk_current, p.vma_map.clone())
-    }) {
-        Some(info) => info,
-        None => return -errno::ENOSYS,
-    };
-    let (pml4_phys, cwd, fd_table, brk_base, brk_current, vma_map) = parent_info;
+/// `vfork()` (syscall 58) — `clone(CLONE_VM | CLONE_VFORK | SIGCHLD, 0)`.
+pub fn sys_vfork() -> i64 {
+    sys_clone(CLONE_VM | CLONE_VFORK | SIGCHLD, 0, 0, 0, 0)
+}
+
+/// Build the child `Process` shared by both clone shapes: inherited cwd,
+/// fd table, heap bounds, VMAs and signal dispositions.
+fn new_child(parent: &Process, pml4_phys: x86_64::PhysAddr, ctx: &libkernel::syscall::UserContext) -> Process {
+    let mut child = Process::new(pml4_phys, ctx.rip, ctx.rsp, parent.brk_base);
+    child.parent_pid = parent.pid;
+    child.cwd = parent.cwd.clone();
+    child.fd_table = parent.fd_table.clone();
+    child.brk_current = parent.brk_current;
+    child.vma_map = parent.vma_map.clone();
+    child.user_stack_top = parent.user_stack_top;
+    // Dispositions and the calling thread's mask are inherited; pending
+    // signals are not.
+    child.signal = parent.signal.clone();
+    child.signal.pending = 0;
+    let blocked = parent.blocked(process::current_tid());
+    if let Some(t) = child.thread_mut(child.pid) {
+        t.blocked = blocked;
+    }
 
     // Notify handles that fds were duplicated (e.g. PipeWriter writer_count).
-    for slot in &fd_table {
+    for slot in &child.fd_table {
         if let Some(entry) = slot {
             entry.object.notify_dup();
         }
     }
+    child
+}
 
-    // Read user RIP, RFLAGS, R9 and FS_BASE saved by the SYSCALL entry stub.
-    let user_rip = libkernel::syscall::get_user_rip();
-    let user_rflags = libkernel::syscall::get_user_rflags();
-    let user_r9 = libkernel::syscall::get_user_r9();
+/// Insert `child` and start its thread "returning from syscall" with `ctx`.
+fn start_child(child: Process, pml4_phys: x86_64::PhysAddr, ctx: libkernel::syscall::UserContext) -> process::ProcessId {
     let fs_base = libkernel::msr::read_fs_base();
-
-    // Create child process sharing the parent's address space (CLONE_VM).
-    let mut child = Process::new(pml4_phys, user_rip, child_stack, brk_base);
-    child.parent_pid = parent_pid;
-    child.cwd = cwd;
-    child.fd_table = fd_table;
-    child.brk_current = brk_current;
-    child.vma_map = vma_map;
-    child.vfork_parent_thread = Some(parent_thread_idx);
-    child.pml4_shared = true;
-
     let child_pid = child.pid;
     process::insert(child);
 
-    // Spawn a scheduler thread for the child that "returns from syscall" with RAX=0.
-    let thread_idx = scheduler::spawn_clone_thread(
-        child_pid, pml4_phys, user_rip, child_stack, user_rflags, user_r9, fs_base,
-    );
+    let thread_idx = scheduler::spawn_clone_thread(child_pid, pml4_phys, ctx, fs_base);
     process::with_process(child_pid, |p| {
-        p.thread_idx = Some(thread_idx);
+        p.set_main_thread(thread_idx);
     });
+    child_pid
+}
 
-    libkernel::serial_println!("[clone] parent={} child={} child_stack={:#x} user_rip={:#x}",
-        parent_pid.as_u64(), child_pid.as_u64(), child_stack, user_rip);
+fn clone_vfork(ctx: libkernel::syscall::UserContext) -> i64 {
+    let parent_pid = process::current_pid();
+    let parent_thread_idx = scheduler::current_thread_idx();
+
+    // Create child process sharing the parent's address space (CLONE_VM).
+    let child = match process::with_process_ref(parent_pid, |p| {
+        let mut child = new_child(p, p.pml4_phys, &ctx);
+        child.vfork_parent_thread = Some(parent_thread_idx);
+        child.pml4_shared = true;
+        child
+    }) {
+        Some(c) => c,
+        None => return -errno::ENOSYS,
+    };
+    let pml4_phys = child.pml4_phys;
+    let child_pid = start_child(child, pml4_phys, ctx);
+
+    libkernel::serial_println!("[clone] vfork parent={} child={} child_stack={:#x} user_rip={:#x}",
+        parent_pid.as_u64(), child_pid.as_u64(), ctx.rsp, ctx.rip);
 
     // CLONE_VFORK: block parent until child calls execve or _exit.
     // Check under the process table lock whether the child has already consumed
@@ -94,3 +161,102 @@ pub fn sys_clone(flags: u64, child_stack: u64, _ptid: u64, _ctid: u64, _tls: u64
 
     child_pid.as_u64() as i64
 }
+
+fn clone_fork(ctx: libkernel::syscall::UserContext) -> i64 {
+    use libkernel::memory::with_memory;
+
+    let parent_pid = process::current_pid();
+
+    // MAP_SHARED VMAs stay shared between parent and child; everything
+    // else becomes copy-on-write.
+    let (parent_pml4, shared_ranges) = match process::with_process_ref(parent_pid, |p| {
+        let shared: Vec<(u64, u64)> = p.vma_map.values()
+            .filter(|vma| vma.flags & MAP_SHARED != 0)
+            .map(|vma| (vma.start, vma.start + vma.len))
+            .collect();
+        (p.pml4_phys, shared)
+    }) {
+        Some(v) => v,
+        None => return -errno::ENOSYS,
+    };
+
+    let child_pml4 = match with_memory(|mem| {
+        mem.fork_user_address_space(parent_pml4, &shared_ranges)
+    }) {
+        Some(pml4) => pml4,
+        None => return -errno::ENOMEM,
+    };
+
+    let child = match process::with_process_ref(parent_pid, |p| new_child(p, child_pml4, &ctx)) {
+        Some(c) => c,
+        None => {
+            with_memory(|mem| mem.cleanup_user_address_space(child_pml4, false));
+            return -errno::ENOSYS;
+        }
+    };
+    let child_pid = start_child(child, child_pml4, ctx);
+
+    libkernel::serial_println!("[clone] fork parent={} child={} user_rip={:#x}",
+        parent_pid.as_u64(), child_pid.as_u64(), ctx.rip);
+
+    child_pid.as_u64() as i64
+}
+
+fn clone_thread(
+    flags: u64,
+    ctx: libkernel::syscall::UserContext,
+    ptid: u64,
+    ctid: u64,
+    tls: u64,
+) -> i64 {
+    let pid = process::current_pid();
+    let parent_tid = process::current_tid();
+    let tid = process::alloc_tid();
+
+    let fs_base = if flags & CLONE_SETTLS != 0 {
+        tls
+    } else {
+        libkernel::msr::read_fs_base()
+    };
+
+    // Register the thread before it can run: the trampoline treats a TID
+    // missing from the process as "exit immediately".
+    let pml4_phys = match process::with_process(pid, |p| {
+        let mut thread = UserThread::new(p.blocked(parent_tid));
+        if flags & CLONE_CHILD_CLEARTID != 0 {
+            thread.clear_child_tid = ctid;
+        }
+        p.threads.insert(tid, thread);
+        p.pml4_phys
+    }) {
+        Some(pml4) => pml4,
+        None => return -errno::ESRCH,
+    };
+
+    // Both TID words live in the shared address space.  A word that is not
+    // mapped writable is skipped.
+    if flags & CLONE_PARENT_SETTID != 0 {
+        futex::put_user_word(pid, ptid, tid.as_u64() as u32);
+    }
+    if flags & CLONE_CHILD_SETTID != 0 {
+        futex::put_user_word(pid, ctid, tid.as_u64() as u32);
+    }
+
+    let thread_idx = match scheduler::spawn_process_thread(pid, tid, pml4_phys, ctx, fs_base) {
+        Some(idx) => idx,
+        None => {
+            process::with_process(pid, |p| p.threads.remove(&tid));
+            return -errno::EAGAIN;
+        }
+    };
+    process::with_process(pid, |p| {
+        if let Some(t) = p.thread_mut(tid) {
+            t.thread_idx = Some(thread_idx);
+        }
+    });
+
+    libkernel::serial_println!("[clone] thread pid={} tid={} stack={:#x} tls={:#x}",
+        pid.as_u64(), tid.as_u64(), ctx.rsp, fs_base);
+
+    tid.as_u64() as i64
+}
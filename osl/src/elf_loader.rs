@@ -3,8 +3,10 @@
 //! Used by both `spawn::spawn_process_full` and `exec::sys_execve` to avoid
 //! duplicating the page-allocation / segment-copy / stack-mapping loops.
 
+use alloc::vec::Vec;
+
 use libkernel::consts::{PAGE_SIZE, PAGE_MASK};
-use libkernel::elf::{ElfInfo, PF_W, PF_X};
+use libkernel::elf::{ElfInfo, LoadSegment, PF_W, PF_X};
 use libkernel::memory::with_memory;
 use x86_64::structures::paging::PageTableFlags;
 use x86_64::{PhysAddr, VirtAddr};
@@ -21,7 +23,11 @@ pub const USER_DATA_FLAGS: PageTableFlags = PageTableFlags::PRESENT
     .union(PageTableFlags::USER_ACCESSIBLE)
     .union(PageTableFlags::NO_EXECUTE);
 
-/// Create a fresh user PML4, map all ELF PT_LOAD segments and a user stack.
+/// Largest read [`load_elf_from`] asks its reader for.
+const READ_CHUNK: usize = 64 * 1024;
+
+/// Create a fresh user PML4, map all ELF PT_LOAD segments, a user stack and
+/// the vDSO.
 ///
 /// Returns `(pml4_phys, stack_kernel_base)` where `stack_kernel_base` is the
 /// kernel-virtual address of the stack memory (for writing argv/envp/auxv).
@@ -29,15 +35,139 @@ pub fn load_elf_address_space(
     elf_data: &[u8],
     info: &ElfInfo,
 ) -> Result<(PhysAddr, VirtAddr), &'static str> {
-    Ok(with_memory(|mem| {
+    load_elf_from(info, |offset, len| {
+        let start = offset as usize;
+        elf_data.get(start..start + len).map(|d| d.to_vec()).ok_or("ELF segment outside file")
+    }, |_| None)
+}
+
+/// Like [`load_elf_address_space`], but segment data comes from
+/// `read(offset, len)`, at most `READ_CHUNK` bytes at a time, so the file
+/// never has to fit in the kernel heap.  The reader runs with no locks
+/// held and may block.  If it fails, the new address space is freed and
+/// its error returned.
+///
+/// Read-only segments laid out page for page in the file are mapped
+/// straight from the file's page-cache frames instead of copied, so every
+/// process running the binary shares one copy.  `map_page(index)` returns
+/// file page `index` with a frame reference taken, or `None` if the file
+/// cannot be mapped; the segment is then copied as usual.
+pub fn load_elf_from<E>(
+    info: &ElfInfo,
+    mut read: impl FnMut(u64, usize) -> Result<Vec<u8>, E>,
+    mut map_page: impl FnMut(u64) -> Option<PhysAddr>,
+) -> Result<(PhysAddr, VirtAddr), E> {
+    let (pml4_phys, stack_kernel_base, phys_off, frames) = map_address_space(info);
+
+    for (seg, seg_frames) in info.segments.iter().zip(&frames) {
+        if shareable(seg) && share_segment(seg, pml4_phys, &mut map_page) {
+            continue;
+        }
+
+        let page_start = seg.vaddr & !PAGE_MASK;
+        let mut done = 0u64;
+        while done < seg.filesz {
+            let len = (seg.filesz - done).min(READ_CHUNK as u64) as usize;
+            let data = match read(seg.offset + done, len) {
+                Ok(data) => data,
+                Err(e) => {
+                    with_memory(|mem| mem.cleanup_user_address_space(pml4_phys, false));
+                    return Err(e);
+                }
+            };
+
+            // Scatter the chunk over the segment's pages.
+            let avail = data.len().min(len);
+            let mut copied = 0;
+            while copied < avail {
+                let vaddr = seg.vaddr + done + copied as u64;
+                let page = ((vaddr - page_start) / PAGE_SIZE) as usize;
+                let in_page = (vaddr & PAGE_MASK) as usize;
+                let count = (PAGE_SIZE as usize - in_page).min(avail - copied);
+                unsafe {
+                    let dst = (phys_off + seg_frames[page].as_u64() + in_page as u64).as_mut_ptr::<u8>();
+                    core::ptr::copy_nonoverlapping(data[copied..].as_ptr(), dst, count);
+                }
+                copied += count;
+            }
+            done += len as u64;
+        }
+    }
+
+    Ok((pml4_phys, stack_kernel_base))
+}
+
+/// Whether a segment can map page-cache frames: read-only, without bss,
+/// and at the same offset within its page in memory as in the file.
+fn shareable(seg: &LoadSegment) -> bool {
+    seg.flags & PF_W == 0
+        && seg.memsz == seg.filesz
+        && seg.filesz > 0
+        && seg.offset & PAGE_MASK == seg.vaddr & PAGE_MASK
+}
+
+/// Replace the zeroed pages mapped for `seg` with the file's page-cache
+/// frames, keeping their flags.  The pages' tails past the segment show
+/// whatever follows it in the file.  Returns `false`, changing nothing, if
+/// any page cannot be had.
+fn share_segment(
+    seg: &LoadSegment,
+    pml4_phys: PhysAddr,
+    map_page: &mut impl FnMut(u64) -> Option<PhysAddr>,
+) -> bool {
+    let page_start = seg.vaddr & !PAGE_MASK;
+    let page_end = (seg.vaddr + seg.memsz + PAGE_MASK) & !PAGE_MASK;
+    let num_pages = (page_end - page_start) / PAGE_SIZE;
+    let first_index = seg.offset / PAGE_SIZE;
+
+    let mut cached = Vec::with_capacity(num_pages as usize);
+    for p in 0..num_pages {
+        match map_page(first_index + p) {
+            Some(phys) => cached.push(phys),
+            None => {
+                with_memory(|mem| {
+                    for &phys in &cached {
+                        mem.release_shared_frame(phys);
+                    }
+                });
+                return false;
+            }
+        }
+    }
+
+    with_memory(|mem| {
+        for (p, &phys) in cached.iter().enumerate() {
+            let vaddr = VirtAddr::new(page_start + (p as u64) * PAGE_SIZE);
+            let flags = match mem.user_page_entry(pml4_phys, vaddr) {
+                Some((_, flags)) => flags,
+                None => {
+                    mem.release_shared_frame(phys);
+                    continue;
+                }
+            };
+            mem.unmap_and_release_user_page(pml4_phys, vaddr, false);
+            mem.map_user_page(pml4_phys, vaddr, phys, flags)
+                .expect("load_elf: failed to map cached page");
+        }
+    });
+    true
+}
+
+/// Create the PML4 and map zeroed pages for every segment, the stack and
+/// the vDSO.  Returns the PML4, the stack's kernel address, the physical
+/// memory offset and each segment's frames in page order.
+fn map_address_space(info: &ElfInfo) -> (PhysAddr, VirtAddr, VirtAddr, Vec<Vec<PhysAddr>>) {
+    with_memory(|mem| {
         let pml4_phys = mem.create_user_page_table();
         let phys_off = mem.phys_mem_offset();
+        let mut frames = Vec::with_capacity(info.segments.len());
 
         // Map each PT_LOAD segment.
         for seg in &info.segments {
             let page_start = seg.vaddr & !PAGE_MASK;
             let page_end = (seg.vaddr + seg.memsz + PAGE_MASK) & !PAGE_MASK;
             let num_pages = ((page_end - page_start) / PAGE_SIZE) as usize;
+            let mut seg_frames = Vec::with_capacity(num_pages);
 
             for p in 0..num_pages {
                 let page_vaddr = page_start + (p as u64) * PAGE_SIZE;
@@ -49,29 +179,6 @@ pub fn load_elf_address_space(
                     libkernel::consts::clear_page(dst_base.as_mut_ptr::<u8>());
                 }
 
-                let page_off_in_seg = page_vaddr.wrapping_sub(seg.vaddr);
-                let copy_start_in_page = if page_vaddr < seg.vaddr {
-                    (seg.vaddr - page_vaddr) as usize
-                } else {
-                    0
-                };
-                let seg_offset_for_page = if page_vaddr >= seg.vaddr {
-                    page_off_in_seg
-                } else {
-                    0
-                };
-
-                if seg_offset_for_page < seg.filesz {
-                    let avail = (seg.filesz - seg_offset_for_page) as usize;
-                    let room = PAGE_SIZE as usize - copy_start_in_page;
-                    let count = avail.min(room);
-                    let src = &elf_data[(seg.offset + seg_offset_for_page) as usize..][..count];
-                    unsafe {
-                        let dst = (dst_base + copy_start_in_page as u64).as_mut_ptr::<u8>();
-                        core::ptr::copy_nonoverlapping(src.as_ptr(), dst, count);
-                    }
-                }
-
                 let mut flags = PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
                 if seg.flags & PF_W != 0 {
                     flags |= PageTableFlags::WRITABLE;
@@ -86,7 +193,9 @@ pub fn load_elf_address_space(
                     frame_phys,
                     flags,
                 ).expect("load_elf: failed to map segment page");
+                seg_frames.push(frame_phys);
             }
+            frames.push(seg_frames);
         }
 
         // Map user stack (RW, NX).
@@ -107,8 +216,11 @@ pub fn load_elf_address_space(
                 .expect("load_elf: failed to map stack page");
         }
 
-        (pml4_phys, stack_kernel_base)
-    }))
+        // Map the shared vDSO data page and image.
+        libkernel::vdso::map_into(mem, pml4_phys);
+
+        (pml4_phys, stack_kernel_base, phys_off, frames)
+    })
 }
 
 /// Compute brk_base: page-aligned end of the highest PT_LOAD segment.
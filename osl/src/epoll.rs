@@ -0,0 +1,243 @@
+//! epoll instances: readiness of many fds through one fd.
+//!
+//! An epoll instance is a `FileHandle` of kind `"epoll"` holding one watch
+//! per registered fd.  Each watch has a waker of its own, registered with
+//! the watched object whenever the object is polled.  A wake marks the
+//! watch triggered and wakes everything waiting on the instance: threads in
+//! `epoll_wait`, and outer instances or `poll` callers watching this one.
+//!
+//! Level-triggered watches are polled on every scan.  Edge-triggered ones
+//! (`EPOLLET`) are polled only after a wake, so an event is reported once
+//! per change of readiness rather than for as long as it lasts.
+//!
+//! The syscalls are in `syscalls::poll`.
+
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::task::Wake;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::Waker;
+
+use libkernel::file::{FdObject, FileError, FileHandle};
+use libkernel::irq_mutex::IrqMutex;
+use libkernel::poll::{PollWaiters, POLLERR, POLLHUP, POLL_READABLE};
+use libkernel::process::{self, ProcessId};
+use libkernel::spin_mutex::SpinMutex;
+
+pub const EPOLLEXCLUSIVE: u32 = 1 << 28;
+pub const EPOLLWAKEUP: u32 = 1 << 29;
+pub const EPOLLONESHOT: u32 = 1 << 30;
+pub const EPOLLET: u32 = 1 << 31;
+
+/// The waker a watch registers with its object.
+struct ItemWaker {
+    /// Woken since the watch was last scanned.
+    triggered: AtomicBool,
+    /// The instance's waiters.
+    waiters: Arc<IrqMutex<PollWaiters>>,
+}
+
+impl Wake for ItemWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.triggered.store(true, Ordering::Release);
+        // Wake with the lock released: an outer instance's waker takes the
+        // outer instance's lock.
+        let mut waiters = core::mem::take(&mut *self.waiters.lock());
+        waiters.wake_all();
+    }
+}
+
+struct Watch {
+    /// The object the fd referred to when it was added.
+    object: FdObject,
+    events: u32,
+    data: u64,
+    /// An `EPOLLONESHOT` event has been reported; re-enabled by
+    /// `EPOLL_CTL_MOD`.
+    disabled: bool,
+    item: Arc<ItemWaker>,
+    waker: Waker,
+}
+
+impl Watch {
+    /// The requested events the object is ready for, registering the
+    /// watch's waker with it.  Errors and hang-ups are always reported.
+    fn poll(&self) -> u32 {
+        self.object.poll(Some(&self.waker)) & (self.events | POLLERR | POLLHUP)
+    }
+}
+
+struct Interest {
+    watches: BTreeMap<i32, Watch>,
+    /// Scans start at this fd, the one after the last reported, so a small
+    /// `maxevents` does not starve the higher fds.
+    cursor: i32,
+}
+
+pub struct EpollHandle {
+    interest: SpinMutex<Interest>,
+    waiters: Arc<IrqMutex<PollWaiters>>,
+}
+
+/// Why an `EPOLL_CTL_*` operation failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CtlError {
+    /// `EPOLL_CTL_ADD` of an fd already watched.
+    Exists,
+    /// `EPOLL_CTL_MOD` / `EPOLL_CTL_DEL` of an fd not watched.
+    NotFound,
+}
+
+impl EpollHandle {
+    pub fn new() -> Self {
+        EpollHandle {
+            interest: SpinMutex::new(Interest { watches: BTreeMap::new(), cursor: 0 }),
+            waiters: Arc::new(IrqMutex::new(PollWaiters::new())),
+        }
+    }
+
+    /// Start watching `fd`, which refers to `object`.
+    pub fn add(&self, fd: i32, object: FdObject, events: u32, data: u64) -> Result<(), CtlError> {
+        let mut interest = self.interest.lock();
+        if interest.watches.contains_key(&fd) {
+            return Err(CtlError::Exists);
+        }
+        let item = Arc::new(ItemWaker {
+            triggered: AtomicBool::new(false),
+            waiters: self.waiters.clone(),
+        });
+        let waker = Waker::from(item.clone());
+        interest.watches.insert(fd, Watch { object, events, data, disabled: false, item: item.clone(), waker });
+        drop(interest);
+        // Have waiting threads scan the new watch, which reports the
+        // current readiness even if edge-triggered.
+        item.wake_by_ref();
+        Ok(())
+    }
+
+    /// Change the events and data of the watch on `fd`, re-enabling it if
+    /// it was disabled by `EPOLLONESHOT`.
+    pub fn modify(&self, fd: i32, events: u32, data: u64) -> Result<(), CtlError> {
+        let mut interest = self.interest.lock();
+        let watch = interest.watches.get_mut(&fd).ok_or(CtlError::NotFound)?;
+        watch.events = events;
+        watch.data = data;
+        watch.disabled = false;
+        let item = watch.item.clone();
+        drop(interest);
+        item.wake_by_ref();
+        Ok(())
+    }
+
+    /// Stop watching `fd`.
+    pub fn remove(&self, fd: i32) -> Result<(), CtlError> {
+        self.interest.lock().watches.remove(&fd).map(|_| ()).ok_or(CtlError::NotFound)
+    }
+
+    /// Register `waker` to be woken when a watch may have become ready.
+    pub fn register(&self, waker: &Waker) {
+        self.waiters.lock().register(Some(waker));
+    }
+
+    /// Drop the watches whose fd process `pid` has since closed, or reused
+    /// for another object.
+    pub fn prune(&self, pid: ProcessId) {
+        let fds: Vec<i32> = self.interest.lock().watches.keys().copied().collect();
+        let current: Vec<Option<FdObject>> = process::with_process_ref(pid, |p| {
+            fds.iter().map(|&fd| p.get_fd(fd as usize).ok()).collect()
+        })
+        .unwrap_or_default();
+        let mut interest = self.interest.lock();
+        for (fd, object) in fds.iter().zip(current) {
+            let live = match (interest.watches.get(fd), object) {
+                (Some(watch), Some(object)) => watch.object.same_object(&object),
+                _ => false,
+            };
+            if !live {
+                interest.watches.remove(fd);
+            }
+        }
+    }
+
+    /// Up to `max` ready watches, as `(events, data)` pairs.
+    ///
+    /// Reporting consumes an edge-triggered watch's wake and disables a
+    /// one-shot watch.
+    pub fn collect(&self, max: usize) -> Vec<(u32, u64)> {
+        let mut interest = self.interest.lock();
+        let Interest { watches, cursor } = &mut *interest;
+        let order: Vec<i32> = watches
+            .range(*cursor..)
+            .chain(watches.range(..*cursor))
+            .map(|(&fd, _)| fd)
+            .collect();
+        let mut ready = Vec::new();
+        for fd in order {
+            let watch = watches.get_mut(&fd).unwrap();
+            if watch.disabled {
+                continue;
+            }
+            let edge = watch.events & EPOLLET != 0;
+            if edge && !watch.item.triggered.swap(false, Ordering::AcqRel) {
+                continue;
+            }
+            let revents = watch.poll();
+            if revents == 0 {
+                continue;
+            }
+            if ready.len() == max {
+                if edge {
+                    watch.item.triggered.store(true, Ordering::Release);
+                }
+                break;
+            }
+            ready.push((revents, watch.data));
+            if watch.events & EPOLLONESHOT != 0 {
+                watch.disabled = true;
+            }
+            *cursor = fd + 1;
+        }
+        ready
+    }
+
+    /// True if `collect` would report something, without consuming it.
+    /// The instance's lock is only tried, so a cycle of instances watching
+    /// each other reports not ready instead of deadlocking.
+    fn any_ready(&self) -> bool {
+        let interest = match self.interest.try_lock() {
+            Some(interest) => interest,
+            None => return false,
+        };
+        interest.watches.values().any(|watch| {
+            !watch.disabled
+                && (watch.events & EPOLLET == 0 || watch.item.triggered.load(Ordering::Acquire))
+                && watch.poll() != 0
+        })
+    }
+}
+
+impl FileHandle for EpollHandle {
+    fn read(&self, _buf: &mut [u8]) -> Result<usize, FileError> {
+        Err(FileError::InvalidArgument)
+    }
+
+    fn write(&self, _buf: &[u8]) -> Result<usize, FileError> {
+        Err(FileError::InvalidArgument)
+    }
+
+    /// Readable while a watch is ready, so an instance can be watched by
+    /// `poll`, `select` or another instance.
+    fn poll_events(&self, waker: Option<&Waker>) -> u32 {
+        if let Some(waker) = waker {
+            self.register(waker);
+        }
+        if self.any_ready() { POLL_READABLE } else { 0 }
+    }
+
+    fn kind(&self) -> &'static str { "epoll" }
+}
@@ -1,16 +1,19 @@
 //! Linux errno constants and converters from libkernel error types.
 
 use libkernel::file::FileError;
+use libkernel::socket::SocketError;
 
 pub const EPERM:   i64 = 1;
 pub const ENOENT:  i64 = 2;
 pub const ESRCH:   i64 = 3;
 pub const EINTR:   i64 = 4;
 pub const EIO:     i64 = 5;
+pub const ENXIO:   i64 = 6;
 pub const ENOEXEC: i64 = 8;
 pub const EBADF:   i64 = 9;
 pub const ECHILD:  i64 = 10;
 pub const ENOMEM:  i64 = 12;
+pub const EACCES:  i64 = 13;
 pub const EFAULT:  i64 = 14;
 pub const ENODEV:  i64 = 19;
 pub const ENOTDIR: i64 = 20;
@@ -19,11 +22,37 @@ pub const EINVAL:  i64 = 22;
 pub const EMFILE:  i64 = 24;
 pub const ENOTTY:  i64 = 25;
 pub const ESPIPE:  i64 = 29;
+pub const EDOM:    i64 = 33;
 pub const ERANGE:  i64 = 34;
 pub const EAGAIN:  i64 = 11;
 pub const EPIPE:   i64 = 32;
 pub const EBUSY:   i64 = 16;
+pub const EEXIST:  i64 = 17;
+pub const EXDEV:   i64 = 18;
+pub const EFBIG:   i64 = 27;
+pub const ENOSPC:  i64 = 28;
+pub const EROFS:   i64 = 30;
+pub const ENOTEMPTY: i64 = 39;
+pub const ELOOP:   i64 = 40;
 pub const ENOSYS:  i64 = 38;
+pub const ENOTSOCK: i64 = 88;
+pub const EDESTADDRREQ: i64 = 89;
+pub const EMSGSIZE: i64 = 90;
+pub const EPROTOTYPE: i64 = 91;
+pub const ENOPROTOOPT: i64 = 92;
+pub const EPROTONOSUPPORT: i64 = 93;
+pub const ESOCKTNOSUPPORT: i64 = 94;
+pub const EOPNOTSUPP: i64 = 95;
+pub const EAFNOSUPPORT: i64 = 97;
+pub const EADDRINUSE: i64 = 98;
+pub const EADDRNOTAVAIL: i64 = 99;
+pub const ENETUNREACH: i64 = 101;
+pub const EISCONN: i64 = 106;
+pub const ENOTCONN: i64 = 107;
+pub const ETIMEDOUT: i64 = 110;
+pub const ECONNREFUSED: i64 = 111;
+pub const EALREADY: i64 = 114;
+pub const EINPROGRESS: i64 = 115;
 
 pub fn file_errno(e: FileError) -> i64 {
     -(match e {
@@ -32,6 +61,37 @@ pub fn file_errno(e: FileError) -> i64 {
         FileError::NotATty => ENOTTY,
         FileError::TooManyOpenFiles => EMFILE,
         FileError::Interrupted => EINTR,
+        FileError::IoError => EIO,
+        FileError::NoSpace => ENOSPC,
+        FileError::ReadOnly => EROFS,
+        FileError::FileTooLarge => EFBIG,
+        FileError::InvalidArgument => EINVAL,
+        FileError::NotSeekable => ESPIPE,
+        FileError::NoSuchAddress => ENXIO,
+        FileError::Socket { error } => return socket_errno(error),
+    })
+}
+
+pub fn socket_errno(e: SocketError) -> i64 {
+    -(match e {
+        SocketError::WouldBlock => EAGAIN,
+        SocketError::Interrupted => EINTR,
+        SocketError::InvalidArgument => EINVAL,
+        SocketError::NotSupported => EOPNOTSUPP,
+        SocketError::NotPermitted => EPERM,
+        SocketError::NotConnected => ENOTCONN,
+        SocketError::AlreadyConnected => EISCONN,
+        SocketError::ConnectionRefused => ECONNREFUSED,
+        SocketError::AddressInUse => EADDRINUSE,
+        SocketError::WrongType => EPROTOTYPE,
+        SocketError::BrokenPipe => EPIPE,
+        SocketError::MessageTooLong => EMSGSIZE,
+        SocketError::DestinationRequired => EDESTADDRREQ,
+        SocketError::AddressNotAvailable => EADDRNOTAVAIL,
+        SocketError::NetworkUnreachable => ENETUNREACH,
+        SocketError::TimedOut => ETIMEDOUT,
+        SocketError::InProgress => EINPROGRESS,
+        SocketError::AlreadyInProgress => EALREADY,
     })
 }
 
@@ -40,6 +100,19 @@ pub fn vfs_errno(e: &devices::vfs::VfsError) -> i64 {
         devices::vfs::VfsError::NotFound => ENOENT,
         devices::vfs::VfsError::NotAFile => EISDIR,
         devices::vfs::VfsError::NotADirectory => ENOTDIR,
-        _ => EIO,
+        devices::vfs::VfsError::AlreadyExists => EEXIST,
+        devices::vfs::VfsError::NotEmpty => ENOTEMPTY,
+        devices::vfs::VfsError::ReadOnly => EROFS,
+        devices::vfs::VfsError::NoSpace => ENOSPC,
+        devices::vfs::VfsError::CrossDevice => EXDEV,
+        devices::vfs::VfsError::Busy => EBUSY,
+        devices::vfs::VfsError::InvalidArgument => EINVAL,
+        devices::vfs::VfsError::PermissionDenied => EACCES,
+        devices::vfs::VfsError::NoDevice => ENXIO,
+        devices::vfs::VfsError::FileTooLarge => EFBIG,
+        devices::vfs::VfsError::NoFilesystem => EIO,
+        devices::vfs::VfsError::IoError => EIO,
+        devices::vfs::VfsError::NotPermitted => EPERM,
+        devices::vfs::VfsError::TooManyLinks => ELOOP,
     })
 }
@@ -28,17 +28,27 @@ pub fn sys_execve(path_ptr: u64, argv_ptr: u64, envp_ptr: u64) -> i64 {
         Err(e) => return e,
     };
 
-    let resolved = crate::syscalls::resolve_user_path(&path);
+    let resolved = match crate::syscalls::vfs_canonicalize(&path, true) {
+        Ok(p) => p,
+        Err(ref e) => return errno::vfs_errno(e),
+    };
 
-    // 2. Read ELF from VFS.
+    // 2. Read the ELF headers from VFS.  Segment data is streamed in by the
+    // loader, so the binary never has to fit in the kernel heap; read-only
+    // segments map the file's page-cache pages.
     let pid = libkernel::process::current_pid();
-    let elf_data = match crate::syscalls::vfs_read_file(&resolved, pid) {
-        Ok(data) => data,
+    let file_size = match crate::syscalls::vfs_stat(&resolved) {
+        Ok(st) if st.is_dir() => return -errno::EACCES,
+        Ok(st) => st.size,
         Err(_) => return -errno::ENOENT,
     };
+    let headers = match read_headers(&resolved, pid) {
+        Ok(data) => data,
+        Err(e) => return e,
+    };
 
     // 3. Parse ELF.
-    let info = match elf::parse(&elf_data) {
+    let info = match elf::parse_headers(&headers, file_size) {
         Ok(info) => info,
         Err(_) => return -errno::ENOEXEC,
     };
@@ -47,17 +57,45 @@ pub fn sys_execve(path_ptr: u64, argv_ptr: u64, envp_ptr: u64) -> i64 {
         return -errno::ENOEXEC;
     }
 
-    // 4a. Save old address space info before creating new one.
+    // 3a. Create fresh PML4 and map segments + stack.  Done before the
+    // other threads are killed, so a read error still fails cleanly.
+    let loaded = elf_loader::load_elf_from(&info, |offset, len| {
+        match crate::syscalls::vfs_read_at(&resolved, offset, len, pid) {
+            Ok(data) if data.len() == len => Ok(data),
+            Ok(_) => Err(devices::vfs::VfsError::IoError), // truncated meanwhile
+            Err(e) => Err(e),
+        }
+    }, |index| crate::syscalls::vfs_map_page(&resolved, index).ok());
+    let (new_pml4_phys, stack_kernel_base) = match loaded {
+        Ok(v) => v,
+        Err(ref e) => return errno::vfs_errno(e),
+    };
+
+    // 3b. Only the calling thread survives execve.  The others are told to
+    // exit; wait for them so none is still running on the old page tables.
+    let tid = process::current_tid();
+    process::kill_other_threads(pid, tid);
+    while process::with_process_ref(pid, |p| p.threads.len() > 1) == Some(true) {
+        scheduler::yield_now();
+    }
+    // The survivor becomes the main thread and takes over the PID as its TID.
+    if tid != pid {
+        process::with_process(pid, |p| {
+            if let Some(thread) = p.threads.remove(&tid) {
+                p.threads.insert(pid, thread);
+            }
+            p.set_main_thread(scheduler::current_thread_idx());
+        });
+        scheduler::set_current_thread_tid(pid);
+    }
+
+    // 4. Save old address space info.  Pages written through shared file
+    // mappings are queued for write-back before the old tables go.
+    libkernel::memory::mapped_file::sync_shared(pid, 0, libkernel::consts::USER_SPACE_END);
     let (old_pml4_phys, old_pml4_shared) = process::with_process_ref(pid, |p| {
         (p.pml4_phys, p.pml4_shared)
     }).unwrap_or((x86_64::PhysAddr::new(0), false));
 
-    // 4. Create fresh PML4 and map segments + stack.
-    let (new_pml4_phys, stack_kernel_base) = match elf_loader::load_elf_address_space(&elf_data, &info) {
-        Ok(v) => v,
-        Err(_) => return -errno::ENOMEM,
-    };
-
     // 5. Compute brk_base.
     let brk_base = elf_loader::compute_brk_base(&info);
 
@@ -124,15 +162,14 @@ pub fn sys_execve(path_ptr: u64, argv_ptr: u64, envp_ptr: u64) -> i64 {
     drop(envp);
     drop(path);
     drop(resolved);
-    drop(elf_data);
+    drop(headers);
     drop(info);
 
     // 10. Jump to new userspace — never returns.
     let user_cs = libkernel::gdt::user_code_selector().0 as u64;
     let user_ss = libkernel::gdt::user_data_selector().0 as u64;
     let per_cpu = libkernel::syscall::per_cpu_addr();
-    let kernel_stack_top = process::with_process_ref(pid, |p| p.kernel_stack_top)
-        .unwrap_or(0);
+    let kernel_stack_top = scheduler::current_kernel_stack_top();
 
     // Reset FS_BASE (TLS) — the new program's libc will set it up.
     unsafe { libkernel::msr::write_fs_base(0); }
@@ -148,3 +185,22 @@ pub fn sys_execve(path_ptr: u64, argv_ptr: u64, envp_ptr: u64) -> i64 {
         );
     }
 }
+
+/// Largest ELF header plus program header table `execve` accepts.
+const MAX_HEADERS_LEN: u64 = 64 * 1024;
+
+/// Read the ELF header and program header table of the file at `path`.
+fn read_headers(path: &str, pid: process::ProcessId) -> Result<Vec<u8>, i64> {
+    const EHDR_LEN: usize = 64;
+
+    let ehdr = crate::syscalls::vfs_read_at(path, 0, EHDR_LEN, pid)
+        .map_err(|e| errno::vfs_errno(&e))?;
+    let len = match elf::headers_len(&ehdr) {
+        Ok(len) if len <= MAX_HEADERS_LEN => len as usize,
+        _ => return Err(-errno::ENOEXEC),
+    };
+    if len <= ehdr.len() {
+        return Ok(ehdr);
+    }
+    crate::syscalls::vfs_read_at(path, 0, len, pid).map_err(|e| errno::vfs_errno(&e))
+}
@@ -3,6 +3,7 @@
 //! Consolidates the repeated pattern of extracting a specific `FdObject`
 //! variant from the current process's fd table.
 
+use alloc::string::String;
 use alloc::sync::Arc;
 
 use libkernel::completion_port::CompletionPort;
@@ -15,6 +16,17 @@ use libkernel::shmem::SharedMemInner;
 
 use crate::errno;
 
+/// Get any object from the current process's fd table.
+///
+/// Returns `-EBADF` if the fd is invalid.
+pub fn get_fd_object(fd: usize) -> Result<FdObject, i64> {
+    let pid = process::current_pid();
+    match process::with_process_ref(pid, |p| p.get_fd(fd)) {
+        Some(Ok(obj)) => Ok(obj),
+        _ => Err(-errno::EBADF),
+    }
+}
+
 /// Get a `FileHandle` from the current process's fd table.
 ///
 /// Returns `-EBADF` if the fd is invalid or refers to a non-file object.
@@ -30,6 +42,23 @@ pub fn get_fd_file(fd: usize) -> Result<Arc<dyn FileHandle>, i64> {
     }
 }
 
+/// Get the path of a directory open in the current process's fd table,
+/// for the `*at` syscalls and `fchdir`.
+///
+/// Returns `-EBADF` if the fd is invalid, or `-ENOTDIR` if it refers to
+/// anything but a directory.
+pub fn get_fd_dir(fd: usize) -> Result<String, i64> {
+    let pid = process::current_pid();
+    match process::with_process_ref(pid, |p| p.get_fd(fd)) {
+        Some(Ok(obj)) => match obj.as_file() {
+            Some(h) if h.kind() == "dir" => h.vfs_path().map(String::from).ok_or(-errno::ENOTDIR),
+            _ => Err(-errno::ENOTDIR),
+        },
+        Some(Err(e)) => Err(errno::file_errno(e)),
+        None => Err(-errno::EBADF),
+    }
+}
+
 /// Get a `CompletionPort` from the current process's fd table.
 ///
 /// Returns `-EBADF` if the fd is invalid or refers to a non-port object.
@@ -1,22 +1,35 @@
 //! VFS-backed file handles for the per-process file descriptor table.
 
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::sync::Arc;
 use alloc::vec::Vec;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll};
 use libkernel::spin_mutex::SpinMutex as Mutex;
 
-use devices::vfs::VfsDirEntry;
-use libkernel::file::{FileHandle, FileError};
+use devices::vfs::{VfsDirEntry, VfsError};
+use libkernel::file::{seek_offset, FileHandle, FileError, SEEK_CUR, SEEK_SET};
+use libkernel::process::ProcessId;
+use x86_64::PhysAddr;
 
 // ---------------------------------------------------------------------------
 // VfsHandle — buffered file (entire content loaded at open)
+//
+// Used for synthetic files (`/proc`), whose content is generated on each
+// read: taking it once at open means reads in several chunks see one
+// consistent version.
 
 pub struct VfsHandle {
+    path: String,
     content: Vec<u8>,
     pos: Mutex<usize>,
 }
 
 impl VfsHandle {
-    pub fn new(content: Vec<u8>) -> Self {
-        VfsHandle { content, pos: Mutex::new(0) }
+    pub fn new(path: String, content: Vec<u8>) -> Self {
+        VfsHandle { path, content, pos: Mutex::new(0) }
     }
 }
 
@@ -36,34 +49,376 @@ impl FileHandle for VfsHandle {
         Err(FileError::BadFd) // read-only
     }
 
+    fn vfs_path(&self) -> Option<&str> { Some(&self.path) }
+
     fn kind(&self) -> &'static str { "vfs_file" }
 
     fn content_bytes(&self) -> Option<&[u8]> {
         Some(&self.content)
     }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize, FileError> {
+        let start = offset.min(self.content.len() as u64) as usize;
+        let count = buf.len().min(self.content.len() - start);
+        buf[..count].copy_from_slice(&self.content[start..start + count]);
+        Ok(count)
+    }
+
+    fn seek(&self, offset: i64, whence: u32) -> Result<u64, FileError> {
+        let mut pos = self.pos.lock();
+        let new = seek_offset(*pos as u64, self.content.len() as u64, offset, whence)?;
+        *pos = new as usize;
+        Ok(new)
+    }
+}
+
+// ---------------------------------------------------------------------------
+// VfsFileHandle — regular file; every operation goes to the filesystem
+
+/// Open-file access mode mask and flags (Linux values).
+pub const O_ACCMODE: u32 = 0o3;
+pub const O_WRONLY: u32 = 0o1;
+pub const O_RDWR: u32 = 0o2;
+pub const O_APPEND: u32 = 0o2000;
+
+type VfsFuture<T> = Pin<Box<dyn Future<Output = Result<T, VfsError>> + Send>>;
+
+/// An operation started by one of the `poll_*` methods that has not
+/// finished.
+enum PendingOp {
+    /// Reads at the given offset.
+    Read(u64, VfsFuture<Vec<u8>>),
+    /// Resolves to the offset written at and the byte count.
+    Write(VfsFuture<(u64, usize)>),
+    /// Like `Read` and `Write`, but the file position does not move.
+    ReadAt(VfsFuture<Vec<u8>>),
+    WriteAt(VfsFuture<(u64, usize)>),
+}
+
+/// A regular file.  Unlike [`VfsHandle`] it holds no snapshot: reads and
+/// writes go to the filesystem at the current offset, a buffer at a time,
+/// so they observe each other and files of any size can be read.
+pub struct VfsFileHandle {
+    path: String,
+    /// `O_ACCMODE` and `O_APPEND` bits from open.
+    flags: u32,
+    /// Process that opened the file, passed to `read_at`.
+    owner: ProcessId,
+    pos: Mutex<u64>,
+    pending: Mutex<Option<PendingOp>>,
+}
+
+impl VfsFileHandle {
+    /// Open the file at `path` with the given open flags.  Blocks until the
+    /// filesystem has opened it (see [`devices::vfs::open`]).
+    pub fn open(path: String, flags: u32, owner: ProcessId) -> Result<Self, VfsError> {
+        let writable = flags & O_ACCMODE != 0;
+        let open_path = path.clone();
+        crate::blocking::blocking(async move { devices::vfs::open(&open_path, writable).await })?;
+        // Constructed only now: dropping it releases the open.
+        Ok(VfsFileHandle {
+            path,
+            flags: flags & (O_ACCMODE | O_APPEND),
+            owner,
+            pos: Mutex::new(0),
+            pending: Mutex::new(None),
+        })
+    }
+
+    fn readable(&self) -> bool {
+        self.flags & O_ACCMODE != O_WRONLY
+    }
+
+    fn writable(&self) -> bool {
+        self.flags & O_ACCMODE != 0
+    }
+
+    /// Read up to `len` bytes at `offset`.
+    fn read_op(&self, offset: u64, len: usize) -> VfsFuture<Vec<u8>> {
+        let path = self.path.clone();
+        let owner = self.owner;
+        Box::pin(async move { devices::vfs::read_at(&path, offset, len, owner).await })
+    }
+
+    /// Write `data` at `pos`, or at end of file with `O_APPEND` (which
+    /// Linux applies to `pwrite` too).
+    fn write_op(&self, pos: u64, data: &[u8]) -> VfsFuture<(u64, usize)> {
+        let path = self.path.clone();
+        let data = data.to_vec();
+        let append = self.flags & O_APPEND != 0;
+        Box::pin(async move {
+            let offset = if append {
+                devices::vfs::stat(&path).await?.size
+            } else {
+                pos
+            };
+            let n = devices::vfs::write_at(&path, offset, &data).await?;
+            Ok((offset, n))
+        })
+    }
+
+    /// Copy data read at `offset` into `buf` and advance past it.
+    fn finish_read(&self, offset: u64, data: &[u8], buf: &mut [u8]) -> usize {
+        let count = copy_read(data, buf);
+        *self.pos.lock() = offset + count as u64;
+        count
+    }
+
+    /// Advance past a completed write and return its byte count.
+    fn finish_write(&self, (offset, n): (u64, usize)) -> usize {
+        *self.pos.lock() = offset + n as u64;
+        n
+    }
+}
+
+/// Copy data read by a positional read into `buf`.
+fn copy_read(data: &[u8], buf: &mut [u8]) -> usize {
+    let count = buf.len().min(data.len());
+    buf[..count].copy_from_slice(&data[..count]);
+    count
+}
+
+fn file_error(e: VfsError) -> FileError {
+    match e {
+        VfsError::NotAFile => FileError::IsDirectory,
+        VfsError::NoSpace => FileError::NoSpace,
+        VfsError::ReadOnly => FileError::ReadOnly,
+        VfsError::FileTooLarge => FileError::FileTooLarge,
+        VfsError::InvalidArgument => FileError::InvalidArgument,
+        _ => FileError::IoError,
+    }
+}
+
+impl FileHandle for VfsFileHandle {
+    fn read(&self, buf: &mut [u8]) -> Result<usize, FileError> {
+        if !self.readable() {
+            return Err(FileError::BadFd);
+        }
+        let offset = *self.pos.lock();
+        let data = crate::blocking::blocking(self.read_op(offset, buf.len())).map_err(file_error)?;
+        Ok(self.finish_read(offset, &data, buf))
+    }
+
+    fn write(&self, buf: &[u8]) -> Result<usize, FileError> {
+        if !self.writable() {
+            return Err(FileError::BadFd);
+        }
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let pos = *self.pos.lock();
+        let done = crate::blocking::blocking(self.write_op(pos, buf)).map_err(file_error)?;
+        Ok(self.finish_write(done))
+    }
+
+    // The poll variants run on the executor, where `blocking` would
+    // deadlock.  They keep the in-flight future between polls instead; the
+    // caller passes the same buffer each time.
+
+    fn poll_read(&self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<Result<usize, FileError>> {
+        if !self.readable() {
+            return Poll::Ready(Err(FileError::BadFd));
+        }
+        let mut pending = self.pending.lock();
+        if !matches!(*pending, Some(PendingOp::Read(..))) {
+            let offset = *self.pos.lock();
+            *pending = Some(PendingOp::Read(offset, self.read_op(offset, buf.len())));
+        }
+        let (offset, result) = match pending.as_mut() {
+            Some(PendingOp::Read(offset, fut)) => match fut.as_mut().poll(cx) {
+                Poll::Ready(r) => (*offset, r),
+                Poll::Pending => return Poll::Pending,
+            },
+            _ => unreachable!(),
+        };
+        *pending = None;
+        drop(pending);
+        Poll::Ready(result.map(|data| self.finish_read(offset, &data, buf)).map_err(file_error))
+    }
+
+    fn poll_write(&self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize, FileError>> {
+        if !self.writable() {
+            return Poll::Ready(Err(FileError::BadFd));
+        }
+        let mut pending = self.pending.lock();
+        if !matches!(*pending, Some(PendingOp::Write(_))) {
+            let pos = *self.pos.lock();
+            *pending = Some(PendingOp::Write(self.write_op(pos, buf)));
+        }
+        let result = match pending.as_mut() {
+            Some(PendingOp::Write(fut)) => match fut.as_mut().poll(cx) {
+                Poll::Ready(r) => r,
+                Poll::Pending => return Poll::Pending,
+            },
+            _ => unreachable!(),
+        };
+        *pending = None;
+        drop(pending);
+        Poll::Ready(result.map(|done| self.finish_write(done)).map_err(file_error))
+    }
+
+    fn poll_read_at(&self, cx: &mut Context<'_>, offset: u64, buf: &mut [u8])
+        -> Poll<Result<usize, FileError>>
+    {
+        if !self.readable() {
+            return Poll::Ready(Err(FileError::BadFd));
+        }
+        let mut pending = self.pending.lock();
+        if !matches!(*pending, Some(PendingOp::ReadAt(_))) {
+            *pending = Some(PendingOp::ReadAt(self.read_op(offset, buf.len())));
+        }
+        let result = match pending.as_mut() {
+            Some(PendingOp::ReadAt(fut)) => match fut.as_mut().poll(cx) {
+                Poll::Ready(r) => r,
+                Poll::Pending => return Poll::Pending,
+            },
+            _ => unreachable!(),
+        };
+        *pending = None;
+        Poll::Ready(result.map(|data| copy_read(&data, buf)).map_err(file_error))
+    }
+
+    fn poll_write_at(&self, cx: &mut Context<'_>, offset: u64, buf: &[u8])
+        -> Poll<Result<usize, FileError>>
+    {
+        if !self.writable() {
+            return Poll::Ready(Err(FileError::BadFd));
+        }
+        let mut pending = self.pending.lock();
+        if !matches!(*pending, Some(PendingOp::WriteAt(_))) {
+            *pending = Some(PendingOp::WriteAt(self.write_op(offset, buf)));
+        }
+        let result = match pending.as_mut() {
+            Some(PendingOp::WriteAt(fut)) => match fut.as_mut().poll(cx) {
+                Poll::Ready(r) => r,
+                Poll::Pending => return Poll::Pending,
+            },
+            _ => unreachable!(),
+        };
+        *pending = None;
+        Poll::Ready(result.map(|(_, n)| n).map_err(file_error))
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize, FileError> {
+        if !self.readable() {
+            return Err(FileError::BadFd);
+        }
+        let data = crate::blocking::blocking(self.read_op(offset, buf.len())).map_err(file_error)?;
+        Ok(copy_read(&data, buf))
+    }
+
+    fn write_at(&self, offset: u64, buf: &[u8]) -> Result<usize, FileError> {
+        if !self.writable() {
+            return Err(FileError::BadFd);
+        }
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let (_, n) = crate::blocking::blocking(self.write_op(offset, buf)).map_err(file_error)?;
+        Ok(n)
+    }
+
+    fn seek(&self, offset: i64, whence: u32) -> Result<u64, FileError> {
+        // Only the whence values relative to the end need the size.
+        let size = match whence {
+            SEEK_SET | SEEK_CUR => 0,
+            _ => {
+                let path = self.path.clone();
+                crate::blocking::blocking(async move { devices::vfs::stat(&path).await })
+                    .map_err(file_error)?
+                    .size
+            }
+        };
+        let mut pos = self.pos.lock();
+        *pos = seek_offset(*pos, size, offset, whence)?;
+        Ok(*pos)
+    }
+
+    fn truncate(&self, len: u64) -> Result<(), FileError> {
+        if !self.writable() {
+            return Err(FileError::InvalidArgument);
+        }
+        let path = self.path.clone();
+        crate::blocking::blocking(async move { devices::vfs::truncate(&path, len).await })
+            .map_err(file_error)
+    }
+
+    fn fsync(&self) -> Result<(), FileError> {
+        let path = self.path.clone();
+        crate::blocking::blocking(async move { devices::vfs::fsync(&path).await })
+            .map_err(file_error)
+    }
+
+    fn map_page(&self, index: u64) -> Result<PhysAddr, FileError> {
+        let path = self.path.clone();
+        crate::blocking::blocking(async move { devices::vfs::map_page(&path, index).await })
+            .map_err(file_error)
+    }
+
+    fn page_written(&self, phys: PhysAddr) {
+        devices::vfs::page_cache::frame_written(phys);
+    }
+
+    fn status_flags(&self) -> u32 { self.flags }
+
+    fn vfs_path(&self) -> Option<&str> { Some(&self.path) }
+
+    fn kind(&self) -> &'static str { "vfs_file" }
+}
+
+impl Drop for VfsFileHandle {
+    fn drop(&mut self) {
+        devices::vfs::release(&self.path, self.writable());
+    }
 }
 
 // ---------------------------------------------------------------------------
-// DirHandle — buffered directory listing
+// DirHandle — an open directory
+//
+// Like `VfsFileHandle`, the handle names the directory by its canonical
+// path rather than holding a copy of it.  The path anchors the `*at`
+// syscalls and `fchdir`; the listing is read by the first `getdents64`,
+// and again after a rewind (`lseek` to 0).
 
 pub struct DirHandle {
-    entries: Vec<VfsDirEntry>,
+    path: String,
+    /// Entries read by the first `getdents64`.
+    entries: Mutex<Option<Vec<VfsDirEntry>>>,
     cursor: Mutex<usize>,
 }
 
 impl DirHandle {
-    pub fn new(entries: Vec<VfsDirEntry>) -> Self {
-        DirHandle { entries, cursor: Mutex::new(0) }
+    /// Open the directory at `path`, which must be canonical.  Fails with
+    /// `NotADirectory` if it names anything else.
+    pub fn open(path: String) -> Result<Self, VfsError> {
+        let stat_path = path.clone();
+        let st = crate::blocking::blocking(async move { devices::vfs::stat(&stat_path).await })?;
+        if !st.is_dir() {
+            return Err(VfsError::NotADirectory);
+        }
+        Ok(DirHandle { path, entries: Mutex::new(None), cursor: Mutex::new(0) })
     }
 
     /// Consume entries starting at cursor, serializing as linux_dirent64 into `buf`.
     /// Returns total bytes written.
-    pub fn getdents64(&self, buf: &mut [u8]) -> usize {
+    pub fn getdents64(&self, buf: &mut [u8]) -> Result<usize, FileError> {
+        if self.entries.lock().is_none() {
+            // Not under the lock: listing blocks.
+            let path = self.path.clone();
+            let listed = crate::blocking::blocking(async move { devices::vfs::list_dir(&path).await })
+                .map_err(file_error)?;
+            self.entries.lock().get_or_insert(listed);
+        }
+        let entries = self.entries.lock();
+        let entries = match entries.as_ref() {
+            Some(e) => e,
+            None => return Ok(0),
+        };
         let mut cursor = self.cursor.lock();
         let mut offset = 0usize;
 
-        while *cursor < self.entries.len() {
-            let entry = &self.entries[*cursor];
+        while *cursor < entries.len() {
+            let entry = &entries[*cursor];
             let name_bytes = entry.name.as_bytes();
             // linux_dirent64: d_ino(8) + d_off(8) + d_reclen(2) + d_type(1) + name + null
             let reclen_raw = 8 + 8 + 2 + 1 + name_bytes.len() + 1;
@@ -81,8 +436,8 @@ impl DirHandle {
             buf[offset + 8..offset + 16].copy_from_slice(&d_off.to_le_bytes());
             // d_reclen
             buf[offset + 16..offset + 18].copy_from_slice(&(reclen as u16).to_le_bytes());
-            // d_type: DT_DIR=4, DT_REG=8
-            buf[offset + 18] = if entry.is_dir { 4 } else { 8 };
+            // d_type: DT_* from the entry kind
+            buf[offset + 18] = entry.kind.dirent_type();
             // d_name (null-terminated)
             let name_start = offset + 19;
             buf[name_start..name_start + name_bytes.len()].copy_from_slice(name_bytes);
@@ -97,7 +452,7 @@ impl DirHandle {
             *cursor += 1;
         }
 
-        offset
+        Ok(offset)
     }
 }
 
@@ -110,9 +465,35 @@ impl FileHandle for DirHandle {
         Err(FileError::BadFd)
     }
 
+    fn vfs_path(&self) -> Option<&str> { Some(&self.path) }
+
     fn kind(&self) -> &'static str { "dir" }
 
     fn getdents64(&self, buf: &mut [u8]) -> Result<usize, FileError> {
-        Ok(DirHandle::getdents64(self, buf))
+        DirHandle::getdents64(self, buf)
+    }
+
+    fn read_at(&self, _offset: u64, _buf: &mut [u8]) -> Result<usize, FileError> {
+        Err(FileError::IsDirectory)
+    }
+
+    /// The position is an entry index, the `d_off` values `getdents64`
+    /// reports.  Seeking to 0 (`rewinddir`) drops the listing, so the next
+    /// `getdents64` sees entries created since the directory was opened.
+    fn seek(&self, offset: i64, whence: u32) -> Result<u64, FileError> {
+        // Same lock order as `getdents64`.
+        let mut entries = self.entries.lock();
+        let mut cursor = self.cursor.lock();
+        let base = match whence {
+            SEEK_SET => 0,
+            SEEK_CUR => *cursor as i64,
+            _ => return Err(FileError::InvalidArgument),
+        };
+        let new = base.checked_add(offset).filter(|&n| n >= 0).ok_or(FileError::InvalidArgument)?;
+        *cursor = new as usize;
+        if new == 0 {
+            *entries = None;
+        }
+        Ok(new as u64)
     }
 }
@@ -11,7 +11,7 @@ use core::task::{Context, Poll};
 use libkernel::completion_port::{
     CompletionPort, Completion, IoSubmission, IoCompletion, IoRing,
     OP_NOP, OP_TIMEOUT, OP_READ, OP_WRITE, OP_IRQ_WAIT, OP_IPC_RECV, OP_IPC_SEND,
-    OP_RING_WAIT, MAX_SQ_ENTRIES, MAX_CQ_ENTRIES,
+    OP_RING_WAIT, IOSUB_OFFSET, MAX_SQ_ENTRIES, MAX_CQ_ENTRIES,
 };
 use libkernel::wait_condition::WaitCondition;
 use libkernel::shmem::SharedMemInner;
@@ -27,10 +27,12 @@ use crate::errno;
 // ---------------------------------------------------------------------------
 // Async futures for FileHandle poll_read / poll_write
 
-/// Future that calls `FileHandle::poll_read` into a kernel buffer.
+/// Future that calls `FileHandle::poll_read` into a kernel buffer, or
+/// `poll_read_at` if the submission gave an offset.
 struct HandleReadFuture {
     handle: Arc<dyn FileHandle>,
     buf: Vec<u8>,
+    offset: Option<u64>,
 }
 
 impl Future for HandleReadFuture {
@@ -38,25 +40,49 @@ impl Future for HandleReadFuture {
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
         let this = self.get_mut();
-        match this.handle.poll_read(cx, &mut this.buf) {
+        let poll = match this.offset {
+            Some(offset) => this.handle.poll_read_at(cx, offset, &mut this.buf),
+            None => this.handle.poll_read(cx, &mut this.buf),
+        };
+        match poll {
             Poll::Ready(result) => Poll::Ready((result, core::mem::take(&mut this.buf))),
             Poll::Pending => Poll::Pending,
         }
     }
 }
 
-/// Future that calls `FileHandle::poll_write` from a kernel buffer.
+/// Future that calls `FileHandle::poll_write` from a kernel buffer, or
+/// `poll_write_at` if the submission gave an offset.
 struct HandleWriteFuture {
     handle: Arc<dyn FileHandle>,
     buf: Vec<u8>,
+    offset: Option<u64>,
 }
 
 impl Future for HandleWriteFuture {
     type Output = Result<usize, FileError>;
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        self.handle.poll_write(cx, &self.buf)
+        match self.offset {
+            Some(offset) => self.handle.poll_write_at(cx, offset, &self.buf),
+            None => self.handle.poll_write(cx, &self.buf),
+        }
+    }
+}
+
+/// The offset an OP_READ / OP_WRITE submission transfers at: `None` to use
+/// the file position, or `Err` with an errno for bad flags or offset.
+fn submission_offset(sub: &IoSubmission) -> Result<Option<u64>, i64> {
+    if sub.flags & !IOSUB_OFFSET != 0 {
+        return Err(-errno::EINVAL);
+    }
+    if sub.flags & IOSUB_OFFSET == 0 {
+        return Ok(None);
     }
+    if sub.offset > i64::MAX as u64 {
+        return Err(-errno::EINVAL);
+    }
+    Ok(Some(sub.offset))
 }
 
 // ---------------------------------------------------------------------------
@@ -165,6 +191,21 @@ fn process_submission(
                     return false;
                 }
             };
+            let offset = match submission_offset(sub) {
+                Ok(o) => o,
+                Err(e) => {
+                    port.lock().post(Completion {
+                        user_data: sub.user_data,
+                        result: e,
+                        flags: 0,
+                        opcode: OP_READ,
+                        read_buf: None,
+                        read_dest: 0,
+                        transfer_fds: None,
+                    });
+                    return false;
+                }
+            };
 
             if sub.buf_len == 0 {
                 port.lock().post(Completion {
@@ -194,7 +235,7 @@ fn process_submission(
 
                 executor::spawn(Task::new(async move {
                     let kernel_buf = vec![0u8; buf_len];
-                    let fut = HandleReadFuture { handle, buf: kernel_buf };
+                    let fut = HandleReadFuture { handle, buf: kernel_buf, offset };
                     let (result, mut buf) = fut.await;
                     let result = match result {
                         Ok(n) => { buf.truncate(n); n as i64 }
@@ -229,6 +270,21 @@ fn process_submission(
                     return false;
                 }
             };
+            let offset = match submission_offset(sub) {
+                Ok(o) => o,
+                Err(e) => {
+                    port.lock().post(Completion {
+                        user_data: sub.user_data,
+                        result: e,
+                        flags: 0,
+                        opcode: OP_WRITE,
+                        read_buf: None,
+                        read_dest: 0,
+                        transfer_fds: None,
+                    });
+                    return false;
+                }
+            };
 
             if sub.buf_len == 0 {
                 port.lock().post(Completion {
@@ -262,7 +318,7 @@ fn process_submission(
                 let user_data = sub.user_data;
 
                 executor::spawn(Task::new(async move {
-                    let fut = HandleWriteFuture { handle, buf: kernel_buf };
+                    let fut = HandleWriteFuture { handle, buf: kernel_buf, offset };
                     let result = match fut.await {
                         Ok(n) => n as i64,
                         Err(e) => crate::errno::file_errno(e),
@@ -205,6 +205,66 @@ pub fn sys_ipc_recv(fd: i32, msg_ptr: u64, flags: u32) -> i64 {
 // -------------------------------------------------------------------------
 // fd-passing helpers
 
+/// Take a reference to the object open at each of `fds` in the current
+/// process, for passing to another (`ipc_send`, `SCM_RIGHTS`).
+///
+/// On a bad fd, closes the references already taken and returns EBADF.
+pub fn extract_fds(fds: &[i32]) -> Result<Vec<FdObject>, i64> {
+    let pid = process::current_pid();
+    let mut objects: Vec<FdObject> = Vec::with_capacity(fds.len());
+
+    for &fd in fds {
+        let entry = match fd {
+            fd if fd < 0 => None,
+            fd => process::with_process(pid, |p| p.get_fd_entry(fd as usize).ok()).flatten(),
+        };
+        match entry {
+            Some(entry) => {
+                entry.object.notify_dup();
+                objects.push(entry.object);
+            }
+            None => {
+                for obj in objects {
+                    obj.close();
+                }
+                return Err(-errno::EBADF);
+            }
+        }
+    }
+    Ok(objects)
+}
+
+/// Install passed objects into the current process's fd table with
+/// `fd_flags`, returning the new fd numbers in order.
+///
+/// On error (too many fds), closes the fds already installed and the
+/// objects not yet installed, and returns EMFILE.
+pub fn install_fds(objects: Vec<FdObject>, fd_flags: u32) -> Result<Vec<i32>, i64> {
+    let pid = process::current_pid();
+    let mut allocated: Vec<i32> = Vec::with_capacity(objects.len());
+    let mut objects = objects.into_iter();
+
+    while let Some(object) = objects.next() {
+        match process::with_process(pid, |p| p.alloc_fd_with_flags(object, fd_flags)) {
+            Some(Ok(new_fd)) => allocated.push(new_fd as i32),
+            _ => {
+                // Rollback: close already-allocated fds.
+                for &afd in &allocated {
+                    process::with_process(pid, |p| {
+                        p.close_fd(afd as usize).ok();
+                    });
+                }
+                // Close remaining uninstalled objects.
+                for obj in objects {
+                    obj.close();
+                }
+                return Err(-errno::EMFILE);
+            }
+        }
+    }
+    Ok(allocated)
+}
+
 /// Extract fd objects from the sender's fd table based on `msg.fds`.
 ///
 /// Returns `None` if all fd slots are -1 (no transfer needed).
@@ -215,28 +275,11 @@ pub fn extract_transfer_fds(msg: &IpcMessage) -> Result<Option<TransferFds>, i64
         return Ok(None);
     }
 
-    let pid = process::current_pid();
+    let slots: Vec<usize> = (0..msg.fds.len()).filter(|&i| msg.fds[i] != -1).collect();
+    let fds: Vec<i32> = slots.iter().map(|&i| msg.fds[i]).collect();
     let mut objects: TransferFds = [None, None, None, None];
-
-    for (i, &fd) in msg.fds.iter().enumerate() {
-        if fd == -1 {
-            continue;
-        }
-        if fd < 0 {
-            // Negative fds other than -1 are invalid.
-            rollback_transfer_fds(&mut objects);
-            return Err(-errno::EBADF);
-        }
-        match process::with_process(pid, |p| p.get_fd_entry(fd as usize)) {
-            Some(Ok(entry)) => {
-                entry.object.notify_dup();
-                objects[i] = Some(entry.object);
-            }
-            _ => {
-                rollback_transfer_fds(&mut objects);
-                return Err(-errno::EBADF);
-            }
-        }
+    for (i, object) in slots.into_iter().zip(extract_fds(&fds)?) {
+        objects[i] = Some(object);
     }
     Ok(Some(objects))
 }
@@ -246,48 +289,16 @@ pub fn extract_transfer_fds(msg: &IpcMessage) -> Result<Option<TransferFds>, i64
 /// Rewrites `msg.fds` with the new fd numbers.  On error (too many fds),
 /// rolls back and returns EMFILE.
 pub fn install_transfer_fds(msg: &mut IpcMessage, mut fds: TransferFds) -> Result<(), i64> {
-    let pid = process::current_pid();
+    let slots: Vec<usize> = (0..fds.len()).filter(|&i| fds[i].is_some()).collect();
+    let objects: Vec<FdObject> = slots.iter().filter_map(|&i| fds[i].take()).collect();
     let mut allocated: [i32; 4] = [-1; 4];
-
-    for i in 0..4 {
-        if let Some(object) = fds[i].take() {
-            match process::with_process(pid, |p| p.alloc_fd(object)) {
-                Some(Ok(new_fd)) => {
-                    allocated[i] = new_fd as i32;
-                }
-                _ => {
-                    // Rollback: close already-allocated fds.
-                    for &afd in &allocated {
-                        if afd != -1 {
-                            process::with_process(pid, |p| {
-                                p.close_fd(afd as usize).ok();
-                            });
-                        }
-                    }
-                    // Close remaining uninstalled objects.
-                    for slot in fds.iter_mut().skip(i) {
-                        if let Some(obj) = slot.take() {
-                            obj.close();
-                        }
-                    }
-                    return Err(-errno::EMFILE);
-                }
-            }
-        }
+    for (i, new_fd) in slots.into_iter().zip(install_fds(objects, 0)?) {
+        allocated[i] = new_fd;
     }
     msg.fds = allocated;
     Ok(())
 }
 
-/// Close any already-duped objects on send-side error.
-fn rollback_transfer_fds(objects: &mut TransferFds) {
-    for slot in objects.iter_mut() {
-        if let Some(obj) = slot.take() {
-            obj.close();
-        }
-    }
-}
-
 /// Deliver an enveloped message to user memory, installing any transferred fds.
 fn deliver_to_user(mut env: EnvelopedMessage, msg_ptr: u64) -> i64 {
     if let Some(fds) = env.transfer_fds.take() {
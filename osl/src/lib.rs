@@ -4,6 +4,7 @@ extern crate alloc;
 pub mod blocking;
 pub mod clone;
 pub mod elf_loader;
+pub mod epoll;
 pub mod errno;
 pub mod exec;
 pub mod fd_close;
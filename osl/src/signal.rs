@@ -1,5 +1,6 @@
 //! Signal-related syscall implementations: rt_sigaction, rt_sigprocmask,
-//! rt_sigreturn, kill.
+//! rt_sigreturn, kill, tkill, tgkill; and the temporary masks of ppoll,
+//! pselect6 and epoll_pwait.
 
 use crate::errno;
 use crate::user_mem::validate_user_buf;
@@ -13,6 +14,7 @@ pub fn sys_rt_sigreturn() -> i64 {
     use libkernel::syscall::{get_saved_frame_ptr, get_saved_user_rsp, set_saved_user_rsp};
 
     let pid = libkernel::process::current_pid();
+    let tid = libkernel::process::current_tid();
 
     // Offsets must match deliver_signal in libkernel/src/syscall.rs.
     const PRETCODE_SIZE: u64 = 8;
@@ -29,8 +31,12 @@ pub fn sys_rt_sigreturn() -> i64 {
     let (r8, r9, r10) = unsafe {
         (sc.add(0).read(), sc.add(1).read(), sc.add(2).read())
     };
-    let (rdi, rsi, rdx, rax) = unsafe {
-        (sc.add(8).read(), sc.add(9).read(), sc.add(12).read(), sc.add(13).read())
+    let (r12, r13, r14, r15) = unsafe {
+        (sc.add(4).read(), sc.add(5).read(), sc.add(6).read(), sc.add(7).read())
+    };
+    let (rdi, rsi, rbp, rbx, rdx, rax) = unsafe {
+        (sc.add(8).read(), sc.add(9).read(), sc.add(10).read(), sc.add(11).read(),
+         sc.add(12).read(), sc.add(13).read())
     };
     let (orig_rip, orig_rflags, orig_rsp) = unsafe {
         (sc.add(16).read(), sc.add(17).read(), sc.add(15).read())
@@ -42,7 +48,9 @@ pub fn sys_rt_sigreturn() -> i64 {
     // Restore signal mask.
     let unblockable = (1u64 << (SIGKILL - 1)) | (1u64 << (SIGSTOP - 1));
     libkernel::process::with_process(pid, |p| {
-        p.signal.blocked = old_blocked & !unblockable;
+        if let Some(t) = p.thread_mut(tid) {
+            t.blocked = old_blocked & !unblockable;
+        }
     });
 
     // Restore the SYSCALL saved frame.
@@ -57,6 +65,12 @@ pub fn sys_rt_sigreturn() -> i64 {
         frame.r8 = r8;
         frame.r9 = r9;
         frame.r10 = r10;
+        frame.r12 = r12;
+        frame.r13 = r13;
+        frame.r14 = r14;
+        frame.r15 = r15;
+        frame.rbp = rbp;
+        frame.rbx = rbx;
     }
 
     set_saved_user_rsp(orig_rsp);
@@ -130,13 +144,14 @@ pub fn sys_rt_sigprocmask(how: u64, set_ptr: u64, oldset_ptr: u64, sigsetsize: u
     }
 
     let pid = libkernel::process::current_pid();
+    let tid = libkernel::process::current_tid();
 
-    // Write old mask to user memory.
+    // Write old mask to user memory.  Masks are per-thread.
     if oldset_ptr != 0 {
         if !validate_user_buf(oldset_ptr, 8) {
             return -errno::EFAULT;
         }
-        let old_mask = match libkernel::process::with_process_ref(pid, |p| p.signal.blocked) {
+        let old_mask = match libkernel::process::with_process_ref(pid, |p| p.blocked(tid)) {
             Some(m) => m,
             None => return -errno::ESRCH,
         };
@@ -152,11 +167,13 @@ pub fn sys_rt_sigprocmask(how: u64, set_ptr: u64, oldset_ptr: u64, sigsetsize: u
         let unblockable = (1u64 << (SIGKILL - 1)) | (1u64 << (SIGSTOP - 1));
 
         libkernel::process::with_process(pid, |p| {
-            match how {
-                SIG_BLOCK => p.signal.blocked |= set & !unblockable,
-                SIG_UNBLOCK => p.signal.blocked &= !set,
-                SIG_SETMASK => p.signal.blocked = set & !unblockable,
-                _ => {}
+            if let Some(t) = p.thread_mut(tid) {
+                match how {
+                    SIG_BLOCK => t.blocked |= set & !unblockable,
+                    SIG_UNBLOCK => t.blocked &= !set,
+                    SIG_SETMASK => t.blocked = set & !unblockable,
+                    _ => {}
+                }
             }
         });
 
@@ -169,10 +186,81 @@ pub fn sys_rt_sigprocmask(how: u64, set_ptr: u64, oldset_ptr: u64, sigsetsize: u
 }
 
 // ---------------------------------------------------------------------------
-// kill
+// Temporary masks for ppoll / pselect6 / epoll_pwait
+
+/// Read the optional `sigset_t` argument of ppoll, pselect6 or epoll_pwait.
+/// A null pointer means "keep the current mask".
+pub(crate) fn read_sigmask(ptr: u64, sigsetsize: u64) -> Result<Option<u64>, i64> {
+    if ptr == 0 {
+        return Ok(None);
+    }
+    if sigsetsize != 8 {
+        return Err(-errno::EINVAL);
+    }
+    if !validate_user_buf(ptr, 8) {
+        return Err(-errno::EFAULT);
+    }
+    Ok(Some(unsafe { *(ptr as *const u64) }))
+}
+
+/// Replace the calling thread's signal mask with `mask` until the syscall
+/// returns to user mode.  A handler run on that return still sees `mask`,
+/// and the original is restored when it returns.
+pub(crate) fn set_temporary_mask(mask: u64) {
+    use libkernel::signal::*;
+
+    let pid = libkernel::process::current_pid();
+    let tid = libkernel::process::current_tid();
+    let unblockable = (1u64 << (SIGKILL - 1)) | (1u64 << (SIGSTOP - 1));
+    libkernel::process::with_process(pid, |p| {
+        if let Some(t) = p.thread_mut(tid) {
+            if t.saved_blocked.is_none() {
+                t.saved_blocked = Some(t.blocked);
+            }
+            t.blocked = mask & !unblockable;
+        }
+    });
+}
+
+// ---------------------------------------------------------------------------
+// kill / tkill / tgkill
 
 /// `kill` (syscall 62) — send a signal to a process.
 pub fn sys_kill(pid_arg: u64, sig: u64) -> i64 {
+    signal_process(libkernel::process::ProcessId::from_raw(pid_arg), sig)
+}
+
+/// `tkill` (syscall 200) — send a signal to a thread.
+///
+/// Signals are queued process-wide, so this is `kill` on the thread's
+/// process; whichever thread leaves the kernel first with the signal
+/// unblocked handles it.
+pub fn sys_tkill(tid_arg: u64, sig: u64) -> i64 {
+    let tid = libkernel::process::ProcessId::from_raw(tid_arg);
+    match thread_group_of(tid) {
+        Some(pid) => signal_process(pid, sig),
+        None => -errno::ESRCH,
+    }
+}
+
+/// `tgkill` (syscall 234) — like `tkill`, but `tid` must belong to `tgid`.
+pub fn sys_tgkill(tgid: u64, tid_arg: u64, sig: u64) -> i64 {
+    let tid = libkernel::process::ProcessId::from_raw(tid_arg);
+    match thread_group_of(tid) {
+        Some(pid) if pid.as_u64() == tgid => signal_process(pid, sig),
+        _ => -errno::ESRCH,
+    }
+}
+
+/// Find the process that owns thread `tid`.
+fn thread_group_of(tid: libkernel::process::ProcessId) -> Option<libkernel::process::ProcessId> {
+    let table = libkernel::process::lock_table();
+    table.values()
+        .find(|p| p.thread(tid).is_some())
+        .map(|p| p.pid)
+}
+
+fn signal_process(target_pid: libkernel::process::ProcessId, sig: u64) -> i64 {
     use libkernel::signal::*;
     use libkernel::process;
 
@@ -181,20 +269,27 @@ pub fn sys_kill(pid_arg: u64, sig: u64) -> i64 {
         return -errno::EINVAL;
     }
 
-    let target_pid = process::ProcessId::from_raw(pid_arg);
-
-    let signal_thread = match process::with_process(target_pid, |p| {
+    // Wake the target's threads that are in an interruptible block.
+    let to_wake: alloc::vec::Vec<usize> = match process::with_process(target_pid, |p| {
         p.signal.queue(sig);
-        p.signal_thread
+        p.threads.values_mut()
+            .filter(|t| core::mem::take(&mut t.interruptible))
+            .filter_map(|t| t.thread_idx)
+            .collect()
     }) {
-        Some(t) => t,
+        Some(v) => v,
         None => return -errno::ESRCH,
     };
 
-    // Wake the target only if it is in an interruptible block.
-    if let Some(idx) = signal_thread {
+    for idx in to_wake {
         libkernel::task::scheduler::unblock(idx);
     }
 
     0
 }
+
+/// Queue `sig` on the calling process, for signals the kernel raises
+/// itself (`SIGPIPE`).
+pub(crate) fn raise(sig: u8) {
+    signal_process(libkernel::process::current_pid(), sig as u64);
+}
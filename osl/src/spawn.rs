@@ -49,7 +49,7 @@ pub fn spawn_process_full(
     // Spawn a scheduler thread and record the thread index on the process.
     let thread_idx = libkernel::task::scheduler::spawn_user_thread(pid, pml4_phys);
     libkernel::process::with_process(pid, |p| {
-        p.thread_idx = Some(thread_idx);
+        p.set_main_thread(thread_idx);
     });
 
     log::info!("spawn_process: pid={} entry={:#x} pml4={:#x}",
@@ -66,7 +66,7 @@ pub fn spawn_process_full(
 /// ```text
 /// [stack_top]
 ///   argv/envp string data (null-terminated strings)
-///   16 bytes of zeros (AT_RANDOM target)
+///   16 random bytes (AT_RANDOM target)
 ///   auxv pairs (AT_NULL terminator)
 ///   envp[n-1] ptr ... envp[0] ptr
 ///   NULL                    <- envp terminator
@@ -127,14 +127,12 @@ pub fn build_initial_stack(
         envp_user_addrs.push(str_user_addr);
     }
 
-    // 2. AT_RANDOM data: 16 bytes of "random" data.
+    // 2. AT_RANDOM data: 16 random bytes (musl seeds its stack canary
+    //    and pointer guard from them).
     cursor -= 16;
     let random_user_addr = k2u(cursor);
     unsafe {
-        let p = cursor as *mut u8;
-        for i in 0..16u8 {
-            *p.add(i as usize) = i.wrapping_mul(7).wrapping_add(0x42);
-        }
+        libkernel::random::fill_bytes(core::slice::from_raw_parts_mut(cursor as *mut u8, 16));
     }
 
     // Align cursor to 8 bytes.
@@ -149,12 +147,16 @@ pub fn build_initial_stack(
     const AT_ENTRY: u64 = 9;
     const AT_UID: u64 = 11;
     const AT_RANDOM: u64 = 25;
+    const AT_SYSINFO_EHDR: u64 = 33;
+
+    let vdso_base = libkernel::vdso::image_base();
+    let auxv_pairs: u64 = 8 + vdso_base.is_some() as u64;
 
     // Pre-compute alignment: count all items that will be pushed below the
     // cursor, then check if the resulting RSP is 16-byte aligned.  If not,
     // add one padding word above AT_NULL (where musl never looks).
-    // Items: 8 auxv pairs (16) + envp NULL + envp ptrs + argv NULL + argv ptrs + argc
-    let total_pushes: u64 = 16 + 1 + envp.len() as u64 + 1 + argv.len() as u64 + 1;
+    // Items: auxv pairs + envp NULL + envp ptrs + argv NULL + argv ptrs + argc
+    let total_pushes: u64 = auxv_pairs * 2 + 1 + envp.len() as u64 + 1 + argv.len() as u64 + 1;
     let prospective_cursor = cursor - total_pushes * 8;
     let prospective_rsp = user_top - (kernel_top - prospective_cursor);
     if prospective_rsp % 16 != 0 {
@@ -162,6 +164,9 @@ pub fn build_initial_stack(
     }
 
     push(&mut cursor, 0); push(&mut cursor, AT_NULL);
+    if let Some(base) = vdso_base {
+        push(&mut cursor, base); push(&mut cursor, AT_SYSINFO_EHDR);
+    }
     push(&mut cursor, random_user_addr); push(&mut cursor, AT_RANDOM);
     push(&mut cursor, info.entry); push(&mut cursor, AT_ENTRY);
     push(&mut cursor, info.phnum as u64); push(&mut cursor, AT_PHNUM);
@@ -1,47 +1,109 @@
-//! Filesystem syscalls: open, close, chdir, getcwd, fstat, dup2, fcntl, pipe2.
+//! Filesystem syscalls: open, close, chdir, fchdir, getcwd, stat, lstat,
+//! fstat, statx, access, dup2, fcntl, pipe2, unlink, mkdir, rmdir,
+//! rename, link, symlink, readlink, truncate, ftruncate, fsync, and the
+//! `*at` forms of the path calls.
+//!
+//! Paths are resolved once, on entry, by [`vfs_canonicalize`]; the VFS
+//! itself never follows a symbolic link.  A relative path given to an
+//! `*at` call starts from the directory open at `dirfd`, or from the CWD
+//! for `AT_FDCWD`; the plain calls are the `AT_FDCWD` case.
 
+use alloc::string::String;
 use alloc::sync::Arc;
 
 use crate::errno;
 use crate::fd_helpers;
 use crate::user_mem::{validate_user_buf, read_user_string, user_slice_mut};
+use devices::vfs::{FileType, VfsStat, S_IFCHR, S_IFIFO, S_IFSOCK};
 use libkernel::file::{FileHandle, FdEntry, FdObject, FD_CLOEXEC};
 use libkernel::process;
+use libkernel::time::NSEC_PER_SEC;
 
-use super::{resolve_user_path, vfs_read_file, vfs_list_dir};
+use super::{vfs_canonicalize, vfs_create, vfs_read_file, vfs_stat, vfs_truncate};
 
-pub(crate) fn sys_open(path_ptr: u64, flags: u64, _mode: u64) -> i64 {
-    let path = match read_user_string(path_ptr, 4096) {
+const AT_FDCWD: i32 = -100;
+const AT_SYMLINK_NOFOLLOW: u64 = 0x100;
+/// `unlinkat`: remove a directory.  `faccessat2` uses the same bit for
+/// `AT_EACCESS`.
+const AT_REMOVEDIR: u64 = 0x200;
+const AT_EACCESS: u64 = 0x200;
+const AT_SYMLINK_FOLLOW: u64 = 0x400;
+const AT_NO_AUTOMOUNT: u64 = 0x800;
+const AT_EMPTY_PATH: u64 = 0x1000;
+/// `AT_STATX_FORCE_SYNC | AT_STATX_DONT_SYNC`: there is nothing to sync.
+const AT_STATX_SYNC_TYPE: u64 = 0x6000;
+
+pub(crate) fn sys_open(path_ptr: u64, flags: u64, mode: u64) -> i64 {
+    sys_openat(AT_FDCWD as u64, path_ptr, flags, mode)
+}
+
+pub(crate) fn sys_openat(dirfd: u64, path_ptr: u64, flags: u64, _mode: u64) -> i64 {
+    let path = match read_user_string(path_ptr, 4096).and_then(|p| at_path(dirfd, &p)) {
         Ok(p) => p,
         Err(e) => return e,
     };
 
-    let resolved = resolve_user_path(&path);
     let pid = process::current_pid();
 
+    const O_ACCMODE: u64 = 0o3;
+    const O_CREAT: u64 = 0o100;
+    const O_EXCL: u64 = 0o200;
     const O_DIRECTORY: u64 = 0o200000;
+    const O_NOFOLLOW: u64 = 0o400000;
+    const O_CLOEXEC: u64 = 0o2000000;
     let want_dir = flags & O_DIRECTORY != 0;
+    let fd_flags = if flags & O_CLOEXEC != 0 { FD_CLOEXEC } else { 0 };
+    let accmode = flags & O_ACCMODE;
+    if accmode == O_ACCMODE {
+        return -errno::EINVAL;
+    }
 
-    if !want_dir {
-        match vfs_read_file(&resolved, pid) {
-            Ok(data) => {
-                let handle: Arc<dyn FileHandle> = Arc::new(crate::file::VfsHandle::new(data));
-                return match fd_helpers::alloc_fd(FdObject::File(handle)) {
-                    Ok(fd) => fd as i64,
-                    Err(e) => e,
-                };
-            }
-            Err(devices::vfs::VfsError::NotFound) | Err(devices::vfs::VfsError::NotAFile) => {
-                // Fall through to try as directory.
+    // O_CREAT|O_EXCL never follows a link in the last component: a
+    // dangling one must fail with EEXIST rather than create its target.
+    let exclusive = flags & (O_CREAT | O_EXCL) == O_CREAT | O_EXCL;
+    let follow = flags & O_NOFOLLOW == 0 && !exclusive;
+    let resolved = match vfs_canonicalize(&path, follow) {
+        Ok(p) => p,
+        Err(ref e) => return errno::vfs_errno(e),
+    };
+    if !follow {
+        if let Ok(st) = vfs_stat(&resolved) {
+            if st.is_symlink() {
+                return if exclusive { -errno::EEXIST } else { -errno::ELOOP };
             }
+        }
+    }
+
+    match devices::vfs::open_device(&resolved, flags as u32) {
+        Some(Ok(obj)) => {
+            return match fd_helpers::alloc_fd_with_flags(obj, fd_flags) {
+                Ok(fd) => fd as i64,
+                Err(e) => e,
+            };
+        }
+        Some(Err(ref e)) => return errno::vfs_errno(e),
+        None => {}
+    }
+
+    if accmode != 0 || flags & O_CREAT != 0 {
+        if want_dir {
+            return -errno::EISDIR;
+        }
+        return open_file(resolved, flags, fd_flags, pid);
+    }
+
+    if !want_dir {
+        match vfs_stat(&resolved) {
+            Ok(st) if !st.is_dir() => return open_file(resolved, flags, fd_flags, pid),
+            Ok(_) => {} // Fall through to open as directory.
             Err(ref e) => return errno::vfs_errno(e),
         }
     }
 
-    match vfs_list_dir(&resolved) {
-        Ok(entries) => {
-            let handle: Arc<dyn FileHandle> = Arc::new(crate::file::DirHandle::new(entries));
-            match fd_helpers::alloc_fd(FdObject::File(handle)) {
+    match crate::file::DirHandle::open(resolved) {
+        Ok(dir) => {
+            let handle: Arc<dyn FileHandle> = Arc::new(dir);
+            match fd_helpers::alloc_fd_with_flags(FdObject::File(handle), fd_flags) {
                 Ok(fd) => fd as i64,
                 Err(e) => e,
             }
@@ -50,6 +112,55 @@ pub(crate) fn sys_open(path_ptr: u64, flags: u64, _mode: u64) -> i64 {
     }
 }
 
+/// Open (creating or truncating as requested) a regular file through a
+/// [`VfsFileHandle`](crate::file::VfsFileHandle), or a snapshot
+/// [`VfsHandle`](crate::file::VfsHandle) for a synthetic file opened
+/// read-only.
+fn open_file(path: String, flags: u64, fd_flags: u32, pid: process::ProcessId) -> i64 {
+    const O_CREAT: u64 = 0o100;
+    const O_EXCL: u64 = 0o200;
+    const O_TRUNC: u64 = 0o1000;
+    let creat = flags & O_CREAT != 0;
+
+    let created = match vfs_stat(&path) {
+        Ok(st) if st.is_dir() => return -errno::EISDIR,
+        Ok(_) if creat && flags & O_EXCL != 0 => return -errno::EEXIST,
+        // A socket node is reached with connect(), not open().
+        Ok(st) if st.kind() == FileType::Socket => return -errno::ENXIO,
+        Ok(_) => false,
+        Err(devices::vfs::VfsError::NotFound) if creat => {
+            if let Err(ref e) = vfs_create(&path) {
+                return errno::vfs_errno(e);
+            }
+            true
+        }
+        Err(ref e) => return errno::vfs_errno(e),
+    };
+
+    let writable = flags as u32 & crate::file::O_ACCMODE != 0;
+    if flags & O_TRUNC != 0 && writable && !created {
+        if let Err(ref e) = vfs_truncate(&path, 0) {
+            return errno::vfs_errno(e);
+        }
+    }
+
+    let handle: Arc<dyn FileHandle> = if !writable && devices::vfs::is_synthetic(&path) {
+        match vfs_read_file(&path, pid) {
+            Ok(data) => Arc::new(crate::file::VfsHandle::new(path, data)),
+            Err(ref e) => return errno::vfs_errno(e),
+        }
+    } else {
+        match crate::file::VfsFileHandle::open(path, flags as u32, pid) {
+            Ok(handle) => Arc::new(handle),
+            Err(ref e) => return errno::vfs_errno(e),
+        }
+    };
+    match fd_helpers::alloc_fd_with_flags(FdObject::File(handle), fd_flags) {
+        Ok(fd) => fd as i64,
+        Err(e) => e,
+    }
+}
+
 pub(crate) fn sys_close(fd: u64) -> i64 {
     let pid = process::current_pid();
     let result = process::with_process(pid, |p| p.close_fd(fd as usize));
@@ -63,18 +174,165 @@ pub(crate) fn sys_close(fd: u64) -> i64 {
     }
 }
 
-pub(crate) fn sys_fstat(_fd: u64, buf: u64) -> i64 {
-    const STAT_SIZE: usize = 144;
-    const S_IFCHR: u32 = 0o020000;
-    let stat_ptr = buf as *mut u8;
-    unsafe {
-        core::ptr::write_bytes(stat_ptr, 0, STAT_SIZE);
-        let mode_ptr = stat_ptr.add(24) as *mut u32;
-        mode_ptr.write(S_IFCHR | 0o666);
+pub(crate) fn sys_stat(path_ptr: u64, buf: u64) -> i64 {
+    stat_user_path(path_ptr, true, buf)
+}
+
+pub(crate) fn sys_lstat(path_ptr: u64, buf: u64) -> i64 {
+    stat_user_path(path_ptr, false, buf)
+}
+
+pub(crate) fn sys_fstat(fd: u64, buf: u64) -> i64 {
+    match fd_stat(fd) {
+        Ok(st) => write_stat(buf, &st),
+        Err(e) => e,
+    }
+}
+
+pub(crate) fn sys_newfstatat(dirfd: u64, path_ptr: u64, buf: u64, flags: u64) -> i64 {
+    if flags & !(AT_SYMLINK_NOFOLLOW | AT_NO_AUTOMOUNT | AT_EMPTY_PATH) != 0 {
+        return -errno::EINVAL;
+    }
+    match stat_at(dirfd, path_ptr, flags) {
+        Ok(st) => write_stat(buf, &st),
+        Err(e) => e,
+    }
+}
+
+pub(crate) fn sys_statx(dirfd: u64, path_ptr: u64, flags: u64, mask: u64, buf: u64) -> i64 {
+    const STATX_RESERVED: u64 = 0x8000_0000;
+    if flags & !(AT_SYMLINK_NOFOLLOW | AT_NO_AUTOMOUNT | AT_EMPTY_PATH | AT_STATX_SYNC_TYPE) != 0
+        || flags & AT_STATX_SYNC_TYPE == AT_STATX_SYNC_TYPE
+        || mask & STATX_RESERVED != 0
+    {
+        return -errno::EINVAL;
+    }
+    match stat_at(dirfd, path_ptr, flags) {
+        Ok(st) => write_statx(buf, &st),
+        Err(e) => e,
+    }
+}
+
+fn stat_user_path(path_ptr: u64, follow: bool, buf: u64) -> i64 {
+    let flags = if follow { 0 } else { AT_SYMLINK_NOFOLLOW };
+    match stat_at(AT_FDCWD as u64, path_ptr, flags) {
+        Ok(st) => write_stat(buf, &st),
+        Err(e) => e,
+    }
+}
+
+/// Stat the path argument at `path_ptr`, resolved against `dirfd`.  With
+/// `AT_EMPTY_PATH`, an empty path stats `dirfd` itself.
+fn stat_at(dirfd: u64, path_ptr: u64, flags: u64) -> Result<VfsStat, i64> {
+    let path = read_user_string(path_ptr, 4096)?;
+    if path.is_empty() && flags & AT_EMPTY_PATH != 0 {
+        return fd_stat(dirfd);
+    }
+    let path = at_path(dirfd, &path)?;
+    path_stat(&path, flags & AT_SYMLINK_NOFOLLOW == 0)
+}
+
+/// `path` as seen from the directory open at `dirfd`: absolute paths and
+/// `AT_FDCWD` leave it to the CWD.
+fn at_path(dirfd: u64, path: &str) -> Result<String, i64> {
+    if path.is_empty() {
+        return Err(-errno::ENOENT);
+    }
+    if path.starts_with('/') || dirfd as i32 == AT_FDCWD {
+        return Ok(String::from(path));
+    }
+    let dir = fd_helpers::get_fd_dir(dirfd as usize)?;
+    Ok(alloc::format!("{}/{}", dir.trim_end_matches('/'), path))
+}
+
+fn path_stat(path: &str, follow: bool) -> Result<VfsStat, i64> {
+    let resolved = vfs_canonicalize(path, follow).map_err(|ref e| errno::vfs_errno(e))?;
+    vfs_stat(&resolved).map_err(|ref e| errno::vfs_errno(e))
+}
+
+/// Metadata for the object open at `fd`.  Files and directories are
+/// stat'ed through the VFS; pipes, consoles and devices opened directly
+/// get a synthetic entry, and kernel objects (ports, channels, ...) one
+/// with no file type, like Linux's anonymous inodes.
+fn fd_stat(fd: u64) -> Result<VfsStat, i64> {
+    let pid = process::current_pid();
+    let obj = match process::with_process_ref(pid, |p| p.get_fd(fd as usize)) {
+        Some(Ok(obj)) => obj,
+        Some(Err(e)) => return Err(errno::file_errno(e)),
+        None => return Err(-errno::EBADF),
+    };
+    let handle = match obj.as_file() {
+        Some(h) => h,
+        None => return Ok(VfsStat { mode: 0o600, nlink: 1, ..VfsStat::default() }),
+    };
+    if let Some(path) = handle.vfs_path() {
+        return vfs_stat(path).map_err(|ref e| errno::vfs_errno(e));
+    }
+    let mode = match handle.kind() {
+        "pipe_r" | "pipe_w" => S_IFIFO | 0o600,
+        "unix_socket" | "inet_socket" => S_IFSOCK | 0o777,
+        _ => S_IFCHR | 0o666,
+    };
+    Ok(VfsStat { mode, nlink: 1, ..VfsStat::default() })
+}
+
+/// Copy `st` to a user `struct stat` (x86-64 layout, 144 bytes).
+fn write_stat(buf: u64, st: &VfsStat) -> i64 {
+    const STAT_SIZE: u64 = 144;
+    let out = match user_slice_mut(buf, STAT_SIZE) {
+        Ok(s) => s,
+        Err(e) => return e,
+    };
+    out.fill(0);
+    let mut put = |offset: usize, bytes: &[u8]| out[offset..offset + bytes.len()].copy_from_slice(bytes);
+    put(0, &st.dev.to_le_bytes());
+    put(8, &st.ino.to_le_bytes());
+    put(16, &st.nlink.to_le_bytes());
+    put(24, &st.mode.to_le_bytes());
+    put(28, &st.uid.to_le_bytes());
+    put(32, &st.gid.to_le_bytes());
+    put(40, &st.rdev.to_le_bytes());
+    put(48, &st.size.to_le_bytes());
+    put(56, &4096u64.to_le_bytes());
+    put(64, &st.blocks.to_le_bytes());
+    for (offset, time) in [(72, st.atime), (88, st.mtime), (104, st.ctime)] {
+        put(offset, &(time / NSEC_PER_SEC).to_le_bytes());
+        put(offset + 8, &(time % NSEC_PER_SEC).to_le_bytes());
     }
     0
 }
 
+/// Copy `st` to a user `struct statx` (256 bytes).  Every basic field is
+/// filled in whatever the caller asked for; the birth time is not known.
+fn write_statx(buf: u64, st: &VfsStat) -> i64 {
+    const STATX_SIZE: u64 = 256;
+    const STATX_BASIC_STATS: u32 = 0x7ff;
+    let out = match user_slice_mut(buf, STATX_SIZE) {
+        Ok(s) => s,
+        Err(e) => return e,
+    };
+    out.fill(0);
+    let mut put = |offset: usize, bytes: &[u8]| out[offset..offset + bytes.len()].copy_from_slice(bytes);
+    put(0, &STATX_BASIC_STATS.to_le_bytes());
+    put(4, &4096u32.to_le_bytes());
+    put(16, &(st.nlink as u32).to_le_bytes());
+    put(20, &st.uid.to_le_bytes());
+    put(24, &st.gid.to_le_bytes());
+    put(28, &(st.mode as u16).to_le_bytes());
+    put(32, &st.ino.to_le_bytes());
+    put(40, &st.size.to_le_bytes());
+    put(48, &st.blocks.to_le_bytes());
+    for (offset, time) in [(64, st.atime), (96, st.ctime), (112, st.mtime)] {
+        put(offset, &(time / NSEC_PER_SEC).to_le_bytes());
+        put(offset + 8, &((time % NSEC_PER_SEC) as u32).to_le_bytes());
+    }
+    put(128, &devices::vfs::major(st.rdev).to_le_bytes());
+    put(132, &devices::vfs::minor(st.rdev).to_le_bytes());
+    put(136, &devices::vfs::major(st.dev).to_le_bytes());
+    put(140, &devices::vfs::minor(st.dev).to_le_bytes());
+    0
+}
+
 pub(crate) fn sys_getcwd(buf: u64, size: u64) -> i64 {
     if !validate_user_buf(buf, size) {
         return -errno::EFAULT;
@@ -103,24 +361,43 @@ pub(crate) fn sys_chdir(path_ptr: u64) -> i64 {
         Ok(p) => p,
         Err(e) => return e,
     };
-    let resolved = resolve_user_path(&path);
+    let resolved = match vfs_canonicalize(&path, true) {
+        Ok(p) => p,
+        Err(ref e) => return errno::vfs_errno(e),
+    };
 
-    match vfs_list_dir(&resolved) {
-        Ok(_) => {
-            let pid = process::current_pid();
-            process::with_process(pid, |p| {
-                p.cwd = resolved;
-            });
+    match vfs_stat(&resolved) {
+        Ok(st) if st.is_dir() => {
+            set_cwd(resolved);
             0
         }
+        Ok(_) => -errno::ENOTDIR,
         Err(ref e) => errno::vfs_errno(e),
     }
 }
 
+pub(crate) fn sys_fchdir(fd: u64) -> i64 {
+    match fd_helpers::get_fd_dir(fd as usize) {
+        Ok(dir) => {
+            set_cwd(dir);
+            0
+        }
+        Err(e) => e,
+    }
+}
+
+fn set_cwd(dir: String) {
+    let pid = process::current_pid();
+    process::with_process(pid, |p| {
+        p.cwd = dir;
+    });
+}
+
 pub(crate) fn sys_fcntl(fd: u64, cmd: u64, arg: u64) -> i64 {
     const F_GETFD: u64 = 1;
     const F_SETFD: u64 = 2;
     const F_GETFL: u64 = 3;
+    const F_SETFL: u64 = 4;
 
     let pid = process::current_pid();
     match cmd {
@@ -136,7 +413,19 @@ pub(crate) fn sys_fcntl(fd: u64, cmd: u64, arg: u64) -> i64 {
                 _ => -errno::EBADF,
             }
         }
-        F_GETFL => 0,
+        F_GETFL => match fd_helpers::get_fd_file(fd as usize) {
+            Ok(h) => h.status_flags() as i64,
+            Err(_) => 0,
+        },
+        F_SETFL => match fd_helpers::get_fd_object(fd as usize) {
+            Ok(obj) => {
+                if let Some(h) = obj.as_file() {
+                    h.set_status_flags(arg as u32);
+                }
+                0
+            }
+            Err(e) => e,
+        },
         _ => -errno::EINVAL,
     }
 }
@@ -196,3 +485,246 @@ pub(crate) fn sys_pipe2(fds_ptr: u64, flags: u64) -> i64 {
         None => -errno::EBADF,
     }
 }
+
+/// Read a path argument and resolve it against the CWD, without following
+/// a symbolic link in the last component.
+fn user_path(path_ptr: u64) -> Result<String, i64> {
+    user_path_at(AT_FDCWD as u64, path_ptr, false)
+}
+
+/// Read a path argument, resolve it against `dirfd` and canonicalize it,
+/// following a symbolic link in the last component if `follow` is set.
+fn user_path_at(dirfd: u64, path_ptr: u64, follow: bool) -> Result<String, i64> {
+    let path = read_user_string(path_ptr, 4096)?;
+    let path = at_path(dirfd, &path)?;
+    vfs_canonicalize(&path, follow).map_err(|ref e| errno::vfs_errno(e))
+}
+
+/// Run a VFS operation to completion from syscall context.
+fn vfs_op<F>(future: F) -> i64
+where
+    F: core::future::Future<Output = Result<(), devices::vfs::VfsError>> + Send + 'static,
+{
+    match crate::blocking::blocking(future) {
+        Ok(()) => 0,
+        Err(ref e) => errno::vfs_errno(e),
+    }
+}
+
+pub(crate) fn sys_unlink(path_ptr: u64) -> i64 {
+    sys_unlinkat(AT_FDCWD as u64, path_ptr, 0)
+}
+
+pub(crate) fn sys_rmdir(path_ptr: u64) -> i64 {
+    sys_unlinkat(AT_FDCWD as u64, path_ptr, AT_REMOVEDIR)
+}
+
+/// unlinkat(2): `unlink`, or `rmdir` with `AT_REMOVEDIR`.
+pub(crate) fn sys_unlinkat(dirfd: u64, path_ptr: u64, flags: u64) -> i64 {
+    if flags & !AT_REMOVEDIR != 0 {
+        return -errno::EINVAL;
+    }
+    let path = match user_path_at(dirfd, path_ptr, false) {
+        Ok(p) => p,
+        Err(e) => return e,
+    };
+    if flags & AT_REMOVEDIR != 0 {
+        vfs_op(async move { devices::vfs::rmdir(&path).await })
+    } else {
+        vfs_op(async move { devices::vfs::unlink(&path).await })
+    }
+}
+
+pub(crate) fn sys_mkdir(path_ptr: u64, mode: u64) -> i64 {
+    sys_mkdirat(AT_FDCWD as u64, path_ptr, mode)
+}
+
+pub(crate) fn sys_mkdirat(dirfd: u64, path_ptr: u64, _mode: u64) -> i64 {
+    let path = match user_path_at(dirfd, path_ptr, false) {
+        Ok(p) => p,
+        Err(e) => return e,
+    };
+    vfs_op(async move { devices::vfs::mkdir(&path).await })
+}
+
+pub(crate) fn sys_rename(old_ptr: u64, new_ptr: u64) -> i64 {
+    sys_renameat2(AT_FDCWD as u64, old_ptr, AT_FDCWD as u64, new_ptr, 0)
+}
+
+/// renameat2(2).  Of the flags only `RENAME_NOREPLACE` is supported; no
+/// filesystem can exchange two entries or leave a whiteout.
+pub(crate) fn sys_renameat2(olddirfd: u64, old_ptr: u64, newdirfd: u64, new_ptr: u64, flags: u64) -> i64 {
+    const RENAME_NOREPLACE: u64 = 1;
+    if flags & !RENAME_NOREPLACE != 0 {
+        return -errno::EINVAL;
+    }
+    let from = match user_path_at(olddirfd, old_ptr, false) {
+        Ok(p) => p,
+        Err(e) => return e,
+    };
+    let to = match user_path_at(newdirfd, new_ptr, false) {
+        Ok(p) => p,
+        Err(e) => return e,
+    };
+    if flags & RENAME_NOREPLACE != 0 && vfs_stat(&to).is_ok() {
+        return -errno::EEXIST;
+    }
+    if from == to {
+        return 0;
+    }
+    vfs_op(async move { devices::vfs::rename(&from, &to).await })
+}
+
+pub(crate) fn sys_link(old_ptr: u64, new_ptr: u64) -> i64 {
+    sys_linkat(AT_FDCWD as u64, old_ptr, AT_FDCWD as u64, new_ptr, 0)
+}
+
+/// linkat(2): `oldpath` itself is linked, even if it is a symbolic link,
+/// unless `AT_SYMLINK_FOLLOW` is set.  With `AT_EMPTY_PATH`, an empty
+/// `oldpath` links the file open at `olddirfd`.
+pub(crate) fn sys_linkat(olddirfd: u64, old_ptr: u64, newdirfd: u64, new_ptr: u64, flags: u64) -> i64 {
+    if flags & !(AT_SYMLINK_FOLLOW | AT_EMPTY_PATH) != 0 {
+        return -errno::EINVAL;
+    }
+    let old = match read_user_string(old_ptr, 4096) {
+        Ok(p) => p,
+        Err(e) => return e,
+    };
+    let target = if old.is_empty() && flags & AT_EMPTY_PATH != 0 {
+        fd_helpers::get_fd_file(olddirfd as usize)
+            .and_then(|h| h.vfs_path().map(String::from).ok_or(-errno::ENOENT))
+    } else {
+        at_path(olddirfd, &old).and_then(|p| {
+            vfs_canonicalize(&p, flags & AT_SYMLINK_FOLLOW != 0).map_err(|ref e| errno::vfs_errno(e))
+        })
+    };
+    let target = match target {
+        Ok(p) => p,
+        Err(e) => return e,
+    };
+    let path = match user_path_at(newdirfd, new_ptr, false) {
+        Ok(p) => p,
+        Err(e) => return e,
+    };
+    vfs_op(async move { devices::vfs::link(&path, &target).await })
+}
+
+pub(crate) fn sys_symlink(target_ptr: u64, path_ptr: u64) -> i64 {
+    sys_symlinkat(target_ptr, AT_FDCWD as u64, path_ptr)
+}
+
+pub(crate) fn sys_symlinkat(target_ptr: u64, dirfd: u64, path_ptr: u64) -> i64 {
+    let target = match read_user_string(target_ptr, 4096) {
+        Ok(t) => t,
+        Err(e) => return e,
+    };
+    if target.is_empty() {
+        return -errno::ENOENT;
+    }
+    let path = match user_path_at(dirfd, path_ptr, false) {
+        Ok(p) => p,
+        Err(e) => return e,
+    };
+    vfs_op(async move { devices::vfs::symlink(&path, &target).await })
+}
+
+pub(crate) fn sys_readlink(path_ptr: u64, buf: u64, bufsiz: u64) -> i64 {
+    sys_readlinkat(AT_FDCWD as u64, path_ptr, buf, bufsiz)
+}
+
+/// readlinkat(2): copies at most `bufsiz` bytes of the target, without a
+/// terminating NUL, and returns the number copied.
+pub(crate) fn sys_readlinkat(dirfd: u64, path_ptr: u64, buf: u64, bufsiz: u64) -> i64 {
+    if bufsiz as i64 <= 0 {
+        return -errno::EINVAL;
+    }
+    let path = match user_path_at(dirfd, path_ptr, false) {
+        Ok(p) => p,
+        Err(e) => return e,
+    };
+    let target = match crate::blocking::blocking(async move { devices::vfs::readlink(&path).await }) {
+        Ok(t) => t,
+        Err(ref e) => return errno::vfs_errno(e),
+    };
+    let n = target.len().min(bufsiz as usize);
+    let out = match user_slice_mut(buf, n as u64) {
+        Ok(s) => s,
+        Err(e) => return e,
+    };
+    out.copy_from_slice(&target.as_bytes()[..n]);
+    n as i64
+}
+
+pub(crate) fn sys_access(path_ptr: u64, mode: u64) -> i64 {
+    sys_faccessat2(AT_FDCWD as u64, path_ptr, mode, 0)
+}
+
+pub(crate) fn sys_faccessat(dirfd: u64, path_ptr: u64, mode: u64) -> i64 {
+    sys_faccessat2(dirfd, path_ptr, mode, 0)
+}
+
+/// faccessat2(2).  Every process runs as root, so the real and effective
+/// IDs agree (`AT_EACCESS` changes nothing) and only execute permission
+/// can be refused: a file needs at least one execute bit.
+pub(crate) fn sys_faccessat2(dirfd: u64, path_ptr: u64, mode: u64, flags: u64) -> i64 {
+    const X_OK: u64 = 1;
+    const W_OK: u64 = 2;
+    const R_OK: u64 = 4;
+    if mode & !(R_OK | W_OK | X_OK) != 0
+        || flags & !(AT_EACCESS | AT_SYMLINK_NOFOLLOW | AT_EMPTY_PATH) != 0
+    {
+        return -errno::EINVAL;
+    }
+    let st = match stat_at(dirfd, path_ptr, flags) {
+        Ok(st) => st,
+        Err(e) => return e,
+    };
+    if mode & X_OK != 0 && !st.is_dir() && st.mode & 0o111 == 0 {
+        return -errno::EACCES;
+    }
+    0
+}
+
+pub(crate) fn sys_truncate(path_ptr: u64, length: u64) -> i64 {
+    if (length as i64) < 0 {
+        return -errno::EINVAL;
+    }
+    let path = match read_user_string(path_ptr, 4096) {
+        Ok(p) => p,
+        Err(e) => return e,
+    };
+    let path = match vfs_canonicalize(&path, true) {
+        Ok(p) => p,
+        Err(ref e) => return errno::vfs_errno(e),
+    };
+    match vfs_truncate(&path, length) {
+        Ok(()) => 0,
+        Err(ref e) => errno::vfs_errno(e),
+    }
+}
+
+pub(crate) fn sys_ftruncate(fd: u64, length: u64) -> i64 {
+    if (length as i64) < 0 {
+        return -errno::EINVAL;
+    }
+    let handle = match fd_helpers::get_fd_file(fd as usize) {
+        Ok(h) => h,
+        Err(e) => return e,
+    };
+    match handle.truncate(length) {
+        Ok(()) => 0,
+        Err(e) => errno::file_errno(e),
+    }
+}
+
+/// fsync and fdatasync: there is no separate metadata flush.
+pub(crate) fn sys_fsync(fd: u64) -> i64 {
+    let handle = match fd_helpers::get_fd_file(fd as usize) {
+        Ok(h) => h,
+        Err(e) => return e,
+    };
+    match handle.fsync() {
+        Ok(()) => 0,
+        Err(e) => errno::file_errno(e),
+    }
+}
@@ -0,0 +1,102 @@
+//! futex(2): FUTEX_WAIT, FUTEX_WAKE, FUTEX_REQUEUE, FUTEX_CMP_REQUEUE,
+//! FUTEX_WAIT_BITSET and FUTEX_WAKE_BITSET.
+//!
+//! The wait queues live in `libkernel::futex`; this module decodes the
+//! operation and converts timeouts to LAPIC timer ticks.
+
+use super::time::read_timespec;
+use crate::errno;
+use libkernel::futex::{self, FutexError, FUTEX_BITSET_MATCH_ANY};
+use libkernel::process;
+use libkernel::time;
+
+const FUTEX_WAIT: u64 = 0;
+const FUTEX_WAKE: u64 = 1;
+const FUTEX_REQUEUE: u64 = 3;
+const FUTEX_CMP_REQUEUE: u64 = 4;
+const FUTEX_WAIT_BITSET: u64 = 9;
+const FUTEX_WAKE_BITSET: u64 = 10;
+
+const FUTEX_PRIVATE_FLAG: u64 = 128;
+const FUTEX_CLOCK_REALTIME: u64 = 256;
+const FUTEX_CMD_MASK: u64 = !(FUTEX_PRIVATE_FLAG | FUTEX_CLOCK_REALTIME);
+
+/// `futex(uaddr, op, val, timeout_or_val2, uaddr2, val3)`.
+///
+/// `FUTEX_PRIVATE_FLAG` is accepted and ignored: every futex is keyed by
+/// physical address, which is correct for private and shared ones alike.
+pub(crate) fn sys_futex(uaddr: u64, op: u64, val: u64, timeout: u64, uaddr2: u64) -> i64 {
+    // The 6th argument arrives in R9; read it before anything can block.
+    let val3 = libkernel::syscall::get_user_r9() as u32;
+    let pid = process::current_pid();
+    let val = val as u32;
+
+    let result = match op & FUTEX_CMD_MASK {
+        FUTEX_WAIT => match relative_deadline(timeout) {
+            Ok(deadline) => futex::wait(pid, process::current_tid(), uaddr, val,
+                FUTEX_BITSET_MATCH_ANY, deadline).map(|()| 0),
+            Err(e) => return e,
+        },
+        FUTEX_WAIT_BITSET => {
+            if val3 == 0 {
+                return -errno::EINVAL;
+            }
+            match absolute_deadline(timeout, op & FUTEX_CLOCK_REALTIME != 0) {
+                Ok(deadline) => futex::wait(pid, process::current_tid(), uaddr, val,
+                    val3, deadline).map(|()| 0),
+                Err(e) => return e,
+            }
+        }
+        FUTEX_WAKE => futex::wake(pid, uaddr, val, FUTEX_BITSET_MATCH_ANY),
+        FUTEX_WAKE_BITSET => {
+            if val3 == 0 {
+                return -errno::EINVAL;
+            }
+            futex::wake(pid, uaddr, val, val3)
+        }
+        // For the requeue operations the timeout slot carries val2, the
+        // maximum number of waiters to move.
+        FUTEX_REQUEUE => futex::requeue(pid, uaddr, val, uaddr2, timeout as u32, None),
+        FUTEX_CMP_REQUEUE => futex::requeue(pid, uaddr, val, uaddr2, timeout as u32, Some(val3)),
+        other => {
+            log::warn!("futex: unsupported op {:#x}", other);
+            return -errno::ENOSYS;
+        }
+    };
+
+    match result {
+        Ok(n) => n as i64,
+        Err(e) => futex_errno(e),
+    }
+}
+
+fn futex_errno(e: FutexError) -> i64 {
+    -(match e {
+        FutexError::Fault => errno::EFAULT,
+        FutexError::WouldBlock => errno::EAGAIN,
+        FutexError::TimedOut => errno::ETIMEDOUT,
+        FutexError::Interrupted => errno::EINTR,
+    })
+}
+
+/// `FUTEX_WAIT` timeouts are relative.  Null means wait forever.
+fn relative_deadline(timeout: u64) -> Result<Option<u64>, i64> {
+    if timeout == 0 {
+        return Ok(None);
+    }
+    let ns = read_timespec(timeout)?;
+    Ok(Some(time::deadline_ticks(time::monotonic_ns().saturating_add(ns))))
+}
+
+/// `FUTEX_WAIT_BITSET` timeouts are absolute times on the monotonic clock,
+/// or on the realtime clock with `FUTEX_CLOCK_REALTIME`.
+fn absolute_deadline(timeout: u64, realtime: bool) -> Result<Option<u64>, i64> {
+    if timeout == 0 {
+        return Ok(None);
+    }
+    let mut ns = read_timespec(timeout)?;
+    if realtime {
+        ns = ns.saturating_sub(time::realtime_offset_ns());
+    }
+    Ok(Some(time::deadline_ticks(ns)))
+}
@@ -1,8 +1,18 @@
-//! I/O syscalls: read, write, writev, getdents64.
+//! I/O syscalls: read, write, lseek, the positional and vectored forms
+//! (pread64, pwrite64, readv, writev, preadv, pwritev), getdents64.
+
+use alloc::vec::Vec;
+use core::convert::TryInto;
+use libkernel::file::FileError;
+use libkernel::signal::SIGPIPE;
+use libkernel::socket::SocketError;
 
 use crate::errno;
 use crate::fd_helpers;
-use crate::user_mem::{user_slice, user_slice_mut};
+use crate::user_mem::{user_slice, user_slice_mut, validate_user_buf};
+
+/// Most iovecs one call accepts (Linux `UIO_MAXIOV`).
+const IOV_MAX: u64 = 1024;
 
 pub(crate) fn sys_write(fd: u64, buf: u64, count: u64) -> i64 {
     let bytes = match user_slice(buf, count) {
@@ -15,8 +25,17 @@ pub(crate) fn sys_write(fd: u64, buf: u64, count: u64) -> i64 {
     };
     match handle.write(bytes) {
         Ok(n) => n as i64,
-        Err(e) => errno::file_errno(e),
+        Err(e) => write_errno(e),
+    }
+}
+
+/// The errno for a failed write.  Writing to a hung-up socket also raises
+/// `SIGPIPE`, as `send` does without `MSG_NOSIGNAL`.
+fn write_errno(e: FileError) -> i64 {
+    if let FileError::Socket { error: SocketError::BrokenPipe } = e {
+        crate::signal::raise(SIGPIPE);
     }
+    errno::file_errno(e)
 }
 
 pub(crate) fn sys_read(fd: u64, buf: u64, count: u64) -> i64 {
@@ -35,28 +54,206 @@ pub(crate) fn sys_read(fd: u64, buf: u64, count: u64) -> i64 {
     }
 }
 
-pub(crate) fn sys_writev(fd: u64, iov_ptr: u64, iovcnt: u64) -> i64 {
+pub(crate) fn sys_lseek(fd: u64, offset: u64, whence: u64) -> i64 {
     let handle = match fd_helpers::get_fd_file(fd as usize) {
         Ok(h) => h,
         Err(e) => return e,
     };
+    match handle.seek(offset as i64, whence as u32) {
+        Ok(pos) => pos as i64,
+        Err(e) => errno::file_errno(e),
+    }
+}
+
+pub(crate) fn sys_pread64(fd: u64, buf: u64, count: u64, offset: u64) -> i64 {
+    let handle = match fd_helpers::get_fd_file(fd as usize) {
+        Ok(h) => h,
+        Err(e) => return e,
+    };
+    if (offset as i64) < 0 {
+        return -errno::EINVAL;
+    }
+    if count == 0 { return 0; }
+    let user_buf = match user_slice_mut(buf, count) {
+        Ok(s) => s,
+        Err(e) => return e,
+    };
+    match handle.read_at(offset, user_buf) {
+        Ok(n) => n as i64,
+        Err(e) => errno::file_errno(e),
+    }
+}
+
+pub(crate) fn sys_pwrite64(fd: u64, buf: u64, count: u64, offset: u64) -> i64 {
+    let handle = match fd_helpers::get_fd_file(fd as usize) {
+        Ok(h) => h,
+        Err(e) => return e,
+    };
+    if (offset as i64) < 0 {
+        return -errno::EINVAL;
+    }
+    let bytes = match user_slice(buf, count) {
+        Ok(s) => s,
+        Err(e) => return e,
+    };
+    match handle.write_at(offset, bytes) {
+        Ok(n) => n as i64,
+        Err(e) => errno::file_errno(e),
+    }
+}
+
+/// Read the `(base, len)` pairs of a user `struct iovec` array, checking
+/// that each buffer lies in user space.
+pub(super) fn user_iovecs(iov_ptr: u64, iovcnt: u64) -> Result<Vec<(u64, u64)>, i64> {
+    if iovcnt > IOV_MAX {
+        return Err(-errno::EINVAL);
+    }
+    if iovcnt == 0 {
+        return Ok(Vec::new());
+    }
+    let raw = user_slice(iov_ptr, iovcnt * 16)?;
+    let mut iovs = Vec::with_capacity(iovcnt as usize);
+    let mut total: u64 = 0;
+    for iov in raw.chunks_exact(16) {
+        let base = u64::from_le_bytes(iov[..8].try_into().unwrap());
+        let len = u64::from_le_bytes(iov[8..].try_into().unwrap());
+        total = match total.checked_add(len) {
+            Some(t) if t <= i64::MAX as u64 => t,
+            _ => return Err(-errno::EINVAL),
+        };
+        if len != 0 && !validate_user_buf(base, len) {
+            return Err(-errno::EFAULT);
+        }
+        iovs.push((base, len));
+    }
+    Ok(iovs)
+}
+
+/// Fill the buffers in order with `read` until one comes back short.  An
+/// error after some data was read returns the count so far, as on Linux.
+fn read_vectored(
+    iovs: &[(u64, u64)],
+    mut read: impl FnMut(&mut [u8]) -> Result<usize, FileError>,
+) -> i64 {
     let mut total: usize = 0;
-    for i in 0..iovcnt as usize {
-        let iov_addr = iov_ptr + (i * 16) as u64;
-        let iov_base = unsafe { *(iov_addr as *const u64) };
-        let iov_len = unsafe { *((iov_addr + 8) as *const u64) } as usize;
-        if iov_len == 0 {
+    for &(base, len) in iovs {
+        if len == 0 {
             continue;
         }
-        let bytes = unsafe { core::slice::from_raw_parts(iov_base as *const u8, iov_len) };
-        match handle.write(bytes) {
-            Ok(n) => total += n,
-            Err(e) => return errno::file_errno(e),
+        let buf = match user_slice_mut(base, len) {
+            Ok(s) => s,
+            Err(e) => return e,
+        };
+        match read(buf) {
+            Ok(n) => {
+                total += n;
+                if n < buf.len() {
+                    break;
+                }
+            }
+            Err(e) if total == 0 => return write_errno(e),
+            Err(_) => break,
         }
     }
     total as i64
 }
 
+/// Write the buffers in order with `write`; the counterpart of
+/// [`read_vectored`].
+fn write_vectored(
+    iovs: &[(u64, u64)],
+    mut write: impl FnMut(&[u8]) -> Result<usize, FileError>,
+) -> i64 {
+    let mut total: usize = 0;
+    for &(base, len) in iovs {
+        if len == 0 {
+            continue;
+        }
+        let bytes = match user_slice(base, len) {
+            Ok(s) => s,
+            Err(e) => return e,
+        };
+        match write(bytes) {
+            Ok(n) => {
+                total += n;
+                if n < bytes.len() {
+                    break;
+                }
+            }
+            Err(e) if total == 0 => return write_errno(e),
+            Err(_) => break,
+        }
+    }
+    total as i64
+}
+
+pub(crate) fn sys_readv(fd: u64, iov_ptr: u64, iovcnt: u64) -> i64 {
+    let handle = match fd_helpers::get_fd_file(fd as usize) {
+        Ok(h) => h,
+        Err(e) => return e,
+    };
+    let iovs = match user_iovecs(iov_ptr, iovcnt) {
+        Ok(v) => v,
+        Err(e) => return e,
+    };
+    read_vectored(&iovs, |buf| handle.read(buf))
+}
+
+pub(crate) fn sys_writev(fd: u64, iov_ptr: u64, iovcnt: u64) -> i64 {
+    let handle = match fd_helpers::get_fd_file(fd as usize) {
+        Ok(h) => h,
+        Err(e) => return e,
+    };
+    let iovs = match user_iovecs(iov_ptr, iovcnt) {
+        Ok(v) => v,
+        Err(e) => return e,
+    };
+    write_vectored(&iovs, |bytes| handle.write(bytes))
+}
+
+/// `preadv(fd, iov, iovcnt, pos_l, pos_h)`.  On x86-64 the whole offset is
+/// in `pos_l`; the kernel ignores `pos_h`, as Linux does.
+pub(crate) fn sys_preadv(fd: u64, iov_ptr: u64, iovcnt: u64, pos_l: u64, _pos_h: u64) -> i64 {
+    let handle = match fd_helpers::get_fd_file(fd as usize) {
+        Ok(h) => h,
+        Err(e) => return e,
+    };
+    if (pos_l as i64) < 0 {
+        return -errno::EINVAL;
+    }
+    let iovs = match user_iovecs(iov_ptr, iovcnt) {
+        Ok(v) => v,
+        Err(e) => return e,
+    };
+    let mut offset = pos_l;
+    read_vectored(&iovs, |buf| {
+        let n = handle.read_at(offset, buf)?;
+        offset += n as u64;
+        Ok(n)
+    })
+}
+
+/// `pwritev(fd, iov, iovcnt, pos_l, pos_h)`; see [`sys_preadv`].
+pub(crate) fn sys_pwritev(fd: u64, iov_ptr: u64, iovcnt: u64, pos_l: u64, _pos_h: u64) -> i64 {
+    let handle = match fd_helpers::get_fd_file(fd as usize) {
+        Ok(h) => h,
+        Err(e) => return e,
+    };
+    if (pos_l as i64) < 0 {
+        return -errno::EINVAL;
+    }
+    let iovs = match user_iovecs(iov_ptr, iovcnt) {
+        Ok(v) => v,
+        Err(e) => return e,
+    };
+    let mut offset = pos_l;
+    write_vectored(&iovs, |bytes| {
+        let n = handle.write_at(offset, bytes)?;
+        offset += n as u64;
+        Ok(n)
+    })
+}
+
 pub(crate) fn sys_getdents64(fd: u64, buf: u64, count: u64) -> i64 {
     let user_buf = match user_slice_mut(buf, count) {
         Ok(s) => s,
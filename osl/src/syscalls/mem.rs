@@ -1,9 +1,8 @@
-//! Memory management syscalls: mmap, munmap, mprotect, brk.
+//! Memory management syscalls: mmap, munmap, msync, mprotect, brk.
 
 use x86_64::structures::paging::PageTableFlags;
 
 use crate::errno;
-use crate::elf_loader::USER_DATA_FLAGS;
 use libkernel::consts::{PAGE_SIZE, PAGE_MASK};
 use libkernel::process;
 
@@ -28,12 +27,13 @@ pub(crate) fn sys_brk(addr: u64) -> i64 {
 
     let new_brk = (addr + PAGE_MASK) & !PAGE_MASK;
     if new_brk < brk_current {
-        // Shrink: free pages in [new_brk, brk_current).
+        // Shrink: free pages in [new_brk, brk_current).  Heap pages may be
+        // shared copy-on-write with a forked child, so release by refcount.
         let pages_to_free = ((brk_current - new_brk) / PAGE_SIZE) as usize;
         with_memory(|mem| {
             for i in 0..pages_to_free {
                 let vaddr = x86_64::VirtAddr::new(new_brk + (i as u64) * PAGE_SIZE);
-                mem.unmap_and_free_user_page(pml4_phys, vaddr, true);
+                mem.unmap_and_release_user_page(pml4_phys, vaddr, true);
             }
         });
         process::with_process(pid, |p| p.brk_current = new_brk);
@@ -43,14 +43,18 @@ pub(crate) fn sys_brk(addr: u64) -> i64 {
         return new_brk as i64;
     }
 
-    let pages_needed = ((new_brk - brk_current) / PAGE_SIZE) as usize;
-    let ok = with_memory(|mem| {
-        mem.alloc_and_map_user_pages(pages_needed, brk_current, pml4_phys, USER_DATA_FLAGS)
-            .is_ok()
-    });
+    // Grow: nothing is mapped here — the page-fault handler populates heap
+    // pages on first touch.  Refuse to grow into an existing mapping.
+    let grown = process::with_process(pid, |p| {
+        let overlaps = p.vma_map.range(..new_brk)
+            .any(|(_, vma)| vma.start + vma.len > brk_current);
+        if !overlaps {
+            p.brk_current = new_brk;
+        }
+        !overlaps
+    }).unwrap_or(false);
 
-    if ok {
-        process::with_process(pid, |p| p.brk_current = new_brk);
+    if grown {
         new_brk as i64
     } else {
         brk_current as i64
@@ -59,9 +63,9 @@ pub(crate) fn sys_brk(addr: u64) -> i64 {
 
 pub(crate) fn sys_mmap(addr: u64, length: u64, prot: u64, flags: u64, a5: u64) -> i64 {
     use alloc::sync::Arc;
-    use libkernel::process::{Vma, MAP_ANONYMOUS, MAP_FIXED, MAP_SHARED, MAP_PRIVATE};
-    use libkernel::memory::with_memory;
+    use libkernel::process::{Vma, MAP_ANONYMOUS, MAP_FIXED, MAP_SHARED, MAP_PRIVATE, PROT_WRITE};
     use libkernel::shmem::SharedMemInner;
+    use crate::file::{O_ACCMODE, O_RDWR, O_WRONLY};
 
     let pid = process::current_pid();
     if pid == process::ProcessId::KERNEL {
@@ -91,7 +95,7 @@ pub(crate) fn sys_mmap(addr: u64, length: u64, prot: u64, flags: u64, a5: u64) -
     }
 
     // -----------------------------------------------------------------------
-    // MAP_SHARED with a shmem fd
+    // MAP_SHARED: a shmem object, or a file served by the page cache
     if shared {
         let fd = a5 as usize;
         let offset = libkernel::syscall::get_user_r9();
@@ -99,40 +103,58 @@ pub(crate) fn sys_mmap(addr: u64, length: u64, prot: u64, flags: u64, a5: u64) -
             return -errno::EINVAL;
         }
 
-        // Get the SharedMemInner from the fd table.
-        let shmem: Arc<SharedMemInner> = match process::with_process_ref(pid, |p| {
-            p.get_fd(fd).ok().and_then(|obj| obj.as_shmem().cloned())
-        }) {
-            Some(Some(s)) => s,
-            _ => return -errno::ENODEV,
+        let object = match process::with_process_ref(pid, |p| p.get_fd(fd).ok()) {
+            Some(Some(object)) => object,
+            _ => return -errno::EBADF,
         };
 
-        // Validate that the requested range fits within the shmem object.
-        let offset_usize = offset as usize;
-        if offset_usize + aligned_len as usize > shmem.size() {
-            // Allow mapping up to the page-aligned size of the object.
-            let page_aligned_size = (shmem.size() as u64 + PAGE_MASK) & !PAGE_MASK;
-            if offset + aligned_len > page_aligned_size {
+        if let Some(shmem) = object.as_shmem() {
+            let shmem: Arc<SharedMemInner> = shmem.clone();
+
+            // Validate that the requested range fits within the shmem object.
+            let offset_usize = offset as usize;
+            if offset_usize + aligned_len as usize > shmem.size() {
+                // Allow mapping up to the page-aligned size of the object.
+                let page_aligned_size = (shmem.size() as u64 + PAGE_MASK) & !PAGE_MASK;
+                if offset + aligned_len > page_aligned_size {
+                    return -errno::EINVAL;
+                }
+            }
+
+            let frames = shmem.frames();
+            let first_page = (offset / PAGE_SIZE) as usize;
+            if first_page + num_pages > frames.len() {
                 return -errno::EINVAL;
             }
-        }
 
-        let frames = shmem.frames();
-        let first_page = (offset / PAGE_SIZE) as usize;
-        if first_page + num_pages > frames.len() {
-            return -errno::EINVAL;
+            return mmap_shared_inner(
+                pid, addr, aligned_len, prot as u32, flags32,
+                fixed, offset, &frames[first_page..first_page + num_pages],
+            );
         }
 
-        return mmap_shared_inner(
-            pid, addr, aligned_len, prot as u32, flags32,
-            fixed, fd, offset, &frames[first_page..first_page + num_pages],
-        );
+        let handle = match object.as_file() {
+            Some(handle) => handle.clone(),
+            None => return -errno::ENODEV,
+        };
+        // Writes reach the file, so a writable mapping needs a writable fd.
+        let access = handle.status_flags() & O_ACCMODE;
+        if access == O_WRONLY || (prot as u32 & PROT_WRITE != 0 && access != O_RDWR) {
+            return -errno::EACCES;
+        }
+        return match file_mappable(&handle, offset) {
+            Ok(true) => mmap_file(pid, addr, aligned_len, prot as u32, flags32, fixed, handle, offset),
+            Ok(false) => -errno::ENODEV,
+            Err(e) => e,
+        };
     }
 
     // -----------------------------------------------------------------------
-    // MAP_PRIVATE path (anonymous or file-backed) — existing logic
+    // MAP_PRIVATE path (anonymous or file-backed)
 
-    // For file-backed mappings: extract fd, offset, and grab file content.
+    // File-backed mappings are served from the page cache when the file
+    // supports it.  Otherwise (synthetic files) the mapped part of the
+    // file is read here and copied into private pages.
     let file_info: Option<(i32, u64, alloc::vec::Vec<u8>)> = if !anonymous {
         let fd = a5 as i32;
         let offset = libkernel::syscall::get_user_r9();
@@ -140,19 +162,40 @@ pub(crate) fn sys_mmap(addr: u64, length: u64, prot: u64, flags: u64, a5: u64) -
             return -errno::EINVAL;
         }
 
-        // Get the file content from the fd's buffer.
-        let content = match process::with_process_ref(pid, |p| {
+        let handle = match process::with_process_ref(pid, |p| {
             let obj = p.get_fd(fd as usize).ok()?;
             let handle = obj.as_file()?.clone();
             Some(handle)
         }) {
-            Some(Some(handle)) => {
-                match handle.content_bytes() {
-                    Some(bytes) => alloc::vec::Vec::from(bytes),
-                    None => return -errno::ENODEV,
+            Some(Some(handle)) => handle,
+            _ => return -errno::EBADF,
+        };
+        if handle.status_flags() & O_ACCMODE == O_WRONLY {
+            return -errno::EACCES;
+        }
+        match file_mappable(&handle, offset) {
+            Ok(true) => {
+                return mmap_file(pid, addr, aligned_len, prot as u32, flags32, fixed, handle, offset);
+            }
+            Ok(false) => {}
+            Err(e) => return e,
+        }
+
+        let content = match handle.content_bytes() {
+            Some(bytes) => {
+                let start = (offset as usize).min(bytes.len());
+                let end = bytes.len().min(start + aligned_len as usize);
+                alloc::vec::Vec::from(&bytes[start..end])
+            }
+            None => {
+                let mut window = alloc::vec![0u8; aligned_len as usize];
+                match handle.read_at(offset, &mut window) {
+                    Ok(n) => window.truncate(n),
+                    Err(libkernel::file::FileError::NotSeekable) => return -errno::ENODEV,
+                    Err(e) => return errno::file_errno(e),
                 }
+                window
             }
-            _ => return -errno::EBADF,
         };
 
         Some((fd, offset, content))
@@ -160,10 +203,7 @@ pub(crate) fn sys_mmap(addr: u64, length: u64, prot: u64, flags: u64, a5: u64) -
         None
     };
 
-    let (vma_fd, vma_offset) = match &file_info {
-        Some((fd, offset, _)) => (Some(*fd as usize), *offset),
-        None => (None, 0),
-    };
+    let vma_offset = file_info.as_ref().map_or(0, |(_, offset, _)| *offset);
 
     if fixed {
         // MAP_FIXED: addr must be page-aligned and non-zero.
@@ -176,28 +216,15 @@ pub(crate) fn sys_mmap(addr: u64, length: u64, prot: u64, flags: u64, a5: u64) -
             None => return -errno::ENOMEM,
         };
 
-        // Implicit munmap: remove any overlapping VMAs and free their pages.
-        let pages_to_free = process::with_process(pid, |p| {
-            p.munmap_vmas(addr, aligned_len)
-        }).unwrap_or_default();
-
-        if !pages_to_free.is_empty() {
-            with_memory(|mem| {
-                for (base, count) in &pages_to_free {
-                    for i in 0..*count {
-                        let vaddr = x86_64::VirtAddr::new(base + (i as u64) * PAGE_SIZE);
-                        mem.unmap_and_release_user_page(pml4_phys, vaddr, true);
-                    }
-                }
-            });
-        }
+        // Implicit munmap of overlapping VMAs.
+        unmap_range(pid, pml4_phys, addr, aligned_len);
 
         let vma = Vma {
             start: addr,
             len: aligned_len,
             prot: prot as u32,
             flags: flags32,
-            fd: vma_fd,
+            file: None,
             offset: vma_offset,
         };
         let pt_flags = vma.page_table_flags();
@@ -225,7 +252,7 @@ pub(crate) fn sys_mmap(addr: u64, length: u64, prot: u64, flags: u64, a5: u64) -
             len: aligned_len,
             prot: prot as u32,
             flags: flags32,
-            fd: vma_fd,
+            file: None,
             offset: vma_offset,
         };
         let pt_flags = vma.page_table_flags();
@@ -242,6 +269,97 @@ pub(crate) fn sys_mmap(addr: u64, length: u64, prot: u64, flags: u64, a5: u64) -
     }
 }
 
+/// Whether `handle` can serve a mapping from the page cache.  Probes by
+/// mapping the first page and dropping the reference again; the page stays
+/// cached for the first fault.
+fn file_mappable(handle: &alloc::sync::Arc<dyn libkernel::file::FileHandle>, offset: u64) -> Result<bool, i64> {
+    use libkernel::file::FileError;
+    use libkernel::memory::with_memory;
+
+    match handle.map_page(offset / PAGE_SIZE) {
+        Ok(phys) => {
+            with_memory(|mem| mem.release_shared_frame(phys));
+            Ok(true)
+        }
+        Err(FileError::NotSeekable) => Ok(false),
+        Err(e) => Err(errno::file_errno(e)),
+    }
+}
+
+/// Map a file served by the page cache, `MAP_SHARED` or `MAP_PRIVATE`.
+/// Only the VMA is recorded: the page-fault handler maps each page's
+/// cached frame on first touch (see `libkernel::memory::mapped_file`).
+fn mmap_file(
+    pid: process::ProcessId,
+    addr: u64,
+    aligned_len: u64,
+    prot: u32,
+    flags: u32,
+    fixed: bool,
+    file: alloc::sync::Arc<dyn libkernel::file::FileHandle>,
+    offset: u64,
+) -> i64 {
+    use libkernel::process::Vma;
+
+    let base = if fixed {
+        if addr == 0 || addr & PAGE_MASK != 0 {
+            return -errno::EINVAL;
+        }
+        let pml4_phys = match process::with_process_ref(pid, |p| p.pml4_phys) {
+            Some(v) => v,
+            None => return -errno::ENOMEM,
+        };
+        // Implicit munmap of overlapping VMAs.
+        unmap_range(pid, pml4_phys, addr, aligned_len);
+        addr
+    } else {
+        match process::with_process_ref(pid, |p| p.find_mmap_gap(aligned_len)) {
+            Some(Some(base)) => base,
+            _ => return -errno::ENOMEM,
+        }
+    };
+
+    let vma = Vma {
+        start: base,
+        len: aligned_len,
+        prot,
+        flags,
+        file: Some(file),
+        offset,
+    };
+    process::with_process(pid, |p| {
+        p.vma_map.insert(base, vma);
+    });
+    base as i64
+}
+
+/// Remove the mappings in `[addr, addr + len)`.  Pages written through
+/// shared file mappings are queued for write-back first; then the VMAs are
+/// trimmed and their pages released by refcount, so frames shared with
+/// other processes or the page cache survive.
+fn unmap_range(pid: process::ProcessId, pml4_phys: x86_64::PhysAddr, addr: u64, len: u64) {
+    use libkernel::memory::with_memory;
+
+    libkernel::memory::mapped_file::sync_shared(pid, addr, addr + len);
+
+    let pages_to_free = process::with_process(pid, |p| {
+        p.munmap_vmas(addr, len)
+    }).unwrap_or_default();
+
+    if pages_to_free.is_empty() {
+        return;
+    }
+
+    with_memory(|mem| {
+        for (base, count) in &pages_to_free {
+            for i in 0..*count {
+                let vaddr = x86_64::VirtAddr::new(base + (i as u64) * PAGE_SIZE);
+                mem.unmap_and_release_user_page(pml4_phys, vaddr, true);
+            }
+        }
+    });
+}
+
 /// MAP_SHARED inner: map existing physical frames from a shmem object,
 /// incrementing refcounts.
 fn mmap_shared_inner(
@@ -251,19 +369,17 @@ fn mmap_shared_inner(
     prot: u32,
     flags: u32,
     fixed: bool,
-    fd: usize,
     offset: u64,
     frames: &[x86_64::PhysAddr],
 ) -> i64 {
     use libkernel::process::Vma;
-    use libkernel::memory::with_memory;
 
     let vma = Vma {
         start: 0, // filled in below
         len: aligned_len,
         prot,
         flags,
-        fd: Some(fd),
+        file: None,
         offset,
     };
     let pt_flags = vma.page_table_flags();
@@ -279,20 +395,7 @@ fn mmap_shared_inner(
         };
 
         // Implicit munmap of overlapping VMAs.
-        let pages_to_free = process::with_process(pid, |p| {
-            p.munmap_vmas(addr, aligned_len)
-        }).unwrap_or_default();
-
-        if !pages_to_free.is_empty() {
-            with_memory(|mem| {
-                for (base, count) in &pages_to_free {
-                    for i in 0..*count {
-                        let vaddr = x86_64::VirtAddr::new(base + (i as u64) * PAGE_SIZE);
-                        mem.unmap_and_release_user_page(pml4_phys, vaddr, true);
-                    }
-                }
-            });
-        }
+        unmap_range(pid, pml4_phys, addr, aligned_len);
 
         let ok = mmap_shared_pages(frames, addr, pml4_phys, pt_flags);
         if ok {
@@ -354,7 +457,12 @@ fn mmap_shared_pages(
     })
 }
 
-/// Allocate, zero (and optionally fill with file data), and map pages for mmap.
+/// Populate pages for a private mmap.
+///
+/// Anonymous mappings are demand-paged: nothing is mapped here and the
+/// page-fault handler supplies zeroed frames on first touch.  Files the
+/// page cache cannot serve are allocated, filled with file data and mapped
+/// eagerly.
 fn mmap_alloc_pages(
     num_pages: usize,
     vaddr_base: u64,
@@ -365,13 +473,8 @@ fn mmap_alloc_pages(
     use libkernel::memory::with_memory;
 
     match file_info {
-        None => {
-            with_memory(|mem| {
-                mem.alloc_and_map_user_pages(num_pages, vaddr_base, pml4_phys, flags)
-                    .is_ok()
-            })
-        }
-        Some((_fd, offset, content)) => {
+        None => true,
+        Some((_fd, _offset, content)) => {
             with_memory(|mem| {
                 let phys_off = mem.phys_mem_offset();
                 for i in 0..num_pages {
@@ -386,9 +489,10 @@ fn mmap_alloc_pages(
                         libkernel::consts::clear_page(dst_base.as_mut_ptr::<u8>());
                     }
 
-                    let file_offset = *offset + (i as u64) * PAGE_SIZE;
-                    if (file_offset as usize) < content.len() {
-                        let src_start = file_offset as usize;
+                    // `content` starts at the mapping's file offset.
+                    let window_offset = (i as u64) * PAGE_SIZE;
+                    if (window_offset as usize) < content.len() {
+                        let src_start = window_offset as usize;
                         let src_end = content.len().min(src_start + PAGE_SIZE as usize);
                         let count = src_end - src_start;
                         unsafe {
@@ -417,8 +521,6 @@ fn mmap_alloc_pages(
 }
 
 pub(crate) fn sys_munmap(addr: u64, length: u64) -> i64 {
-    use libkernel::memory::with_memory;
-
     if addr & PAGE_MASK != 0 || length == 0 {
         return -errno::EINVAL;
     }
@@ -430,28 +532,61 @@ pub(crate) fn sys_munmap(addr: u64, length: u64) -> i64 {
 
     let aligned_len = (length + PAGE_MASK) & !PAGE_MASK;
 
-    let result = process::with_process(pid, |p| {
-        (p.pml4_phys, p.munmap_vmas(addr, aligned_len))
-    });
-    let (pml4_phys, pages_to_free) = match result {
+    let pml4_phys = match process::with_process_ref(pid, |p| p.pml4_phys) {
         Some(v) => v,
         None => return -errno::EINVAL,
     };
+    unmap_range(pid, pml4_phys, addr, aligned_len);
 
-    if pages_to_free.is_empty() {
-        return 0;
+    0
+}
+
+/// `msync` flags.
+const MS_ASYNC: u64 = 1;
+const MS_INVALIDATE: u64 = 2;
+const MS_SYNC: u64 = 4;
+
+pub(crate) fn sys_msync(addr: u64, length: u64, flags: u64) -> i64 {
+    use alloc::sync::Arc;
+    use alloc::vec::Vec;
+    use libkernel::file::FileHandle;
+
+    if addr & PAGE_MASK != 0
+        || flags & !(MS_ASYNC | MS_INVALIDATE | MS_SYNC) != 0
+        || (flags & MS_ASYNC != 0 && flags & MS_SYNC != 0)
+    {
+        return -errno::EINVAL;
     }
 
-    // Use refcount-aware release: shared frames are only freed when
-    // refcount reaches 0; non-shared frames are freed immediately.
-    with_memory(|mem| {
-        for (base, count) in &pages_to_free {
-            for i in 0..*count {
-                let vaddr = x86_64::VirtAddr::new(base + (i as u64) * PAGE_SIZE);
-                mem.unmap_and_release_user_page(pml4_phys, vaddr, true);
+    let pid = process::current_pid();
+    if pid == process::ProcessId::KERNEL {
+        return -errno::EINVAL;
+    }
+
+    let end = match length.checked_add(PAGE_MASK).and_then(|l| addr.checked_add(l & !PAGE_MASK)) {
+        Some(end) => end,
+        None => return -errno::ENOMEM,
+    };
+
+    // Written pages join the page cache's dirty list either way; MS_SYNC
+    // also waits for them to reach the filesystem.
+    libkernel::memory::mapped_file::sync_shared(pid, addr, end);
+
+    if flags & MS_SYNC != 0 {
+        let ranges = process::with_process_ref(pid, |p| p.shared_file_ranges(addr, end))
+            .unwrap_or_default();
+        let mut files: Vec<Arc<dyn FileHandle>> = Vec::new();
+        for (_, _, file) in ranges {
+            if !files.iter().any(|f| Arc::as_ptr(f) as *const () == Arc::as_ptr(&file) as *const ()) {
+                files.push(file);
             }
         }
-    });
+        for file in files {
+            if let Err(e) = file.fsync() {
+                return errno::file_errno(e);
+            }
+        }
+    }
 
     0
 }
@@ -471,8 +606,32 @@ pub(crate) fn sys_mprotect(addr: u64, length: u64, prot: u64) -> i64 {
     let aligned_len = (length + PAGE_MASK) & !PAGE_MASK;
     let prot32 = prot as u32;
 
+    // A shared file mapping writes to the file, so it only becomes
+    // writable if the file was opened read-write.
+    if prot32 & process::PROT_WRITE != 0 {
+        use crate::file::{O_ACCMODE, O_RDWR};
+        let denied = process::with_process_ref(pid, |p| {
+            p.shared_file_ranges(addr, addr + aligned_len).iter()
+                .any(|(_, _, file)| file.status_flags() & O_ACCMODE != O_RDWR)
+        }).unwrap_or(false);
+        if denied {
+            return -errno::EACCES;
+        }
+    }
+
+    // For each range, also note whether it lies in a private mapping: those
+    // pages may share frames copy-on-write after fork() and must not be
+    // made directly writable.
     let result = process::with_process(pid, |p| {
-        (p.pml4_phys, p.mprotect_vmas(addr, aligned_len, prot32))
+        let ranges: alloc::vec::Vec<(u64, usize, bool)> = p.mprotect_vmas(addr, aligned_len, prot32)
+            .into_iter()
+            .map(|(base, count)| {
+                let private = p.find_vma(base)
+                    .map_or(true, |vma| vma.flags & process::MAP_SHARED == 0);
+                (base, count, private)
+            })
+            .collect();
+        (p.pml4_phys, ranges)
     });
     let (pml4_phys, pages_to_update) = match result {
         Some(v) => v,
@@ -486,10 +645,15 @@ pub(crate) fn sys_mprotect(addr: u64, length: u64, prot: u64) -> i64 {
     let flags = process::prot_to_page_flags(prot32);
 
     with_memory(|mem| {
-        for (base, count) in &pages_to_update {
-            for i in 0..*count {
+        for &(base, count, private) in &pages_to_update {
+            for i in 0..count {
                 let vaddr = x86_64::VirtAddr::new(base + (i as u64) * PAGE_SIZE);
-                mem.update_user_page_flags(pml4_phys, vaddr, flags, true);
+                let page_flags = if private {
+                    mem.private_page_flags(pml4_phys, vaddr, flags)
+                } else {
+                    flags
+                };
+                mem.update_user_page_flags(pml4_phys, vaddr, page_flags, true);
             }
         }
     });
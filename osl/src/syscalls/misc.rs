@@ -1,7 +1,8 @@
-//! Miscellaneous syscalls: arch_prctl, getrandom, clock_gettime, sched_getaffinity.
+//! Miscellaneous syscalls: arch_prctl, getrandom, sched_getaffinity.
 
 use crate::errno;
-use crate::user_mem::{validate_user_buf, user_slice_mut};
+use crate::user_mem::user_slice_mut;
+use libkernel::{process, random, time};
 
 pub(crate) fn sys_arch_prctl(code: u64, addr: u64) -> i64 {
     const ARCH_SET_FS: u64 = 0x1002;
@@ -14,20 +15,45 @@ pub(crate) fn sys_arch_prctl(code: u64, addr: u64) -> i64 {
     }
 }
 
-pub(crate) fn sys_getrandom(buf: u64, count: u64, _flags: u64) -> i64 {
+const GRND_NONBLOCK: u64 = 0x1;
+const GRND_RANDOM: u64 = 0x2;
+const GRND_INSECURE: u64 = 0x4;
+
+/// Largest request served by one `getrandom` call, as on Linux.
+const GETRANDOM_MAX: u64 = (i32::MAX >> 6) as u64;
+
+/// How often a blocked `getrandom` rechecks whether the generator is seeded.
+const SEED_POLL_NS: u64 = 10_000_000;
+
+/// `getrandom(buf, count, flags)`.
+///
+/// Output comes from the kernel ChaCha20 generator (`libkernel::random`).
+/// Until it is seeded the call blocks, or fails with `-EAGAIN` under
+/// `GRND_NONBLOCK`; `GRND_INSECURE` never waits.  `GRND_RANDOM` draws from
+/// the same generator, as on Linux 5.6+.
+pub(crate) fn sys_getrandom(buf: u64, count: u64, flags: u64) -> i64 {
+    if flags & !(GRND_NONBLOCK | GRND_RANDOM | GRND_INSECURE) != 0
+        || flags & (GRND_RANDOM | GRND_INSECURE) == GRND_RANDOM | GRND_INSECURE
+    {
+        return -errno::EINVAL;
+    }
+    let count = count.min(GETRANDOM_MAX);
     let user_buf = match user_slice_mut(buf, count) {
         Ok(s) => s,
         Err(e) => return e,
     };
-    // Simple xorshift64* PRNG seeded from TSC.
-    let mut state: u64 = unsafe { core::arch::x86_64::_rdtsc() };
-    if state == 0 { state = 0xDEAD_BEEF_CAFE_BABE; }
-    for byte in user_buf.iter_mut() {
-        state ^= state << 13;
-        state ^= state >> 7;
-        state ^= state << 17;
-        *byte = state as u8;
+    if flags & GRND_INSECURE == 0 {
+        while !random::is_ready() {
+            if flags & GRND_NONBLOCK != 0 {
+                return -errno::EAGAIN;
+            }
+            let deadline = time::monotonic_ns() + SEED_POLL_NS;
+            if time::sleep_until(process::current_pid(), process::current_tid(), deadline).is_err() {
+                return -errno::EINTR;
+            }
+        }
     }
+    random::fill_bytes(user_buf);
     count as i64
 }
 
@@ -43,14 +69,3 @@ pub(crate) fn sys_sched_getaffinity(_pid: u64, cpusetsize: u64, mask_ptr: u64) -
     user_buf[0] = 1;
     cpusetsize as i64
 }
-
-pub(crate) fn sys_clock_gettime(_clk_id: u64, tp: u64) -> i64 {
-    if !validate_user_buf(tp, 16) {
-        return -errno::EFAULT;
-    }
-    unsafe {
-        *(tp as *mut u64) = 0;         // tv_sec
-        *((tp + 8) as *mut u64) = 0;   // tv_nsec
-    }
-    0
-}
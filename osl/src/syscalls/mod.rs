@@ -7,12 +7,16 @@
 
 mod fb;
 mod fs;
+mod futex;
 mod io;
 mod mem;
 mod misc;
+mod poll;
 mod process;
 mod service;
 mod shmem;
+mod socket;
+mod time;
 
 use crate::errno;
 use crate::syscall_nr::*;
@@ -37,8 +41,11 @@ fn syscall_inner(
         SYS_WRITE          => io::sys_write(a1, a2, a3),
         SYS_OPEN           => fs::sys_open(a1, a2, a3),
         SYS_CLOSE          => fs::sys_close(a1),
+        SYS_STAT           => fs::sys_stat(a1, a2),
         SYS_FSTAT          => fs::sys_fstat(a1, a2),
-        SYS_LSEEK          => -errno::ESPIPE,
+        SYS_LSTAT          => fs::sys_lstat(a1, a2),
+        SYS_POLL           => poll::sys_poll(a1, a2, a3),
+        SYS_LSEEK          => io::sys_lseek(a1, a2, a3),
         SYS_MMAP           => mem::sys_mmap(a1, a2, a3, a4, a5),
         SYS_MPROTECT       => mem::sys_mprotect(a1, a2, a3),
         SYS_MUNMAP         => mem::sys_munmap(a1, a2),
@@ -47,30 +54,94 @@ fn syscall_inner(
         SYS_RT_SIGPROCMASK => crate::signal::sys_rt_sigprocmask(a1, a2, a3, a4),
         SYS_RT_SIGRETURN   => crate::signal::sys_rt_sigreturn(),
         SYS_IOCTL          => -errno::ENOTTY,
+        SYS_PREAD64        => io::sys_pread64(a1, a2, a3, a4),
+        SYS_PWRITE64       => io::sys_pwrite64(a1, a2, a3, a4),
+        SYS_READV          => io::sys_readv(a1, a2, a3),
         SYS_WRITEV         => io::sys_writev(a1, a2, a3),
+        SYS_ACCESS         => fs::sys_access(a1, a2),
+        SYS_SELECT         => poll::sys_select(a1, a2, a3, a4, a5),
+        SYS_MSYNC          => mem::sys_msync(a1, a2, a3),
         SYS_MADVISE        => 0,
+        SYS_NANOSLEEP      => time::sys_nanosleep(a1, a2),
         SYS_DUP2           => fs::sys_dup2(a1, a2),
         SYS_GETPID         => process::sys_getpid(),
+        SYS_SOCKET         => socket::sys_socket(a1, a2, a3),
+        SYS_CONNECT        => socket::sys_connect(a1, a2, a3),
+        SYS_ACCEPT         => socket::sys_accept(a1, a2, a3),
+        SYS_SENDTO         => socket::sys_sendto(a1, a2, a3, a4, a5),
+        SYS_RECVFROM       => socket::sys_recvfrom(a1, a2, a3, a4, a5),
+        SYS_SENDMSG        => socket::sys_sendmsg(a1, a2, a3),
+        SYS_RECVMSG        => socket::sys_recvmsg(a1, a2, a3),
+        SYS_SHUTDOWN       => socket::sys_shutdown(a1, a2),
+        SYS_BIND           => socket::sys_bind(a1, a2, a3),
+        SYS_LISTEN         => socket::sys_listen(a1, a2),
+        SYS_GETSOCKNAME    => socket::sys_getsockname(a1, a2, a3),
+        SYS_GETPEERNAME    => socket::sys_getpeername(a1, a2, a3),
+        SYS_SOCKETPAIR     => socket::sys_socketpair(a1, a2, a3, a4),
+        SYS_SETSOCKOPT     => socket::sys_setsockopt(a1, a2, a3, a4, a5),
+        SYS_GETSOCKOPT     => socket::sys_getsockopt(a1, a2, a3, a4, a5),
         SYS_CLONE          => crate::clone::sys_clone(a1, a2, a3, a4, a5),
+        SYS_FORK           => crate::clone::sys_fork(),
+        SYS_VFORK          => crate::clone::sys_vfork(),
         SYS_EXECVE         => crate::exec::sys_execve(a1, a2, a3),
-        SYS_EXIT
-        | SYS_EXIT_GROUP   => process::sys_exit(a1 as i32),
+        SYS_EXIT           => process::sys_exit(a1 as i32),
+        SYS_EXIT_GROUP     => process::sys_exit_group(a1 as i32),
         SYS_WAIT4          => process::sys_wait4(a1, a2, a3),
         SYS_KILL           => crate::signal::sys_kill(a1, a2),
+        SYS_GETTID         => process::sys_gettid(),
+        SYS_TKILL          => crate::signal::sys_tkill(a1, a2),
+        SYS_TGKILL         => crate::signal::sys_tgkill(a1, a2, a3),
         SYS_FCNTL          => fs::sys_fcntl(a1, a2, a3),
+        SYS_FSYNC          => fs::sys_fsync(a1),
+        SYS_FDATASYNC      => fs::sys_fsync(a1),
+        SYS_TRUNCATE       => fs::sys_truncate(a1, a2),
+        SYS_FTRUNCATE      => fs::sys_ftruncate(a1, a2),
         SYS_GETCWD         => fs::sys_getcwd(a1, a2),
         SYS_CHDIR          => fs::sys_chdir(a1),
+        SYS_FCHDIR         => fs::sys_fchdir(a1),
+        SYS_RENAME         => fs::sys_rename(a1, a2),
+        SYS_MKDIR          => fs::sys_mkdir(a1, a2),
+        SYS_RMDIR          => fs::sys_rmdir(a1),
+        SYS_LINK           => fs::sys_link(a1, a2),
+        SYS_UNLINK         => fs::sys_unlink(a1),
+        SYS_SYMLINK        => fs::sys_symlink(a1, a2),
+        SYS_READLINK       => fs::sys_readlink(a1, a2, a3),
+        SYS_GETTIMEOFDAY   => time::sys_gettimeofday(a1, a2),
         SYS_SIGALTSTACK    => 0,
         SYS_ARCH_PRCTL     => misc::sys_arch_prctl(a1, a2),
-        SYS_FUTEX          => 0,
+        SYS_FUTEX          => futex::sys_futex(a1, a2, a3, a4, a5),
         SYS_SCHED_GETAFFINITY => misc::sys_sched_getaffinity(a1, a2, a3),
+        SYS_EPOLL_CREATE   => poll::sys_epoll_create(a1),
         SYS_GETDENTS64     => io::sys_getdents64(a1, a2, a3),
-        SYS_SET_TID_ADDRESS => process::sys_set_tid_address(),
-        SYS_CLOCK_GETTIME  => misc::sys_clock_gettime(a1, a2),
+        SYS_SET_TID_ADDRESS => process::sys_set_tid_address(a1),
+        SYS_CLOCK_GETTIME  => time::sys_clock_gettime(a1, a2),
+        SYS_CLOCK_GETRES   => time::sys_clock_getres(a1, a2),
+        SYS_CLOCK_NANOSLEEP => time::sys_clock_nanosleep(a1, a2, a3, a4),
+        SYS_EPOLL_WAIT     => poll::sys_epoll_wait(a1, a2, a3, a4),
+        SYS_EPOLL_CTL      => poll::sys_epoll_ctl(a1, a2, a3, a4),
+        SYS_OPENAT         => fs::sys_openat(a1, a2, a3, a4),
+        SYS_MKDIRAT        => fs::sys_mkdirat(a1, a2, a3),
+        SYS_NEWFSTATAT     => fs::sys_newfstatat(a1, a2, a3, a4),
+        SYS_UNLINKAT       => fs::sys_unlinkat(a1, a2, a3),
+        SYS_RENAMEAT       => fs::sys_renameat2(a1, a2, a3, a4, 0),
+        SYS_LINKAT         => fs::sys_linkat(a1, a2, a3, a4, a5),
+        SYS_SYMLINKAT      => fs::sys_symlinkat(a1, a2, a3),
+        SYS_READLINKAT     => fs::sys_readlinkat(a1, a2, a3, a4),
+        SYS_FACCESSAT      => fs::sys_faccessat(a1, a2, a3),
+        SYS_PSELECT6       => poll::sys_pselect6(a1, a2, a3, a4, a5),
+        SYS_PPOLL          => poll::sys_ppoll(a1, a2, a3, a4, a5),
         SYS_SET_ROBUST_LIST => 0,
+        SYS_EPOLL_PWAIT    => poll::sys_epoll_pwait(a1, a2, a3, a4, a5),
+        SYS_ACCEPT4        => socket::sys_accept4(a1, a2, a3, a4),
+        SYS_EPOLL_CREATE1  => poll::sys_epoll_create1(a1),
         SYS_PIPE           => fs::sys_pipe2(a1, 0),
+        SYS_PREADV         => io::sys_preadv(a1, a2, a3, a4, a5),
+        SYS_PWRITEV        => io::sys_pwritev(a1, a2, a3, a4, a5),
         SYS_PIPE2          => fs::sys_pipe2(a1, a2),
+        SYS_RENAMEAT2      => fs::sys_renameat2(a1, a2, a3, a4, a5),
         SYS_GETRANDOM      => misc::sys_getrandom(a1, a2, a3),
+        SYS_STATX          => fs::sys_statx(a1, a2, a3, a4, a5),
+        SYS_FACCESSAT2     => fs::sys_faccessat2(a1, a2, a3, a4),
         SYS_IO_CREATE      => crate::io_port::sys_io_create(a1 as u32),
         SYS_IO_SUBMIT      => crate::io_port::sys_io_submit(a1 as i32, a2, a3 as u32),
         SYS_IO_WAIT        => crate::io_port::sys_io_wait(a1 as i32, a2, a3 as u32, a4 as u32, a5),
@@ -97,12 +168,25 @@ fn syscall_inner(
 // ---------------------------------------------------------------------------
 // Shared helpers used by syscall submodules and other osl crates
 
-/// Resolve a path relative to the current process's CWD.
-pub fn resolve_user_path(path: &str) -> alloc::string::String {
-    let pid = libkernel::process::current_pid();
-    let cwd = libkernel::process::with_process_ref(pid, |p| p.cwd.clone())
-        .unwrap_or_else(|| alloc::string::String::from("/"));
-    libkernel::path::resolve(&cwd, path)
+/// Resolve a path relative to the current process's CWD through the VFS,
+/// following symbolic links in every component but the last, and in the
+/// last too if `follow` is set.  `..` is applied after the component before
+/// it is resolved, as on Linux, so the path is not normalised first.
+pub fn vfs_canonicalize(path: &str, follow: bool) -> Result<alloc::string::String, devices::vfs::VfsError> {
+    if path.is_empty() {
+        return Err(devices::vfs::VfsError::NotFound);
+    }
+    let joined = if path.starts_with('/') {
+        alloc::string::String::from(path)
+    } else {
+        let pid = libkernel::process::current_pid();
+        let cwd = libkernel::process::with_process_ref(pid, |p| p.cwd.clone())
+            .unwrap_or_else(|| alloc::string::String::from("/"));
+        alloc::format!("{}/{}", cwd.trim_end_matches('/'), path)
+    };
+    crate::blocking::blocking(async move {
+        devices::vfs::canonicalize(&joined, follow).await
+    })
 }
 
 /// Read a file via the VFS (blocking async bridge).
@@ -113,10 +197,60 @@ pub fn vfs_read_file(path: &str, caller_pid: libkernel::process::ProcessId) -> R
     })
 }
 
-/// List a directory via the VFS (blocking async bridge).
-pub(crate) fn vfs_list_dir(path: &str) -> Result<alloc::vec::Vec<devices::vfs::VfsDirEntry>, devices::vfs::VfsError> {
+/// Read part of a file via the VFS (blocking async bridge).
+pub fn vfs_read_at(path: &str, offset: u64, len: usize, caller_pid: libkernel::process::ProcessId) -> Result<alloc::vec::Vec<u8>, devices::vfs::VfsError> {
+    let path = alloc::string::String::from(path);
+    crate::blocking::blocking(async move {
+        devices::vfs::read_at(&path, offset, len, caller_pid).await
+    })
+}
+
+/// Map a page of a file from the page cache (blocking async bridge).
+/// Returns the frame with a reference taken for the caller.
+pub(crate) fn vfs_map_page(path: &str, index: u64) -> Result<x86_64::PhysAddr, devices::vfs::VfsError> {
+    let path = alloc::string::String::from(path);
+    crate::blocking::blocking(async move {
+        devices::vfs::map_page(&path, index).await
+    })
+}
+
+/// Stat a path via the VFS (blocking async bridge).
+pub(crate) fn vfs_stat(path: &str) -> Result<devices::vfs::VfsStat, devices::vfs::VfsError> {
+    let path = alloc::string::String::from(path);
+    crate::blocking::blocking(async move {
+        devices::vfs::stat(&path).await
+    })
+}
+
+/// Create an empty file via the VFS (blocking async bridge).
+pub(crate) fn vfs_create(path: &str) -> Result<(), devices::vfs::VfsError> {
     let path = alloc::string::String::from(path);
     crate::blocking::blocking(async move {
-        devices::vfs::list_dir(&path).await
+        devices::vfs::create(&path).await
     })
 }
+
+/// Resize a file via the VFS (blocking async bridge).
+pub(crate) fn vfs_truncate(path: &str, size: u64) -> Result<(), devices::vfs::VfsError> {
+    let path = alloc::string::String::from(path);
+    crate::blocking::blocking(async move {
+        devices::vfs::truncate(&path, size).await
+    })
+}
+
+/// Create a socket node via the VFS (blocking async bridge).
+pub(crate) fn vfs_mksock(path: &str) -> Result<(), devices::vfs::VfsError> {
+    let path = alloc::string::String::from(path);
+    crate::blocking::blocking(async move {
+        devices::vfs::mksock(&path).await
+    })
+}
+
+/// Remove a file via the VFS (blocking async bridge).
+pub(crate) fn vfs_unlink(path: &str) -> Result<(), devices::vfs::VfsError> {
+    let path = alloc::string::String::from(path);
+    crate::blocking::blocking(async move {
+        devices::vfs::unlink(&path).await
+    })
+}
+
@@ -0,0 +1,458 @@
+//! Readiness syscalls: poll, ppoll, select, pselect6, and the epoll family
+//! (epoll_create, epoll_create1, epoll_ctl, epoll_wait, epoll_pwait).
+//!
+//! All of them block in `libkernel::poll::wait_until`, registering the
+//! thread's waker with every object they poll.
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use libkernel::file::{FdObject, FD_CLOEXEC, MAX_FDS};
+use libkernel::poll::{
+    self as kpoll, WaitError, POLLERR, POLLHUP, POLLIN, POLLNVAL, POLLOUT, POLLPRI,
+    POLLRDBAND, POLLRDNORM, POLLWRBAND, POLLWRNORM,
+};
+use libkernel::process;
+use libkernel::time::{self, NSEC_PER_SEC};
+
+use crate::epoll::{CtlError, EpollHandle, EPOLLEXCLUSIVE, EPOLLONESHOT, EPOLLWAKEUP};
+use crate::errno;
+use crate::fd_helpers;
+use crate::signal::{read_sigmask, set_temporary_mask};
+use crate::user_mem::{user_slice, user_slice_mut, validate_user_buf};
+
+use super::time::read_timespec;
+
+const NSEC_PER_MSEC: u64 = 1_000_000;
+const NSEC_PER_USEC: u64 = 1000;
+
+/// `select` ready sets: what makes an fd readable, writable or exceptional.
+const SELECT_IN: u32 = POLLIN | POLLRDNORM | POLLRDBAND | POLLHUP | POLLERR;
+const SELECT_OUT: u32 = POLLOUT | POLLWRNORM | POLLWRBAND | POLLERR;
+const SELECT_EX: u32 = POLLPRI;
+
+const EPOLL_CLOEXEC: u64 = 0o2000000;
+const EPOLL_CTL_ADD: u64 = 1;
+const EPOLL_CTL_DEL: u64 = 2;
+const EPOLL_CTL_MOD: u64 = 3;
+
+/// Size of `struct epoll_event`, which is packed on x86-64.
+const EPOLL_EVENT_SIZE: u64 = 12;
+
+/// Most events one `epoll_wait` returns, as on Linux.
+const EP_MAX_EVENTS: u64 = i32::MAX as u64 / EPOLL_EVENT_SIZE;
+
+/// Monotonic deadline of a millisecond timeout; negative means none.
+fn deadline_from_ms(timeout: i32) -> Option<u64> {
+    if timeout < 0 {
+        None
+    } else {
+        Some(time::monotonic_ns().saturating_add(timeout as u64 * NSEC_PER_MSEC))
+    }
+}
+
+/// Block until one of `objects` is ready for the events in `wanted`, the
+/// deadline passes or a signal arrives, and return each one's ready events
+/// (all zero on timeout).  A missing object reports `POLLNVAL`, if wanted.
+fn wait_ready(objects: &[Option<FdObject>], wanted: &[u32], deadline: Option<u64>) -> Result<Vec<u32>, i64> {
+    let pid = process::current_pid();
+    let tid = process::current_tid();
+    let result = kpoll::wait_until(pid, tid, deadline, |waker| {
+        let revents: Vec<u32> = objects
+            .iter()
+            .zip(wanted)
+            .map(|(object, &wanted)| match object {
+                Some(object) => object.poll(Some(waker)) & wanted,
+                None => POLLNVAL & wanted,
+            })
+            .collect();
+        if revents.iter().any(|&r| r != 0) { Some(revents) } else { None }
+    });
+    match result {
+        Ok(revents) => Ok(revents),
+        Err(WaitError::TimedOut) => Ok(alloc::vec![0; objects.len()]),
+        Err(WaitError::Interrupted) => Err(-errno::EINTR),
+    }
+}
+
+/// Store the time left until `deadline` at `ptr`, in the layout of
+/// `struct timespec` or (`usec`) `struct timeval`.
+fn write_remaining(ptr: u64, deadline: u64, usec: bool) -> Result<(), i64> {
+    if !validate_user_buf(ptr, 16) {
+        return Err(-errno::EFAULT);
+    }
+    let left = deadline.saturating_sub(time::monotonic_ns());
+    let frac = if usec { left % NSEC_PER_SEC / NSEC_PER_USEC } else { left % NSEC_PER_SEC };
+    unsafe {
+        *(ptr as *mut u64) = left / NSEC_PER_SEC;
+        *((ptr + 8) as *mut u64) = frac;
+    }
+    Ok(())
+}
+
+// ---------------------------------------------------------------------------
+// poll / ppoll
+
+/// `struct pollfd`: `int fd; short events; short revents;`.
+const POLLFD_SIZE: u64 = 8;
+
+fn do_poll(fds_ptr: u64, nfds: u64, deadline: Option<u64>) -> i64 {
+    if nfds > MAX_FDS as u64 {
+        return -errno::EINVAL;
+    }
+    let pollfds: &mut [u8] = if nfds == 0 {
+        &mut []
+    } else {
+        match user_slice_mut(fds_ptr, nfds * POLLFD_SIZE) {
+            Ok(s) => s,
+            Err(e) => return e,
+        }
+    };
+    let mut objects = Vec::with_capacity(nfds as usize);
+    let mut wanted = Vec::with_capacity(nfds as usize);
+    for pollfd in pollfds.chunks_exact(POLLFD_SIZE as usize) {
+        let fd = i32::from_le_bytes([pollfd[0], pollfd[1], pollfd[2], pollfd[3]]);
+        let events = u16::from_le_bytes([pollfd[4], pollfd[5]]) as u32;
+        if fd < 0 {
+            // Ignored: never ready, not even POLLNVAL.
+            objects.push(None);
+            wanted.push(0);
+        } else {
+            objects.push(fd_helpers::get_fd_object(fd as usize).ok());
+            wanted.push(events | POLLERR | POLLHUP | POLLNVAL);
+        }
+    }
+    let revents = match wait_ready(&objects, &wanted, deadline) {
+        Ok(r) => r,
+        Err(e) => return e,
+    };
+    for (pollfd, &r) in pollfds.chunks_exact_mut(POLLFD_SIZE as usize).zip(&revents) {
+        pollfd[6..8].copy_from_slice(&(r as u16).to_le_bytes());
+    }
+    revents.iter().filter(|&&r| r != 0).count() as i64
+}
+
+/// `poll(fds, nfds, timeout_ms)`.
+pub(crate) fn sys_poll(fds: u64, nfds: u64, timeout: u64) -> i64 {
+    do_poll(fds, nfds, deadline_from_ms(timeout as i32))
+}
+
+/// `ppoll(fds, nfds, tmo_p, sigmask, sigsetsize)`.  The remaining time is
+/// written back to `tmo_p`, as the Linux syscall does.
+pub(crate) fn sys_ppoll(fds: u64, nfds: u64, tmo_p: u64, sigmask: u64, sigsetsize: u64) -> i64 {
+    let deadline = if tmo_p == 0 {
+        None
+    } else {
+        match read_timespec(tmo_p) {
+            Ok(ns) => Some(time::monotonic_ns().saturating_add(ns)),
+            Err(e) => return e,
+        }
+    };
+    match read_sigmask(sigmask, sigsetsize) {
+        Ok(Some(mask)) => set_temporary_mask(mask),
+        Ok(None) => {}
+        Err(e) => return e,
+    }
+    let ret = do_poll(fds, nfds, deadline);
+    if let Some(deadline) = deadline {
+        if let Err(e) = write_remaining(tmo_p, deadline, false) {
+            return e;
+        }
+    }
+    ret
+}
+
+// ---------------------------------------------------------------------------
+// select / pselect6
+
+/// Read an optional `fd_set` of `nfds` bits as 64-bit words.
+fn read_fd_set(ptr: u64, words: usize) -> Result<Option<Vec<u64>>, i64> {
+    if ptr == 0 {
+        return Ok(None);
+    }
+    let bytes = user_slice(ptr, words as u64 * 8)?;
+    Ok(Some(
+        bytes
+            .chunks_exact(8)
+            .map(|w| u64::from_le_bytes([w[0], w[1], w[2], w[3], w[4], w[5], w[6], w[7]]))
+            .collect(),
+    ))
+}
+
+fn write_fd_set(ptr: u64, set: &[u64]) -> Result<(), i64> {
+    if ptr == 0 {
+        return Ok(());
+    }
+    let bytes = user_slice_mut(ptr, set.len() as u64 * 8)?;
+    for (dst, word) in bytes.chunks_exact_mut(8).zip(set) {
+        dst.copy_from_slice(&word.to_le_bytes());
+    }
+    Ok(())
+}
+
+fn do_select(nfds: u64, readfds: u64, writefds: u64, exceptfds: u64, deadline: Option<u64>) -> i64 {
+    let nfds = nfds as i32;
+    if nfds < 0 {
+        return -errno::EINVAL;
+    }
+    // Bits past the fd table cannot name an open fd; like Linux, ignore them.
+    let nfds = (nfds as usize).min(MAX_FDS);
+    let words = (nfds + 63) / 64;
+    let mut sets = [None, None, None];
+    for (set, &ptr) in sets.iter_mut().zip(&[readfds, writefds, exceptfds]) {
+        *set = match read_fd_set(ptr, words) {
+            Ok(s) => s,
+            Err(e) => return e,
+        };
+    }
+    let is_set = |set: &Option<Vec<u64>>, fd: usize| {
+        set.as_ref().map_or(false, |s| s[fd / 64] & (1 << (fd % 64)) != 0)
+    };
+
+    let mut fds = Vec::new();
+    let mut objects = Vec::new();
+    let mut wanted = Vec::new();
+    for fd in 0..nfds {
+        let mut mask = 0;
+        for (set, events) in sets.iter().zip(&[SELECT_IN, SELECT_OUT, SELECT_EX]) {
+            if is_set(set, fd) {
+                mask |= events;
+            }
+        }
+        if mask == 0 {
+            continue;
+        }
+        match fd_helpers::get_fd_object(fd) {
+            Ok(object) => objects.push(Some(object)),
+            Err(e) => return e,
+        }
+        fds.push(fd);
+        wanted.push(mask);
+    }
+
+    let revents = match wait_ready(&objects, &wanted, deadline) {
+        Ok(r) => r,
+        Err(e) => return e,
+    };
+
+    let mut count = 0;
+    let mut out = [alloc::vec![0u64; words], alloc::vec![0u64; words], alloc::vec![0u64; words]];
+    for (&fd, &r) in fds.iter().zip(&revents) {
+        for ((out, set), events) in out.iter_mut().zip(&sets).zip(&[SELECT_IN, SELECT_OUT, SELECT_EX]) {
+            if is_set(set, fd) && r & events != 0 {
+                out[fd / 64] |= 1 << (fd % 64);
+                count += 1;
+            }
+        }
+    }
+    for (out, &ptr) in out.iter().zip(&[readfds, writefds, exceptfds]) {
+        if let Err(e) = write_fd_set(ptr, out) {
+            return e;
+        }
+    }
+    count
+}
+
+/// `select(nfds, readfds, writefds, exceptfds, timeout)`.  The remaining
+/// time is written back to `timeout`, as on Linux.
+pub(crate) fn sys_select(nfds: u64, readfds: u64, writefds: u64, exceptfds: u64, timeout: u64) -> i64 {
+    let deadline = if timeout == 0 {
+        None
+    } else {
+        if !validate_user_buf(timeout, 16) {
+            return -errno::EFAULT;
+        }
+        let (sec, usec) = unsafe { (*(timeout as *const i64), *((timeout + 8) as *const i64)) };
+        if sec < 0 || !(0..1_000_000).contains(&usec) {
+            return -errno::EINVAL;
+        }
+        let ns = (sec as u64).saturating_mul(NSEC_PER_SEC).saturating_add(usec as u64 * NSEC_PER_USEC);
+        Some(time::monotonic_ns().saturating_add(ns))
+    };
+    let ret = do_select(nfds, readfds, writefds, exceptfds, deadline);
+    if let Some(deadline) = deadline {
+        if let Err(e) = write_remaining(timeout, deadline, true) {
+            return e;
+        }
+    }
+    ret
+}
+
+/// `pselect6(nfds, readfds, writefds, exceptfds, timeout, sigmask)`.  The
+/// sixth argument, read from R9, points to
+/// `{ const sigset_t *ss; size_t ss_len; }`.
+pub(crate) fn sys_pselect6(nfds: u64, readfds: u64, writefds: u64, exceptfds: u64, timeout: u64) -> i64 {
+    let sig = libkernel::syscall::get_user_r9();
+    let deadline = if timeout == 0 {
+        None
+    } else {
+        match read_timespec(timeout) {
+            Ok(ns) => Some(time::monotonic_ns().saturating_add(ns)),
+            Err(e) => return e,
+        }
+    };
+    if sig != 0 {
+        if !validate_user_buf(sig, 16) {
+            return -errno::EFAULT;
+        }
+        let (ss, ss_len) = unsafe { (*(sig as *const u64), *((sig + 8) as *const u64)) };
+        match read_sigmask(ss, ss_len) {
+            Ok(Some(mask)) => set_temporary_mask(mask),
+            Ok(None) => {}
+            Err(e) => return e,
+        }
+    }
+    let ret = do_select(nfds, readfds, writefds, exceptfds, deadline);
+    if let Some(deadline) = deadline {
+        if let Err(e) = write_remaining(timeout, deadline, false) {
+            return e;
+        }
+    }
+    ret
+}
+
+// ---------------------------------------------------------------------------
+// epoll
+
+/// The epoll instance open at `epfd`.
+fn get_epoll(epfd: u64) -> Result<Arc<EpollHandle>, i64> {
+    let handle = fd_helpers::get_fd_file(epfd as usize)?;
+    if handle.kind() != "epoll" {
+        return Err(-errno::EINVAL);
+    }
+    // SAFETY: only `EpollHandle` reports the kind "epoll".
+    Ok(unsafe { Arc::from_raw(Arc::into_raw(handle) as *const EpollHandle) })
+}
+
+/// `epoll_create1(flags)`.
+pub(crate) fn sys_epoll_create1(flags: u64) -> i64 {
+    if flags & !EPOLL_CLOEXEC != 0 {
+        return -errno::EINVAL;
+    }
+    let fd_flags = if flags & EPOLL_CLOEXEC != 0 { FD_CLOEXEC } else { 0 };
+    let obj = FdObject::File(Arc::new(EpollHandle::new()));
+    match fd_helpers::alloc_fd_with_flags(obj, fd_flags) {
+        Ok(fd) => fd as i64,
+        Err(e) => e,
+    }
+}
+
+/// `epoll_create(size)`.  The size hint is ignored but must be positive.
+pub(crate) fn sys_epoll_create(size: u64) -> i64 {
+    if size as i32 <= 0 {
+        return -errno::EINVAL;
+    }
+    sys_epoll_create1(0)
+}
+
+/// `epoll_ctl(epfd, op, fd, event)`.
+///
+/// Regular files and directories are always ready and cannot be watched
+/// (`EPERM`), as on Linux.  An instance cannot watch itself.
+pub(crate) fn sys_epoll_ctl(epfd: u64, op: u64, fd: u64, event: u64) -> i64 {
+    let epoll = match get_epoll(epfd) {
+        Ok(e) => e,
+        Err(e) => return e,
+    };
+    let target = match fd_helpers::get_fd_object(fd as usize) {
+        Ok(t) => t,
+        Err(e) => return e,
+    };
+    if let Some(h) = target.as_file() {
+        if matches!(h.kind(), "vfs_file" | "dir") {
+            return -errno::EPERM;
+        }
+        if Arc::as_ptr(h) as *const () == Arc::as_ptr(&epoll) as *const () {
+            return -errno::EINVAL;
+        }
+    }
+    let fd = fd as i32;
+
+    let (events, data) = match op {
+        EPOLL_CTL_ADD | EPOLL_CTL_MOD => {
+            if !validate_user_buf(event, EPOLL_EVENT_SIZE) {
+                return -errno::EFAULT;
+            }
+            unsafe {
+                (
+                    core::ptr::read_unaligned(event as *const u32),
+                    core::ptr::read_unaligned((event + 4) as *const u64),
+                )
+            }
+        }
+        EPOLL_CTL_DEL => (0, 0),
+        _ => return -errno::EINVAL,
+    };
+    // Exclusive wake-ups only matter with several waiters; accepted and
+    // ignored, but only on ADD and without ONESHOT, as on Linux.
+    if events & EPOLLEXCLUSIVE != 0 && (op == EPOLL_CTL_MOD || events & EPOLLONESHOT != 0) {
+        return -errno::EINVAL;
+    }
+    let events = events & !(EPOLLEXCLUSIVE | EPOLLWAKEUP);
+
+    let result = match op {
+        EPOLL_CTL_ADD => epoll.add(fd, target, events, data),
+        EPOLL_CTL_MOD => epoll.modify(fd, events, data),
+        _ => epoll.remove(fd),
+    };
+    match result {
+        Ok(()) => 0,
+        Err(CtlError::Exists) => -errno::EEXIST,
+        Err(CtlError::NotFound) => -errno::ENOENT,
+    }
+}
+
+fn do_epoll_wait(epfd: u64, events: u64, maxevents: u64, timeout: i32, sigmask: Option<u64>) -> i64 {
+    let epoll = match get_epoll(epfd) {
+        Ok(e) => e,
+        Err(e) => return e,
+    };
+    let maxevents = maxevents as i32;
+    if maxevents <= 0 || maxevents as u64 > EP_MAX_EVENTS {
+        return -errno::EINVAL;
+    }
+    if !validate_user_buf(events, maxevents as u64 * EPOLL_EVENT_SIZE) {
+        return -errno::EFAULT;
+    }
+    if let Some(mask) = sigmask {
+        set_temporary_mask(mask);
+    }
+
+    let pid = process::current_pid();
+    let tid = process::current_tid();
+    let result = kpoll::wait_until(pid, tid, deadline_from_ms(timeout), |waker| {
+        epoll.register(waker);
+        epoll.prune(pid);
+        let ready = epoll.collect(maxevents as usize);
+        if ready.is_empty() { None } else { Some(ready) }
+    });
+    let ready = match result {
+        Ok(ready) => ready,
+        Err(WaitError::TimedOut) => return 0,
+        Err(WaitError::Interrupted) => return -errno::EINTR,
+    };
+    for (i, &(revents, data)) in ready.iter().enumerate() {
+        let slot = events + i as u64 * EPOLL_EVENT_SIZE;
+        unsafe {
+            core::ptr::write_unaligned(slot as *mut u32, revents);
+            core::ptr::write_unaligned((slot + 4) as *mut u64, data);
+        }
+    }
+    ready.len() as i64
+}
+
+/// `epoll_wait(epfd, events, maxevents, timeout_ms)`.
+pub(crate) fn sys_epoll_wait(epfd: u64, events: u64, maxevents: u64, timeout: u64) -> i64 {
+    do_epoll_wait(epfd, events, maxevents, timeout as i32, None)
+}
+
+/// `epoll_pwait(epfd, events, maxevents, timeout_ms, sigmask, sigsetsize)`.
+/// `sigsetsize` is the sixth argument, read from R9.
+pub(crate) fn sys_epoll_pwait(epfd: u64, events: u64, maxevents: u64, timeout: u64, sigmask: u64) -> i64 {
+    let sigsetsize = libkernel::syscall::get_user_r9();
+    let mask = match read_sigmask(sigmask, sigsetsize) {
+        Ok(m) => m,
+        Err(e) => return e,
+    };
+    do_epoll_wait(epfd, events, maxevents, timeout as i32, mask)
+}
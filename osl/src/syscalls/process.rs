@@ -1,17 +1,34 @@
-//! Process management syscalls: exit, wait4, getpid, set_tid_address.
+//! Process management syscalls: exit, exit_group, wait4, getpid, gettid,
+//! set_tid_address.
 
 use crate::errno;
 use crate::user_mem::validate_user_buf;
 use libkernel::process;
 use libkernel::wait_condition::WaitCondition;
 
+/// `exit` ends only the calling thread; the process exits with `code` when
+/// its last thread is gone.
 pub(crate) fn sys_exit(code: i32) -> i64 {
+    let pid = process::current_pid();
+    if pid != process::ProcessId::KERNEL {
+        let tid = process::current_tid();
+        libkernel::serial_println!("[kernel] pid {} tid {} exited with code {}",
+            pid.as_u64(), tid.as_u64(), code);
+        process::exit_thread(pid, tid, code);
+    } else {
+        libkernel::println!("\n[kernel] kernel sys_exit({}) — halting", code);
+        libkernel::task::scheduler::kill_current_thread();
+    }
+}
+
+/// `exit_group` ends every thread in the process.
+pub(crate) fn sys_exit_group(code: i32) -> i64 {
     let pid = process::current_pid();
     if pid != process::ProcessId::KERNEL {
         libkernel::serial_println!("[kernel] pid {} exited with code {}", pid.as_u64(), code);
         process::terminate_process(pid, code);
     } else {
-        libkernel::println!("\n[kernel] kernel sys_exit({}) — halting", code);
+        libkernel::println!("\n[kernel] kernel sys_exit_group({}) — halting", code);
         libkernel::task::scheduler::kill_current_thread();
     }
 }
@@ -20,12 +37,26 @@ pub(crate) fn sys_getpid() -> i64 {
     process::current_pid().as_u64() as i64
 }
 
-pub(crate) fn sys_set_tid_address() -> i64 {
-    process::current_pid().as_u64() as i64
+pub(crate) fn sys_gettid() -> i64 {
+    process::current_tid().as_u64() as i64
+}
+
+/// Record `tidptr` as the calling thread's clear_child_tid word: it is
+/// zeroed (and futex-woken) when the thread exits.
+pub(crate) fn sys_set_tid_address(tidptr: u64) -> i64 {
+    let pid = process::current_pid();
+    let tid = process::current_tid();
+    process::with_process(pid, |p| {
+        if let Some(t) = p.thread_mut(tid) {
+            t.clear_child_tid = tidptr;
+        }
+    });
+    tid.as_u64() as i64
 }
 
 pub(crate) fn sys_wait4(pid_arg: u64, status_ptr: u64, _options: u64) -> i64 {
     let parent_pid = process::current_pid();
+    let tid = process::current_tid();
     let target_pid = pid_arg as i64;
 
     // [spec: completion_port/completion_port.tla — single lock acquisition for
@@ -50,21 +81,26 @@ pub(crate) fn sys_wait4(pid_arg: u64, status_ptr: u64, _options: u64) -> i64 {
 
         // Check for pending signals under same lock.
         let has_signal = table.get(&parent_pid)
-            .map_or(false, |p| (p.signal.pending & !p.signal.blocked) != 0);
+            .map_or(false, |p| p.interrupted(tid));
         if has_signal {
             return -errno::EINTR;
         }
 
         WaitCondition::wait_while(Some(table), |table, idx| {
             if let Some(p) = table.get_mut(&parent_pid) {
-                p.wait_thread = Some(idx);
-                p.signal_thread = Some(idx);
+                if !p.wait_threads.contains(&idx) {
+                    p.wait_threads.push(idx);
+                }
+                p.set_interruptible(tid, true);
             }
         });
 
-        // Clear signal_thread after waking.
+        // Clear the interruptible mark after waking, and the registration
+        // if a signal rather than an exiting child woke us.
+        let idx = libkernel::task::scheduler::current_thread_idx();
         process::with_process(parent_pid, |p| {
-            p.signal_thread = None;
+            p.set_interruptible(tid, false);
+            p.wait_threads.retain(|&i| i != idx);
         });
     }
 }
@@ -0,0 +1,1166 @@
+//! Socket syscalls: socket, socketpair, bind, listen, accept, accept4,
+//! connect, sendto, recvfrom, sendmsg, recvmsg, shutdown, getsockname,
+//! getpeername, setsockopt, getsockopt.
+//!
+//! `AF_UNIX` sockets are implemented by `libkernel::unix_socket` and are
+//! `FileHandle`s of kind `"unix_socket"`; `AF_INET` and `AF_INET6` sockets
+//! by `devices::net`, of kind `"inet_socket"`.  These calls convert
+//! addresses, ancillary data and options between user memory and the
+//! socket's own types.  Descriptors passed with `SCM_RIGHTS` are taken and
+//! installed by the same helpers as `ipc_send` / `ipc_recv`; Internet
+//! sockets take no ancillary data.
+
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::convert::TryInto;
+use core::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
+
+use devices::net::{self, Family, InetSocket};
+use devices::vfs::{VfsError, S_IFMT, S_IFSOCK};
+use libkernel::file::{FdObject, FileHandle, FD_CLOEXEC};
+use libkernel::process::{self, ProcessId};
+use libkernel::signal::SIGPIPE;
+use libkernel::socket::{
+    SocketError, SocketType, MSG_CMSG_CLOEXEC, MSG_CTRUNC, MSG_NOSIGNAL, MSG_OOB, MSG_TRUNC,
+};
+use libkernel::time::NSEC_PER_SEC;
+use libkernel::unix_socket::{self, NameKey, Received, Rights, Ucred, UnixAddr, UnixSocket};
+
+use crate::errno;
+use crate::fd_helpers;
+use crate::ipc::{extract_fds, install_fds};
+use crate::user_mem::{user_slice, user_slice_mut};
+
+use super::io::user_iovecs;
+
+const AF_UNSPEC: u16 = 0;
+const AF_UNIX: u16 = 1;
+const AF_INET: u16 = 2;
+const AF_INET6: u16 = 10;
+
+const IPPROTO_TCP: u64 = 6;
+const IPPROTO_UDP: u64 = 17;
+
+const SOCK_STREAM: u64 = 1;
+const SOCK_DGRAM: u64 = 2;
+const SOCK_SEQPACKET: u64 = 5;
+const SOCK_TYPE_MASK: u64 = 0xf;
+const SOCK_NONBLOCK: u64 = 0o4000;
+const SOCK_CLOEXEC: u64 = 0o2000000;
+
+const SHUT_RD: u64 = 0;
+const SHUT_WR: u64 = 1;
+const SHUT_RDWR: u64 = 2;
+
+const SOL_SOCKET: u64 = 1;
+const SO_REUSEADDR: u64 = 2;
+const SO_TYPE: u64 = 3;
+const SO_ERROR: u64 = 4;
+const SO_SNDBUF: u64 = 7;
+const SO_RCVBUF: u64 = 8;
+const SO_KEEPALIVE: u64 = 9;
+const SO_PASSCRED: u64 = 16;
+const SO_PEERCRED: u64 = 17;
+const SO_RCVTIMEO: u64 = 20;
+const SO_SNDTIMEO: u64 = 21;
+const SO_ACCEPTCONN: u64 = 30;
+const SO_PROTOCOL: u64 = 38;
+const SO_DOMAIN: u64 = 39;
+const TCP_NODELAY: u64 = 1;
+
+const SCM_RIGHTS: i32 = 1;
+const SCM_CREDENTIALS: i32 = 2;
+/// Most descriptors one message carries (Linux `SCM_MAX_FD`).
+const SCM_MAX_FD: usize = 253;
+
+/// `struct sockaddr_un`: `sa_family_t sun_family; char sun_path[108];`.
+const SOCKADDR_UN_SIZE: u64 = 110;
+/// `struct sockaddr_in`: family, port, address, 8 bytes of padding.
+const SOCKADDR_IN_SIZE: u64 = 16;
+/// `struct sockaddr_in6`: family, port, flow info, address, scope id.
+const SOCKADDR_IN6_SIZE: u64 = 28;
+/// `struct msghdr`.
+const MSGHDR_SIZE: u64 = 56;
+/// `struct cmsghdr`: `size_t cmsg_len; int cmsg_level; int cmsg_type;`.
+const CMSG_HDR: usize = 16;
+/// `struct ucred`: `pid_t pid; uid_t uid; gid_t gid;`.
+const UCRED_SIZE: usize = 12;
+const NSEC_PER_USEC: u64 = 1000;
+
+// ---------------------------------------------------------------------------
+// socket / socketpair
+
+/// `socket(domain, type, protocol)`.  `type` may carry `SOCK_NONBLOCK`
+/// and `SOCK_CLOEXEC`.
+pub(crate) fn sys_socket(domain: u64, ty: u64, protocol: u64) -> i64 {
+    let (ty, fd_flags, nonblock) = match check_socket_args(domain, ty, protocol) {
+        Ok(args) => args,
+        Err(e) => return e,
+    };
+    let result = match inet_family(domain) {
+        Some(family) => {
+            let sock = InetSocket::new(family, ty);
+            sock.set_nonblocking(nonblock);
+            install(sock, fd_flags)
+        }
+        None => {
+            let sock = UnixSocket::new(ty);
+            sock.set_nonblocking(nonblock);
+            install(sock, fd_flags)
+        }
+    };
+    match result {
+        Ok(fd) => fd as i64,
+        Err(e) => e,
+    }
+}
+
+/// `socketpair(domain, type, protocol, sv)`: a connected pair, stored as
+/// two `int`s at `sv`.  Only `AF_UNIX` has pairs.
+pub(crate) fn sys_socketpair(domain: u64, ty: u64, protocol: u64, sv: u64) -> i64 {
+    let (ty, fd_flags, nonblock) = match check_socket_args(domain, ty, protocol) {
+        Ok(args) => args,
+        Err(e) => return e,
+    };
+    if inet_family(domain).is_some() {
+        return -errno::EOPNOTSUPP;
+    }
+    let out = match user_slice_mut(sv, 8) {
+        Ok(s) => s,
+        Err(e) => return e,
+    };
+    let (a, b) = UnixSocket::pair(ty);
+    a.set_nonblocking(nonblock);
+    b.set_nonblocking(nonblock);
+
+    let fd_a = match install(a, fd_flags) {
+        Ok(fd) => fd,
+        Err(e) => {
+            b.close();
+            return e;
+        }
+    };
+    let fd_b = match install(b, fd_flags) {
+        Ok(fd) => fd,
+        Err(e) => {
+            let pid = process::current_pid();
+            process::with_process(pid, |p| {
+                p.close_fd(fd_a).ok();
+            });
+            return e;
+        }
+    };
+    out[..4].copy_from_slice(&(fd_a as i32).to_le_bytes());
+    out[4..].copy_from_slice(&(fd_b as i32).to_le_bytes());
+    0
+}
+
+/// Check the arguments of `socket` / `socketpair`, returning the socket
+/// type, the fd flags and whether it starts non-blocking.
+fn check_socket_args(domain: u64, ty: u64, protocol: u64) -> Result<(SocketType, u32, bool), i64> {
+    let inet = match inet_family(domain) {
+        Some(_) => true,
+        None if domain == AF_UNIX as u64 => false,
+        None => return Err(-errno::EAFNOSUPPORT),
+    };
+    if ty & !(SOCK_TYPE_MASK | SOCK_NONBLOCK | SOCK_CLOEXEC) != 0 {
+        return Err(-errno::EINVAL);
+    }
+    let kind = match ty & SOCK_TYPE_MASK {
+        SOCK_STREAM => SocketType::Stream,
+        SOCK_DGRAM => SocketType::Datagram,
+        SOCK_SEQPACKET => SocketType::SeqPacket,
+        _ => return Err(-errno::ESOCKTNOSUPPORT),
+    };
+    if inet {
+        // TCP for streams and UDP for datagrams; 0 picks the one there is.
+        match (kind, protocol) {
+            (SocketType::Stream, 0) | (SocketType::Stream, IPPROTO_TCP) => {}
+            (SocketType::Datagram, 0) | (SocketType::Datagram, IPPROTO_UDP) => {}
+            (SocketType::SeqPacket, _) => return Err(-errno::ESOCKTNOSUPPORT),
+            _ => return Err(-errno::EPROTONOSUPPORT),
+        }
+    } else if protocol != 0 && protocol != AF_UNIX as u64 {
+        // 0 picks the default; PF_UNIX has only the one protocol.
+        return Err(-errno::EPROTONOSUPPORT);
+    }
+    let fd_flags = if ty & SOCK_CLOEXEC != 0 { FD_CLOEXEC } else { 0 };
+    Ok((kind, fd_flags, ty & SOCK_NONBLOCK != 0))
+}
+
+/// The family of an `AF_INET` / `AF_INET6` domain.
+fn inet_family(domain: u64) -> Option<Family> {
+    match domain {
+        d if d == AF_INET as u64 => Some(Family::Inet),
+        d if d == AF_INET6 as u64 => Some(Family::Inet6),
+        _ => None,
+    }
+}
+
+/// Open `sock` in the current process.  If that fails the socket is
+/// closed, so a connected peer sees the hang-up.
+fn install<S: FileHandle + 'static>(sock: Arc<S>, fd_flags: u32) -> Result<usize, i64> {
+    let handle: Arc<dyn FileHandle> = sock.clone();
+    fd_helpers::alloc_fd_with_flags(FdObject::File(handle), fd_flags).map_err(|e| {
+        sock.close();
+        e
+    })
+}
+
+/// A socket of either implementation.
+enum Socket {
+    Unix(Arc<UnixSocket>),
+    Inet(Arc<InetSocket>),
+}
+
+/// Get the socket open at `fd`.
+///
+/// Returns `-EBADF` if the fd is invalid, or `-ENOTSOCK` if it is not a
+/// socket.
+fn get_socket(fd: u64) -> Result<Socket, i64> {
+    let obj = fd_helpers::get_fd_object(fd as usize)?;
+    match obj.as_file() {
+        Some(h) if h.kind() == "unix_socket" => {
+            let raw = Arc::into_raw(h.clone()) as *const UnixSocket;
+            // SAFETY: only `UnixSocket` reports kind "unix_socket".
+            Ok(Socket::Unix(unsafe { Arc::from_raw(raw) }))
+        }
+        Some(h) if h.kind() == "inet_socket" => {
+            let raw = Arc::into_raw(h.clone()) as *const InetSocket;
+            // SAFETY: only `InetSocket` reports kind "inet_socket".
+            Ok(Socket::Inet(unsafe { Arc::from_raw(raw) }))
+        }
+        _ => Err(-errno::ENOTSOCK),
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Addresses
+
+/// The `sa_family` of the address at `ptr`, or `None` if it is too short
+/// to have one.
+fn sockaddr_family(ptr: u64, len: u64) -> Result<Option<u16>, i64> {
+    if len < 2 {
+        return Ok(None);
+    }
+    let raw = user_slice(ptr, 2)?;
+    Ok(Some(u16::from_le_bytes([raw[0], raw[1]])))
+}
+
+/// Read a `sockaddr_un` of `len` bytes.  A bare family names no socket; a
+/// path ends at its first NUL; an abstract name is every byte after the
+/// leading NUL.
+fn read_sockaddr(ptr: u64, len: u64) -> Result<UnixAddr, i64> {
+    if len < 2 || len > SOCKADDR_UN_SIZE {
+        return Err(-errno::EINVAL);
+    }
+    let raw = user_slice(ptr, len)?;
+    if u16::from_le_bytes([raw[0], raw[1]]) != AF_UNIX {
+        return Err(-errno::EINVAL);
+    }
+    let path = &raw[2..];
+    Ok(match path.first() {
+        None => UnixAddr::Unnamed,
+        Some(0) => UnixAddr::Abstract(path[1..].to_vec()),
+        Some(_) => {
+            let end = path.iter().position(|&b| b == 0).unwrap_or(path.len());
+            let path = core::str::from_utf8(&path[..end]).map_err(|_| -errno::EINVAL)?;
+            UnixAddr::Path(String::from(path))
+        }
+    })
+}
+
+/// `name` in the layout of `struct sockaddr_un`, only as long as it needs.
+fn sockaddr_bytes(name: &UnixAddr) -> Vec<u8> {
+    let mut out = AF_UNIX.to_le_bytes().to_vec();
+    match name {
+        UnixAddr::Unnamed => {}
+        UnixAddr::Path(path) => {
+            out.extend_from_slice(path.as_bytes());
+            out.push(0);
+        }
+        UnixAddr::Abstract(bytes) => {
+            out.push(0);
+            out.extend_from_slice(bytes);
+        }
+    }
+    out
+}
+
+/// Read the `sockaddr_in` (or for an `AF_INET6` socket, `sockaddr_in6`)
+/// of `len` bytes at `ptr`.  An address of the other family is refused.
+fn read_inet_sockaddr(family: Family, ptr: u64, len: u64) -> Result<SocketAddr, i64> {
+    let (af, size) = match family {
+        Family::Inet => (AF_INET, SOCKADDR_IN_SIZE),
+        Family::Inet6 => (AF_INET6, SOCKADDR_IN6_SIZE),
+    };
+    if len < size {
+        return Err(-errno::EINVAL);
+    }
+    let raw = user_slice(ptr, size)?;
+    if u16::from_le_bytes([raw[0], raw[1]]) != af {
+        return Err(-errno::EAFNOSUPPORT);
+    }
+    let port = u16::from_be_bytes([raw[2], raw[3]]);
+    Ok(match family {
+        Family::Inet => {
+            let ip = Ipv4Addr::new(raw[4], raw[5], raw[6], raw[7]);
+            SocketAddr::V4(SocketAddrV4::new(ip, port))
+        }
+        Family::Inet6 => {
+            let flowinfo = u32::from_be_bytes(raw[4..8].try_into().unwrap());
+            let ip: [u8; 16] = raw[8..24].try_into().unwrap();
+            let scope_id = u32::from_le_bytes(raw[24..28].try_into().unwrap());
+            SocketAddr::V6(SocketAddrV6::new(Ipv6Addr::from(ip), port, flowinfo, scope_id))
+        }
+    })
+}
+
+/// `addr` in the layout of `struct sockaddr_in` or `struct sockaddr_in6`.
+fn inet_sockaddr_bytes(addr: &SocketAddr) -> Vec<u8> {
+    match addr {
+        SocketAddr::V4(a) => {
+            let mut out = vec![0u8; SOCKADDR_IN_SIZE as usize];
+            out[..2].copy_from_slice(&AF_INET.to_le_bytes());
+            out[2..4].copy_from_slice(&a.port().to_be_bytes());
+            out[4..8].copy_from_slice(&a.ip().octets());
+            out
+        }
+        SocketAddr::V6(a) => {
+            let mut out = vec![0u8; SOCKADDR_IN6_SIZE as usize];
+            out[..2].copy_from_slice(&AF_INET6.to_le_bytes());
+            out[2..4].copy_from_slice(&a.port().to_be_bytes());
+            out[4..8].copy_from_slice(&a.flowinfo().to_be_bytes());
+            out[8..24].copy_from_slice(&a.ip().octets());
+            out[24..].copy_from_slice(&a.scope_id().to_le_bytes());
+            out
+        }
+    }
+}
+
+/// Copy as much of the address `bytes` as fits in the `cap` bytes at
+/// `ptr`, returning its full length.
+fn put_sockaddr(bytes: &[u8], ptr: u64, cap: usize) -> Result<u32, i64> {
+    let n = cap.min(bytes.len());
+    if n > 0 {
+        user_slice_mut(ptr, n as u64)?.copy_from_slice(&bytes[..n]);
+    }
+    Ok(bytes.len() as u32)
+}
+
+/// Store the address `bytes` for `accept`, `getsockname` and the like:
+/// `*len_ptr` holds the buffer size on entry and the address's full length
+/// on return.  A null `ptr` stores nothing.
+fn write_sockaddr(bytes: &[u8], ptr: u64, len_ptr: u64) -> Result<(), i64> {
+    if ptr == 0 {
+        return Ok(());
+    }
+    let len_slot = user_slice_mut(len_ptr, 4)?;
+    let cap = i32::from_le_bytes(len_slot[..].try_into().unwrap());
+    if cap < 0 {
+        return Err(-errno::EINVAL);
+    }
+    let full = put_sockaddr(bytes, ptr, cap as usize)?;
+    len_slot.copy_from_slice(&full.to_le_bytes());
+    Ok(())
+}
+
+/// An `AF_UNIX` sender's name as `recvfrom` / `recvmsg` report it: nothing
+/// at all for an unnamed sender.
+fn unix_source(name: &UnixAddr) -> Option<Vec<u8>> {
+    match name {
+        UnixAddr::Unnamed => None,
+        name => Some(sockaddr_bytes(name)),
+    }
+}
+
+/// Store a sender's address for `recvfrom` / `recvmsg`, returning its full
+/// length: 0 when there is none.
+fn put_source(source: Option<&[u8]>, ptr: u64, cap: usize) -> Result<u32, i64> {
+    match source {
+        Some(bytes) => put_sockaddr(bytes, ptr, cap),
+        None => Ok(0),
+    }
+}
+
+/// Find the listening or receiving socket `name` refers to.  A path that
+/// names no socket node is refused, as is a node nothing is bound to.
+fn resolve_target(name: UnixAddr) -> Result<Arc<UnixSocket>, i64> {
+    let key = match name {
+        UnixAddr::Unnamed => return Err(-errno::EINVAL),
+        UnixAddr::Abstract(bytes) => NameKey::Abstract(bytes),
+        UnixAddr::Path(path) => {
+            let resolved = super::vfs_canonicalize(&path, true).map_err(|e| errno::vfs_errno(&e))?;
+            let st = super::vfs_stat(&resolved).map_err(|e| errno::vfs_errno(&e))?;
+            if st.mode & S_IFMT != S_IFSOCK {
+                return Err(-errno::ECONNREFUSED);
+            }
+            NameKey::Inode { dev: st.dev, ino: st.ino }
+        }
+    };
+    unix_socket::lookup(&key).ok_or(-errno::ECONNREFUSED)
+}
+
+// ---------------------------------------------------------------------------
+// bind / listen / accept / connect
+
+/// `bind(fd, addr, addrlen)`.  A path name creates a socket node, which
+/// must not exist yet; a bare family picks an abstract name.  An Internet
+/// socket given port 0 gets a free one.
+pub(crate) fn sys_bind(fd: u64, addr: u64, addrlen: u64) -> i64 {
+    let result = get_socket(fd).and_then(|sock| match sock {
+        Socket::Unix(sock) => match read_sockaddr(addr, addrlen)? {
+            UnixAddr::Unnamed => sock.autobind().map_err(errno::socket_errno),
+            UnixAddr::Abstract(bytes) => sock
+                .bind(UnixAddr::Abstract(bytes.clone()), NameKey::Abstract(bytes))
+                .map_err(errno::socket_errno),
+            UnixAddr::Path(path) => bind_path(&sock, path),
+        },
+        Socket::Inet(sock) => {
+            let addr = read_inet_sockaddr(sock.family(), addr, addrlen)?;
+            sock.bind(addr).map_err(errno::socket_errno)
+        }
+    });
+    match result {
+        Ok(()) => 0,
+        Err(e) => e,
+    }
+}
+
+fn bind_path(sock: &Arc<UnixSocket>, path: String) -> Result<(), i64> {
+    if sock.is_bound() {
+        return Err(-errno::EINVAL);
+    }
+    let resolved = super::vfs_canonicalize(&path, false).map_err(|e| errno::vfs_errno(&e))?;
+    super::vfs_mksock(&resolved).map_err(|e| match e {
+        VfsError::AlreadyExists => -errno::EADDRINUSE,
+        e => errno::vfs_errno(&e),
+    })?;
+    let st = match super::vfs_stat(&resolved) {
+        Ok(st) => st,
+        Err(e) => {
+            super::vfs_unlink(&resolved).ok();
+            return Err(errno::vfs_errno(&e));
+        }
+    };
+    let key = NameKey::Inode { dev: st.dev, ino: st.ino };
+    sock.bind(UnixAddr::Path(path), key).map_err(|e| {
+        super::vfs_unlink(&resolved).ok();
+        errno::socket_errno(e)
+    })
+}
+
+/// `listen(fd, backlog)`.  A negative backlog asks for the most.
+pub(crate) fn sys_listen(fd: u64, backlog: u64) -> i64 {
+    let sock = match get_socket(fd) {
+        Ok(s) => s,
+        Err(e) => return e,
+    };
+    let result = match sock {
+        Socket::Unix(sock) => sock.listen(match backlog as i32 {
+            n if n < 0 => unix_socket::MAX_BACKLOG,
+            n => n as usize,
+        }),
+        Socket::Inet(sock) => sock.listen(match backlog as i32 {
+            n if n < 0 => net::MAX_BACKLOG,
+            n => n as usize,
+        }),
+    };
+    match result {
+        Ok(()) => 0,
+        Err(e) => errno::socket_errno(e),
+    }
+}
+
+/// `accept(fd, addr, addrlen)`.
+pub(crate) fn sys_accept(fd: u64, addr: u64, addrlen: u64) -> i64 {
+    sys_accept4(fd, addr, addrlen, 0)
+}
+
+/// `accept4(fd, addr, addrlen, flags)`: `accept` with `SOCK_NONBLOCK` and
+/// `SOCK_CLOEXEC` for the new socket.
+pub(crate) fn sys_accept4(fd: u64, addr: u64, addrlen: u64, flags: u64) -> i64 {
+    if flags & !(SOCK_NONBLOCK | SOCK_CLOEXEC) != 0 {
+        return -errno::EINVAL;
+    }
+    let sock = match get_socket(fd) {
+        Ok(s) => s,
+        Err(e) => return e,
+    };
+    let nonblock = flags & SOCK_NONBLOCK != 0;
+    let fd_flags = if flags & SOCK_CLOEXEC != 0 { FD_CLOEXEC } else { 0 };
+    let result = match sock {
+        Socket::Unix(sock) => sock.accept().map_err(errno::socket_errno).and_then(|conn| {
+            conn.set_nonblocking(nonblock);
+            let peer = conn.peer_name().unwrap_or(UnixAddr::Unnamed);
+            if let Err(e) = write_sockaddr(&sockaddr_bytes(&peer), addr, addrlen) {
+                conn.close();
+                return Err(e);
+            }
+            install(conn, fd_flags)
+        }),
+        Socket::Inet(sock) => sock.accept().map_err(errno::socket_errno).and_then(|(conn, peer)| {
+            conn.set_nonblocking(nonblock);
+            if let Err(e) = write_sockaddr(&inet_sockaddr_bytes(&peer), addr, addrlen) {
+                conn.close();
+                return Err(e);
+            }
+            install(conn, fd_flags)
+        }),
+    };
+    match result {
+        Ok(fd) => fd as i64,
+        Err(e) => e,
+    }
+}
+
+/// `connect(fd, addr, addrlen)`.  On a datagram socket this sets the
+/// default destination, and `AF_UNSPEC` clears it.  A non-blocking
+/// Internet stream socket fails with `EINPROGRESS` and connects in the
+/// background.
+pub(crate) fn sys_connect(fd: u64, addr: u64, addrlen: u64) -> i64 {
+    let result = get_socket(fd).and_then(|sock| {
+        let unspec = sockaddr_family(addr, addrlen)? == Some(AF_UNSPEC);
+        match sock {
+            Socket::Unix(sock) if unspec => sock.disconnect().map_err(errno::socket_errno),
+            Socket::Unix(sock) => {
+                let target = resolve_target(read_sockaddr(addr, addrlen)?)?;
+                sock.connect(target).map_err(errno::socket_errno)
+            }
+            Socket::Inet(sock) if unspec => sock.disconnect().map_err(errno::socket_errno),
+            Socket::Inet(sock) => {
+                let to = read_inet_sockaddr(sock.family(), addr, addrlen)?;
+                sock.connect(to).map_err(errno::socket_errno)
+            }
+        }
+    });
+    match result {
+        Ok(()) => 0,
+        Err(e) => e,
+    }
+}
+
+// ---------------------------------------------------------------------------
+// send
+
+/// `sendto(fd, buf, len, flags, dest_addr, addrlen)`.  `addrlen` is the
+/// sixth argument, read from R9.
+pub(crate) fn sys_sendto(fd: u64, buf: u64, len: u64, flags: u64, dest: u64) -> i64 {
+    let addrlen = libkernel::syscall::get_user_r9();
+    let sock = match get_socket(fd) {
+        Ok(s) => s,
+        Err(e) => return e,
+    };
+    let bytes: &[u8] = if len == 0 {
+        &[]
+    } else {
+        match user_slice(buf, len) {
+            Ok(s) => s,
+            Err(e) => return e,
+        }
+    };
+    match sock {
+        Socket::Unix(sock) => {
+            send(&sock, &[bytes], dest, addrlen, Rights::default(), Ucred::current(), flags as u32)
+        }
+        Socket::Inet(sock) => send_inet(&sock, &[bytes], dest, addrlen, flags as u32),
+    }
+}
+
+/// `sendmsg(fd, msg, flags)`, with `SCM_RIGHTS` and `SCM_CREDENTIALS`
+/// ancillary data on an `AF_UNIX` socket.
+pub(crate) fn sys_sendmsg(fd: u64, msg: u64, flags: u64) -> i64 {
+    let sock = match get_socket(fd) {
+        Ok(s) => s,
+        Err(e) => return e,
+    };
+    let hdr = match user_slice(msg, MSGHDR_SIZE) {
+        Ok(s) => s,
+        Err(e) => return e,
+    };
+    let name = u64_at(hdr, 0);
+    let namelen = u32::from_le_bytes(hdr[8..12].try_into().unwrap()) as u64;
+
+    let iovs = match user_iovecs(u64_at(hdr, 16), u64_at(hdr, 24)) {
+        Ok(v) => v,
+        Err(e) => return e,
+    };
+    let mut bufs: Vec<&[u8]> = Vec::with_capacity(iovs.len());
+    for (base, len) in iovs {
+        if len != 0 {
+            match user_slice(base, len) {
+                Ok(s) => bufs.push(s),
+                Err(e) => return e,
+            }
+        }
+    }
+    let sock = match sock {
+        Socket::Unix(sock) => sock,
+        Socket::Inet(sock) => {
+            if u64_at(hdr, 40) != 0 {
+                return -errno::EINVAL;
+            }
+            return send_inet(&sock, &bufs, name, namelen, flags as u32);
+        }
+    };
+    let (rights, cred) = match read_control(u64_at(hdr, 32), u64_at(hdr, 40)) {
+        Ok(c) => c,
+        Err(e) => return e,
+    };
+    send(&sock, &bufs, name, namelen, rights, cred, flags as u32)
+}
+
+/// Parse the control messages of a `sendmsg`, taking a reference to every
+/// descriptor passed.  Credentials default to the caller's.
+fn read_control(ptr: u64, len: u64) -> Result<(Rights, Ucred), i64> {
+    let mut rights = Rights::default();
+    let mut cred = Ucred::current();
+    if len == 0 {
+        return Ok((rights, cred));
+    }
+    let control = user_slice(ptr, len)?;
+    let mut off = 0;
+    while off + CMSG_HDR <= control.len() {
+        let cmsg_len = u64_at(control, off) as usize;
+        if cmsg_len < CMSG_HDR || cmsg_len > control.len() - off {
+            return Err(-errno::EINVAL);
+        }
+        let level = i32::from_le_bytes(control[off + 8..off + 12].try_into().unwrap());
+        let ty = i32::from_le_bytes(control[off + 12..off + 16].try_into().unwrap());
+        let data = &control[off + CMSG_HDR..off + cmsg_len];
+        if level as u64 != SOL_SOCKET {
+            return Err(-errno::EINVAL);
+        }
+        match ty {
+            SCM_RIGHTS => {
+                let fds: Vec<i32> = data
+                    .chunks_exact(4)
+                    .map(|b| i32::from_le_bytes(b.try_into().unwrap()))
+                    .collect();
+                if fds.is_empty() || rights.0.len() + fds.len() > SCM_MAX_FD {
+                    return Err(-errno::EINVAL);
+                }
+                rights.0.extend(extract_fds(&fds)?);
+            }
+            SCM_CREDENTIALS => {
+                if data.len() != UCRED_SIZE {
+                    return Err(-errno::EINVAL);
+                }
+                let word = |i: usize| u32::from_le_bytes(data[i * 4..i * 4 + 4].try_into().unwrap());
+                // Every process runs as root, so any live pid and any ids
+                // may be claimed.
+                let pid = ProcessId::from_raw(word(0) as u64);
+                if process::with_process_ref(pid, |_| ()).is_none() {
+                    return Err(-errno::ESRCH);
+                }
+                cred = Ucred { pid: word(0), uid: word(1), gid: word(2) };
+            }
+            _ => return Err(-errno::EINVAL),
+        }
+        off += cmsg_align(cmsg_len);
+    }
+    Ok((rights, cred))
+}
+
+/// The `u64` at byte `off` of a user structure.
+fn u64_at(raw: &[u8], off: usize) -> u64 {
+    u64::from_le_bytes(raw[off..off + 8].try_into().unwrap())
+}
+
+/// `CMSG_ALIGN`.
+fn cmsg_align(len: usize) -> usize {
+    (len + 7) & !7
+}
+
+/// Send `bufs` on `sock`, to the address at `name` if one is given.
+fn send(
+    sock: &Arc<UnixSocket>,
+    bufs: &[&[u8]],
+    name: u64,
+    namelen: u64,
+    rights: Rights,
+    cred: Ucred,
+    flags: u32,
+) -> i64 {
+    if flags & MSG_OOB != 0 {
+        return -errno::EOPNOTSUPP;
+    }
+    let to = if name != 0 && namelen != 0 {
+        match sock.socket_type() {
+            SocketType::Datagram => match read_sockaddr(name, namelen).and_then(resolve_target) {
+                Ok(target) => Some(target),
+                Err(e) => return e,
+            },
+            SocketType::Stream if sock.peer_name().is_ok() => return -errno::EISCONN,
+            SocketType::Stream => return -errno::EOPNOTSUPP,
+            // A seqpacket socket goes to its peer whatever it is given.
+            SocketType::SeqPacket => None,
+        }
+    } else {
+        None
+    };
+    sent(sock.send(bufs, rights, cred, to, flags), flags)
+}
+
+/// Send `bufs` on an Internet socket.  A stream socket ignores the
+/// address, as TCP does on Linux.
+fn send_inet(sock: &Arc<InetSocket>, bufs: &[&[u8]], name: u64, namelen: u64, flags: u32) -> i64 {
+    if flags & MSG_OOB != 0 {
+        return -errno::EOPNOTSUPP;
+    }
+    let to = if name != 0 && namelen != 0 && sock.socket_type() == SocketType::Datagram {
+        match read_inet_sockaddr(sock.family(), name, namelen) {
+            Ok(addr) => Some(addr),
+            Err(e) => return e,
+        }
+    } else {
+        None
+    };
+    sent(sock.send(bufs, to, flags), flags)
+}
+
+/// What a send returns.  A write to a hung-up connection raises `SIGPIPE`
+/// unless `MSG_NOSIGNAL`.
+fn sent(result: Result<usize, SocketError>, flags: u32) -> i64 {
+    match result {
+        Ok(n) => n as i64,
+        Err(SocketError::BrokenPipe) => {
+            if flags & MSG_NOSIGNAL == 0 {
+                crate::signal::raise(SIGPIPE);
+            }
+            -errno::EPIPE
+        }
+        Err(e) => errno::socket_errno(e),
+    }
+}
+
+// ---------------------------------------------------------------------------
+// recv
+
+/// `recvfrom(fd, buf, len, flags, src_addr, addrlen)`.  `addrlen` is the
+/// sixth argument, read from R9.
+pub(crate) fn sys_recvfrom(fd: u64, buf: u64, len: u64, flags: u64, src: u64) -> i64 {
+    let addrlen = libkernel::syscall::get_user_r9();
+    let sock = match get_socket(fd) {
+        Ok(s) => s,
+        Err(e) => return e,
+    };
+    let bytes: &mut [u8] = if len == 0 {
+        &mut []
+    } else {
+        match user_slice_mut(buf, len) {
+            Ok(s) => s,
+            Err(e) => return e,
+        }
+    };
+    let flags = flags as u32;
+    let (len, msg_len, source) = match sock {
+        Socket::Unix(sock) => match sock.recv(&mut [bytes], flags) {
+            Ok(got) => (got.len, got.msg_len, unix_source(&got.from)),
+            Err(e) => return errno::socket_errno(e),
+        },
+        Socket::Inet(sock) => match sock.recv(&mut [bytes], flags) {
+            Ok(got) => (got.len, got.msg_len, got.from.as_ref().map(inet_sockaddr_bytes)),
+            Err(e) => return errno::socket_errno(e),
+        },
+    };
+    if src != 0 {
+        let result = user_slice_mut(addrlen, 4).and_then(|slot| {
+            let cap = i32::from_le_bytes(slot[..].try_into().unwrap());
+            if cap < 0 {
+                return Err(-errno::EINVAL);
+            }
+            let full = put_source(source.as_deref(), src, cap as usize)?;
+            slot.copy_from_slice(&full.to_le_bytes());
+            Ok(())
+        });
+        if let Err(e) = result {
+            return e;
+        }
+    }
+    received_len(len, msg_len, flags)
+}
+
+/// `recvmsg(fd, msg, flags)`.  Passed descriptors are installed with
+/// `FD_CLOEXEC` under `MSG_CMSG_CLOEXEC`; those that do not fit in the
+/// control buffer are closed and `MSG_CTRUNC` set.
+pub(crate) fn sys_recvmsg(fd: u64, msg: u64, flags: u64) -> i64 {
+    let sock = match get_socket(fd) {
+        Ok(s) => s,
+        Err(e) => return e,
+    };
+    let hdr = match user_slice_mut(msg, MSGHDR_SIZE) {
+        Ok(s) => s,
+        Err(e) => return e,
+    };
+    let name = u64_at(hdr, 0);
+    let namelen = u32::from_le_bytes(hdr[8..12].try_into().unwrap()) as usize;
+    let control_ptr = u64_at(hdr, 32);
+    let control_len = u64_at(hdr, 40);
+
+    let iovs = match user_iovecs(u64_at(hdr, 16), u64_at(hdr, 24)) {
+        Ok(v) => v,
+        Err(e) => return e,
+    };
+    let mut bufs: Vec<&mut [u8]> = Vec::with_capacity(iovs.len());
+    for (base, len) in iovs {
+        if len != 0 {
+            match user_slice_mut(base, len) {
+                Ok(s) => bufs.push(s),
+                Err(e) => return e,
+            }
+        }
+    }
+    let control: &mut [u8] = if control_len == 0 {
+        &mut []
+    } else {
+        match user_slice_mut(control_ptr, control_len) {
+            Ok(s) => s,
+            Err(e) => return e,
+        }
+    };
+
+    let flags = flags as u32;
+    let (len, msg_len, source, control_used, mut msg_flags) = match sock {
+        Socket::Unix(sock) => {
+            let mut got = match sock.recv(&mut bufs, flags) {
+                Ok(got) => got,
+                Err(e) => return errno::socket_errno(e),
+            };
+            let (used, msg_flags) = write_control(control, &mut got, flags);
+            (got.len, got.msg_len, unix_source(&got.from), used, msg_flags)
+        }
+        Socket::Inet(sock) => match sock.recv(&mut bufs, flags) {
+            Ok(got) => (got.len, got.msg_len, got.from.as_ref().map(inet_sockaddr_bytes), 0, 0),
+            Err(e) => return errno::socket_errno(e),
+        },
+    };
+
+    let full_namelen = if name != 0 {
+        match put_source(source.as_deref(), name, namelen) {
+            Ok(n) => n,
+            Err(e) => return e,
+        }
+    } else {
+        0
+    };
+    if msg_len > len {
+        msg_flags |= MSG_TRUNC;
+    }
+
+    hdr[8..12].copy_from_slice(&full_namelen.to_le_bytes());
+    hdr[40..48].copy_from_slice(&(control_used as u64).to_le_bytes());
+    hdr[48..52].copy_from_slice(&msg_flags.to_le_bytes());
+    received_len(len, msg_len, flags)
+}
+
+/// Store the credentials and descriptors `got` carries as control
+/// messages, returning the bytes used and `MSG_CTRUNC` if any did not fit.
+fn write_control(control: &mut [u8], got: &mut Received, flags: u32) -> (usize, u32) {
+    let mut used = 0;
+    let mut msg_flags = 0;
+
+    if let Some(cred) = got.cred {
+        let mut data = [0u8; UCRED_SIZE];
+        data[..4].copy_from_slice(&cred.pid.to_le_bytes());
+        data[4..8].copy_from_slice(&cred.uid.to_le_bytes());
+        data[8..].copy_from_slice(&cred.gid.to_le_bytes());
+        if !put_cmsg(control, &mut used, SCM_CREDENTIALS, &data) {
+            msg_flags |= MSG_CTRUNC;
+        }
+    }
+
+    let mut objects = core::mem::take(&mut got.rights.0);
+    if !objects.is_empty() {
+        let room = control.len().saturating_sub(used + CMSG_HDR) / 4;
+        if objects.len() > room {
+            drop(Rights(objects.split_off(room)));
+            msg_flags |= MSG_CTRUNC;
+        }
+        if !objects.is_empty() {
+            let fd_flags = if flags & MSG_CMSG_CLOEXEC != 0 { FD_CLOEXEC } else { 0 };
+            match install_fds(objects, fd_flags) {
+                Ok(fds) => {
+                    let data: Vec<u8> = fds.iter().flat_map(|fd| fd.to_le_bytes()).collect();
+                    put_cmsg(control, &mut used, SCM_RIGHTS, &data);
+                }
+                Err(_) => msg_flags |= MSG_CTRUNC,
+            }
+        }
+    }
+    (used, msg_flags)
+}
+
+/// Append one `SOL_SOCKET` control message at `*used`, if it fits.
+fn put_cmsg(control: &mut [u8], used: &mut usize, ty: i32, data: &[u8]) -> bool {
+    let len = CMSG_HDR + data.len();
+    if *used + len > control.len() {
+        return false;
+    }
+    let cmsg = &mut control[*used..*used + len];
+    cmsg[..8].copy_from_slice(&(len as u64).to_le_bytes());
+    cmsg[8..12].copy_from_slice(&(SOL_SOCKET as i32).to_le_bytes());
+    cmsg[12..16].copy_from_slice(&ty.to_le_bytes());
+    cmsg[CMSG_HDR..].copy_from_slice(data);
+    *used = cmsg_align(*used + len).min(control.len());
+    true
+}
+
+/// What a receive returns: the `len` bytes copied, or under `MSG_TRUNC`
+/// the whole message's length.
+fn received_len(len: usize, msg_len: usize, flags: u32) -> i64 {
+    if flags & MSG_TRUNC != 0 {
+        msg_len.max(len) as i64
+    } else {
+        len as i64
+    }
+}
+
+// ---------------------------------------------------------------------------
+// shutdown / getsockname / getpeername
+
+/// `shutdown(fd, how)`.
+pub(crate) fn sys_shutdown(fd: u64, how: u64) -> i64 {
+    let sock = match get_socket(fd) {
+        Ok(s) => s,
+        Err(e) => return e,
+    };
+    let (read, write) = match how {
+        SHUT_RD => (true, false),
+        SHUT_WR => (false, true),
+        SHUT_RDWR => (true, true),
+        _ => return -errno::EINVAL,
+    };
+    match sock {
+        Socket::Unix(sock) => sock.shutdown(read, write),
+        Socket::Inet(sock) => {
+            if let Err(e) = sock.shutdown(read, write) {
+                return errno::socket_errno(e);
+            }
+        }
+    }
+    0
+}
+
+/// `getsockname(fd, addr, addrlen)`.
+pub(crate) fn sys_getsockname(fd: u64, addr: u64, addrlen: u64) -> i64 {
+    let result = get_socket(fd).and_then(|sock| {
+        let name = match sock {
+            Socket::Unix(sock) => sockaddr_bytes(&sock.local_name()),
+            Socket::Inet(sock) => inet_sockaddr_bytes(&sock.local_addr()),
+        };
+        write_sockaddr(&name, addr, addrlen)
+    });
+    match result {
+        Ok(()) => 0,
+        Err(e) => e,
+    }
+}
+
+/// `getpeername(fd, addr, addrlen)`.
+pub(crate) fn sys_getpeername(fd: u64, addr: u64, addrlen: u64) -> i64 {
+    let result = get_socket(fd).and_then(|sock| {
+        let peer = match sock {
+            Socket::Unix(sock) => sockaddr_bytes(&sock.peer_name().map_err(errno::socket_errno)?),
+            Socket::Inet(sock) => inet_sockaddr_bytes(&sock.peer_addr().map_err(errno::socket_errno)?),
+        };
+        write_sockaddr(&peer, addr, addrlen)
+    });
+    match result {
+        Ok(()) => 0,
+        Err(e) => e,
+    }
+}
+
+// ---------------------------------------------------------------------------
+// setsockopt / getsockopt
+
+/// `setsockopt(fd, level, optname, optval, optlen)`.  Internet sockets
+/// also have `IPPROTO_TCP` options; everything else is `SOL_SOCKET`.
+pub(crate) fn sys_setsockopt(fd: u64, level: u64, optname: u64, optval: u64, optlen: u64) -> i64 {
+    let result = get_socket(fd).and_then(|sock| match sock {
+        Socket::Unix(sock) => set_unix_option(&sock, level, optname, optval, optlen),
+        Socket::Inet(sock) => set_inet_option(&sock, level, optname, optval, optlen),
+    });
+    match result {
+        Ok(()) => 0,
+        Err(e) => e,
+    }
+}
+
+fn set_unix_option(sock: &UnixSocket, level: u64, optname: u64, optval: u64, optlen: u64) -> Result<(), i64> {
+    if level != SOL_SOCKET {
+        return Err(-errno::ENOPROTOOPT);
+    }
+    match optname {
+        SO_SNDBUF => sock.set_sndbuf(read_int_opt(optval, optlen)?.max(0) as usize),
+        SO_RCVBUF => sock.set_rcvbuf(read_int_opt(optval, optlen)?.max(0) as usize),
+        SO_PASSCRED => sock.set_passcred(read_int_opt(optval, optlen)? != 0),
+        SO_RCVTIMEO | SO_SNDTIMEO => {
+            let (rcv, snd) = set_timeout_opt(sock.timeouts(), optname, optval, optlen)?;
+            sock.set_timeouts(rcv, snd);
+        }
+        _ => return Err(-errno::ENOPROTOOPT),
+    }
+    Ok(())
+}
+
+fn set_inet_option(sock: &InetSocket, level: u64, optname: u64, optval: u64, optlen: u64) -> Result<(), i64> {
+    match (level, optname) {
+        (SOL_SOCKET, SO_REUSEADDR) => sock.set_reuseaddr(read_int_opt(optval, optlen)? != 0),
+        (SOL_SOCKET, SO_KEEPALIVE) => sock.set_keepalive(read_int_opt(optval, optlen)? != 0),
+        // The buffers are fixed; the request is checked and ignored.
+        (SOL_SOCKET, SO_SNDBUF) | (SOL_SOCKET, SO_RCVBUF) => {
+            read_int_opt(optval, optlen)?;
+        }
+        (SOL_SOCKET, SO_RCVTIMEO) | (SOL_SOCKET, SO_SNDTIMEO) => {
+            let (rcv, snd) = set_timeout_opt(sock.timeouts(), optname, optval, optlen)?;
+            sock.set_timeouts(rcv, snd);
+        }
+        (IPPROTO_TCP, TCP_NODELAY) if sock.socket_type() == SocketType::Stream => {
+            sock.set_nodelay(read_int_opt(optval, optlen)? != 0)
+        }
+        _ => return Err(-errno::ENOPROTOOPT),
+    }
+    Ok(())
+}
+
+fn read_int_opt(optval: u64, optlen: u64) -> Result<i32, i64> {
+    if optlen < 4 {
+        return Err(-errno::EINVAL);
+    }
+    Ok(i32::from_le_bytes(user_slice(optval, 4)?.try_into().unwrap()))
+}
+
+/// The `(SO_RCVTIMEO, SO_SNDTIMEO)` pair `timeouts` becomes when
+/// `optname`, one of the two, is set.
+fn set_timeout_opt(
+    timeouts: (Option<u64>, Option<u64>),
+    optname: u64,
+    optval: u64,
+    optlen: u64,
+) -> Result<(Option<u64>, Option<u64>), i64> {
+    let timeout = read_timeval_opt(optval, optlen)?;
+    let (rcv, snd) = timeouts;
+    Ok(if optname == SO_RCVTIMEO { (timeout, snd) } else { (rcv, timeout) })
+}
+
+/// Read a `struct timeval` timeout.  Zero means wait forever.
+fn read_timeval_opt(optval: u64, optlen: u64) -> Result<Option<u64>, i64> {
+    if optlen < 16 {
+        return Err(-errno::EINVAL);
+    }
+    let raw = user_slice(optval, 16)?;
+    let sec = i64::from_le_bytes(raw[..8].try_into().unwrap());
+    let usec = i64::from_le_bytes(raw[8..].try_into().unwrap());
+    if !(0..1_000_000).contains(&usec) {
+        return Err(-errno::EDOM);
+    }
+    if sec < 0 {
+        // Linux treats a negative timeout as "do not wait".
+        return Ok(Some(0));
+    }
+    let ns = (sec as u64).saturating_mul(NSEC_PER_SEC).saturating_add(usec as u64 * NSEC_PER_USEC);
+    Ok(if ns == 0 { None } else { Some(ns) })
+}
+
+/// `getsockopt(fd, level, optname, optval, optlen)`.  `*optlen` holds the
+/// buffer size on entry and the value's size on return.
+pub(crate) fn sys_getsockopt(fd: u64, level: u64, optname: u64, optval: u64, optlen: u64) -> i64 {
+    let result = get_socket(fd).and_then(|sock| {
+        let value = match sock {
+            Socket::Unix(sock) => unix_option(&sock, level, optname)?,
+            Socket::Inet(sock) => inet_option(&sock, level, optname)?,
+        };
+        let len_slot = user_slice_mut(optlen, 4)?;
+        let cap = i32::from_le_bytes(len_slot[..].try_into().unwrap());
+        if cap < 0 {
+            return Err(-errno::EINVAL);
+        }
+        let n = (cap as usize).min(value.len());
+        if n > 0 {
+            user_slice_mut(optval, n as u64)?.copy_from_slice(&value[..n]);
+        }
+        len_slot.copy_from_slice(&(n as u32).to_le_bytes());
+        Ok(())
+    });
+    match result {
+        Ok(()) => 0,
+        Err(e) => e,
+    }
+}
+
+fn int_opt(v: i32) -> Vec<u8> {
+    v.to_le_bytes().to_vec()
+}
+
+fn type_opt(ty: SocketType) -> Vec<u8> {
+    int_opt(match ty {
+        SocketType::Stream => SOCK_STREAM,
+        SocketType::Datagram => SOCK_DGRAM,
+        SocketType::SeqPacket => SOCK_SEQPACKET,
+    } as i32)
+}
+
+/// `SO_RCVTIMEO` or `SO_SNDTIMEO` from `timeouts` as a `struct timeval`.
+fn timeval_opt(timeouts: (Option<u64>, Option<u64>), optname: u64) -> Vec<u8> {
+    let (rcv, snd) = timeouts;
+    let ns = if optname == SO_RCVTIMEO { rcv } else { snd }.unwrap_or(0);
+    let mut v = (ns / NSEC_PER_SEC).to_le_bytes().to_vec();
+    v.extend_from_slice(&(ns % NSEC_PER_SEC / NSEC_PER_USEC).to_le_bytes());
+    v
+}
+
+fn unix_option(sock: &UnixSocket, level: u64, optname: u64) -> Result<Vec<u8>, i64> {
+    if level != SOL_SOCKET {
+        return Err(-errno::ENOPROTOOPT);
+    }
+    Ok(match optname {
+        SO_TYPE => type_opt(sock.socket_type()),
+        SO_ERROR => int_opt(0),
+        SO_SNDBUF => int_opt(sock.buffer_sizes().0 as i32),
+        SO_RCVBUF => int_opt(sock.buffer_sizes().1 as i32),
+        SO_PASSCRED => int_opt(sock.passcred() as i32),
+        SO_ACCEPTCONN => int_opt(sock.is_listening() as i32),
+        SO_DOMAIN => int_opt(AF_UNIX as i32),
+        SO_PROTOCOL => int_opt(0),
+        SO_PEERCRED => {
+            let cred = sock.peer_cred();
+            let mut v = Vec::with_capacity(UCRED_SIZE);
+            v.extend_from_slice(&cred.pid.to_le_bytes());
+            v.extend_from_slice(&cred.uid.to_le_bytes());
+            v.extend_from_slice(&cred.gid.to_le_bytes());
+            v
+        }
+        SO_RCVTIMEO | SO_SNDTIMEO => timeval_opt(sock.timeouts(), optname),
+        _ => return Err(-errno::ENOPROTOOPT),
+    })
+}
+
+fn inet_option(sock: &InetSocket, level: u64, optname: u64) -> Result<Vec<u8>, i64> {
+    Ok(match (level, optname) {
+        (SOL_SOCKET, SO_TYPE) => type_opt(sock.socket_type()),
+        // Reading the error clears it.
+        (SOL_SOCKET, SO_ERROR) => int_opt(sock.take_error().map_or(0, |e| -errno::socket_errno(e)) as i32),
+        (SOL_SOCKET, SO_SNDBUF) => int_opt(sock.buffer_sizes().0 as i32),
+        (SOL_SOCKET, SO_RCVBUF) => int_opt(sock.buffer_sizes().1 as i32),
+        (SOL_SOCKET, SO_REUSEADDR) => int_opt(sock.reuseaddr() as i32),
+        (SOL_SOCKET, SO_KEEPALIVE) => int_opt(sock.keepalive() as i32),
+        (SOL_SOCKET, SO_ACCEPTCONN) => int_opt(sock.is_listening() as i32),
+        (SOL_SOCKET, SO_DOMAIN) => int_opt(match sock.family() {
+            Family::Inet => AF_INET,
+            Family::Inet6 => AF_INET6,
+        } as i32),
+        (SOL_SOCKET, SO_PROTOCOL) => int_opt(match sock.socket_type() {
+            SocketType::Datagram => IPPROTO_UDP,
+            _ => IPPROTO_TCP,
+        } as i32),
+        (SOL_SOCKET, SO_RCVTIMEO) | (SOL_SOCKET, SO_SNDTIMEO) => timeval_opt(sock.timeouts(), optname),
+        (IPPROTO_TCP, TCP_NODELAY) if sock.socket_type() == SocketType::Stream => {
+            int_opt(sock.nodelay() as i32)
+        }
+        _ => return Err(-errno::ENOPROTOOPT),
+    })
+}
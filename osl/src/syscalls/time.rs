@@ -0,0 +1,153 @@
+//! Clock syscalls: clock_gettime, clock_getres, gettimeofday, nanosleep and
+//! clock_nanosleep.
+//!
+//! The clocks live in `libkernel::time`; this module maps Linux clock ids
+//! onto them and converts to and from `struct timespec` / `struct timeval`.
+
+use crate::errno;
+use crate::user_mem::validate_user_buf;
+use libkernel::process;
+use libkernel::task::timer;
+use libkernel::time::{self, SleepError, NSEC_PER_SEC};
+
+const CLOCK_REALTIME: u64 = 0;
+const CLOCK_MONOTONIC: u64 = 1;
+const CLOCK_PROCESS_CPUTIME_ID: u64 = 2;
+const CLOCK_THREAD_CPUTIME_ID: u64 = 3;
+const CLOCK_MONOTONIC_RAW: u64 = 4;
+const CLOCK_REALTIME_COARSE: u64 = 5;
+const CLOCK_MONOTONIC_COARSE: u64 = 6;
+const CLOCK_BOOTTIME: u64 = 7;
+
+/// `clock_nanosleep` flag: `request` is an absolute time.
+const TIMER_ABSTIME: u64 = 1;
+
+const NSEC_PER_USEC: u64 = 1000;
+
+/// Current reading of `clk_id` in nanoseconds, or `None` for an unknown
+/// clock.  The coarse and raw variants are served by the precise clocks.
+fn clock_now(clk_id: u64) -> Option<u64> {
+    match clk_id {
+        CLOCK_REALTIME | CLOCK_REALTIME_COARSE => Some(time::realtime_ns()),
+        CLOCK_MONOTONIC | CLOCK_MONOTONIC_RAW | CLOCK_MONOTONIC_COARSE
+            | CLOCK_BOOTTIME => Some(time::monotonic_ns()),
+        CLOCK_PROCESS_CPUTIME_ID => Some(time::process_cpu_ns(process::current_pid())),
+        CLOCK_THREAD_CPUTIME_ID => Some(time::thread_cpu_ns()),
+        _ => None,
+    }
+}
+
+/// Read a `struct timespec` from user memory as nanoseconds.
+pub(super) fn read_timespec(ptr: u64) -> Result<u64, i64> {
+    if !validate_user_buf(ptr, 16) {
+        return Err(-errno::EFAULT);
+    }
+    let (sec, nsec) = unsafe { (*(ptr as *const i64), *((ptr + 8) as *const i64)) };
+    if sec < 0 || !(0..NSEC_PER_SEC as i64).contains(&nsec) {
+        return Err(-errno::EINVAL);
+    }
+    Ok((sec as u64).saturating_mul(NSEC_PER_SEC).saturating_add(nsec as u64))
+}
+
+/// Write `ns` to user memory as a `struct timespec`.
+fn write_timespec(ptr: u64, ns: u64) -> Result<(), i64> {
+    if !validate_user_buf(ptr, 16) {
+        return Err(-errno::EFAULT);
+    }
+    unsafe {
+        *(ptr as *mut u64) = ns / NSEC_PER_SEC;          // tv_sec
+        *((ptr + 8) as *mut u64) = ns % NSEC_PER_SEC;    // tv_nsec
+    }
+    Ok(())
+}
+
+pub(crate) fn sys_clock_gettime(clk_id: u64, tp: u64) -> i64 {
+    let now = match clock_now(clk_id) {
+        Some(ns) => ns,
+        None => return -errno::EINVAL,
+    };
+    match write_timespec(tp, now) {
+        Ok(()) => 0,
+        Err(e) => e,
+    }
+}
+
+pub(crate) fn sys_clock_getres(clk_id: u64, res: u64) -> i64 {
+    if clock_now(clk_id).is_none() {
+        return -errno::EINVAL;
+    }
+    if res == 0 {
+        return 0;
+    }
+    // Every clock reads the TSC once it is calibrated; before that the
+    // monotonic clock counts timer ticks.
+    let resolution = if time::tsc_frequency() == 0 {
+        NSEC_PER_SEC / timer::TICKS_PER_SECOND
+    } else {
+        1
+    };
+    match write_timespec(res, resolution) {
+        Ok(()) => 0,
+        Err(e) => e,
+    }
+}
+
+/// `gettimeofday(tv, tz)`.  The timezone is always UTC.
+pub(crate) fn sys_gettimeofday(tv: u64, tz: u64) -> i64 {
+    if tv != 0 {
+        if !validate_user_buf(tv, 16) {
+            return -errno::EFAULT;
+        }
+        let now = time::realtime_ns();
+        unsafe {
+            *(tv as *mut u64) = now / NSEC_PER_SEC;                         // tv_sec
+            *((tv + 8) as *mut u64) = now % NSEC_PER_SEC / NSEC_PER_USEC;   // tv_usec
+        }
+    }
+    if tz != 0 {
+        if !validate_user_buf(tz, 8) {
+            return -errno::EFAULT;
+        }
+        unsafe { *(tz as *mut u64) = 0; }   // tz_minuteswest, tz_dsttime
+    }
+    0
+}
+
+pub(crate) fn sys_nanosleep(req: u64, rem: u64) -> i64 {
+    sys_clock_nanosleep(CLOCK_MONOTONIC, 0, req, rem)
+}
+
+/// `clock_nanosleep(clk_id, flags, request, remain)`.
+///
+/// Sleeps on the realtime, monotonic and boot-time clocks.  An absolute
+/// realtime deadline is converted to the monotonic clock when the sleep
+/// starts.  On `-EINTR` a relative sleep stores the unslept time in `rem`.
+pub(crate) fn sys_clock_nanosleep(clk_id: u64, flags: u64, req: u64, rem: u64) -> i64 {
+    let ns = match read_timespec(req) {
+        Ok(ns) => ns,
+        Err(e) => return e,
+    };
+    let absolute = flags & TIMER_ABSTIME != 0;
+    let deadline = match clk_id {
+        CLOCK_REALTIME if absolute => ns.saturating_sub(time::realtime_offset_ns()),
+        CLOCK_MONOTONIC | CLOCK_BOOTTIME if absolute => ns,
+        CLOCK_REALTIME | CLOCK_MONOTONIC | CLOCK_BOOTTIME => {
+            time::monotonic_ns().saturating_add(ns)
+        }
+        _ => return -errno::EINVAL,
+    };
+
+    let pid = process::current_pid();
+    match time::sleep_until(pid, process::current_tid(), deadline) {
+        Ok(()) => 0,
+        Err(SleepError::Interrupted) => {
+            if !absolute && rem != 0 {
+                let left = deadline.saturating_sub(time::monotonic_ns());
+                if let Err(e) = write_timespec(rem, left) {
+                    return e;
+                }
+            }
+            -errno::EINTR
+        }
+    }
+}
@@ -0,0 +1,11 @@
+#!/bin/bash
+# Build an ext2 disk image from a directory tree (default: ./rootfs).
+# Needs e2fsprogs (mke2fs); runs without root.
+
+set -e
+
+src="${1:-rootfs}"
+img="${2:-disk.img}"
+
+rm -f "$img"
+mke2fs -q -t ext2 -b 4096 -L ostoo -d "$src" "$img" 64M
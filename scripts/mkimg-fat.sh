@@ -0,0 +1,12 @@
+#!/bin/bash
+# Build a FAT32 disk image from a directory tree (default: ./rootfs).
+# Needs dosfstools (mkfs.vfat) and mtools (mcopy); runs without root.
+
+set -e
+
+src="${1:-rootfs}"
+img="${2:-disk.img}"
+
+rm -f "$img"
+mkfs.vfat -F 32 -n OSTOO -C "$img" $((64 * 1024))
+mcopy -s -i "$img" "$src"/* ::/
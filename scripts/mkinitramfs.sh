@@ -0,0 +1,21 @@
+#!/bin/bash
+# Pack a directory tree (default: ./user/bin as /bin) into a newc cpio
+# archive for the kernel to link in (OSTOO_INITRAMFS).  Needs cpio.
+
+set -e
+
+out="${1:-target/initramfs.cpio}"
+src="${2:-}"
+
+if [ -z "$src" ]; then
+    # Stage the userspace binaries as /bin.
+    src="$(mktemp -d)"
+    trap 'rm -rf "$src"' EXIT
+    mkdir -p "$src/bin"
+    cp user/bin/* "$src/bin/"
+fi
+
+mkdir -p "$(dirname "$out")"
+out="$(cd "$(dirname "$out")" && pwd)/$(basename "$out")"
+(cd "$src" && find . | LC_ALL=C sort | cpio --quiet -o -H newc -R 0:0) > "$out"
+echo "initramfs: $out ($(wc -c < "$out" | tr -d ' ') bytes)"
@@ -0,0 +1,8 @@
+#!/bin/bash
+# Run with only the linked-in initramfs: no disk image, no host share.
+set -e
+
+qemu-system-x86_64 \
+    -machine q35 \
+    -drive format=raw,file=target/x86_64-os/debug/bootimage-kernel.bin \
+    -serial stdio
@@ -7,4 +7,6 @@ qemu-system-x86_64 \
     -drive format=raw,file=target/x86_64-os/debug/bootimage-kernel.bin \
     -serial stdio \
     -fsdev local,id=fsdev0,path=./user,security_model=none \
-    -device virtio-9p-pci,fsdev=fsdev0,mount_tag=hostfs
+    -device virtio-9p-pci,fsdev=fsdev0,mount_tag=hostfs \
+    -netdev user,id=net0 \
+    -device virtio-net-pci,netdev=net0
@@ -93,8 +93,7 @@ fn run() -> Result<(), OsError> {
 
     // 9. Keep running (sleep loop) — in a real client we'd handle input.
     loop {
-        // Sleep ~1 second via a simple busy-wait alternative.
-        // In the real kernel, clock_gettime returns zero, so we just yield.
+        // No input handling yet, so there is nothing to wait for.
         syscall::exit(0);
     }
 }
@@ -13,5 +13,11 @@ fn main() -> i32 {
     map.insert("key", 42);
     println!("HashMap works: {:?}", map);
 
+    let handles: Vec<_> = (0..4u64)
+        .map(|i| std::thread::spawn(move || (1..=1000 * (i + 1)).sum::<u64>()))
+        .collect();
+    let sums: Vec<u64> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+    println!("std::thread works: {:?}", sums);
+
     0
 }
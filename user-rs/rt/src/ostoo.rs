@@ -1,11 +1,14 @@
 //! Safe, high-level wrappers for ostoo custom syscalls.
 //!
 //! Provides RAII types that automatically close file descriptors on drop,
-//! builder methods for [`IoSubmission`], and typed error handling.
+//! builder methods for [`IoSubmission`], futex-based [`Mutex`] and
+//! [`Condvar`], and typed error handling.
 
 use crate::sys;
 use crate::syscall;
-use core::sync::atomic::Ordering;
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicU32, Ordering};
 
 /// Error type for ostoo syscalls — wraps a negative errno return.
 #[derive(Debug, Clone, Copy)]
@@ -557,6 +560,160 @@ impl SharedMem {
     }
 }
 
+// ═══════════════════════════════════════════════════════════════════════
+// Mutex / Condvar
+// ═══════════════════════════════════════════════════════════════════════
+
+const UNLOCKED: u32 = 0;
+const LOCKED: u32 = 1;
+/// Locked, and some thread may be sleeping in `futex_wait`.
+const CONTENDED: u32 = 2;
+
+/// A futex-based mutual-exclusion lock.
+///
+/// Uncontended `lock`/`unlock` never enter the kernel.  Futexes are keyed by
+/// physical address, so a `Mutex` placed in [`SharedMem`] also works
+/// between processes.
+pub struct Mutex<T> {
+    state: AtomicU32,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Send for Mutex<T> {}
+unsafe impl<T: Send> Sync for Mutex<T> {}
+
+impl<T> Mutex<T> {
+    pub const fn new(value: T) -> Self {
+        Mutex { state: AtomicU32::new(UNLOCKED), data: UnsafeCell::new(value) }
+    }
+
+    /// Acquire the lock, sleeping in the kernel while another thread holds it.
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        if self.state
+            .compare_exchange(UNLOCKED, LOCKED, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            self.lock_contended();
+        }
+        MutexGuard { mutex: self }
+    }
+
+    /// Acquire the lock only if it is free.
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        self.state
+            .compare_exchange(UNLOCKED, LOCKED, Ordering::Acquire, Ordering::Relaxed)
+            .ok()
+            .map(|_| MutexGuard { mutex: self })
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+
+    fn lock_contended(&self) {
+        // Once we have slept we cannot tell whether others are still waiting,
+        // so take the lock as CONTENDED and let unlock wake the next one.
+        while self.state.swap(CONTENDED, Ordering::Acquire) != UNLOCKED {
+            syscall::futex_wait(&self.state, CONTENDED, None);
+        }
+    }
+
+    fn unlock(&self) {
+        if self.state.swap(UNLOCKED, Ordering::Release) == CONTENDED {
+            syscall::futex_wake(&self.state, 1);
+        }
+    }
+}
+
+/// RAII guard returned by [`Mutex::lock`]; unlocks on drop.
+pub struct MutexGuard<'a, T> {
+    mutex: &'a Mutex<T>,
+}
+
+impl<T> Deref for MutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<T> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+impl<T> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        self.mutex.unlock();
+    }
+}
+
+/// A futex-based condition variable for use with [`Mutex`].
+///
+/// Waiters sleep on a sequence counter that every notify bumps, so a notify
+/// between unlocking the mutex and sleeping is never lost.  As with any
+/// condvar, wake-ups may be spurious: re-check the condition in a loop.
+pub struct Condvar {
+    seq: AtomicU32,
+}
+
+impl Condvar {
+    pub const fn new() -> Self {
+        Condvar { seq: AtomicU32::new(0) }
+    }
+
+    /// Unlock `guard`'s mutex, sleep until notified, and re-lock.
+    pub fn wait<'a, T>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        self.wait_inner(guard, None).0
+    }
+
+    /// Like [`wait`](Self::wait), but give up after `timeout_ns` nanoseconds.
+    /// The returned flag is `true` if the wait timed out.
+    pub fn wait_timeout<'a, T>(
+        &self,
+        guard: MutexGuard<'a, T>,
+        timeout_ns: u64,
+    ) -> (MutexGuard<'a, T>, bool) {
+        self.wait_inner(guard, Some(syscall::Timespec::from_nanos(timeout_ns)))
+    }
+
+    /// Wake one waiter.
+    pub fn notify_one(&self) {
+        self.seq.fetch_add(1, Ordering::Release);
+        syscall::futex_wake(&self.seq, 1);
+    }
+
+    /// Wake every waiter.
+    pub fn notify_all(&self) {
+        self.seq.fetch_add(1, Ordering::Release);
+        syscall::futex_wake(&self.seq, i32::MAX as u32);
+    }
+
+    fn wait_inner<'a, T>(
+        &self,
+        guard: MutexGuard<'a, T>,
+        timeout: Option<syscall::Timespec>,
+    ) -> (MutexGuard<'a, T>, bool) {
+        let seq = self.seq.load(Ordering::Relaxed);
+        let mutex = guard.mutex;
+        drop(guard);
+        let ret = syscall::futex_wait(&self.seq, seq, timeout.as_ref());
+        (mutex.lock(), ret == -syscall::ETIMEDOUT)
+    }
+}
+
+impl Default for Condvar {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 // ═══════════════════════════════════════════════════════════════════════
 // IoSubmission builder methods
 // ═══════════════════════════════════════════════════════════════════════
@@ -590,6 +747,15 @@ impl sys::IoSubmission {
         }
     }
 
+    /// Build an OP_READ submission at a file offset, like `pread`.
+    pub fn read_at(user_data: u64, fd: i32, buf: &mut [u8], offset: u64) -> Self {
+        sys::IoSubmission {
+            flags: sys::IOSUB_OFFSET,
+            offset,
+            ..Self::read(user_data, fd, buf)
+        }
+    }
+
     /// Build an OP_WRITE submission (named `write_op` to avoid shadowing
     /// `core::ptr::write`).
     pub fn write_op(user_data: u64, fd: i32, buf: &[u8]) -> Self {
@@ -603,6 +769,15 @@ impl sys::IoSubmission {
         }
     }
 
+    /// Build an OP_WRITE submission at a file offset, like `pwrite`.
+    pub fn write_at(user_data: u64, fd: i32, buf: &[u8], offset: u64) -> Self {
+        sys::IoSubmission {
+            flags: sys::IOSUB_OFFSET,
+            offset,
+            ..Self::write_op(user_data, fd, buf)
+        }
+    }
+
     pub fn irq_wait(user_data: u64, irq_fd: i32) -> Self {
         sys::IoSubmission {
             user_data,
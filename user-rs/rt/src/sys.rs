@@ -37,6 +37,11 @@ pub const OP_IPC_SEND: u32 = 5;
 pub const OP_IPC_RECV: u32 = 6;
 pub const OP_RING_WAIT: u32 = 7;
 
+// ---- Submission flags (must match libkernel/src/completion_port.rs) ----
+
+/// OP_READ / OP_WRITE at `offset` (pread / pwrite) instead of the file position.
+pub const IOSUB_OFFSET: u32 = 0x1;
+
 // ---- Flags ----
 
 pub const IPC_NONBLOCK: u32 = 0x1;
@@ -58,10 +63,10 @@ pub struct IoSubmission {
     pub opcode: u32,
     pub flags: u32,
     pub fd: i32,
-    pub _pad: i32,
-    pub buf_addr: u64,
     pub buf_len: u32,
-    pub offset: u32,
+    pub buf_addr: u64,
+    /// File offset for OP_READ / OP_WRITE with `IOSUB_OFFSET`.
+    pub offset: u64,
     pub timeout_ns: u64,
 }
 
@@ -72,9 +77,8 @@ impl Default for IoSubmission {
             opcode: 0,
             flags: 0,
             fd: -1,
-            _pad: 0,
-            buf_addr: 0,
             buf_len: 0,
+            buf_addr: 0,
             offset: 0,
             timeout_ns: 0,
         }
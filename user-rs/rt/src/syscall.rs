@@ -1,6 +1,7 @@
 //! Raw syscall wrappers via inline assembly (SYSCALL instruction).
 
 use core::arch::asm;
+use core::sync::atomic::AtomicU32;
 
 // Syscall numbers (must match osl/src/syscall_nr.rs)
 pub const SYS_READ: u64 = 0;
@@ -211,3 +212,41 @@ pub fn getpid() -> i64 {
 pub fn kill(pid: i64, sig: i32) -> i64 {
     unsafe { syscall2(SYS_KILL, pid as u64, sig as u64) }
 }
+
+// ---- Futex ----
+
+pub const SYS_FUTEX: u64 = 202;
+pub const FUTEX_WAIT: u64 = 0;
+pub const FUTEX_WAKE: u64 = 1;
+pub const ETIMEDOUT: i64 = 110;
+
+/// `struct timespec`.
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct Timespec {
+    pub tv_sec: i64,
+    pub tv_nsec: i64,
+}
+
+impl Timespec {
+    pub fn from_nanos(ns: u64) -> Self {
+        Timespec {
+            tv_sec: (ns / 1_000_000_000) as i64,
+            tv_nsec: (ns % 1_000_000_000) as i64,
+        }
+    }
+}
+
+/// `futex(word, FUTEX_WAIT, expected, timeout)` — sleep while `*word ==
+/// expected`.  `timeout` is relative; `None` waits forever.
+pub fn futex_wait(word: &AtomicU32, expected: u32, timeout: Option<&Timespec>) -> i64 {
+    let ts = timeout.map_or(0, |t| t as *const Timespec as u64);
+    unsafe {
+        syscall4(SYS_FUTEX, word as *const AtomicU32 as u64, FUTEX_WAIT, expected as u64, ts)
+    }
+}
+
+/// `futex(word, FUTEX_WAKE, n)` — wake up to `n` waiters; returns the number woken.
+pub fn futex_wake(word: &AtomicU32, n: u32) -> i64 {
+    unsafe { syscall3(SYS_FUTEX, word as *const AtomicU32 as u64, FUTEX_WAKE, n as u64) }
+}
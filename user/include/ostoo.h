@@ -49,6 +49,9 @@
 #define OP_IPC_RECV  6
 #define OP_RING_WAIT 7
 
+/* Submission flag: OP_READ / OP_WRITE at `offset` (pread / pwrite) */
+#define IOSUB_OFFSET 0x1
+
 /* ═══════════════════════════════════════════════════════════════════════
  * Flags
  * ═══════════════════════════════════════════════════════════════════════ */
@@ -74,10 +77,9 @@ struct io_submission {
     unsigned int  opcode;        /* 4 */
     unsigned int  flags;         /* 4 */
     int           fd;            /* 4 */
-    int           _pad;          /* 4 */
-    unsigned long buf_addr;      /* 8 */
     unsigned int  buf_len;       /* 4 */
-    unsigned int  offset;        /* 4 */
+    unsigned long buf_addr;      /* 8 */
+    unsigned long offset;        /* 8 — with IOSUB_OFFSET */
     unsigned long timeout_ns;    /* 8 */
 };
 
@@ -0,0 +1,106 @@
+/*
+ * mmap_shared.c — file mappings served from the page cache.
+ *
+ * Writes a two-page file, then checks that a MAP_SHARED mapping sees the
+ * file's data, that stores through it reach the file after msync, that a
+ * MAP_PRIVATE mapping sees them too but keeps its own stores to itself,
+ * and that a forked child shares the MAP_SHARED pages.  File contents are
+ * read back through a fresh descriptor, since there is no lseek or pread
+ * yet.
+ */
+#define _GNU_SOURCE
+#include <fcntl.h>
+#include <string.h>
+#include <sys/mman.h>
+#include <sys/wait.h>
+#include <unistd.h>
+#include "ostoo.h"
+
+#define PAGE 4096
+
+static const char *test_path = "/mmap_shared.tmp";
+
+static void check(int ok, const char *what) {
+    puts_stdout(ok ? "mmap_shared: ok   - " : "mmap_shared: FAIL - ");
+    puts_stdout(what);
+    puts_stdout("\n");
+    if (!ok) {
+        unlink(test_path);
+        _exit(1);
+    }
+}
+
+/* Read `n` bytes at `off` of the test file through a new descriptor. */
+static int read_at(long off, char *out, long n) {
+    static char skip[2 * PAGE];
+    int fd = open(test_path, O_RDONLY);
+    if (fd < 0)
+        return 0;
+    int ok = (off == 0 || read(fd, skip, off) == off) && read(fd, out, n) == n;
+    close(fd);
+    return ok;
+}
+
+int main(void) {
+    static char buf[2 * PAGE];
+
+    int fd = open(test_path, O_RDWR | O_CREAT | O_TRUNC, 0644);
+    check(fd >= 0, "create test file");
+    for (int i = 0; i < 2 * PAGE; i++)
+        buf[i] = 'a' + i % 26;
+    check(write(fd, buf, sizeof(buf)) == (long)sizeof(buf), "write two pages");
+
+    /* 1. A shared mapping shows the file's contents. */
+    char *shared = mmap(0, 2 * PAGE, PROT_READ | PROT_WRITE, MAP_SHARED, fd, 0);
+    check(shared != MAP_FAILED, "mmap MAP_SHARED");
+    check(memcmp(shared, buf, sizeof(buf)) == 0, "shared mapping matches file");
+
+    /* 2. Stores through it reach the file. */
+    memcpy(shared + PAGE, "written through the mapping", 27);
+    check(msync(shared, 2 * PAGE, MS_SYNC) == 0, "msync MS_SYNC");
+    char back[27];
+    check(read_at(PAGE, back, sizeof(back))
+          && memcmp(back, "written through the mapping", 27) == 0,
+          "read sees stores through the mapping");
+
+    /* 3. A private mapping of the same page sees them, but its own stores
+     *    stay private. */
+    char *priv = mmap(0, PAGE, PROT_READ | PROT_WRITE, MAP_PRIVATE, fd, PAGE);
+    check(priv != MAP_FAILED, "mmap MAP_PRIVATE at offset 4096");
+    check(memcmp(priv, "written through the mapping", 27) == 0,
+          "private mapping sees the shared page");
+    priv[0] = 'W';
+    check(shared[PAGE] == 'w', "private store does not reach the shared mapping");
+    check(read_at(PAGE, back, 1) && back[0] == 'w',
+          "private store does not reach the file");
+    check(munmap(priv, PAGE) == 0, "munmap private mapping");
+
+    /* 4. A forked child writes through its inherited shared mapping. */
+    pid_t pid = fork();
+    if (pid == 0) {
+        shared[0] = 'C';
+        _exit(0);
+    }
+    int status = 0;
+    check(pid > 0 && waitpid(pid, &status, 0) == pid, "fork and wait");
+    check(shared[0] == 'C', "parent sees the child's store");
+
+    /* 5. munmap queues written pages for write-back; fsync forces it. */
+    shared[1] = 'D';
+    check(munmap(shared, 2 * PAGE) == 0, "munmap shared mapping");
+    check(fsync(fd) == 0, "fsync");
+    check(read_at(0, back, 2) && back[0] == 'C' && back[1] == 'D',
+          "file has stores made before munmap");
+
+    /* 6. A writable shared mapping needs a read-write fd. */
+    int rdonly = open(test_path, O_RDONLY);
+    check(rdonly >= 0, "reopen read-only");
+    check(mmap(0, PAGE, PROT_READ | PROT_WRITE, MAP_SHARED, rdonly, 0) == MAP_FAILED,
+          "writable MAP_SHARED of an O_RDONLY fd fails");
+    close(rdonly);
+
+    close(fd);
+    unlink(test_path);
+    puts_stdout("mmap_shared: all tests passed\n");
+    return 0;
+}
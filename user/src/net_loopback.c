@@ -0,0 +1,238 @@
+/*
+ * net_loopback.c — TCP and UDP between two processes over `lo`.
+ *
+ * The parent listens or binds on 127.0.0.1 (and ::1) and a forked child
+ * talks to it: connect/accept with the addresses each end sees, a transfer
+ * many times the socket buffers, a half-close where the child shuts down
+ * writing and still reads the answer, and UDP datagrams keeping their
+ * boundaries.  No NIC is needed.  Exits non-zero on the first failure.
+ */
+#include <arpa/inet.h>
+#include <netinet/in.h>
+#include <string.h>
+#include <sys/socket.h>
+#include <sys/wait.h>
+#include <unistd.h>
+#include "ostoo.h"
+
+#define BIG_LEN (256 * 1024)
+
+static void check(int ok, const char *what) {
+    puts_stdout(ok ? "net_loopback: ok   - " : "net_loopback: FAIL - ");
+    puts_stdout(what);
+    puts_stdout("\n");
+    if (!ok)
+        _exit(1);
+}
+
+static struct sockaddr_in localhost(int port) {
+    struct sockaddr_in addr;
+    memset(&addr, 0, sizeof(addr));
+    addr.sin_family = AF_INET;
+    addr.sin_port = htons(port);
+    addr.sin_addr.s_addr = htonl(INADDR_LOOPBACK);
+    return addr;
+}
+
+/* A TCP listener on 127.0.0.1:port. */
+static int listen_on(int port) {
+    struct sockaddr_in addr = localhost(port);
+    int fd = socket(AF_INET, SOCK_STREAM, 0);
+    check(fd >= 0, "socket(AF_INET, SOCK_STREAM)");
+    check(bind(fd, (struct sockaddr *)&addr, sizeof(addr)) == 0, "bind 127.0.0.1");
+    check(listen(fd, 1) == 0, "listen");
+    return fd;
+}
+
+/* In the child: a connection to 127.0.0.1:port, or exit. */
+static int connect_to(int port) {
+    struct sockaddr_in addr = localhost(port);
+    int fd = socket(AF_INET, SOCK_STREAM, 0);
+    if (fd < 0 || connect(fd, (struct sockaddr *)&addr, sizeof(addr)) != 0)
+        _exit(2);
+    return fd;
+}
+
+/* Read exactly `len` bytes; 0 if the stream ends or fails first. */
+static int read_full(int fd, char *buf, long len) {
+    long got = 0;
+    while (got < len) {
+        long n = read(fd, buf + got, len - got);
+        if (n <= 0)
+            return 0;
+        got += n;
+    }
+    return 1;
+}
+
+static int write_full(int fd, const char *buf, long len) {
+    long done = 0;
+    while (done < len) {
+        long n = write(fd, buf + done, len - done);
+        if (n <= 0)
+            return 0;
+        done += n;
+    }
+    return 1;
+}
+
+static void wait_child(pid_t pid, const char *what) {
+    int status = 0;
+    check(pid > 0 && waitpid(pid, &status, 0) == pid
+          && WIFEXITED(status) && WEXITSTATUS(status) == 0, what);
+}
+
+/* 1. connect / accept, and the names each end sees. */
+static void test_connect_accept(void) {
+    int lfd = listen_on(7101);
+    pid_t pid = fork();
+    if (pid == 0) {
+        int fd = connect_to(7101);
+        char buf[4];
+        if (!write_full(fd, "ping", 4) || !read_full(fd, buf, 4) || memcmp(buf, "pong", 4))
+            _exit(3);
+        _exit(0);
+    }
+
+    struct sockaddr_in peer, local;
+    socklen_t len = sizeof(peer);
+    int fd = accept(lfd, (struct sockaddr *)&peer, &len);
+    check(fd >= 0 && len == sizeof(peer), "accept");
+    check(peer.sin_family == AF_INET && peer.sin_addr.s_addr == htonl(INADDR_LOOPBACK)
+          && peer.sin_port != 0, "peer is 127.0.0.1 on a bound port");
+    len = sizeof(local);
+    check(getsockname(fd, (struct sockaddr *)&local, &len) == 0
+          && local.sin_addr.s_addr == htonl(INADDR_LOOPBACK) && ntohs(local.sin_port) == 7101,
+          "accepted socket is 127.0.0.1:7101");
+
+    char buf[4];
+    check(read_full(fd, buf, 4) && memcmp(buf, "ping", 4) == 0, "read ping");
+    check(write_full(fd, "pong", 4), "write pong");
+    wait_child(pid, "child read pong");
+    close(fd);
+    close(lfd);
+}
+
+/* 2. IPv6: the same over ::1. */
+static void test_ipv6(void) {
+    struct sockaddr_in6 addr;
+    memset(&addr, 0, sizeof(addr));
+    addr.sin6_family = AF_INET6;
+    addr.sin6_port = htons(7102);
+    addr.sin6_addr = in6addr_loopback;
+
+    int lfd = socket(AF_INET6, SOCK_STREAM, 0);
+    check(lfd >= 0, "socket(AF_INET6, SOCK_STREAM)");
+    check(bind(lfd, (struct sockaddr *)&addr, sizeof(addr)) == 0, "bind ::1");
+    check(listen(lfd, 1) == 0, "listen on ::1");
+    pid_t pid = fork();
+    if (pid == 0) {
+        int fd = socket(AF_INET6, SOCK_STREAM, 0);
+        if (fd < 0 || connect(fd, (struct sockaddr *)&addr, sizeof(addr)) != 0)
+            _exit(2);
+        _exit(write_full(fd, "six", 3) ? 0 : 3);
+    }
+
+    int fd = accept(lfd, 0, 0);
+    char buf[3];
+    check(fd >= 0 && read_full(fd, buf, 3) && memcmp(buf, "six", 3) == 0,
+          "accept and read over ::1");
+    wait_child(pid, "child connected over ::1");
+    close(fd);
+    close(lfd);
+}
+
+/* 3. A transfer far larger than the socket buffers, ended by close. */
+static void test_large_transfer(void) {
+    static char data[BIG_LEN], got[BIG_LEN];
+    for (int i = 0; i < BIG_LEN; i++)
+        data[i] = (char)(i % 251);
+
+    int lfd = listen_on(7103);
+    pid_t pid = fork();
+    if (pid == 0) {
+        int fd = connect_to(7103);
+        if (!write_full(fd, data, BIG_LEN))
+            _exit(3);
+        close(fd);
+        _exit(0);
+    }
+
+    int fd = accept(lfd, 0, 0);
+    check(fd >= 0, "accept");
+    check(read_full(fd, got, BIG_LEN), "read 256 KiB");
+    check(memcmp(got, data, BIG_LEN) == 0, "data arrives intact and in order");
+    check(read(fd, got, 1) == 0, "end of stream after the sender closes");
+    wait_child(pid, "child wrote 256 KiB");
+    close(fd);
+    close(lfd);
+}
+
+/* 4. Half-close: the child shuts down writing and still reads. */
+static void test_half_close(void) {
+    int lfd = listen_on(7104);
+    pid_t pid = fork();
+    if (pid == 0) {
+        int fd = connect_to(7104);
+        char buf[16];
+        if (!write_full(fd, "request", 7) || shutdown(fd, SHUT_WR) != 0)
+            _exit(3);
+        if (!read_full(fd, buf, 8) || memcmp(buf, "response", 8) || read(fd, buf, 1) != 0)
+            _exit(4);
+        _exit(0);
+    }
+
+    int fd = accept(lfd, 0, 0);
+    char buf[16];
+    check(fd >= 0 && read_full(fd, buf, 7) && memcmp(buf, "request", 7) == 0, "read request");
+    check(read(fd, buf, sizeof(buf)) == 0, "end of stream after SHUT_WR");
+    check(write_full(fd, "response", 8), "write after the peer's SHUT_WR");
+    close(fd);
+    wait_child(pid, "child read the response, then end of stream");
+    close(lfd);
+}
+
+/* 5. UDP keeps datagram boundaries. */
+static void test_udp(void) {
+    struct sockaddr_in addr = localhost(7105);
+    int fd = socket(AF_INET, SOCK_DGRAM, 0);
+    check(fd >= 0, "socket(AF_INET, SOCK_DGRAM)");
+    check(bind(fd, (struct sockaddr *)&addr, sizeof(addr)) == 0, "bind UDP 127.0.0.1");
+
+    pid_t pid = fork();
+    if (pid == 0) {
+        static char big[1000];
+        int s = socket(AF_INET, SOCK_DGRAM, 0);
+        memset(big, 'x', sizeof(big));
+        if (s < 0 || connect(s, (struct sockaddr *)&addr, sizeof(addr)) != 0)
+            _exit(2);
+        if (send(s, "one", 3, 0) != 3 || send(s, "two parts", 9, 0) != 9
+            || send(s, big, sizeof(big), 0) != (long)sizeof(big) || send(s, "", 0, 0) != 0)
+            _exit(3);
+        _exit(0);
+    }
+    wait_child(pid, "child sent four datagrams");
+
+    char buf[64];
+    struct sockaddr_in from;
+    socklen_t len = sizeof(from);
+    check(recvfrom(fd, buf, sizeof(buf), 0, (struct sockaddr *)&from, &len) == 3
+          && memcmp(buf, "one", 3) == 0, "first datagram alone");
+    check(from.sin_addr.s_addr == htonl(INADDR_LOOPBACK), "sender is 127.0.0.1");
+    check(recv(fd, buf, sizeof(buf), 0) == 9 && memcmp(buf, "two parts", 9) == 0,
+          "second datagram alone");
+    check(recv(fd, buf, sizeof(buf), MSG_TRUNC) == 1000, "MSG_TRUNC gives the full length");
+    check(recv(fd, buf, sizeof(buf), 0) == 0, "empty datagram");
+    check(recv(fd, buf, sizeof(buf), MSG_DONTWAIT) < 0, "nothing else queued");
+    close(fd);
+}
+
+int main(void) {
+    test_connect_accept();
+    test_ipv6();
+    test_large_transfer();
+    test_half_close();
+    test_udp();
+    puts_stdout("net_loopback: all tests passed\n");
+    return 0;
+}
@@ -0,0 +1,87 @@
+/*
+ * time_demo.c — clock and sleep syscall demo.
+ *
+ * Reads CLOCK_REALTIME/CLOCK_MONOTONIC and gettimeofday, checks that
+ * nanosleep and clock_nanosleep (relative and TIMER_ABSTIME) sleep at least
+ * as long as asked, and that busy work shows up in the CPU-time clocks.
+ */
+#define _GNU_SOURCE
+#include <stdint.h>
+#include <stdio.h>
+#include <sys/time.h>
+#include <time.h>
+#include <unistd.h>
+#include "ostoo.h"
+
+#define MS 1000000LL
+
+/* 2020-01-01T00:00:00Z: any RTC reading older than this is wrong. */
+#define EPOCH_2020 1577836800LL
+
+static int64_t ns(clockid_t clk) {
+    struct timespec ts;
+    clock_gettime(clk, &ts);
+    return (int64_t)ts.tv_sec * 1000000000LL + ts.tv_nsec;
+}
+
+static void check(int ok, const char *what) {
+    puts_stdout(ok ? "time_demo: ok   - " : "time_demo: FAIL - ");
+    puts_stdout(what);
+    puts_stdout("\n");
+    if (!ok)
+        _exit(1);
+}
+
+int main(void) {
+    char buf[96];
+
+    /* 1. Wall-clock time comes from the RTC. */
+    struct timespec rt;
+    clock_gettime(CLOCK_REALTIME, &rt);
+    snprintf(buf, sizeof(buf), "CLOCK_REALTIME = %lld s", (long long)rt.tv_sec);
+    check(rt.tv_sec > EPOCH_2020, buf);
+
+    /* 2. gettimeofday agrees with CLOCK_REALTIME. */
+    struct timeval tv;
+    gettimeofday(&tv, NULL);
+    check(tv.tv_sec - rt.tv_sec <= 1 && tv.tv_sec >= rt.tv_sec,
+          "gettimeofday matches CLOCK_REALTIME");
+
+    /* 3. The monotonic clock advances. */
+    int64_t a = ns(CLOCK_MONOTONIC);
+    int64_t b = ns(CLOCK_MONOTONIC);
+    check(a > 0 && b >= a, "CLOCK_MONOTONIC is monotonic");
+
+    /* 4. nanosleep sleeps at least 50 ms. */
+    struct timespec req = { 0, 50 * MS };
+    a = ns(CLOCK_MONOTONIC);
+    nanosleep(&req, NULL);
+    b = ns(CLOCK_MONOTONIC);
+    snprintf(buf, sizeof(buf), "nanosleep(50ms) slept %lld us", (long long)(b - a) / 1000);
+    check(b - a >= 50 * MS, buf);
+
+    /* 5. clock_nanosleep to an absolute deadline 20 ms ahead. */
+    int64_t deadline = ns(CLOCK_MONOTONIC) + 20 * MS;
+    struct timespec abs = { deadline / 1000000000LL, deadline % 1000000000LL };
+    clock_nanosleep(CLOCK_MONOTONIC, TIMER_ABSTIME, &abs, NULL);
+    check(ns(CLOCK_MONOTONIC) >= deadline, "clock_nanosleep(TIMER_ABSTIME)");
+
+    /* 6. Spinning for 20 ms is charged as CPU time; sleeping is not. */
+    int64_t cpu0 = ns(CLOCK_PROCESS_CPUTIME_ID);
+    int64_t spin_end = ns(CLOCK_MONOTONIC) + 20 * MS;
+    while (ns(CLOCK_MONOTONIC) < spin_end)
+        ;
+    int64_t cpu1 = ns(CLOCK_PROCESS_CPUTIME_ID);
+    snprintf(buf, sizeof(buf), "process CPU time +%lld us for a 20 ms spin",
+             (long long)(cpu1 - cpu0) / 1000);
+    check(cpu1 - cpu0 >= 10 * MS, buf);
+    check(ns(CLOCK_THREAD_CPUTIME_ID) <= ns(CLOCK_PROCESS_CPUTIME_ID),
+          "thread CPU time <= process CPU time");
+
+    req.tv_nsec = 50 * MS;
+    nanosleep(&req, NULL);
+    check(ns(CLOCK_PROCESS_CPUTIME_ID) - cpu1 < 25 * MS, "CPU time excludes sleeps");
+
+    puts_stdout("time_demo: PASS\n");
+    return 0;
+}